@@ -0,0 +1,219 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Consistency checks of the layout constants.
+//!
+//! `build_time` and `runtime` are generated by `td-layout-config`, so the checks live here instead
+//! of in the generated files. Relations between individual constants are checked by `const`
+//! assertions and fail the build, relations over the whole region table are checked by tests.
+
+use core::ops::Range;
+
+use crate::build_time::*;
+
+/// A region of the shim binary defined by the `build_time` layout.
+#[derive(Clone, Copy, Debug)]
+pub struct BuildTimeRegion {
+    pub name: &'static str,
+    /// Offset of the region in the shim binary.
+    pub offset: u32,
+    pub size: u32,
+    /// Address of the region after the shim binary is loaded into memory.
+    pub base: u32,
+}
+
+impl BuildTimeRegion {
+    /// Range of the region in the shim binary, always inside of `TD_SHIM_FIRMWARE_SIZE`.
+    pub const fn image_range(&self) -> Range<usize> {
+        self.offset as usize..self.offset as usize + self.size as usize
+    }
+
+    /// Range of the region in memory, always inside of the loaded shim binary.
+    pub const fn memory_range(&self) -> Range<u64> {
+        self.base as u64..self.base as u64 + self.size as u64
+    }
+}
+
+/// Regions of the shim binary, ordered by offset.
+pub const BUILD_TIME_REGIONS: [BuildTimeRegion; 9] = [
+    BuildTimeRegion {
+        name: "CONFIG",
+        offset: TD_SHIM_CONFIG_OFFSET,
+        size: TD_SHIM_CONFIG_SIZE,
+        base: TD_SHIM_CONFIG_BASE,
+    },
+    BuildTimeRegion {
+        name: "MAILBOX",
+        offset: TD_SHIM_MAILBOX_OFFSET,
+        size: TD_SHIM_MAILBOX_SIZE,
+        base: TD_SHIM_MAILBOX_BASE,
+    },
+    BuildTimeRegion {
+        name: "TEMP_STACK",
+        offset: TD_SHIM_TEMP_STACK_OFFSET,
+        size: TD_SHIM_TEMP_STACK_SIZE,
+        base: TD_SHIM_TEMP_STACK_BASE,
+    },
+    BuildTimeRegion {
+        name: "TEMP_HEAP",
+        offset: TD_SHIM_TEMP_HEAP_OFFSET,
+        size: TD_SHIM_TEMP_HEAP_SIZE,
+        base: TD_SHIM_TEMP_HEAP_BASE,
+    },
+    BuildTimeRegion {
+        name: "FREE",
+        offset: TD_SHIM_FREE_OFFSET,
+        size: TD_SHIM_FREE_SIZE,
+        base: TD_SHIM_FREE_BASE,
+    },
+    BuildTimeRegion {
+        name: "PAYLOAD",
+        offset: TD_SHIM_PAYLOAD_OFFSET,
+        size: TD_SHIM_PAYLOAD_SIZE,
+        base: TD_SHIM_PAYLOAD_BASE,
+    },
+    BuildTimeRegion {
+        name: "METADATA",
+        offset: TD_SHIM_METADATA_OFFSET,
+        size: TD_SHIM_METADATA_SIZE,
+        base: TD_SHIM_METADATA_BASE,
+    },
+    BuildTimeRegion {
+        name: "IPL",
+        offset: TD_SHIM_IPL_OFFSET,
+        size: TD_SHIM_IPL_SIZE,
+        base: TD_SHIM_IPL_BASE,
+    },
+    BuildTimeRegion {
+        name: "RESET_VECTOR",
+        offset: TD_SHIM_RESET_VECTOR_OFFSET,
+        size: TD_SHIM_RESET_VECTOR_SIZE,
+        base: TD_SHIM_RESET_VECTOR_BASE,
+    },
+];
+
+const SIZE_4KB: u64 = 0x1000;
+const SIZE_4GB: u64 = 0x1_0000_0000;
+
+const fn region_fits(offset: u32, size: u32, base: u32) -> bool {
+    let end = offset as u64 + size as u64;
+    end <= TD_SHIM_FIRMWARE_SIZE as u64
+        && base as u64 == TD_SHIM_FIRMWARE_BASE as u64 + offset as u64
+        && offset as u64 % SIZE_4KB == 0
+        && size as u64 % SIZE_4KB == 0
+}
+
+// The shim binary is mapped right below 4G, so the reset vector ends at 0xFFFFFFFF.
+const _: () = assert!(TD_SHIM_FIRMWARE_BASE as u64 + TD_SHIM_FIRMWARE_SIZE as u64 == SIZE_4GB);
+
+const _: () = assert!(region_fits(
+    TD_SHIM_CONFIG_OFFSET,
+    TD_SHIM_CONFIG_SIZE,
+    TD_SHIM_CONFIG_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_MAILBOX_OFFSET,
+    TD_SHIM_MAILBOX_SIZE,
+    TD_SHIM_MAILBOX_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_TEMP_STACK_OFFSET,
+    TD_SHIM_TEMP_STACK_SIZE,
+    TD_SHIM_TEMP_STACK_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_TEMP_HEAP_OFFSET,
+    TD_SHIM_TEMP_HEAP_SIZE,
+    TD_SHIM_TEMP_HEAP_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_FREE_OFFSET,
+    TD_SHIM_FREE_SIZE,
+    TD_SHIM_FREE_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_PAYLOAD_OFFSET,
+    TD_SHIM_PAYLOAD_SIZE,
+    TD_SHIM_PAYLOAD_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_METADATA_OFFSET,
+    TD_SHIM_METADATA_SIZE,
+    TD_SHIM_METADATA_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_IPL_OFFSET,
+    TD_SHIM_IPL_SIZE,
+    TD_SHIM_IPL_BASE
+));
+const _: () = assert!(region_fits(
+    TD_SHIM_RESET_VECTOR_OFFSET,
+    TD_SHIM_RESET_VECTOR_SIZE,
+    TD_SHIM_RESET_VECTOR_BASE
+));
+
+// The CFV starts the image and the reset vector ends it.
+const _: () = assert!(TD_SHIM_CONFIG_OFFSET == 0);
+const _: () = assert!(
+    TD_SHIM_RESET_VECTOR_OFFSET as u64 + TD_SHIM_RESET_VECTOR_SIZE as u64
+        == TD_SHIM_FIRMWARE_SIZE as u64
+);
+
+// SEC core information, the OVMF GUID table and the metadata pointer live in the reset vector.
+const _: () = assert!(TD_SHIM_SEC_CORE_INFO_OFFSET >= TD_SHIM_RESET_VECTOR_OFFSET);
+const _: () = assert!(TD_SHIM_SEC_CORE_INFO_OFFSET < TD_SHIM_FIRMWARE_SIZE - 0x20);
+const _: () = assert!(
+    TD_SHIM_SEC_CORE_INFO_BASE as u64
+        == TD_SHIM_FIRMWARE_BASE as u64 + TD_SHIM_SEC_CORE_INFO_OFFSET as u64
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::runtime;
+
+    #[test]
+    fn test_build_time_regions() {
+        // Regions are contiguous and cover the whole shim binary.
+        let mut end = 0;
+        for region in BUILD_TIME_REGIONS.iter() {
+            assert_eq!(
+                region.image_range().start,
+                end,
+                "region {} doesn't follow the previous one",
+                region.name
+            );
+            assert!(!region.image_range().is_empty(), "region {}", region.name);
+            assert_eq!(
+                region.memory_range().start - TD_SHIM_FIRMWARE_BASE as u64,
+                region.image_range().start as u64
+            );
+            end = region.image_range().end;
+        }
+        assert_eq!(end, TD_SHIM_FIRMWARE_SIZE as usize);
+    }
+
+    #[test]
+    fn test_runtime_layout() {
+        for (config, total_usage) in [
+            (
+                runtime::exec::MEMORY_LAYOUT_CONFIG,
+                runtime::exec::TOTAL_USAGE,
+            ),
+            (
+                runtime::linux::MEMORY_LAYOUT_CONFIG,
+                runtime::linux::TOTAL_USAGE,
+            ),
+        ] {
+            assert_eq!(config.iter().map(|item| item.1).sum::<usize>(), total_usage);
+            for item in config.iter() {
+                assert_eq!(item.1 as u64 % SIZE_4KB, 0, "region {}", item.0);
+            }
+        }
+        assert!(
+            runtime::exec::PAYLOAD_SIZE >= TD_SHIM_PAYLOAD_SIZE as usize,
+            "payload can't be loaded into the runtime payload region"
+        );
+    }
+}
@@ -21,6 +21,7 @@
 use memslice::SliceType;
 
 pub mod build_time;
+pub mod layout_check;
 pub mod mailbox;
 pub mod memslice;
 pub mod runtime;
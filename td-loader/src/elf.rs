@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
+use core::mem::size_of;
 use core::ops::Range;
 use scroll::Pwrite;
 
@@ -35,8 +36,18 @@ pub fn relocate_elf_with_per_program_header(
     // parser file and get entry point
     let elf = crate::elf64::Elf::parse(image)?;
 
+    // The program header table must fit in the image, instead of reading garbage as headers.
+    let table_size = (elf.header.e_phnum as u64).checked_mul(elf.header.e_phentsize as u64)?;
+    if elf.header.e_phnum == 0
+        || elf.header.e_phentsize as usize != size_of::<elf64::ProgramHeader>()
+        || elf.header.e_phoff.checked_add(table_size)? > image.len() as u64
+    {
+        return None;
+    }
+
     let mut bottom: u64 = 0xFFFFFFFFu64;
     let mut top: u64 = 0u64;
+    let mut load_end: u64 = 0u64;
 
     for ph in elf.program_headers()? {
         if bottom > ph.p_vaddr {
@@ -45,6 +56,17 @@ pub fn relocate_elf_with_per_program_header(
         if top < ph.p_vaddr.checked_add(ph.p_memsz)? {
             top = ph.p_vaddr + ph.p_memsz;
         }
+        // Loadable segments are sorted by address and don't overlap, as required by the ELF spec.
+        if ph.p_type == elf64::PT_LOAD {
+            if ph.p_vaddr < load_end {
+                return None;
+            }
+            load_end = ph.p_vaddr + ph.p_memsz;
+        }
+    }
+    // The entry point must be inside the loaded image.
+    if elf.header.e_entry < bottom || elf.header.e_entry >= top {
+        return None;
     }
 
     bottom.checked_add(new_image_base as u64)?;
@@ -80,6 +80,8 @@ const COFF_STANDAND_SIZE: usize = 24;
 const COFF_OPTIONAL_SIZE: usize = 88;
 // COFF Section is 40 bytes
 const COFF_SECTION_SIZE: usize = 40;
+// CheckSum field is at offset 64 of the optional header.
+const OPTIONAL_HDR_CHECKSUM_OFFSET: usize = 64;
 
 /// Check whether the data s a file header of `Portable Executable` format.
 pub fn is_x86_64_pe(pe_image: &[u8]) -> bool {
@@ -112,6 +114,65 @@ pub fn is_x86_64_pe(pe_image: &[u8]) -> bool {
     true
 }
 
+fn checksum_offset(pe_image: &[u8]) -> Option<usize> {
+    if !is_x86_64_pe(pe_image) {
+        return None;
+    }
+    let coff_header_offset = pe_image.pread::<u32>(0x3c).ok()? as usize;
+    let coff_optional_size = pe_image.pread::<u16>(coff_header_offset + 20).ok()? as usize;
+    if coff_optional_size < OPTIONAL_HDR_CHECKSUM_OFFSET + 4 {
+        return None;
+    }
+    let offset = coff_header_offset
+        .checked_add(COFF_HEADER_SIZE)?
+        .checked_add(OPTIONAL_HDR_CHECKSUM_OFFSET)?;
+    pe_image.len().checked_sub(offset.checked_add(4)?)?;
+
+    Some(offset)
+}
+
+/// Get the checksum stored in the optional header of a `Portable Executable` object.
+pub fn checksum(pe_image: &[u8]) -> Option<u32> {
+    let offset = checksum_offset(pe_image)?;
+    pe_image.pread::<u32>(offset).ok()
+}
+
+/// Compute the checksum of a `Portable Executable` object.
+///
+/// The checksum is the 16-bit one's complement sum of the image, excluding the CheckSum field
+/// itself, plus the image size.
+pub fn compute_checksum(pe_image: &[u8]) -> Option<u32> {
+    let offset = checksum_offset(pe_image)?;
+    let mut sum = 0u32;
+
+    for (idx, chunk) in pe_image.chunks(2).enumerate() {
+        let pos = idx * 2;
+        if pos >= offset && pos < offset + 4 {
+            continue;
+        }
+        let word = match chunk.len() {
+            2 => u16::from_le_bytes([chunk[0], chunk[1]]) as u32,
+            _ => chunk[0] as u32,
+        };
+        sum += word;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+
+    sum.checked_add(pe_image.len() as u32)
+}
+
+/// Recompute and update the checksum of a `Portable Executable` object.
+///
+/// Return the new checksum.
+pub fn update_checksum(pe_image: &mut [u8]) -> Option<u32> {
+    let offset = checksum_offset(pe_image)?;
+    let sum = compute_checksum(pe_image)?;
+    pe_image.pwrite(sum, offset).ok()?;
+
+    Some(sum)
+}
+
 /// Relocate a `Portable Executable` object to the new base.
 pub fn relocate(pe_image: &[u8], new_pe_image: &mut [u8], new_image_base: usize) -> Option<usize> {
     relocate_with_per_section(pe_image, new_pe_image, new_image_base)
@@ -192,23 +253,34 @@ pub fn relocate_with_per_section(
         .ok()?;
 
     let sections = Sections::parse(sections_buffer, num_sections as usize)?;
-    // Load the PE header into the destination memory
+    // Load the PE header into the destination memory. Sections are sorted by address and don't
+    // overlap each other nor the headers.
+    let mut loaded_end = total_header_size;
     for section in sections {
         let section_size = section.section_size() as usize;
         let src_start = section.pointer_to_raw_data as usize;
         let src_end = src_start.checked_add(section_size)?;
         let dst_start = section.virtual_address as usize;
         let dst_end = dst_start.checked_add(section_size)?;
+        if dst_start < loaded_end {
+            return None;
+        }
 
         image_buffer.len().checked_sub(src_end as usize)?;
         loaded_buffer.len().checked_sub(dst_end as usize)?;
         loaded_buffer[dst_start..dst_end].copy_from_slice(&image_buffer[src_start..src_end]);
+        loaded_end = dst_end;
         if section.virtual_size as usize > section_size {
             let fill_end = dst_start.checked_add(section.virtual_size as usize)?;
             loaded_buffer.len().checked_sub(fill_end)?;
             loaded_buffer[dst_end..fill_end].fill(0);
+            loaded_end = fill_end;
         }
     }
+    // The entry point must be inside the loaded sections.
+    if (entry_point as usize) < total_header_size || entry_point as usize >= loaded_end {
+        return None;
+    }
 
     let sections = Sections::parse(sections_buffer, num_sections as usize)?;
     for section in sections {
@@ -599,6 +671,27 @@ mod test {
         assert_eq!(entries, 1);
     }
 
+    #[test]
+    fn test_checksum() {
+        let mut pe_image = include_bytes!("../../data/blobs/td-payload.efi").to_vec();
+        assert_eq!(checksum(&pe_image), Some(0));
+        assert_eq!(compute_checksum(&pe_image), Some(0x2d58f));
+
+        pe_image.pwrite(0x1234u32, 0xd0).unwrap();
+        assert_eq!(checksum(&pe_image), Some(0x1234));
+        // The CheckSum field is excluded from the checksum.
+        assert_eq!(compute_checksum(&pe_image), Some(0x2d58f));
+
+        assert_eq!(update_checksum(&mut pe_image), Some(0x2d58f));
+        assert_eq!(checksum(&pe_image), compute_checksum(&pe_image));
+
+        pe_image[0x1000] ^= 0xff;
+        assert_ne!(checksum(&pe_image), compute_checksum(&pe_image));
+
+        assert!(checksum(&[0u8; 0x100]).is_none());
+        assert!(update_checksum(&mut [0u8; 0x100]).is_none());
+    }
+
     #[test]
     fn test_sections_iterator() {
         let pe_image = &include_bytes!("../../data/blobs/td-payload.efi")[..];
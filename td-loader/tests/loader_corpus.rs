@@ -0,0 +1,78 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Regression corpus of malformed ELF and PE images, such as truncated headers, bogus section
+//! counts, overlapping sections and out-of-range entry points.
+//!
+//! Every file of `tests/corpus/elf` and `tests/corpus/pe` must be rejected by the loader without
+//! panicking nor accessing memory out of bounds. When fuzzing finds a loader bug, add the
+//! minimized input to the corpus with a name describing what is wrong with the image, so the
+//! bug stays fixed.
+
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use td_loader::{elf, pe};
+
+const LOADED_BUFFER_SIZE: usize = 0x80_0000;
+const NEW_IMAGE_BASE: usize = 0x20_0000;
+
+fn corpus(kind: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/corpus")
+        .join(kind);
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "empty corpus {}", dir.display());
+    files
+}
+
+// Run `load` on every file of the corpus `kind`, and check that all of them are rejected.
+fn check_corpus<T, F>(kind: &str, load: F)
+where
+    T: std::fmt::Debug,
+    F: Fn(&[u8]) -> Option<T> + panic::RefUnwindSafe,
+{
+    let mut failures = Vec::new();
+    for path in corpus(kind) {
+        let data = fs::read(&path).unwrap();
+        match panic::catch_unwind(|| load(&data)) {
+            Ok(None) => {}
+            Ok(Some(v)) => failures.push(format!("{}: loaded as {:x?}", path.display(), v)),
+            Err(_) => failures.push(format!("{}: loader panicked", path.display())),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn load_elf(image: &[u8]) -> Option<(u64, u64, u64)> {
+    let mut loaded_buffer = vec![0u8; LOADED_BUFFER_SIZE];
+    elf::relocate_elf_with_per_program_header(image, &mut loaded_buffer, NEW_IMAGE_BASE)
+}
+
+fn load_pe(image: &[u8]) -> Option<usize> {
+    let mut loaded_buffer = vec![0u8; LOADED_BUFFER_SIZE];
+    pe::relocate(image, &mut loaded_buffer, NEW_IMAGE_BASE)
+}
+
+#[test]
+fn test_elf_corpus() {
+    check_corpus("elf", load_elf);
+}
+
+#[test]
+fn test_pe_corpus() {
+    check_corpus("pe", load_pe);
+}
+
+// The checks rejecting the corpus must not reject well-formed images.
+#[test]
+fn test_load_valid_images() {
+    assert!(load_elf(include_bytes!("../../data/blobs/td-payload.elf")).is_some());
+    assert!(load_pe(include_bytes!("../../data/blobs/td-payload.efi")).is_some());
+}
@@ -0,0 +1,253 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Append-only audit log of signing operations.
+//!
+//! Each line of the log is a JSON object describing one signing operation. The log is never
+//! truncated, so it accumulates records across runs. Entries are chained by recording the SHA384
+//! of the previous line in `prev_hash`, so removing or modifying any entry but the last one breaks
+//! the chain.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use td_shim::secure_boot::HashAlg;
+
+use crate::signer::SigningAlgorithm;
+
+/// `prev_hash` of the first entry in an audit log.
+pub const AUDIT_LOG_CHAIN_ROOT: &str = "000000000000000000000000000000000000000000000000\
+                                        000000000000000000000000000000000000000000000000";
+
+/// Record of one signing operation.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// SHA384 of the payload in hex.
+    pub payload_sha384: String,
+    /// SHA384 of the signed payload in hex, `None` if signing fails.
+    pub output_sha384: Option<String>,
+    /// Name of the signing algorithm with the digest of the signed region, e.g.
+    /// `RSAPSS_3072_SHA256`.
+    pub algorithm: String,
+    /// SHA384 of the public key in hex, as enrolled into the CFV.
+    pub key_sha384: String,
+    /// `ok` or the error message.
+    pub result: String,
+    /// SHA384 of the previous line of the log in hex.
+    pub prev_hash: String,
+}
+
+impl AuditEntry {
+    /// Create a record for signing `payload` with `algorithm`, hashing the signed region with
+    /// `digest`.
+    ///
+    /// `prev_hash` is filled in by `AuditLog::append()`.
+    pub fn new(
+        payload: &[u8],
+        algorithm: &SigningAlgorithm,
+        digest: HashAlg,
+        signed_payload: Result<&[u8], &io::Error>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_secs())
+            .unwrap_or(0);
+        let (output_sha384, result) = match signed_payload {
+            Ok(v) => (Some(sha384_hex(v)), "ok".to_string()),
+            Err(e) => (None, e.to_string()),
+        };
+
+        AuditEntry {
+            timestamp,
+            payload_sha384: sha384_hex(payload),
+            output_sha384,
+            algorithm: algorithm.signature_name(digest),
+            key_sha384: sha384_hex(&algorithm.public_key_bytes()),
+            result,
+            prev_hash: String::new(),
+        }
+    }
+}
+
+fn sha384_hex(data: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA384, data))
+}
+
+/// An append-only audit log file.
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        AuditLog {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Chain `entry` to the last entry of the log and append it to the log file.
+    ///
+    /// The log file is created if it doesn't exist.
+    pub fn append(&self, mut entry: AuditEntry) -> io::Result<AuditEntry> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                error!("Can not read audit log {}: {}", self.path.display(), e);
+                return Err(e);
+            }
+        };
+        entry.prev_hash = match content.lines().filter(|l| !l.is_empty()).last() {
+            Some(line) => sha384_hex(line.as_bytes()),
+            None => AUDIT_LOG_CHAIN_ROOT.to_string(),
+        };
+
+        // Safe to unwrap() because serializing a plain struct never fails.
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| {
+                error!("Can not open audit log {}: {}", self.path.display(), e);
+                e
+            })?;
+        // Terminate a truncated last line so the new entry starts on its own line.
+        if !content.is_empty() && !content.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(line.as_bytes()).map_err(|e| {
+            error!("Can not write audit log {}: {}", self.path.display(), e);
+            e
+        })?;
+        file.sync_data()?;
+
+        Ok(entry)
+    }
+}
+
+/// Check the hash chain of an audit log, return the number of entries.
+pub fn verify_audit_log(content: &str) -> io::Result<usize> {
+    let mut prev_hash = AUDIT_LOG_CHAIN_ROOT.to_string();
+    let mut count = 0;
+
+    for (idx, line) in content.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let broken = || {
+            error!("Audit log hash chain is broken at line {}", idx + 1);
+            io::Error::new(io::ErrorKind::InvalidData, "broken audit log hash chain")
+        };
+        let entry: AuditEntry = serde_json::from_str(line).map_err(|_| broken())?;
+        if entry.prev_hash != prev_hash {
+            return Err(broken());
+        }
+        prev_hash = sha384_hex(line.as_bytes());
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::EcdsaP384KeyPair;
+
+    fn algorithm() -> SigningAlgorithm {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        SigningAlgorithm::EcdsaNistP384Sha384(key)
+    }
+
+    #[test]
+    fn test_audit_log_append() {
+        let path = std::env::temp_dir().join(format!("td-shim-audit-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let log = AuditLog::new(&path);
+        let algorithm = algorithm();
+
+        let first = log
+            .append(AuditEntry::new(
+                b"payload",
+                &algorithm,
+                HashAlg::Sha384,
+                Ok(&b"signed"[..]),
+            ))
+            .unwrap();
+        assert_eq!(first.prev_hash, AUDIT_LOG_CHAIN_ROOT);
+        assert_eq!(first.output_sha384, Some(sha384_hex(b"signed")));
+        assert_eq!(first.result, "ok");
+        assert_eq!(first.algorithm, "ECDSA_NIST_P384_SHA384");
+
+        // A second log instance, as in another run, keeps the existing entries.
+        let err = io::Error::new(io::ErrorKind::Other, "failed to sign message");
+        let second = AuditLog::new(&path)
+            .append(AuditEntry::new(
+                b"payload",
+                &algorithm,
+                HashAlg::Sha384,
+                Err(&err),
+            ))
+            .unwrap();
+        assert_eq!(second.output_sha384, None);
+        assert_eq!(second.result, "failed to sign message");
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(serde_json::from_str::<AuditEntry>(lines[0]).unwrap(), first);
+        assert_eq!(
+            serde_json::from_str::<AuditEntry>(lines[1]).unwrap(),
+            second
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_audit_log_chain() {
+        let path = std::env::temp_dir().join(format!("td-shim-chain-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AuditLog::new(&path);
+        let algorithm = algorithm();
+        for idx in 0..3u8 {
+            log.append(AuditEntry::new(
+                &[idx],
+                &algorithm,
+                HashAlg::Sha384,
+                Ok(&[idx, idx][..]),
+            ))
+            .unwrap();
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(verify_audit_log(&content).unwrap(), 3);
+        let lines: Vec<&str> = content.lines().collect();
+        let second: AuditEntry = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.prev_hash, sha384_hex(lines[0].as_bytes()));
+
+        // Removing an entry is detected.
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(verify_audit_log(&removed).is_err());
+        let removed = format!("{}\n{}\n", lines[1], lines[2]);
+        assert!(verify_audit_log(&removed).is_err());
+
+        // Modifying an entry is detected.
+        let modified = content.replacen("\"result\":\"ok\"", "\"result\":\"ko\"", 1);
+        assert!(verify_audit_log(&modified).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Sign a batch of payloads with the same key.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use log::error;
+use scroll::Endian;
+use td_layout::build_time::TD_SHIM_PAYLOAD_SIZE;
+
+use crate::audit::{AuditEntry, AuditLog};
+use crate::signer::{build_sign_header, RngSource, SigningAlgorithm};
+use crate::{InputData, OutputFile};
+
+/// A payload to be signed by `BatchSigner`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchEntry {
+    pub payload: PathBuf,
+    pub version: u64,
+    pub svn: u64,
+    pub output: PathBuf,
+}
+
+impl BatchEntry {
+    /// Name of the entry used in progress reports.
+    pub fn name(&self) -> String {
+        self.payload.display().to_string()
+    }
+}
+
+/// Progress of a batch signing job, reported once an entry is done.
+pub struct BatchProgress<'a> {
+    /// Number of entries done, including the current one.
+    pub completed: usize,
+    /// Total number of entries in the job.
+    pub total: usize,
+    pub entry: &'a BatchEntry,
+    pub result: &'a io::Result<()>,
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1)
+}
+
+/// Parse a batch manifest.
+///
+/// Each line of the manifest describes a payload to sign in form of
+/// `{payload_file} {payload_version} {payload_svn} {output_file}`. Empty lines and lines
+/// starting with `#` are ignored.
+pub fn parse_batch_manifest(manifest: &str) -> io::Result<Vec<BatchEntry>> {
+    let mut entries = Vec::new();
+
+    for (idx, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || {
+            error!("Invalid batch manifest entry at line {}: {}", idx + 1, line);
+            io::Error::new(io::ErrorKind::InvalidData, "invalid batch manifest entry")
+        };
+        if fields.len() != 4 {
+            return Err(invalid());
+        }
+        entries.push(BatchEntry {
+            payload: PathBuf::from(fields[0]),
+            version: fields[1].parse().map_err(|_| invalid())?,
+            svn: fields[2].parse().map_err(|_| invalid())?,
+            output: PathBuf::from(fields[3]),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Utility structure to sign a batch of payloads with the same key.
+pub struct BatchSigner {
+    algorithm: SigningAlgorithm,
+    endian: Endian,
+    rng: RngSource,
+    max_output_size: Option<u64>,
+    audit_log: Option<AuditLog>,
+    threads: usize,
+}
+
+impl BatchSigner {
+    /// Create a new instance of `BatchSigner`.
+    pub fn new(algorithm: SigningAlgorithm) -> Self {
+        BatchSigner {
+            algorithm,
+            endian: Endian::Little,
+            rng: RngSource::System,
+            max_output_size: None,
+            audit_log: None,
+            threads: default_threads(),
+        }
+    }
+
+    /// Set byte order of multi-byte fields in the signing header, default to little-endian.
+    pub fn set_endian(&mut self, endian: Endian) -> &mut Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Set source of random numbers used for signing, default to `RngSource::System`.
+    pub fn set_rng(&mut self, rng: RngSource) -> &mut Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Set maximum size of the signed payload files.
+    pub fn set_max_output_size(&mut self, max_size: u64) -> &mut Self {
+        self.max_output_size = Some(max_size);
+        self
+    }
+
+    /// Record every signing operation into the audit log.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) -> &mut Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Set number of worker threads signing entries concurrently, default to the available
+    /// parallelism.
+    ///
+    /// With one thread the entries are signed one by one in the order given, in the calling
+    /// thread.
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sign all entries, return the result for each entry.
+    ///
+    /// Failure of an entry doesn't stop signing the remaining entries.
+    pub fn sign(&mut self, entries: &[BatchEntry]) -> Vec<io::Result<()>> {
+        self.sign_with_progress(entries, |_| {})
+    }
+
+    /// Sign all entries and invoke `progress` once an entry is done.
+    ///
+    /// `progress` and the audit log are always invoked from the calling thread, in the order
+    /// entries are done.
+    pub fn sign_with_progress<F>(
+        &mut self,
+        entries: &[BatchEntry],
+        mut progress: F,
+    ) -> Vec<io::Result<()>>
+    where
+        F: FnMut(&BatchProgress),
+    {
+        let mut results: Vec<Option<io::Result<()>>> = entries.iter().map(|_| None).collect();
+        let mut completed = 0;
+        let mut finish = |idx: usize, (result, record): (io::Result<()>, Option<AuditEntry>)| {
+            let result = match (record, self.audit_log.as_ref()) {
+                (Some(record), Some(audit_log)) => audit_log.append(record).and(result),
+                _ => result,
+            };
+            completed += 1;
+            progress(&BatchProgress {
+                completed,
+                total: entries.len(),
+                entry: &entries[idx],
+                result: &result,
+            });
+            results[idx] = Some(result);
+        };
+
+        let threads = self.threads.min(entries.len());
+        if threads <= 1 {
+            for (idx, entry) in entries.iter().enumerate() {
+                finish(idx, self.sign_entry(entry));
+            }
+        } else {
+            let next = AtomicUsize::new(0);
+            let (sender, receiver) = mpsc::channel();
+            let this = &*self;
+            thread::scope(|scope| {
+                for _ in 0..threads {
+                    let sender = sender.clone();
+                    let next = &next;
+                    scope.spawn(move || loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        if idx >= entries.len() {
+                            break;
+                        }
+                        if sender.send((idx, this.sign_entry(&entries[idx]))).is_err() {
+                            break;
+                        }
+                    });
+                }
+                drop(sender);
+                for (idx, result) in receiver {
+                    finish(idx, result);
+                }
+            });
+        }
+
+        // Safe to unwrap() because every entry has been done.
+        results.into_iter().map(|v| v.unwrap()).collect()
+    }
+
+    // Sign an entry, return the result and the record for the audit log if it's enabled.
+    fn sign_entry(&self, entry: &BatchEntry) -> (io::Result<()>, Option<AuditEntry>) {
+        let name = entry.name();
+        let payload = match InputData::new(&name, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload") {
+            Ok(v) => v,
+            Err(e) => return (Err(e), None),
+        };
+
+        let header = build_sign_header(
+            self.algorithm.id(),
+            self.endian,
+            None,
+            payload.as_bytes().len(),
+            entry.version,
+            entry.svn,
+        );
+        let mut signed_image = header.to_bytes().to_vec();
+        signed_image.extend_from_slice(payload.as_bytes());
+        let result = self
+            .algorithm
+            .sign_block(&signed_image, &self.rng)
+            .and_then(|signature_block| {
+                signed_image.extend_from_slice(&signature_block);
+                self.write_output(&entry.output, &signed_image)?;
+                Ok(signed_image)
+            });
+
+        let record = self.audit_log.as_ref().map(|_| {
+            AuditEntry::new(
+                payload.as_bytes(),
+                &self.algorithm,
+                self.algorithm.default_digest(),
+                result.as_deref(),
+            )
+        });
+        (result.map(|_| ()), record)
+    }
+
+    fn write_output(&self, path: &Path, signed_image: &[u8]) -> io::Result<()> {
+        let mut output = OutputFile::new(path)?;
+        if let Some(max_size) = self.max_output_size {
+            output.set_max_size(max_size);
+        }
+        output.seek_and_write(0, signed_image, "signed payload")?;
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::EcdsaP384KeyPair;
+    use std::fs;
+    use td_shim::secure_boot::PayloadSignHeader;
+
+    #[test]
+    fn test_parse_batch_manifest() {
+        let manifest = "# payload version svn output\n\
+                        \n\
+                        a.efi 1 2 a-signed\n  b.elf 3 4   b-signed  \n";
+        let entries = parse_batch_manifest(manifest).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            BatchEntry {
+                payload: PathBuf::from("b.elf"),
+                version: 3,
+                svn: 4,
+                output: PathBuf::from("b-signed"),
+            }
+        );
+
+        assert!(parse_batch_manifest("a.efi 1 2").is_err());
+        assert!(parse_batch_manifest("a.efi 1 x a-signed").is_err());
+        assert!(parse_batch_manifest("a.efi 1 2 a-signed extra").is_err());
+        assert!(parse_batch_manifest("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_batch_sign_progress() {
+        let dir = std::env::temp_dir().join(format!("td-shim-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut entries = Vec::new();
+        for idx in 0..3u64 {
+            let payload = dir.join(format!("payload-{}", idx));
+            fs::write(&payload, [idx as u8; 64]).unwrap();
+            entries.push(BatchEntry {
+                payload,
+                version: idx,
+                svn: idx + 1,
+                output: dir.join(format!("payload-{}-signed", idx)),
+            });
+        }
+        // The second entry doesn't exist.
+        entries[1].payload = dir.join("missing");
+
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = BatchSigner::new(SigningAlgorithm::EcdsaNistP384Sha384(key));
+        // Entries are done in order with a single thread.
+        signer.set_threads(1);
+
+        let mut reports = Vec::new();
+        let results = signer.sign_with_progress(&entries, |p| {
+            reports.push((p.completed, p.total, p.entry.name(), p.result.is_ok()));
+        });
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            reports,
+            vec![
+                (1, 3, entries[0].name(), true),
+                (2, 3, entries[1].name(), false),
+                (3, 3, entries[2].name(), true),
+            ]
+        );
+
+        let signed = fs::read(&entries[2].output).unwrap();
+        let header = PayloadSignHeader::from_bytes(&signed).unwrap();
+        assert_eq!(header.payload_version, 2);
+        assert_eq!(header.payload_svn, 3);
+        assert!(!entries[1].output.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_sign_threads() {
+        let dir =
+            std::env::temp_dir().join(format!("td-shim-batch-threads-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Fixed entropy makes the signatures reproducible.
+        let entropy = dir.join("entropy");
+        fs::write(&entropy, [0xa5u8; 80]).unwrap();
+
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let sign = |threads: usize| {
+            let mut entries = Vec::new();
+            for idx in 0..6u64 {
+                let payload = dir.join(format!("payload-{}", idx));
+                fs::write(&payload, [idx as u8; 256]).unwrap();
+                entries.push(BatchEntry {
+                    payload,
+                    version: idx,
+                    svn: idx,
+                    output: dir.join(format!("payload-{}-signed-{}", idx, threads)),
+                });
+            }
+            entries[3].payload = dir.join("missing");
+
+            let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+            let mut signer = BatchSigner::new(SigningAlgorithm::EcdsaNistP384Sha384(key));
+            signer.set_rng(RngSource::File(entropy.clone()));
+            signer.set_threads(threads);
+            let mut completed = Vec::new();
+            let results = signer.sign_with_progress(&entries, |p| completed.push(p.completed));
+            assert_eq!(completed, (1..=entries.len()).collect::<Vec<_>>());
+
+            let outputs: Vec<Option<Vec<u8>>> =
+                entries.iter().map(|e| fs::read(&e.output).ok()).collect();
+            (
+                results.iter().map(|r| r.is_ok()).collect::<Vec<_>>(),
+                outputs,
+            )
+        };
+
+        let (results, outputs) = sign(1);
+        assert_eq!(results, [true, true, true, false, true, true]);
+        assert!(outputs[3].is_none());
+        for threads in [2, 4, 16] {
+            assert_eq!(sign(threads), (results.clone(), outputs.clone()));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
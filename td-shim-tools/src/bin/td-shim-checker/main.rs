@@ -9,15 +9,20 @@ use clap::ArgAction;
 use log::{error, LevelFilter};
 use std::str::FromStr;
 use std::vec::Vec;
-use std::{env, io};
+use std::{env, fs, io};
 use td_shim::metadata::{TdxMetadataDescriptor, TdxMetadataSection};
-use td_shim_tools::loader::TdShimLoader;
+use td_shim_tools::loader::{TdShimLoader, PARSE_TRACE_TARGET};
+use td_shim_tools::validator::validate_image;
 
 struct Config {
     // Input file path to be read
     pub input: String,
     // Log level "SHA384" by default
     pub log_level: String,
+    // Report all problems of the image instead of stopping at the first one
+    pub report_all: bool,
+    // Log each parsing step of the TdxMetadata at debug level
+    pub trace_parse: bool,
 }
 
 #[derive(Debug)]
@@ -36,6 +41,16 @@ impl Config {
                     .default_value("info")
                     .action(ArgAction::Set),
             )
+            .arg(
+                arg!(--"report-all" "validate the whole image and report all problems found")
+                    .required(false)
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                arg!(--"trace-parse" "log each parsing step at debug level, to find where parsing of a malformed image fails")
+                    .required(false)
+                    .action(ArgAction::SetTrue),
+            )
             .get_matches();
 
         // Safe to unwrap() because they are mandatory or have default values.
@@ -46,7 +61,15 @@ impl Config {
         // Safe to unwrap() because they are mandatory or have default values.
         let log_level = matches.get_one::<String>("log-level").unwrap().clone();
 
-        Ok(Self { input, log_level })
+        let report_all = matches.get_flag("report-all");
+        let trace_parse = matches.get_flag("trace-parse");
+
+        Ok(Self {
+            input,
+            log_level,
+            report_all,
+            trace_parse,
+        })
     }
 }
 
@@ -87,14 +110,45 @@ fn main() -> io::Result<()> {
     let env = Env::default()
         .filter_or("MY_LOG_LEVEL", "info")
         .write_style_or("MY_LOG_STYLE", "always");
-    env_logger::init_from_env(env);
-    let config = Config::new().map_err(|e| {
+    let config = Config::new();
+    let mut builder = env_logger::Builder::from_env(env);
+    if matches!(&config, Ok(c) if c.trace_parse) {
+        builder.filter_module(PARSE_TRACE_TARGET, LevelFilter::Debug);
+    }
+    builder.init();
+    let config = config.map_err(|e| {
         error!("Parse command line error: {:?}", e);
         io::Error::new(io::ErrorKind::Other, "Invalid command line parameter")
     })?;
 
     if let Ok(lvl) = LevelFilter::from_str(config.log_level.as_str()) {
         log::set_max_level(lvl);
+        if config.trace_parse {
+            log::set_max_level(lvl.max(LevelFilter::Debug));
+        }
+    }
+
+    if config.report_all {
+        let image = fs::read(&config.input).map_err(|e| {
+            error!("Can not read td-shim binary {}: {}", config.input, e);
+            e
+        })?;
+        let problems = validate_image(&image);
+        if !problems.is_empty() {
+            println!(
+                "Found {} problem(s) in td-shim binary [{}]:",
+                problems.len(),
+                config.input
+            );
+            for (i, problem) in problems.iter().enumerate() {
+                println!("  {}: {}", i + 1, problem);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid td-shim binary",
+            ));
+        }
+        println!("No problem found in td-shim binary [{}]", config.input);
     }
 
     println!(
@@ -107,6 +161,10 @@ fn main() -> io::Result<()> {
             "Failed to parse td-shim binary [{}] to get TdxMetadata",
             config.input
         );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid TdxMetadata in td-shim binary",
+        ));
     } else {
         let tdx_metadata = tdx_metadata.unwrap();
         println!(
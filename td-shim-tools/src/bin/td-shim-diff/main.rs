@@ -0,0 +1,74 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::io;
+use std::str::FromStr;
+
+use clap::ArgAction;
+use env_logger::Env;
+use log::{error, LevelFilter};
+use td_layout::build_time::TD_SHIM_FIRMWARE_SIZE;
+use td_shim_tools::diff::diff_signed_payloads;
+use td_shim_tools::InputData;
+
+fn main() -> io::Result<()> {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let matches = command!()
+        .about("Compare two signed payloads, and check whether they could be treated interchangeably")
+        .arg(arg!([old] "signed payload file to compare against").required(true))
+        .arg(arg!([new] "signed payload file to compare").required(true))
+        .arg(
+            arg!(--"allow-incompatible" "don't fail if the signing algorithm, header version or byte order differs")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .get_matches();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+    }
+
+    // Safe to unwrap() because they are mandatory.
+    let old_file = matches.get_one::<String>("old").unwrap();
+    let new_file = matches.get_one::<String>("new").unwrap();
+
+    let old = InputData::new(
+        old_file,
+        1..=TD_SHIM_FIRMWARE_SIZE as usize,
+        "signed payload",
+    )?;
+    let new = InputData::new(
+        new_file,
+        1..=TD_SHIM_FIRMWARE_SIZE as usize,
+        "signed payload",
+    )?;
+    let diff = diff_signed_payloads(old.as_bytes(), new.as_bytes())?;
+    println!("{}", diff);
+
+    if !diff.compatible && !matches.get_flag("allow-incompatible") {
+        error!(
+            "Signed payloads {} and {} are not compatible",
+            old_file, new_file
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "signed payloads are not compatible",
+        ));
+    }
+
+    Ok(())
+}
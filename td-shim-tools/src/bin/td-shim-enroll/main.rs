@@ -5,14 +5,21 @@
 
 #[macro_use]
 extern crate clap;
-use clap::ArgAction;
+use clap::{ArgAction, ArgMatches, Command};
 use log::{error, LevelFilter};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::vec::Vec;
 use std::{env, io, path::Path};
-use td_shim_tools::enroller::{create_key_file, enroll_files, FirmwareRawFile};
-use td_shim_tools::InputData;
+use td_layout::build_time::{
+    TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_PAYLOAD_SIZE,
+};
+use td_shim_tools::enroller::{
+    check_enrolled_key, check_signed_payload_key, create_key_file, create_measured_config_file,
+    create_min_svn_file, create_revocation_list_file, enroll_files, key_file_fingerprint,
+    merge_cfvs, parse_public_key, parse_public_key_file, FirmwareRawFile, PUB_KEY_MAX_SIZE,
+};
+use td_shim_tools::{InputData, OutputFile};
 use td_uefi_pi::pi::guid;
 const TDSHIM_SB_NAME: &str = "final.sb.bin";
 
@@ -23,11 +30,22 @@ struct Config {
     pub output: PathBuf,
     // Public key file path
     pub key: Option<String>,
+    // Signed payload file whose signing key must be the enrolled key
+    pub against_signed: Option<String>,
     // Hash algorithm "SHA384" by default
     pub hash_alg: String,
     // Firmware file information to be enrolled into CFV,
     // consists of (Guid, FilePath)
     pub firmware_files: Vec<(guid::Guid, String)>,
+    // Config blobs to be enrolled into CFV and measured at boot,
+    // consists of (Guid, Name, FilePath)
+    pub configs: Vec<(guid::Guid, String, String)>,
+    // Public key files to be revoked
+    pub revoked_keys: Vec<String>,
+    // Hashes of public keys to be revoked
+    pub revoked_hashes: Vec<Vec<u8>>,
+    // Minimum payload SVN accepted by td-shim
+    pub min_svn: Option<u64>,
     // Log level "SHA384" by default
     pub log_level: String,
 }
@@ -35,45 +53,99 @@ struct Config {
 #[derive(Debug)]
 pub enum ConfigParseError {
     InvlidGuid,
+    InvalidRevokedHash,
     InvalidLogLevel,
     InvalidInputFilePath,
 }
 
-impl Config {
-    pub fn new() -> Result<Self, ConfigParseError> {
-        let matches = command!()
-            .arg(arg!([tdshim] "shim binary file").required(true))
-            .arg(
-                arg!(-k --key "public key file for enrollment")
-                    .required(false)
-                    .action(ArgAction::Set),
-            )
-            .arg(
-                arg!(-H --hash "hash algorithm to compute digest")
-                    .required(false)
-                    .default_value("SHA384")
-                    .action(ArgAction::Set),
-            )
-            .arg(
-                arg!(-f --file "<Guid> <FilePath> Firmware file to be enrolled into CFV")
-                    .required(false)
-                    .num_args(..)
-                    .action(ArgAction::Set),
-            )
-            .arg(
-                arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
-                    .required(false)
-                    .default_value("info")
-                    .action(ArgAction::Set),
-            )
-            .arg(
-                arg!(-o --output "output of the enrolled shim binary file")
-                    .required(false)
-                    .value_parser(value_parser!(PathBuf))
-                    .action(ArgAction::Set),
-            )
-            .get_matches();
+fn build_command() -> Command {
+    command!()
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .arg(arg!([tdshim] "shim binary file").required(true))
+        .arg(
+            arg!(-k --key "public key file for enrollment")
+                .required(false)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"against-signed" "signed payload file, fail unless it's signed by the enrolled public key")
+                .required(false)
+                .requires("key")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-H --hash "hash algorithm to compute digest: ['SHA256', 'SHA384', 'SHA512']")
+                .required(false)
+                .default_value("SHA384")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-f --file "<Guid> <FilePath> Firmware file to be enrolled into CFV")
+                .required(false)
+                .num_args(..)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-c --config "<Guid> <Name> <FilePath> Config blob to be enrolled into CFV and measured at boot")
+                .required(false)
+                .num_args(3)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(-r --revoke "<FilePath> public key file to be enrolled into the revocation list")
+                .required(false)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"revoke-hash" "<Hex> hash of a public key to be enrolled into the revocation list")
+                .required(false)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"min-svn" "minimum payload SVN to be enrolled into CFV")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-o --output "output of the enrolled shim binary file")
+                .required(false)
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge the firmware files of two CFVs into one CFV")
+                .arg(arg!([cfv_a] "first CFV file").required(true))
+                .arg(arg!([cfv_b] "second CFV file").required(true))
+                .arg(
+                    arg!(-o --output "output of the merged CFV file")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check whether a public key matches the trust anchor enrolled into a shim binary")
+                .arg(arg!([tdshim] "shim binary file or CFV file").required(true))
+                .arg(
+                    arg!(--pubkey "PEM or DER encoded public key file to be checked")
+                        .required(true)
+                        .action(ArgAction::Set),
+                ),
+        )
+}
 
+impl Config {
+    pub fn new(matches: &ArgMatches) -> Result<Self, ConfigParseError> {
         // Safe to unwrap() because they are mandatory or have default values.
         //
         // rust-td binary file
@@ -92,6 +164,7 @@ impl Config {
             Some(v) => Some(v.clone()),
             None => None,
         };
+        let against_signed = matches.get_one::<String>("against-signed").cloned();
 
         let firmware_files = match matches.get_many::<String>("file") {
             Some(inputs) => {
@@ -111,6 +184,35 @@ impl Config {
             None => Vec::new(),
         };
 
+        let configs = match matches.get_many::<String>("config") {
+            Some(inputs) => {
+                let inputs = inputs.collect::<Vec<&String>>();
+                let mut configs: Vec<(guid::Guid, String, String)> = Vec::new();
+                for i in 0..(inputs.len() / 3) {
+                    configs.push((
+                        guid::Guid::from_str(inputs[i * 3].as_str())
+                            .map_err(|_| ConfigParseError::InvlidGuid)?,
+                        inputs[i * 3 + 1].clone(),
+                        inputs[i * 3 + 2].clone(),
+                    ));
+                }
+                configs
+            }
+            None => Vec::new(),
+        };
+        let min_svn = matches.get_one::<u64>("min-svn").copied();
+
+        let revoked_keys = match matches.get_many::<String>("revoke") {
+            Some(inputs) => inputs.cloned().collect(),
+            None => Vec::new(),
+        };
+        let revoked_hashes = match matches.get_many::<String>("revoke-hash") {
+            Some(inputs) => inputs
+                .map(|v| hex::decode(v).map_err(|_| ConfigParseError::InvalidRevokedHash))
+                .collect::<Result<Vec<Vec<u8>>, ConfigParseError>>()?,
+            None => Vec::new(),
+        };
+
         // Safe to unwrap() because they are mandatory or have default values.
         let log_level = String::from_str(matches.get_one::<String>("log-level").unwrap())
             .map_err(|_| ConfigParseError::InvalidLogLevel)?;
@@ -120,7 +222,12 @@ impl Config {
             output,
             hash_alg,
             key,
+            against_signed,
             firmware_files,
+            configs,
+            revoked_keys,
+            revoked_hashes,
+            min_svn,
             log_level,
         })
     }
@@ -132,7 +239,14 @@ fn main() -> io::Result<()> {
         .filter_or("MY_LOG_LEVEL", "info")
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
-    let config = Config::new().map_err(|e| {
+    let matches = build_command().get_matches();
+    if let Some(("merge", m)) = matches.subcommand() {
+        return merge(m);
+    }
+    if let Some(("check", m)) = matches.subcommand() {
+        return check(m);
+    }
+    let config = Config::new(&matches).map_err(|e| {
         error!("Parse command line error: {:?}", e);
         io::Error::new(io::ErrorKind::Other, "Invalid command line parameter")
     })?;
@@ -141,6 +255,18 @@ fn main() -> io::Result<()> {
         log::set_max_level(lvl);
     }
 
+    if let (Some(key), Some(signed)) = (&config.key, &config.against_signed) {
+        let key_data = InputData::new(key, 1..=PUB_KEY_MAX_SIZE, "public key")?;
+        let public_bytes = parse_public_key(key_data.as_bytes(), key)?;
+        let signed_payload =
+            InputData::new(signed, 1..=TD_SHIM_PAYLOAD_SIZE as usize, "signed payload")?;
+        check_signed_payload_key(
+            signed_payload.as_bytes(),
+            &public_bytes,
+            config.hash_alg.as_str(),
+        )?;
+    }
+
     // Convert input files as firmware file format
     let ffs = create_firmware_files(&config)?;
     // Enroll the files into CFV
@@ -149,9 +275,97 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+// Merge the CFV files given to the `merge` subcommand
+fn merge(matches: &ArgMatches) -> io::Result<()> {
+    let cfv_size = TD_SHIM_CONFIG_SIZE as usize;
+    // Safe to unwrap() because they are mandatory.
+    let cfv_a = matches.get_one::<String>("cfv_a").unwrap();
+    let cfv_b = matches.get_one::<String>("cfv_b").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+
+    let cfv_a = InputData::new(cfv_a, cfv_size..=cfv_size, "CFV")?;
+    let cfv_b = InputData::new(cfv_b, cfv_size..=cfv_size, "CFV")?;
+    let merged = merge_cfvs(cfv_a.as_bytes(), cfv_b.as_bytes())?;
+
+    let mut output = OutputFile::new(output)?;
+    output.write(&merged, "merged CFV")?;
+    output.flush()
+}
+
+// Check the public key given to the `check` subcommand against the enrolled trust anchors
+fn check(matches: &ArgMatches) -> io::Result<()> {
+    let cfv_size = TD_SHIM_CONFIG_SIZE as usize;
+    let firmware_size = TD_SHIM_FIRMWARE_SIZE as usize;
+    // Safe to unwrap() because they are mandatory.
+    let input = matches.get_one::<String>("tdshim").unwrap();
+    let key_file = matches.get_one::<String>("pubkey").unwrap();
+
+    let input = InputData::new(input, cfv_size..=firmware_size, "shim binary")?;
+    let cfv = match input.as_bytes().len() {
+        v if v == cfv_size => input.as_bytes(),
+        v if v == firmware_size => {
+            let offset = TD_SHIM_CONFIG_OFFSET as usize;
+            &input.as_bytes()[offset..offset + cfv_size]
+        }
+        v => {
+            error!(
+                "Size of shim binary {} is neither the firmware size {} nor the CFV size {}",
+                v, firmware_size, cfv_size
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid shim binary size",
+            ));
+        }
+    };
+    let key_data = InputData::new(key_file, 1..=PUB_KEY_MAX_SIZE, "public key")?;
+    let public_bytes = parse_public_key_file(key_data.as_bytes(), key_file)?;
+
+    let result = check_enrolled_key(cfv, &public_bytes)?;
+    for (idx, anchor) in result.anchors.iter().enumerate() {
+        println!("Trust anchor {} ({}):", idx, anchor.hash_alg.name());
+        println!("  enrolled hash:   {}", hex::encode(&anchor.hash));
+        println!("  public key hash: {}", hex::encode(&anchor.key_hash));
+        println!(
+            "  {}",
+            if anchor.matches() {
+                "MATCH"
+            } else {
+                "MISMATCH"
+            }
+        );
+    }
+    if result.anchors.len() > 1 {
+        println!("td-shim only uses trust anchor 0");
+    }
+
+    match result.result {
+        Ok(()) => {
+            println!(
+                "Public key {} is trusted by the enrolled trust anchor",
+                key_file
+            );
+            Ok(())
+        }
+        Err(e) => {
+            error!(
+                "Public key {} is not trusted by the enrolled trust anchor: {:?}",
+                key_file, e
+            );
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "public key is not trusted",
+            ))
+        }
+    }
+}
+
 // Build firmware files according to command line input
 // 0 / 1 public key file to be enrolled
+// 0 / 1 revocation list of public key files and hashes
+// 0 / 1 minimum payload SVN
 // 0 ~ n raw file read from system path to be enrolled
+// 0 ~ n config blob read from system path to be enrolled and measured
 fn create_firmware_files(config: &Config) -> io::Result<Vec<FirmwareRawFile>> {
     let mut files: Vec<FirmwareRawFile> = Vec::new();
 
@@ -160,6 +374,21 @@ fn create_firmware_files(config: &Config) -> io::Result<Vec<FirmwareRawFile>> {
         files.push(ff_sb);
     }
 
+    if !config.revoked_keys.is_empty() || !config.revoked_hashes.is_empty() {
+        let mut fingerprints = config.revoked_hashes.clone();
+        for key in &config.revoked_keys {
+            fingerprints.push(key_file_fingerprint(key, config.hash_alg.as_str())?);
+        }
+        files.push(create_revocation_list_file(
+            &fingerprints,
+            config.hash_alg.as_str(),
+        )?);
+    }
+
+    if let Some(min_svn) = config.min_svn {
+        files.push(create_min_svn_file(min_svn));
+    }
+
     for (guid, path) in &config.firmware_files {
         // Create a firmware file
         let mut f = FirmwareRawFile::new(guid.as_bytes());
@@ -168,5 +397,14 @@ fn create_firmware_files(config: &Config) -> io::Result<Vec<FirmwareRawFile>> {
         files.push(f)
     }
 
+    for (guid, name, path) in &config.configs {
+        let data = InputData::new(path, 0..=1024 * 1024, "measured config")?;
+        files.push(create_measured_config_file(
+            guid.as_bytes(),
+            name,
+            data.as_bytes(),
+        )?);
+    }
+
     Ok(files)
 }
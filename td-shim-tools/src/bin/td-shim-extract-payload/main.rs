@@ -0,0 +1,65 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::ArgAction;
+use env_logger::Env;
+use log::{error, LevelFilter};
+use td_layout::build_time::TD_SHIM_FIRMWARE_SIZE;
+use td_shim_tools::signer::extract_payload;
+use td_shim_tools::{check_output_path, InputData, OutputFile};
+
+fn main() -> io::Result<()> {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let matches = command!()
+        .about("Extract the original payload from a signed payload, no key is needed")
+        .arg(arg!([signed_payload] "signed payload file").required(true))
+        .arg(
+            arg!(-o --output <FILE> "output of the extracted payload")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .get_matches();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+    }
+
+    // Safe to unwrap() because they are mandatory.
+    let input = matches.get_one::<String>("signed_payload").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    check_output_path(output, &[(Path::new(input), "signed payload")])?;
+
+    let signed_payload =
+        InputData::new(input, 1..=TD_SHIM_FIRMWARE_SIZE as usize, "signed payload")?;
+    let payload = extract_payload(signed_payload.as_bytes()).map_err(|e| {
+        error!("Can not extract payload from {}: {}", input, e);
+        e
+    })?;
+
+    let mut file = OutputFile::new(output)?;
+    file.write(payload, "payload")
+        .and_then(|_| file.flush())
+        .map_err(|e| {
+            error!("Can not write payload to {}: {}", output.display(), e);
+            e
+        })
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::ArgAction;
+use env_logger::Env;
+use log::{error, LevelFilter};
+use td_layout::build_time::TD_SHIM_FIRMWARE_SIZE;
+use td_shim_tools::fixup::fixup_image;
+use td_shim_tools::{InputData, OutputFile};
+
+fn main() -> io::Result<()> {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let matches = command!()
+        .about("Recompute the checksums and length fields of a td-shim image")
+        .arg(arg!([tdshim] "shim binary file").required(true))
+        .arg(
+            arg!(-o --output "output of the fixed image, default to update the image in place")
+                .required(false)
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .get_matches();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+    }
+
+    // Safe to unwrap() because it's mandatory.
+    let input = matches.get_one::<String>("tdshim").unwrap();
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from(input));
+
+    let mut image = InputData::new(input, 1..=TD_SHIM_FIRMWARE_SIZE as usize, "shim binary")?;
+    let fixups = fixup_image(image.as_mut_bytes());
+    for fixup in fixups.iter() {
+        println!("Fixed {}", fixup);
+    }
+    if fixups.is_empty() {
+        println!("No checksum changed");
+        // Nothing to rewrite in place.
+        if matches.get_one::<PathBuf>("output").is_none() {
+            return Ok(());
+        }
+    }
+
+    let mut file = OutputFile::new(&output)?;
+    file.write(image.as_bytes(), "fixed shim binary")
+        .and_then(|_| file.flush())
+        .map_err(|e| {
+            error!("Can not write fixed image to {}: {}", output.display(), e);
+            e
+        })
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{ArgAction, ArgMatches, Command};
+use env_logger::Env;
+use log::error;
+use log::LevelFilter;
+use td_shim_tools::inspect::{
+    extract_cfv_file, extract_region, list_acpi_tables, ImageInfo, SectionDigests, REGION_NAMES,
+};
+use td_shim_tools::json_schema::{json_schema_string, schema_names};
+use td_shim_tools::loader::PARSE_TRACE_TARGET;
+use td_shim_tools::reserved::report_reserved_fields;
+use td_shim_tools::OutputFile;
+use td_uefi_pi::pi::guid;
+
+fn dump_image_info(info: &ImageInfo) {
+    println!("Image size: 0x{:x}", info.image_size);
+    println!("TdxMetadata version: {}", info.metadata.version);
+    for (i, section) in info.metadata.sections.iter().enumerate() {
+        println!(
+            "  Section {} - {:<14} data_offset: 0x{:x}, raw_data_size: 0x{:x}, memory_address: 0x{:x}, memory_data_size: 0x{:x}, attributes: 0x{:x}",
+            i,
+            section.r#type,
+            section.data_offset,
+            section.raw_data_size,
+            section.memory_address,
+            section.memory_data_size,
+            section.attributes
+        );
+    }
+    println!("MRTD: {}", info.mrtd);
+    println!("Image digest: {}", info.image_digest);
+
+    match info.payload.as_ref() {
+        Some(payload) => {
+            println!("Payload size: 0x{:x}", payload.size);
+            println!("Payload SHA384: {}", payload.sha384);
+            match payload.verify_header.as_ref() {
+                Some(h) => println!(
+                    "Verify header: struct_version: {}, payload_version: 0x{:x}, payload_svn: {}, signing_algorithm: {}",
+                    h.struct_version, h.payload_version, h.payload_svn, h.signing_algorithm
+                ),
+                None => println!("Verify header: none"),
+            }
+            match (payload.entry_point, payload.entry_point_problem.as_ref()) {
+                (Some(entry), _) => println!("Payload entry point: +0x{:x}", entry),
+                (None, Some(problem)) => println!("Payload entry point: {}", problem),
+                (None, None) => println!("Payload entry point: unknown"),
+            }
+        }
+        None => println!("Payload: none"),
+    }
+
+    match info.cfv.as_ref() {
+        Some(cfv) => {
+            for file in cfv.files.iter() {
+                println!(
+                    "CFV file: {} type: 0x{:x} size: 0x{:x}",
+                    file.guid, file.r#type, file.size
+                );
+            }
+            for anchor in cfv.trust_anchors.iter() {
+                println!("Trust anchor: {} {}", anchor.hash_algorithm, anchor.hash);
+            }
+        }
+        None => println!("CFV: none"),
+    }
+}
+
+// Write the raw bytes of a named region of the image, for debugging with tools like xxd.
+fn extract_region_command(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory.
+    let input = matches.get_one::<String>("tdshim").unwrap();
+    let name = matches.get_one::<String>("name").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+
+    let region = extract_region(input, name)?;
+    let mut file = OutputFile::new(output)?;
+    file.write(&region, "region")
+        .and_then(|_| file.flush())
+        .map_err(|e| {
+            error!(
+                "Can not write region {} to {}: {}",
+                name,
+                output.display(),
+                e
+            );
+            e
+        })
+}
+
+// List and validate the ACPI tables embedded into a CFV file or a region of the image.
+fn acpi_tables_command(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because it's mandatory.
+    let input = matches.get_one::<String>("tdshim").unwrap();
+
+    let tables = match matches.get_one::<String>("file") {
+        Some(file) => {
+            let guid = guid::Guid::from_str(file).map_err(|_| {
+                error!("Invalid GUID {}", file);
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid GUID")
+            })?;
+            extract_cfv_file(input, &guid)?
+        }
+        // Safe to unwrap() because it's mandatory without --file.
+        None => extract_region(input, matches.get_one::<String>("name").unwrap())?,
+    };
+
+    let tables = list_acpi_tables(&tables)?;
+    if matches.get_flag("json") {
+        // Safe to unwrap() because the list is serializable.
+        println!("{}", serde_json::to_string_pretty(&tables).unwrap());
+    } else {
+        for table in tables.iter() {
+            println!(
+                "ACPI table: {} offset: 0x{:x} length: 0x{:x}",
+                table.signature, table.offset, table.length
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let matches = command!()
+        .about("Dump information about a td-shim image")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("extract-region")
+                .about("Write the raw bytes of a region of a td-shim image")
+                .arg(arg!([tdshim] "shim binary file").required(true))
+                .arg(
+                    arg!(-n --name <NAME> "name of the region")
+                        .required(true)
+                        .value_parser(REGION_NAMES.to_vec())
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-o --output <FILE> "output of the region")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("acpi-tables")
+                .about("List the ACPI tables embedded into a td-shim image, validating their lengths and checksums")
+                .arg(arg!([tdshim] "shim binary file").required(true))
+                .arg(
+                    arg!(-f --file <GUID> "raw firmware file in the CFV holding the ACPI tables")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-n --name <NAME> "name of the region starting with the ACPI tables")
+                        .required_unless_present("file")
+                        .conflicts_with("file")
+                        .value_parser(REGION_NAMES.to_vec())
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--json "output a JSON document instead of text")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("json-schema")
+                .about("Print the JSON Schema of a JSON document written or read by the td-shim tools")
+                .arg(
+                    arg!([which] "name of the JSON document")
+                        .required(true)
+                        .value_parser(schema_names()),
+                ),
+        )
+        .arg(arg!([tdshim] "shim binary file").required(true))
+        .arg(
+            arg!(--json "output a JSON document instead of text")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--digests "output the SHA256 of each metadata section as a JSON document")
+                .required(false)
+                .conflicts_with("json")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--strict "fail if reserved fields of the verify header or the SVN policy of the payload are not zero")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"trace-parse" "log each parsing step at debug level, to find where parsing of a malformed image fails")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .get_matches();
+
+    let trace_parse = matches.get_flag("trace-parse");
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    let mut builder = env_logger::Builder::from_env(env);
+    if trace_parse {
+        builder.filter_module(PARSE_TRACE_TARGET, LevelFilter::Debug);
+    }
+    builder.init();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+        if trace_parse {
+            log::set_max_level(lvl.max(LevelFilter::Debug));
+        }
+    }
+
+    match matches.subcommand() {
+        Some(("extract-region", matches)) => return extract_region_command(matches),
+        Some(("acpi-tables", matches)) => return acpi_tables_command(matches),
+        Some(("json-schema", matches)) => {
+            // Safe to unwrap() because it's mandatory.
+            let which = matches.get_one::<String>("which").unwrap();
+            println!("{}", json_schema_string(which)?);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Safe to unwrap() because it's mandatory.
+    let input = matches.get_one::<String>("tdshim").unwrap();
+    if matches.get_flag("digests") {
+        println!("{}", SectionDigests::from_file(input)?.to_json());
+        return Ok(());
+    }
+
+    let info = ImageInfo::new(input)?;
+    report_reserved_fields(input, info.reserved_fields(), matches.get_flag("strict"))?;
+    if matches.get_flag("json") {
+        println!("{}", info.to_json());
+    } else {
+        dump_image_info(&info);
+    }
+
+    Ok(())
+}
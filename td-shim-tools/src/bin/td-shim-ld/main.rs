@@ -11,7 +11,9 @@ use std::str::FromStr;
 
 use clap::{builder::PossibleValue, ArgAction};
 use log::{error, LevelFilter};
-use td_shim_tools::linker::{PayloadType, TdShimLinker};
+use td_layout::build_time::TD_SHIM_FIRMWARE_SIZE;
+use td_shim_tools::linker::{build_flat_image, verify_embedded_payload, PayloadType, TdShimLinker};
+use td_shim_tools::{InputData, OutputFile};
 
 fn main() -> io::Result<()> {
     use env_logger::Env;
@@ -62,6 +64,29 @@ fn main() -> io::Result<()> {
                 .required(false)
                 .action(ArgAction::Set),
         )
+        .arg(
+            arg!(--"reset-vector-entry" "Replace the entry stub at the reset address with a jump to the given address, such as 0xFFFFF000")
+                .required(false)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"flat-image" "Also generate a flat image for QEMU direct boot by '-bios'")
+                .required(false)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"flat-image-size" "Size of the flat image in bytes, must be 64KB aligned")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"verify-after-embed" "Verify the signed payload read back from the shim binary")
+                .required(false)
+                .requires("payload")
+                .conflicts_with("relocate-payload")
+                .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
     if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
@@ -69,9 +94,11 @@ fn main() -> io::Result<()> {
     }
 
     let mut builder = TdShimLinker::default();
-    if let Some(output_name) = matches.get_one::<String>("output") {
-        builder.set_output_file(output_name.clone());
-    }
+    let output_name = matches
+        .get_one::<String>("output")
+        .map(|s| s.as_str())
+        .unwrap_or("td_shim.bin");
+    builder.set_output_file(output_name.to_string());
     if matches.get_flag("relocate-payload") {
         builder.set_payload_relocation(true);
     }
@@ -84,11 +111,54 @@ fn main() -> io::Result<()> {
             io::Error::new(io::ErrorKind::Other, "Invalid payload type")
         })?;
 
+    if let Some(entry_point) = matches.get_one::<String>("reset-vector-entry") {
+        let value = entry_point.trim_start_matches("0x");
+        let entry_point = u32::from_str_radix(value, 16).map_err(|_e| {
+            error!("Invalid reset vector entry point {}", entry_point);
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid reset vector entry point",
+            )
+        })?;
+        builder.set_reset_vector_entry(entry_point);
+    }
+
     // Safe to unwrap() because these are mandatory arguments.
     let reset_name = matches.get_one::<String>("reset_vector").unwrap().as_str();
     let ipl_name = matches.get_one::<String>("ipl").unwrap().as_str();
     let payload_name = matches.get_one::<String>("payload").map(|s| s.as_str());
     let metadata_name = matches.get_one::<String>("metadata").map(|s| s.as_str());
 
-    builder.build(reset_name, ipl_name, payload_name, metadata_name)
+    builder.build(reset_name, ipl_name, payload_name, metadata_name)?;
+
+    if matches.get_flag("verify-after-embed") {
+        // Safe to unwrap() because "verify-after-embed" requires "payload".
+        let payload = InputData::new(payload_name.unwrap(), 0..=usize::MAX, "payload")?;
+        let firmware = InputData::new(
+            output_name,
+            TD_SHIM_FIRMWARE_SIZE as usize..=TD_SHIM_FIRMWARE_SIZE as usize,
+            "shim firmware",
+        )?;
+        verify_embedded_payload(firmware.as_bytes(), payload.as_bytes())?;
+    }
+
+    if let Some(flat_name) = matches.get_one::<String>("flat-image") {
+        let image_size = matches
+            .get_one::<u64>("flat-image-size")
+            .copied()
+            .unwrap_or(TD_SHIM_FIRMWARE_SIZE as u64);
+        let firmware = InputData::new(
+            output_name,
+            TD_SHIM_FIRMWARE_SIZE as usize..=TD_SHIM_FIRMWARE_SIZE as usize,
+            "shim firmware",
+        )?;
+        let image = build_flat_image(firmware.as_bytes(), image_size as usize)?;
+
+        let mut output = OutputFile::new(flat_name)?;
+        output.set_max_size(image_size);
+        output.seek_and_write(0, &image, "flat image")?;
+        output.flush()?;
+    }
+
+    Ok(())
 }
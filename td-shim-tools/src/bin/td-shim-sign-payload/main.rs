@@ -7,17 +7,304 @@ extern crate clap;
 
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::{env, io, path::Path};
+use std::{env, fs, io, path::Path};
 
-use clap::ArgAction;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use env_logger::Env;
-use log::{error, trace, LevelFilter};
-use ring::signature::{EcdsaKeyPair, RsaKeyPair, ECDSA_P384_SHA384_FIXED_SIGNING};
+use log::{error, trace, warn, LevelFilter};
+use scroll::Endian;
 use td_layout::build_time::TD_SHIM_PAYLOAD_SIZE;
-use td_shim_tools::signer::{PayloadSigner, SigningAlgorithm};
-use td_shim_tools::{InputData, OutputFile};
+use td_shim::secure_boot::{
+    HashAlg, SignedPayloadType, SvnPolicy, PAYLOAD_SIGN_HEADER_VERSION_LATEST,
+    SVN_POLICY_MAX_VERSIONS,
+};
+use td_shim_tools::audit::{AuditEntry, AuditLog};
+use td_shim_tools::batch::{parse_batch_manifest, BatchSigner};
+use td_shim_tools::cli_env::{env_vars, is_explicit, with_env_vars, SIGN_ENV_PREFIX};
+use td_shim_tools::cms::build_cms_signed_data;
+use td_shim_tools::components::{assemble_components, load_component_manifest};
+use td_shim_tools::diff::diff_signed_payloads;
+use td_shim_tools::enroller::{create_trust_anchor_file, enroll_files, parse_public_key};
+use td_shim_tools::gzip::gzip_encode;
+use td_shim_tools::keygen::generate_signing_key;
+use td_shim_tools::profile::{load_profile, SigningProfile};
+use td_shim_tools::reference::{measure_payload, predict_reference_values};
+use td_shim_tools::sign_command::{sign_payload_with_command, SignCommand};
+use td_shim_tools::signature::{convert_payload_signature, SigFormat};
+use td_shim_tools::signer::{
+    check_key_backend, check_key_change, check_pe_checksum, edit_header, fix_pe_checksum,
+    is_executable_payload, pad_signed_payload, parse_load_base, parse_payload_type,
+    unsigned_payload_range, PayloadSigner, RngSource, SigningAlgorithm,
+};
+use td_shim_tools::split::{assemble_signed_payload, parse_digest, prepare_signing, sign_digest};
+use td_shim_tools::svn_counter::SvnCounter;
+#[cfg(feature = "tsa")]
+use td_shim_tools::tsa::timestamp_signed_payload;
+use td_shim_tools::{check_output_path, InputData, OutputFile, STDOUT_OUTPUT};
 
 const SIGNED_TDPAYLOAD_NAME: &str = "td-payload-signed";
+const DEFAULT_PROFILE_FILE: &str = "td-shim-sign-profiles.toml";
+const DEFAULT_ALGORITHM: &str = "RSAPSS_3072_SHA384";
+
+// Get the value of an option only if it's given on the command line or by its environment
+// variable, instead of the default value.
+fn explicit_value(matches: &ArgMatches, id: &str) -> Option<String> {
+    if is_explicit(matches, id) {
+        matches.get_one::<String>(id).cloned()
+    } else {
+        None
+    }
+}
+
+// Print the environment variables setting the options of the sign command.
+fn list_env(command: &Command) {
+    for (name, option) in env_vars(command) {
+        println!("{:<40} {}", name, option);
+    }
+}
+
+fn parse_number(value: &str, desc: &str) -> io::Result<u64> {
+    u64::from_str(value).map_err(|e| {
+        error!("Invalid {} {}: {}", desc, value, e);
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid number")
+    })
+}
+
+fn write_output(name: &Path, data: &[u8], desc: &str) -> io::Result<()> {
+    let mut output = OutputFile::new(name)?;
+    output.seek_and_write(0, data, desc)?;
+    output.flush()
+}
+
+// Rewrite an ECDSA signature of a signed payload into another encoding, without the private key.
+fn convert_signature(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory or have default values.
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let slot = *matches.get_one::<usize>("slot").unwrap();
+    let to = SigFormat::from_str(matches.get_one::<String>("to").unwrap())?;
+
+    let signed_payload =
+        InputData::new(input, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "signed payload")?;
+    let converted = convert_payload_signature(signed_payload.as_bytes(), slot, to)?;
+
+    write_output(output, &converted, "signed payload")
+}
+
+// Build the verify header and the digest to be signed on another machine.
+fn prepare(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory or have default values.
+    let payload_file = matches.get_one::<String>("payload").unwrap();
+    let version = parse_number(matches.get_one::<String>("ver").unwrap(), "payload version")?;
+    let svn = parse_number(
+        matches.get_one::<String>("svn").unwrap(),
+        "security version number",
+    )?;
+    let algorithm = matches.get_one::<String>("algorithm").unwrap();
+    let endian = match matches.get_one::<String>("endian").unwrap().as_str() {
+        "be" => Endian::Big,
+        _ => Endian::Little,
+    };
+    let header_file = matches.get_one::<PathBuf>("header-output").unwrap();
+
+    let payload = InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?;
+    let request = prepare_signing(payload.as_bytes(), algorithm, endian, version, svn)?;
+    write_output(header_file, &request.header.to_bytes(), "verify header")?;
+
+    let digest = hex::encode(&request.digest);
+    if let Some(digest_file) = matches.get_one::<PathBuf>("digest-output") {
+        write_output(digest_file, format!("{}\n", digest).as_bytes(), "digest")?;
+    }
+    println!("{}", digest);
+
+    Ok(())
+}
+
+// Print the measurement of a payload, which doesn't need the key nor produce a signed payload.
+fn measure(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because it's mandatory.
+    let payload_file = matches.get_one::<String>("payload").unwrap();
+    let rtmr_index = matches.get_one::<u32>("rtmr-index").copied();
+    let prev = match matches.get_one::<String>("prev") {
+        Some(v) => {
+            let mut prev = [0u8; 48];
+            prev.copy_from_slice(&parse_digest(v.as_bytes())?);
+            Some(prev)
+        }
+        None => None,
+    };
+
+    let payload = InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?;
+    let measurement = measure_payload(payload.as_bytes(), rtmr_index, prev.as_ref())?;
+    println!("{}", measurement);
+
+    Ok(())
+}
+
+// Sign a digest computed on another machine, so the payload never reaches the signing machine.
+fn sign_digest_command(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory or have default values.
+    let private_file = matches.get_one::<String>("key").unwrap();
+    let algorithm = matches.get_one::<String>("algorithm").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let digest = match matches.get_one::<String>("digest") {
+        Some(v) => parse_digest(v.as_bytes())?,
+        None => {
+            // Safe to unwrap() because either --digest or --digest-file is required.
+            let digest_file = matches.get_one::<String>("digest-file").unwrap();
+            parse_digest(InputData::new(digest_file, 0..=4096, "digest")?.as_bytes())?
+        }
+    };
+
+    check_key_backend(private_file)?;
+    let mut private = InputData::new(private_file, 0..=1024 * 1024, "private key")?;
+    let result = sign_digest(algorithm, private.as_bytes(), &digest);
+    // Clear the private key memory.
+    private.clear();
+
+    write_output(output, &result?, "signature")
+}
+
+// Put the verify header, the payload and the external signature together.
+fn assemble(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory.
+    let payload_file = matches.get_one::<String>("payload").unwrap();
+    let header_file = matches.get_one::<String>("header").unwrap();
+    let signature_file = matches.get_one::<String>("signature").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+
+    let payload = InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?;
+    let header = InputData::new(header_file, 0..=4096, "verify header")?;
+    let signature = InputData::new(signature_file, 0..=4096, "signature")?;
+    let signed_payload =
+        assemble_signed_payload(header.as_bytes(), payload.as_bytes(), signature.as_bytes())?;
+
+    write_output(output, &signed_payload, "signed payload")
+}
+
+// Edit the version or SVN of a signed payload, which is only allowed along with re-signing.
+fn edit_header_command(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory or have default values.
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<PathBuf>("output").unwrap();
+    let algorithm = matches.get_one::<String>("algorithm").unwrap();
+    let version = match matches.get_one::<String>("ver") {
+        Some(v) => Some(parse_number(v, "payload version")?),
+        None => None,
+    };
+    let svn = match matches.get_one::<String>("svn") {
+        Some(v) => Some(parse_number(v, "security version number")?),
+        None => None,
+    };
+
+    let signed_payload =
+        InputData::new(input, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "signed payload")?;
+    // The key is only loaded for re-signing, edit_header() refuses to edit without it.
+    let mut private = None;
+    let algorithm = match matches.get_one::<String>("key") {
+        Some(private_file) if matches.get_flag("resign") => {
+            check_key_backend(private_file)?;
+            let private = private.insert(InputData::new(
+                private_file,
+                0..=1024 * 1024,
+                "private key",
+            )?);
+            Some(SigningAlgorithm::from_pkcs8(algorithm, private.as_bytes())?)
+        }
+        _ => None,
+    };
+    let result = edit_header(signed_payload.as_bytes(), version, svn, algorithm);
+    // Clear the private key memory.
+    if let Some(private) = private.as_mut() {
+        private.clear();
+    }
+
+    write_output(output, &result?, "signed payload")
+}
+
+// Sign the payload with an external signing command, which is given the digest of the signed
+// region, instead of a private key file.
+fn sign_with_command(matches: &ArgMatches, template: &str) -> io::Result<()> {
+    // The key file is omitted from the positional arguments.
+    let positionals: Vec<&String> = ["key", "payload", "ver", "svn"]
+        .iter()
+        .filter_map(|id| matches.get_one::<String>(id))
+        .collect();
+    if positionals.len() != 3 {
+        error!("Unexpected number of positional arguments, no private key file is expected");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unexpected number of positional arguments",
+        ));
+    }
+    let payload_file = positionals[0].as_str();
+    let version = parse_number(positionals[1], "payload version")?;
+    let svn = parse_number(positionals[2], "security version number")?;
+    // Safe to unwrap() because they are mandatory or have default values.
+    let algorithm = matches.get_one::<String>("algorithm").unwrap();
+    let endian = match matches.get_one::<String>("endian").unwrap().as_str() {
+        "be" => Endian::Big,
+        _ => Endian::Little,
+    };
+    let public_key_file = matches.get_one::<String>("pubkey").unwrap();
+    let output_file = match matches.get_one::<PathBuf>("output") {
+        Some(v) => v.clone(),
+        None => {
+            let p = Path::new(payload_file).canonicalize().map_err(|e| {
+                error!("Invalid payload file path {}: {}", payload_file, e);
+                e
+            })?;
+            p.parent()
+                .unwrap_or(Path::new("/"))
+                .join(SIGNED_TDPAYLOAD_NAME)
+        }
+    };
+    check_output_path(&output_file, &[(Path::new(public_key_file), "public key")])?;
+
+    let mut command = SignCommand::new(template)?;
+    if let Some(key_id) = matches.get_one::<String>("key-id") {
+        command.set_key_id(key_id);
+    }
+    let public_key = InputData::new(public_key_file, 1..=1024 * 1024, "public key")?;
+    let public_key = parse_public_key(public_key.as_bytes(), public_key_file)?;
+    let payload = InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?;
+    if !is_executable_payload(payload.as_bytes()) {
+        if matches.get_flag("strict") {
+            error!("Payload {} is not a PE or ELF image", payload_file);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload is not a PE or ELF image",
+            ));
+        }
+        warn!(
+            "Payload {} does not look like a PE or ELF image, is it the right file?",
+            payload_file
+        );
+    }
+
+    trace!("Signing {} with command {}", payload_file, template);
+    let signed_image = sign_payload_with_command(
+        &command,
+        payload.as_bytes(),
+        algorithm,
+        &public_key,
+        endian,
+        version,
+        svn,
+    )?;
+
+    let mut output = OutputFile::new(&output_file)?;
+    if let Some(max_size) = matches.get_one::<u64>("max-output-size") {
+        output.set_max_size(*max_size);
+    }
+    if matches.get_flag("gzip-output") {
+        output.seek_and_write(0, &gzip_encode(&signed_image)?, "gzipped signed payload")?;
+    } else {
+        output.seek_and_write(0, &signed_image, "signed payload")?;
+    }
+    output.flush()
+}
 
 fn main() -> io::Result<()> {
     let env = Env::default()
@@ -25,30 +312,498 @@ fn main() -> io::Result<()> {
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
-    let matches = command!()
+    let command = command!()
         .about("Sign shim payload with given private key")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("prepare")
+                .about("Build the verify header and the SHA384 digest to be signed by sign-digest")
+                .arg(arg!([payload] "payload binary file").required(true))
+                .arg(arg!([ver] "payload version number").required(true))
+                .arg(arg!([svn] "security version number").required(true))
+                .arg(
+                    arg!(-A --algorithm "message signing algorithm: ['RSAPSS_3072_SHA384', 'ECDSA_NIST_P384_SHA384']")
+                        .required(false)
+                        .default_value(DEFAULT_ALGORITHM)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--endian "byte order of multi-byte fields in the verify header: ['le', 'be']")
+                        .required(false)
+                        .default_value("le")
+                        .value_parser(["le", "be"])
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--"header-output" "output of the verify header")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--"digest-output" "output of the digest in hex, it's printed to stdout anyway")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("sign-digest")
+                .about("Sign a SHA384 digest generated by prepare, the output is the signature block")
+                .arg(arg!([key] "private key file to sign the digest").required(true))
+                .arg(
+                    arg!(--digest "SHA384 digest in hex")
+                        .required_unless_present("digest-file")
+                        .conflicts_with("digest-file")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--"digest-file" "file containing the SHA384 digest, in hex or raw bytes")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-A --algorithm "message signing algorithm: ['RSAPSS_3072_SHA384', 'ECDSA_NIST_P384_SHA384']")
+                        .required(false)
+                        .default_value(DEFAULT_ALGORITHM)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-o --output "output of the signature block")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("assemble")
+                .about("Assemble a signed payload from the payload, the verify header and the signature block")
+                .arg(arg!([payload] "payload binary file").required(true))
+                .arg(
+                    arg!(--header "verify header generated by prepare")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--signature "signature block generated by sign-digest")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-o --output "output of the signed payload")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("edit-header")
+                .about("Edit the version or SVN of a signed payload, which requires re-signing it with --resign and --key")
+                .arg(arg!([input] "signed payload file").required(true))
+                .arg(
+                    arg!(--ver "new payload version number")
+                        .required_unless_present("svn")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--svn "new security version number")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--resign "sign the edited payload again, the signature is invalidated otherwise")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--key "private key file to sign the edited payload")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-A --algorithm "message signing algorithm: ['RSAPSS_3072_SHA384', 'ECDSA_NIST_P384_SHA384']")
+                        .required(false)
+                        .default_value(DEFAULT_ALGORITHM)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-o --output "output of the edited signed payload")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("measure")
+                .about("Print the SHA384 measurement of a payload or signed payload, without signing it")
+                .arg(arg!([payload] "payload binary file or signed payload file").required(true))
+                .arg(
+                    arg!(--prev "value of the RTMR before the payload is measured in hex, the extended RTMR is printed")
+                        .required(false)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--"rtmr-index" "index of the RTMR the payload is measured into, 0 to 3, default to the one recorded by the verify header or RTMR[1]")
+                        .required(false)
+                        .value_parser(value_parser!(u32).range(0..=3))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .subcommand(
+            Command::new("list-env")
+                .about("List the environment variables setting the options of the sign command"),
+        )
+        .subcommand(
+            Command::new("convert-signature")
+                .about("Convert an ECDSA signature of a signed payload between the fixed and ASN.1 encodings")
+                .arg(arg!([input] "signed payload file").required(true))
+                .arg(
+                    arg!(--to "target encoding of the signature: ['fixed', 'asn1']")
+                        .required(true)
+                        .value_parser(["fixed", "asn1"])
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--slot "index of the signature to convert, 0 is the primary signature")
+                        .required(false)
+                        .default_value("0")
+                        .value_parser(value_parser!(usize))
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(-o --output "output of the converted signed payload")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
         .arg(
-            arg!([key] "private key file to sign the payload")
-                .required(true)
+            arg!([key] "private key file to sign the payload, may be omitted if given by the profile or --key")
+                .required_unless_present_any(["profile", "generate-key", "sign-command", "key-file", "format-version"])
         )
         .arg(
             arg!([payload] "payload binary file")
-                .required(true)
+                .required_unless_present_any(["batch", "profile", "generate-key", "sign-command", "key-file", "format-version"])
         )
         .arg(
             arg!([ver] "payload version number")
-                .required(true)
-                .value_parser(value_parser!(u64)),
+                .required_unless_present_any(["batch", "profile", "generate-key", "sign-command", "key-file", "format-version"])
         )
         .arg(
             arg!([svn] "security version number")
-                .required(true)
-                .value_parser(value_parser!(u64)),
+                .required_unless_present_any(["batch", "profile", "generate-key", "sign-command", "key-file", "format-version"])
+        )
+        .arg(
+            arg!(--"format-version" "print the newest verify header format version emitted, and exit. Version 1 headers are emitted unless a field of a newer version is used")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("key-file")
+                .long("key")
+                .help("private key file to sign the payload, instead of the first positional argument")
+                .required(false)
+                .conflicts_with_all(["generate-key", "sign-command"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--profile "name of the signing profile to apply, explicit options override the profile")
+                .required(false)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"profile-file" "TOML file containing the signing profiles")
+                .required(false)
+                .default_value(DEFAULT_PROFILE_FILE)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--batch "sign all payloads listed in the batch manifest file")
+                .required(false)
+                .conflicts_with_all([
+                    "payload",
+                    "output",
+                    "policy-min-svn",
+                    "policy-version",
+                    "check-pe-checksum",
+                    "fix-pe-checksum",
+                ])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--progress "print progress of the batch job to stderr")
+                .required(false)
+                .requires("batch")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--threads "number of payloads of the batch job signed concurrently, default to the available parallelism")
+                .required(false)
+                .requires("batch")
+                .value_parser(value_parser!(u64).range(1..=1024))
+                .action(ArgAction::Set),
         )
         .arg(
             arg!(-A --algorithm "message signing algorithm: ['RSAPSS_3072_SHA384', 'ECDSA_NIST_P384_SHA384']")
                 .required(false)
-                .default_value("RSAPSS_3072_SHA384")
+                .default_value(DEFAULT_ALGORITHM)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--endian "byte order of multi-byte fields in the verify header: ['le', 'be']")
+                .required(false)
+                .default_value("le")
+                .value_parser(["le", "be"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--format "format of the signed payload, 'compact' records no version, SVN or SVN policy: ['standard', 'compact']")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(["standard", "compact"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--digest "digest of the signed region, default to the one of the signing algorithm, only RSAPSS_3072_SHA384 accepts others: ['sha256', 'sha384', 'sha512']")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(["sha256", "sha384", "sha512"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"payload-type" "type of the payload recorded in the verify header: ['unspecified', 'executable', 'linux-kernel', 'migration-td', 'multi-component'] or a custom id in 0x80..=0xff")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--components "the payload file is a TOML component manifest, sign the multi-component payload assembled from the listed files")
+                .required(false)
+                .conflicts_with_all([
+                    "batch",
+                    "resign",
+                    "payload-type",
+                    "check-pe-checksum",
+                    "fix-pe-checksum",
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"load-base" "load base of the relocatable payload recorded in the verify header, aligned to 4 KiB, default to letting the loader choose")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"rtmr-index" "index of the RTMR td-shim measures the payload into recorded in the verify header, 0 to 3, default to RTMR[1]")
+                .required(false)
+                .conflicts_with_all(["batch", "components"])
+                .value_parser(value_parser!(u32).range(0..=3))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--normalize "zero the reserved fields and the signature padding, so the signed payload is in the canonical form")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--rng "source of random numbers used for signing: ['system', 'file:PATH']")
+                .required(false)
+                .default_value("system")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--strict "fail if the payload is not a PE or ELF image accepted by the loader, or the signing key is weak")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"check-pe-checksum" "fail if the checksum in the PE header of the payload is wrong")
+                .required(false)
+                .conflicts_with("fix-pe-checksum")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"fix-pe-checksum" "recompute the checksum in the PE header of the payload before signing")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"policy-min-svn" "attach an SVN policy with the minimum security version number accepted")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"policy-version" "payload version allowed by the attached SVN policy, may be repeated")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"max-output-size" "maximum size of the signed payload file in bytes")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"pad-to" "pad the signed payload with zeroes up to the size in bytes, such as the payload region size, the padding is not measured")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(value_parser!(u64).range(1..=TD_SHIM_PAYLOAD_SIZE as u64))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"compare-to" "signed payload file to compare the new signed payload with, the changed parts are printed before writing")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"compare-only" "only print the comparison with the --compare-to signed payload, don't write any output")
+                .required(false)
+                .requires("compare-to")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"gzip-output" "write the signed payload file gzip encoded for transport, the signed bytes are unchanged")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"svn-counter" "SVN counter file of the signing host, reject an SVN lower than the last signed one and advance it on success")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"audit-log" "append a record of each signing operation to the audit log file")
+                .required(false)
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--resign "the payload is a signed payload, strip its verify header and signatures and sign it again")
+                .required(false)
+                .conflicts_with("batch")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"require-key-change" "fail if the payload is re-signed with the key which signed it")
+                .required(false)
+                .requires("resign")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"generate-key" "sign with a new key pair of the algorithm generated in memory, instead of a private key file: ['RSAPSS_3072_SHA384', 'ECDSA_NIST_P384_SHA384']")
+                .required(false)
+                .conflicts_with_all(["batch", "profile"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"sign-command" "sign the digest with the external command built from the template, instead of a private key file")
+                .required(false)
+                .requires("pubkey")
+                .conflicts_with_all([
+                    "batch",
+                    "profile",
+                    "generate-key",
+                    "key-file",
+                    "pad-to",
+                    "format",
+                    "digest",
+                    "payload-type",
+                    "load-base",
+                    "rtmr-index",
+                    "normalize",
+                    "rng",
+                    "resign",
+                    "policy-min-svn",
+                    "policy-version",
+                    "svn-counter",
+                    "audit-log",
+                    "emit-reference-values",
+                    "emit-cbor",
+                    "cms",
+                    "components",
+                    "compare-to",
+                ])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--pubkey "DER encoded public key file of the key used by the signing command")
+                .required(false)
+                .requires("sign-command")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"key-id" "key identifier replacing {key_id} in the signing command template")
+                .required(false)
+                .requires("sign-command")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"export-public-key" "write the DER encoded public key of the generated key pair to the file")
+                .required(false)
+                .requires("generate-key")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"enroll-image" "shim binary file to enroll the public key hash of the generated key pair into")
+                .required(false)
+                .requires_all(["generate-key", "enroll-output"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"enroll-output" "output of the shim binary with the public key hash enrolled")
+                .required(false)
+                .requires("enroll-image")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"emit-reference-values" "write the MRTD and RTMRs predicted for booting the signed payload to the JSON file")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"emit-cbor" "write the reference values predicted for booting the signed payload to the CBOR file")
+                .required(false)
+                .conflicts_with("batch")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .group(
+            ArgGroup::new("reference-values")
+                .args(["emit-reference-values", "emit-cbor"])
+                .multiple(true),
+        )
+        .arg(
+            arg!(--"reference-image" "shim binary file the reference values are predicted for, default to --enroll-output")
+                .required(false)
+                .requires("reference-values")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--cms "also write the signature as a DER encoded CMS SignedData to the file")
+                .required(false)
+                .requires("cms-cert")
+                .conflicts_with("batch")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"cms-cert" "DER encoded X.509 certificate of the signing key, embedded into the CMS SignedData")
+                .required(false)
+                .requires("cms")
                 .action(ArgAction::Set),
         )
         .arg(
@@ -62,18 +817,343 @@ fn main() -> io::Result<()> {
                 .required(false)
                 .value_parser(value_parser!(PathBuf))
                 .action(ArgAction::Set),
+        );
+    #[cfg(feature = "tsa")]
+    let command = command
+        .arg(
+            arg!(--"tsa-url" "get an RFC 3161 timestamp token over the signature from the TSA at the http:// URL")
+                .required(false)
+                .conflicts_with_all(["batch", "sign-command"])
+                .action(ArgAction::Set),
         )
-        .get_matches();
+        .arg(
+            arg!(--"tsa-token" "output of the timestamp token, default to the signed payload file name with .tst appended")
+                .required(false)
+                .requires("tsa-url")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        );
+    let command = with_env_vars(command, SIGN_ENV_PREFIX);
+    let matches = command.clone().get_matches();
 
     if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
         log::set_max_level(lvl);
     }
 
-    let payload_file = matches.get_one::<String>("payload").unwrap().as_str();
-    let private_file = matches.get_one::<String>("key").unwrap().as_str();
-    let version = matches.get_one::<u64>("ver").unwrap().clone();
-    let svn = matches.get_one::<u64>("svn").unwrap().clone();
-    let algorithm = matches.get_one::<String>("algorithm").unwrap().as_str();
+    if matches.get_flag("format-version") {
+        println!("{}", PAYLOAD_SIGN_HEADER_VERSION_LATEST);
+        return Ok(());
+    }
+
+    match matches.subcommand() {
+        Some(("list-env", _)) => {
+            list_env(&command);
+            return Ok(());
+        }
+        Some(("convert-signature", matches)) => return convert_signature(matches),
+        Some(("measure", matches)) => return measure(matches),
+        Some(("prepare", matches)) => return prepare(matches),
+        Some(("sign-digest", matches)) => return sign_digest_command(matches),
+        Some(("assemble", matches)) => return assemble(matches),
+        Some(("edit-header", matches)) => return edit_header_command(matches),
+        _ => {}
+    }
+    if let Some(template) = matches.get_one::<String>("sign-command") {
+        return sign_with_command(&matches, template);
+    }
+
+    // The key file is omitted from the positional arguments if it's given by the profile.
+    let mut positionals: Vec<String> = ["key", "payload", "ver", "svn"]
+        .iter()
+        .filter_map(|id| matches.get_one::<String>(id).cloned())
+        .collect();
+    let expected = if matches.get_one::<String>("batch").is_some() {
+        1
+    } else {
+        4
+    };
+    let generate_key = matches.get_one::<String>("generate-key");
+    let key_file = if generate_key.is_none() && positionals.len() == expected {
+        Some(PathBuf::from(positionals.remove(0)))
+    } else {
+        None
+    };
+    // The key positional argument takes precedence over TD_SHIM_SIGN_KEY, but can't be given
+    // along with --key on the command line.
+    let key_file = match (key_file, matches.get_one::<String>("key-file")) {
+        (Some(_), Some(_))
+            if matches.value_source("key-file") == Some(ValueSource::CommandLine) =>
+        {
+            error!("The private key file is given both by --key and a positional argument");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unexpected number of positional arguments",
+            ));
+        }
+        (Some(v), _) => Some(v),
+        (None, v) => v.map(PathBuf::from),
+    };
+
+    let cli = SigningProfile {
+        algorithm: explicit_value(&matches, "algorithm"),
+        key: key_file,
+        endian: explicit_value(&matches, "endian"),
+        rng: explicit_value(&matches, "rng"),
+        strict: matches.get_flag("strict").then_some(true),
+        policy_min_svn: matches.get_one::<u64>("policy-min-svn").copied(),
+        policy_versions: matches
+            .get_many::<u64>("policy-version")
+            .map(|v| v.copied().collect()),
+        max_output_size: matches.get_one::<u64>("max-output-size").copied(),
+        rtmr_index: matches.get_one::<u32>("rtmr-index").copied(),
+        allowed_rtmr_indices: None,
+    };
+    let settings = match matches.get_one::<String>("profile") {
+        Some(name) => {
+            let profile_file = matches.get_one::<String>("profile-file").unwrap();
+            let profiles = fs::read_to_string(profile_file).map_err(|e| {
+                error!("Can not read signing profiles {}: {}", profile_file, e);
+                e
+            })?;
+            load_profile(&profiles, name)?.merge(cli)
+        }
+        None => cli,
+    };
+    settings.validate()?;
+    if positionals.len() + 1 != expected {
+        error!("Unexpected number of positional arguments");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unexpected number of positional arguments",
+        ));
+    }
+
+    let private_file = match (settings.key.as_ref(), generate_key) {
+        (Some(v), _) => v.display().to_string(),
+        (None, Some(_)) => "generated key".to_string(),
+        (None, None) => {
+            error!("No private key file is given by the command line or the profile");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no private key file",
+            ));
+        }
+    };
+    let algorithm = settings.algorithm.as_deref().unwrap_or(DEFAULT_ALGORITHM);
+    let strict = settings.strict.unwrap_or(false);
+    let rng = match settings.rng.as_deref() {
+        // Safe to unwrap() because settings have been validated.
+        Some(v) => RngSource::from_str(v).unwrap(),
+        None => RngSource::System,
+    };
+    let endian = match settings.endian.as_deref() {
+        Some("be") => Endian::Big,
+        _ => Endian::Little,
+    };
+    // The generated private key never leaves the memory.
+    let mut private = None;
+    let mut generated_public_key = None;
+    let algorithm = match generate_key {
+        Some(name) => {
+            let key = generate_signing_key(name)?;
+            generated_public_key = Some((key.algorithm.public_key_bytes(), key.public_key_der));
+            key.algorithm
+        }
+        None => {
+            check_key_backend(&private_file)?;
+            let private = private.insert(InputData::new(
+                &private_file,
+                0..=1024 * 1024,
+                "private key",
+            )?);
+            SigningAlgorithm::from_pkcs8(algorithm, private.as_bytes()).map_err(|e| {
+                error!("Can not load private key from {}", private_file);
+                e
+            })?
+        }
+    };
+
+    let digest = match matches.get_one::<String>("digest") {
+        Some(v) => {
+            // Safe to unwrap() because the value parser only accepts known digests.
+            let digest = HashAlg::from_str(v).unwrap();
+            algorithm.check_digest(digest)?;
+            Some(digest)
+        }
+        None => None,
+    };
+    let signing_digest = digest.unwrap_or_else(|| algorithm.default_digest());
+    algorithm.enforce_strength(signing_digest, strict)?;
+    // Only the default digest is supported by the compact format and the CMS SignedData.
+    let custom_digest = signing_digest != algorithm.default_digest();
+
+    if let Some(manifest) = matches.get_one::<String>("batch") {
+        let manifest = fs::read_to_string(manifest).map_err(|e| {
+            error!("Can not read batch manifest {}: {}", manifest, e);
+            e
+        })?;
+        let entries = parse_batch_manifest(&manifest)?;
+        let progress = matches.get_flag("progress");
+        // Payloads signed in batch are measured into the default RTMR.
+        if settings.rtmr_index.is_some() {
+            error!("RTMR index can not be recorded by payloads signed in batch");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "RTMR index is not supported by batch signing",
+            ));
+        }
+        settings.check_rtmr_permitted(settings.payload_rtmr_index())?;
+
+        let mut signer = BatchSigner::new(algorithm);
+        signer.set_endian(endian);
+        signer.set_rng(rng);
+        if let Some(max_size) = settings.max_output_size {
+            signer.set_max_output_size(max_size);
+        }
+        if let Some(audit_log) = matches.get_one::<PathBuf>("audit-log") {
+            signer.set_audit_log(AuditLog::new(audit_log));
+        }
+        if let Some(threads) = matches.get_one::<u64>("threads") {
+            signer.set_threads(*threads as usize);
+        }
+        let results = signer.sign_with_progress(&entries, |p| {
+            if progress {
+                match p.result {
+                    Ok(_) => eprintln!("signed {}/{}: {}", p.completed, p.total, p.entry.name()),
+                    Err(e) => eprintln!(
+                        "failed {}/{}: {}: {}",
+                        p.completed,
+                        p.total,
+                        p.entry.name(),
+                        e
+                    ),
+                }
+            }
+        });
+
+        // Clear the private key memory.
+        if let Some(private) = private.as_mut() {
+            private.clear();
+        }
+
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        if failed != 0 {
+            error!("Failed to sign {} of {} payloads", failed, results.len());
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to sign some payloads",
+            ));
+        }
+
+        return Ok(());
+    }
+
+    let payload_file = positionals[0].as_str();
+    let version = parse_number(&positionals[1], "payload version")?;
+    let svn = parse_number(&positionals[2], "security version number")?;
+    let policy_versions: Vec<u64> = settings.policy_versions.clone().unwrap_or_default();
+    let policy = match settings.policy_min_svn {
+        Some(min_svn) => Some(SvnPolicy {
+            min_svn,
+            allowed_versions: policy_versions,
+        }),
+        None if !policy_versions.is_empty() => Some(SvnPolicy {
+            min_svn: 0,
+            allowed_versions: policy_versions,
+        }),
+        None => None,
+    };
+    if let Some(policy) = policy.as_ref() {
+        if policy.allowed_versions.len() > SVN_POLICY_MAX_VERSIONS {
+            error!(
+                "Too many allowed versions in SVN policy: {}, max {}",
+                policy.allowed_versions.len(),
+                SVN_POLICY_MAX_VERSIONS
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many allowed versions in SVN policy",
+            ));
+        }
+        if !policy.is_allowed(svn, version) {
+            error!(
+                "Payload version {} svn {} is rejected by its own SVN policy",
+                version, svn
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload is rejected by its own SVN policy",
+            ));
+        }
+    }
+    let components = matches.get_flag("components");
+    let payload_type = if components {
+        Some(SignedPayloadType::MultiComponent)
+    } else {
+        matches
+            .get_one::<String>("payload-type")
+            .map(|v| parse_payload_type(v))
+            .transpose()?
+    };
+    let load_base = matches
+        .get_one::<String>("load-base")
+        .map(|v| parse_load_base(v))
+        .transpose()?;
+    let compact = matches.get_one::<String>("format").map(|v| v.as_str()) == Some("compact");
+    if compact {
+        let mut unsupported = Vec::new();
+        if policy.is_some() {
+            unsupported.push("SVN policy");
+        }
+        if components {
+            unsupported.push("--components");
+        } else if payload_type.is_some() {
+            unsupported.push("--payload-type");
+        }
+        if load_base.is_some() {
+            unsupported.push("--load-base");
+        }
+        if settings.rtmr_index.is_some() {
+            unsupported.push("--rtmr-index");
+        }
+        if custom_digest {
+            unsupported.push("--digest");
+        }
+        if endian == Endian::Big {
+            unsupported.push("big-endian verify header");
+        }
+        if matches.get_one::<PathBuf>("svn-counter").is_some() {
+            unsupported.push("--svn-counter");
+        }
+        if matches.get_one::<PathBuf>("cms").is_some() {
+            unsupported.push("--cms");
+        }
+        #[cfg(feature = "tsa")]
+        if matches.get_one::<String>("tsa-url").is_some() {
+            unsupported.push("--tsa-url");
+        }
+        if !unsupported.is_empty() {
+            error!(
+                "The compact format does not support: {}",
+                unsupported.join(", ")
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "option not supported by the compact format",
+            ));
+        }
+        warn!(
+            "The compact format does not record payload version {} and svn {}",
+            version, svn
+        );
+    }
+    if custom_digest && matches.get_one::<PathBuf>("cms").is_some() {
+        error!("The CMS SignedData only supports the default digest of the signing algorithm");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "digest not supported by the CMS SignedData",
+        ));
+    }
     let output_file = match matches.get_one::<PathBuf>("output") {
         Some(v) => v.clone(),
         None => {
@@ -96,48 +1176,270 @@ fn main() -> io::Result<()> {
         private_file
     );
 
-    let payload = InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?;
-    let mut private = InputData::new(private_file, 0..=1024 * 1024, "private key")?;
-    let algorithm = match algorithm {
-        "RSAPSS_3072_SHA384" => {
-            let rsa_key_pair = RsaKeyPair::from_pkcs8(private.as_bytes()).map_err(|e| {
-                error!("Can not load RSA private key from {}: {}", private_file, e);
-                io::Error::new(io::ErrorKind::Other, "Can not load RSA private key")
-            })?;
-            SigningAlgorithm::Rsapss3072Sha384(rsa_key_pair)
-        }
-        "ECDSA_NIST_P384_SHA384" => {
-            let ecdsa_key_pair =
-                EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, private.as_bytes())
-                    .map_err(|e| {
-                        error!("Can not load DSA private key from {}: {}", private_file, e);
-                        io::Error::new(io::ErrorKind::Other, "Can not load DSA private key")
-                    })?;
-            SigningAlgorithm::EcdsaNistP384Sha384(ecdsa_key_pair)
-        }
-        _ => {
-            error!("Unsupported signing algorithm: {}", algorithm);
+    let cms_cert = match matches.get_one::<String>("cms-cert") {
+        Some(v) => Some(InputData::new(
+            v,
+            1..=1024 * 1024,
+            "CMS signer certificate",
+        )?),
+        None => None,
+    };
+
+    // The payload may be signed in place, it's read completely before the output is created.
+    let mut inputs = Vec::new();
+    if generate_key.is_none() {
+        inputs.push((Path::new(&private_file), "private key"));
+    }
+    if let Some(cert) = matches.get_one::<String>("cms-cert") {
+        inputs.push((Path::new(cert), "CMS signer certificate"));
+    }
+    check_output_path(&output_file, &inputs)?;
+    inputs.push((Path::new(payload_file), "payload"));
+    inputs.push((&output_file, "signed payload"));
+    if let Some(cms_file) = matches.get_one::<PathBuf>("cms") {
+        check_output_path(cms_file, &inputs)?;
+    }
+    #[cfg(feature = "tsa")]
+    let tsa_token = match matches.get_one::<String>("tsa-url") {
+        Some(url) => {
+            let token_file = match matches.get_one::<PathBuf>("tsa-token") {
+                Some(v) => v.clone(),
+                None => {
+                    let mut name = output_file.clone().into_os_string();
+                    name.push(".tst");
+                    PathBuf::from(name)
+                }
+            };
+            check_output_path(&token_file, &inputs)?;
+            Some((url, token_file))
+        }
+        None => None,
+    };
+
+    // Hold the counter until the signed payload is written, to serialize concurrent signings. It is
+    // never taken for the compact format, which records no SVN and rejects --svn-counter above.
+    let svn_counter = match matches.get_one::<PathBuf>("svn-counter") {
+        Some(path) => {
+            let counter = SvnCounter::lock(path)?;
+            counter.check(svn)?;
+            Some(counter)
+        }
+        None => None,
+    };
+
+    // The table of contents gives the RTMR of each component of a multi-component payload.
+    let mut payload = if components {
+        if settings.rtmr_index.is_some() {
+            error!(
+                "The RTMR index of a multi-component payload is given by the component manifest"
+            );
             return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Unsupported signing algorithm",
+                io::ErrorKind::InvalidInput,
+                "RTMR index is not supported by multi-component payloads",
             ));
         }
+        let manifest = load_component_manifest(Path::new(payload_file))?;
+        for component in manifest.iter() {
+            settings.check_rtmr_permitted(component.rtmr_index)?;
+        }
+        InputData::from(assemble_components(&manifest)?)
+    } else {
+        settings.check_rtmr_permitted(settings.payload_rtmr_index())?;
+        InputData::new(payload_file, 0..=TD_SHIM_PAYLOAD_SIZE as usize, "payload")?
     };
+    if matches.get_flag("resign") {
+        if matches.get_flag("require-key-change") {
+            check_key_change(&algorithm, payload.as_bytes())?;
+        }
+        let range = unsigned_payload_range(payload.as_bytes())?;
+        trace!(
+            "Re-signing payload at 0x{:x}..0x{:x} of {}",
+            range.start,
+            range.end,
+            payload_file
+        );
+        payload.keep_range(range);
+    }
+    // The executable component of a multi-component payload is checked by the loader.
+    if !components && !is_executable_payload(payload.as_bytes()) {
+        if strict {
+            error!("Payload {} is not a PE or ELF image", payload_file);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload is not a PE or ELF image",
+            ));
+        }
+        warn!(
+            "Payload {} does not look like a PE or ELF image, is it the right file?",
+            payload_file
+        );
+    }
+
+    if matches.get_flag("check-pe-checksum") {
+        check_pe_checksum(payload.as_bytes())?;
+    }
+    if matches.get_flag("fix-pe-checksum") {
+        let checksum = fix_pe_checksum(payload.as_mut_bytes())?;
+        trace!(
+            "Updated PE checksum of {} to 0x{:x}",
+            payload_file,
+            checksum
+        );
+    }
 
     // 1) Generate the verify header and write into the start of signed image
     // 2) Sign the data(verify header | payload binary)
     // 3) Put the public key bytes and signature at the end of the signed imgae.
+    trace!(
+        "Signing {} with {}",
+        payload_file,
+        algorithm.signature_name(signing_digest)
+    );
     let mut signer = PayloadSigner::new(payload.as_bytes(), algorithm);
-    let header = signer.build_header(version, svn);
-    let signed_image = signer.sign(header)?;
+    signer.set_endian(endian);
+    signer.set_rng(rng);
+    signer.set_normalize(matches.get_flag("normalize"));
+    if let Some(digest) = digest {
+        signer.set_digest(digest)?;
+    }
+    if let Some(payload_type) = payload_type {
+        signer.set_payload_type(payload_type);
+    }
+    if let Some(load_base) = load_base {
+        signer.set_load_base(load_base)?;
+    }
+    if let Some(rtmr_index) = settings.rtmr_index {
+        signer.set_rtmr_index(rtmr_index)?;
+    }
+    if let Some(policy) = policy {
+        signer.set_svn_policy(policy);
+    }
+    let result = if compact {
+        signer.sign_compact()
+    } else {
+        let header = signer.build_header(version, svn);
+        trace!("Verify header: {}", header);
+        signer.sign(header)
+    };
+    let result = result.map(|v| v.to_vec()).and_then(|mut signed_image| {
+        if let Some(size) = matches.get_one::<u64>("pad-to") {
+            pad_signed_payload(&mut signed_image, *size as usize)?;
+        }
+        Ok(signed_image)
+    });
 
     // Clear the private key memory.
-    private.clear();
+    if let Some(private) = private.as_mut() {
+        private.clear();
+    }
+
+    // Show what changes against the reference signed payload before writing anything.
+    let result = result.and_then(|signed_image| {
+        if let Some(reference_file) = matches.get_one::<String>("compare-to") {
+            let reference = InputData::new(
+                reference_file,
+                0..=TD_SHIM_PAYLOAD_SIZE as usize,
+                "reference signed payload",
+            )?;
+            let report = diff_signed_payloads(reference.as_bytes(), &signed_image)?;
+            // Keep the report out of the signed payload when it's written to stdout.
+            if output_file == Path::new(STDOUT_OUTPUT) && !matches.get_flag("compare-only") {
+                eprintln!("{}", report);
+            } else {
+                println!("{}", report);
+            }
+        }
+        Ok(signed_image)
+    });
+    if matches.get_flag("compare-only") {
+        return result.map(|_| ());
+    }
 
     // Create and write the signed payload image.
-    let mut output = OutputFile::new(output_file)?;
-    output.seek_and_write(0, signed_image, "signed payload")?;
-    output.flush()?;
+    let result = result.and_then(|signed_image| {
+        let mut output = OutputFile::new(&output_file)?;
+        if let Some(max_size) = settings.max_output_size {
+            output.set_max_size(max_size);
+        }
+        if matches.get_flag("gzip-output") {
+            output.seek_and_write(0, &gzip_encode(&signed_image)?, "gzipped signed payload")?;
+        } else {
+            output.seek_and_write(0, &signed_image, "signed payload")?;
+        }
+        output.flush()?;
+        Ok(signed_image)
+    });
+    let result = result.and_then(|signed_image| {
+        if let (Some(cms_file), Some(cert)) = (matches.get_one::<PathBuf>("cms"), &cms_cert) {
+            let cms = build_cms_signed_data(&signed_image, cert.as_bytes())?;
+            write_output(cms_file, &cms, "CMS SignedData")?;
+        }
+        Ok(signed_image)
+    });
+    #[cfg(feature = "tsa")]
+    let result = result.and_then(|signed_image| {
+        if let Some((url, token_file)) = tsa_token.as_ref() {
+            let info = timestamp_signed_payload(url, &signed_image, token_file)?;
+            trace!("Signature timestamped at {}", info.gen_time);
+        }
+        Ok(signed_image)
+    });
+    let result = result.and_then(|signed_image| {
+        if let Some((public_key, public_key_der)) = generated_public_key.as_ref() {
+            if let Some(public_key_file) = matches.get_one::<PathBuf>("export-public-key") {
+                write_output(public_key_file, public_key_der, "public key")?;
+            }
+            if let (Some(image), Some(enrolled)) = (
+                matches.get_one::<String>("enroll-image"),
+                matches.get_one::<PathBuf>("enroll-output"),
+            ) {
+                let trust_anchor = create_trust_anchor_file(public_key, "SHA384")?;
+                enroll_files(image, enrolled.clone(), vec![trust_anchor])?;
+            }
+        }
+        Ok(signed_image)
+    });
+    let result = result.and_then(|signed_image| {
+        let reference_file = matches.get_one::<PathBuf>("emit-reference-values");
+        let cbor_file = matches.get_one::<PathBuf>("emit-cbor");
+        if reference_file.is_some() || cbor_file.is_some() {
+            let shim_image = matches
+                .get_one::<PathBuf>("reference-image")
+                .or_else(|| matches.get_one::<PathBuf>("enroll-output"))
+                .ok_or_else(|| {
+                    error!("--emit-reference-values and --emit-cbor require --reference-image or --enroll-output");
+                    io::Error::new(io::ErrorKind::InvalidInput, "no shim image")
+                })?;
+            let values = predict_reference_values(shim_image, &signed_image)?;
+            if let Some(reference_file) = reference_file {
+                write_output(
+                    reference_file,
+                    values.to_json()?.as_bytes(),
+                    "reference values",
+                )?;
+            }
+            if let Some(cbor_file) = cbor_file {
+                write_output(cbor_file, &values.to_cbor()?, "CBOR reference values")?;
+            }
+        }
+        Ok(signed_image)
+    });
+    let result = result.and_then(|signed_image| {
+        if let Some(counter) = svn_counter.as_ref() {
+            counter.advance(svn)?;
+        }
+        Ok(signed_image)
+    });
 
-    Ok(())
+    if let Some(audit_log) = matches.get_one::<PathBuf>("audit-log") {
+        let algorithm = signer.into_algorithm();
+        AuditLog::new(audit_log).append(AuditEntry::new(
+            payload.as_bytes(),
+            &algorithm,
+            signing_digest,
+            result.as_deref(),
+        ))?;
+    }
+
+    result.map(|_| ())
 }
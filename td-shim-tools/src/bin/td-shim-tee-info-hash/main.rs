@@ -10,11 +10,15 @@ use log::{error, LevelFilter};
 use serde_json;
 use sha2::{Digest, Sha384};
 use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::{env, io, path::Path};
 use std::{fs, io::Write};
-use td_shim_tools::tee_info_hash::{Manifest, TdInfoStruct, SHA384_DIGEST_SIZE};
+use td_layout::build_time::{TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE};
+use td_shim_tools::tee_info_hash::{
+    predict_cfv_measurements, replay_rtmr, Manifest, TdInfoStruct, SHA384_DIGEST_SIZE,
+};
 
 const TEE_INFO_HASH_BIN: &str = "tee_info_hash.bin";
 const TDINFO_SIZE: usize = size_of::<TdInfoStruct>();
@@ -28,6 +32,8 @@ struct Config {
     pub output: PathBuf,
     // Log level
     pub log_level: String,
+    // Log the predicted measurement steps
+    pub measurements: bool,
 }
 
 #[derive(Debug)]
@@ -55,6 +61,11 @@ impl Config {
                     .value_parser(value_parser!(PathBuf))
                     .action(ArgAction::Set),
             )
+            .arg(
+                arg!(--measurements "log the measurement steps contributed by the CFV")
+                    .required(false)
+                    .action(ArgAction::SetTrue),
+            )
             .arg(
                 arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
                     .required(false)
@@ -76,16 +87,57 @@ impl Config {
         };
         let manifest = matches.get_one::<String>("manifest").unwrap().clone();
         let log_level = matches.get_one::<String>("log-level").unwrap().clone();
+        let measurements = matches.get_flag("measurements");
 
         Ok(Self {
             manifest,
             image,
             output,
             log_level,
+            measurements,
         })
     }
 }
 
+fn log_cfv_measurements(image: &mut File, image_size: u64) -> io::Result<()> {
+    if image_size != TD_SHIM_FIRMWARE_SIZE as u64 {
+        error!(
+            "Image size 0x{:x} doesn't match the td-shim firmware size, can not locate the CFV",
+            image_size
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can not locate the CFV",
+        ));
+    }
+
+    let mut cfv = vec![0u8; TD_SHIM_CONFIG_SIZE as usize];
+    image.seek(SeekFrom::Start(TD_SHIM_CONFIG_OFFSET as u64))?;
+    image.read_exact(&mut cfv)?;
+
+    let steps = predict_cfv_measurements(&cfv);
+    if steps.is_empty() {
+        log::info!("* CFV: no trust anchor, not measured");
+        return Ok(());
+    }
+    for step in steps.iter() {
+        log::info!(
+            "* CFV: {} <- {} (CFV offset 0x{:x}, size 0x{:x}) digest {}",
+            step.register,
+            step.description,
+            step.region.start,
+            step.region.len(),
+            hex::encode(step.digest)
+        );
+    }
+    log::info!(
+        "* CFV contribution to RTMR[0]: {}",
+        hex::encode(replay_rtmr(&steps, 0))
+    );
+
+    Ok(())
+}
+
 fn main() -> io::Result<()> {
     use env_logger::Env;
     let env = Env::default()
@@ -126,6 +178,10 @@ fn main() -> io::Result<()> {
     tee_info.build_mrtd(&mut image, image_size);
     log::info!("{}", &tee_info);
 
+    if config.measurements {
+        log_cfv_measurements(&mut image, image_size)?;
+    }
+
     log::info!(
         "* Generate tee hash info binary file {}",
         &config.output.display()
@@ -0,0 +1,74 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::ArgAction;
+use env_logger::Env;
+use log::{error, LevelFilter};
+use td_shim_tools::integrity::{HashManifest, IntegrityStatus};
+
+fn main() -> io::Result<()> {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let matches = command!()
+        .about("Check the SHA256 digest of images against a hash manifest")
+        .arg(
+            arg!([image] "image file, or directory of image files")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-m --manifest <FILE> "manifest of '<sha256 digest> <path>' lines")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        )
+        .get_matches();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+    }
+
+    // Safe to unwrap() because they are mandatory.
+    let image = matches.get_one::<PathBuf>("image").unwrap();
+    let manifest = HashManifest::from_file(matches.get_one::<PathBuf>("manifest").unwrap())?;
+
+    let results = manifest.check_path(image)?;
+    let mut failed = 0;
+    for (path, status) in results.iter() {
+        println!("{}: {}", path, status);
+        if *status != IntegrityStatus::Match {
+            failed += 1;
+        }
+    }
+
+    if failed != 0 {
+        error!(
+            "{} of {} images failed the integrity check",
+            failed,
+            results.len()
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "image integrity check failed",
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,434 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#[macro_use]
+extern crate clap;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{ArgAction, ArgMatches, Command};
+use env_logger::Env;
+use log::{error, LevelFilter};
+use ring::digest;
+use td_layout::build_time::{TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE};
+use td_shim::secure_boot::{
+    signing_algorithm_name, verify_trust_anchor, CompactPayloadVerifier, PayloadSignHeader,
+    PayloadVerifier, SignatureStatus, SignedPayloadFormat, VerifyErr,
+};
+use td_shim_tools::detached::verify_detached;
+use td_shim_tools::enroller::{check_key_fingerprint, parse_pem_public_key};
+use td_shim_tools::gzip::is_gzip;
+use td_shim_tools::reserved::{check_signed_payload, check_verify_header, report_reserved_fields};
+use td_shim_tools::stream_verify::StreamVerifier;
+use td_shim_tools::trust_store::{DirTrustStore, TrustStore};
+#[cfg(feature = "tsa")]
+use td_shim_tools::tsa::{check_timestamp_coverage, timestamped_data};
+use td_shim_tools::verify_report::VerifyReport;
+use td_shim_tools::InputData;
+
+// Print the report of a signed payload failing before its signatures are checked in the JSON
+// format, and pass the error on.
+fn report_error(json: bool, e: io::Error) -> io::Error {
+    if json {
+        if let Ok(report) = VerifyReport::from_error(&e.to_string()).to_json() {
+            println!("{}", report);
+        }
+    }
+    e
+}
+
+// Report a payload carrying no signature, which td-shim only boots if permitted by the build,
+// as a distinct status instead of an invalid signed payload.
+fn report_unsigned(payload_file: &str, json: bool) -> io::Error {
+    if json {
+        if let Ok(report) = VerifyReport::unsigned().to_json() {
+            println!("{}", report);
+        }
+    } else {
+        println!("Payload: UNSIGNED");
+    }
+    error!("Payload {} is unsigned", payload_file);
+    io::Error::new(io::ErrorKind::InvalidData, "payload is unsigned")
+}
+
+// Print the verification status of each signature.
+fn print_status(status: &[SignatureStatus], trust_store: Option<&DirTrustStore>) {
+    for s in status.iter() {
+        let algorithm = signing_algorithm_name(s.signing_algorithm)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| format!("unknown({:#x})", s.signing_algorithm));
+        let key = if s.public_key.is_empty() {
+            "none".to_string()
+        } else {
+            digest::digest(&digest::SHA384, s.public_key)
+                .as_ref()
+                .iter()
+                .map(|v| format!("{:02x}", v))
+                .collect()
+        };
+        let anchor = trust_store
+            .and_then(|v| v.find_anchor(s.public_key))
+            .map(|v| format!(", anchor {}", v))
+            .unwrap_or_default();
+        match s.result {
+            Ok(_) => println!(
+                "Signature {}: {}, key SHA384 {}{}: PASS",
+                s.slot, algorithm, key, anchor
+            ),
+            Err(e) => println!(
+                "Signature {}: {}, key SHA384 {}: FAIL ({:?})",
+                s.slot, algorithm, key, e
+            ),
+        }
+    }
+}
+
+// Print the verification status of each signature, or the report of the primary one in the JSON
+// format, and fail if the primary one is invalid.
+fn report_status(
+    status: &[SignatureStatus],
+    header: Option<&PayloadSignHeader>,
+    trust_store: Option<&DirTrustStore>,
+    json: bool,
+) -> io::Result<()> {
+    if json {
+        println!("{}", VerifyReport::new(header, &status[0]).to_json()?);
+    } else {
+        print_status(status, trust_store);
+    }
+
+    // td-shim only boots the payload with a valid primary signature.
+    if status[0].result.is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "primary signature verification fails",
+        ));
+    }
+
+    Ok(())
+}
+
+// Verify a detached signature, the verify header and the signature block generated by split
+// signing, against the payload.
+fn verify_detached_command(matches: &ArgMatches) -> io::Result<()> {
+    // Safe to unwrap() because they are mandatory.
+    let payload_file = matches.get_one::<String>("payload").unwrap();
+    let signature_file = matches.get_one::<String>("signature").unwrap();
+    let header_file = matches.get_one::<String>("header").unwrap();
+
+    let payload = InputData::new(payload_file, 0..=TD_SHIM_FIRMWARE_SIZE as usize, "payload")?;
+    let signature = InputData::new(signature_file, 1..=4096, "signature block")?;
+    let header = InputData::new(header_file, 1..=4096, "verify header")?;
+
+    let trust_store = match matches.get_one::<PathBuf>("trust-dir") {
+        Some(dir) => Some(DirTrustStore::load(dir)?),
+        None => None,
+    };
+    let trusted_key = match matches.get_one::<String>("pubkey") {
+        Some(pubkey_file) => {
+            let pem = InputData::new(pubkey_file, 1..=1024 * 1024, "public key")?;
+            let text = std::str::from_utf8(pem.as_bytes()).map_err(|_| {
+                error!("Public key {} is not a PEM file", pubkey_file);
+                io::Error::new(io::ErrorKind::InvalidData, "invalid public key")
+            })?;
+            Some(parse_pem_public_key(text, pubkey_file)?)
+        }
+        None => None,
+    };
+    let check_public_key = |public_key: &[u8]| match (trust_store.as_ref(), trusted_key.as_ref()) {
+        (Some(store), _) => store
+            .find_anchor(public_key)
+            .map(|_| ())
+            .ok_or(VerifyErr::InvalidPublicKey),
+        (None, Some(key)) if key.as_slice() == public_key => Ok(()),
+        _ => Err(VerifyErr::InvalidPublicKey),
+    };
+
+    let result = verify_detached(
+        header.as_bytes(),
+        payload.as_bytes(),
+        signature.as_bytes(),
+        check_public_key,
+    )?;
+    match result {
+        Ok(_) => {
+            println!("Detached signature: PASS");
+            Ok(())
+        }
+        Err(e) => {
+            println!("Detached signature: FAIL ({:?})", e);
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "detached signature verification fails",
+            ))
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let command = command!()
+        .about("Verify signatures of a signed shim payload")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("verify-detached")
+                .about("Verify a detached signature generated by split signing against the payload")
+                .arg(arg!([payload] "payload file").required(true))
+                .arg(
+                    arg!([signature] "signature block file, the public key followed by the signature")
+                        .required(true),
+                )
+                .arg(
+                    arg!(--header <FILE> "verify header file generated with the signature")
+                        .required(true)
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--pubkey <FILE> "PEM encoded public key trusted to sign the payload")
+                        .required_unless_present("trust-dir")
+                        .action(ArgAction::Set),
+                )
+                .arg(
+                    arg!(--"trust-dir" <DIR> "directory of trusted public keys, .pem or .hash files")
+                        .required(false)
+                        .conflicts_with("pubkey")
+                        .value_parser(value_parser!(PathBuf))
+                        .action(ArgAction::Set),
+                ),
+        )
+        .arg(arg!([payload] "signed payload file").required(true))
+        .arg(
+            arg!(-c --cfv "CFV file or shim binary file containing the trust anchor")
+                .required_unless_present_any(["trust-dir", "expect-key-sha256"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"trust-dir" "directory of trusted public keys, .pem or .hash files, used instead of the CFV")
+                .required(false)
+                .conflicts_with("cfv")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--"expect-key-sha256" "check that the payload is signed by the key with the SHA256 fingerprint in hex, the signature is only verified along with --cfv or --trust-dir")
+                .required(false)
+                .conflicts_with_all(["stream", "format"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--stream "verify the primary signature reading the signed payload in chunks, instead of buffering it")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"chunk-size" "number of bytes read at a time with --stream")
+                .required(false)
+                .requires("stream")
+                .default_value("65536")
+                .value_parser(value_parser!(usize))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(--strict "fail if reserved fields of the verify header or the SVN policy are not zero")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--format "output format, json prints the result of the primary signature as a JSON object: ['text', 'json']")
+                .required(false)
+                .default_value("text")
+                .value_parser(["text", "json"])
+                .action(ArgAction::Set),
+        )
+        .arg(
+            arg!(-l --"log-level" "logging level: [off, error, warn, info, debug, trace]")
+                .required(false)
+                .default_value("info")
+                .action(ArgAction::Set),
+        );
+    #[cfg(feature = "tsa")]
+    let command = command.arg(
+        arg!(--timestamp "RFC 3161 timestamp token file generated by td-shim-sign-payload --tsa-url, checked to cover the signature without verifying the signature of the TSA")
+            .required(false)
+            .conflicts_with("stream")
+            .action(ArgAction::Set),
+    );
+    let matches = command.get_matches();
+
+    if let Ok(lvl) = LevelFilter::from_str(matches.get_one::<String>("log-level").unwrap()) {
+        log::set_max_level(lvl);
+    }
+    if let Some(("verify-detached", matches)) = matches.subcommand() {
+        return verify_detached_command(matches);
+    }
+
+    // Safe to unwrap() because they are mandatory or have default values.
+    let payload_file = matches.get_one::<String>("payload").unwrap();
+    let json = matches.get_one::<String>("format").unwrap() == "json";
+    let strict = matches.get_flag("strict");
+    #[cfg(feature = "tsa")]
+    if json && matches.get_one::<String>("timestamp").is_some() {
+        error!("--timestamp is not supported by the json format");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--timestamp is not supported by the json format",
+        ));
+    }
+
+    let trust_store = match matches.get_one::<PathBuf>("trust-dir") {
+        Some(dir) => Some(DirTrustStore::load(dir)?),
+        None => None,
+    };
+    let cfv = match matches.get_one::<String>("cfv") {
+        Some(cfv_file) => Some(InputData::new(
+            cfv_file,
+            0..=TD_SHIM_FIRMWARE_SIZE as usize,
+            "CFV",
+        )?),
+        None => None,
+    };
+    // Extract the CFV if a whole shim binary is given.
+    let cfv = match cfv.as_ref().map(|v| v.as_bytes()) {
+        Some(cfv) if cfv.len() == TD_SHIM_FIRMWARE_SIZE as usize => {
+            &cfv[TD_SHIM_CONFIG_OFFSET as usize
+                ..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize]
+        }
+        Some(cfv) => cfv,
+        None => &[],
+    };
+    let check_public_key = |public_key: &[u8]| match trust_store.as_ref() {
+        Some(store) => store
+            .find_anchor(public_key)
+            .map(|_| ())
+            .ok_or(VerifyErr::InvalidPublicKey),
+        None => verify_trust_anchor(cfv, public_key),
+    };
+
+    if matches.get_flag("stream") {
+        let file = File::open(payload_file).map_err(|e| {
+            error!("Can not open signed payload {}: {}", payload_file, e);
+            e
+        })?;
+        // Gzip encoded signed payloads are decompressed on the fly.
+        let mut file = BufReader::new(file);
+        let reader: Box<dyn Read> = if is_gzip(file.fill_buf()?) {
+            Box::new(flate2::read::GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut verifier = StreamVerifier::new();
+        // Safe to unwrap() because it has a default value.
+        verifier.set_chunk_size(*matches.get_one::<usize>("chunk-size").unwrap());
+        let stream_status = verifier
+            .verify(reader, check_public_key)
+            .map_err(|e| report_error(json, e))?;
+        // The SVN policy is not kept by the stream verifier, only the verify header is checked.
+        report_reserved_fields(
+            payload_file,
+            &check_verify_header(&stream_status.header),
+            strict,
+        )
+        .map_err(|e| report_error(json, e))?;
+        let status = SignatureStatus {
+            slot: 0,
+            signing_algorithm: stream_status.header.signing_algorithm,
+            public_key: &stream_status.public_key,
+            result: stream_status.result,
+        };
+        return report_status(
+            &[status],
+            Some(&stream_status.header),
+            trust_store.as_ref(),
+            json,
+        );
+    }
+
+    let mut payload = InputData::new(
+        payload_file,
+        0..=TD_SHIM_FIRMWARE_SIZE as usize,
+        "signed payload",
+    )
+    .map_err(|e| report_error(json, e))?;
+    // Gzip encoded signed payloads, written by td-shim-sign-payload --gzip-output, are verified
+    // after decompression.
+    payload
+        .decompress_gzip(TD_SHIM_FIRMWARE_SIZE as usize)
+        .map_err(|e| report_error(json, e))?;
+
+    // The key identity is checked before the signatures, and alone without a trust anchor.
+    if let Some(fingerprint) = matches.get_one::<String>("expect-key-sha256") {
+        check_key_fingerprint(payload.as_bytes(), fingerprint, "SHA256")?;
+        println!("Key SHA256 {}: MATCH", fingerprint.trim().to_lowercase());
+        if matches.get_one::<String>("cfv").is_none() && trust_store.is_none() {
+            println!("Only the key identity is verified, not the signature");
+            return Ok(());
+        }
+    }
+
+    let parse_error = |e| {
+        match e {
+            VerifyErr::SizeMismatch { declared, actual } => error!(
+                "Signed payload {} is {} bytes, but its verify header declares {} bytes",
+                payload_file, actual, declared
+            ),
+            VerifyErr::UnsupportedVersion(version) => error!(
+                "Can not parse signed payload {}: unsupported format version {}",
+                payload_file, version
+            ),
+            e => error!("Can not parse signed payload {}: {:?}", payload_file, e),
+        }
+        report_error(
+            json,
+            io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload"),
+        )
+    };
+    let header = PayloadSignHeader::from_bytes(payload.as_bytes());
+    let (header, status) = match SignedPayloadFormat::detect(payload.as_bytes()) {
+        Some(SignedPayloadFormat::Compact) => {
+            if !json {
+                println!("Format: compact, no version or SVN recorded");
+            }
+            let status = CompactPayloadVerifier::new(payload.as_bytes(), cfv)
+                .map_err(parse_error)?
+                .verify_all_with(check_public_key);
+            (None, status)
+        }
+        _ => {
+            let status = match PayloadVerifier::new(payload.as_bytes(), cfv) {
+                Err(VerifyErr::Unsigned) => return Err(report_unsigned(payload_file, json)),
+                res => res.map_err(parse_error)?.verify_all_with(check_public_key),
+            };
+            (header, status)
+        }
+    };
+    report_reserved_fields(
+        payload_file,
+        &check_signed_payload(payload.as_bytes()),
+        strict,
+    )
+    .map_err(|e| report_error(json, e))?;
+    report_status(&status, header.as_ref(), trust_store.as_ref(), json)?;
+
+    #[cfg(feature = "tsa")]
+    if let Some(token_file) = matches.get_one::<String>("timestamp") {
+        let token = InputData::new(token_file, 1..=1024 * 1024, "timestamp token")?;
+        let data = timestamped_data(payload.as_bytes())?;
+        let info = check_timestamp_coverage(token.as_bytes(), data)?;
+        // The signature of the TSA is not verified, so the time is only claimed by the token.
+        println!(
+            "Timestamp: {}: covers the signature, TSA signature NOT verified",
+            info.gen_time
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Set command line options by environment variables, for containerized CI where passing flags
+//! is awkward.
+//!
+//! The variable of an option is named after its long name, upper cased with dashes replaced by
+//! underscores and prefixed, such as `TD_SHIM_SIGN_ALGORITHM` for `--algorithm`. Options given on
+//! the command line take precedence over the environment variables, which take precedence over
+//! the default values. Positional arguments are filled by position, so they have no variable.
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, Id};
+
+/// Prefix of the environment variables of `td-shim-sign-payload`.
+pub const SIGN_ENV_PREFIX: &str = "TD_SHIM_SIGN_";
+
+/// Get the name of the environment variable of the option `--long`.
+pub fn env_var_name(prefix: &str, long: &str) -> String {
+    format!("{}{}", prefix, long.to_uppercase().replace('-', "_"))
+}
+
+/// Let every option of `command` with a long name be set by its environment variable. Options of
+/// the subcommands are left alone.
+pub fn with_env_vars(mut command: Command, prefix: &str) -> Command {
+    let ids: Vec<Id> = command
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some())
+        .map(|arg| arg.get_id().clone())
+        .collect();
+    for id in ids {
+        command = command.mut_arg(id, |arg| {
+            // Safe to unwrap() because only options with a long name are selected. The name is
+            // leaked as the command outlives main() anyway.
+            let name = env_var_name(prefix, arg.get_long().unwrap());
+            arg.env(&*Box::leak(name.into_boxed_str()))
+        });
+    }
+
+    command
+}
+
+/// List the environment variables of `command` set up by `with_env_vars()`, along with the
+/// options they set.
+pub fn env_vars(command: &Command) -> Vec<(String, String)> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let name = arg.get_env()?.to_string_lossy().into_owned();
+            Some((name, format!("--{}", arg.get_long()?)))
+        })
+        .collect()
+}
+
+/// Check whether the value of the option `id` is given on the command line or by its environment
+/// variable, instead of being the default value.
+pub fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::{Arg, ArgAction};
+    use std::env;
+
+    // Each test has its own prefix, as tests run in parallel and share the environment.
+    fn command(prefix: &str) -> Command {
+        let command = Command::new("test")
+            .arg(Arg::new("payload").required(false))
+            .arg(
+                Arg::new("algorithm")
+                    .short('A')
+                    .long("algorithm")
+                    .default_value("RSAPSS_3072_SHA384")
+                    .action(ArgAction::Set),
+            )
+            .arg(
+                Arg::new("max-output-size")
+                    .long("max-output-size")
+                    .value_parser(clap::value_parser!(u64))
+                    .action(ArgAction::Set),
+            )
+            .arg(Arg::new("strict").long("strict").action(ArgAction::SetTrue))
+            .subcommand(
+                Command::new("prepare")
+                    .arg(Arg::new("digest").long("digest").action(ArgAction::Set)),
+            );
+        with_env_vars(command, prefix)
+    }
+
+    #[test]
+    fn test_env_var_name() {
+        assert_eq!(
+            env_var_name(SIGN_ENV_PREFIX, "algorithm"),
+            "TD_SHIM_SIGN_ALGORITHM"
+        );
+        assert_eq!(
+            env_var_name(SIGN_ENV_PREFIX, "max-output-size"),
+            "TD_SHIM_SIGN_MAX_OUTPUT_SIZE"
+        );
+
+        let command = command("TD_SHIM_TEST_LIST_");
+        assert_eq!(
+            env_vars(&command),
+            vec![
+                (
+                    "TD_SHIM_TEST_LIST_ALGORITHM".to_string(),
+                    "--algorithm".to_string()
+                ),
+                (
+                    "TD_SHIM_TEST_LIST_MAX_OUTPUT_SIZE".to_string(),
+                    "--max-output-size".to_string()
+                ),
+                (
+                    "TD_SHIM_TEST_LIST_STRICT".to_string(),
+                    "--strict".to_string()
+                ),
+            ]
+        );
+        // Positional arguments and options of the subcommands have no variable.
+        assert!(command.get_positionals().all(|arg| arg.get_env().is_none()));
+        let prepare = command.find_subcommand("prepare").unwrap();
+        assert!(env_vars(prepare).is_empty());
+    }
+
+    #[test]
+    fn test_env_vars_honored() {
+        let matches = command("TD_SHIM_TEST_UNSET_").get_matches_from(["test"]);
+        assert_eq!(
+            matches.get_one::<String>("algorithm").unwrap(),
+            "RSAPSS_3072_SHA384"
+        );
+        assert!(!is_explicit(&matches, "algorithm"));
+        assert!(!matches.get_flag("strict"));
+
+        env::set_var("TD_SHIM_TEST_SET_ALGORITHM", "ECDSA_NIST_P384_SHA384");
+        env::set_var("TD_SHIM_TEST_SET_MAX_OUTPUT_SIZE", "4096");
+        env::set_var("TD_SHIM_TEST_SET_STRICT", "true");
+        let matches = command("TD_SHIM_TEST_SET_").get_matches_from(["test", "payload.bin"]);
+        assert_eq!(
+            matches.get_one::<String>("algorithm").unwrap(),
+            "ECDSA_NIST_P384_SHA384"
+        );
+        assert!(is_explicit(&matches, "algorithm"));
+        assert_eq!(matches.get_one::<u64>("max-output-size"), Some(&4096));
+        assert!(matches.get_flag("strict"));
+        assert_eq!(matches.get_one::<String>("payload").unwrap(), "payload.bin");
+
+        // Values are checked as the ones of the command line.
+        env::set_var("TD_SHIM_TEST_INVALID_MAX_OUTPUT_SIZE", "4 KiB");
+        assert!(command("TD_SHIM_TEST_INVALID_")
+            .try_get_matches_from(["test"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_env_vars_overridden() {
+        env::set_var("TD_SHIM_TEST_OVERRIDE_ALGORITHM", "ECDSA_NIST_P384_SHA384");
+        env::set_var("TD_SHIM_TEST_OVERRIDE_MAX_OUTPUT_SIZE", "4096");
+        let matches = command("TD_SHIM_TEST_OVERRIDE_").get_matches_from([
+            "test",
+            "-A",
+            "RSAPSS_3072_SHA384",
+            "--max-output-size",
+            "8192",
+        ]);
+        assert_eq!(
+            matches.get_one::<String>("algorithm").unwrap(),
+            "RSAPSS_3072_SHA384"
+        );
+        assert_eq!(
+            matches.value_source("algorithm"),
+            Some(ValueSource::CommandLine)
+        );
+        assert!(is_explicit(&matches, "algorithm"));
+        assert_eq!(matches.get_one::<u64>("max-output-size"), Some(&8192));
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Wrap the payload signature in a CMS (PKCS#7) SignedData structure.
+//!
+//! The SignedData encapsulates the signed region, the verify header followed by the payload, and
+//! carries the signer certificate. There are no signed attributes, so the CMS signature is the
+//! payload signature itself and can be checked by CMS tooling, for example:
+//!
+//! ```text
+//! openssl cms -verify -inform DER -in payload.p7s -binary -noverify -out signed-region.bin
+//! ```
+
+use std::io;
+
+use log::error;
+use td_shim::secure_boot::{
+    signature_block_size, PayloadSignHeader, PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+    PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE, PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+    PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+use crate::signature::{asn1_signature_len, convert_ecdsa_signature, Curve, SigFormat};
+
+pub(crate) const DER_TAG_INTEGER: u8 = 0x02;
+pub(crate) const DER_TAG_BIT_STRING: u8 = 0x03;
+pub(crate) const DER_TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const DER_TAG_NULL: u8 = 0x05;
+pub(crate) const DER_TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const DER_TAG_SET: u8 = 0x31;
+pub(crate) const DER_TAG_CONTEXT_0: u8 = 0xa0;
+const DER_TAG_CONTEXT_1: u8 = 0xa1;
+const DER_TAG_CONTEXT_2: u8 = 0xa2;
+
+// DER encoded object identifiers.
+pub(crate) const OID_SIGNED_DATA: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02,
+];
+const OID_DATA: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01,
+];
+pub(crate) const OID_SHA384: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02,
+];
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const OID_RSASSA_PSS: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a,
+];
+const OID_MGF1: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x08,
+];
+
+/// Salt length of RSASSA-PSS signatures, as required by the td-shim verifier.
+const RSA_PSS_SHA384_SALT_SIZE: u8 = 48;
+
+fn invalid(reason: &str) -> io::Error {
+    error!("Can not build CMS SignedData: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidInput, reason.to_string())
+}
+
+fn der_error(reason: &str) -> io::Error {
+    error!("Invalid DER encoding: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Encode a DER TLV.
+pub(crate) fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let len: usize = parts.iter().map(|v| v.len()).sum();
+    let mut output = Vec::with_capacity(len + 6);
+    output.push(tag);
+    if len < 0x80 {
+        output.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let bytes = &bytes[bytes.iter().take_while(|v| **v == 0).count()..];
+        output.push(0x80 | bytes.len() as u8);
+        output.extend_from_slice(bytes);
+    }
+    for part in parts {
+        output.extend_from_slice(part);
+    }
+    output
+}
+
+/// A DER TLV parsed by `read_der()`.
+pub(crate) struct Der<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+    /// The whole TLV.
+    pub encoded: &'a [u8],
+}
+
+// Read the DER TLV at the start of `data`, return it and the data following it.
+pub(crate) fn read_der(data: &[u8]) -> io::Result<(Der, &[u8])> {
+    let truncated = || der_error("truncated DER encoding");
+
+    if data.len() < 2 {
+        return Err(truncated());
+    }
+    let (len, header_len) = match data[1] {
+        v if v < 0x80 => (v as usize, 2),
+        v if v > 0x80 && v <= 0x84 => {
+            let n = (v & 0x7f) as usize;
+            let bytes = data.get(2..2 + n).ok_or_else(truncated)?;
+            (
+                bytes.iter().fold(0usize, |acc, v| acc << 8 | *v as usize),
+                2 + n,
+            )
+        }
+        _ => return Err(der_error("unsupported DER length")),
+    };
+    let end = header_len.checked_add(len).ok_or_else(truncated)?;
+    if end > data.len() {
+        return Err(truncated());
+    }
+
+    Ok((
+        Der {
+            tag: data[0],
+            value: &data[header_len..end],
+            encoded: &data[..end],
+        },
+        &data[end..],
+    ))
+}
+
+pub(crate) fn read_der_tag(data: &[u8], tag: u8) -> io::Result<(Der, &[u8])> {
+    let (v, rest) = read_der(data)?;
+    if v.tag != tag {
+        return Err(der_error("unexpected DER tag"));
+    }
+    Ok((v, rest))
+}
+
+/// Fields of an X.509 certificate needed to identify the signer.
+struct Certificate<'a> {
+    /// DER encoded issuer Name.
+    issuer: &'a [u8],
+    /// DER encoded serial number INTEGER.
+    serial_number: &'a [u8],
+    /// Content of the subjectPublicKey BIT STRING, without the unused bits octet.
+    public_key: &'a [u8],
+}
+
+fn parse_certificate(cert: &[u8]) -> io::Result<Certificate> {
+    let (cert, _) = read_der_tag(cert, DER_TAG_SEQUENCE)?;
+    let (tbs, _) = read_der_tag(cert.value, DER_TAG_SEQUENCE)?;
+
+    let mut fields = tbs.value;
+    let (version, rest) = read_der(fields)?;
+    if version.tag == DER_TAG_CONTEXT_0 {
+        fields = rest;
+    }
+    let (serial_number, fields) = read_der_tag(fields, DER_TAG_INTEGER)?;
+    let (_signature, fields) = read_der_tag(fields, DER_TAG_SEQUENCE)?;
+    let (issuer, fields) = read_der_tag(fields, DER_TAG_SEQUENCE)?;
+    let (_validity, fields) = read_der_tag(fields, DER_TAG_SEQUENCE)?;
+    let (_subject, fields) = read_der_tag(fields, DER_TAG_SEQUENCE)?;
+    let (spki, _) = read_der_tag(fields, DER_TAG_SEQUENCE)?;
+    let (_algorithm, spki) = read_der_tag(spki.value, DER_TAG_SEQUENCE)?;
+    let (public_key, _) = read_der_tag(spki, DER_TAG_BIT_STRING)?;
+    if public_key.value.first() != Some(&0) {
+        return Err(invalid("invalid public key in certificate"));
+    }
+
+    Ok(Certificate {
+        issuer: issuer.encoded,
+        serial_number: serial_number.encoded,
+        public_key: &public_key.value[1..],
+    })
+}
+
+fn strip_leading_zeros(v: &[u8]) -> &[u8] {
+    &v[v.iter().take_while(|v| **v == 0).count()..]
+}
+
+// Check that the RSAPublicKey of the certificate matches the modulus and exponent of the signature
+// block.
+fn rsa_key_matches(public_key: &[u8], modulus: &[u8], exponent: &[u8]) -> io::Result<bool> {
+    let (key, _) = read_der_tag(public_key, DER_TAG_SEQUENCE)?;
+    let (n, rest) = read_der_tag(key.value, DER_TAG_INTEGER)?;
+    let (e, _) = read_der_tag(rest, DER_TAG_INTEGER)?;
+
+    Ok(strip_leading_zeros(n.value) == strip_leading_zeros(modulus)
+        && strip_leading_zeros(e.value) == strip_leading_zeros(exponent))
+}
+
+fn algorithm_identifier(oid: &[u8], parameters: &[u8]) -> Vec<u8> {
+    der(DER_TAG_SEQUENCE, &[oid, parameters])
+}
+
+fn rsassa_pss_sha384_identifier() -> Vec<u8> {
+    let sha384 = algorithm_identifier(OID_SHA384, &der(DER_TAG_NULL, &[]));
+    let parameters = der(
+        DER_TAG_SEQUENCE,
+        &[
+            &der(DER_TAG_CONTEXT_0, &[&sha384]),
+            &der(
+                DER_TAG_CONTEXT_1,
+                &[&algorithm_identifier(OID_MGF1, &sha384)],
+            ),
+            &der(
+                DER_TAG_CONTEXT_2,
+                &[&der(DER_TAG_INTEGER, &[&[RSA_PSS_SHA384_SALT_SIZE]])],
+            ),
+        ],
+    );
+    algorithm_identifier(OID_RSASSA_PSS, &parameters)
+}
+
+/// Build a DER encoded CMS ContentInfo with SignedData for the primary signature of
+/// `signed_payload`, signed by the holder of the X.509 certificate `certificate` (DER).
+///
+/// The public key of the certificate must match the public key in the signature block.
+pub fn build_cms_signed_data(signed_payload: &[u8], certificate: &[u8]) -> io::Result<Vec<u8>> {
+    let header = PayloadSignHeader::from_bytes(signed_payload)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .ok_or_else(|| invalid("invalid signed payload header"))?;
+    let length = header.length as usize;
+    let block = signature_block_size(header.signing_algorithm)
+        .and_then(|size| signed_payload.get(length..length.checked_add(size)?))
+        .ok_or_else(|| invalid("signature block not found"))?;
+    let cert = parse_certificate(certificate)?;
+
+    let (key_matches, signature_algorithm, signature) = match header.signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 | PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            let (public_key, sig) = block.split_at(PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE);
+            let sig = if header.signing_algorithm == PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 {
+                convert_ecdsa_signature(sig, SigFormat::Fixed, SigFormat::Asn1, Curve::NistP384)?
+            } else {
+                sig[..asn1_signature_len(sig)?].to_vec()
+            };
+            // Uncompressed point with the format tag 0x4.
+            let key_matches =
+                cert.public_key.first() == Some(&0x4) && &cert.public_key[1..] == public_key;
+            (
+                key_matches,
+                algorithm_identifier(OID_ECDSA_WITH_SHA384, &[]),
+                sig,
+            )
+        }
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => {
+            let (public_key, sig) = block.split_at(PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE);
+            let (modulus, exponent) = public_key.split_at(PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE);
+            (
+                rsa_key_matches(cert.public_key, modulus, exponent)?,
+                rsassa_pss_sha384_identifier(),
+                sig.to_vec(),
+            )
+        }
+        _ => return Err(invalid("unsupported signing algorithm")),
+    };
+    if !key_matches {
+        return Err(invalid(
+            "certificate doesn't match the public key of the signature",
+        ));
+    }
+
+    let digest_algorithm = algorithm_identifier(OID_SHA384, &[]);
+    let signer_info = der(
+        DER_TAG_SEQUENCE,
+        &[
+            // CMSVersion 1 for IssuerAndSerialNumber.
+            &der(DER_TAG_INTEGER, &[&[1]]),
+            &der(DER_TAG_SEQUENCE, &[cert.issuer, cert.serial_number]),
+            &digest_algorithm,
+            &signature_algorithm,
+            &der(DER_TAG_OCTET_STRING, &[&signature]),
+        ],
+    );
+    let encapsulated_content = der(
+        DER_TAG_SEQUENCE,
+        &[
+            OID_DATA,
+            &der(
+                DER_TAG_CONTEXT_0,
+                &[&der(DER_TAG_OCTET_STRING, &[&signed_payload[..length]])],
+            ),
+        ],
+    );
+    let signed_data = der(
+        DER_TAG_SEQUENCE,
+        &[
+            &der(DER_TAG_INTEGER, &[&[1]]),
+            &der(DER_TAG_SET, &[&digest_algorithm]),
+            &encapsulated_content,
+            &der(DER_TAG_CONTEXT_0, &[certificate]),
+            &der(DER_TAG_SET, &[&signer_info]),
+        ],
+    );
+
+    Ok(der(
+        DER_TAG_SEQUENCE,
+        &[OID_SIGNED_DATA, &der(DER_TAG_CONTEXT_0, &[&signed_data])],
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::{PayloadSigner, SigningAlgorithm};
+    use ring::signature::{self, UnparsedPublicKey};
+
+    const ECDSA_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+    const ECDSA_CERT: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-cert.der");
+    const RSA_KEY: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+    const RSA_CERT: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-cert.der");
+
+    // Get the encapsulated content and the signature of the only signer of a ContentInfo.
+    fn parse_cms(cms: &[u8]) -> (&[u8], &[u8]) {
+        let (content_info, rest) = read_der_tag(cms, DER_TAG_SEQUENCE).unwrap();
+        assert!(rest.is_empty());
+        let (oid, rest) = read_der(content_info.value).unwrap();
+        assert_eq!(oid.encoded, OID_SIGNED_DATA);
+        let (explicit, _) = read_der_tag(rest, DER_TAG_CONTEXT_0).unwrap();
+        let (signed_data, _) = read_der_tag(explicit.value, DER_TAG_SEQUENCE).unwrap();
+
+        let (_version, rest) = read_der_tag(signed_data.value, DER_TAG_INTEGER).unwrap();
+        let (_digest_algorithms, rest) = read_der_tag(rest, DER_TAG_SET).unwrap();
+        let (encapsulated, rest) = read_der_tag(rest, DER_TAG_SEQUENCE).unwrap();
+        let (certificates, rest) = read_der_tag(rest, DER_TAG_CONTEXT_0).unwrap();
+        assert!(parse_certificate(certificates.value).is_ok());
+        let (signer_infos, _) = read_der_tag(rest, DER_TAG_SET).unwrap();
+
+        let (oid, rest) = read_der(encapsulated.value).unwrap();
+        assert_eq!(oid.encoded, OID_DATA);
+        let (explicit, _) = read_der_tag(rest, DER_TAG_CONTEXT_0).unwrap();
+        let (content, _) = read_der_tag(explicit.value, DER_TAG_OCTET_STRING).unwrap();
+
+        let (signer_info, _) = read_der_tag(signer_infos.value, DER_TAG_SEQUENCE).unwrap();
+        let mut fields = signer_info.value;
+        for _ in 0..4 {
+            fields = read_der(fields).unwrap().1;
+        }
+        let (signature, _) = read_der_tag(fields, DER_TAG_OCTET_STRING).unwrap();
+
+        (content.value, signature.value)
+    }
+
+    #[test]
+    fn test_build_cms_signed_data() {
+        let payload = [0x5au8; 0x200];
+
+        for (algorithm, key, cert) in [
+            ("ECDSA_NIST_P384_SHA384", ECDSA_KEY, ECDSA_CERT),
+            ("RSAPSS_3072_SHA384", RSA_KEY, RSA_CERT),
+        ] {
+            let key = SigningAlgorithm::from_pkcs8(algorithm, key).unwrap();
+            let mut signer = PayloadSigner::new(&payload, key);
+            let header = signer.build_header(1, 1);
+            let signed = signer.sign(header).unwrap().to_vec();
+            let length = PayloadSignHeader::from_bytes(&signed).unwrap().length as usize;
+
+            let cms = build_cms_signed_data(&signed, cert).unwrap();
+            let (content, sig) = parse_cms(&cms);
+            assert_eq!(content, &signed[..length]);
+
+            // The CMS signature verifies against the public key of the signer certificate.
+            let public_key = parse_certificate(cert).unwrap().public_key;
+            let verification_algorithm: &dyn signature::VerificationAlgorithm =
+                if algorithm == "RSAPSS_3072_SHA384" {
+                    &signature::RSA_PSS_2048_8192_SHA384
+                } else {
+                    &signature::ECDSA_P384_SHA384_ASN1
+                };
+            UnparsedPublicKey::new(verification_algorithm, public_key)
+                .verify(content, sig)
+                .unwrap();
+        }
+
+        // The certificate must belong to the signing key.
+        let key = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ECDSA_KEY).unwrap();
+        let mut signer = PayloadSigner::new(&payload, key);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        assert!(build_cms_signed_data(&signed, RSA_CERT).is_err());
+        assert!(build_cms_signed_data(&signed, &ECDSA_CERT[..100]).is_err());
+        assert!(build_cms_signed_data(&signed[..signed.len() - 1], ECDSA_CERT).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Multi-component payloads assembled from a component manifest, to be signed as one payload.
+//!
+//! A component manifest is a TOML document listing the components in the order they are laid
+//! out and measured, for example:
+//!
+//! ```toml
+//! [[component]]
+//! type = "executable"
+//! file = "td-payload.elf"
+//!
+//! [[component]]
+//! type = "initrd"
+//! file = "initrd.img"
+//! rtmr = 2
+//! ```
+//!
+//! `type` is a component type name or a custom id from 0x80, `rtmr` is the RTMR the component is
+//! measured into, default to 1. Files are relative to the directory of the manifest.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use scroll::{Pwrite, LE};
+use serde::Deserialize;
+use td_layout::build_time::TD_SHIM_PAYLOAD_SIZE;
+use td_shim::multi_component::{
+    component_type_from_name, toc_size, ComponentEntry, MultiComponentHeader,
+    COMPONENT_TYPE_CUSTOM_MIN, MULTI_COMPONENT_ALIGNMENT, MULTI_COMPONENT_MAX_COUNT,
+    MULTI_COMPONENT_MAX_RTMR, MULTI_COMPONENT_SIGNATURE, MULTI_COMPONENT_STRUCT_VERSION_V1,
+};
+
+use crate::InputData;
+
+/// RTMR the components are measured into if the manifest doesn't tell.
+pub const DEFAULT_COMPONENT_RTMR: u32 = 1;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ComponentManifest {
+    component: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    r#type: String,
+    file: PathBuf,
+    rtmr: Option<u32>,
+}
+
+/// A component of a multi-component payload.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Component {
+    pub component_type: u32,
+    pub rtmr_index: u32,
+    pub data: Vec<u8>,
+}
+
+fn invalid_components(reason: &str) -> io::Error {
+    error!("Invalid payload components: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidInput, reason.to_string())
+}
+
+/// Parse a component type given by its name, such as `initrd`, or by its custom id, such as
+/// `0x80`.
+pub fn parse_component_type(s: &str) -> io::Result<u32> {
+    let component_type = match component_type_from_name(s) {
+        Some(v) => Some(v),
+        None => match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u32>().ok(),
+        }
+        .filter(|v| *v >= COMPONENT_TYPE_CUSTOM_MIN),
+    };
+
+    component_type.ok_or_else(|| {
+        error!(
+            "Invalid component type {}, expect one of executable, kernel, initrd, cmdline or a \
+             custom id from 0x80",
+            s
+        );
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid component type")
+    })
+}
+
+/// Parse the component manifest `manifest`, reading the component files relative to `base_dir`.
+pub fn parse_component_manifest(manifest: &str, base_dir: &Path) -> io::Result<Vec<Component>> {
+    let manifest: ComponentManifest = toml::from_str(manifest).map_err(|e| {
+        error!("Can not parse component manifest: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid component manifest")
+    })?;
+
+    let mut components = Vec::new();
+    for entry in manifest.component {
+        let file = base_dir.join(&entry.file);
+        let data = InputData::new(
+            &file.display().to_string(),
+            0..=TD_SHIM_PAYLOAD_SIZE as usize,
+            "payload component",
+        )?;
+        components.push(Component {
+            component_type: parse_component_type(&entry.r#type)?,
+            rtmr_index: entry.rtmr.unwrap_or(DEFAULT_COMPONENT_RTMR),
+            data: data.as_bytes().to_vec(),
+        });
+    }
+
+    Ok(components)
+}
+
+/// Read the component manifest file `manifest` and the component files it lists.
+pub fn load_component_manifest(manifest: &Path) -> io::Result<Vec<Component>> {
+    let text = fs::read_to_string(manifest).map_err(|e| {
+        error!(
+            "Can not read component manifest {}: {}",
+            manifest.display(),
+            e
+        );
+        e
+    })?;
+
+    parse_component_manifest(&text, manifest.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Lay out `components` into one payload: the table of contents, then the data of each
+/// component in order, aligned to `MULTI_COMPONENT_ALIGNMENT` and padded with zero.
+///
+/// The payload is to be signed as a whole with the payload type `MultiComponent`.
+pub fn assemble_components(components: &[Component]) -> io::Result<Vec<u8>> {
+    if components.is_empty() || components.len() > MULTI_COMPONENT_MAX_COUNT {
+        return Err(invalid_components(&format!(
+            "expect 1 to {} components",
+            MULTI_COMPONENT_MAX_COUNT
+        )));
+    }
+
+    let mut payload = vec![0u8; toc_size(components.len())];
+    let header = MultiComponentHeader {
+        signature: MULTI_COMPONENT_SIGNATURE,
+        struct_version: MULTI_COMPONENT_STRUCT_VERSION_V1,
+        component_count: components.len() as u32,
+        reserved: 0,
+    };
+    // Safe to unwrap() because the buffer holds the table of contents.
+    payload.pwrite_with(header, 0, LE).unwrap();
+    for (idx, component) in components.iter().enumerate() {
+        if component.rtmr_index == 0 || component.rtmr_index > MULTI_COMPONENT_MAX_RTMR {
+            return Err(invalid_components(&format!(
+                "components are measured into RTMR[1] to RTMR[{}], not RTMR[{}]",
+                MULTI_COMPONENT_MAX_RTMR, component.rtmr_index
+            )));
+        }
+        let offset = align_up(payload.len());
+        payload.resize(offset, 0);
+        payload.extend_from_slice(&component.data);
+        if payload.len() > TD_SHIM_PAYLOAD_SIZE as usize {
+            return Err(invalid_components("components exceed the payload region"));
+        }
+
+        // Safe to unwrap() because the payload region is smaller than 4 GiB.
+        let entry = ComponentEntry {
+            component_type: component.component_type,
+            rtmr_index: component.rtmr_index,
+            offset: u32::try_from(offset).unwrap(),
+            size: u32::try_from(component.data.len()).unwrap(),
+        };
+        payload.pwrite_with(entry, toc_size(idx), LE).unwrap();
+    }
+
+    Ok(payload)
+}
+
+fn align_up(offset: usize) -> usize {
+    (offset + MULTI_COMPONENT_ALIGNMENT - 1) / MULTI_COMPONENT_ALIGNMENT * MULTI_COMPONENT_ALIGNMENT
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::multi_component::{
+        MultiComponentPayload, COMPONENT_TYPE_CMDLINE, COMPONENT_TYPE_EXECUTABLE,
+        COMPONENT_TYPE_INITRD,
+    };
+    use td_shim::secure_boot::{PayloadVerifier, SignedPayloadType, VerifyErr};
+
+    use crate::signer::{EcdsaP384KeyPair, PayloadSigner, SigningAlgorithm};
+    use crate::tee_info_hash::{predict_payload_measurements, MeasurementRegister};
+
+    fn sample_components() -> Vec<Component> {
+        vec![
+            Component {
+                component_type: COMPONENT_TYPE_EXECUTABLE,
+                rtmr_index: 1,
+                data: vec![0x11; 13],
+            },
+            Component {
+                component_type: COMPONENT_TYPE_INITRD,
+                rtmr_index: 2,
+                data: vec![0x22; 20],
+            },
+            Component {
+                component_type: COMPONENT_TYPE_CMDLINE,
+                rtmr_index: 2,
+                data: b"console=hvc0".to_vec(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_assemble_components() {
+        let components = sample_components();
+        let payload = assemble_components(&components).unwrap();
+
+        // The table of contents is parsed back by the runtime.
+        let parsed = MultiComponentPayload::parse(&payload).unwrap();
+        assert_eq!(parsed.len(), components.len());
+        for (parsed, component) in parsed.components().zip(components.iter()) {
+            assert_eq!(parsed.component_type, component.component_type);
+            assert_eq!(parsed.rtmr_index, component.rtmr_index);
+            assert_eq!(parsed.offset % MULTI_COMPONENT_ALIGNMENT, 0);
+            assert_eq!(parsed.data, component.data.as_slice());
+        }
+        assert_eq!(parsed.component(0).unwrap().offset, toc_size(3));
+        // Padding is zero.
+        assert_eq!(&payload[toc_size(3) + 13..toc_size(3) + 16], &[0u8; 3]);
+
+        assert!(assemble_components(&[]).is_err());
+        let mut invalid = sample_components();
+        invalid[1].rtmr_index = 0;
+        assert!(assemble_components(&invalid).is_err());
+        invalid[1].rtmr_index = MULTI_COMPONENT_MAX_RTMR + 1;
+        assert!(assemble_components(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_sign_components() {
+        let payload = assemble_components(&sample_components()).unwrap();
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+        signer.set_payload_type(SignedPayloadType::MultiComponent);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        // The whole region is signed.
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+        assert_eq!(
+            verifier.get_payload_type(),
+            SignedPayloadType::MultiComponent
+        );
+        let image = PayloadVerifier::get_payload_image(&signed).unwrap();
+        assert_eq!(image, payload.as_slice());
+        let offset = image.as_ptr() as usize - signed.as_ptr() as usize;
+        let mut tampered = signed.clone();
+        tampered[offset + toc_size(3) + 16] ^= 0x1;
+        let verifier = PayloadVerifier::new(&tampered, &[]).unwrap();
+        assert_eq!(
+            verifier.verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // The table of contents and each component are predicted as measurements of their own,
+        // once the payload is verified.
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        let steps =
+            predict_payload_measurements(cfv, image, Some(1), 1, SignedPayloadType::MultiComponent);
+        let table: Vec<_> = steps
+            .iter()
+            .filter(|v| v.description == "td_payload_components")
+            .collect();
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[0].register, MeasurementRegister::Rtmr(1));
+        assert_eq!(table[0].region, 0..toc_size(3));
+        let components: Vec<_> = steps
+            .iter()
+            .filter(|v| v.description == "td_payload_component")
+            .collect();
+        assert_eq!(components.len(), 3);
+        assert_eq!(components[0].register, MeasurementRegister::Rtmr(1));
+        assert_eq!(components[1].register, MeasurementRegister::Rtmr(2));
+        assert_eq!(components[2].register, MeasurementRegister::Rtmr(2));
+        let cmdline = MultiComponentPayload::parse(image)
+            .unwrap()
+            .find(COMPONENT_TYPE_CMDLINE)
+            .unwrap();
+        assert_eq!(
+            components[2].region,
+            cmdline.offset..cmdline.offset + cmdline.data.len()
+        );
+        assert!(steps.iter().all(|v| v.description != "td_payload"));
+
+        // Without secure boot, or the multi-component type recorded by the verify header, the
+        // payload is measured as a whole.
+        for (cfv, payload_type) in [
+            (&[0u8; 0x100][..], SignedPayloadType::MultiComponent),
+            (&cfv[..], SignedPayloadType::Unspecified),
+        ] {
+            let steps = predict_payload_measurements(cfv, image, Some(1), 1, payload_type);
+            assert!(steps
+                .iter()
+                .all(|v| !v.description.starts_with("td_payload_component")));
+            assert_eq!(
+                steps
+                    .iter()
+                    .filter(|v| v.description == "td_payload")
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_component_manifest() {
+        let dir = std::env::temp_dir().join(format!("td-shim-components-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("payload.elf"), b"executable").unwrap();
+        fs::write(dir.join("initrd.img"), b"initrd").unwrap();
+        let manifest = r#"
+            [[component]]
+            type = "executable"
+            file = "payload.elf"
+
+            [[component]]
+            type = "0x81"
+            file = "initrd.img"
+            rtmr = 3
+        "#;
+        fs::write(dir.join("components.toml"), manifest).unwrap();
+
+        let components = load_component_manifest(&dir.join("components.toml")).unwrap();
+        assert_eq!(
+            components,
+            vec![
+                Component {
+                    component_type: COMPONENT_TYPE_EXECUTABLE,
+                    rtmr_index: DEFAULT_COMPONENT_RTMR,
+                    data: b"executable".to_vec(),
+                },
+                Component {
+                    component_type: 0x81,
+                    rtmr_index: 3,
+                    data: b"initrd".to_vec(),
+                },
+            ]
+        );
+
+        // Unknown types and fields, missing files.
+        assert!(parse_component_manifest(
+            "[[component]]\ntype = \"bzImage\"\nfile = \"payload.elf\"\n",
+            &dir
+        )
+        .is_err());
+        assert!(parse_component_manifest(
+            "[[component]]\ntype = \"kernel\"\npath = \"payload.elf\"\n",
+            &dir
+        )
+        .is_err());
+        assert!(parse_component_manifest(
+            "[[component]]\ntype = \"kernel\"\nfile = \"missing\"\n",
+            &dir
+        )
+        .is_err());
+        assert!(parse_component_type("0x7f").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
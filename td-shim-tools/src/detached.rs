@@ -0,0 +1,201 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Detached signatures of payloads, that is the verify header and the signature block kept apart
+//! from the payload, as generated by split signing.
+//!
+//! The signed payload is rebuilt by `join_detached()` for both assembling and verifying, so they
+//! always agree on the signed region: the verify header followed by the payload. Both lay out the
+//! signed payload with `assemble_signed_image()`, the same way as `PayloadSigner::sign()` does.
+
+use std::io;
+
+use log::error;
+use td_shim::secure_boot::{
+    signature_block_size, PayloadSignHeader, PayloadVerifier, SvnPolicy, VerifyErr,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1, PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_RSA_PSS_3072_SHA384, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+fn mismatch(reason: &str) -> io::Error {
+    error!("Invalid detached signature: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidInput, reason.to_string())
+}
+
+// Size of the public key at the start of the signature block of `signing_algorithm`.
+fn public_key_size(signing_algorithm: u32) -> Option<usize> {
+    match signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 | PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            Some(PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE)
+        }
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => Some(PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE),
+        _ => None,
+    }
+}
+
+/// Lay out the signed payload from already computed pieces, the same way as
+/// `PayloadSigner::sign()` does: the verify header `header`, the signed data `payload`, then the
+/// signature block made of `public_key` followed by `signature`.
+///
+/// `payload` is everything signed after the verify header, that is the payload followed by the
+/// SVN policy if the header declares one. The pieces must match the length, the SVN policy and
+/// the signing algorithm declared by the header. The signature is not verified.
+pub fn assemble_signed_image(
+    payload: &[u8],
+    header: &PayloadSignHeader,
+    signature: &[u8],
+    public_key: &[u8],
+) -> io::Result<Vec<u8>> {
+    if &header.type_guid != SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() {
+        return Err(mismatch("invalid verify header"));
+    }
+    let header_size = header.header_size();
+    let signed_size = header_size + payload.len();
+    if header.length as usize != signed_size {
+        return Err(mismatch("verify header doesn't match the payload"));
+    }
+    // The SVN policy is signed right after the payload, at the end of the signed region.
+    if header.policy_size != 0 {
+        let policy_offset = header.policy_offset as usize;
+        if policy_offset < header_size
+            || policy_offset.checked_add(header.policy_size as usize) != Some(signed_size)
+            || SvnPolicy::from_bytes(&payload[policy_offset - header_size..], header.endian())
+                .is_none()
+        {
+            return Err(mismatch("SVN policy doesn't match the verify header"));
+        }
+    }
+    let public_key_size = public_key_size(header.signing_algorithm);
+    if public_key_size != Some(public_key.len())
+        || signature_block_size(header.signing_algorithm)
+            != Some(public_key.len() + signature.len())
+    {
+        return Err(mismatch(
+            "public key or signature doesn't match the signing algorithm of the verify header",
+        ));
+    }
+
+    let mut signed_payload = Vec::with_capacity(signed_size + public_key.len() + signature.len());
+    signed_payload.extend_from_slice(&header.to_bytes());
+    signed_payload.extend_from_slice(payload);
+    signed_payload.extend_from_slice(public_key);
+    signed_payload.extend_from_slice(signature);
+
+    Ok(signed_payload)
+}
+
+/// Rebuild the signed payload from the verify header `header`, the original payload `payload`
+/// and the signature block `signature_block`, the public key followed by the signature.
+///
+/// The signature is not verified.
+pub fn join_detached(header: &[u8], payload: &[u8], signature_block: &[u8]) -> io::Result<Vec<u8>> {
+    let hdr = PayloadSignHeader::from_bytes(header)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .ok_or_else(|| mismatch("invalid verify header"))?;
+    if header.len() != hdr.header_size() || hdr.policy_size != 0 {
+        return Err(mismatch("invalid verify header"));
+    }
+    if signature_block_size(hdr.signing_algorithm) != Some(signature_block.len()) {
+        return Err(mismatch(
+            "signature doesn't match the signing algorithm of the verify header",
+        ));
+    }
+
+    // Safe to unwrap() because the signing algorithm has a signature block.
+    let (public_key, signature) =
+        signature_block.split_at(public_key_size(hdr.signing_algorithm).unwrap());
+    assemble_signed_image(payload, &hdr, signature, public_key)
+}
+
+/// Verify the detached signature of `payload`, with the public key in the signature block
+/// checked by `verify_public_key`.
+///
+/// Malformed detached signatures are reported as errors, failures of the signature as the inner
+/// result.
+pub fn verify_detached<F>(
+    header: &[u8],
+    payload: &[u8],
+    signature_block: &[u8],
+    verify_public_key: F,
+) -> io::Result<Result<(), VerifyErr>>
+where
+    F: Fn(&[u8]) -> Result<(), VerifyErr>,
+{
+    let signed_payload = join_detached(header, payload, signature_block)?;
+    let verifier = PayloadVerifier::new(&signed_payload, &[])
+        .map_err(|e| mismatch(&format!("invalid signed payload: {:?}", e)))?;
+
+    Ok(verifier.verify_all_with(verify_public_key)[0].result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::enroller::parse_public_key;
+
+    const SIGNED_PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+    const ECDSA_PUBLIC_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+    const RSA_PUBLIC_KEY: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+
+    // Split the signed payload into the verify header, the payload and the signature block.
+    fn detach(signed_payload: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let header = PayloadSignHeader::from_bytes(signed_payload).unwrap();
+        let (signed, block) = signed_payload.split_at(header.length as usize);
+        let (header, payload) = signed.split_at(header.header_size());
+        (header.to_vec(), payload.to_vec(), block.to_vec())
+    }
+
+    fn verify_with_key(
+        (header, payload, block): &(Vec<u8>, Vec<u8>, Vec<u8>),
+        der: &[u8],
+    ) -> io::Result<Result<(), VerifyErr>> {
+        let trusted = parse_public_key(der, "public key").unwrap();
+        verify_detached(header, payload, block, |public_key| {
+            if public_key == trusted.as_slice() {
+                Ok(())
+            } else {
+                Err(VerifyErr::InvalidPublicKey)
+            }
+        })
+    }
+
+    #[test]
+    fn test_verify_detached() {
+        let detached = detach(SIGNED_PAYLOAD);
+        let (header, payload, block) = &detached;
+        assert_eq!(
+            join_detached(header, payload, block).unwrap(),
+            SIGNED_PAYLOAD
+        );
+        assert_eq!(
+            verify_with_key(&detached, ECDSA_PUBLIC_KEY).unwrap(),
+            Ok(())
+        );
+
+        // Tampered payload.
+        let mut tampered = detached.clone();
+        tampered.1[0x100] ^= 0xff;
+        assert_eq!(
+            verify_with_key(&tampered, ECDSA_PUBLIC_KEY).unwrap(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // Mismatched key.
+        assert_eq!(
+            verify_with_key(&detached, RSA_PUBLIC_KEY).unwrap(),
+            Err(VerifyErr::InvalidPublicKey)
+        );
+    }
+
+    #[test]
+    fn test_join_detached_mismatch() {
+        let (header, payload, block) = detach(SIGNED_PAYLOAD);
+        assert!(join_detached(&header, &payload[1..], &block).is_err());
+        assert!(join_detached(&header, &payload, &block[1..]).is_err());
+        assert!(join_detached(&header[1..], &payload, &block).is_err());
+        assert!(join_detached(&payload[..header.len()], &payload, &block).is_err());
+    }
+}
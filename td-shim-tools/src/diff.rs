@@ -0,0 +1,292 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Compare two signed payloads part by part: the verify header fields, the payload, the SVN
+//! policy and the signatures.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use log::error;
+use td_shim::secure_boot::{
+    signing_algorithm_name, PayloadSignHeader, PayloadVerifier, PAYLOAD_SIGN_HEADER_VERSION_V2,
+    SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+/// A verify header field which differs between two signed payloads.
+#[derive(Debug, Eq, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// Differences between two signed payloads.
+#[derive(Debug)]
+pub struct PayloadDiff {
+    pub header: Vec<FieldChange>,
+    /// Whether the two payloads could be treated interchangeably, see
+    /// `PayloadSignHeader::is_compatible()`.
+    pub compatible: bool,
+    pub payload_changed: bool,
+    pub policy_changed: bool,
+    pub signature_changed: bool,
+}
+
+impl PayloadDiff {
+    /// Check whether the two signed payloads are identical.
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+            && !self.payload_changed
+            && !self.policy_changed
+            && !self.signature_changed
+    }
+}
+
+impl fmt::Display for PayloadDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in self.header.iter() {
+            writeln!(
+                f,
+                "header.{}: {} -> {}",
+                change.field, change.old, change.new
+            )?;
+        }
+        let changed = |v: bool| if v { "changed" } else { "unchanged" };
+        writeln!(f, "payload: {}", changed(self.payload_changed))?;
+        writeln!(f, "policy: {}", changed(self.policy_changed))?;
+        writeln!(f, "signature: {}", changed(self.signature_changed))?;
+        write!(
+            f,
+            "compatible: {}",
+            if self.compatible { "yes" } else { "no" }
+        )
+    }
+}
+
+fn parse_header(signed_payload: &[u8], desc: &str) -> io::Result<PayloadSignHeader> {
+    PayloadSignHeader::from_bytes(signed_payload)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .filter(|h| h.length as usize <= signed_payload.len())
+        .ok_or_else(|| {
+            error!("Invalid verify header of the {} signed payload", desc);
+            io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload")
+        })
+}
+
+fn policy_range(header: &PayloadSignHeader) -> Range<usize> {
+    if header.struct_version < PAYLOAD_SIGN_HEADER_VERSION_V2 || header.policy_size == 0 {
+        return 0..0;
+    }
+    let start = header.policy_offset as usize;
+    start..start.saturating_add(header.policy_size as usize)
+}
+
+fn algorithm_name(signing_algorithm: u32) -> String {
+    signing_algorithm_name(signing_algorithm)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| format!("unknown({:#x})", signing_algorithm))
+}
+
+fn diff_headers(old: &PayloadSignHeader, new: &PayloadSignHeader) -> Vec<FieldChange> {
+    let fields = [
+        (
+            "struct_version",
+            old.struct_version.to_string(),
+            new.struct_version.to_string(),
+        ),
+        (
+            "length",
+            format!("{:#x}", old.length),
+            format!("{:#x}", new.length),
+        ),
+        (
+            "payload_version",
+            format!("{:#x}", old.payload_version),
+            format!("{:#x}", new.payload_version),
+        ),
+        (
+            "payload_svn",
+            old.payload_svn.to_string(),
+            new.payload_svn.to_string(),
+        ),
+        (
+            "signing_algorithm",
+            algorithm_name(old.signing_algorithm),
+            algorithm_name(new.signing_algorithm),
+        ),
+        (
+            "flags",
+            format!("{:#x}", old.flags),
+            format!("{:#x}", new.flags),
+        ),
+        (
+            "payload_type",
+            old.payload_type().to_string(),
+            new.payload_type().to_string(),
+        ),
+        (
+            "policy_offset",
+            format!("{:#x}", old.policy_offset),
+            format!("{:#x}", new.policy_offset),
+        ),
+        (
+            "policy_size",
+            format!("{:#x}", old.policy_size),
+            format!("{:#x}", new.policy_size),
+        ),
+    ];
+
+    IntoIterator::into_iter(fields)
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| FieldChange { field, old, new })
+        .collect()
+}
+
+/// Compare the signed payload `new` against `old`.
+pub fn diff_signed_payloads(old: &[u8], new: &[u8]) -> io::Result<PayloadDiff> {
+    let old_header = parse_header(old, "old")?;
+    let new_header = parse_header(new, "new")?;
+
+    let payload = |signed_payload: &[u8]| {
+        PayloadVerifier::get_payload_image(signed_payload)
+            .map(|v| v.to_vec())
+            .unwrap_or_default()
+    };
+    let policy = |signed_payload: &[u8], header: &PayloadSignHeader| {
+        signed_payload
+            .get(policy_range(header))
+            .map(|v| v.to_vec())
+            .unwrap_or_default()
+    };
+
+    Ok(PayloadDiff {
+        header: diff_headers(&old_header, &new_header),
+        compatible: old_header.is_compatible(&new_header),
+        payload_changed: payload(old) != payload(new),
+        policy_changed: policy(old, &old_header) != policy(new, &new_header),
+        signature_changed: old[old_header.length as usize..] != new[new_header.length as usize..],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::secure_boot::{
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_FLAG_BIG_ENDIAN,
+        PAYLOAD_SIGN_HEADER_VERSION_V1, PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+    };
+
+    fn build_signed_payload(
+        signing_algorithm: u32,
+        flags: u8,
+        payload_svn: u64,
+        payload: &[u8],
+        signature: &[u8],
+    ) -> Vec<u8> {
+        let mut header = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V1,
+            payload_version: 1,
+            payload_svn,
+            signing_algorithm,
+            flags,
+            ..Default::default()
+        };
+        header.length = (header.header_size() + payload.len()) as u32;
+
+        let mut signed_payload = header.to_bytes();
+        signed_payload.extend_from_slice(payload);
+        signed_payload.extend_from_slice(signature);
+        signed_payload
+    }
+
+    #[test]
+    fn test_diff_compatible() {
+        let old = build_signed_payload(PAYLOAD_SIGN_RSA_PSS_3072_SHA384, 0, 1, b"payload", b"sig");
+        let diff = diff_signed_payloads(&old, &old).unwrap();
+        assert!(diff.is_empty());
+        assert!(diff.compatible);
+
+        let new = build_signed_payload(PAYLOAD_SIGN_RSA_PSS_3072_SHA384, 0, 2, b"payload", b"new");
+        let diff = diff_signed_payloads(&old, &new).unwrap();
+        assert!(diff.compatible);
+        assert_eq!(
+            diff.header,
+            vec![FieldChange {
+                field: "payload_svn",
+                old: "1".to_string(),
+                new: "2".to_string(),
+            }]
+        );
+        assert!(!diff.payload_changed);
+        assert!(!diff.policy_changed);
+        assert!(diff.signature_changed);
+        assert!(diff
+            .to_string()
+            .contains("header.payload_svn: 1 -> 2\npayload: unchanged"));
+    }
+
+    #[test]
+    fn test_diff_incompatible() {
+        let old = build_signed_payload(PAYLOAD_SIGN_RSA_PSS_3072_SHA384, 0, 1, b"payload", b"sig");
+        let new = build_signed_payload(
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            0,
+            1,
+            b"payload",
+            b"sig",
+        );
+        let diff = diff_signed_payloads(&old, &new).unwrap();
+        assert!(!diff.compatible);
+        assert_eq!(diff.header[0].field, "signing_algorithm");
+        assert_eq!(diff.header[0].new, "ECDSA_NIST_P384_SHA384");
+        assert!(diff.to_string().ends_with("compatible: no"));
+
+        let new = build_signed_payload(
+            PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+            PAYLOAD_SIGN_FLAG_BIG_ENDIAN,
+            1,
+            b"other payload",
+            b"sig",
+        );
+        let diff = diff_signed_payloads(&old, &new).unwrap();
+        assert!(!diff.compatible);
+        assert!(diff.payload_changed);
+
+        assert!(diff_signed_payloads(&old, b"payload").is_err());
+        assert!(diff_signed_payloads(&old[..old.len() - 4], &old).is_err());
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn test_diff_resigned_svn() {
+        use crate::signer::{PayloadSigner, SigningAlgorithm};
+
+        let payload = include_bytes!("../../data/blobs/td-payload.efi");
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(payload, algorithm);
+        let header = signer.build_header(1, 1);
+        let old = signer.sign(header).unwrap().to_vec();
+        let header = signer.build_header(1, 2);
+        let new = signer.sign(header).unwrap().to_vec();
+
+        // Only the SVN differs, the signature changes with the header it covers.
+        let diff = diff_signed_payloads(&old, &new).unwrap();
+        assert_eq!(
+            diff.header,
+            vec![FieldChange {
+                field: "payload_svn",
+                old: "1".to_string(),
+                new: "2".to_string(),
+            }]
+        );
+        assert!(diff.compatible);
+        assert!(!diff.payload_changed);
+        assert!(!diff.policy_changed);
+        assert!(diff.signature_changed);
+    }
+}
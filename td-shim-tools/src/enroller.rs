@@ -7,19 +7,25 @@ use std::convert::{TryFrom, TryInto};
 use std::io;
 use std::mem::size_of;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::vec::Vec;
 
-use log::error;
-use ring::digest;
+use log::{error, info, warn};
+use scroll::{Pread, LE};
 use td_layout::build_time::{TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE};
 use td_shim::fv::{FvFfsFileHeader, FvHeader};
+use td_shim::measured_config::{MeasuredConfigHeader, MEASURED_CONFIG_NAME_SIZE};
 use td_shim::secure_boot::{
-    CfvPubKeyFileHeader, CFV_FFS_HEADER_TRUST_ANCHOR_GUID, CFV_FILE_HEADER_PUBKEY_GUID,
-    PUBKEY_FILE_STRUCT_VERSION_V1, PUBKEY_HASH_ALGORITHM_SHA384,
+    verify_trust_anchor, CfvPubKeyFileHeader, CompactPayloadVerifier, HashAlg, PayloadVerifier,
+    SignedPayloadFormat, VerifyErr, CFV_FFS_HEADER_MIN_SVN_GUID,
+    CFV_FFS_HEADER_REVOCATION_LIST_GUID, CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
+    CFV_FILE_HEADER_PUBKEY_GUID, CFV_FILE_HEADER_REVOKED_PUBKEY_GUID,
+    PUBKEY_FILE_STRUCT_VERSION_V1,
 };
 use td_shim::write_u24;
+use td_uefi_pi::fv::{CfvEntry, CfvReader};
 use td_uefi_pi::pi::fv::{
-    FIRMWARE_FILE_SYSTEM3_GUID, FVH_REVISION, FVH_SIGNATURE, FV_FILETYPE_RAW,
+    FvFileType, FIRMWARE_FILE_SYSTEM3_GUID, FVH_REVISION, FVH_SIGNATURE, FV_FILETYPE_RAW,
 };
 
 use crate::public_key::{
@@ -32,6 +38,7 @@ use crate::{InputData, OutputFile};
 //
 const FFS_HEADER_HEADER_CHECKSUM_OFFSET: usize = 0x10;
 const FFS_HEADER_FILE_CHECKSUM_OFFSET: usize = 0x11;
+const FFS_HEADER_FILE_TYPE_OFFSET: usize = 0x12;
 const FFS_HEADER_FILE_STATE_OFFSET: usize = 0x17;
 
 fn update_checksum(data: &mut [u8]) {
@@ -69,6 +76,15 @@ impl FirmwareRawFile {
         }
     }
 
+    /// Build a firmware file of type `kind` instead of `FV_FILETYPE_RAW`.
+    pub fn with_type(name: &[u8; 16], kind: FvFileType) -> Self {
+        let mut file = Self::new(name);
+        file.data[FFS_HEADER_FILE_TYPE_OFFSET] = kind;
+        update_checksum(&mut file.data);
+
+        file
+    }
+
     pub fn append(&mut self, data: &[u8]) {
         // Remove the padding zeros before push the data
         self.data.truncate(self.real_size);
@@ -147,6 +163,26 @@ pub fn enroll_files(
         "shim binary",
     )?;
 
+    // Report the files in the existing CFV, they will be replaced by the enrolled ones.
+    let cfv_start = TD_SHIM_CONFIG_OFFSET as usize;
+    let cfv = &tdshim_bin.as_bytes()[cfv_start..cfv_start + TD_SHIM_CONFIG_SIZE as usize];
+    if let Ok(reader) = CfvReader::new(cfv) {
+        for entry in reader.iter() {
+            match entry {
+                Ok(entry) => info!(
+                    "Replace existing CFV file {:?}, type {:x}, size {:x}",
+                    entry.guid,
+                    entry.kind,
+                    entry.data.len()
+                ),
+                Err(e) => {
+                    warn!("Existing CFV is malformed: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
     // Build the CFV header and write on the top of CFV
     let mut output = OutputFile::new(output_file)?;
     // Write the clean shim binary into the new one
@@ -167,6 +203,119 @@ pub fn enroll_files(
     Ok(())
 }
 
+fn merge_error(reason: &str) -> io::Error {
+    error!("Can not merge CFVs: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+fn read_cfv_entries<'a>(cfv: &'a [u8], name: &str) -> io::Result<Vec<CfvEntry<'a>>> {
+    let reader = CfvReader::new(cfv).map_err(|e| {
+        error!("Invalid {} CFV: {:?}", name, e);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid CFV")
+    })?;
+    reader
+        .iter()
+        .collect::<Result<Vec<CfvEntry>, _>>()
+        .map_err(|e| {
+            error!("Malformed {} CFV: {:?}", name, e);
+            io::Error::new(io::ErrorKind::InvalidData, "malformed CFV")
+        })
+}
+
+// Split a revocation list into its hash algorithm and revoked key fingerprints.
+fn parse_revocation_list(data: &[u8]) -> io::Result<(HashAlg, Vec<&[u8]>)> {
+    let header_size = size_of::<CfvPubKeyFileHeader>();
+    let header: CfvPubKeyFileHeader = data
+        .pread_with(0, LE)
+        .map_err(|_| merge_error("invalid revocation list"))?;
+    let hash_alg = HashAlg::from_id(header.hash_algorithm)
+        .ok_or_else(|| merge_error("unknown hash algorithm of revocation list"))?;
+    let length = header.length as usize;
+    if &header.type_guid != CFV_FILE_HEADER_REVOKED_PUBKEY_GUID.as_bytes()
+        || length < header_size
+        || length > data.len()
+        || (length - header_size) % hash_alg.output_len() != 0
+    {
+        return Err(merge_error("invalid revocation list"));
+    }
+
+    Ok((
+        hash_alg,
+        data[header_size..length]
+            .chunks(hash_alg.output_len())
+            .collect(),
+    ))
+}
+
+/// Merge the firmware files of the CFVs `cfv_a` and `cfv_b` into a new CFV.
+///
+/// The files of `cfv_a` come first, files present in both CFVs are kept once. The revoked key
+/// fingerprints of both revocation lists are merged into one list, other files of the same name
+/// must have the same type and content because td-shim only reads the first of them.
+pub fn merge_cfvs(cfv_a: &[u8], cfv_b: &[u8]) -> io::Result<Vec<u8>> {
+    let mut entries = read_cfv_entries(cfv_a, "first")?;
+    let mut revoked: Option<(HashAlg, Vec<&[u8]>)> = None;
+    for entry in read_cfv_entries(cfv_b, "second")? {
+        match entries.iter().find(|v| v.guid == entry.guid) {
+            None => entries.push(entry),
+            Some(v) if v.kind == entry.kind && v.data == entry.data => {}
+            Some(v)
+                if entry.guid == CFV_FFS_HEADER_REVOCATION_LIST_GUID
+                    && v.kind == FV_FILETYPE_RAW
+                    && entry.kind == FV_FILETYPE_RAW =>
+            {
+                let (hash_alg, mut fingerprints) = parse_revocation_list(v.data)?;
+                let (other_alg, other) = parse_revocation_list(entry.data)?;
+                if hash_alg != other_alg {
+                    return Err(merge_error(&format!(
+                        "revocation lists of different hash algorithms {} and {}",
+                        hash_alg, other_alg
+                    )));
+                }
+                for fingerprint in other {
+                    if !fingerprints.contains(&fingerprint) {
+                        fingerprints.push(fingerprint);
+                    }
+                }
+                revoked = Some((hash_alg, fingerprints));
+            }
+            Some(_) => {
+                return Err(merge_error(&format!(
+                    "conflicting CFV files named {:?}",
+                    entry.guid
+                )));
+            }
+        }
+    }
+
+    let mut cfv = build_cfv_header().as_bytes().to_vec();
+    for entry in entries {
+        let file = match &revoked {
+            Some((hash_alg, fingerprints)) if entry.guid == CFV_FFS_HEADER_REVOCATION_LIST_GUID => {
+                let fingerprints: Vec<Vec<u8>> = fingerprints.iter().map(|v| v.to_vec()).collect();
+                create_revocation_list_file(&fingerprints, hash_alg.name())?
+            }
+            _ => {
+                let mut file = FirmwareRawFile::with_type(entry.guid.as_bytes(), entry.kind);
+                file.append(entry.data);
+                file
+            }
+        };
+        cfv.extend_from_slice(file.as_bytes());
+    }
+
+    if cfv.len() > TD_SHIM_CONFIG_SIZE as usize {
+        return Err(merge_error(&format!(
+            "merged CFV of {:#x} bytes exceeds the CFV region of {:#x} bytes",
+            cfv.len(),
+            TD_SHIM_CONFIG_SIZE
+        )));
+    }
+    cfv.resize(TD_SHIM_CONFIG_SIZE as usize, 0);
+
+    Ok(cfv)
+}
+
 // Uncompressed ecdsa p384 public key length
 const ECDSA_P384_PUB_KEY_LEN: usize = 96;
 // Prefix of uncompressed ecdsa public key
@@ -176,7 +325,7 @@ const RSA_EXPONENT: u64 = 0x10001;
 // RSA 3072 public key modulus length
 const RSA_3072_PUB_KEY_MOD_LEN: usize = 384;
 // Maximum size of public key file (1024 KiB)
-const PUB_KEY_MAX_SIZE: usize = 1024 * 1024;
+pub const PUB_KEY_MAX_SIZE: usize = 1024 * 1024;
 
 /// Build a firmware file which contains public key bytes for secure boot.
 ///
@@ -186,19 +335,16 @@ const PUB_KEY_MAX_SIZE: usize = 1024 * 1024;
 ///
 /// Please refer to section "Trust Anchor in Td-Shim" in doc/secure_boot.md for definitions.
 pub fn create_key_file(key_file: &str, hash_alg: &str) -> io::Result<FirmwareRawFile> {
-    let hash_alg = match hash_alg {
-        "SHA384" => &digest::SHA384,
-        _ => {
-            error!("Unsupported hash algorithm {}", hash_alg);
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "unsupported hash algorithm",
-            ));
-        }
-    };
-
     let key_data = InputData::new(key_file, 1..=PUB_KEY_MAX_SIZE, "public key")?;
-    let key = SubjectPublicKeyInfo::try_from(key_data.as_bytes()).map_err(|e| {
+    let public_bytes = parse_public_key(key_data.as_bytes(), key_file)?;
+
+    create_trust_anchor_file(&public_bytes, hash_alg)
+}
+
+/// Convert the DER encoded SubjectPublicKeyInfo `der` read from `key_file` into the format of the
+/// signature block of a signed payload, RSA:N||E or ECDSA:X||Y.
+pub fn parse_public_key(der: &[u8], key_file: &str) -> io::Result<Vec<u8>> {
+    let key = SubjectPublicKeyInfo::try_from(der).map_err(|e| {
         error!("Can not load key from file {}: {}", key_file, e);
         io::Error::new(io::ErrorKind::Other, "invalid key data")
     })?;
@@ -272,8 +418,53 @@ pub fn create_key_file(key_file: &str, hash_alg: &str) -> io::Result<FirmwareRaw
         }
     }
 
+    Ok(public_bytes)
+}
+
+pub const PEM_PUBLIC_KEY_BEGIN: &str = "-----BEGIN PUBLIC KEY-----";
+pub const PEM_PUBLIC_KEY_END: &str = "-----END PUBLIC KEY-----";
+
+/// Parse the PEM encoded SubjectPublicKeyInfo `text` of the file `name` into the format of the
+/// signature block of a signed payload.
+pub fn parse_pem_public_key(text: &str, name: &str) -> io::Result<Vec<u8>> {
+    let invalid = |reason: &str| {
+        error!("Can not load key from file {}: {}", name, reason);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid key data")
+    };
+    let body = text
+        .trim()
+        .strip_prefix(PEM_PUBLIC_KEY_BEGIN)
+        .and_then(|v| v.strip_suffix(PEM_PUBLIC_KEY_END))
+        .ok_or_else(|| invalid("expect a PEM encoded public key"))?;
+    let encoded: String = body.split_whitespace().collect();
+    let der = base64::decode(encoded).map_err(|_| invalid("invalid base64"))?;
+
+    parse_public_key(&der, name)
+}
+
+/// Parse the PEM or DER encoded SubjectPublicKeyInfo `data` of the file `name` into the format of
+/// the signature block of a signed payload.
+pub fn parse_public_key_file(data: &[u8], name: &str) -> io::Result<Vec<u8>> {
+    match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with(PEM_PUBLIC_KEY_BEGIN) => {
+            parse_pem_public_key(text, name)
+        }
+        _ => parse_public_key(data, name),
+    }
+}
+
+/// Build a firmware file which contains the hash of `public_bytes` for secure boot.
+///
+/// `public_bytes` is the public key in the format of the signature block of a signed payload,
+/// RSA:N||E or ECDSA:X||Y.
+pub fn create_trust_anchor_file(
+    public_bytes: &[u8],
+    hash_alg: &str,
+) -> io::Result<FirmwareRawFile> {
+    let hash_alg = parse_hash_alg(hash_alg)?;
+
     // Hash public key
-    let hash = digest::digest(hash_alg, public_bytes.as_slice());
+    let hash = hash_alg.digest(public_bytes);
     let hash = hash.as_ref();
 
     // Create a firmware file to hold secure boot contens
@@ -283,21 +474,294 @@ pub fn create_key_file(key_file: &str, hash_alg: &str) -> io::Result<FirmwareRaw
         type_guid: *CFV_FILE_HEADER_PUBKEY_GUID.as_bytes(),
         struct_version: PUBKEY_FILE_STRUCT_VERSION_V1,
         length: (size_of::<CfvPubKeyFileHeader>() + hash.len()) as u32,
-        hash_algorithm: PUBKEY_HASH_ALGORITHM_SHA384,
+        hash_algorithm: hash_alg.id(),
         ..Default::default()
     };
-    ff.append(pub_key_header.as_bytes());
+    ff.append(&pub_key_header.to_bytes());
     // public key hash value
     ff.append(hash);
 
     Ok(ff)
 }
 
+/// Compute the hash of the DER encoded public key in `key_file`, the fingerprint of the key in a
+/// revocation list.
+pub fn key_file_fingerprint(key_file: &str, hash_alg: &str) -> io::Result<Vec<u8>> {
+    let hash_alg = parse_hash_alg(hash_alg)?;
+    let key_data = InputData::new(key_file, 1..=PUB_KEY_MAX_SIZE, "public key")?;
+    let public_bytes = parse_public_key(key_data.as_bytes(), key_file)?;
+
+    Ok(hash_alg.digest(&public_bytes).as_ref().to_vec())
+}
+
+/// A trust anchor enrolled into a CFV, compared with a public key by `check_enrolled_key()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnrolledAnchor {
+    /// Hash algorithm recorded in the trust anchor.
+    pub hash_alg: HashAlg,
+    /// Enrolled public key hash.
+    pub hash: Vec<u8>,
+    /// Hash of the checked public key, computed with `hash_alg`.
+    pub key_hash: Vec<u8>,
+}
+
+impl EnrolledAnchor {
+    /// Whether the checked public key matches the enrolled hash.
+    pub fn matches(&self) -> bool {
+        self.hash == self.key_hash
+    }
+}
+
+/// Result of checking a public key against the trust anchors enrolled into a CFV.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnrollmentCheck {
+    /// Trust anchors enrolled into the CFV, in order. td-shim only reads the first of them.
+    pub anchors: Vec<EnrolledAnchor>,
+    /// Result of the trust anchor check td-shim runs at boot, including the revocation list.
+    pub result: Result<(), VerifyErr>,
+}
+
+/// Check the public key `public_bytes`, in the format of the signature block of a signed payload,
+/// against the trust anchors enrolled into the CFV `cfv`.
+///
+/// The key is hashed the same way as td-shim does at boot, so a re-encoded key which doesn't
+/// hash to the enrolled value is reported as a mismatch without booting the image.
+pub fn check_enrolled_key(cfv: &[u8], public_bytes: &[u8]) -> io::Result<EnrollmentCheck> {
+    let mut anchors = Vec::new();
+    for entry in read_cfv_entries(cfv, "enrolled")? {
+        if entry.kind != FV_FILETYPE_RAW || entry.guid != CFV_FFS_HEADER_TRUST_ANCHOR_GUID {
+            continue;
+        }
+        let header_size = size_of::<CfvPubKeyFileHeader>();
+        let header: CfvPubKeyFileHeader = entry.data.pread_with(0, LE).map_err(|_| {
+            error!("Invalid trust anchor in CFV");
+            io::Error::new(io::ErrorKind::InvalidData, "invalid trust anchor")
+        })?;
+        let length = header.length as usize;
+        let hash_alg = HashAlg::from_id(header.hash_algorithm);
+        if &header.type_guid != CFV_FILE_HEADER_PUBKEY_GUID.as_bytes()
+            || length < header_size
+            || length > entry.data.len()
+            || hash_alg.is_none()
+        {
+            error!("Invalid trust anchor in CFV");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid trust anchor",
+            ));
+        }
+        // Safe to unwrap() because it has been checked above.
+        let hash_alg = hash_alg.unwrap();
+        anchors.push(EnrolledAnchor {
+            hash_alg,
+            hash: entry.data[header_size..length].to_vec(),
+            key_hash: hash_alg.digest(public_bytes).as_ref().to_vec(),
+        });
+    }
+    if anchors.is_empty() {
+        error!("No trust anchor is enrolled into the CFV");
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no trust anchor enrolled",
+        ));
+    }
+
+    Ok(EnrollmentCheck {
+        anchors,
+        result: verify_trust_anchor(cfv, public_bytes),
+    })
+}
+
+/// Get the public key of the primary signature of `signed_payload`, in the standard or the
+/// compact format, in the format of the signature block.
+///
+/// The signature is not checked against any trust anchor.
+pub fn signed_payload_public_key(signed_payload: &[u8]) -> io::Result<&[u8]> {
+    let status = match SignedPayloadFormat::detect(signed_payload) {
+        Some(SignedPayloadFormat::Standard) => PayloadVerifier::new(signed_payload, &[])
+            .map(|v| v.verify_all_with(|_| Ok(())).remove(0)),
+        Some(SignedPayloadFormat::Compact) => CompactPayloadVerifier::new(signed_payload, &[])
+            .map(|v| v.verify_all_with(|_| Ok(())).remove(0)),
+        None => Err(VerifyErr::InvalidContent),
+    }
+    .map_err(|e| {
+        error!("Invalid signed payload: {:?}", e);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload")
+    })?;
+
+    Ok(status.public_key)
+}
+
+/// Check that `public_bytes`, the public key to be enrolled, is the key which signed
+/// `signed_payload`, so that td-shim doesn't reject the payload at boot.
+///
+/// The keys are compared by their `hash_alg` hashes, which are the trust anchor to be enrolled and
+/// the trust anchor the payload needs.
+pub fn check_signed_payload_key(
+    signed_payload: &[u8],
+    public_bytes: &[u8],
+    hash_alg: &str,
+) -> io::Result<()> {
+    let hash_alg = parse_hash_alg(hash_alg)?;
+    let enrolled = hash_alg.digest(public_bytes);
+    let signing = hash_alg.digest(signed_payload_public_key(signed_payload)?);
+    if enrolled.as_ref() != signing.as_ref() {
+        error!(
+            "The enrolled public key is not the key which signed the payload, {} of the enrolled \
+             key: {}, {} of the signing key: {}",
+            hash_alg.name(),
+            hex::encode(enrolled),
+            hash_alg.name(),
+            hex::encode(signing)
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "enrolled public key doesn't match the signing key",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that the public key embedded in `signed_payload` has the `hash_alg` fingerprint
+/// `fingerprint`, given in hex, as a quick check of the identity of the signing key.
+///
+/// Only the key identity is checked, the signature is not verified, so a payload tampered with
+/// after signing passes the check.
+pub fn check_key_fingerprint(
+    signed_payload: &[u8],
+    fingerprint: &str,
+    hash_alg: &str,
+) -> io::Result<()> {
+    let hash_alg = parse_hash_alg(hash_alg)?;
+    let expected = match hex::decode(fingerprint.trim()) {
+        Ok(v) if v.len() == hash_alg.output_len() => v,
+        _ => {
+            error!(
+                "Invalid {} key fingerprint {}, expect {} hex digits",
+                hash_alg.name(),
+                fingerprint,
+                hash_alg.output_len() * 2
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid key fingerprint",
+            ));
+        }
+    };
+    let signing = hash_alg.digest(signed_payload_public_key(signed_payload)?);
+    if signing.as_ref() != expected.as_slice() {
+        error!(
+            "The payload is not signed by the expected key, {} of the expected key: {}, {} of \
+             the signing key: {}",
+            hash_alg.name(),
+            hex::encode(expected),
+            hash_alg.name(),
+            hex::encode(signing)
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "signing key doesn't match the expected fingerprint",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Build a firmware file which contains the revocation list `fingerprints`, the hashes of public
+/// keys td-shim must reject even if they match the trust anchor.
+///
+/// Please refer to section "Revocation List in Td-Shim" in doc/secure_boot.md for definitions.
+pub fn create_revocation_list_file(
+    fingerprints: &[Vec<u8>],
+    hash_alg: &str,
+) -> io::Result<FirmwareRawFile> {
+    let hash_alg = parse_hash_alg(hash_alg)?;
+    if let Some(v) = fingerprints
+        .iter()
+        .find(|v| v.len() != hash_alg.output_len())
+    {
+        error!(
+            "Invalid revoked key fingerprint {}, expect a {} hash of {} bytes",
+            hex::encode(v),
+            hash_alg,
+            hash_alg.output_len()
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid revoked key fingerprint",
+        ));
+    }
+
+    let mut ff = FirmwareRawFile::new(CFV_FFS_HEADER_REVOCATION_LIST_GUID.as_bytes());
+    let header = CfvPubKeyFileHeader {
+        type_guid: *CFV_FILE_HEADER_REVOKED_PUBKEY_GUID.as_bytes(),
+        struct_version: PUBKEY_FILE_STRUCT_VERSION_V1,
+        length: (size_of::<CfvPubKeyFileHeader>() + fingerprints.len() * hash_alg.output_len())
+            as u32,
+        hash_algorithm: hash_alg.id(),
+        ..Default::default()
+    };
+    ff.append(&header.to_bytes());
+    for fingerprint in fingerprints {
+        ff.append(fingerprint);
+    }
+
+    Ok(ff)
+}
+
+/// Build a firmware file which contains the minimum payload SVN td-shim accepts, whatever the SVN
+/// policy attached to the signed payload.
+///
+/// Please refer to section "Minimum SVN in Td-Shim" in doc/secure_boot.md for definitions.
+pub fn create_min_svn_file(min_svn: u64) -> FirmwareRawFile {
+    let mut ff = FirmwareRawFile::new(CFV_FFS_HEADER_MIN_SVN_GUID.as_bytes());
+    ff.append(&min_svn.to_le_bytes());
+    ff
+}
+
+fn parse_hash_alg(hash_alg: &str) -> io::Result<HashAlg> {
+    HashAlg::from_str(hash_alg).map_err(|_| {
+        error!(
+            "Unsupported hash algorithm {}, expect one of {:?}",
+            hash_alg,
+            HashAlg::ALL_NAMES
+        );
+        io::Error::new(io::ErrorKind::Other, "unsupported hash algorithm")
+    })
+}
+
+/// Build a firmware file named `guid` which contains the config blob `data` named `name`, to be
+/// measured into RTMR[0] by td-shim at boot.
+///
+/// Please refer to section "Measured Config in Td-Shim" in doc/secure_boot.md for definitions.
+pub fn create_measured_config_file(
+    guid: &[u8; 16],
+    name: &str,
+    data: &[u8],
+) -> io::Result<FirmwareRawFile> {
+    let header = MeasuredConfigHeader::new(name.as_bytes(), data.len()).ok_or_else(|| {
+        error!(
+            "Invalid measured config name '{}', expect 1 to {} bytes",
+            name, MEASURED_CONFIG_NAME_SIZE
+        );
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid measured config name")
+    })?;
+
+    let mut ff = FirmwareRawFile::new(guid);
+    ff.append(&header.to_bytes());
+    ff.append(data);
+
+    Ok(ff)
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
 
     use super::*;
+    use td_shim::secure_boot::{check_min_svn, get_min_svn};
+    use td_uefi_pi::pi::fv::FV_FILETYPE_FREEFORM;
     use td_uefi_pi::pi::guid;
 
     fn read_u24(data: &[u8]) -> u32 {
@@ -329,4 +793,280 @@ mod test {
         assert_eq!(read_u24(&ff.as_bytes()[20..23]), size as u32 + 25);
         assert_eq!(ff.as_bytes().len(), size + 32);
     }
+
+    fn build_cfv(files: &[FirmwareRawFile]) -> Vec<u8> {
+        let mut cfv = build_cfv_header().as_bytes().to_vec();
+        for f in files {
+            cfv.extend_from_slice(f.as_bytes());
+        }
+        cfv.resize(TD_SHIM_CONFIG_SIZE as usize, 0);
+        cfv
+    }
+
+    #[test]
+    fn test_revocation_list() {
+        let ecdsa_der = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+        let ecdsa_key = parse_public_key(ecdsa_der, "ecdsa").unwrap();
+        let rsa_der = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+        let rsa_key = parse_public_key(rsa_der, "rsa").unwrap();
+        let ecdsa_hash = HashAlg::Sha384.digest(&ecdsa_key).as_ref().to_vec();
+        let rsa_hash = HashAlg::Sha384.digest(&rsa_key).as_ref().to_vec();
+
+        // A clean trusted key is accepted.
+        let cfv = build_cfv(&[
+            create_trust_anchor_file(&ecdsa_key, "SHA384").unwrap(),
+            create_revocation_list_file(&[rsa_hash.clone()], "SHA384").unwrap(),
+        ]);
+        assert!(verify_trust_anchor(&cfv, &ecdsa_key).is_ok());
+        assert_eq!(
+            verify_trust_anchor(&cfv, &rsa_key),
+            Err(VerifyErr::RevokedPublicKey)
+        );
+
+        // A key both trusted and revoked is rejected.
+        let cfv = build_cfv(&[
+            create_trust_anchor_file(&ecdsa_key, "SHA384").unwrap(),
+            create_revocation_list_file(&[rsa_hash, ecdsa_hash], "SHA384").unwrap(),
+        ]);
+        assert_eq!(
+            verify_trust_anchor(&cfv, &ecdsa_key),
+            Err(VerifyErr::RevokedPublicKey)
+        );
+
+        // An empty revocation list revokes nothing.
+        let cfv = build_cfv(&[
+            create_revocation_list_file(&[], "SHA256").unwrap(),
+            create_trust_anchor_file(&ecdsa_key, "SHA384").unwrap(),
+        ]);
+        assert!(verify_trust_anchor(&cfv, &ecdsa_key).is_ok());
+
+        // The fingerprints must be hashes of the given algorithm.
+        assert!(create_revocation_list_file(&[vec![0u8; 48]], "SHA256").is_err());
+        assert!(create_revocation_list_file(&[vec![0u8; 48]], "MD5").is_err());
+    }
+
+    #[test]
+    fn test_min_svn_file() {
+        let cfv = build_cfv(&[create_min_svn_file(3)]);
+        assert!(matches!(get_min_svn(&cfv), Ok(Some(3))));
+        assert!(matches!(
+            check_min_svn(&cfv, 2),
+            Err(VerifyErr::SvnPolicyViolation)
+        ));
+        assert!(check_min_svn(&cfv, 3).is_ok());
+        assert!(matches!(get_min_svn(&build_cfv(&[])), Ok(None)));
+    }
+
+    #[test]
+    fn test_check_enrolled_key() {
+        let ecdsa_der = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+        let ecdsa_key = parse_public_key(ecdsa_der, "ecdsa").unwrap();
+        let rsa_der = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+        let rsa_key = parse_public_key(rsa_der, "rsa").unwrap();
+
+        // The PEM and DER encodings of a key parse to the same key.
+        let pem = format!(
+            "{}\n{}\n{}\n",
+            PEM_PUBLIC_KEY_BEGIN,
+            base64::encode(ecdsa_der),
+            PEM_PUBLIC_KEY_END
+        );
+        assert_eq!(
+            parse_public_key_file(pem.as_bytes(), "ecdsa").unwrap(),
+            ecdsa_key
+        );
+        assert_eq!(
+            parse_public_key_file(ecdsa_der, "ecdsa").unwrap(),
+            ecdsa_key
+        );
+        assert!(parse_pem_public_key(&pem[1..], "ecdsa").is_err());
+
+        // A matching key.
+        let cfv = build_cfv(&[create_trust_anchor_file(&ecdsa_key, "SHA256").unwrap()]);
+        let check = check_enrolled_key(&cfv, &ecdsa_key).unwrap();
+        assert_eq!(check.result, Ok(()));
+        assert_eq!(check.anchors.len(), 1);
+        assert_eq!(check.anchors[0].hash_alg, HashAlg::Sha256);
+        assert!(check.anchors[0].matches());
+
+        // Another key.
+        let check = check_enrolled_key(&cfv, &rsa_key).unwrap();
+        assert_eq!(check.result, Err(VerifyErr::InvalidPublicKey));
+        assert!(!check.anchors[0].matches());
+
+        // The same key enrolled in another encoding, the uncompressed SEC1 point or the whole
+        // SubjectPublicKeyInfo, doesn't match the key td-shim hashes at boot.
+        let mut point = vec![ECDSA_UNCOMPRESSED_PUB_KEY_PREFIX];
+        point.extend_from_slice(&ecdsa_key);
+        for encoded in [point.as_slice(), ecdsa_der.as_slice()] {
+            let cfv = build_cfv(&[create_trust_anchor_file(encoded, "SHA384").unwrap()]);
+            let check = check_enrolled_key(&cfv, &ecdsa_key).unwrap();
+            assert_eq!(check.result, Err(VerifyErr::InvalidPublicKey));
+            assert!(!check.anchors[0].matches());
+            assert_eq!(
+                check.anchors[0].key_hash,
+                HashAlg::Sha384.digest(&ecdsa_key).as_ref()
+            );
+        }
+
+        // A revoked key matches the trust anchor but is rejected.
+        let rsa_hash = HashAlg::Sha384.digest(&rsa_key).as_ref().to_vec();
+        let cfv = build_cfv(&[
+            create_trust_anchor_file(&rsa_key, "SHA384").unwrap(),
+            create_revocation_list_file(&[rsa_hash], "SHA384").unwrap(),
+        ]);
+        let check = check_enrolled_key(&cfv, &rsa_key).unwrap();
+        assert!(check.anchors[0].matches());
+        assert_eq!(check.result, Err(VerifyErr::RevokedPublicKey));
+
+        // No trust anchor.
+        let cfv = build_cfv(&[create_revocation_list_file(&[], "SHA384").unwrap()]);
+        assert!(check_enrolled_key(&cfv, &ecdsa_key).is_err());
+    }
+
+    #[test]
+    fn test_check_signed_payload_key() {
+        let signed =
+            include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let ecdsa_der = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+        let ecdsa_key = parse_public_key(ecdsa_der, "ecdsa").unwrap();
+        let rsa_der = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+        let rsa_key = parse_public_key(rsa_der, "rsa").unwrap();
+
+        // The sample payload is signed with the sample ECDSA key.
+        assert_eq!(signed_payload_public_key(signed).unwrap(), ecdsa_key);
+        for hash_alg in ["SHA256", "SHA384", "SHA512"] {
+            assert!(check_signed_payload_key(signed, &ecdsa_key, hash_alg).is_ok());
+        }
+
+        // Enrolling another key.
+        assert_eq!(
+            check_signed_payload_key(signed, &rsa_key, "SHA384")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // Not a signed payload, or an unknown hash algorithm.
+        assert!(check_signed_payload_key(&signed[1..], &ecdsa_key, "SHA384").is_err());
+        assert!(check_signed_payload_key(signed, &ecdsa_key, "MD5").is_err());
+    }
+
+    #[test]
+    fn test_check_key_fingerprint() {
+        let signed =
+            include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let ecdsa_der = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+        let ecdsa_key = parse_public_key(ecdsa_der, "ecdsa").unwrap();
+        let rsa_der = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+        let rsa_key = parse_public_key(rsa_der, "rsa").unwrap();
+        let fingerprint =
+            |key: &[u8]| hex::encode(ring::digest::digest(&ring::digest::SHA256, key));
+
+        // The sample payload is signed with the sample ECDSA key.
+        let expected = fingerprint(&ecdsa_key);
+        assert!(check_key_fingerprint(signed, &expected, "SHA256").is_ok());
+        assert!(check_key_fingerprint(signed, &expected.to_uppercase(), "SHA256").is_ok());
+
+        // The signature is not verified.
+        let mut tampered = signed.to_vec();
+        tampered[0x1000] ^= 0xff;
+        assert!(check_key_fingerprint(&tampered, &expected, "SHA256").is_ok());
+
+        // Fingerprint of another key.
+        assert_eq!(
+            check_key_fingerprint(signed, &fingerprint(&rsa_key), "SHA256")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // Malformed fingerprints, or not a signed payload.
+        for (v, hash_alg) in [
+            (&expected[2..], "SHA256"),
+            ("not a fingerprint", "SHA256"),
+            (expected.as_str(), "SHA384"),
+        ] {
+            assert_eq!(
+                check_key_fingerprint(signed, v, hash_alg)
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        assert!(check_key_fingerprint(&signed[1..], &expected, "SHA256").is_err());
+    }
+
+    fn cfv_entries(cfv: &[u8]) -> Vec<(guid::Guid, FvFileType, Vec<u8>)> {
+        CfvReader::new(cfv)
+            .unwrap()
+            .iter()
+            .map(|v| v.map(|v| (v.guid, v.kind, v.data.to_vec())))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge_cfvs() {
+        let ecdsa_der = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+        let ecdsa_key = parse_public_key(ecdsa_der, "ecdsa").unwrap();
+        let rsa_der = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+        let rsa_key = parse_public_key(rsa_der, "rsa").unwrap();
+        let rsa_hash = HashAlg::Sha384.digest(&rsa_key).as_ref().to_vec();
+        let config_guid = guid::Guid::from_str("5D1A7C3E-2F64-4B9A-8C0E-93A1B2C4D5E6").unwrap();
+        let freeform_guid = guid::Guid::from_str("214D240F-77A3-441B-9DA8-C588E43192C1").unwrap();
+
+        let keys = build_cfv(&[
+            create_trust_anchor_file(&ecdsa_key, "SHA384").unwrap(),
+            create_revocation_list_file(&[rsa_hash.clone()], "SHA384").unwrap(),
+        ]);
+        let mut freeform =
+            FirmwareRawFile::with_type(freeform_guid.as_bytes(), FV_FILETYPE_FREEFORM);
+        freeform.append(b"freeform file");
+        let configs = build_cfv(&[
+            create_measured_config_file(config_guid.as_bytes(), "boot_config", b"{}").unwrap(),
+            freeform,
+        ]);
+
+        // All entries survive with their names and types.
+        let merged = merge_cfvs(&keys, &configs).unwrap();
+        assert_eq!(merged.len(), TD_SHIM_CONFIG_SIZE as usize);
+        let mut expected = cfv_entries(&keys);
+        expected.extend(cfv_entries(&configs));
+        assert_eq!(cfv_entries(&merged), expected);
+        assert_eq!(expected[3].1, FV_FILETYPE_FREEFORM);
+        assert!(verify_trust_anchor(&merged, &ecdsa_key).is_ok());
+        assert_eq!(
+            verify_trust_anchor(&merged, &rsa_key),
+            Err(VerifyErr::RevokedPublicKey)
+        );
+
+        // Identical files are kept once, revoked key fingerprints are de-duplicated.
+        assert_eq!(merge_cfvs(&merged, &configs).unwrap(), merged);
+        let revoked = build_cfv(&[create_revocation_list_file(
+            &[vec![0x5a; 48], rsa_hash.clone()],
+            "SHA384",
+        )
+        .unwrap()]);
+        let entries = cfv_entries(&merge_cfvs(&keys, &revoked).unwrap());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].0, CFV_FFS_HEADER_REVOCATION_LIST_GUID);
+        let (_, fingerprints) = parse_revocation_list(&entries[1].2).unwrap();
+        assert_eq!(fingerprints, vec![rsa_hash.as_slice(), &[0x5a; 48][..]]);
+
+        // A different trust anchor or hash algorithm of revocation lists conflicts.
+        let other_anchor = build_cfv(&[create_trust_anchor_file(&rsa_key, "SHA384").unwrap()]);
+        assert!(merge_cfvs(&keys, &other_anchor).is_err());
+        let other_alg = build_cfv(&[create_revocation_list_file(&[], "SHA256").unwrap()]);
+        assert!(merge_cfvs(&keys, &other_alg).is_err());
+
+        // The merged CFV must fit the CFV region.
+        let mut big = FirmwareRawFile::new(freeform_guid.as_bytes());
+        big.append(&vec![0x5a; TD_SHIM_CONFIG_SIZE as usize / 2]);
+        let mut other_big = FirmwareRawFile::new(config_guid.as_bytes());
+        other_big.append(&vec![0xa5; TD_SHIM_CONFIG_SIZE as usize / 2]);
+        assert!(merge_cfvs(&build_cfv(&[big]), &build_cfv(&[other_big])).is_err());
+
+        assert!(merge_cfvs(&keys, &[0u8; 16]).is_err());
+    }
 }
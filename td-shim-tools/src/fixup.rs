@@ -0,0 +1,272 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Recompute the checksums and length fields of a td-shim image after patching it.
+//!
+//! td-shim generates the ACPI tables at runtime, so there's no ACPI checksum in the image.
+
+use std::fmt;
+use std::mem::size_of;
+
+use scroll::{Pread, Pwrite, LE};
+use td_layout::build_time::{
+    TD_SHIM_CONFIG_OFFSET, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_IPL_OFFSET, TD_SHIM_PAYLOAD_OFFSET,
+};
+use td_shim::metadata::{
+    TdxMetadataDescriptor, TdxMetadataGuid, TdxMetadataSection, TDX_METADATA_DESCRIPTOR_LEN,
+    TDX_METADATA_GUID_LEN, TDX_METADATA_OFFSET, TDX_METADATA_SECTION_LEN,
+    TDX_METADATA_SECTION_TYPE_BFV, TDX_METADATA_SECTION_TYPE_CFV, TDX_METADATA_SIGNATURE,
+};
+use td_uefi_pi::pi::fv::{FfsFileHeader, FirmwareVolumeHeader, FVH_SIGNATURE};
+
+/// A field of the image rewritten by `fixup_image()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fixup {
+    /// Offset of the structure containing the field in the image.
+    pub offset: usize,
+    pub field: &'static str,
+    pub old: u64,
+    pub new: u64,
+}
+
+impl fmt::Display for Fixup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:x}: 0x{:x} -> 0x{:x}",
+            self.field, self.offset, self.old, self.new
+        )
+    }
+}
+
+/// Recompute the TDX metadata descriptor length, firmware volume header checksums and FFS file
+/// header checksums of `image` in place, return the fields changed.
+///
+/// Running it on a fixed image changes nothing.
+pub fn fixup_image(image: &mut [u8]) -> Vec<Fixup> {
+    let mut fixups = Vec::new();
+    let mut fv_offsets = Vec::new();
+
+    if let Some(sections) = fixup_metadata(image, &mut fixups) {
+        for section in sections.iter() {
+            if section.r#type == TDX_METADATA_SECTION_TYPE_BFV
+                || section.r#type == TDX_METADATA_SECTION_TYPE_CFV
+            {
+                fv_offsets.push(section.data_offset as usize);
+            }
+        }
+    }
+    // The firmware volumes are also located by td-layout, as td-shim does at runtime.
+    if image.len() == TD_SHIM_FIRMWARE_SIZE as usize {
+        fv_offsets.extend_from_slice(&[
+            TD_SHIM_CONFIG_OFFSET as usize,
+            TD_SHIM_PAYLOAD_OFFSET as usize,
+            TD_SHIM_IPL_OFFSET as usize,
+        ]);
+    }
+    fv_offsets.sort_unstable();
+    fv_offsets.dedup();
+
+    for offset in fv_offsets {
+        fixup_fv(image, offset, &mut fixups);
+    }
+
+    fixups
+}
+
+fn fixup_metadata(image: &mut [u8], fixups: &mut Vec<Fixup>) -> Option<Vec<TdxMetadataSection>> {
+    let ptr_offset = image.len().checked_sub(TDX_METADATA_OFFSET as usize)?;
+    let offset = image.pread_with::<u32>(ptr_offset, LE).ok()? as usize;
+    if offset < TDX_METADATA_GUID_LEN as usize
+        || offset + TDX_METADATA_DESCRIPTOR_LEN as usize > ptr_offset
+    {
+        return None;
+    }
+    let mut guid = [0u8; TDX_METADATA_GUID_LEN as usize];
+    guid.copy_from_slice(&image[offset - TDX_METADATA_GUID_LEN as usize..offset]);
+    TdxMetadataGuid::from_bytes(&guid)?;
+
+    let mut descriptor = image.pread_with::<TdxMetadataDescriptor>(offset, LE).ok()?;
+    if descriptor.signature != TDX_METADATA_SIGNATURE {
+        return None;
+    }
+    // Only trust the section count if the section table fits into the image.
+    let table_offset = offset + TDX_METADATA_DESCRIPTOR_LEN as usize;
+    let count = descriptor.number_of_section_entry as usize;
+    if count > (image.len() - table_offset) / TDX_METADATA_SECTION_LEN as usize {
+        return None;
+    }
+
+    let length = TDX_METADATA_DESCRIPTOR_LEN + TDX_METADATA_SECTION_LEN * count as u32;
+    if descriptor.length != length {
+        fixups.push(Fixup {
+            offset,
+            field: "TdxMetadata descriptor length",
+            old: descriptor.length as u64,
+            new: length as u64,
+        });
+        descriptor.length = length;
+        image.pwrite_with(&descriptor, offset, LE).ok()?;
+    }
+
+    (0..count)
+        .map(|i| {
+            image
+                .pread_with::<TdxMetadataSection>(
+                    table_offset + i * TDX_METADATA_SECTION_LEN as usize,
+                    LE,
+                )
+                .ok()
+        })
+        .collect()
+}
+
+fn fixup_fv(image: &mut [u8], offset: usize, fixups: &mut Vec<Fixup>) {
+    let mut header = match image.pread_with::<FirmwareVolumeHeader>(offset, LE) {
+        Ok(v) if v.signature == FVH_SIGNATURE => v,
+        // Regions without a firmware volume, such as a CFV before enrollment, are skipped.
+        _ => return,
+    };
+
+    if !header.validate_checksum() {
+        let old = header.checksum;
+        header.update_checksum();
+        fixups.push(Fixup {
+            offset,
+            field: "FV header checksum",
+            old: old as u64,
+            new: header.checksum as u64,
+        });
+        // Safe to unwrap() because the header has been read from the same place.
+        image.pwrite_with(header, offset, LE).unwrap();
+    }
+
+    let fv_end = (offset as u64)
+        .saturating_add(header.fv_length)
+        .min(image.len() as u64) as usize;
+    let mut file_offset = offset + header.header_length as usize;
+    loop {
+        // FFS files are 8-byte aligned relative to the start of the FV.
+        file_offset = offset + ((file_offset - offset + 7) & !7);
+        if file_offset + size_of::<FfsFileHeader>() > fv_end {
+            break;
+        }
+
+        // Free space is either erased (all 0xFF) or zero filled.
+        let raw_header = &image[file_offset..file_offset + size_of::<FfsFileHeader>()];
+        if raw_header.iter().all(|v| *v == 0xff) || raw_header.iter().all(|v| *v == 0) {
+            break;
+        }
+        // Safe to unwrap() because the range has been checked above.
+        let file_header = image.pread_with::<FfsFileHeader>(file_offset, LE).unwrap();
+        let size = file_header.size[0] as usize
+            | (file_header.size[1] as usize) << 8
+            | (file_header.size[2] as usize) << 16;
+        if size < size_of::<FfsFileHeader>() || file_offset + size > fv_end {
+            break;
+        }
+
+        if !file_header.validate_checksum() {
+            let mut fixed = file_header;
+            fixed.update_checksum();
+            // Only the header checksum is fixed, files in another state are left alone.
+            if fixed.state == file_header.state
+                && fixed.integrity_check.file == file_header.integrity_check.file
+            {
+                fixups.push(Fixup {
+                    offset: file_offset,
+                    field: "FFS file header checksum",
+                    old: file_header.integrity_check.header as u64,
+                    new: fixed.integrity_check.header as u64,
+                });
+                image.pwrite_with(fixed, file_offset, LE).unwrap();
+            }
+        }
+
+        file_offset += size;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::metadata::{TDX_METADATA_ATTRIBUTES_EXTENDMR, TDX_METADATA_GUID};
+    use td_uefi_pi::pi::fv::{FVH_REVISION, FV_FILETYPE_RAW};
+
+    const IMAGE_SIZE: usize = 0x10000;
+    const FV_OFFSET: usize = 0x2000;
+    const METADATA_OFFSET: usize = 0x8000;
+
+    fn build_image() -> Vec<u8> {
+        let mut image = vec![0u8; IMAGE_SIZE];
+
+        let mut fv_header = FirmwareVolumeHeader::default();
+        fv_header.fv_length = 0x2000;
+        fv_header.signature = FVH_SIGNATURE;
+        fv_header.header_length = (size_of::<FirmwareVolumeHeader>() + 16) as u16;
+        fv_header.revision = FVH_REVISION;
+        fv_header.update_checksum();
+        image.pwrite_with(fv_header, FV_OFFSET, LE).unwrap();
+
+        let mut file_header = FfsFileHeader::default();
+        file_header.name = [0x5a; 16];
+        file_header.r#type = FV_FILETYPE_RAW;
+        file_header.size = [0x40, 0, 0];
+        file_header.update_checksum();
+        let file_offset = FV_OFFSET + fv_header.header_length as usize;
+        image.pwrite_with(file_header, file_offset, LE).unwrap();
+
+        let mut descriptor = TdxMetadataDescriptor::default();
+        descriptor.set_sections(1);
+        let section = TdxMetadataSection {
+            data_offset: FV_OFFSET as u32,
+            raw_data_size: 0x2000,
+            memory_address: 0xfff02000,
+            memory_data_size: 0x2000,
+            r#type: TDX_METADATA_SECTION_TYPE_BFV,
+            attributes: TDX_METADATA_ATTRIBUTES_EXTENDMR,
+        };
+        image[METADATA_OFFSET..METADATA_OFFSET + 16].copy_from_slice(TDX_METADATA_GUID.as_bytes());
+        image[METADATA_OFFSET + 16..METADATA_OFFSET + 32].copy_from_slice(&descriptor.to_bytes());
+        image[METADATA_OFFSET + 32..METADATA_OFFSET + 64].copy_from_slice(&section.to_bytes());
+        let ptr = IMAGE_SIZE - TDX_METADATA_OFFSET as usize;
+        image[ptr..ptr + 4].copy_from_slice(&(METADATA_OFFSET as u32 + 16).to_le_bytes());
+
+        image
+    }
+
+    #[test]
+    fn test_fixup_clean_image() {
+        let mut image = build_image();
+        let clean = image.clone();
+        assert!(fixup_image(&mut image).is_empty());
+        assert_eq!(image, clean);
+    }
+
+    #[test]
+    fn test_fixup_corrupted_image() {
+        let clean = build_image();
+        let file_offset = FV_OFFSET + size_of::<FirmwareVolumeHeader>() + 16;
+
+        let mut image = clean.clone();
+        // FirmwareVolumeHeader.checksum
+        image[FV_OFFSET + 50] ^= 0x5a;
+        // FfsFileHeader.integrity_check.header
+        image[file_offset + 16] ^= 0x01;
+        // TdxMetadataDescriptor.length
+        image[METADATA_OFFSET + 16 + 4] = 0x80;
+
+        let fixups = fixup_image(&mut image);
+        assert_eq!(fixups.len(), 3);
+        assert_eq!(fixups[0].field, "TdxMetadata descriptor length");
+        assert_eq!(fixups[0].new, 48);
+        assert_eq!(fixups[1].offset, FV_OFFSET);
+        assert_eq!(fixups[2].offset, file_offset);
+        assert_eq!(image, clean);
+
+        // Running it again changes nothing.
+        assert!(fixup_image(&mut image).is_empty());
+        assert_eq!(image, clean);
+    }
+}
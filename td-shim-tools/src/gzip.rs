@@ -0,0 +1,139 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Gzip encoding of signed payload files for transport.
+//!
+//! Only the file is encoded, the signed bytes are unchanged and td-shim always loads the plain
+//! signed payload, so the files must be decompressed before they are put into a shim image.
+
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::error;
+
+use crate::InputData;
+
+/// Magic number at the start of gzip files.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Check whether `data` starts with the gzip magic number.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/// Encode `data` in the gzip format.
+pub fn gzip_encode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| {
+            error!("Can not gzip encode data: {}", e);
+            e
+        })
+}
+
+/// Decode the gzip encoded `data`, which must decompress to at most `max_size` bytes.
+pub fn gzip_decode(data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| {
+            error!("Can not decompress gzip data: {}", e);
+            e
+        })?;
+    if decoded.len() > max_size {
+        error!(
+            "Decompressed gzip data exceeds the maximum size 0x{:x}",
+            max_size
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompressed gzip data is too big",
+        ));
+    }
+
+    Ok(decoded)
+}
+
+impl InputData {
+    /// Decompress the input data in place if it's gzip encoded, to at most `max_size` bytes.
+    ///
+    /// Return whether the input data was gzip encoded.
+    pub fn decompress_gzip(&mut self, max_size: usize) -> io::Result<bool> {
+        if !is_gzip(&self.data) {
+            return Ok(false);
+        }
+        self.data = gzip_decode(&self.data, max_size)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use td_layout::build_time::TD_SHIM_FIRMWARE_SIZE;
+    use td_shim::secure_boot::PayloadVerifier;
+
+    use crate::OutputFile;
+
+    const CFV: &[u8] = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+    const SIGNED_PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+    fn read_signed_payload(name: &str) -> (InputData, bool) {
+        let mut input =
+            InputData::new(name, 0..=TD_SHIM_FIRMWARE_SIZE as usize, "signed payload").unwrap();
+        let gzipped = input
+            .decompress_gzip(TD_SHIM_FIRMWARE_SIZE as usize)
+            .unwrap();
+        (input, gzipped)
+    }
+
+    #[test]
+    fn test_gzip_signed_payload() {
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("td-shim-signed-{}", std::process::id()));
+        let gzipped = dir.join(format!("td-shim-signed-{}.gz", std::process::id()));
+        fs::write(&plain, SIGNED_PAYLOAD).unwrap();
+        let mut output = OutputFile::new(&gzipped).unwrap();
+        output
+            .seek_and_write(0, &gzip_encode(SIGNED_PAYLOAD).unwrap(), "signed payload")
+            .unwrap();
+        output.flush().unwrap();
+
+        // The gzipped file is decompressed to the plain signed payload, which still verifies.
+        let (input, is_gzipped) = read_signed_payload(gzipped.to_str().unwrap());
+        assert!(is_gzipped);
+        assert_eq!(input.as_bytes(), SIGNED_PAYLOAD);
+        assert!(PayloadVerifier::new(input.as_bytes(), CFV)
+            .unwrap()
+            .verify()
+            .is_ok());
+
+        // Plain files are left as they are.
+        let (input, is_gzipped) = read_signed_payload(plain.to_str().unwrap());
+        assert!(!is_gzipped);
+        assert_eq!(input.as_bytes(), SIGNED_PAYLOAD);
+
+        fs::remove_file(&plain).unwrap();
+        fs::remove_file(&gzipped).unwrap();
+    }
+
+    #[test]
+    fn test_gzip_decode_limits() {
+        let encoded = gzip_encode(&[0u8; 0x1000]).unwrap();
+        assert_eq!(gzip_decode(&encoded, 0x1000).unwrap(), vec![0u8; 0x1000]);
+        assert!(gzip_decode(&encoded, 0xfff).is_err());
+
+        // Truncated or corrupted gzip data is rejected.
+        assert!(gzip_decode(&encoded[..encoded.len() - 4], 0x1000).is_err());
+        assert!(gzip_decode(&GZIP_MAGIC, 0x1000).is_err());
+    }
+}
@@ -0,0 +1,1031 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Aggregate information about a td-shim image into one machine-readable document.
+//!
+//! The document combines the TDX metadata sections, the verify header of the payload, the MRTD
+//! prediction and the files enrolled into the CFV. Please refer to the README of
+//! `td-shim-inspect` for the JSON schema.
+//!
+//! `SectionDigests` is a separate document with the SHA256 of every metadata section stored in the
+//! image, used to track component level changes in a software bill of materials.
+//!
+//! `extract_region()` gets the raw bytes of a named region of the image, for debugging.
+//!
+//! `list_acpi_tables()` enumerates and validates the ACPI tables embedded into the image.
+//!
+//! `image_digest()` is a canonical SHA384 of the whole image, which excludes the volatile bytes
+//! of the firmware volumes.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::mem::size_of;
+use std::ops::Range;
+
+use log::{debug, error};
+use r_efi::efi::Guid;
+use ring::digest;
+use schemars::JsonSchema;
+use scroll::{Pread, LE};
+use serde::Serialize;
+use td_layout::build_time::{
+    TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_PAYLOAD_OFFSET,
+    TD_SHIM_PAYLOAD_SIZE,
+};
+use td_shim::acpi::{AcpiTableError, AcpiTableReader};
+use td_shim::metadata::{
+    TdxMetadataDescriptor, TdxMetadataSection, TDX_METADATA_GUID_LEN, TDX_METADATA_OFFSET,
+    TDX_METADATA_SECTION_TYPE_BFV, TDX_METADATA_SECTION_TYPE_CFV,
+};
+use td_shim::secure_boot::{
+    signing_algorithm_name, CfvPubKeyFileHeader, HashAlg, PayloadSignHeader,
+    CFV_FFS_HEADER_REVOCATION_LIST_GUID, CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
+    CFV_FILE_HEADER_PUBKEY_GUID, CFV_FILE_HEADER_REVOKED_PUBKEY_GUID,
+    SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+use td_uefi_pi::fv::{get_file_from_fv, get_image_from_fv, CfvReader};
+use td_uefi_pi::pi;
+
+use crate::loader::{TdShimLoader, PARSE_TRACE_TARGET};
+use crate::reserved::{check_signed_payload, ReservedField};
+use crate::tee_info_hash::{find_misaligned_measured_sections, TdInfoStruct};
+use crate::validator::{check_payload_entry, payload_image};
+
+/// Version of the JSON schema generated by `ImageInfo`.
+///
+/// It's bumped whenever an existing key is removed or its meaning changes.
+pub const INSPECT_SCHEMA_VERSION: u32 = 1;
+
+/// Information about a td-shim image.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct ImageInfo {
+    pub schema_version: u32,
+    pub image_size: u64,
+    pub metadata: MetadataInfo,
+    /// Predicted MRTD in hex.
+    pub mrtd: String,
+    /// Canonical SHA384 of the image in hex, see `image_digest()`.
+    pub image_digest: String,
+    pub payload: Option<PayloadInfo>,
+    pub cfv: Option<CfvInfo>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct MetadataInfo {
+    pub version: u32,
+    pub sections: Vec<SectionInfo>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct SectionInfo {
+    pub r#type: String,
+    pub data_offset: u32,
+    pub raw_data_size: u32,
+    pub memory_address: u64,
+    pub memory_data_size: u64,
+    pub attributes: u32,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct PayloadInfo {
+    pub size: usize,
+    /// SHA384 of the payload image in hex, the verify header is included if signed.
+    pub sha384: String,
+    pub verify_header: Option<VerifyHeaderInfo>,
+    /// Entry point relative to the payload load base, `None` if it can't be determined.
+    pub entry_point: Option<u64>,
+    /// Why the entry point can't be used by td-shim.
+    pub entry_point_problem: Option<String>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct VerifyHeaderInfo {
+    pub struct_version: u32,
+    pub length: u32,
+    pub payload_version: u64,
+    pub payload_svn: u64,
+    pub signing_algorithm: String,
+    pub endian: String,
+    pub policy_offset: u32,
+    pub policy_size: u32,
+    /// Load base requested by the signer, `None` if the loader chooses it.
+    pub load_base: Option<u64>,
+    /// RTMR requested by the signer for the payload measurement, `None` for the default RTMR.
+    pub rtmr_index: Option<u32>,
+    /// Reserved fields of the verify header and the SVN policy which are not zero.
+    pub reserved_fields: Vec<ReservedField>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct CfvInfo {
+    pub files: Vec<CfvFileInfo>,
+    pub trust_anchors: Vec<TrustAnchorInfo>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct CfvFileInfo {
+    pub guid: String,
+    pub r#type: u8,
+    pub size: usize,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct TrustAnchorInfo {
+    pub hash_algorithm: String,
+    /// Fingerprint of the enrolled public key in hex.
+    pub hash: String,
+}
+
+/// SHA256 digests of the metadata sections stored in a td-shim image.
+#[derive(Debug, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct SectionDigests {
+    pub schema_version: u32,
+    pub hash_algorithm: String,
+    /// Digests in hex keyed by section type, in the order stored in the image if several sections
+    /// have the same type.
+    pub sections: BTreeMap<String, Vec<String>>,
+}
+
+impl ImageInfo {
+    /// Collect information about the td-shim image `filename`.
+    pub fn new(filename: &str) -> io::Result<Self> {
+        let image = fs::read(filename).map_err(|e| {
+            error!("Can not read td-shim image {}: {}", filename, e);
+            e
+        })?;
+        let (descriptor, sections) =
+            TdShimLoader::parse(&filename.to_string()).ok_or_else(|| {
+                error!("Failed to parse TdxMetadata from {}", filename);
+                io::Error::new(io::ErrorKind::InvalidData, "invalid TdxMetadata")
+            })?;
+
+        let misaligned = find_misaligned_measured_sections(&sections);
+        if !misaligned.is_empty() {
+            for section in misaligned.iter() {
+                error!("{}", section);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "misaligned measured section",
+            ));
+        }
+
+        let mut tee_info = TdInfoStruct::default();
+        let mut file = File::open(filename)?;
+        tee_info.build_mrtd(&mut file, image.len() as u64);
+
+        // The payload and CFV are located by td-layout, as td-shim does at runtime.
+        let (payload, cfv) = if image.len() == TD_SHIM_FIRMWARE_SIZE as usize {
+            debug!(
+                target: PARSE_TRACE_TARGET,
+                "Payload FV at 0x{:x}, CFV at 0x{:x}",
+                TD_SHIM_PAYLOAD_OFFSET,
+                TD_SHIM_CONFIG_OFFSET
+            );
+            let payload_fv = &image[TD_SHIM_PAYLOAD_OFFSET as usize
+                ..(TD_SHIM_PAYLOAD_OFFSET + TD_SHIM_PAYLOAD_SIZE) as usize];
+            let cfv = &image[TD_SHIM_CONFIG_OFFSET as usize
+                ..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize];
+            (PayloadInfo::from_fv(payload_fv), CfvInfo::new(cfv))
+        } else {
+            debug!(
+                target: PARSE_TRACE_TARGET,
+                "Image size isn't 0x{:x}, skip payload and CFV", TD_SHIM_FIRMWARE_SIZE
+            );
+            (None, None)
+        };
+
+        Ok(ImageInfo {
+            schema_version: INSPECT_SCHEMA_VERSION,
+            image_size: image.len() as u64,
+            metadata: MetadataInfo::new(&descriptor, &sections),
+            mrtd: hex::encode(tee_info.mrtd),
+            image_digest: hex::encode(image_digest(&image)),
+            payload,
+            cfv,
+        })
+    }
+
+    /// Render the information as a pretty printed JSON document.
+    pub fn to_json(&self) -> String {
+        // Serializing plain structs never fails.
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Get the reserved fields of the signed payload which are not zero.
+    pub fn reserved_fields(&self) -> &[ReservedField] {
+        self.payload
+            .as_ref()
+            .and_then(|p| p.verify_header.as_ref())
+            .map(|h| h.reserved_fields.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+impl SectionDigests {
+    /// Compute the digests of the sections of the td-shim image `filename`.
+    pub fn from_file(filename: &str) -> io::Result<Self> {
+        let image = fs::read(filename).map_err(|e| {
+            error!("Can not read td-shim image {}: {}", filename, e);
+            e
+        })?;
+        let (_, sections) = TdShimLoader::parse(&filename.to_string()).ok_or_else(|| {
+            error!("Failed to parse TdxMetadata from {}", filename);
+            io::Error::new(io::ErrorKind::InvalidData, "invalid TdxMetadata")
+        })?;
+
+        Self::new(&image, &sections)
+    }
+
+    /// Compute the digests of `sections` in `image`.
+    ///
+    /// Sections without data in the image, such as `TempMem` and `TD_HOB`, are skipped.
+    pub fn new(image: &[u8], sections: &[TdxMetadataSection]) -> io::Result<Self> {
+        let mut digests = BTreeMap::new();
+
+        for (i, section) in sections.iter().enumerate() {
+            if section.raw_data_size == 0 {
+                continue;
+            }
+            let start = section.data_offset as usize;
+            let data = start
+                .checked_add(section.raw_data_size as usize)
+                .and_then(|end| image.get(start..end))
+                .ok_or_else(|| {
+                    error!(
+                        "Section {} - data offset 0x{:x} and raw data size 0x{:x} exceed the image",
+                        i, section.data_offset, section.raw_data_size
+                    );
+                    io::Error::new(io::ErrorKind::InvalidData, "section exceeds the image")
+                })?;
+
+            let name = TdxMetadataSection::get_type_name(section.r#type)
+                .unwrap_or_else(|| format!("Unknown({})", section.r#type));
+            digests
+                .entry(name)
+                .or_insert_with(Vec::new)
+                .push(hex::encode(digest::digest(&digest::SHA256, data)));
+        }
+
+        Ok(SectionDigests {
+            schema_version: INSPECT_SCHEMA_VERSION,
+            hash_algorithm: "SHA256".to_string(),
+            sections: digests,
+        })
+    }
+
+    /// Render the digests as a pretty printed JSON document.
+    pub fn to_json(&self) -> String {
+        // Serializing plain structs never fails.
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl MetadataInfo {
+    pub fn new(descriptor: &TdxMetadataDescriptor, sections: &[TdxMetadataSection]) -> Self {
+        let sections = sections
+            .iter()
+            .map(|s| SectionInfo {
+                r#type: TdxMetadataSection::get_type_name(s.r#type)
+                    .unwrap_or_else(|| format!("Unknown({})", s.r#type)),
+                data_offset: s.data_offset,
+                raw_data_size: s.raw_data_size,
+                memory_address: s.memory_address,
+                memory_data_size: s.memory_data_size,
+                attributes: s.attributes,
+            })
+            .collect();
+
+        MetadataInfo {
+            version: descriptor.version,
+            sections,
+        }
+    }
+}
+
+impl PayloadInfo {
+    /// Get information about the payload stored in the payload firmware volume.
+    pub fn from_fv(fv: &[u8]) -> Option<Self> {
+        let payload = get_image_from_fv(fv, pi::fv::FV_FILETYPE_DXE_CORE, pi::fv::SECTION_PE32);
+        if payload.is_none() {
+            debug!(target: PARSE_TRACE_TARGET, "No payload in payload FV");
+        }
+        Some(Self::new(payload?))
+    }
+
+    pub fn new(payload: &[u8]) -> Self {
+        let verify_header = PayloadSignHeader::from_bytes(payload)
+            .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+            .map(|h| VerifyHeaderInfo::new(&h, check_signed_payload(payload)));
+        match verify_header.as_ref() {
+            Some(h) => debug!(
+                target: PARSE_TRACE_TARGET,
+                "Verify header GUID matched: signed length 0x{:x}", h.length
+            ),
+            None => debug!(
+                target: PARSE_TRACE_TARGET,
+                "No verify header, payload is not signed"
+            ),
+        }
+        let (entry_point, entry_point_problem) =
+            match payload_image(payload).map(check_payload_entry) {
+                Some(Ok(entry)) => (Some(entry.entry), None),
+                Some(Err(e)) => (None, Some(e.to_string())),
+                None => (None, Some("malformed verify header".to_string())),
+            };
+
+        PayloadInfo {
+            size: payload.len(),
+            sha384: hex::encode(digest::digest(&digest::SHA384, payload)),
+            verify_header,
+            entry_point,
+            entry_point_problem,
+        }
+    }
+}
+
+impl VerifyHeaderInfo {
+    pub fn new(header: &PayloadSignHeader, reserved_fields: Vec<ReservedField>) -> Self {
+        VerifyHeaderInfo {
+            struct_version: header.struct_version,
+            length: header.length,
+            payload_version: header.payload_version,
+            payload_svn: header.payload_svn,
+            signing_algorithm: signing_algorithm_name(header.signing_algorithm)
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| format!("Unknown({})", header.signing_algorithm)),
+            endian: match header.endian() {
+                scroll::Endian::Big => "big".to_string(),
+                scroll::Endian::Little => "little".to_string(),
+            },
+            policy_offset: header.policy_offset,
+            policy_size: header.policy_size,
+            load_base: header.load_base(),
+            rtmr_index: header.rtmr_index(),
+            reserved_fields,
+        }
+    }
+}
+
+impl CfvInfo {
+    /// Get information about files enrolled into the CFV, `None` if it's not a valid CFV.
+    pub fn new(cfv: &[u8]) -> Option<Self> {
+        let reader = match CfvReader::new(cfv) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!(target: PARSE_TRACE_TARGET, "Invalid CFV header: {:?}", e);
+                return None;
+            }
+        };
+        let mut files = Vec::new();
+        let mut trust_anchors = Vec::new();
+
+        for entry in reader.iter() {
+            let entry = match entry {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Malformed entry in CFV: {:?}", e);
+                    break;
+                }
+            };
+
+            debug!(
+                target: PARSE_TRACE_TARGET,
+                "CFV file {}: type 0x{:x}, size 0x{:x}",
+                format_guid(&entry.guid),
+                entry.kind,
+                entry.data.len()
+            );
+            files.push(CfvFileInfo {
+                guid: format_guid(&entry.guid),
+                r#type: entry.kind,
+                size: entry.data.len(),
+            });
+            if entry.kind == pi::fv::FV_FILETYPE_RAW
+                && entry.guid == CFV_FFS_HEADER_TRUST_ANCHOR_GUID
+            {
+                if let Some(anchor) = TrustAnchorInfo::new(entry.data) {
+                    trust_anchors.push(anchor);
+                }
+            }
+        }
+
+        Some(CfvInfo {
+            files,
+            trust_anchors,
+        })
+    }
+}
+
+impl TrustAnchorInfo {
+    fn new(data: &[u8]) -> Option<Self> {
+        let header: CfvPubKeyFileHeader = data.pread_with(0, LE).ok()?;
+        let offset = size_of::<CfvPubKeyFileHeader>();
+        if &header.type_guid != CFV_FILE_HEADER_PUBKEY_GUID.as_bytes()
+            || (header.length as usize) < offset
+            || header.length as usize > data.len()
+        {
+            return None;
+        }
+
+        let hash_algorithm = match HashAlg::from_id(header.hash_algorithm) {
+            Some(alg) => alg.name().to_string(),
+            None => format!("Unknown({})", header.hash_algorithm),
+        };
+
+        Some(TrustAnchorInfo {
+            hash_algorithm,
+            hash: hex::encode(&data[offset..header.length as usize]),
+        })
+    }
+}
+
+/// Size of the digest computed by `image_digest()`.
+pub const IMAGE_DIGEST_SIZE: usize = 48;
+
+// Offset of `_reserved` and `_pad` in `CfvPubKeyFileHeader`.
+const PUBKEY_FILE_RESERVED: Range<usize> = 32..40;
+
+// Normalize the volatile bytes of the firmware volume `fv`, see `image_digest()`. A firmware
+// volume which isn't valid or has a malformed file is left as it is.
+fn canonicalize_fv(fv: &mut [u8], is_cfv: bool) {
+    const HEADER_SIZE: usize = size_of::<pi::fv::FfsFileHeader>();
+
+    let reader = match CfvReader::new(fv) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    // Safe to unwrap() because the header has been checked by `CfvReader`.
+    let mut offset = fv
+        .pread_with::<pi::fv::FirmwareVolumeHeader>(0, LE)
+        .unwrap()
+        .header_length as usize;
+
+    let mut reserved = Vec::new();
+    for entry in reader.iter() {
+        let entry = match entry {
+            Ok(v) => v,
+            // The free space can't be located.
+            Err(_) => return,
+        };
+        let is_pubkey_file = is_cfv
+            && entry.kind == pi::fv::FV_FILETYPE_RAW
+            && (entry.guid == CFV_FFS_HEADER_TRUST_ANCHOR_GUID
+                || entry.guid == CFV_FFS_HEADER_REVOCATION_LIST_GUID);
+        if is_pubkey_file && entry.data.len() >= size_of::<CfvPubKeyFileHeader>() {
+            let type_guid = &entry.data[..16];
+            if type_guid == CFV_FILE_HEADER_PUBKEY_GUID.as_bytes()
+                || type_guid == CFV_FILE_HEADER_REVOKED_PUBKEY_GUID.as_bytes()
+            {
+                reserved.push(offset + HEADER_SIZE);
+            }
+        }
+        offset += (HEADER_SIZE + entry.data.len() + 7) & !7;
+    }
+
+    for data in reserved {
+        fv[data + PUBKEY_FILE_RESERVED.start..data + PUBKEY_FILE_RESERVED.end].fill(0);
+    }
+    if offset < fv.len() {
+        fv[offset..].fill(0);
+    }
+}
+
+/// Compute the canonical SHA384 of the td-shim image `image`, so the same logical build always
+/// produces the same digest.
+///
+/// The digest covers the whole image, after zeroing the following bytes of the payload firmware
+/// volume and the CFV located by td-layout:
+/// - The free space following the last file, which is either erased (all 0xFF) or zero filled.
+/// - The reserved `_reserved` and `_pad` fields of the `CfvPubKeyFileHeader` of the trust anchor
+///   and revocation list files, which td-shim ignores.
+///
+/// Nothing is excluded from an image whose size isn't the td-shim firmware size, or from a
+/// firmware volume which isn't valid or has a malformed file.
+pub fn image_digest(image: &[u8]) -> [u8; IMAGE_DIGEST_SIZE] {
+    let mut image = image.to_vec();
+    if image.len() == TD_SHIM_FIRMWARE_SIZE as usize {
+        let payload = TD_SHIM_PAYLOAD_OFFSET as usize
+            ..(TD_SHIM_PAYLOAD_OFFSET + TD_SHIM_PAYLOAD_SIZE) as usize;
+        canonicalize_fv(&mut image[payload], false);
+        let cfv =
+            TD_SHIM_CONFIG_OFFSET as usize..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize;
+        canonicalize_fv(&mut image[cfv], true);
+    }
+
+    let mut result = [0u8; IMAGE_DIGEST_SIZE];
+    result.copy_from_slice(digest::digest(&digest::SHA384, &image).as_ref());
+    result
+}
+
+/// Names of the regions accepted by `extract_region()`.
+pub const REGION_NAMES: &[&str] = &["bfv", "cfv", "payload", "metadata"];
+
+fn unknown_region(name: &str) -> io::Error {
+    error!(
+        "Unknown region {}, valid regions: {}",
+        name,
+        REGION_NAMES.join(", ")
+    );
+    io::Error::new(io::ErrorKind::InvalidInput, "unknown region")
+}
+
+fn region_error(name: &str, reason: &str) -> io::Error {
+    error!("Can not locate region {}: {}", name, reason);
+    io::Error::new(io::ErrorKind::InvalidData, "can not locate region")
+}
+
+/// Locate the region `name` of `image`, one of `REGION_NAMES`.
+///
+/// The BFV and CFV are located by their metadata sections, the first one if there are several. The
+/// metadata region holds the TdxMetadata GUID, the descriptor and the section table. The payload is
+/// located by td-layout, as td-shim does at runtime.
+pub fn locate_region(
+    image: &[u8],
+    descriptor: &TdxMetadataDescriptor,
+    sections: &[TdxMetadataSection],
+    name: &str,
+) -> io::Result<Range<usize>> {
+    let section_range = |r#type: u32| {
+        let section = sections
+            .iter()
+            .find(|s| s.r#type == r#type)
+            .ok_or_else(|| region_error(name, "no metadata section"))?;
+        let start = section.data_offset as usize;
+        let end = start
+            .checked_add(section.raw_data_size as usize)
+            .ok_or_else(|| region_error(name, "region exceeds the image"))?;
+        Ok(start..end)
+    };
+
+    let range = match name {
+        "bfv" => section_range(TDX_METADATA_SECTION_TYPE_BFV)?,
+        "cfv" => section_range(TDX_METADATA_SECTION_TYPE_CFV)?,
+        "payload" => {
+            if image.len() != TD_SHIM_FIRMWARE_SIZE as usize {
+                return Err(region_error(name, "image size isn't the firmware size"));
+            }
+            TD_SHIM_PAYLOAD_OFFSET as usize
+                ..(TD_SHIM_PAYLOAD_OFFSET + TD_SHIM_PAYLOAD_SIZE) as usize
+        }
+        "metadata" => {
+            let ptr = image
+                .len()
+                .checked_sub(TDX_METADATA_OFFSET as usize)
+                .and_then(|off| image.pread_with::<u32>(off, LE).ok())
+                .ok_or_else(|| region_error(name, "truncated image"))?;
+            let start = (ptr as usize)
+                .checked_sub(TDX_METADATA_GUID_LEN as usize)
+                .ok_or_else(|| region_error(name, "invalid TdxMetadata offset"))?;
+            start..ptr as usize + descriptor.length as usize
+        }
+        _ => return Err(unknown_region(name)),
+    };
+    if range.start > range.end || range.end > image.len() {
+        return Err(region_error(name, "region exceeds the image"));
+    }
+
+    Ok(range)
+}
+
+/// Get the raw bytes of the region `name` of the td-shim image `filename`.
+pub fn extract_region(filename: &str, name: &str) -> io::Result<Vec<u8>> {
+    // Reject an unknown name before parsing the image.
+    if !REGION_NAMES.contains(&name) {
+        return Err(unknown_region(name));
+    }
+    let image = fs::read(filename).map_err(|e| {
+        error!("Can not read td-shim image {}: {}", filename, e);
+        e
+    })?;
+    let (descriptor, sections) = TdShimLoader::parse(&filename.to_string()).ok_or_else(|| {
+        error!("Failed to parse TdxMetadata from {}", filename);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid TdxMetadata")
+    })?;
+
+    let range = locate_region(&image, &descriptor, &sections, name)?;
+    Ok(image[range].to_vec())
+}
+
+/// An ACPI table embedded into the image.
+#[derive(Debug, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct AcpiTableInfo {
+    pub signature: String,
+    /// Offset of the table relative to the start of the tables.
+    pub offset: usize,
+    pub length: usize,
+}
+
+fn acpi_table_error(e: AcpiTableError) -> io::Error {
+    let reason = match e {
+        AcpiTableError::Truncated { offset } => {
+            format!("ACPI table at offset 0x{:x} is truncated", offset)
+        }
+        AcpiTableError::InvalidLength { offset, length } => format!(
+            "ACPI table at offset 0x{:x} has invalid length 0x{:x}",
+            offset, length
+        ),
+        AcpiTableError::InvalidChecksum { offset, signature } => format!(
+            "ACPI table {} at offset 0x{:x} has invalid checksum",
+            String::from_utf8_lossy(&signature),
+            offset
+        ),
+    };
+    error!("{}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// List the ACPI tables stored back to back in `data`, after validating the length and checksum
+/// of each table.
+pub fn list_acpi_tables(data: &[u8]) -> io::Result<Vec<AcpiTableInfo>> {
+    AcpiTableReader::new(data)
+        .map(|table| {
+            table
+                .map(|v| AcpiTableInfo {
+                    signature: String::from_utf8_lossy(&v.signature).to_string(),
+                    offset: v.offset,
+                    length: v.data.len(),
+                })
+                .map_err(acpi_table_error)
+        })
+        .collect()
+}
+
+/// Get the content of the raw firmware file `guid` in the CFV of the td-shim image `filename`,
+/// such as a blob of ACPI tables enrolled by `td-shim-enroll -f`.
+pub fn extract_cfv_file(filename: &str, guid: &Guid) -> io::Result<Vec<u8>> {
+    let cfv = extract_region(filename, "cfv")?;
+    get_file_from_fv(&cfv, pi::fv::FV_FILETYPE_RAW, *guid)
+        .map(|v| v.to_vec())
+        .ok_or_else(|| {
+            error!(
+                "No raw firmware file {} in the CFV of {}",
+                format_guid(guid),
+                filename
+            );
+            io::Error::new(io::ErrorKind::NotFound, "firmware file not found in CFV")
+        })
+}
+
+/// Format a GUID in the registry format, such as `FCF2D558-9DF5-4F4D-B0D7-3E4B798AB066`.
+pub fn format_guid(guid: &Guid) -> String {
+    let b = guid.as_bytes();
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8],
+        b[9],
+        b[10],
+        b[11],
+        b[12],
+        b[13],
+        b[14],
+        b[15]
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::linker::{build_tdx_metadata, build_tdx_metadata_ptr, PayloadType};
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+    use td_layout::build_time::TD_SHIM_METADATA_OFFSET;
+    use td_shim::secure_boot::PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384;
+
+    #[test]
+    fn test_format_guid() {
+        assert_eq!(
+            format_guid(&SIGNED_PAYLOAD_FILE_HEADER_GUID),
+            "FCF2D558-9DF5-4F4D-B0D7-3E4B798AB066"
+        );
+    }
+
+    fn build_image() -> Vec<u8> {
+        let mut image = vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize];
+
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        image[TD_SHIM_CONFIG_OFFSET as usize..TD_SHIM_CONFIG_OFFSET as usize + cfv.len()]
+            .copy_from_slice(cfv);
+
+        let metadata = build_tdx_metadata(None, PayloadType::Executable).unwrap();
+        let metadata = metadata.to_vec();
+        let offset = TD_SHIM_METADATA_OFFSET as usize;
+        image[offset..offset + metadata.len()].copy_from_slice(&metadata);
+        let ptr = build_tdx_metadata_ptr();
+        let offset = image.len() - 0x20;
+        image[offset..offset + ptr.as_bytes().len()].copy_from_slice(ptr.as_bytes());
+
+        image
+    }
+
+    #[test]
+    fn test_inspect_json() {
+        let image = build_image();
+        let name = std::env::temp_dir().join(format!("td-shim-inspect-{}", std::process::id()));
+        fs::write(&name, &image).unwrap();
+        let info = ImageInfo::new(name.to_str().unwrap()).unwrap();
+        fs::remove_file(&name).unwrap();
+
+        assert_eq!(info.image_size, TD_SHIM_FIRMWARE_SIZE as u64);
+        assert!(!info.metadata.sections.is_empty());
+        assert_eq!(info.metadata.sections[0].r#type, "BFV");
+        assert_eq!(info.mrtd.len(), 96);
+        assert!(info.payload.is_none());
+        let cfv = info.cfv.as_ref().unwrap();
+        assert_eq!(cfv.trust_anchors.len(), 1);
+        assert_eq!(cfv.trust_anchors[0].hash_algorithm, "SHA384");
+        assert_eq!(cfv.trust_anchors[0].hash.len(), 96);
+
+        let json: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        for key in [
+            "schema_version",
+            "image_size",
+            "metadata",
+            "mrtd",
+            "image_digest",
+            "payload",
+            "cfv",
+        ] {
+            assert!(keys.contains(&&key.to_string()), "missing key {}", key);
+        }
+        assert_eq!(json["schema_version"], INSPECT_SCHEMA_VERSION);
+        assert!(json["metadata"]["sections"][0]["type"].is_string());
+        assert!(json["payload"].is_null());
+        assert!(json["cfv"]["trust_anchors"][0]["hash"].is_string());
+    }
+
+    #[test]
+    fn test_payload_info() {
+        let payload = [0x5au8; 32];
+        let info = PayloadInfo::new(&payload);
+        assert_eq!(info.size, 32);
+        assert!(info.verify_header.is_none());
+        assert_eq!(info.entry_point, None);
+        assert!(info.entry_point_problem.is_some());
+
+        let header = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: 1,
+            length: 0x40,
+            payload_version: 2,
+            payload_svn: 3,
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            ..Default::default()
+        };
+        let mut signed = header.to_bytes();
+        signed.extend_from_slice(&payload);
+        let info = PayloadInfo::new(&signed);
+        let header = info.verify_header.unwrap();
+        assert_eq!(header.payload_svn, 3);
+        assert_eq!(header.signing_algorithm, "ECDSA_NIST_P384_SHA384");
+        assert_eq!(header.endian, "little");
+    }
+
+    // Capture the parsing trace logged by the current thread, the tests run in parallel.
+    struct TraceLogger {
+        records: Mutex<Vec<(ThreadId, String)>>,
+    }
+
+    impl log::Log for TraceLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == PARSE_TRACE_TARGET
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let line = record.args().to_string();
+                let id = thread::current().id();
+                self.records.lock().unwrap().push((id, line));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static TRACE_LOGGER: TraceLogger = TraceLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    fn parse_trace(image: &[u8], name: &str) -> (bool, Vec<String>) {
+        let _ = log::set_logger(&TRACE_LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let name = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::write(&name, image).unwrap();
+        let parsed = ImageInfo::new(name.to_str().unwrap()).is_ok();
+        fs::remove_file(&name).unwrap();
+
+        let id = thread::current().id();
+        let trace = TRACE_LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(t, _)| *t == id)
+            .map(|(_, line)| line.clone())
+            .collect();
+        (parsed, trace)
+    }
+
+    #[test]
+    fn test_trace_parse() {
+        let image = build_image();
+        let (parsed, trace) = parse_trace(&image, "td-shim-trace");
+        assert!(parsed);
+        assert!(trace[0].starts_with("Image size"));
+        assert!(trace
+            .iter()
+            .any(|l| l.starts_with("Located TdxMetadata GUID")));
+        assert!(trace.iter().any(|l| l.starts_with("Section 0 at")));
+        assert!(trace.iter().any(|l| l == "TdxMetadata sections validated"));
+        assert!(trace.iter().any(|l| l.starts_with("CFV file")));
+
+        // The trace of a corrupted section count stops at the descriptor.
+        let mut bad = image.clone();
+        let offset = TD_SHIM_METADATA_OFFSET as usize + 16 + 12;
+        bad[offset..offset + 4].copy_from_slice(&0x10000u32.to_le_bytes());
+        let (parsed, trace) = parse_trace(&bad, "td-shim-trace-count");
+        assert!(!parsed);
+        assert!(trace
+            .last()
+            .unwrap()
+            .starts_with("TdxMetadata descriptor at"));
+        assert!(trace.last().unwrap().ends_with("65536 sections"));
+
+        // The trace of a corrupted GUID stops before locating it.
+        let mut bad = image;
+        bad[TD_SHIM_METADATA_OFFSET as usize] ^= 0xff;
+        let (parsed, trace) = parse_trace(&bad, "td-shim-trace-guid");
+        assert!(!parsed);
+        assert_eq!(trace.len(), 2);
+        assert!(trace[1].starts_with("TdxMetadata offset"));
+    }
+
+    #[test]
+    fn test_extract_region() {
+        let image = build_image();
+        let name = std::env::temp_dir().join(format!("td-shim-region-{}", std::process::id()));
+        fs::write(&name, &image).unwrap();
+        let filename = name.to_str().unwrap();
+        let cfv = extract_region(filename, "cfv").unwrap();
+        let metadata = extract_region(filename, "metadata").unwrap();
+        let payload = extract_region(filename, "payload").unwrap();
+        let unknown = extract_region(filename, "ipl");
+        fs::remove_file(&name).unwrap();
+
+        let known = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        assert_eq!(cfv.len(), TD_SHIM_CONFIG_SIZE as usize);
+        assert_eq!(&cfv[..known.len()], &known[..]);
+        assert_eq!(
+            &cfv[..],
+            &image[TD_SHIM_CONFIG_OFFSET as usize..][..cfv.len()]
+        );
+
+        let expected = build_tdx_metadata(None, PayloadType::Executable)
+            .unwrap()
+            .to_vec();
+        assert_eq!(metadata, expected);
+        assert_eq!(payload.len(), TD_SHIM_PAYLOAD_SIZE as usize);
+        assert_eq!(unknown.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn build_acpi_table(signature: &[u8; 4], length: usize) -> Vec<u8> {
+        let mut table = vec![0x5au8; length];
+        table[..4].copy_from_slice(signature);
+        table[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        table[9] = 0;
+        table[9] = td_shim::acpi::calculate_checksum(&table);
+        table
+    }
+
+    #[test]
+    fn test_list_acpi_tables() {
+        let mut tables = build_acpi_table(b"CCEL", 56);
+        tables.extend_from_slice(&build_acpi_table(b"APIC", 44));
+        tables.resize(0x100, 0);
+
+        let list = list_acpi_tables(&tables).unwrap();
+        assert_eq!(
+            list,
+            vec![
+                AcpiTableInfo {
+                    signature: "CCEL".to_string(),
+                    offset: 0,
+                    length: 56
+                },
+                AcpiTableInfo {
+                    signature: "APIC".to_string(),
+                    offset: 56,
+                    length: 44
+                },
+            ]
+        );
+
+        let mut corrupted = tables.clone();
+        corrupted[70] ^= 0x1;
+        let e = list_acpi_tables(&corrupted).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            e.to_string(),
+            "ACPI table APIC at offset 0x38 has invalid checksum"
+        );
+        let e = list_acpi_tables(&tables[..90]).unwrap_err();
+        assert_eq!(e.to_string(), "ACPI table at offset 0x38 is truncated");
+    }
+
+    #[test]
+    fn test_extract_cfv_file() {
+        let image = build_image();
+        let name = std::env::temp_dir().join(format!("td-shim-cfv-file-{}", std::process::id()));
+        fs::write(&name, &image).unwrap();
+        let filename = name.to_str().unwrap();
+        let anchor = extract_cfv_file(filename, &CFV_FFS_HEADER_TRUST_ANCHOR_GUID);
+        let unknown = extract_cfv_file(filename, &SIGNED_PAYLOAD_FILE_HEADER_GUID);
+        fs::remove_file(&name).unwrap();
+
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        let expected = get_file_from_fv(
+            cfv,
+            pi::fv::FV_FILETYPE_RAW,
+            CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
+        );
+        assert_eq!(anchor.unwrap(), expected.unwrap());
+        assert_eq!(unknown.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_section_digests() {
+        let image = build_image();
+        let name =
+            std::env::temp_dir().join(format!("td-shim-inspect-digests-{}", std::process::id()));
+        fs::write(&name, &image).unwrap();
+        let filename = name.to_str().unwrap();
+        let digests = SectionDigests::from_file(filename).unwrap();
+        // The digests are stable across runs.
+        assert_eq!(SectionDigests::from_file(filename).unwrap(), digests);
+        let (_, sections) = TdShimLoader::parse(&filename.to_string()).unwrap();
+        fs::remove_file(&name).unwrap();
+        assert_eq!(SectionDigests::new(&image, &sections).unwrap(), digests);
+
+        let cfv = sections
+            .iter()
+            .find(|s| s.r#type == TDX_METADATA_SECTION_TYPE_CFV)
+            .unwrap();
+        let start = cfv.data_offset as usize;
+        let expected = digest::digest(
+            &digest::SHA256,
+            &image[start..start + cfv.raw_data_size as usize],
+        );
+        assert_eq!(digests.sections["CFV"], vec![hex::encode(expected)]);
+        assert!(!digests.sections.contains_key("TD_HOB"));
+
+        let json: serde_json::Value = serde_json::from_str(&digests.to_json()).unwrap();
+        assert_eq!(json["hash_algorithm"], "SHA256");
+        assert_eq!(json["sections"]["CFV"][0], hex::encode(expected));
+
+        // A section which doesn't fit into the image is rejected.
+        assert!(SectionDigests::new(&image[..0x1000], &sections).is_err());
+    }
+
+    #[test]
+    fn test_image_digest() {
+        let image = build_image();
+        let expected = image_digest(&image);
+        assert_eq!(image_digest(&image), expected);
+
+        // Fill the reserved fields of the trust anchor and erase the free space of the CFV.
+        let cfv =
+            TD_SHIM_CONFIG_OFFSET as usize..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize;
+        let anchor = cfv.start
+            + image[cfv.clone()]
+                .windows(16)
+                .position(|v| v == CFV_FILE_HEADER_PUBKEY_GUID.as_bytes())
+                .unwrap();
+        let mut volatile = image.clone();
+        volatile[anchor + PUBKEY_FILE_RESERVED.start..anchor + PUBKEY_FILE_RESERVED.end].fill(0x5a);
+        volatile[cfv.end - 0x1000..cfv.end].fill(0xff);
+        assert_ne!(volatile, image);
+        assert!(CfvInfo::new(&volatile[cfv.clone()]).is_some());
+        assert_eq!(image_digest(&volatile), expected);
+
+        // Other changes are caught.
+        let mut changed = image.clone();
+        changed[anchor + size_of::<CfvPubKeyFileHeader>()] ^= 0x01;
+        assert_ne!(image_digest(&changed), expected);
+        let mut changed = image.clone();
+        changed[TD_SHIM_METADATA_OFFSET as usize] ^= 0x01;
+        assert_ne!(image_digest(&changed), expected);
+
+        // Images of other sizes are hashed as they are.
+        assert_eq!(
+            image_digest(&image[..0x1000]).as_ref(),
+            digest::digest(&digest::SHA384, &image[..0x1000]).as_ref()
+        );
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Check images against a manifest of expected SHA256 digests, for reproducibility audits.
+//!
+//! The manifest uses the format of `sha256sum`, one `<hex digest> <path>` entry per line. Empty
+//! lines and lines starting with `#` are ignored.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::{fs, io};
+
+use log::error;
+use td_shim::secure_boot::HashAlg;
+
+/// Maximum size of a manifest file.
+const MAX_MANIFEST_SIZE: usize = 1024 * 1024;
+
+/// Manifest of expected SHA256 digests, keyed by image path.
+#[derive(Debug, Default)]
+pub struct HashManifest {
+    entries: BTreeMap<String, String>,
+}
+
+/// Result of checking an image against the manifest.
+#[derive(Debug, Eq, PartialEq)]
+pub enum IntegrityStatus {
+    Match,
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+    /// The manifest has no entry for the image.
+    Missing {
+        actual: String,
+    },
+}
+
+impl fmt::Display for IntegrityStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityStatus::Match => write!(f, "OK"),
+            IntegrityStatus::Mismatch { expected, actual } => {
+                write!(f, "MISMATCH (expected {}, actual {})", expected, actual)
+            }
+            IntegrityStatus::Missing { actual } => {
+                write!(f, "MISSING (no manifest entry, actual {})", actual)
+            }
+        }
+    }
+}
+
+fn manifest_error(line: usize, reason: &str) -> io::Error {
+    error!("Invalid manifest line {}: {}", line, reason);
+    io::Error::new(io::ErrorKind::InvalidData, "invalid hash manifest")
+}
+
+impl HashManifest {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path).map_err(|e| {
+            error!("Can not read manifest {}: {}", path.display(), e);
+            e
+        })?;
+        if data.len() > MAX_MANIFEST_SIZE {
+            error!(
+                "Manifest {} is bigger than 0x{:x}",
+                path.display(),
+                MAX_MANIFEST_SIZE
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "manifest too big",
+            ));
+        }
+        let text = String::from_utf8(data).map_err(|_| manifest_error(0, "not UTF-8"))?;
+
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, path) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| manifest_error(index + 1, "expect '<digest> <path>'"))?;
+            // `sha256sum` marks files read in binary mode with '*'.
+            let path = path.trim_start();
+            let path = path.strip_prefix('*').unwrap_or(path);
+            let digest = digest.to_ascii_lowercase();
+            if path.is_empty()
+                || digest.len() != 2 * HashAlg::Sha256.output_len()
+                || hex::decode(&digest).is_err()
+            {
+                return Err(manifest_error(index + 1, "expect '<digest> <path>'"));
+            }
+            if entries.insert(path.to_string(), digest).is_some() {
+                return Err(manifest_error(index + 1, "duplicated path"));
+            }
+        }
+
+        Ok(HashManifest { entries })
+    }
+
+    /// Get the expected digest of `path`, falling back to the entry of its file name.
+    pub fn expected(&self, path: &Path) -> Option<&str> {
+        let by_path = path.to_str().and_then(|v| self.entries.get(v));
+        let by_name = || {
+            path.file_name()
+                .and_then(|v| v.to_str())
+                .and_then(|v| self.entries.get(v))
+        };
+        by_path.or_else(by_name).map(|v| v.as_str())
+    }
+
+    /// Check the image `data` read from `path`.
+    pub fn check(&self, path: &Path, data: &[u8]) -> IntegrityStatus {
+        let actual = hex::encode(HashAlg::Sha256.digest(data));
+        match self.expected(path) {
+            Some(expected) if expected == actual => IntegrityStatus::Match,
+            Some(expected) => IntegrityStatus::Mismatch {
+                expected: expected.to_string(),
+                actual,
+            },
+            None => IntegrityStatus::Missing { actual },
+        }
+    }
+
+    /// Check the image file `path`, or each regular file in the directory `path`, in the order of
+    /// file names.
+    pub fn check_path(&self, path: &Path) -> io::Result<Vec<(String, IntegrityStatus)>> {
+        let read_error = |path: &Path, e: io::Error| {
+            error!("Can not read {}: {}", path.display(), e);
+            e
+        };
+
+        let mut files = Vec::new();
+        if path.is_dir() {
+            for entry in fs::read_dir(path).map_err(|e| read_error(path, e))? {
+                let entry = entry.map_err(|e| read_error(path, e))?;
+                if entry.path().is_file() {
+                    files.push(entry.path());
+                }
+            }
+            files.sort();
+        } else {
+            files.push(path.to_path_buf());
+        }
+
+        let mut results = Vec::new();
+        for file in files {
+            let data = fs::read(&file).map_err(|e| read_error(&file, e))?;
+            results.push((file.display().to_string(), self.check(&file, &data)));
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IMAGE: &[u8] = b"td-shim image";
+
+    fn image_digest() -> String {
+        hex::encode(HashAlg::Sha256.digest(IMAGE))
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let text = format!(
+            "# images\n{}  final.bin\n\n{} *out/final.sb.bin\n",
+            image_digest().to_uppercase(),
+            image_digest()
+        );
+        let manifest = HashManifest::parse(&text).unwrap();
+        assert_eq!(
+            manifest.expected(Path::new("final.bin")),
+            Some(image_digest().as_str())
+        );
+        assert!(manifest.expected(Path::new("out/final.sb.bin")).is_some());
+        // Fall back to the file name.
+        assert!(manifest.expected(Path::new("/tmp/final.bin")).is_some());
+        assert!(manifest.expected(Path::new("final.sb.bin")).is_none());
+
+        assert!(HashManifest::parse("final.bin").is_err());
+        assert!(HashManifest::parse("1234 final.bin").is_err());
+        let duplicated = format!("{} a\n{} a\n", image_digest(), image_digest());
+        assert!(HashManifest::parse(&duplicated).is_err());
+    }
+
+    #[test]
+    fn test_check_image() {
+        let manifest = format!(
+            "{} match.bin\n{} mismatch.bin\n",
+            image_digest(),
+            "00".repeat(32)
+        );
+        let manifest = HashManifest::parse(&manifest).unwrap();
+
+        assert_eq!(
+            manifest.check(Path::new("match.bin"), IMAGE),
+            IntegrityStatus::Match
+        );
+        assert_eq!(
+            manifest.check(Path::new("mismatch.bin"), IMAGE),
+            IntegrityStatus::Mismatch {
+                expected: "00".repeat(32),
+                actual: image_digest(),
+            }
+        );
+        assert_eq!(
+            manifest.check(Path::new("missing.bin"), IMAGE),
+            IntegrityStatus::Missing {
+                actual: image_digest()
+            }
+        );
+
+        // Check a directory of images.
+        let dir = std::env::temp_dir().join(format!("td-shim-integrity-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["match.bin", "mismatch.bin", "missing.bin"] {
+            fs::write(dir.join(name), IMAGE).unwrap();
+        }
+        let results = manifest.check_path(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let status: Vec<&IntegrityStatus> = results.iter().map(|v| &v.1).collect();
+        assert_eq!(status.len(), 3);
+        assert_eq!(status[0], &IntegrityStatus::Match);
+        assert!(matches!(status[1], IntegrityStatus::Mismatch { .. }));
+        assert!(matches!(status[2], IntegrityStatus::Missing { .. }));
+    }
+}
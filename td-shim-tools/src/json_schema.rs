@@ -0,0 +1,186 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! JSON schemas of the JSON documents written or read by the td-shim tools, generated from the
+//! serde structs, so downstream parsers could be validated against them.
+
+use std::io;
+
+use log::error;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+
+use crate::inspect::{AcpiTableInfo, ImageInfo, SectionDigests};
+#[cfg(feature = "signer")]
+use crate::reference::ReferenceValues;
+use crate::tee_info_hash::Manifest;
+#[cfg(feature = "verifier")]
+use crate::verify_report::VerifyReport;
+
+/// Get the names of the JSON documents with a schema.
+pub fn schema_names() -> Vec<&'static str> {
+    let mut names = vec!["inspect", "digests", "acpi-tables", "manifest"];
+    #[cfg(feature = "verifier")]
+    names.push("verify");
+    #[cfg(feature = "signer")]
+    names.push("reference-values");
+    names
+}
+
+/// Generate the JSON schema of the document `name`:
+/// - `inspect`: `td-shim-inspect --json`
+/// - `digests`: `td-shim-inspect --digests`
+/// - `acpi-tables`: `td-shim-inspect acpi-tables --json`
+/// - `manifest`: the manifest read by `td-shim-tee-info-hash`
+/// - `verify`: `td-shim-verify --format json`
+/// - `reference-values`: `td-shim-sign-payload --emit-reference-values`
+pub fn json_schema(name: &str) -> Option<RootSchema> {
+    match name {
+        "inspect" => Some(schema_for!(ImageInfo)),
+        "digests" => Some(schema_for!(SectionDigests)),
+        "acpi-tables" => Some(schema_for!(Vec<AcpiTableInfo>)),
+        "manifest" => Some(schema_for!(Manifest)),
+        #[cfg(feature = "verifier")]
+        "verify" => Some(schema_for!(VerifyReport)),
+        #[cfg(feature = "signer")]
+        "reference-values" => Some(schema_for!(ReferenceValues)),
+        _ => None,
+    }
+}
+
+/// Generate the JSON schema of the document `name` as a JSON document.
+pub fn json_schema_string(name: &str) -> io::Result<String> {
+    let schema = json_schema(name).ok_or_else(|| {
+        error!(
+            "Unknown JSON document {}, expect one of: {}",
+            name,
+            schema_names().join(", ")
+        );
+        io::Error::new(io::ErrorKind::InvalidInput, "unknown JSON document")
+    })?;
+
+    // Serializing a schema never fails.
+    Ok(serde_json::to_string_pretty(&schema).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jsonschema::JSONSchema;
+    use serde_json::Value;
+
+    use crate::inspect::{
+        CfvFileInfo, CfvInfo, MetadataInfo, PayloadInfo, SectionInfo, TrustAnchorInfo,
+        VerifyHeaderInfo, INSPECT_SCHEMA_VERSION,
+    };
+    use crate::reserved::ReservedField;
+    #[cfg(feature = "verifier")]
+    use crate::verify_report::VerifyStatus;
+
+    // Compiling the schema also validates it against the JSON Schema meta-schema.
+    fn compile(name: &str) -> JSONSchema {
+        let schema: Value = serde_json::from_str(&json_schema_string(name).unwrap()).unwrap();
+        JSONSchema::compile(&schema).unwrap()
+    }
+
+    fn sample_image_info() -> ImageInfo {
+        ImageInfo {
+            schema_version: INSPECT_SCHEMA_VERSION,
+            image_size: 0x1000000,
+            metadata: MetadataInfo {
+                version: 1,
+                sections: vec![SectionInfo {
+                    r#type: "BFV".to_string(),
+                    data_offset: 0x1000,
+                    raw_data_size: 0x2000,
+                    memory_address: 0xff000000,
+                    memory_data_size: 0x2000,
+                    attributes: 1,
+                }],
+            },
+            mrtd: "00".repeat(48),
+            image_digest: "11".repeat(48),
+            payload: Some(PayloadInfo {
+                size: 0x100,
+                sha384: "22".repeat(48),
+                verify_header: Some(VerifyHeaderInfo {
+                    struct_version: 2,
+                    length: 0xf0,
+                    payload_version: 1,
+                    payload_svn: 2,
+                    signing_algorithm: "ECDSA_NIST_P384_SHA384".to_string(),
+                    endian: "little".to_string(),
+                    policy_offset: 0,
+                    policy_size: 0,
+                    load_base: Some(0x200000),
+                    rtmr_index: Some(2),
+                    reserved_fields: vec![ReservedField {
+                        field: "verify header reserved".to_string(),
+                        offset: 47,
+                        value: "5a".to_string(),
+                    }],
+                }),
+                entry_point: None,
+                entry_point_problem: Some("no entry point".to_string()),
+            }),
+            cfv: Some(CfvInfo {
+                files: vec![CfvFileInfo {
+                    guid: "77A2742E-9340-4AC9-8F85-B7B978580021".to_string(),
+                    r#type: 1,
+                    size: 0x80,
+                }],
+                trust_anchors: vec![TrustAnchorInfo {
+                    hash_algorithm: "SHA384".to_string(),
+                    hash: "33".repeat(48),
+                }],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_json_schema() {
+        for name in schema_names() {
+            let schema = compile(name);
+            assert!(!schema.is_valid(&Value::String("not a document".to_string())));
+        }
+        assert!(json_schema_string("unknown").is_err());
+
+        // Outputs validate against the schemas.
+        let schema = compile("inspect");
+        let mut info = serde_json::to_value(sample_image_info()).unwrap();
+        assert!(schema.is_valid(&info));
+        info["payload"] = Value::Null;
+        info["cfv"] = Value::Null;
+        assert!(schema.is_valid(&info));
+        info.as_object_mut().unwrap().remove("mrtd");
+        assert!(!schema.is_valid(&info));
+
+        let manifest: Value = serde_json::from_str(include_str!(
+            "bin/td-shim-tee-info-hash/sample_manifest.json"
+        ))
+        .unwrap();
+        assert!(compile("manifest").is_valid(&manifest));
+    }
+
+    #[cfg(feature = "verifier")]
+    #[test]
+    fn test_verify_report_schema() {
+        let schema = compile("verify");
+        let report = VerifyReport::from_error("invalid signed payload");
+        assert!(schema.is_valid(&serde_json::to_value(&report).unwrap()));
+        let report = VerifyReport {
+            verified: true,
+            status: VerifyStatus::Verified,
+            algorithm: Some("ECDSA_NIST_P384_SHA384".to_string()),
+            version: Some(1),
+            svn: Some(1),
+            key_fingerprint: Some("44".repeat(48)),
+            error: None,
+        };
+        assert!(schema.is_valid(&serde_json::to_value(&report).unwrap()));
+        let report = VerifyReport::unsigned();
+        assert!(schema.is_valid(&serde_json::to_value(&report).unwrap()));
+        assert!(!schema.is_valid(&serde_json::json!({ "verified": "yes" })));
+    }
+}
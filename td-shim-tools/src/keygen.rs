@@ -0,0 +1,138 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Generate an ephemeral signing key, for builds which sign the payload and enroll the public key
+//! into the same image in one shot.
+//!
+//! The private key only lives in memory. The PKCS#8 encoding is held in a `SecretDocument`, which
+//! is zeroized on drop, and is dropped as soon as the key is loaded for signing.
+
+use std::io;
+
+use log::error;
+use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey};
+
+use crate::signer::{check_algorithm_backend, SigningAlgorithm};
+
+/// Size of the RSA modulus in bits, as required by `RSAPSS_3072_SHA384`.
+const RSA_KEY_BITS: usize = 3072;
+
+/// A freshly generated signing key.
+pub struct GeneratedKey {
+    pub algorithm: SigningAlgorithm,
+    /// DER encoded SubjectPublicKeyInfo of the public key, as accepted by td-shim-enroll.
+    pub public_key_der: Vec<u8>,
+}
+
+fn keygen_error(e: &dyn std::fmt::Display) -> io::Error {
+    error!("Failed to generate signing key: {}", e);
+    io::Error::new(io::ErrorKind::Other, "failed to generate signing key")
+}
+
+/// Generate a key pair for the signing algorithm named `algorithm`.
+pub fn generate_signing_key(algorithm: &str) -> io::Result<GeneratedKey> {
+    let (pkcs8, public_key) = match algorithm {
+        "ECDSA_NIST_P384_SHA384" => {
+            let secret = p384::SecretKey::random(&mut rand_core::OsRng);
+            let pkcs8 = secret.to_pkcs8_der().map_err(|e| keygen_error(&e))?;
+            let public_key = secret
+                .public_key()
+                .to_public_key_der()
+                .map_err(|e| keygen_error(&e))?;
+            (pkcs8, public_key)
+        }
+        "RSAPSS_3072_SHA384" => {
+            let secret = rsa::RsaPrivateKey::new(&mut rand_core::OsRng, RSA_KEY_BITS)
+                .map_err(|e| keygen_error(&e))?;
+            let pkcs8 = secret.to_pkcs8_der().map_err(|e| keygen_error(&e))?;
+            let public_key = secret
+                .to_public_key()
+                .to_public_key_der()
+                .map_err(|e| keygen_error(&e))?;
+            (pkcs8, public_key)
+        }
+        _ => {
+            check_algorithm_backend(algorithm)?;
+            error!("Unsupported signing algorithm: {}", algorithm);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unsupported signing algorithm",
+            ));
+        }
+    };
+
+    let algorithm = SigningAlgorithm::from_pkcs8(algorithm, pkcs8.as_bytes())?;
+    // Zeroize the PKCS#8 encoding of the private key.
+    drop(pkcs8);
+
+    Ok(GeneratedKey {
+        algorithm,
+        public_key_der: public_key.as_bytes().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::enroller::{create_key_file, create_trust_anchor_file, enroll_files};
+    use crate::signer::PayloadSigner;
+    use std::fs;
+    use td_layout::build_time::{
+        TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE,
+    };
+    use td_shim::secure_boot::PayloadVerifier;
+
+    #[test]
+    fn test_generate_and_enroll() {
+        let key = generate_signing_key("ECDSA_NIST_P384_SHA384").unwrap();
+        let public_key = key.algorithm.public_key_bytes();
+        // Every call generates a new key.
+        let other = generate_signing_key("ECDSA_NIST_P384_SHA384").unwrap();
+        assert_ne!(other.algorithm.public_key_bytes(), public_key);
+
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let mut signer = PayloadSigner::new(payload, key.algorithm);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        let dir = std::env::temp_dir();
+        let image = dir.join(format!("td-shim-keygen-{}", std::process::id()));
+        let enrolled = dir.join(format!("td-shim-keygen-enrolled-{}", std::process::id()));
+        let exported = dir.join(format!("td-shim-keygen-public-{}", std::process::id()));
+        fs::write(&image, vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize]).unwrap();
+        fs::write(&exported, &key.public_key_der).unwrap();
+
+        let trust_anchor = create_trust_anchor_file(&public_key, "SHA384").unwrap();
+        // The exported public key enrolls the same trust anchor.
+        let from_der = create_key_file(exported.to_str().unwrap(), "SHA384").unwrap();
+        assert_eq!(from_der.as_bytes(), trust_anchor.as_bytes());
+        enroll_files(
+            image.to_str().unwrap(),
+            enrolled.clone(),
+            vec![trust_anchor],
+        )
+        .unwrap();
+
+        let enrolled_image = fs::read(&enrolled).unwrap();
+        for f in [&image, &enrolled, &exported] {
+            fs::remove_file(f).unwrap();
+        }
+        let cfv = &enrolled_image[TD_SHIM_CONFIG_OFFSET as usize
+            ..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize];
+        let verifier = PayloadVerifier::new(&signed, cfv).unwrap();
+        assert!(verifier.verify().is_ok());
+
+        // The payload signed by another key is rejected by the enrolled image.
+        let mut signer = PayloadSigner::new(payload, other.algorithm);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let verifier = PayloadVerifier::new(&signed, cfv).unwrap();
+        assert!(verifier.verify().is_err());
+    }
+
+    #[test]
+    fn test_generate_unsupported() {
+        assert!(generate_signing_key("ED25519").is_err());
+    }
+}
@@ -4,7 +4,7 @@
 
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
-use std::ops::RangeInclusive;
+use std::ops::{Range, RangeInclusive};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
@@ -20,16 +20,78 @@ pub mod public_key;
 pub mod linker;
 #[cfg(feature = "linker")]
 pub mod metadata;
+#[cfg(feature = "linker")]
+pub mod td_hob;
 
+#[cfg(feature = "signer")]
+pub mod audit;
+#[cfg(feature = "signer")]
+pub mod batch;
+#[cfg(feature = "signer")]
+pub mod cli_env;
+#[cfg(feature = "signer")]
+pub mod cms;
+#[cfg(feature = "signer")]
+pub mod components;
+#[cfg(feature = "signer")]
+pub mod keygen;
+#[cfg(feature = "signer")]
+pub mod profile;
+#[cfg(feature = "signer")]
+pub mod reference;
+#[cfg(feature = "signer")]
+pub mod sign_command;
+#[cfg(feature = "signer")]
+pub mod signature;
 #[cfg(feature = "signer")]
 pub mod signer;
+#[cfg(feature = "signer")]
+pub mod split;
+#[cfg(feature = "signer")]
+pub mod svn_counter;
+
+#[cfg(feature = "tsa")]
+pub mod tsa;
+
+#[cfg(any(feature = "signer", feature = "verifier"))]
+pub mod detached;
+#[cfg(any(feature = "signer", feature = "verifier"))]
+pub mod gzip;
+
+#[cfg(any(feature = "inspect", feature = "verifier"))]
+pub mod reserved;
 
 #[cfg(feature = "loader")]
 pub mod loader;
+#[cfg(feature = "loader")]
+pub mod snapshot;
+#[cfg(feature = "loader")]
+pub mod validator;
 
 #[cfg(feature = "tee")]
 pub mod tee_info_hash;
 
+#[cfg(feature = "inspect")]
+pub mod inspect;
+#[cfg(feature = "inspect")]
+pub mod json_schema;
+
+#[cfg(feature = "verifier")]
+pub mod stream_verify;
+#[cfg(feature = "verifier")]
+pub mod trust_store;
+#[cfg(feature = "verifier")]
+pub mod verify_report;
+
+#[cfg(feature = "fixup")]
+pub mod fixup;
+
+#[cfg(feature = "integrity")]
+pub mod integrity;
+
+#[cfg(feature = "diff")]
+pub mod diff;
+
 /// Struct to read input data from a file.
 pub struct InputData {
     data: Vec<u8>,
@@ -82,48 +144,169 @@ impl InputData {
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
+
+    /// Get the mutable input data.
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Keep `range` of the input data only.
+    pub fn keep_range(&mut self, range: Range<usize>) {
+        self.data.truncate(range.end);
+        self.data.drain(..range.start);
+    }
+}
+
+impl From<Vec<u8>> for InputData {
+    /// Wrap data built in memory, such as an assembled payload.
+    fn from(data: Vec<u8>) -> Self {
+        InputData { data }
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    // A file which doesn't exist yet can't be one of the inputs.
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Check that writing `output` doesn't overwrite one of the `inputs`, given as the file and its
+/// description.
+///
+/// Inputs read into an `InputData` before the output is created, such as the payload to be
+/// signed in place, don't need to be checked.
+pub fn check_output_path(output: &Path, inputs: &[(&Path, &str)]) -> io::Result<()> {
+    for (input, desc) in inputs {
+        if same_file(output, input) {
+            error!(
+                "Output file {} is the {} file {}",
+                output.display(),
+                desc,
+                input.display()
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("output file is the {} file", desc),
+            ));
+        }
+    }
+
+    Ok(())
 }
 
+/// Output name which writes to stdout instead of a file.
+pub const STDOUT_OUTPUT: &str = "-";
+
 /// Struct to write out built data.
+///
+/// Writes which would grow the output file beyond the maximum output size, default to the size
+/// of the shim firmware image, are rejected.
+///
+/// Regular files are written in place. Outputs which can't seek, like FIFOs or stdout given as
+/// `-`, are built in memory instead and written start to end by `flush()`.
 pub struct OutputFile {
-    file: File,
+    output: Output,
     name: PathBuf,
+    max_size: u64,
+}
+
+enum Output {
+    File(File),
+    // A non-seekable file, or stdout if `None`.
+    Sequential(Option<File>, SequentialBuffer),
+}
+
+// Data of a non-seekable output, of which `flushed` bytes have already been written out.
+#[derive(Default)]
+struct SequentialBuffer {
+    data: Vec<u8>,
+    pos: u64,
+    flushed: usize,
+}
+
+impl SequentialBuffer {
+    // Write `data` at `off` as a file would, the gap after the current end is filled with zero.
+    fn write_at(&mut self, off: u64, data: &[u8]) -> io::Result<()> {
+        // Safe to cast, the offset has been checked against the maximum output size.
+        let off = off as usize;
+        if off < self.flushed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can not rewrite data already written to a non-seekable output",
+            ));
+        }
+        let end = off + data.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[off..end].copy_from_slice(data);
+        self.pos = end as u64;
+
+        Ok(())
+    }
 }
 
 impl OutputFile {
     pub fn new<P: AsRef<Path>>(name: P) -> io::Result<Self> {
-        let file = File::create(name.as_ref()).map_err(|e| {
-            error!(
-                "Can not open output file {}: {}",
-                name.as_ref().display(),
+        let name = name.as_ref();
+        let output = if name == Path::new(STDOUT_OUTPUT) {
+            Output::Sequential(None, SequentialBuffer::default())
+        } else {
+            let file = File::create(name).map_err(|e| {
+                error!("Can not open output file {}: {}", name.display(), e);
                 e
-            );
-            e
-        })?;
+            })?;
+            match file.metadata() {
+                Ok(md) if md.is_file() => Output::File(file),
+                _ => Output::Sequential(Some(file), SequentialBuffer::default()),
+            }
+        };
 
         Ok(Self {
-            file,
-            name: name.as_ref().to_path_buf(),
+            output,
+            name: name.to_path_buf(),
+            max_size: TD_SHIM_FIRMWARE_SIZE as u64,
         })
     }
 
-    pub fn seek_and_write(&mut self, off: u64, data: &[u8], desc: &str) -> io::Result<()> {
-        self.file
-            .seek(SeekFrom::Start(off))
-            .and(self.file.write_all(data))
-            .map_err(|e| {
+    /// Set the maximum size of the output file.
+    pub fn set_max_size(&mut self, max_size: u64) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    fn check_size(&self, off: u64, len: usize, desc: &str) -> io::Result<()> {
+        let end = off.checked_add(len as u64);
+        match end {
+            Some(end) if end <= self.max_size => Ok(()),
+            _ => {
                 error!(
-                    "Can not write {} to file {}: {}",
+                    "Can not write {} to file {}: end offset 0x{:x} exceeds the maximum output size 0x{:x}",
                     desc,
                     self.name.display(),
-                    e
+                    end.unwrap_or(u64::MAX),
+                    self.max_size
                 );
-                e
-            })
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "end offset 0x{:x} exceeds the maximum output size 0x{:x}",
+                        end.unwrap_or(u64::MAX),
+                        self.max_size
+                    ),
+                ))
+            }
+        }
     }
 
-    pub fn write(&mut self, data: &[u8], desc: &str) -> io::Result<()> {
-        self.file.write_all(data).map_err(|e| {
+    fn write_at(&mut self, off: u64, data: &[u8], desc: &str) -> io::Result<()> {
+        match &mut self.output {
+            Output::File(file) => file.seek(SeekFrom::Start(off)).and(file.write_all(data)),
+            Output::Sequential(_, buffer) => buffer.write_at(off, data),
+        }
+        .map_err(|e| {
             error!(
                 "Can not write {} to file {}: {}",
                 desc,
@@ -134,19 +317,151 @@ impl OutputFile {
         })
     }
 
+    pub fn seek_and_write(&mut self, off: u64, data: &[u8], desc: &str) -> io::Result<()> {
+        self.check_size(off, data.len(), desc)?;
+        self.write_at(off, data, desc)
+    }
+
+    pub fn write(&mut self, data: &[u8], desc: &str) -> io::Result<()> {
+        let pos = match &mut self.output {
+            Output::File(file) => file.seek(SeekFrom::Current(0))?,
+            Output::Sequential(_, buffer) => buffer.pos,
+        };
+        self.check_size(pos, data.len(), desc)?;
+        self.write_at(pos, data, desc)
+    }
+
+    /// Flush the output. The data built for a non-seekable output is written out, it can't be
+    /// rewritten afterwards.
     pub fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()
+        let name = &self.name;
+        let (file, buffer) = match &mut self.output {
+            Output::File(file) => return file.flush(),
+            Output::Sequential(file, buffer) => (file, buffer),
+        };
+        let pending = &buffer.data[buffer.flushed..];
+        match file {
+            Some(file) => file.write_all(pending).and(file.flush()),
+            None => {
+                let mut stdout = io::stdout();
+                stdout.write_all(pending).and(stdout.flush())
+            }
+        }
+        .map_err(|e| {
+            error!("Can not write to file {}: {}", name.display(), e);
+            e
+        })?;
+        buffer.flushed = buffer.data.len();
+
+        Ok(())
     }
 
     pub fn current_pos(&self) -> io::Result<u64> {
         let name = &self.name;
-        Ok(self
-            .file
-            .metadata()
-            .map_err(|e| {
-                error!("Can not get size of file {:?}", name.as_os_str());
-                e
-            })?
-            .len())
+        match &self.output {
+            Output::File(file) => Ok(file
+                .metadata()
+                .map_err(|e| {
+                    error!("Can not get size of file {:?}", name.as_os_str());
+                    e
+                })?
+                .len()),
+            Output::Sequential(_, buffer) => Ok(buffer.data.len() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_output_file_max_size() {
+        let name = std::env::temp_dir().join(format!("td-shim-output-{}", std::process::id()));
+        let mut output = OutputFile::new(&name).unwrap();
+        output.set_max_size(0x100);
+
+        assert!(output.seek_and_write(0, &[0u8; 0x80], "data").is_ok());
+        assert!(output.write(&[0u8; 0x80], "data").is_ok());
+        assert_eq!(output.current_pos().unwrap(), 0x100);
+
+        let err = output.write(&[0u8], "data").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("0x101"));
+        assert!(err.to_string().contains("0x100"));
+        assert!(output.seek_and_write(0xff, &[0u8; 2], "data").is_err());
+        assert!(output.seek_and_write(u64::MAX, &[0u8; 2], "data").is_err());
+        output.flush().unwrap();
+        assert_eq!(fs::metadata(&name).unwrap().len(), 0x100);
+
+        fs::remove_file(&name).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_file_fifo() {
+        let name = std::env::temp_dir().join(format!("td-shim-fifo-{}", std::process::id()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&name)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Opening the FIFO for writing blocks until it's opened for reading.
+        let reader = {
+            let name = name.clone();
+            std::thread::spawn(move || fs::read(name).unwrap())
+        };
+        let mut output = OutputFile::new(&name).unwrap();
+
+        // Written out of order, as the linker does.
+        output.seek_and_write(0x80, &[2u8; 0x80], "second").unwrap();
+        output.seek_and_write(0, &[1u8; 0x40], "first").unwrap();
+        output.write(&[3u8; 0x20], "third").unwrap();
+        assert_eq!(output.current_pos().unwrap(), 0x100);
+        output.flush().unwrap();
+
+        // Data already written out can't be rewritten, but may be appended to.
+        assert_eq!(
+            output.seek_and_write(0, &[0u8], "data").unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        output.set_max_size(0x110);
+        output
+            .seek_and_write(0x100, &[4u8; 0x10], "fourth")
+            .unwrap();
+        output.flush().unwrap();
+        drop(output);
+
+        let mut expected = vec![1u8; 0x40];
+        expected.extend_from_slice(&[3u8; 0x20]);
+        expected.extend_from_slice(&[0u8; 0x20]);
+        expected.extend_from_slice(&[2u8; 0x80]);
+        expected.extend_from_slice(&[4u8; 0x10]);
+        assert_eq!(reader.join().unwrap(), expected);
+
+        fs::remove_file(&name).unwrap();
+    }
+
+    #[test]
+    fn test_check_output_path() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("td-shim-input-{}", std::process::id()));
+        let output = dir.join(format!("td-shim-output-path-{}", std::process::id()));
+        fs::write(&input, b"key").unwrap();
+
+        assert!(check_output_path(&output, &[(&input, "private key")]).is_ok());
+        fs::write(&output, b"output").unwrap();
+        assert!(check_output_path(&output, &[(&input, "private key")]).is_ok());
+
+        // The same file given by another path is caught.
+        let alias = dir.join(".").join(input.file_name().unwrap());
+        let err = check_output_path(&alias, &[(&output, "payload"), (&input, "private key")])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("private key"));
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
     }
 }
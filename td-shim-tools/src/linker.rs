@@ -6,13 +6,14 @@
 use std::mem::size_of;
 use std::{fs, io};
 
-use log::trace;
+use log::{error, trace};
 use r_efi::base::Guid;
 use scroll::Pwrite;
 use td_layout::build_time::{
     TD_SHIM_FIRMWARE_BASE, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_IPL_OFFSET, TD_SHIM_IPL_SIZE,
     TD_SHIM_MAILBOX_OFFSET, TD_SHIM_METADATA_OFFSET, TD_SHIM_PAYLOAD_BASE, TD_SHIM_PAYLOAD_OFFSET,
-    TD_SHIM_PAYLOAD_SIZE, TD_SHIM_RESET_VECTOR_SIZE, TD_SHIM_SEC_CORE_INFO_OFFSET,
+    TD_SHIM_PAYLOAD_SIZE, TD_SHIM_RESET_VECTOR_BASE, TD_SHIM_RESET_VECTOR_OFFSET,
+    TD_SHIM_RESET_VECTOR_SIZE, TD_SHIM_SEC_CORE_INFO_OFFSET,
 };
 use td_layout::mailbox::TdxMpWakeupMailbox;
 use td_loader::{elf, pe};
@@ -21,8 +22,13 @@ use td_shim::fv::{
     IplFvHeader,
 };
 use td_shim::metadata::{TdxMetadataGuid, TdxMetadataPtr};
-use td_shim::reset_vector::{ResetVectorHeader, ResetVectorParams};
+use td_shim::reset_vector::{
+    build_reset_vector_entry, reset_vector_entry_target, ResetVectorHeader, ResetVectorParams,
+    RESET_VECTOR_ADDRESS, RESET_VECTOR_ENTRY_SIZE,
+};
+use td_shim::secure_boot::PayloadVerifier;
 use td_shim::write_u24;
+use td_uefi_pi::fv::get_image_from_fv;
 use td_uefi_pi::pi::fv::{
     FfsFileHeader, FVH_REVISION, FVH_SIGNATURE, FV_FILETYPE_DXE_CORE, FV_FILETYPE_SECURITY_CORE,
     SECTION_PE32,
@@ -36,6 +42,12 @@ pub const MAX_IPL_CONTENT_SIZE: usize =
 pub const MAX_PAYLOAD_CONTENT_SIZE: usize =
     TD_SHIM_PAYLOAD_SIZE as usize - size_of::<FvHeaderByte>();
 pub const MAX_METADATA_CONFIG_SIZE: usize = 1024 * 1024;
+/// Size of firmware images passed to QEMU by `-bios` must be multiple of 64KB.
+pub const QEMU_BIOS_ALIGNMENT: usize = 0x10000;
+
+/// The processor starts execution at 16 bytes below 4GB.
+const RESET_VECTOR_ENTRY_TOP_OFFSET: usize = RESET_VECTOR_ENTRY_SIZE;
+const SIZE_4G: u64 = 0x1_0000_0000;
 
 pub const OVMF_TABLE_FOOTER_GUID: Guid = Guid::from_fields(
     0x96b582de,
@@ -312,6 +324,7 @@ pub struct TdShimLinker {
     payload_relocation: bool,
     output_file_name: Option<String>,
     payload_type: PayloadType,
+    reset_vector_entry: Option<u32>,
 }
 
 impl TdShimLinker {
@@ -333,6 +346,13 @@ impl TdShimLinker {
         self
     }
 
+    /// Replace the entry stub at the reset address with a jump to `entry_point`, instead of the
+    /// one from the reset vector binary.
+    pub fn set_reset_vector_entry(&mut self, entry_point: u32) -> &mut Self {
+        self.reset_vector_entry = Some(entry_point);
+        self
+    }
+
     /// Build the shim binary.
     pub fn build(
         &self,
@@ -440,10 +460,247 @@ impl TdShimLinker {
         output_file.write(ovmf_guid_table.as_slice(), "OVMF GUID table")?;
 
         let metadata_ptr = build_tdx_metadata_ptr();
-        output_file.write(metadata_ptr.as_bytes(), "metadata_ptr")?;
+        output_file.write(&metadata_ptr.to_bytes(), "metadata_ptr")?;
+
+        if let Some(entry_point) = self.reset_vector_entry {
+            let entry = build_reset_vector_entry(entry_point).ok_or_else(|| {
+                error!(
+                    "Reset vector entry point 0x{:x} is out of the shim firmware [0x{:x}, 0x{:x})",
+                    entry_point, TD_SHIM_FIRMWARE_BASE, RESET_VECTOR_ADDRESS
+                );
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid reset vector entry point",
+                )
+            })?;
+            let pos = (TD_SHIM_FIRMWARE_SIZE as usize - RESET_VECTOR_ENTRY_TOP_OFFSET) as u64;
+            output_file.seek_and_write(pos, &entry, "reset vector entry")?;
+        }
 
         output_file.flush()?;
 
         Ok(())
     }
 }
+
+/// Build a flat image from the assembled shim firmware, which could be passed to QEMU by `-bios`.
+///
+/// QEMU maps the image right below 4GB, so the firmware is placed at the end of the image and
+/// the image is padded with zero at the beginning up to `image_size`.
+pub fn build_flat_image(firmware: &[u8], image_size: usize) -> io::Result<Vec<u8>> {
+    if firmware.len() != TD_SHIM_FIRMWARE_SIZE as usize {
+        error!(
+            "Invalid shim firmware size 0x{:x}, expect 0x{:x}",
+            firmware.len(),
+            TD_SHIM_FIRMWARE_SIZE
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid shim firmware size",
+        ));
+    }
+    if image_size < firmware.len()
+        || image_size as u64 > TD_SHIM_FIRMWARE_BASE as u64
+        || image_size % QEMU_BIOS_ALIGNMENT != 0
+    {
+        error!(
+            "Invalid flat image size 0x{:x}, must be 64KB aligned and in range [0x{:x}, 0x{:x}]",
+            image_size, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_FIRMWARE_BASE
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid flat image size",
+        ));
+    }
+
+    let padding = image_size - firmware.len();
+    let mut image = vec![0u8; padding];
+    image.extend_from_slice(firmware);
+
+    // Make sure the reset vector region lands at where td-layout expects it to be.
+    let image_base = SIZE_4G - image_size as u64;
+    let reset_vector_offset = padding + TD_SHIM_RESET_VECTOR_OFFSET as usize;
+    if image_base + reset_vector_offset as u64 != TD_SHIM_RESET_VECTOR_BASE as u64 {
+        error!(
+            "Reset vector of the flat image is at 0x{:x}, expect 0x{:x}",
+            image_base + reset_vector_offset as u64,
+            TD_SHIM_RESET_VECTOR_BASE
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reset vector at unexpected address",
+        ));
+    }
+    let entry = &image[image_size - RESET_VECTOR_ENTRY_TOP_OFFSET..];
+    match reset_vector_entry_target(entry) {
+        Some(target) if target >= TD_SHIM_FIRMWARE_BASE && target < RESET_VECTOR_ADDRESS => {}
+        Some(target) => {
+            error!(
+                "Reset vector of the flat image jumps to 0x{:x}, out of the shim firmware",
+                target
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "reset vector jumps out of shim firmware",
+            ));
+        }
+        None => {
+            error!(
+                "No reset vector found at offset 0x{:x} of the flat image",
+                image_size - RESET_VECTOR_ENTRY_TOP_OFFSET
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no reset vector found in shim firmware",
+            ));
+        }
+    }
+
+    Ok(image)
+}
+
+/// Verify the signed payload embedded into the shim firmware `image`.
+///
+/// The payload is read back from the payload firmware volume the same way td-shim locates it at
+/// boot time, so it must start with the signed payload `payload` which has been embedded and its
+/// signature must be valid. This catches mistakes in the offset or size of the payload section.
+pub fn verify_embedded_payload(image: &[u8], payload: &[u8]) -> io::Result<()> {
+    if image.len() != TD_SHIM_FIRMWARE_SIZE as usize {
+        error!(
+            "Invalid shim firmware size 0x{:x}, expect 0x{:x}",
+            image.len(),
+            TD_SHIM_FIRMWARE_SIZE
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid shim firmware size",
+        ));
+    }
+
+    let payload_fv = &image[TD_SHIM_PAYLOAD_OFFSET as usize
+        ..TD_SHIM_PAYLOAD_OFFSET as usize + TD_SHIM_PAYLOAD_SIZE as usize];
+    let embedded =
+        get_image_from_fv(payload_fv, FV_FILETYPE_DXE_CORE, SECTION_PE32).ok_or_else(|| {
+            error!("No payload found in the payload firmware volume");
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no payload found in shim firmware",
+            )
+        })?;
+    if embedded.len() < payload.len() {
+        error!(
+            "Payload section of 0x{:x} bytes can't hold the signed payload of 0x{:x} bytes",
+            embedded.len(),
+            payload.len()
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "payload section size mismatch",
+        ));
+    }
+    if &embedded[..payload.len()] != payload {
+        error!("Embedded payload doesn't match the signed payload");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "embedded payload mismatch",
+        ));
+    }
+
+    PayloadVerifier::new(embedded, &[])
+        .and_then(|v| v.verify_signature())
+        .map_err(|e| {
+            error!("Failed to verify the embedded payload: {:?}", e);
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "embedded payload verification failed",
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_firmware() -> Vec<u8> {
+        let mut firmware = vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize];
+        let entry = build_reset_vector_entry(TD_SHIM_RESET_VECTOR_BASE).unwrap();
+        firmware[TD_SHIM_FIRMWARE_SIZE as usize - RESET_VECTOR_ENTRY_TOP_OFFSET..]
+            .copy_from_slice(&entry);
+        firmware
+    }
+
+    #[test]
+    fn test_build_flat_image() {
+        let firmware = build_firmware();
+
+        let image = build_flat_image(&firmware, TD_SHIM_FIRMWARE_SIZE as usize).unwrap();
+        assert_eq!(image, firmware);
+
+        let size = TD_SHIM_FIRMWARE_SIZE as usize * 2;
+        let image = build_flat_image(&firmware, size).unwrap();
+        assert_eq!(image.len(), size);
+        assert!(image[..size / 2].iter().all(|v| *v == 0));
+        assert_eq!(&image[size / 2..], &firmware);
+        assert_eq!(
+            &image[size - RESET_VECTOR_ENTRY_TOP_OFFSET..size - RESET_VECTOR_ENTRY_TOP_OFFSET + 3],
+            &[0x90, 0x90, 0xe9]
+        );
+    }
+
+    #[test]
+    fn test_build_flat_image_invalid() {
+        let firmware = build_firmware();
+        let size = TD_SHIM_FIRMWARE_SIZE as usize;
+
+        assert!(build_flat_image(&firmware[1..], size).is_err());
+        assert!(build_flat_image(&firmware, size - QEMU_BIOS_ALIGNMENT).is_err());
+        assert!(build_flat_image(&firmware, size + 0x1000).is_err());
+        assert!(build_flat_image(&firmware, TD_SHIM_FIRMWARE_BASE as usize + size).is_err());
+
+        let mut bad = firmware.clone();
+        bad[size - RESET_VECTOR_ENTRY_TOP_OFFSET + 2] = 0;
+        assert!(build_flat_image(&bad, size).is_err());
+
+        // The reset vector jumps below the shim firmware.
+        let mut bad = firmware.clone();
+        bad[size - RESET_VECTOR_ENTRY_TOP_OFFSET + 6] = 0;
+        assert!(build_flat_image(&bad, size).is_err());
+    }
+
+    #[test]
+    fn test_verify_embedded_payload() {
+        let payload =
+            include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let offset = TD_SHIM_PAYLOAD_OFFSET as usize;
+        let header = PayloadFvHeaderByte::build_tdx_payload_fv_header();
+        let mut image = vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize];
+        image[offset..offset + header.data.len()].copy_from_slice(&header.data);
+        let start = offset + header.data.len();
+        image[start..start + payload.len()].copy_from_slice(payload);
+        assert!(verify_embedded_payload(&image, payload).is_ok());
+
+        // The payload section is too small to hold the signed payload.
+        let mut small = image.clone();
+        let mut section_size = [0u8; 3];
+        write_u24(
+            (payload.len() / 2 + size_of::<FvFfsSectionHeader>()) as u32,
+            &mut section_size,
+        );
+        let pos = offset + size_of::<FvHeader>() + size_of::<FvFfsFileHeader>();
+        small[pos..pos + 3].copy_from_slice(&section_size);
+        assert!(verify_embedded_payload(&small, payload).is_err());
+
+        // The payload is embedded at a wrong offset.
+        let mut shifted = image.clone();
+        shifted[start..start + payload.len()].fill(0);
+        shifted[start + 8..start + 8 + payload.len()].copy_from_slice(payload);
+        assert!(verify_embedded_payload(&shifted, payload).is_err());
+
+        // The embedded payload has been modified, for example by relocation.
+        let mut modified = image.clone();
+        modified[start + 0x1000] ^= 0xff;
+        assert!(verify_embedded_payload(&modified, payload).is_err());
+
+        assert!(verify_embedded_payload(&image[1..], payload).is_err());
+    }
+}
@@ -4,16 +4,24 @@
 
 use log::debug;
 use log::error;
-use scroll::Pread;
+use scroll::{Pread, LE};
 use std::fs;
 use std::io;
 use std::io::Read;
 use std::io::Seek;
 use td_shim::metadata::{
-    self, TdxMetadataDescriptor, TdxMetadataGuid, TdxMetadataSection, TDX_METADATA_DESCRIPTOR_LEN,
-    TDX_METADATA_GUID_LEN, TDX_METADATA_OFFSET, TDX_METADATA_SECTION_LEN,
+    self, TdxMetadataDescriptor, TdxMetadataError, TdxMetadataGuid, TdxMetadataSection,
+    TDX_METADATA_DESCRIPTOR_LEN, TDX_METADATA_GUID_LEN, TDX_METADATA_OFFSET,
+    TDX_METADATA_SECTION_LEN,
 };
 
+/// The in-place TdxMetadata parser shared with td-shim, for images already read into memory.
+pub use td_shim::metadata::TdxMetadataRef;
+
+/// Log target of the parsing steps traced at debug level, enabled by `--trace-parse` of the
+/// inspection tools to find where parsing of a malformed image diverges.
+pub const PARSE_TRACE_TARGET: &str = "td_shim_tools::parse";
+
 pub struct TdShimLoader;
 
 fn read_from_file(file: &mut std::fs::File, pos: u64, buffer: &mut [u8]) -> io::Result<()> {
@@ -49,23 +57,37 @@ impl TdShimLoader {
 
         let file_metadata = file_metadata.unwrap();
         let file_size = file_metadata.len();
+        debug!(target: PARSE_TRACE_TARGET, "Image size 0x{:x}", file_size);
 
         // Then read 4 bytes at the pos of [file_len - 0x20]
         // This is the offset of TdxMetadata
+        let ptr_offset = match file_size.checked_sub(TDX_METADATA_OFFSET as u64) {
+            Some(v) => v,
+            None => {
+                error!(
+                    "The file is too small to hold TdxMetadata: 0x{:x}",
+                    file_size
+                );
+                return None;
+            }
+        };
         let mut buffer: [u8; 4] = [0; 4];
-        if read_from_file(
-            &mut file,
-            file_size - TDX_METADATA_OFFSET as u64,
-            &mut buffer,
-        )
-        .is_err()
-        {
+        if read_from_file(&mut file, ptr_offset, &mut buffer).is_err() {
             error!("Failed to read metadata offset");
             return None;
         }
 
         let mut metadata_offset = u32::from_le_bytes(buffer);
-        if metadata_offset > file_size as u32 - TDX_METADATA_OFFSET - TDX_METADATA_DESCRIPTOR_LEN {
+        debug!(
+            target: PARSE_TRACE_TARGET,
+            "TdxMetadata offset 0x{:x} read at 0x{:x}", metadata_offset, ptr_offset
+        );
+        // The GUID and the descriptor are placed before the TdxMetadata pointer.
+        if (metadata_offset as u64)
+            .checked_add(TDX_METADATA_DESCRIPTOR_LEN as u64)
+            .map_or(true, |end| end > ptr_offset)
+            || metadata_offset < TDX_METADATA_GUID_LEN
+        {
             error!("The metadata offset is invalid. {}", metadata_offset);
             error!("{:X?}", buffer);
             return None;
@@ -84,6 +106,10 @@ impl TdShimLoader {
             error!("{:X?}", &buffer);
             return None;
         }
+        debug!(
+            target: PARSE_TRACE_TARGET,
+            "Located TdxMetadata GUID at 0x{:x}", metadata_offset
+        );
 
         // Then the descriptor
         let mut buffer: [u8; TDX_METADATA_DESCRIPTOR_LEN as usize] =
@@ -94,20 +120,34 @@ impl TdShimLoader {
             return None;
         }
         let metadata_descriptor: TdxMetadataDescriptor =
-            buffer.pread::<TdxMetadataDescriptor>(0).unwrap();
-        if !metadata_descriptor.is_valid() {
-            error!("Invalid TdxMetadata Descriptor: {:?}", metadata_descriptor);
+            buffer.pread_with::<TdxMetadataDescriptor>(0, LE).unwrap();
+        debug!(
+            target: PARSE_TRACE_TARGET,
+            "TdxMetadata descriptor at 0x{:x}: version {}, {} sections",
+            metadata_offset,
+            metadata_descriptor.version,
+            metadata_descriptor.number_of_section_entry
+        );
+
+        // check if the section table declared by the descriptor fits into the file
+        let table_size =
+            file_size.saturating_sub(metadata_offset as u64 + TDX_METADATA_DESCRIPTOR_LEN as u64);
+        if let Err(e) = metadata::validate_section_count(&metadata_descriptor, table_size as usize)
+        {
+            match e {
+                TdxMetadataError::SectionCountMismatch {
+                    declared,
+                    available,
+                } => error!(
+                    "Corrupted TdxMetadata section table: {} sections declared but only {} available",
+                    declared, available
+                ),
+                _ => error!("Corrupted TdxMetadata section table: {:?}", e),
+            }
             return None;
         }
-
-        // check if the metadata length exceeds the file size
-        let metadata_len = metadata_descriptor.number_of_section_entry * TDX_METADATA_SECTION_LEN
-            + TDX_METADATA_GUID_LEN
-            + TDX_METADATA_DESCRIPTOR_LEN;
-        if metadata_offset + metadata_len + TDX_METADATA_GUID_LEN + TDX_METADATA_DESCRIPTOR_LEN
-            > file_size as u32
-        {
-            error!("Invalid TdxMetadata length {}", metadata_len);
+        if !metadata_descriptor.is_valid() {
+            error!("Invalid TdxMetadata Descriptor: {:?}", metadata_descriptor);
             return None;
         }
 
@@ -124,14 +164,29 @@ impl TdShimLoader {
                 return None;
             }
 
-            let section = buffer.pread::<TdxMetadataSection>(0).unwrap();
+            let section = buffer.pread_with::<TdxMetadataSection>(0, LE).unwrap();
+            debug!(
+                target: PARSE_TRACE_TARGET,
+                "Section {} at 0x{:x}: type {}, data_offset 0x{:x}, raw_data_size 0x{:x}",
+                i,
+                metadata_offset,
+                section.r#type,
+                section.data_offset,
+                section.raw_data_size
+            );
             metadata_sections.push(section);
 
             i += 1;
             if i == metadata_descriptor.number_of_section_entry {
                 break;
             }
-            metadata_offset += TDX_METADATA_SECTION_LEN;
+            metadata_offset = match metadata_offset.checked_add(TDX_METADATA_SECTION_LEN) {
+                Some(v) => v,
+                None => {
+                    error!("Section[{}] exceeds the maximum file offset", i);
+                    return None;
+                }
+            };
         }
 
         if i != metadata_descriptor.number_of_section_entry {
@@ -144,7 +199,53 @@ impl TdShimLoader {
             error!("Invalid metadata sections.");
             return None;
         }
+        debug!(target: PARSE_TRACE_TARGET, "TdxMetadata sections validated");
 
         Some((metadata_descriptor, metadata_sections))
     }
+
+    /// Same as `parse()` for an image already read into memory, with the parser td-shim uses at
+    /// runtime.
+    pub fn parse_image(image: &[u8]) -> Option<(TdxMetadataDescriptor, Vec<TdxMetadataSection>)> {
+        let metadata = match TdxMetadataRef::parse(image) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Invalid TdxMetadata: {:?}", e);
+                return None;
+            }
+        };
+        if metadata.validate().is_err() {
+            error!("Invalid metadata sections.");
+            return None;
+        }
+
+        Some((metadata.descriptor(), metadata.sections().collect()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(image: &[u8], name: &str) -> bool {
+        let name = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::write(&name, image).unwrap();
+        let parsed = TdShimLoader::parse(&name.to_str().unwrap().to_string()).is_some();
+        fs::remove_file(&name).unwrap();
+        parsed
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        // Smaller than the TdxMetadata pointer.
+        assert!(!parse(&[0u8; 0x10], "td-shim-loader-small"));
+
+        // Pointers near the maximum offset, or pointing before the start of the image.
+        let mut image = vec![0u8; 0x1000];
+        let ptr = image.len() - TDX_METADATA_OFFSET as usize;
+        for offset in [u32::MAX, u32::MAX - TDX_METADATA_DESCRIPTOR_LEN + 1, 0, 8] {
+            image[ptr..ptr + 4].copy_from_slice(&offset.to_le_bytes());
+            assert!(!parse(&image, "td-shim-loader-ptr"));
+        }
+    }
 }
@@ -44,6 +44,18 @@ impl TdxMetadataSection {
             )
         }
     }
+
+    /// Encode the section in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(size_of::<Self>());
+        bytes.extend_from_slice(&self.data_offset.to_le_bytes());
+        bytes.extend_from_slice(&self.raw_data_size.to_le_bytes());
+        bytes.extend_from_slice(&self.memory_address.to_le_bytes());
+        bytes.extend_from_slice(&self.memory_data_size.to_le_bytes());
+        bytes.extend_from_slice(&self.r#type.to_le_bytes());
+        bytes.extend_from_slice(&self.attributes.to_le_bytes());
+        bytes
+    }
 }
 
 fn u32_deserialize<'de, D>(deserializer: D) -> Result<u32, D::Error>
@@ -272,10 +284,10 @@ impl TdxMetadata {
     pub fn to_vec(&self) -> Vec<u8> {
         let mut metadata = Vec::new();
         metadata.extend_from_slice(self.guid.as_bytes());
-        metadata.extend_from_slice(self.descriptor.as_bytes());
+        metadata.extend_from_slice(&self.descriptor.to_bytes());
 
         for section in self.sections.as_slice() {
-            metadata.extend_from_slice(section.as_bytes());
+            metadata.extend_from_slice(&section.to_bytes());
         }
 
         metadata
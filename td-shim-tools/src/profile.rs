@@ -0,0 +1,231 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Named signing profiles for td-shim-sign-payload.
+//!
+//! A profile file is a TOML document with one table per profile, for example:
+//!
+//! ```toml
+//! [release]
+//! algorithm = "ECDSA_NIST_P384_SHA384"
+//! key = "keys/release.pk8"
+//! endian = "le"
+//! policy-min-svn = 2
+//! allowed-rtmr-indices = [1, 2]
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::error;
+use serde::Deserialize;
+use td_shim::secure_boot::PAYLOAD_DEFAULT_RTMR_INDEX;
+
+use crate::signer::{check_algorithm_backend, check_rtmr_index, RngSource};
+
+/// Signing algorithms which may be named by a profile.
+pub const PROFILE_ALGORITHMS: [&str; 2] = ["RSAPSS_3072_SHA384", "ECDSA_NIST_P384_SHA384"];
+
+/// Settings of a signing profile, `None` means not set.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SigningProfile {
+    pub algorithm: Option<String>,
+    pub key: Option<PathBuf>,
+    pub endian: Option<String>,
+    pub rng: Option<String>,
+    pub strict: Option<bool>,
+    pub policy_min_svn: Option<u64>,
+    pub policy_versions: Option<Vec<u64>>,
+    pub max_output_size: Option<u64>,
+    pub rtmr_index: Option<u32>,
+    /// RTMRs the payload may be measured into, any RTMR if not set.
+    pub allowed_rtmr_indices: Option<Vec<u32>>,
+}
+
+impl SigningProfile {
+    /// Merge settings from the command line, which override settings of the profile.
+    pub fn merge(self, overrides: SigningProfile) -> SigningProfile {
+        SigningProfile {
+            algorithm: overrides.algorithm.or(self.algorithm),
+            key: overrides.key.or(self.key),
+            endian: overrides.endian.or(self.endian),
+            rng: overrides.rng.or(self.rng),
+            strict: overrides.strict.or(self.strict),
+            policy_min_svn: overrides.policy_min_svn.or(self.policy_min_svn),
+            policy_versions: overrides.policy_versions.or(self.policy_versions),
+            max_output_size: overrides.max_output_size.or(self.max_output_size),
+            rtmr_index: overrides.rtmr_index.or(self.rtmr_index),
+            allowed_rtmr_indices: overrides.allowed_rtmr_indices.or(self.allowed_rtmr_indices),
+        }
+    }
+
+    /// Check that all settings have valid values.
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |field: &str, value: &str| {
+            error!("Invalid {} in signing profile: {}", field, value);
+            io::Error::new(io::ErrorKind::InvalidData, "invalid signing profile")
+        };
+
+        if let Some(algorithm) = self.algorithm.as_deref() {
+            check_algorithm_backend(algorithm)?;
+            if !PROFILE_ALGORITHMS.contains(&algorithm) {
+                return Err(invalid("algorithm", algorithm));
+            }
+        }
+        if let Some(endian) = self.endian.as_deref() {
+            if endian != "le" && endian != "be" {
+                return Err(invalid("endian", endian));
+            }
+        }
+        if let Some(rng) = self.rng.as_deref() {
+            RngSource::from_str(rng).map_err(|_| invalid("rng", rng))?;
+        }
+        for index in self
+            .rtmr_index
+            .iter()
+            .chain(self.allowed_rtmr_indices.iter().flatten())
+        {
+            check_rtmr_index(*index).map_err(|_| invalid("RTMR index", &index.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the index of the RTMR the payload is measured into, the default RTMR if the profile
+    /// requests none.
+    pub fn payload_rtmr_index(&self) -> u32 {
+        self.rtmr_index.unwrap_or(PAYLOAD_DEFAULT_RTMR_INDEX)
+    }
+
+    /// Check that `allowed-rtmr-indices` permits measuring the payload, or a component of it, into
+    /// RTMR[`rtmr_index`].
+    pub fn check_rtmr_permitted(&self, rtmr_index: u32) -> io::Result<()> {
+        match self.allowed_rtmr_indices.as_ref() {
+            Some(allowed) if !allowed.contains(&rtmr_index) => {
+                error!(
+                    "RTMR[{}] is not permitted by the signing profile, expect one of {:?}",
+                    rtmr_index, allowed
+                );
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "RTMR index is not permitted",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Load and validate the profile `name` from the content of a profile file.
+pub fn load_profile(profiles: &str, name: &str) -> io::Result<SigningProfile> {
+    let mut profiles: HashMap<String, SigningProfile> = toml::from_str(profiles).map_err(|e| {
+        error!("Can not parse signing profiles: {}", e);
+        io::Error::new(io::ErrorKind::InvalidData, "invalid signing profiles")
+    })?;
+
+    let profile = profiles.remove(name).ok_or_else(|| {
+        error!("Signing profile {} is not found", name);
+        io::Error::new(io::ErrorKind::NotFound, "signing profile not found")
+    })?;
+    profile.validate()?;
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROFILES: &str = r#"
+        [release]
+        algorithm = "ECDSA_NIST_P384_SHA384"
+        key = "keys/release.pk8"
+        endian = "be"
+        policy-min-svn = 2
+        policy-versions = [1, 2]
+        allowed-rtmr-indices = [1, 2]
+
+        [debug]
+        key = "keys/debug.pk8"
+        rng = "file:/dev/urandom"
+    "#;
+
+    #[test]
+    fn test_load_profile() {
+        let profile = load_profile(PROFILES, "release").unwrap();
+        assert_eq!(profile.algorithm.as_deref(), Some("ECDSA_NIST_P384_SHA384"));
+        assert_eq!(profile.key, Some(PathBuf::from("keys/release.pk8")));
+        assert_eq!(profile.endian.as_deref(), Some("be"));
+        assert_eq!(profile.policy_min_svn, Some(2));
+        assert_eq!(profile.policy_versions, Some(vec![1, 2]));
+        assert_eq!(profile.max_output_size, None);
+        assert_eq!(profile.rtmr_index, None);
+        assert_eq!(profile.allowed_rtmr_indices, Some(vec![1, 2]));
+
+        let profile = load_profile(PROFILES, "debug").unwrap();
+        assert_eq!(profile.algorithm, None);
+        assert_eq!(profile.rng.as_deref(), Some("file:/dev/urandom"));
+
+        assert!(load_profile(PROFILES, "missing").is_err());
+        assert!(load_profile("[release]\nkey-file = \"a.pk8\"\n", "release").is_err());
+    }
+
+    #[test]
+    fn test_profile_override() {
+        let profile = load_profile(PROFILES, "release").unwrap();
+        let profile = profile.merge(SigningProfile {
+            key: Some(PathBuf::from("other.pk8")),
+            policy_min_svn: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(profile.key, Some(PathBuf::from("other.pk8")));
+        assert_eq!(profile.policy_min_svn, Some(3));
+        assert_eq!(profile.algorithm.as_deref(), Some("ECDSA_NIST_P384_SHA384"));
+        assert_eq!(profile.endian.as_deref(), Some("be"));
+        assert_eq!(profile.policy_versions, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_profile_rtmr_index() {
+        let profile = load_profile(PROFILES, "release").unwrap();
+        assert_eq!(profile.payload_rtmr_index(), 1);
+        assert!(profile.check_rtmr_permitted(2).is_ok());
+        assert_eq!(
+            profile.check_rtmr_permitted(3).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        let profile = profile.merge(SigningProfile {
+            rtmr_index: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(profile.payload_rtmr_index(), 3);
+        assert!(profile.validate().is_ok());
+        assert!(profile
+            .check_rtmr_permitted(profile.payload_rtmr_index())
+            .is_err());
+
+        // Any RTMR is permitted without allowed-rtmr-indices.
+        let profile = load_profile("[p]\nrtmr-index = 0\n", "p").unwrap();
+        assert_eq!(profile.payload_rtmr_index(), 0);
+        assert!(profile.check_rtmr_permitted(0).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_profile() {
+        for profile in [
+            "[p]\nalgorithm = \"RSAPSS_2048_SHA256\"\n",
+            "[p]\nendian = \"middle\"\n",
+            "[p]\nrng = \"hardware\"\n",
+            "[p]\npolicy-min-svn = \"two\"\n",
+            "[p]\nrtmr-index = 4\n",
+            "[p]\nallowed-rtmr-indices = [1, 5]\n",
+        ] {
+            assert!(load_profile(profile, "p").is_err());
+        }
+    }
+}
@@ -0,0 +1,548 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Reference values of a signed payload booted by a shim image, that is the MRTD and RTMRs
+//! expected in the TD report, for attestation verifiers.
+//!
+//! The reference values are predicted from the shim image and the signed payload, following the
+//! boot measurements of td-shim. The TD HOB event is built by the VMM at launch time, so it's not
+//! predictable and excluded from RTMR[0], verifiers have to replay the event log for it.
+//!
+//! The reference values are serialized as a JSON document, or as a CBOR document for verifiers
+//! consuming CBOR. The CBOR document is a map with the same text keys and values as the JSON one.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use log::error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use td_layout::build_time::{TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE};
+use td_shim::metadata::TdxMetadataSection;
+use td_shim::multi_component::MultiComponentPayload;
+use td_shim::secure_boot::{
+    HashAlg, PayloadSignHeader, SignedPayloadFormat, SignedPayloadType,
+    CFV_FFS_HEADER_TRUST_ANCHOR_GUID, PAYLOAD_DEFAULT_RTMR_INDEX,
+};
+use td_uefi_pi::fv::get_file_from_fv;
+use td_uefi_pi::pi::fv::FV_FILETYPE_RAW;
+
+use crate::tee_info_hash::{
+    extend_rtmr, predict_payload_measurements, replay_rtmr, TdInfoStruct, SHA384_DIGEST_SIZE,
+};
+use crate::validator::{measured_sections, payload_image};
+
+/// Version of the reference values document.
+pub const REFERENCE_VALUES_VERSION: u32 = 2;
+
+/// Events not covered by the reference values.
+pub const EXCLUDED_EVENTS: [&str; 1] = ["td_hob"];
+
+/// Digest of a section measured into MRTD.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct SectionReference {
+    /// Index of the section in the TDX metadata section table.
+    pub index: usize,
+    pub r#type: String,
+    /// Digest of the section data stored in the shim image.
+    pub digest: String,
+}
+
+/// Predicted measurements of booting a signed payload, digests are hex encoded SHA384.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct ReferenceValues {
+    pub version: u32,
+    /// Algorithm of the digests, always `SHA384`.
+    pub hash_algorithm: String,
+    pub mrtd: String,
+    pub rtmr0: String,
+    pub rtmr1: String,
+    pub rtmr2: String,
+    pub rtmr3: String,
+    /// SVN measured into RTMR[1], `None` if the shim image is built without secure boot or the
+    /// payload is unsigned.
+    pub payload_svn: Option<u64>,
+    /// Sections measured into MRTD, in the order they are measured.
+    pub sections: Vec<SectionReference>,
+    pub excluded_events: Vec<String>,
+}
+
+impl ReferenceValues {
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| {
+            error!("Can not serialize reference values: {}", e);
+            io::Error::new(io::ErrorKind::Other, "can not serialize reference values")
+        })
+    }
+
+    /// Serialize the reference values as a CBOR map with the same keys as the JSON document.
+    pub fn to_cbor(&self) -> io::Result<Vec<u8>> {
+        let mut cbor = Vec::new();
+        ciborium::ser::into_writer(self, &mut cbor).map_err(|e| {
+            error!("Can not serialize reference values: {}", e);
+            io::Error::new(io::ErrorKind::Other, "can not serialize reference values")
+        })?;
+        Ok(cbor)
+    }
+
+    pub fn from_cbor(cbor: &[u8]) -> io::Result<Self> {
+        ciborium::de::from_reader(cbor).map_err(|e| {
+            error!("Can not parse CBOR reference values: {}", e);
+            io::Error::new(io::ErrorKind::InvalidData, "invalid CBOR reference values")
+        })
+    }
+}
+
+/// Measurement of a payload, the digest td-shim extends into the RTMR of the payload.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PayloadMeasurement {
+    /// SHA384 of the payload image.
+    pub digest: [u8; SHA384_DIGEST_SIZE],
+    /// Index of the RTMR the payload is measured into.
+    pub rtmr_index: u32,
+    /// Value of the RTMR once extended with the digest, if its previous value is known.
+    pub rtmr: Option<[u8; SHA384_DIGEST_SIZE]>,
+}
+
+impl fmt::Display for PayloadMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Measurement: {}", hex::encode(self.digest))?;
+        if let Some(rtmr) = self.rtmr {
+            write!(f, "\nRTMR[{}]: {}", self.rtmr_index, hex::encode(rtmr))?;
+        }
+        Ok(())
+    }
+}
+
+// The payload image of a signed payload, with what td-shim measures it with.
+struct SignedImage<'a> {
+    image: &'a [u8],
+    // `None` if the payload is unsigned.
+    svn: Option<u64>,
+    rtmr_index: u32,
+    payload_type: SignedPayloadType,
+}
+
+// Parse `signed_payload`. td-shim measures SVN 0 for payloads signed in the compact format, and no
+// SVN for unsigned payloads. Both record no payload type and are measured into the default RTMR.
+fn parse_signed_payload(signed_payload: &[u8]) -> io::Result<SignedImage> {
+    let image = payload_image(signed_payload).ok_or_else(|| {
+        error!("Invalid signed payload");
+        io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload")
+    })?;
+    let parsed = match SignedPayloadFormat::detect(signed_payload) {
+        Some(SignedPayloadFormat::Standard) => {
+            // Safe to unwrap() because the format has been detected.
+            let header = PayloadSignHeader::from_bytes(signed_payload).unwrap();
+            let rtmr_index = header.payload_rtmr_index().map_err(|_| {
+                error!("Invalid RTMR index in the verify header");
+                io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload")
+            })?;
+            SignedImage {
+                image,
+                svn: Some(header.payload_svn),
+                rtmr_index,
+                payload_type: header.payload_type(),
+            }
+        }
+        format => SignedImage {
+            image,
+            svn: format.map(|_| 0),
+            rtmr_index: PAYLOAD_DEFAULT_RTMR_INDEX,
+            payload_type: SignedPayloadType::Unspecified,
+        },
+    };
+
+    Ok(parsed)
+}
+
+/// Measure `payload` as td-shim does, without a key nor a shim image, so the measurement can be
+/// registered before the payload is signed.
+///
+/// `payload` is the payload binary or a signed payload, whose signature is not verified. The
+/// RTMR index defaults to the one recorded by the verify header, or the default RTMR. If `prev`,
+/// the value of the RTMR before the payload is measured, is given, the RTMR is extended with the
+/// measurement. Multi-component payloads are measured per component and rejected.
+pub fn measure_payload(
+    payload: &[u8],
+    rtmr_index: Option<u32>,
+    prev: Option<&[u8; SHA384_DIGEST_SIZE]>,
+) -> io::Result<PayloadMeasurement> {
+    let parsed = parse_signed_payload(payload)?;
+    if parsed.payload_type == SignedPayloadType::MultiComponent
+        && MultiComponentPayload::parse(parsed.image).is_some()
+    {
+        error!("Multi-component payloads are measured per component, see td-shim-sign-payload --emit-reference-values");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "multi-component payload",
+        ));
+    }
+
+    let mut digest = [0u8; SHA384_DIGEST_SIZE];
+    digest.copy_from_slice(HashAlg::Sha384.digest(parsed.image).as_ref());
+    Ok(PayloadMeasurement {
+        digest,
+        rtmr_index: rtmr_index.unwrap_or(parsed.rtmr_index),
+        rtmr: prev.map(|v| extend_rtmr(v, &digest)),
+    })
+}
+
+/// Predict the reference values of booting `signed_payload` with the shim image `shim_image`.
+pub fn predict_reference_values(
+    shim_image: &Path,
+    signed_payload: &[u8],
+) -> io::Result<ReferenceValues> {
+    let read_error = |e: io::Error| {
+        error!("Can not read shim image {}: {}", shim_image.display(), e);
+        e
+    };
+    let image = fs::read(shim_image).map_err(read_error)?;
+    if image.len() != TD_SHIM_FIRMWARE_SIZE as usize || measured_sections(&image).next().is_none() {
+        error!(
+            "Shim image {} is not a {:#x} bytes td-shim image with measured sections",
+            shim_image.display(),
+            TD_SHIM_FIRMWARE_SIZE
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid shim image",
+        ));
+    }
+
+    let mut tee_info = TdInfoStruct::default();
+    let mut file = File::open(shim_image).map_err(read_error)?;
+    tee_info.build_mrtd(&mut file, image.len() as u64);
+
+    let parsed = parse_signed_payload(signed_payload)?;
+
+    let start = TD_SHIM_CONFIG_OFFSET as usize;
+    let cfv = &image[start..start + TD_SHIM_CONFIG_SIZE as usize];
+    let steps = predict_payload_measurements(
+        cfv,
+        parsed.image,
+        parsed.svn,
+        parsed.rtmr_index,
+        parsed.payload_type,
+    );
+    let secure_boot =
+        get_file_from_fv(cfv, FV_FILETYPE_RAW, CFV_FFS_HEADER_TRUST_ANCHOR_GUID).is_some();
+
+    let sections = measured_sections(&image)
+        .map(|s| SectionReference {
+            index: s.index,
+            r#type: TdxMetadataSection::get_type_name(s.r#type)
+                .unwrap_or_else(|| format!("Unknown({})", s.r#type)),
+            digest: hex::encode(
+                HashAlg::Sha384
+                    .digest(&image[s.file_range.start as usize..s.file_range.end as usize]),
+            ),
+        })
+        .collect();
+
+    Ok(ReferenceValues {
+        version: REFERENCE_VALUES_VERSION,
+        hash_algorithm: "SHA384".to_string(),
+        mrtd: hex::encode(tee_info.mrtd),
+        rtmr0: hex::encode(replay_rtmr(&steps, 0)),
+        rtmr1: hex::encode(replay_rtmr(&steps, 1)),
+        rtmr2: hex::encode(replay_rtmr(&steps, 2)),
+        rtmr3: hex::encode(replay_rtmr(&steps, 3)),
+        payload_svn: if secure_boot { parsed.svn } else { None },
+        sections,
+        excluded_events: EXCLUDED_EVENTS.iter().map(|v| v.to_string()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::{Digest, Sha384};
+    use td_shim::event_log::SEPARATOR_DATA;
+    use td_shim::metadata::{
+        TdxMetadataDescriptor, TdxMetadataSection, TDX_METADATA_ATTRIBUTES_EXTENDMR,
+        TDX_METADATA_GUID, TDX_METADATA_OFFSET, TDX_METADATA_SECTION_TYPE_BFV,
+    };
+    use td_shim::secure_boot::{
+        PAYLOAD_SIGN_HEADER_VERSION_V1, PAYLOAD_SIGN_HEADER_VERSION_V2,
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+    };
+
+    const METADATA_OFFSET: usize = 0x50000;
+
+    fn build_shim_image() -> Vec<u8> {
+        let mut image = vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize];
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        let start = TD_SHIM_CONFIG_OFFSET as usize;
+        image[start..start + cfv.len()].copy_from_slice(cfv);
+
+        let bfv = TdxMetadataSection {
+            data_offset: 0x60000,
+            raw_data_size: 0x1000,
+            memory_address: 0xfff60000,
+            memory_data_size: 0x1000,
+            r#type: TDX_METADATA_SECTION_TYPE_BFV,
+            attributes: TDX_METADATA_ATTRIBUTES_EXTENDMR,
+        };
+        image[0x60000..0x61000].fill(0x5a);
+        let mut descriptor = TdxMetadataDescriptor::default();
+        descriptor.set_sections(1);
+
+        let offset = METADATA_OFFSET + 16;
+        image[METADATA_OFFSET..offset].copy_from_slice(TDX_METADATA_GUID.as_bytes());
+        image[offset..offset + 16].copy_from_slice(&descriptor.to_bytes());
+        image[offset + 16..offset + 48].copy_from_slice(&bfv.to_bytes());
+        let ptr = image.len() - TDX_METADATA_OFFSET as usize;
+        image[ptr..ptr + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+
+        image
+    }
+
+    fn build_signed_payload(payload: &[u8], payload_svn: u64) -> Vec<u8> {
+        build_signed_payload_with_header(payload, payload_svn, |_| {})
+    }
+
+    fn build_signed_payload_with_header<F>(payload: &[u8], payload_svn: u64, edit: F) -> Vec<u8>
+    where
+        F: Fn(&mut PayloadSignHeader),
+    {
+        let mut header = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V1,
+            payload_version: 1,
+            payload_svn,
+            signing_algorithm: PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+            ..Default::default()
+        };
+        edit(&mut header);
+        header.length = (header.header_size() + payload.len()) as u32;
+
+        let mut signed_payload = header.to_bytes();
+        signed_payload.extend_from_slice(payload);
+        signed_payload.extend_from_slice(&[0u8; 776]);
+        signed_payload
+    }
+
+    fn extend(digests: &[&[u8]]) -> String {
+        let mut rtmr = [0u8; SHA384_DIGEST_SIZE];
+        for data in digests {
+            let mut sha384hasher = Sha384::new();
+            sha384hasher.update(rtmr);
+            sha384hasher.update(Sha384::digest(data));
+            rtmr.copy_from_slice(sha384hasher.finalize().as_slice());
+        }
+        hex::encode(rtmr)
+    }
+
+    #[test]
+    fn test_predict_reference_values() {
+        let image = build_shim_image();
+        let path = std::env::temp_dir().join(format!("td-shim-reference-{}", std::process::id()));
+        fs::write(&path, &image).unwrap();
+
+        let payload = b"td payload image";
+        let values = predict_reference_values(&path, &build_signed_payload(payload, 9)).unwrap();
+
+        // Independent prediction of the MRTD.
+        let mut tee_info = TdInfoStruct::default();
+        tee_info.build_mrtd(&mut File::open(&path).unwrap(), image.len() as u64);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(values.mrtd, hex::encode(tee_info.mrtd));
+
+        let cfv = &image[TD_SHIM_CONFIG_OFFSET as usize..];
+        let trust_anchor =
+            get_file_from_fv(cfv, FV_FILETYPE_RAW, CFV_FFS_HEADER_TRUST_ANCHOR_GUID).unwrap();
+        assert_eq!(
+            values.rtmr0,
+            extend(&[trust_anchor, trust_anchor, &SEPARATOR_DATA])
+        );
+        assert_eq!(
+            values.rtmr1,
+            extend(&[&9u64.to_le_bytes(), payload, &SEPARATOR_DATA])
+        );
+        assert_eq!(values.rtmr2, "00".repeat(SHA384_DIGEST_SIZE));
+        assert_eq!(values.payload_svn, Some(9));
+        assert_eq!(values.excluded_events, vec!["td_hob".to_string()]);
+
+        assert_eq!(values.hash_algorithm, "SHA384");
+        assert_eq!(
+            values.sections,
+            vec![SectionReference {
+                index: 0,
+                r#type: "BFV".to_string(),
+                digest: hex::encode(Sha384::digest(&image[0x60000..0x61000])),
+            }]
+        );
+
+        let json = values.to_json().unwrap();
+        let parsed: ReferenceValues = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, values);
+
+        // The shim image has been removed.
+        assert!(predict_reference_values(&path, payload).is_err());
+    }
+
+    #[test]
+    fn test_predict_reference_values_unsigned() {
+        let image = build_shim_image();
+        let path =
+            std::env::temp_dir().join(format!("td-shim-reference-unsigned-{}", std::process::id()));
+        fs::write(&path, &image).unwrap();
+
+        // The CFV has a trust anchor, but td-shim logs neither the trust anchor nor the SVN for an
+        // unsigned payload.
+        let payload = b"td payload image";
+        let values = predict_reference_values(&path, payload).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(values.rtmr0, extend(&[&SEPARATOR_DATA]));
+        assert_eq!(values.rtmr1, extend(&[payload, &SEPARATOR_DATA]));
+        assert_eq!(values.payload_svn, None);
+    }
+
+    #[test]
+    fn test_predict_reference_values_rtmr_index() {
+        let image = build_shim_image();
+        let path =
+            std::env::temp_dir().join(format!("td-shim-reference-rtmr-{}", std::process::id()));
+        fs::write(&path, &image).unwrap();
+
+        // The payload binary moves to the RTMR requested by the verify header.
+        let payload = b"td payload image";
+        let with_rtmr = |mr_index: u8| {
+            build_signed_payload_with_header(payload, 9, |header| {
+                header.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V2;
+                header.mr_index = mr_index;
+            })
+        };
+        let values = predict_reference_values(&path, &with_rtmr(3)).unwrap();
+        assert_eq!(values.rtmr2, extend(&[payload]));
+        assert_eq!(
+            values.rtmr1,
+            extend(&[&9u64.to_le_bytes(), &SEPARATOR_DATA])
+        );
+        assert_eq!(values.rtmr3, "00".repeat(SHA384_DIGEST_SIZE));
+
+        let default = predict_reference_values(&path, &build_signed_payload(payload, 9)).unwrap();
+        assert_eq!(
+            predict_reference_values(&path, &with_rtmr(2)).unwrap(),
+            default
+        );
+
+        // There is no RTMR[4].
+        assert!(predict_reference_values(&path, &with_rtmr(5)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reference_values_cbor() {
+        let image = build_shim_image();
+        let path =
+            std::env::temp_dir().join(format!("td-shim-reference-cbor-{}", std::process::id()));
+        fs::write(&path, &image).unwrap();
+        let values =
+            predict_reference_values(&path, &build_signed_payload(b"td payload image", 9)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // The CBOR document decodes to the same fields as the JSON one.
+        let cbor = values.to_cbor().unwrap();
+        let from_cbor: serde_json::Value = ciborium::de::from_reader(cbor.as_slice()).unwrap();
+        let from_json: serde_json::Value =
+            serde_json::from_str(&values.to_json().unwrap()).unwrap();
+        assert_eq!(from_cbor, from_json);
+        assert_eq!(from_cbor["version"], REFERENCE_VALUES_VERSION);
+        assert_eq!(from_cbor["sections"][0]["type"], "BFV");
+        assert_eq!(ReferenceValues::from_cbor(&cbor).unwrap(), values);
+
+        // Null values are kept.
+        let unsecure = ReferenceValues {
+            payload_svn: None,
+            ..values
+        };
+        let from_cbor: serde_json::Value =
+            ciborium::de::from_reader(unsecure.to_cbor().unwrap().as_slice()).unwrap();
+        assert_eq!(from_cbor.get("payload_svn"), Some(&serde_json::Value::Null));
+
+        assert!(ReferenceValues::from_cbor(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_padding_excluded_from_measurements() {
+        use crate::signer::{pad_signed_payload, PayloadSigner, SigningAlgorithm};
+
+        let image = build_shim_image();
+        let path = std::env::temp_dir().join(format!("td-shim-padding-{}", std::process::id()));
+        fs::write(&path, &image).unwrap();
+
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut signer = PayloadSigner::new(payload, algorithm);
+        let header = signer.build_header(1, 3);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let mut padded = signed.clone();
+        pad_signed_payload(&mut padded, signed.len() + 0x10000).unwrap();
+        assert_ne!(padded.len(), signed.len());
+
+        assert_eq!(
+            predict_reference_values(&path, &padded).unwrap(),
+            predict_reference_values(&path, &signed).unwrap()
+        );
+        assert_eq!(
+            measure_payload(&padded, None, None).unwrap(),
+            measure_payload(&signed, None, None).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_measure_payload() {
+        use ring::digest::{digest, SHA384};
+
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let measurement = measure_payload(payload, None, None).unwrap();
+        let expected = digest(&SHA384, payload);
+        assert_eq!(&measurement.digest[..], expected.as_ref());
+        assert_eq!(measurement.rtmr_index, PAYLOAD_DEFAULT_RTMR_INDEX);
+        assert_eq!(
+            measurement.to_string(),
+            format!("Measurement: {}", hex::encode(expected))
+        );
+
+        // The RTMR is extended from its previous value.
+        let prev = [0x5au8; SHA384_DIGEST_SIZE];
+        let measurement = measure_payload(payload, Some(2), Some(&prev)).unwrap();
+        let mut data = prev.to_vec();
+        data.extend_from_slice(expected.as_ref());
+        let rtmr = digest(&SHA384, &data);
+        assert_eq!(&measurement.rtmr.unwrap()[..], rtmr.as_ref());
+        assert_eq!(
+            measurement.to_string(),
+            format!(
+                "Measurement: {}\nRTMR[2]: {}",
+                hex::encode(expected),
+                hex::encode(rtmr)
+            )
+        );
+
+        // The payload image of a signed payload is measured, into the RTMR of the verify header.
+        let signed_payload = build_signed_payload_with_header(b"td payload image", 1, |h| {
+            h.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V2;
+            h.mr_index = 3;
+        });
+        let measurement = measure_payload(&signed_payload, None, None).unwrap();
+        assert_eq!(
+            &measurement.digest[..],
+            digest(&SHA384, b"td payload image").as_ref()
+        );
+        assert_eq!(measurement.rtmr_index, 2);
+        assert_eq!(
+            measure_payload(&signed_payload, Some(1), None)
+                .unwrap()
+                .rtmr_index,
+            1
+        );
+    }
+}
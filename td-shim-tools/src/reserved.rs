@@ -0,0 +1,206 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Check that the reserved fields of the verify header and the SVN policy are zero.
+//!
+//! td-shim ignores these reserved fields, so non-zero bytes don't change how a payload boots, but
+//! they are not produced by the td-shim tools and may reveal tampering or a buggy tool. They are
+//! reported as warnings, or as errors in strict mode. Reserved attribute bits of the TDX metadata
+//! sections are already rejected by `td_shim::metadata::validate_sections()`.
+
+use std::fmt;
+use std::io;
+
+use log::{error, warn};
+use schemars::JsonSchema;
+use scroll::Pread;
+use serde::Serialize;
+use td_shim::secure_boot::{
+    PayloadSignHeader, SvnPolicyHeader, PAYLOAD_SIGN_FLAG_BIG_ENDIAN,
+    PAYLOAD_SIGN_HEADER_FLAGS_OFFSET, PAYLOAD_SIGN_HEADER_V1_SIZE, PAYLOAD_SIGN_HEADER_VERSION_V1,
+    SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+/// Offset of `PayloadSignHeader.reserved`.
+const VERIFY_HEADER_RESERVED_OFFSET: usize = PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 3;
+/// Offset of `PayloadSignHeader.reserved2`, after the SVN policy offset and size, the load base
+/// and the measurement register.
+const VERIFY_HEADER_RESERVED2_OFFSET: usize = PAYLOAD_SIGN_HEADER_V1_SIZE + 17;
+/// Offset of `SvnPolicyHeader.reserved`.
+const SVN_POLICY_RESERVED_OFFSET: usize = 20;
+
+/// A reserved field carrying non-zero bytes.
+#[derive(Clone, Debug, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct ReservedField {
+    /// Name of the structure and the field.
+    pub field: String,
+    /// Offset of the field from the start of the signed payload.
+    pub offset: usize,
+    /// Reserved bits or bytes of the field in hex.
+    pub value: String,
+}
+
+impl fmt::Display for ReservedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "reserved bits of {} at offset 0x{:x} are not zero: {}",
+            self.field, self.offset, self.value
+        )
+    }
+}
+
+/// Find the reserved fields of the verify header `header` which are not zero: the undefined
+/// flags, `reserved` and, for version 2 and later headers, `reserved2`.
+pub fn check_verify_header(header: &PayloadSignHeader) -> Vec<ReservedField> {
+    let mut fields = Vec::new();
+
+    let flags = header.flags & !PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+    if flags != 0 {
+        fields.push(ReservedField {
+            field: "verify header flags".to_string(),
+            offset: PAYLOAD_SIGN_HEADER_FLAGS_OFFSET,
+            value: format!("0x{:02x}", flags),
+        });
+    }
+    if header.reserved.iter().any(|v| *v != 0) {
+        fields.push(ReservedField {
+            field: "verify header reserved".to_string(),
+            offset: VERIFY_HEADER_RESERVED_OFFSET,
+            value: hex::encode(header.reserved),
+        });
+    }
+    // Version 1 headers end before `reserved2`, which always reads as zero.
+    if header.struct_version > PAYLOAD_SIGN_HEADER_VERSION_V1
+        && header.reserved2.iter().any(|v| *v != 0)
+    {
+        fields.push(ReservedField {
+            field: "verify header reserved2".to_string(),
+            offset: VERIFY_HEADER_RESERVED2_OFFSET,
+            value: hex::encode(header.reserved2),
+        });
+    }
+
+    fields
+}
+
+/// Find the reserved fields of the signed payload `signed_payload` which are not zero, in the
+/// verify header and the SVN policy. Nothing is reported for unsigned payloads.
+pub fn check_signed_payload(signed_payload: &[u8]) -> Vec<ReservedField> {
+    let header = match PayloadSignHeader::from_bytes(signed_payload) {
+        Some(v) if &v.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() => v,
+        _ => return Vec::new(),
+    };
+    let mut fields = check_verify_header(&header);
+
+    // A malformed SVN policy is reported by the verifier.
+    let policy_offset = header.policy_offset as usize;
+    if header.policy_size != 0 {
+        if let Ok(policy) =
+            signed_payload.pread_with::<SvnPolicyHeader>(policy_offset, header.endian())
+        {
+            if policy.reserved != 0 {
+                fields.push(ReservedField {
+                    field: "SVN policy reserved".to_string(),
+                    offset: policy_offset + SVN_POLICY_RESERVED_OFFSET,
+                    value: format!("0x{:08x}", policy.reserved),
+                });
+            }
+        }
+    }
+
+    fields
+}
+
+/// Report the non-zero reserved `fields` of `name` as warnings, or fail if `strict` is set.
+pub fn report_reserved_fields(
+    name: &str,
+    fields: &[ReservedField],
+    strict: bool,
+) -> io::Result<()> {
+    for field in fields {
+        if strict {
+            error!("{}: {}", name, field);
+        } else {
+            warn!("{}: {}", name, field);
+        }
+    }
+    if strict && !fields.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reserved fields are not zero",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SIGNED_PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+    #[test]
+    fn test_check_verify_header() {
+        let header = PayloadSignHeader::from_bytes(SIGNED_PAYLOAD).unwrap();
+        assert!(check_verify_header(&header).is_empty());
+        assert!(report_reserved_fields("payload", &[], true).is_ok());
+
+        // Dirty the reserved byte and the undefined flags of the version 1 header.
+        let mut payload = SIGNED_PAYLOAD.to_vec();
+        payload[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET] |= 0x80;
+        payload[VERIFY_HEADER_RESERVED_OFFSET] = 0x5a;
+        let header = PayloadSignHeader::from_bytes(&payload).unwrap();
+        let fields = check_verify_header(&header);
+        assert_eq!(
+            fields,
+            vec![
+                ReservedField {
+                    field: "verify header flags".to_string(),
+                    offset: PAYLOAD_SIGN_HEADER_FLAGS_OFFSET,
+                    value: "0x80".to_string(),
+                },
+                ReservedField {
+                    field: "verify header reserved".to_string(),
+                    offset: VERIFY_HEADER_RESERVED_OFFSET,
+                    value: "5a".to_string(),
+                },
+            ]
+        );
+
+        // A warning by default, an error in strict mode.
+        assert!(report_reserved_fields("payload", &fields, false).is_ok());
+        assert_eq!(
+            report_reserved_fields("payload", &fields, true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // reserved2 only exists in version 2 headers.
+        let mut header = PayloadSignHeader::from_bytes(SIGNED_PAYLOAD).unwrap();
+        header.reserved2[14] = 1;
+        assert!(check_verify_header(&header).is_empty());
+        header.struct_version = 2;
+        assert_eq!(
+            check_verify_header(&header)[0].offset,
+            VERIFY_HEADER_RESERVED2_OFFSET
+        );
+    }
+
+    #[test]
+    fn test_check_signed_payload() {
+        assert!(check_signed_payload(SIGNED_PAYLOAD).is_empty());
+        assert!(check_signed_payload(&SIGNED_PAYLOAD[PAYLOAD_SIGN_HEADER_V1_SIZE..]).is_empty());
+
+        let mut payload = SIGNED_PAYLOAD.to_vec();
+        payload[VERIFY_HEADER_RESERVED_OFFSET] = 0xff;
+        let fields = check_signed_payload(&payload);
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].field, "verify header reserved");
+        assert!(report_reserved_fields("payload", &fields, true).is_err());
+    }
+}
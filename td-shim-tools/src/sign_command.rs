@@ -0,0 +1,397 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Sign payloads with an external signing command, for signing agents and HSMs which are only
+//! reachable through their own command line tools.
+//!
+//! The command line is built from a template split at whitespace, no shell is involved. In each
+//! argument `{key_id}` is replaced by the key identifier, `{algorithm}` by the signing algorithm
+//! and `{digest}` by the digest in hex. The command reads the raw SHA384 digest of the signed
+//! region from stdin and writes the signature to stdout, either raw or in hex. ECDSA signatures
+//! may be R||S or DER encoded, as written by `openssl pkeyutl -sign`.
+//!
+//! The signature is verified against the public key before the signed payload is assembled.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use log::{error, trace};
+use scroll::Endian;
+use td_shim::secure_boot::{
+    PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE, PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE,
+};
+
+use crate::signature::{convert_ecdsa_signature, Curve, SigFormat};
+use crate::split::{assemble_signed_payload, prepare_signing};
+
+/// Placeholder of the key identifier in the command template.
+pub const PLACEHOLDER_KEY_ID: &str = "{key_id}";
+/// Placeholder of the signing algorithm in the command template.
+pub const PLACEHOLDER_ALGORITHM: &str = "{algorithm}";
+/// Placeholder of the hex encoded digest in the command template.
+pub const PLACEHOLDER_DIGEST: &str = "{digest}";
+
+fn command_error(reason: &str) -> io::Error {
+    error!("Signing command failed: {}", reason);
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("signing command failed: {}", reason),
+    )
+}
+
+/// External command signing the digest of a payload.
+#[derive(Debug, Clone)]
+pub struct SignCommand {
+    template: Vec<String>,
+    key_id: Option<String>,
+}
+
+impl SignCommand {
+    /// Parse the command line template `template`.
+    pub fn new(template: &str) -> io::Result<Self> {
+        let template: Vec<String> = template.split_whitespace().map(String::from).collect();
+        if template.is_empty() {
+            error!("The signing command template is empty");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty signing command",
+            ));
+        }
+
+        Ok(SignCommand {
+            template,
+            key_id: None,
+        })
+    }
+
+    /// Set the key identifier replacing `{key_id}` in the template.
+    pub fn set_key_id(&mut self, key_id: &str) -> &mut Self {
+        self.key_id = Some(key_id.to_string());
+        self
+    }
+
+    /// Build the command line for signing `digest` with `algorithm`.
+    pub fn command_line(&self, algorithm: &str, digest: &[u8]) -> io::Result<Vec<String>> {
+        let digest = hex::encode(digest);
+        self.template
+            .iter()
+            .map(|arg| {
+                if arg.contains(PLACEHOLDER_KEY_ID) && self.key_id.is_none() {
+                    error!(
+                        "The signing command requires a key id for {}",
+                        PLACEHOLDER_KEY_ID
+                    );
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "no key id"));
+                }
+                Ok(arg
+                    .replace(PLACEHOLDER_KEY_ID, self.key_id.as_deref().unwrap_or(""))
+                    .replace(PLACEHOLDER_ALGORITHM, algorithm)
+                    .replace(PLACEHOLDER_DIGEST, &digest))
+            })
+            .collect()
+    }
+
+    /// Run the command to sign `digest` with `algorithm`, and return the signature of
+    /// `signature_size` bytes.
+    pub fn sign_digest(
+        &self,
+        algorithm: &str,
+        digest: &[u8],
+        signature_size: usize,
+    ) -> io::Result<Vec<u8>> {
+        let command_line = self.command_line(algorithm, digest)?;
+        trace!("Running signing command: {}", command_line.join(" "));
+        let mut child = Command::new(&command_line[0])
+            .args(&command_line[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| command_error(&format!("can not run {}: {}", command_line[0], e)))?;
+
+        // Safe to unwrap() because stdin is piped, it's closed once written.
+        let written = child.stdin.take().unwrap().write_all(digest);
+        let output = child
+            .wait_with_output()
+            .map_err(|e| command_error(&e.to_string()))?;
+        if !output.status.success() {
+            return Err(command_error(&format!("exited with {}", output.status)));
+        }
+        written.map_err(|e| command_error(&format!("can not write digest: {}", e)))?;
+
+        parse_signature(&output.stdout, algorithm, signature_size)
+    }
+}
+
+// Parse the signature written by the signing command, raw or in hex.
+fn parse_signature(output: &[u8], algorithm: &str, signature_size: usize) -> io::Result<Vec<u8>> {
+    let hex_text = std::str::from_utf8(output)
+        .ok()
+        .and_then(|v| hex::decode(v.trim()).ok());
+    let signature = match hex_text {
+        Some(v) if output.len() != signature_size => v,
+        _ => output.to_vec(),
+    };
+    if signature.len() == signature_size {
+        return Ok(signature);
+    }
+
+    // DER encoded ECDSA signature.
+    if algorithm == "ECDSA_NIST_P384_SHA384" {
+        if let Ok(v) = convert_ecdsa_signature(
+            &signature,
+            SigFormat::Asn1,
+            SigFormat::Fixed,
+            Curve::NistP384,
+        ) {
+            return Ok(v);
+        }
+    }
+
+    Err(command_error(&format!(
+        "unexpected signature of {} bytes, expect {} bytes",
+        signature.len(),
+        signature_size
+    )))
+}
+
+/// Sign `payload` with `command`, where `public_key` is the public key of the signing key in the
+/// format of the signature block, see `crate::enroller::parse_public_key()`.
+///
+/// Return the signed payload, the signature is verified against `public_key`.
+pub fn sign_payload_with_command(
+    command: &SignCommand,
+    payload: &[u8],
+    algorithm: &str,
+    public_key: &[u8],
+    endian: Endian,
+    payload_version: u64,
+    payload_svn: u64,
+) -> io::Result<Vec<u8>> {
+    let request = prepare_signing(payload, algorithm, endian, payload_version, payload_svn)?;
+    let (public_key_size, signature_size) = match request.header.signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => (
+            PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE,
+        ),
+        _ => (
+            PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE,
+            PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE,
+        ),
+    };
+    if public_key.len() != public_key_size {
+        error!(
+            "The public key doesn't match signing algorithm {}",
+            algorithm
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "public key doesn't match the signing algorithm",
+        ));
+    }
+
+    let signature = command.sign_digest(algorithm, &request.digest, signature_size)?;
+    let mut block = public_key.to_vec();
+    block.extend_from_slice(&signature);
+
+    assemble_signed_payload(&request.header.to_bytes(), payload, &block)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use td_shim::secure_boot::PayloadVerifier;
+
+    use crate::enroller::parse_public_key;
+
+    fn sample_key(name: &str) -> String {
+        format!(
+            "{}/../data/sample-keys/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    fn public_key(name: &str) -> Vec<u8> {
+        let file = sample_key(name);
+        parse_public_key(&fs::read(&file).unwrap(), &file).unwrap()
+    }
+
+    // Write a signing script taking the private key file as its argument.
+    #[cfg(unix)]
+    fn write_script(name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::write(&script, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    #[cfg(unix)]
+    fn has_openssl() -> bool {
+        let found = Command::new("openssl")
+            .arg("version")
+            .stdout(Stdio::null())
+            .status()
+            .map_or(false, |s| s.success());
+        if !found {
+            eprintln!("Skipping the signing command test: openssl is not found");
+        }
+        found
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut command =
+            SignCommand::new("signer --key {key_id} -a {algorithm} {digest}").unwrap();
+        assert!(command
+            .command_line("ECDSA_NIST_P384_SHA384", &[0x5a])
+            .is_err());
+        command.set_key_id("slot-1");
+        assert_eq!(
+            command
+                .command_line("ECDSA_NIST_P384_SHA384", &[0x5a])
+                .unwrap(),
+            vec![
+                "signer",
+                "--key",
+                "slot-1",
+                "-a",
+                "ECDSA_NIST_P384_SHA384",
+                "5a"
+            ]
+        );
+        assert!(SignCommand::new(" ").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sign_with_command() {
+        if !has_openssl() {
+            return;
+        }
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let rsa_script = write_script(
+            "td-shim-sign-command",
+            "exec openssl pkeyutl -sign -keyform DER -inkey \"$1\" \
+             -pkeyopt rsa_padding_mode:pss -pkeyopt rsa_pss_saltlen:48 -pkeyopt digest:sha384",
+        );
+        let ecdsa_script = write_script(
+            "td-shim-sign-command-ecdsa",
+            "exec openssl pkeyutl -sign -keyform DER -inkey \"$1\" -pkeyopt digest:sha384",
+        );
+
+        for (algorithm, script, private, public) in [
+            (
+                "RSAPSS_3072_SHA384",
+                &rsa_script,
+                "rsa-3072-private.pk8",
+                "rsa-3072-public.der",
+            ),
+            (
+                "ECDSA_NIST_P384_SHA384",
+                &ecdsa_script,
+                "ecdsa-p384-private.pk8",
+                "ecdsa-p384-public.der",
+            ),
+        ] {
+            let mut command =
+                SignCommand::new(&format!("{} {{key_id}}", script.display())).unwrap();
+            command.set_key_id(&sample_key(private));
+            let signed = sign_payload_with_command(
+                &command,
+                payload,
+                algorithm,
+                &public_key(public),
+                Endian::Little,
+                1,
+                2,
+            )
+            .unwrap();
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert!(verifier.verify_signature().is_ok());
+            assert_eq!(verifier.get_payload_svn(), 2);
+        }
+
+        // The signature is made by another key than the public key.
+        let mut command =
+            SignCommand::new(&format!("{} {{key_id}}", rsa_script.display())).unwrap();
+        command.set_key_id(&sample_key("rsa-4096-private.pk8"));
+        assert!(sign_payload_with_command(
+            &command,
+            payload,
+            "RSAPSS_3072_SHA384",
+            &public_key("rsa-3072-public.der"),
+            Endian::Little,
+            1,
+            2,
+        )
+        .is_err());
+
+        // The public key doesn't match the algorithm.
+        assert!(sign_payload_with_command(
+            &command,
+            payload,
+            "RSAPSS_3072_SHA384",
+            &public_key("ecdsa-p384-public.der"),
+            Endian::Little,
+            1,
+            2,
+        )
+        .is_err());
+
+        fs::remove_file(&rsa_script).unwrap();
+        fs::remove_file(&ecdsa_script).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sign_command_failure() {
+        let failing = write_script("td-shim-sign-command-fail", "cat > /dev/null; exit 1");
+        let garbage = write_script("td-shim-sign-command-garbage", "cat > /dev/null; echo 5a5a");
+        for script in [&failing, &garbage] {
+            let command = SignCommand::new(&script.display().to_string()).unwrap();
+            assert!(command
+                .sign_digest("RSAPSS_3072_SHA384", &[0u8; 48], 384)
+                .is_err());
+        }
+        let command = SignCommand::new("/nonexistent/td-shim-signer").unwrap();
+        assert!(command
+            .sign_digest("RSAPSS_3072_SHA384", &[0u8; 48], 384)
+            .is_err());
+
+        fs::remove_file(&failing).unwrap();
+        fs::remove_file(&garbage).unwrap();
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        let signature = [0x5au8; 96];
+        let algorithm = "ECDSA_NIST_P384_SHA384";
+        assert_eq!(
+            parse_signature(&signature, algorithm, 96).unwrap(),
+            signature
+        );
+        let text = format!("{}\n", hex::encode(signature));
+        assert_eq!(
+            parse_signature(text.as_bytes(), algorithm, 96).unwrap(),
+            signature
+        );
+        let der = convert_ecdsa_signature(
+            &signature,
+            SigFormat::Fixed,
+            SigFormat::Asn1,
+            Curve::NistP384,
+        )
+        .unwrap();
+        assert_eq!(parse_signature(&der, algorithm, 96).unwrap(), signature);
+        assert!(parse_signature(&der, "RSAPSS_3072_SHA384", 96).is_err());
+        assert!(parse_signature(&signature[1..], algorithm, 96).is_err());
+    }
+}
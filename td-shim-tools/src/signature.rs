@@ -0,0 +1,566 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Convert ECDSA signatures between the fixed R||S and the ASN.1 DER encodings.
+//!
+//! Both encodings carry the same R and S, so a signed payload can be converted without the
+//! private key.
+
+use std::io;
+use std::str::FromStr;
+
+use log::error;
+use ring::signature::{self, UnparsedPublicKey};
+use scroll::{Pread, Pwrite};
+use td_shim::secure_boot::{
+    signature_block_size, AdditionalSignatureHeader, PayloadSignHeader,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+    SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+const ASN1_TAG_SEQUENCE: u8 = 0x30;
+const ASN1_TAG_INTEGER: u8 = 0x02;
+
+/// Encoding of an ECDSA signature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SigFormat {
+    /// R||S, each zero padded to the size of the curve scalar.
+    Fixed,
+    /// DER encoded SEQUENCE of the INTEGERs R and S.
+    Asn1,
+}
+
+impl SigFormat {
+    /// Get the signing algorithm identifier in `PayloadSignHeader` for ECDSA_NIST_P384.
+    pub fn signing_algorithm(&self) -> u32 {
+        match self {
+            SigFormat::Fixed => PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            SigFormat::Asn1 => PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+        }
+    }
+
+    fn from_signing_algorithm(signing_algorithm: u32) -> Option<Self> {
+        match signing_algorithm {
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => Some(SigFormat::Fixed),
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => Some(SigFormat::Asn1),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for SigFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(SigFormat::Fixed),
+            "asn1" => Ok(SigFormat::Asn1),
+            _ => {
+                error!("Unsupported ECDSA signature format: {}", s);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unsupported ECDSA signature format",
+                ))
+            }
+        }
+    }
+}
+
+/// Elliptic curve of an ECDSA signature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Curve {
+    NistP384,
+}
+
+impl Curve {
+    /// Get size of R and S in the fixed encoding.
+    pub fn scalar_len(&self) -> usize {
+        match self {
+            Curve::NistP384 => 48,
+        }
+    }
+}
+
+fn invalid_signature(reason: &str) -> io::Error {
+    error!("Invalid ECDSA signature: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, "invalid ECDSA signature")
+}
+
+// Parse a DER INTEGER at the start of `data`, return the unsigned big-endian value without leading
+// zeros and the size of the encoding.
+fn parse_asn1_integer(data: &[u8], scalar_len: usize) -> io::Result<(&[u8], usize)> {
+    if data.len() < 2 || data[0] != ASN1_TAG_INTEGER {
+        return Err(invalid_signature("INTEGER expected"));
+    }
+    let len = data[1] as usize;
+    if len == 0 || len >= 0x80 || data.len() < 2 + len {
+        return Err(invalid_signature("bad INTEGER length"));
+    }
+    let value = &data[2..2 + len];
+    if value[0] & 0x80 != 0 {
+        return Err(invalid_signature("negative INTEGER"));
+    }
+    if len > 1 && value[0] == 0 && value[1] & 0x80 == 0 {
+        return Err(invalid_signature("INTEGER is not minimally encoded"));
+    }
+
+    let value = &value[value.iter().take_while(|v| **v == 0).count()..];
+    if value.is_empty() || value.len() > scalar_len {
+        return Err(invalid_signature("INTEGER out of range"));
+    }
+
+    Ok((value, 2 + len))
+}
+
+fn encode_asn1_integer(value: &[u8], output: &mut Vec<u8>) {
+    let value = &value[value.iter().take_while(|v| **v == 0).count()..];
+    // Keep the INTEGER positive.
+    let pad = value.first().map(|v| v & 0x80 != 0).unwrap_or(true);
+    output.push(ASN1_TAG_INTEGER);
+    output.push((value.len() + pad as usize) as u8);
+    if pad {
+        output.push(0);
+    }
+    output.extend_from_slice(value);
+}
+
+/// Get size of the DER encoded signature at the start of `data`, which may be followed by padding.
+pub fn asn1_signature_len(data: &[u8]) -> io::Result<usize> {
+    if data.len() < 2 || data[0] != ASN1_TAG_SEQUENCE || data[1] >= 0x80 {
+        return Err(invalid_signature("SEQUENCE expected"));
+    }
+    let len = 2 + data[1] as usize;
+    if len > data.len() {
+        return Err(invalid_signature("truncated SEQUENCE"));
+    }
+
+    Ok(len)
+}
+
+/// Convert the ECDSA signature `sig` from encoding `from` to encoding `to`.
+pub fn convert_ecdsa_signature(
+    sig: &[u8],
+    from: SigFormat,
+    to: SigFormat,
+    curve: Curve,
+) -> io::Result<Vec<u8>> {
+    let scalar_len = curve.scalar_len();
+    let (r, s) = match from {
+        SigFormat::Fixed => {
+            if sig.len() != 2 * scalar_len {
+                return Err(invalid_signature("bad R||S length"));
+            }
+            sig.split_at(scalar_len)
+        }
+        SigFormat::Asn1 => {
+            if asn1_signature_len(sig)? != sig.len() {
+                return Err(invalid_signature("trailing data after SEQUENCE"));
+            }
+            let (r, r_len) = parse_asn1_integer(&sig[2..], scalar_len)?;
+            let (s, s_len) = parse_asn1_integer(&sig[2 + r_len..], scalar_len)?;
+            if 2 + r_len + s_len != sig.len() {
+                return Err(invalid_signature("bad SEQUENCE length"));
+            }
+            (r, s)
+        }
+    };
+
+    let mut output = Vec::with_capacity(2 * scalar_len + 8);
+    match to {
+        SigFormat::Fixed => {
+            for v in [r, s] {
+                let v = &v[v.iter().take_while(|v| **v == 0).count()..];
+                if v.len() > scalar_len {
+                    return Err(invalid_signature("scalar out of range"));
+                }
+                output.resize(output.len() + scalar_len - v.len(), 0);
+                output.extend_from_slice(v);
+            }
+        }
+        SigFormat::Asn1 => {
+            let mut integers = Vec::with_capacity(2 * scalar_len + 6);
+            encode_asn1_integer(r, &mut integers);
+            encode_asn1_integer(s, &mut integers);
+            output.push(ASN1_TAG_SEQUENCE);
+            output.push(integers.len() as u8);
+            output.extend_from_slice(&integers);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Location of an ECDSA signature block inside a signed payload.
+struct EcdsaBlock {
+    // Offset of the `AdditionalSignatureHeader`, `None` for the primary signature.
+    header_offset: Option<usize>,
+    offset: usize,
+    size: usize,
+    format: SigFormat,
+}
+
+fn find_signature_block(
+    signed_payload: &[u8],
+    header: &PayloadSignHeader,
+    slot: usize,
+) -> io::Result<EcdsaBlock> {
+    let mut header_offset = None;
+    let mut offset = header.length as usize;
+    let mut signing_algorithm = header.signing_algorithm;
+
+    for idx in 0..=slot {
+        let size = signature_block_size(signing_algorithm).ok_or_else(|| {
+            error!(
+                "Unknown signing algorithm {:#x} of signature {}",
+                signing_algorithm, idx
+            );
+            io::Error::new(io::ErrorKind::InvalidData, "unknown signing algorithm")
+        })?;
+        if offset + size > signed_payload.len() {
+            error!("Signature {} is truncated", idx);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated signature block",
+            ));
+        }
+        if idx == slot {
+            let format = SigFormat::from_signing_algorithm(signing_algorithm).ok_or_else(|| {
+                error!("Signature {} is not an ECDSA_NIST_P384 signature", slot);
+                io::Error::new(io::ErrorKind::InvalidInput, "not an ECDSA signature")
+            })?;
+            return Ok(EcdsaBlock {
+                header_offset,
+                offset,
+                size,
+                format,
+            });
+        }
+
+        // Move to the next additional signature.
+        offset += size;
+        let hdr = signed_payload
+            .get(offset..)
+            .and_then(|v| {
+                v.pread_with::<AdditionalSignatureHeader>(0, header.endian())
+                    .ok()
+            })
+            .ok_or_else(|| {
+                error!("Signature {} is not found", slot);
+                io::Error::new(io::ErrorKind::NotFound, "signature not found")
+            })?;
+        header_offset = Some(offset);
+        offset += std::mem::size_of::<AdditionalSignatureHeader>();
+        signing_algorithm = hdr.signing_algorithm;
+    }
+
+    unreachable!()
+}
+
+/// Convert the ECDSA signature in `slot` of a signed payload to encoding `to`, without the
+/// private key.
+///
+/// The signing algorithm of the primary signature (slot 0) is recorded in the signed header, so
+/// the converted primary signature is appended as an additional signature instead. Additional
+/// signatures are converted in place and their signing algorithm is updated. The converted
+/// signature is verified against the public key embedded in the signature block.
+pub fn convert_payload_signature(
+    signed_payload: &[u8],
+    slot: usize,
+    to: SigFormat,
+) -> io::Result<Vec<u8>> {
+    let header = PayloadSignHeader::from_bytes(signed_payload)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .ok_or_else(|| {
+            error!("Invalid signed payload header");
+            io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload header")
+        })?;
+    let length = header.length as usize;
+    if length <= header.header_size() || length > signed_payload.len() {
+        error!("Invalid length of signed payload: {}", length);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid length of signed payload",
+        ));
+    }
+
+    let block = find_signature_block(signed_payload, &header, slot)?;
+    if block.format == to {
+        error!("Signature {} is already in {:?} encoding", slot, to);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "signature encoding is not changed",
+        ));
+    }
+
+    let data = &signed_payload[block.offset..block.offset + block.size];
+    let (public_key, sig) = data.split_at(PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE);
+    let sig = match block.format {
+        SigFormat::Fixed => sig,
+        SigFormat::Asn1 => &sig[..asn1_signature_len(sig)?],
+    };
+    let converted = convert_ecdsa_signature(sig, block.format, to, Curve::NistP384)?;
+
+    // Make sure the converted signature still validates before writing anything.
+    let verify_alg: &'static dyn signature::VerificationAlgorithm = match to {
+        SigFormat::Fixed => &signature::ECDSA_P384_SHA384_FIXED,
+        SigFormat::Asn1 => &signature::ECDSA_P384_SHA384_ASN1,
+    };
+    let mut formated_public_key = vec![0x04];
+    formated_public_key.extend_from_slice(public_key);
+    UnparsedPublicKey::new(verify_alg, &formated_public_key)
+        .verify(&signed_payload[..length], &converted)
+        .map_err(|_| {
+            error!("Converted signature {} fails to verify", slot);
+            io::Error::new(io::ErrorKind::InvalidData, "converted signature is invalid")
+        })?;
+
+    let mut new_block = public_key.to_vec();
+    new_block.extend_from_slice(&converted);
+    if to == SigFormat::Asn1 {
+        new_block.resize(
+            PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE
+                + PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE,
+            0,
+        );
+    }
+    let mut additional_header = [0u8; std::mem::size_of::<AdditionalSignatureHeader>()];
+    // Safe to unwrap() because the buffer is big enough.
+    additional_header
+        .pwrite_with(
+            AdditionalSignatureHeader {
+                signing_algorithm: to.signing_algorithm(),
+                reserved: 0,
+            },
+            0,
+            header.endian(),
+        )
+        .unwrap();
+
+    let mut output = Vec::with_capacity(signed_payload.len() + new_block.len() + 8);
+    match block.header_offset {
+        None => {
+            output.extend_from_slice(signed_payload);
+            output.extend_from_slice(&additional_header);
+            output.extend_from_slice(&new_block);
+        }
+        Some(header_offset) => {
+            output.extend_from_slice(&signed_payload[..header_offset]);
+            output.extend_from_slice(&additional_header);
+            output.extend_from_slice(&new_block);
+            output.extend_from_slice(&signed_payload[block.offset + block.size..]);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Byte used to pad ASN.1 signatures to the size of the signature block in a normalized signed
+/// payload.
+pub const NORMALIZED_PADDING_BYTE: u8 = 0;
+
+/// Rewrite the bytes of a signed payload which are not covered by the signatures in the canonical
+/// form: the padding after ASN.1 signatures is set to `NORMALIZED_PADDING_BYTE`, and the reserved
+/// field of additional signature headers is zeroed. The signatures stay valid.
+pub fn normalize_signatures(signed_payload: &mut [u8]) -> io::Result<()> {
+    let header = PayloadSignHeader::from_bytes(signed_payload)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .ok_or_else(|| {
+            error!("Invalid signed payload header");
+            io::Error::new(io::ErrorKind::InvalidData, "invalid signed payload header")
+        })?;
+    let mut offset = header.length as usize;
+    let mut signing_algorithm = header.signing_algorithm;
+
+    while offset < signed_payload.len() {
+        let size = signature_block_size(signing_algorithm).ok_or_else(|| {
+            error!("Unknown signing algorithm {:#x}", signing_algorithm);
+            io::Error::new(io::ErrorKind::InvalidData, "unknown signing algorithm")
+        })?;
+        let block = offset
+            .checked_add(size)
+            .and_then(|end| signed_payload.get_mut(offset..end))
+            .ok_or_else(|| {
+                error!("Signature block at {:#x} is truncated", offset);
+                io::Error::new(io::ErrorKind::InvalidData, "truncated signature block")
+            })?;
+        if signing_algorithm == PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 {
+            let sig = &mut block[PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE..];
+            let len = asn1_signature_len(sig)?;
+            sig[len..].fill(NORMALIZED_PADDING_BYTE);
+        }
+        offset += size;
+        if offset == signed_payload.len() {
+            break;
+        }
+
+        let hdr = signed_payload
+            .pread_with::<AdditionalSignatureHeader>(offset, header.endian())
+            .map_err(|_| {
+                error!("Additional signature header at {:#x} is truncated", offset);
+                io::Error::new(io::ErrorKind::InvalidData, "truncated signature header")
+            })?;
+        signing_algorithm = hdr.signing_algorithm;
+        // Safe to unwrap() because the header has been read from the same place.
+        signed_payload
+            .pwrite_with(
+                AdditionalSignatureHeader {
+                    signing_algorithm,
+                    reserved: 0,
+                },
+                offset,
+                header.endian(),
+            )
+            .unwrap();
+        offset += std::mem::size_of::<AdditionalSignatureHeader>();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signer::{EcdsaP384KeyPair, PayloadSigner, SigningAlgorithm};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P384_SHA384_ASN1_SIGNING};
+
+    const ECDSA_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+
+    #[test]
+    fn test_convert_ecdsa_signature() {
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, ECDSA_KEY).unwrap();
+        let public_key = key.public_key().as_ref().to_vec();
+        let rng = SystemRandom::new();
+
+        for idx in 0..8u8 {
+            let message = [idx; 32];
+            let asn1 = key.sign(&rng, &message).unwrap();
+            let fixed = convert_ecdsa_signature(
+                asn1.as_ref(),
+                SigFormat::Asn1,
+                SigFormat::Fixed,
+                Curve::NistP384,
+            )
+            .unwrap();
+            assert_eq!(fixed.len(), 96);
+            UnparsedPublicKey::new(&signature::ECDSA_P384_SHA384_FIXED, &public_key)
+                .verify(&message, &fixed)
+                .unwrap();
+
+            let back =
+                convert_ecdsa_signature(&fixed, SigFormat::Fixed, SigFormat::Asn1, Curve::NistP384)
+                    .unwrap();
+            assert_eq!(back, asn1.as_ref());
+        }
+
+        // R with the top bit set needs a zero pad byte, S with leading zeros is shortened.
+        let mut fixed = vec![0u8; 96];
+        fixed[0] = 0x80;
+        fixed[47] = 0x01;
+        fixed[95] = 0x7f;
+        let asn1 =
+            convert_ecdsa_signature(&fixed, SigFormat::Fixed, SigFormat::Asn1, Curve::NistP384)
+                .unwrap();
+        assert_eq!(&asn1[..5], &[0x30, 49 + 2 + 1 + 2, 0x02, 49, 0x00]);
+        assert_eq!(&asn1[asn1.len() - 3..], &[0x02, 0x01, 0x7f]);
+        assert_eq!(
+            convert_ecdsa_signature(&asn1, SigFormat::Asn1, SigFormat::Fixed, Curve::NistP384)
+                .unwrap(),
+            fixed
+        );
+
+        // Malformed signatures are rejected.
+        assert!(convert_ecdsa_signature(
+            &fixed[..95],
+            SigFormat::Fixed,
+            SigFormat::Asn1,
+            Curve::NistP384
+        )
+        .is_err());
+        let mut bad = asn1.clone();
+        bad[1] += 1;
+        assert!(
+            convert_ecdsa_signature(&bad, SigFormat::Asn1, SigFormat::Fixed, Curve::NistP384)
+                .is_err()
+        );
+        let mut bad = asn1.clone();
+        bad[4] = 0x80;
+        assert!(
+            convert_ecdsa_signature(&bad, SigFormat::Asn1, SigFormat::Fixed, Curve::NistP384)
+                .is_err()
+        );
+        assert!(convert_ecdsa_signature(
+            &[0x30, 0],
+            SigFormat::Asn1,
+            SigFormat::Fixed,
+            Curve::NistP384
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_convert_payload_signature() {
+        let key = EcdsaP384KeyPair::from_pkcs8(ECDSA_KEY).unwrap();
+        let payload = [0x5au8; 64];
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        // The primary signature is appended as an ASN.1 additional signature.
+        let converted = convert_payload_signature(&signed, 0, SigFormat::Asn1).unwrap();
+        assert_eq!(&converted[..signed.len()], &signed[..]);
+        assert_eq!(
+            converted.len(),
+            signed.len() + 8 + PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE + 104
+        );
+        assert_eq!(
+            &converted[signed.len()..signed.len() + 4],
+            &PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1.to_le_bytes()
+        );
+
+        // Converting the additional signature back restores the fixed encoding in place.
+        let back = convert_payload_signature(&converted, 1, SigFormat::Fixed).unwrap();
+        let primary = &signed[signed.len() - 192..];
+        assert_eq!(back.len(), signed.len() + 8 + 192);
+        assert_eq!(
+            &back[signed.len()..signed.len() + 4],
+            &PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384.to_le_bytes()
+        );
+        assert_eq!(&back[signed.len() + 8..], primary);
+
+        assert!(convert_payload_signature(&signed, 0, SigFormat::Fixed).is_err());
+        assert!(convert_payload_signature(&signed, 1, SigFormat::Asn1).is_err());
+
+        // A tampered payload fails to verify after conversion.
+        let mut tampered = signed.clone();
+        tampered[100] ^= 0xff;
+        assert!(convert_payload_signature(&tampered, 0, SigFormat::Asn1).is_err());
+    }
+
+    #[test]
+    fn test_normalize_signatures() {
+        let key = EcdsaP384KeyPair::from_pkcs8(ECDSA_KEY).unwrap();
+        let payload = [0x5au8; 64];
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let converted = convert_payload_signature(&signed, 0, SigFormat::Asn1).unwrap();
+
+        // Another tool may fill the unsigned bytes differently.
+        let mut other = converted.clone();
+        other[signed.len() + 4..signed.len() + 8].fill(0x1);
+        let sig_start = signed.len() + 8 + PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE;
+        let sig_len = asn1_signature_len(&other[sig_start..]).unwrap();
+        other[sig_start + sig_len..].fill(0xff);
+        normalize_signatures(&mut other).unwrap();
+        assert_eq!(other, converted);
+
+        let mut normalized = signed.clone();
+        normalize_signatures(&mut normalized).unwrap();
+        assert_eq!(normalized, signed);
+        let mut truncated = converted[..converted.len() - 1].to_vec();
+        assert!(normalize_signatures(&mut truncated).is_err());
+    }
+}
@@ -2,61 +2,795 @@
 // Copyright (c) 2022 Alibaba Cloud
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
-use std::io;
-use std::mem::size_of;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::vec::Vec;
 
-use log::error;
-use ring::rand;
-use ring::signature::{EcdsaKeyPair, KeyPair, RsaKeyPair, RSA_PSS_SHA384};
+use log::{error, warn};
+use p384::ecdsa::signature::RandomizedSigner;
+use p384::pkcs8::DecodePrivateKey;
+use ring::digest;
+use ring::rand::{self, SecureRandom};
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, RsaEncoding, RsaKeyPair, ECDSA_P384_SHA384_FIXED_SIGNING,
+    RSA_PSS_SHA256, RSA_PSS_SHA384, RSA_PSS_SHA512,
+};
+use scroll::Endian;
+use td_layout::build_time::TD_SHIM_PAYLOAD_SIZE;
+use td_loader::{elf, pe};
 use td_shim::secure_boot::{
-    PayloadSignHeader, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_RSA_EXPONENT_SIZE,
-    PAYLOAD_SIGN_RSA_PSS_3072_SHA384, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+    signing_algorithm_name, supported_digests, CompactPayloadVerifier, CompactSignatureFooter,
+    HashAlg, PayloadSignHeader, PayloadVerifier, SignedPayloadFormat, SignedPayloadType, SvnPolicy,
+    COMPACT_SIGNATURE_FOOTER_SIZE, PAYLOAD_LOAD_BASE_ALIGNMENT, PAYLOAD_LOAD_BASE_ANY,
+    PAYLOAD_MAX_RTMR_INDEX, PAYLOAD_MR_INDEX_DEFAULT, PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE, PAYLOAD_SIGN_FLAG_BIG_ENDIAN,
+    PAYLOAD_SIGN_HEADER_VERSION_V1, PAYLOAD_SIGN_HEADER_VERSION_V2, PAYLOAD_SIGN_RSA_EXPONENT_SIZE,
+    PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE, PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+    PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE, SIGNED_PAYLOAD_FILE_HEADER_GUID,
 };
 
-/// Type of public key.
-pub enum SigningAlgorithm {
-    Rsapss3072Sha384(RsaKeyPair),
-    EcdsaNistP384Sha384(EcdsaKeyPair),
+use crate::signature::normalize_signatures;
+
+/// Check whether the payload looks like a PE or ELF image accepted by the td-shim loader.
+pub fn is_executable_payload(payload: &[u8]) -> bool {
+    pe::is_x86_64_pe(payload) || elf::is_elf(payload)
 }
 
-/// Utility structure to sign shim payload.
+/// Verify the checksum in the PE optional header of the payload.
 ///
-/// Secure boot in td-shim means the td-shim will verify the digital signature of the payload,
-/// based upon a trusted anchor. The payload includes the digital signature and the public key.
-/// The td-shim includes a trust anchor - hash of public key.
+/// A zero checksum means the checksum is not set and is accepted.
+pub fn check_pe_checksum(payload: &[u8]) -> io::Result<()> {
+    let (stored, computed) = match (pe::checksum(payload), pe::compute_checksum(payload)) {
+        (Some(stored), Some(computed)) => (stored, computed),
+        _ => {
+            error!("Payload is not a PE image with a checksum field");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "payload is not a PE image",
+            ));
+        }
+    };
+
+    if stored == 0 {
+        warn!("PE checksum of payload is not set");
+    } else if stored != computed {
+        error!(
+            "PE checksum mismatch: stored 0x{:x}, computed 0x{:x}",
+            stored, computed
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "PE checksum mismatch",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Recompute and update the checksum in the PE optional header of the payload.
 ///
-/// Please refer to section "Trust Anchor in Td-Shim" in doc/secure_boot.md for definitions.
-pub struct PayloadSigner<'a> {
-    algorithm: SigningAlgorithm,
-    raw_image: &'a [u8],
-    signed_image: Vec<u8>,
+/// Return the new checksum.
+pub fn fix_pe_checksum(payload: &mut [u8]) -> io::Result<u32> {
+    pe::update_checksum(payload).ok_or_else(|| {
+        error!("Payload is not a PE image with a checksum field");
+        io::Error::new(io::ErrorKind::InvalidInput, "payload is not a PE image")
+    })
 }
 
-impl<'a> PayloadSigner<'a> {
-    /// Create a new instance of `PayloadSigner`.
-    pub fn new(raw_image: &'a [u8], algorithm: SigningAlgorithm) -> Self {
-        PayloadSigner {
-            raw_image,
-            algorithm,
-            signed_image: Vec::new(),
+fn invalid_signed_payload(reason: &str) -> io::Error {
+    error!("Invalid signed payload: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+fn parse_signed_payload(signed_payload: &[u8]) -> io::Result<PayloadSignHeader> {
+    let header = PayloadSignHeader::from_bytes(signed_payload)
+        .filter(|h| &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes())
+        .ok_or_else(|| invalid_signed_payload("invalid verify header"))?;
+    if header.length as usize <= header.header_size()
+        || header.length as usize > signed_payload.len()
+    {
+        return Err(invalid_signed_payload("invalid length of signed region"));
+    }
+    Ok(header)
+}
+
+/// Get the range of the original payload in a signed payload, without the verify header, the SVN
+/// policy and the signatures, so it could be signed again.
+pub fn unsigned_payload_range(signed_payload: &[u8]) -> io::Result<Range<usize>> {
+    if SignedPayloadFormat::detect(signed_payload) == Some(SignedPayloadFormat::Compact) {
+        let verifier = CompactPayloadVerifier::new(signed_payload, &[])
+            .map_err(|_| invalid_signed_payload("invalid compact signature footer"))?;
+        return Ok(0..verifier.get_payload_image().len());
+    }
+    parse_signed_payload(signed_payload)?;
+    let image = PayloadVerifier::get_payload_image(signed_payload)
+        .map_err(|_| invalid_signed_payload("invalid SVN policy"))?;
+    let start = image.as_ptr() as usize - signed_payload.as_ptr() as usize;
+
+    Ok(start..start + image.len())
+}
+
+/// Get the original payload of a signed payload, the inverse of signing. No key is needed.
+pub fn extract_payload(signed_payload: &[u8]) -> io::Result<&[u8]> {
+    let range = unsigned_payload_range(signed_payload)?;
+    Ok(&signed_payload[range])
+}
+
+/// Pad `signed_payload` with zeroes up to `size` bytes, such as the size of the payload region.
+///
+/// The padding is deterministic and excluded from the measurement: td-shim measures the payload
+/// image, which ends at the `length` declared by the verify header, and the verifiers ignore zero
+/// bytes after the last signature. Signed payloads in the compact format end with their signature
+/// block, so they can't be padded.
+pub fn pad_signed_payload(signed_payload: &mut Vec<u8>, size: usize) -> io::Result<()> {
+    if SignedPayloadFormat::detect(signed_payload) != Some(SignedPayloadFormat::Standard) {
+        error!("Only signed payloads with a verify header can be padded");
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "signed payload can't be padded",
+        ));
+    }
+    if signed_payload.len() > size {
+        error!(
+            "Signed payload of {} bytes exceeds the padded size of {} bytes",
+            signed_payload.len(),
+            size
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "signed payload exceeds the padded size",
+        ));
+    }
+    signed_payload.resize(size, 0);
+
+    Ok(())
+}
+
+/// Rewrite the payload version and SVN in the verify header of `signed_payload`, and sign it again
+/// with `algorithm`. The digest, the byte order, the payload type, the load base, the RTMR index
+/// and the SVN policy of the verify header are kept.
+///
+/// The verify header is covered by the signature, editing it without signing again invalidates
+/// the signature and td-shim refuses to boot the payload. So the edit is refused if the key is
+/// absent, there is intentionally no way to edit the header in place.
+pub fn edit_header(
+    signed_payload: &[u8],
+    payload_version: Option<u64>,
+    payload_svn: Option<u64>,
+    algorithm: Option<SigningAlgorithm>,
+) -> io::Result<Vec<u8>> {
+    let header = parse_signed_payload(signed_payload)?;
+    let algorithm = algorithm.ok_or_else(|| {
+        error!(
+            "Editing the verify header invalidates the signature of the payload, \
+             use --resign with the signing key to sign the edited payload again"
+        );
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "editing the verify header requires re-signing",
+        )
+    })?;
+    let policy = PayloadVerifier::new(signed_payload, &[])
+        .map_err(|_| invalid_signed_payload("invalid signature block"))?
+        .get_svn_policy()
+        .cloned();
+    let payload = extract_payload(signed_payload)?;
+
+    let digest = header
+        .digest()
+        .map_err(|_| invalid_signed_payload("unsupported digest"))?;
+
+    let mut signer = PayloadSigner::new(payload, algorithm);
+    signer
+        .set_digest(digest)?
+        .set_endian(header.endian())
+        .set_payload_type(header.payload_type());
+    if let Some(load_base) = header.load_base() {
+        signer.set_load_base(load_base)?;
+    }
+    if let Some(rtmr_index) = header.rtmr_index() {
+        signer.set_rtmr_index(rtmr_index)?;
+    }
+    if let Some(policy) = policy {
+        signer.set_svn_policy(policy);
+    }
+    let header = signer.build_header(
+        payload_version.unwrap_or(header.payload_version),
+        payload_svn.unwrap_or(header.payload_svn),
+    );
+    signer.sign(header).map(|v| v.to_vec())
+}
+
+/// Parse a payload type given by its name, such as `linux-kernel`, or by its numeric id, such as
+/// `0x80` for the first custom type.
+///
+/// Ids which are neither defined nor in the custom range are rejected, so they are never written
+/// into a verify header.
+pub fn parse_payload_type(s: &str) -> io::Result<SignedPayloadType> {
+    let payload_type = match SignedPayloadType::from_name(s) {
+        Some(v) => Some(v),
+        None => match s.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u8>().ok(),
+        }
+        .map(SignedPayloadType::from_id),
+    };
+
+    match payload_type {
+        Some(SignedPayloadType::Unknown(_)) | None => {
+            error!(
+                "Invalid payload type {}, expect one of unspecified, executable, linux-kernel, \
+                 migration-td, multi-component or a custom id from 0x80 to 0xff",
+                s
+            );
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid payload type",
+            ))
         }
+        Some(v) => Ok(v),
     }
+}
 
-    /// Sign the payload with given header.
-    pub fn sign(&mut self, header: PayloadSignHeader) -> io::Result<&[u8]> {
-        let rng = rand::SystemRandom::new();
+/// Parse a load base, in hex with the `0x` prefix or in decimal.
+pub fn parse_load_base(s: &str) -> io::Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+    .ok_or_else(|| {
+        error!("Invalid load base {}", s);
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid load base")
+    })
+}
 
-        self.signed_image = header.as_bytes().to_vec();
-        self.signed_image.extend_from_slice(self.raw_image);
+/// Check that RTMR[`rtmr_index`] exists and may be requested for the payload measurement.
+pub fn check_rtmr_index(rtmr_index: u32) -> io::Result<()> {
+    if rtmr_index > PAYLOAD_MAX_RTMR_INDEX {
+        error!(
+            "RTMR index {} is out of range, expect 0 to {}",
+            rtmr_index, PAYLOAD_MAX_RTMR_INDEX
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid RTMR index",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Get the public key of the primary signature embedded in a signed payload.
+pub fn embedded_public_key(signed_payload: &[u8]) -> io::Result<&[u8]> {
+    let header = parse_signed_payload(signed_payload)?;
+    let size = match header.signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 | PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE
+        }
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE,
+        _ => return Err(invalid_signed_payload("unknown signing algorithm")),
+    };
+
+    let offset = header.length as usize;
+    signed_payload
+        .get(offset..offset + size)
+        .ok_or_else(|| invalid_signed_payload("truncated signature block"))
+}
+
+/// Check that `algorithm` doesn't hold the key which signed `signed_payload`, to catch re-signing
+/// with the old key when rotating keys.
+pub fn check_key_change(algorithm: &SigningAlgorithm, signed_payload: &[u8]) -> io::Result<()> {
+    let old_key = embedded_public_key(signed_payload)?;
+    if algorithm.public_key_bytes() == old_key {
+        error!(
+            "The signing key is the one which signed the payload, public key SHA384: {}",
+            hex::encode(digest::digest(&digest::SHA384, old_key))
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "signing key is not changed",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Minimal RSA modulus size in bits required by the payload signing policy.
+pub const MIN_RSA_MODULUS_BITS: usize = 3072;
+
+/// Number of random bytes mixed into the RFC 6979 ECDSA P-384 nonce, the size of a field element.
+const ECDSA_P384_NONCE_ENTROPY_SIZE: usize = 48;
+
+/// Source of random numbers used to generate the ECDSA nonce or the RSA-PSS salt.
+///
+/// Ring's `SystemRandom` is used by default. `File` reads the entropy from a file or a device node,
+/// for example a FIPS validated DRBG exported by the platform, and is only supported with
+/// `ECDSA_NIST_P384_SHA384`: the nonce is derived as in RFC 6979 from the private key and the
+/// message, with the entropy mixed in as additional data. The same key, message and entropy always
+/// produce the same signature, and reusing the entropy for different messages doesn't reuse the
+/// nonce.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RngSource {
+    System,
+    File(PathBuf),
+}
+
+impl Default for RngSource {
+    fn default() -> Self {
+        RngSource::System
+    }
+}
+
+impl FromStr for RngSource {
+    type Err = String;
+
+    /// Parse RNG source in form of `system` or `file:PATH`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "system" {
+            Ok(RngSource::System)
+        } else if let Some(path) = s.strip_prefix("file:") {
+            if path.is_empty() {
+                Err("missing path for RNG source 'file:'".to_string())
+            } else {
+                Ok(RngSource::File(PathBuf::from(path)))
+            }
+        } else {
+            Err(format!(
+                "invalid RNG source '{}', expect 'system' or 'file:PATH'",
+                s
+            ))
+        }
+    }
+}
+
+/// Serve the entropy read by `read_entropy()` to a RustCrypto signer.
+///
+/// `RngCore::fill_bytes()` can't fail, so a request beyond the entropy is filled with zeros and
+/// recorded in `exhausted`, the signature must then be discarded.
+struct EntropyRng<'a> {
+    bytes: &'a [u8],
+    exhausted: bool,
+}
+
+impl<'a> EntropyRng<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        EntropyRng {
+            bytes,
+            exhausted: false,
+        }
+    }
+}
+
+impl rand_core::RngCore for EntropyRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let size = dest.len().min(self.bytes.len());
+        dest[..size].copy_from_slice(&self.bytes[..size]);
+        dest[size..].fill(0);
+        self.bytes = &self.bytes[size..];
+        self.exhausted |= size < dest.len();
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for EntropyRng<'_> {}
+
+fn read_entropy(path: &PathBuf, size: usize) -> io::Result<Vec<u8>> {
+    let mut entropy = vec![0u8; size];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut entropy))
+        .map_err(|e| {
+            error!(
+                "Can not read {} bytes of entropy from {}: {}",
+                size,
+                path.display(),
+                e
+            );
+            e
+        })?;
+
+    Ok(entropy)
+}
+
+/// Signing backends selected by the scheme of the private key, which the tool doesn't implement.
+const UNAVAILABLE_KEY_BACKENDS: [(&str, &str); 2] = [("pkcs11:", "hsm"), ("piv:", "piv")];
+
+/// Signing backends selected by the signing algorithm, which the tool doesn't implement.
+const UNAVAILABLE_ALGORITHM_BACKENDS: [(&str, &str); 1] = [("ED25519", "ed25519")];
+
+fn backend_unsupported(backend: &str, requested: &str) -> io::Error {
+    error!(
+        "Signing backend {} required by {} is not supported, only local PKCS#8 key files are supported",
+        backend, requested
+    );
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "signing backend is not supported",
+    )
+}
+
+/// Check that the backend needed to sign with the private key `key` is supported.
+///
+/// Keys are read from local PKCS#8 files, keys referring to a hardware token such as a
+/// `pkcs11:` URI are rejected with `io::ErrorKind::Unsupported`.
+pub fn check_key_backend(key: &str) -> io::Result<()> {
+    match UNAVAILABLE_KEY_BACKENDS
+        .iter()
+        .find(|(scheme, _)| key.starts_with(scheme))
+    {
+        Some((_, backend)) => Err(backend_unsupported(backend, key)),
+        None => Ok(()),
+    }
+}
+
+/// Check that the backend needed to sign with the signing algorithm `algorithm` is supported.
+pub fn check_algorithm_backend(algorithm: &str) -> io::Result<()> {
+    match UNAVAILABLE_ALGORITHM_BACKENDS
+        .iter()
+        .find(|(name, _)| *name == algorithm)
+    {
+        Some((_, backend)) => Err(backend_unsupported(backend, algorithm)),
+        None => Ok(()),
+    }
+}
 
-        match &self.algorithm {
+/// ECDSA NIST P-384 private key.
+///
+/// `ring` always mixes its own `SystemRandom` into the nonce, so the key is also kept as a `p384`
+/// secret key to sign with the entropy read from `RngSource::File`.
+pub struct EcdsaP384KeyPair {
+    key_pair: EcdsaKeyPair,
+    secret: p384::SecretKey,
+}
+
+impl EcdsaP384KeyPair {
+    /// Load a PKCS#8 encoded ECDSA NIST P-384 private key.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> io::Result<Self> {
+        let load_error = |e: &dyn fmt::Display| {
+            error!("Can not load DSA private key: {}", e);
+            io::Error::new(io::ErrorKind::Other, "Can not load DSA private key")
+        };
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, pkcs8)
+            .map_err(|e| load_error(&e))?;
+        let secret = p384::SecretKey::from_pkcs8_der(pkcs8).map_err(|e| load_error(&e))?;
+        Ok(EcdsaP384KeyPair { key_pair, secret })
+    }
+
+    /// Get the public key in uncompressed SEC1 format.
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    fn sign(&self, rng: &dyn SecureRandom, message: &[u8]) -> io::Result<Vec<u8>> {
+        let signature = self.key_pair.sign(rng, message).map_err(|e| {
+            error!("Failed to sign message with ECDSA: {}", e);
+            io::Error::new(io::ErrorKind::Other, "failed to sign message")
+        })?;
+        Ok(signature.as_ref().to_vec())
+    }
+
+    /// Sign `message` with a hedged RFC 6979 nonce, mixing in `entropy` as additional data.
+    fn sign_with_entropy(&self, entropy: &[u8], message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut rng = EntropyRng::new(entropy);
+        let signature: p384::ecdsa::Signature = p384::ecdsa::SigningKey::from(&self.secret)
+            .try_sign_with_rng(&mut rng, message)
+            .map_err(|e| {
+                error!("Failed to sign message with ECDSA: {}", e);
+                io::Error::new(io::ErrorKind::Other, "failed to sign message")
+            })?;
+        if rng.exhausted {
+            error!("Not enough entropy to sign message with ECDSA");
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "not enough entropy",
+            ));
+        }
+        Ok(signature.as_ref().to_vec())
+    }
+}
+
+/// Get the ECDSA public key stored in the signature block, without the uncompressed format tag.
+fn ecdsa_public_key_bytes(key_pair: &EcdsaP384KeyPair) -> io::Result<&[u8]> {
+    let public_key = key_pair.public_key();
+    // 0x4 -- Uncompressed
+    // 0x0 -- Compressed
+    if public_key[0] != 0x4 {
+        error!("Invalid ECDSA data format: {}", public_key[0],);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid ECDSA data format",
+        ));
+    }
+    Ok(&public_key[1..])
+}
+
+/// Type of public key.
+pub enum SigningAlgorithm {
+    Rsapss3072Sha384(RsaKeyPair),
+    EcdsaNistP384Sha384(EcdsaP384KeyPair),
+}
+
+impl SigningAlgorithm {
+    /// Load a PKCS#8 private key for the signing algorithm named `algorithm`.
+    ///
+    /// Fail with a dedicated error if the key is valid but belongs to the other algorithm.
+    pub fn from_pkcs8(algorithm: &str, pkcs8: &[u8]) -> io::Result<Self> {
+        let is_rsa = RsaKeyPair::from_pkcs8(pkcs8).is_ok();
+        let is_ecdsa = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, pkcs8).is_ok();
+        let mismatch = |key_type: &str| {
+            error!(
+                "Signing algorithm {} doesn't match the {} private key",
+                algorithm, key_type
+            );
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signing algorithm doesn't match private key",
+            )
+        };
+
+        match algorithm {
+            "RSAPSS_3072_SHA384" => {
+                if is_ecdsa {
+                    return Err(mismatch("ECDSA"));
+                }
+                let rsa_key_pair = RsaKeyPair::from_pkcs8(pkcs8).map_err(|e| {
+                    error!("Can not load RSA private key: {}", e);
+                    io::Error::new(io::ErrorKind::Other, "Can not load RSA private key")
+                })?;
+                Ok(SigningAlgorithm::Rsapss3072Sha384(rsa_key_pair))
+            }
+            "ECDSA_NIST_P384_SHA384" => {
+                if is_rsa {
+                    return Err(mismatch("RSA"));
+                }
+                let ecdsa_key_pair = EcdsaP384KeyPair::from_pkcs8(pkcs8)?;
+                Ok(SigningAlgorithm::EcdsaNistP384Sha384(ecdsa_key_pair))
+            }
+            _ => {
+                check_algorithm_backend(algorithm)?;
+                error!("Unsupported signing algorithm: {}", algorithm);
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Unsupported signing algorithm",
+                ))
+            }
+        }
+    }
+
+    /// Get the algorithm identifier recorded in `PayloadSignHeader`.
+    pub fn id(&self) -> u32 {
+        match self {
+            SigningAlgorithm::EcdsaNistP384Sha384(_) => PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            SigningAlgorithm::Rsapss3072Sha384(_) => PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+        }
+    }
+
+    /// Get size of the loaded key in bits.
+    pub fn key_bits(&self) -> usize {
+        match self {
+            SigningAlgorithm::EcdsaNistP384Sha384(_) => 384,
+            SigningAlgorithm::Rsapss3072Sha384(rsa_keypair) => rsa_keypair.public_modulus_len() * 8,
+        }
+    }
+
+    /// Get the maximum size of the signature stored in the signature block.
+    ///
+    /// The size of RSA signatures is read from the loaded key, an RSA key larger than 3072 bits
+    /// needs more space than `PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE`.
+    pub fn max_signature_len(&self) -> usize {
+        match self {
+            SigningAlgorithm::EcdsaNistP384Sha384(_) => PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE,
+            SigningAlgorithm::Rsapss3072Sha384(rsa_keypair) => rsa_keypair.public_modulus_len(),
+        }
+    }
+
+    /// Get the maximum size of the public key stored in the signature block.
+    pub fn max_public_key_len(&self) -> usize {
+        match self {
+            SigningAlgorithm::EcdsaNistP384Sha384(_) => {
+                PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE
+            }
+            SigningAlgorithm::Rsapss3072Sha384(rsa_keypair) => {
+                rsa_keypair.public_modulus_len() + PAYLOAD_SIGN_RSA_EXPONENT_SIZE
+            }
+        }
+    }
+
+    /// Get the public key in the form stored in the signature block and enrolled into the CFV.
+    pub fn public_key_bytes(&self) -> Vec<u8> {
+        let mut public_key = Vec::with_capacity(self.max_public_key_len());
+        match self {
+            SigningAlgorithm::EcdsaNistP384Sha384(ecdsa_keypair) => {
+                // Skip the uncompressed format tag 0x4.
+                public_key.extend_from_slice(&ecdsa_keypair.public_key()[1..]);
+            }
+            SigningAlgorithm::Rsapss3072Sha384(rsa_keypair) => {
+                let exponent = rsa_keypair
+                    .public_key()
+                    .exponent()
+                    .big_endian_without_leading_zero();
+                public_key.extend_from_slice(
+                    rsa_keypair
+                        .public_key()
+                        .modulus()
+                        .big_endian_without_leading_zero(),
+                );
+                public_key.resize(
+                    public_key.len()
+                        + PAYLOAD_SIGN_RSA_EXPONENT_SIZE.saturating_sub(exponent.len()),
+                    0,
+                );
+                public_key.extend_from_slice(exponent);
+            }
+        }
+        public_key
+    }
+
+    /// Get the digests supported by the signing algorithm, the first one is the default.
+    pub fn supported_digests(&self) -> &'static [HashAlg] {
+        supported_digests(self.id())
+    }
+
+    /// Get the default digest of the signing algorithm.
+    pub fn default_digest(&self) -> HashAlg {
+        // All supported algorithms have a default digest.
+        self.supported_digests()[0]
+    }
+
+    /// Check the signing algorithm supports `digest`.
+    pub fn check_digest(&self, digest: HashAlg) -> io::Result<()> {
+        if self.supported_digests().contains(&digest) {
+            return Ok(());
+        }
+        error!(
+            "Digest {} is not supported by signing algorithm {}, supported digests: {}",
+            digest.name(),
+            self,
+            self.supported_digests()
+                .iter()
+                .map(|v| v.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "digest is not supported by signing algorithm",
+        ))
+    }
+
+    /// Get the name of the signatures made with `digest`, the canonical name with the default
+    /// digest replaced by `digest`, e.g. `RSAPSS_3072_SHA256`.
+    pub fn signature_name(&self, digest: HashAlg) -> String {
+        let name = self.to_string();
+        match name.strip_suffix(self.default_digest().name()) {
+            Some(prefix) => format!("{}{}", prefix, digest.name()),
+            None => name,
+        }
+    }
+
+    /// Check the loaded key and the digest of the signed region `digest` against the payload
+    /// signing policy.
+    ///
+    /// Return descriptions of all detected weaknesses, an empty vector means the key is strong
+    /// enough for signing payloads.
+    pub fn check_strength(&self, digest: HashAlg) -> Vec<String> {
+        let mut weaknesses = Vec::new();
+
+        if let SigningAlgorithm::Rsapss3072Sha384(_) = self {
+            if self.key_bits() < MIN_RSA_MODULUS_BITS {
+                weaknesses.push(format!(
+                    "RSA modulus of {} bits is below {} bits",
+                    self.key_bits(),
+                    MIN_RSA_MODULUS_BITS
+                ));
+            }
+        }
+        if digest.output_len() < HashAlg::Sha384.output_len() {
+            weaknesses.push(format!("digest {} is weaker than SHA384", digest.name()));
+        }
+
+        weaknesses
+    }
+
+    /// Log a warning for each weakness of the loaded key signing with `digest`, or fail if `strict`
+    /// is true.
+    pub fn enforce_strength(&self, digest: HashAlg, strict: bool) -> io::Result<()> {
+        let weaknesses = self.check_strength(digest);
+        for weakness in weaknesses.iter() {
+            if strict {
+                error!("Weak signing key: {}", weakness);
+            } else {
+                warn!("Weak signing key: {}", weakness);
+            }
+        }
+
+        if strict && !weaknesses.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signing key is below the signing policy",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sign `message` with random numbers from `rng`, return the signature block stored after the
+    /// signed region, the public key followed by the signature.
+    pub fn sign_block(&self, message: &[u8], rng: &RngSource) -> io::Result<Vec<u8>> {
+        self.sign_block_with_digest(message, self.default_digest(), rng)
+    }
+
+    /// Sign `message` as `sign_block()`, hashed with `digest` instead of the default digest.
+    pub fn sign_block_with_digest(
+        &self,
+        message: &[u8],
+        digest: HashAlg,
+        rng: &RngSource,
+    ) -> io::Result<Vec<u8>> {
+        match rng {
+            RngSource::System => {
+                self.sign_block_with_digest_rng(message, digest, &rand::SystemRandom::new())
+            }
+            RngSource::File(path) => match self {
+                SigningAlgorithm::EcdsaNistP384Sha384(ecdsa_keypair) => {
+                    self.check_digest(digest)?;
+                    let entropy = read_entropy(path, ECDSA_P384_NONCE_ENTROPY_SIZE)?;
+                    let mut block = ecdsa_public_key_bytes(ecdsa_keypair)?.to_vec();
+                    block.extend_from_slice(&ecdsa_keypair.sign_with_entropy(&entropy, message)?);
+                    Ok(block)
+                }
+                SigningAlgorithm::Rsapss3072Sha384(_) => {
+                    error!("RNG source 'file:' is only supported with ECDSA_NIST_P384_SHA384");
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "unsupported RNG source for signing algorithm",
+                    ))
+                }
+            },
+        }
+    }
+
+    /// Sign `message` with given source of random numbers, return the signature block.
+    pub fn sign_block_with_rng(
+        &self,
+        message: &[u8],
+        rng: &dyn SecureRandom,
+    ) -> io::Result<Vec<u8>> {
+        self.sign_block_with_digest_rng(message, self.default_digest(), rng)
+    }
+
+    /// Sign `message` with given digest and source of random numbers, return the signature block.
+    pub fn sign_block_with_digest_rng(
+        &self,
+        message: &[u8],
+        digest: HashAlg,
+        rng: &dyn SecureRandom,
+    ) -> io::Result<Vec<u8>> {
+        self.check_digest(digest)?;
+        let mut block = Vec::with_capacity(self.max_public_key_len() + self.max_signature_len());
+        match self {
             SigningAlgorithm::Rsapss3072Sha384(rsa_keypair) => {
                 let modulus = rsa_keypair
                     .public_key()
                     .modulus()
                     .big_endian_without_leading_zero();
-                if rsa_keypair.public_modulus_len() != 384 {
+                if rsa_keypair.public_modulus_len() != PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE {
                     error!(
                         "Invalid RSA public modulus length: {}",
                         rsa_keypair.public_modulus_len()
@@ -87,61 +821,1342 @@ impl<'a> PayloadSigner<'a> {
                 exp_bytes[PAYLOAD_SIGN_RSA_EXPONENT_SIZE - exponent.len()..]
                     .copy_from_slice(exponent);
 
-                let mut signature: Vec<u8> = vec![0; rsa_keypair.public_modulus_len()];
+                let padding: &'static dyn RsaEncoding = match digest {
+                    HashAlg::Sha256 => &RSA_PSS_SHA256,
+                    HashAlg::Sha384 => &RSA_PSS_SHA384,
+                    HashAlg::Sha512 => &RSA_PSS_SHA512,
+                };
+                let mut signature: Vec<u8> = vec![0; self.max_signature_len()];
                 rsa_keypair
-                    .sign(&RSA_PSS_SHA384, &rng, &self.signed_image, &mut signature)
+                    .sign(padding, rng, message, &mut signature)
                     .map_err(|e| {
                         error!("Failed to sign message with RSA: {}", e);
                         io::Error::new(io::ErrorKind::Other, "failed to sign message")
                     })?;
 
-                self.signed_image.extend_from_slice(&modulus);
-                self.signed_image.extend_from_slice(&exp_bytes);
-                self.signed_image.extend_from_slice(signature.as_slice());
+                block.extend_from_slice(&modulus);
+                block.extend_from_slice(&exp_bytes);
+                block.extend_from_slice(signature.as_slice());
             }
             SigningAlgorithm::EcdsaNistP384Sha384(ecdsa_keypair) => {
-                let public_key = ecdsa_keypair.public_key().as_ref();
-                // 0x4 -- Uncompressed
-                // 0x0 -- Compressed
-                if public_key[0] != 0x4 {
-                    error!("Invalid ECDSA data format: {}", public_key[0],);
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "Invalid ECDSA data format",
-                    ));
-                }
-
-                let signature = ecdsa_keypair
-                    .sign(&rng, self.signed_image.as_slice())
-                    .map_err(|e| {
-                        error!("Failed to sign message with ECDSA: {}", e);
-                        io::Error::new(io::ErrorKind::Other, "failed to sign message")
-                    })?;
+                let public_key = ecdsa_public_key_bytes(ecdsa_keypair)?;
+                let signature = ecdsa_keypair.sign(rng, message)?;
 
-                self.signed_image.extend_from_slice(&public_key[1..]);
-                self.signed_image.extend_from_slice(signature.as_ref());
+                block.extend_from_slice(public_key);
+                block.extend_from_slice(&signature);
             }
         }
 
+        Ok(block)
+    }
+}
+
+impl fmt::Display for SigningAlgorithm {
+    /// Print the canonical name of the algorithm, as accepted by `td-shim-sign-payload -A`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // All supported algorithms have a name.
+        write!(f, "{}", signing_algorithm_name(self.id()).unwrap())
+    }
+}
+
+impl fmt::Debug for SigningAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't dump the key pair.
+        write!(f, "SigningAlgorithm({})", self)
+    }
+}
+
+/// Utility structure to sign shim payload.
+///
+/// Secure boot in td-shim means the td-shim will verify the digital signature of the payload,
+/// based upon a trusted anchor. The payload includes the digital signature and the public key.
+/// The td-shim includes a trust anchor - hash of public key.
+///
+/// Please refer to section "Trust Anchor in Td-Shim" in doc/secure_boot.md for definitions.
+pub struct PayloadSigner<'a> {
+    algorithm: SigningAlgorithm,
+    raw_image: &'a [u8],
+    signed_image: Vec<u8>,
+    endian: Endian,
+    policy: Option<SvnPolicy>,
+    rng: RngSource,
+    normalize: bool,
+    region_size: usize,
+    payload_type: SignedPayloadType,
+    digest: HashAlg,
+    load_base: u64,
+    rtmr_index: Option<u32>,
+}
+
+/// Get the maximum size of a payload signed by `algorithm` in a payload region of `region_size`
+/// bytes, which must also hold the `header_size` bytes of verify header and SVN policy, and the
+/// signature block.
+pub fn max_payload_bytes(
+    algorithm: &SigningAlgorithm,
+    header_size: usize,
+    region_size: usize,
+) -> usize {
+    region_size.saturating_sub(
+        header_size + algorithm.max_public_key_len() + algorithm.max_signature_len(),
+    )
+}
+
+impl<'a> PayloadSigner<'a> {
+    /// Create a new instance of `PayloadSigner`.
+    pub fn new(raw_image: &'a [u8], algorithm: SigningAlgorithm) -> Self {
+        PayloadSigner {
+            raw_image,
+            digest: algorithm.default_digest(),
+            algorithm,
+            signed_image: Vec::new(),
+            endian: Endian::Little,
+            policy: None,
+            rng: RngSource::System,
+            normalize: false,
+            region_size: TD_SHIM_PAYLOAD_SIZE as usize,
+            payload_type: SignedPayloadType::Unspecified,
+            load_base: PAYLOAD_LOAD_BASE_ANY,
+            rtmr_index: None,
+        }
+    }
+
+    /// Consume the signer and give back the signing key, so it could be used to sign another
+    /// payload.
+    pub fn into_algorithm(self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    /// Set byte order of multi-byte fields in the signing header, default to little-endian.
+    pub fn set_endian(&mut self, endian: Endian) -> &mut Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Set source of random numbers used for signing, default to `RngSource::System`.
+    pub fn set_rng(&mut self, rng: RngSource) -> &mut Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Produce the canonical form of the signed payload, see `normalize_header()` and
+    /// `normalize_signatures()`.
+    pub fn set_normalize(&mut self, normalize: bool) -> &mut Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Set size of the payload region the signed payload must fit in, default to
+    /// `TD_SHIM_PAYLOAD_SIZE`.
+    pub fn set_region_size(&mut self, region_size: usize) -> &mut Self {
+        self.region_size = region_size;
+        self
+    }
+
+    /// Set type of the payload recorded in the signing header, default to
+    /// `SignedPayloadType::Unspecified`.
+    pub fn set_payload_type(&mut self, payload_type: SignedPayloadType) -> &mut Self {
+        self.payload_type = payload_type;
+        self
+    }
+
+    /// Set digest of the signed region, default to the default digest of the signing algorithm.
+    ///
+    /// Fail if the signing algorithm pins another digest.
+    pub fn set_digest(&mut self, digest: HashAlg) -> io::Result<&mut Self> {
+        self.algorithm.check_digest(digest)?;
+        self.digest = digest;
+        Ok(self)
+    }
+
+    /// Set load base of the relocatable payload recorded in the signing header, default to
+    /// `PAYLOAD_LOAD_BASE_ANY` which lets the loader choose it.
+    ///
+    /// Fail if the load base is not aligned to `PAYLOAD_LOAD_BASE_ALIGNMENT`.
+    pub fn set_load_base(&mut self, load_base: u64) -> io::Result<&mut Self> {
+        if load_base % PAYLOAD_LOAD_BASE_ALIGNMENT != 0 {
+            error!(
+                "Load base 0x{:x} is not aligned to 0x{:x}",
+                load_base, PAYLOAD_LOAD_BASE_ALIGNMENT
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "load base is not aligned",
+            ));
+        }
+        self.load_base = load_base;
+        Ok(self)
+    }
+
+    /// Set index of the RTMR the payload is measured into recorded in the signing header, default
+    /// to letting td-shim measure it into RTMR[1].
+    ///
+    /// Fail if the index is beyond RTMR[`PAYLOAD_MAX_RTMR_INDEX`].
+    pub fn set_rtmr_index(&mut self, rtmr_index: u32) -> io::Result<&mut Self> {
+        check_rtmr_index(rtmr_index)?;
+        self.rtmr_index = Some(rtmr_index);
+        Ok(self)
+    }
+
+    /// Attach an SVN policy to the signed payload.
+    ///
+    /// The policy is placed right after the payload and is covered by the signature.
+    pub fn set_svn_policy(&mut self, policy: SvnPolicy) -> &mut Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Sign the payload with given header, using the configured source of random numbers.
+    pub fn sign(&mut self, header: PayloadSignHeader) -> io::Result<&[u8]> {
+        let digest = self.build_signed_region(header)?;
+        let signature_block =
+            self.algorithm
+                .sign_block_with_digest(&self.signed_image, digest, &self.rng)?;
+        self.signed_image.extend_from_slice(&signature_block);
+        if self.normalize {
+            normalize_signatures(&mut self.signed_image)?;
+        }
+
+        Ok(self.signed_image.as_slice())
+    }
+
+    /// Sign the payload with given header and source of random numbers.
+    pub fn sign_with_rng(
+        &mut self,
+        header: PayloadSignHeader,
+        rng: &dyn SecureRandom,
+    ) -> io::Result<&[u8]> {
+        let digest = self.build_signed_region(header)?;
+        let signature_block =
+            self.algorithm
+                .sign_block_with_digest_rng(&self.signed_image, digest, rng)?;
+        self.signed_image.extend_from_slice(&signature_block);
+        if self.normalize {
+            normalize_signatures(&mut self.signed_image)?;
+        }
+
+        Ok(self.signed_image.as_slice())
+    }
+
+    /// Sign the payload in the compact format, which has no verify header.
+    ///
+    /// The compact format records no version, SVN or SVN policy, it's meant for payloads too
+    /// small to carry the verify header.
+    pub fn sign_compact(&mut self) -> io::Result<&[u8]> {
+        if self.policy.is_some() {
+            error!("SVN policy can not be attached to a payload signed in the compact format");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SVN policy is not supported by the compact format",
+            ));
+        }
+        if self.digest != self.algorithm.default_digest() {
+            error!("The compact format always uses the default digest of the signing algorithm");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "digest is not supported by the compact format",
+            ));
+        }
+        if self.load_base != PAYLOAD_LOAD_BASE_ANY {
+            error!("Load base can not be recorded by a payload signed in the compact format");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "load base is not supported by the compact format",
+            ));
+        }
+        if self.rtmr_index.is_some() {
+            error!("RTMR index can not be recorded by a payload signed in the compact format");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "RTMR index is not supported by the compact format",
+            ));
+        }
+
+        self.check_region_size(COMPACT_SIGNATURE_FOOTER_SIZE)?;
+        let footer = CompactSignatureFooter::new(self.algorithm.id());
+        self.signed_image = Vec::with_capacity(
+            self.raw_image.len()
+                + COMPACT_SIGNATURE_FOOTER_SIZE
+                + self.algorithm.max_public_key_len()
+                + self.algorithm.max_signature_len(),
+        );
+        self.signed_image.extend_from_slice(self.raw_image);
+        self.signed_image.extend_from_slice(&footer.to_bytes());
+        let signature_block = self.algorithm.sign_block(&self.signed_image, &self.rng)?;
+        self.signed_image.extend_from_slice(&signature_block);
+
         Ok(self.signed_image.as_slice())
     }
 
+    // Put the verify header, the payload and the SVN policy into the signed image, and return the
+    // digest recorded in the verify header.
+    fn build_signed_region(&mut self, mut header: PayloadSignHeader) -> io::Result<HashAlg> {
+        if self.normalize {
+            normalize_header(&mut header);
+        }
+        let digest = header.digest().map_err(|_| {
+            error!(
+                "Digest {:#x} of the signing header is not supported by {}",
+                header.digest_algorithm, self.algorithm
+            );
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "digest is not supported by signing algorithm",
+            )
+        })?;
+        let policy_size = self.policy.as_ref().map(|v| v.size()).unwrap_or(0);
+        self.check_region_size(header.header_size() + policy_size)?;
+        self.signed_image = Vec::with_capacity(
+            header.length as usize
+                + self.algorithm.max_public_key_len()
+                + self.algorithm.max_signature_len(),
+        );
+        self.signed_image.extend_from_slice(&header.to_bytes());
+        self.signed_image.extend_from_slice(self.raw_image);
+        if let Some(policy) = self.policy.as_ref() {
+            if header.policy_size as usize != policy.size() {
+                error!("SVN policy doesn't match the signing header");
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SVN policy doesn't match the signing header",
+                ));
+            }
+            self.signed_image
+                .extend_from_slice(&policy.to_bytes(header.endian()));
+        }
+
+        Ok(digest)
+    }
+
+    // Check the payload region could hold the payload with `header_size` bytes of verify header
+    // and SVN policy, followed by the signature block, before signing.
+    fn check_region_size(&self, header_size: usize) -> io::Result<()> {
+        let max = max_payload_bytes(&self.algorithm, header_size, self.region_size);
+        if self.raw_image.len() > max {
+            error!(
+                "Payload of {} bytes exceeds the maximum of {} bytes: the payload region of {} bytes \
+                 can't hold the {} bytes verify header, the {} bytes public key and the {} bytes \
+                 signature of {} with {} bits key",
+                self.raw_image.len(),
+                max,
+                self.region_size,
+                header_size,
+                self.algorithm.max_public_key_len(),
+                self.algorithm.max_signature_len(),
+                self.algorithm,
+                self.algorithm.key_bits()
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signed payload doesn't fit in the payload region",
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Create a `PayloadSignHeader` with given `payload_version` and `payload_svn`.
     pub fn build_header(&self, payload_version: u64, payload_svn: u64) -> PayloadSignHeader {
-        let signing_algorithm = match self.algorithm {
-            SigningAlgorithm::EcdsaNistP384Sha384(_) => PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
-            SigningAlgorithm::Rsapss3072Sha384(_) => PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
-        };
-        let length = (self.raw_image.len() + size_of::<PayloadSignHeader>()) as u32;
-
-        PayloadSignHeader {
-            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
-            struct_version: 0x1,
-            length,
+        let mut header = build_sign_header(
+            self.algorithm.id(),
+            self.endian,
+            self.policy.as_ref(),
+            self.raw_image.len(),
             payload_version,
             payload_svn,
-            signing_algorithm,
-            reserved: 0,
+        );
+        header.payload_type = self.payload_type.id();
+        // The default digest is recorded as such, so the header reads the same as before the
+        // digest could be selected.
+        if self.digest != self.algorithm.default_digest() {
+            header.digest_algorithm = self.digest.id() as u8;
+        }
+        // The load base and the RTMR index only exist in version 2 headers.
+        if self.load_base != PAYLOAD_LOAD_BASE_ANY || self.rtmr_index.is_some() {
+            if header.struct_version < PAYLOAD_SIGN_HEADER_VERSION_V2 {
+                let v1_size = header.header_size();
+                header.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V2;
+                header.length += (header.header_size() - v1_size) as u32;
+            }
+            header.load_base = self.load_base;
+        }
+        if let Some(index) = self.rtmr_index {
+            // The measurement registers are numbered as in the event log.
+            header.mr_index = index as u8 + 1;
+        }
+        header
+    }
+}
+
+/// Zero the reserved fields and the undefined flags of `header`, and the SVN policy fields, the
+/// load base and the RTMR index of a version 1 header, which are not serialized. The payload type
+/// and the digest are kept.
+pub fn normalize_header(header: &mut PayloadSignHeader) {
+    header.flags &= PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+    header.reserved = [0u8; 1];
+    header.reserved2 = [0u8; 15];
+    if header.struct_version < PAYLOAD_SIGN_HEADER_VERSION_V2 {
+        header.policy_offset = 0;
+        header.policy_size = 0;
+        header.load_base = PAYLOAD_LOAD_BASE_ANY;
+        header.mr_index = PAYLOAD_MR_INDEX_DEFAULT;
+    }
+}
+
+/// Create a `PayloadSignHeader` for signing a payload of `payload_len` bytes with the algorithm
+/// identified by `signing_algorithm`.
+pub fn build_sign_header(
+    signing_algorithm: u32,
+    endian: Endian,
+    policy: Option<&SvnPolicy>,
+    payload_len: usize,
+    payload_version: u64,
+    payload_svn: u64,
+) -> PayloadSignHeader {
+    let flags = match endian {
+        Endian::Big => PAYLOAD_SIGN_FLAG_BIG_ENDIAN,
+        Endian::Little => 0,
+    };
+    let struct_version = match policy {
+        Some(_) => PAYLOAD_SIGN_HEADER_VERSION_V2,
+        None => PAYLOAD_SIGN_HEADER_VERSION_V1,
+    };
+
+    let mut header = PayloadSignHeader {
+        type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+        struct_version,
+        payload_version,
+        payload_svn,
+        signing_algorithm,
+        flags,
+        ..Default::default()
+    };
+    let mut length = header.header_size() + payload_len;
+    if let Some(policy) = policy {
+        header.policy_offset = length as u32;
+        header.policy_size = policy.size() as u32;
+        length += policy.size();
+    }
+    header.length = length as u32;
+
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{InputData, OutputFile};
+    use ring::signature::{UnparsedPublicKey, ECDSA_P384_SHA384_FIXED};
+    use td_shim::secure_boot::{
+        signature_block_size, PayloadVerifier, VerifyErr, PAYLOAD_SIGN_DIGEST_DEFAULT,
+        PAYLOAD_SIGN_HEADER_FLAGS_OFFSET, PAYLOAD_SIGN_HEADER_V1_SIZE,
+        PAYLOAD_SIGN_HEADER_VERSION_LATEST, PAYLOAD_TYPE_EXECUTABLE,
+    };
+
+    #[test]
+    fn test_is_executable_payload() {
+        assert!(is_executable_payload(include_bytes!(
+            "../../data/blobs/td-payload.efi"
+        )));
+        assert!(is_executable_payload(include_bytes!(
+            "../../data/blobs/td-payload.elf"
+        )));
+        assert!(!is_executable_payload(&[]));
+        assert!(!is_executable_payload(b"build log: error"));
+    }
+
+    #[test]
+    fn test_signing_algorithm_display() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let algorithm = SigningAlgorithm::EcdsaNistP384Sha384(key);
+        assert_eq!(algorithm.id(), PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384);
+        assert_eq!(format!("{}", algorithm), "ECDSA_NIST_P384_SHA384");
+        assert_eq!(
+            format!("{:?}", algorithm),
+            "SigningAlgorithm(ECDSA_NIST_P384_SHA384)"
+        );
+
+        let pkcs8 = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let key = RsaKeyPair::from_pkcs8(pkcs8).unwrap();
+        let algorithm = SigningAlgorithm::Rsapss3072Sha384(key);
+        assert_eq!(algorithm.id(), PAYLOAD_SIGN_RSA_PSS_3072_SHA384);
+        assert_eq!(format!("{}", algorithm), "RSAPSS_3072_SHA384");
+
+        let signer = PayloadSigner::new(&[0u8; 16], algorithm);
+        assert_eq!(
+            format!("{}", signer.build_header(1, 2)),
+            "struct_version: 1, length: 0x40, payload_version: 0x1, payload_svn: 2, \
+             signing_algorithm: RSAPSS_3072_SHA384, endian: little"
+        );
+    }
+
+    #[test]
+    fn test_check_strength() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let algorithm = SigningAlgorithm::Rsapss3072Sha384(RsaKeyPair::from_pkcs8(pkcs8).unwrap());
+        assert_eq!(algorithm.key_bits(), 3072);
+        assert!(algorithm.check_strength(HashAlg::Sha384).is_empty());
+        assert!(algorithm.enforce_strength(HashAlg::Sha384, true).is_ok());
+
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let algorithm = SigningAlgorithm::EcdsaNistP384Sha384(key);
+        assert_eq!(algorithm.key_bits(), 384);
+        assert!(algorithm.check_strength(HashAlg::Sha384).is_empty());
+        assert!(algorithm.enforce_strength(HashAlg::Sha384, true).is_ok());
+
+        // The modulus size is read from the key instead of the algorithm name.
+        let pkcs8 = include_bytes!("../../data/sample-keys/rsa-2048-private.pk8");
+        let algorithm = SigningAlgorithm::Rsapss3072Sha384(RsaKeyPair::from_pkcs8(pkcs8).unwrap());
+        assert_eq!(algorithm.key_bits(), 2048);
+        let weaknesses = algorithm.check_strength(HashAlg::Sha384);
+        assert_eq!(weaknesses.len(), 1);
+        assert_eq!(weaknesses[0], "RSA modulus of 2048 bits is below 3072 bits");
+        assert!(algorithm.enforce_strength(HashAlg::Sha384, false).is_ok());
+        assert!(algorithm.enforce_strength(HashAlg::Sha384, true).is_err());
+
+        // The digest of the signed region is checked, SHA512 is stronger than SHA384.
+        let pkcs8 = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let algorithm = SigningAlgorithm::Rsapss3072Sha384(RsaKeyPair::from_pkcs8(pkcs8).unwrap());
+        assert!(algorithm.check_strength(HashAlg::Sha512).is_empty());
+        assert_eq!(
+            algorithm.check_strength(HashAlg::Sha256),
+            ["digest SHA256 is weaker than SHA384"]
+        );
+        assert!(algorithm.enforce_strength(HashAlg::Sha256, true).is_err());
+    }
+
+    #[test]
+    fn test_sign_endian() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+
+        for endian in [Endian::Little, Endian::Big] {
+            let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+            let mut signer =
+                PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+            signer.set_endian(endian);
+            let header = signer.build_header(0x1234, 0x5678);
+            let signed = signer.sign(header).unwrap();
+
+            let header = PayloadSignHeader::from_bytes(signed).unwrap();
+            assert_eq!(header.endian(), endian);
+            assert_eq!(header.payload_version, 0x1234);
+            assert_eq!(header.payload_svn, 0x5678);
+            assert_eq!(header.header_size(), PAYLOAD_SIGN_HEADER_V1_SIZE);
+            assert_eq!(
+                header.length as usize,
+                payload.len() + PAYLOAD_SIGN_HEADER_V1_SIZE
+            );
+            assert_eq!(
+                &signed[PAYLOAD_SIGN_HEADER_V1_SIZE..header.length as usize],
+                &payload
+            );
         }
     }
+
+    #[test]
+    fn test_sign_payload_type() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let algorithm = || SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+        let sign = |payload_type: SignedPayloadType| {
+            let mut signer = PayloadSigner::new(&payload, algorithm());
+            signer.set_payload_type(payload_type);
+            let header = signer.build_header(1, 1);
+            signer.sign(header).unwrap().to_vec()
+        };
+
+        for payload_type in [
+            SignedPayloadType::Unspecified,
+            SignedPayloadType::LinuxKernel,
+            SignedPayloadType::MigrationTd,
+            SignedPayloadType::Custom(0x90),
+        ] {
+            let signed = sign(payload_type);
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert!(verifier.verify_signature().is_ok());
+            assert_eq!(verifier.get_payload_type(), payload_type);
+        }
+
+        // The payload type is covered by the signature.
+        let mut signed = sign(SignedPayloadType::LinuxKernel);
+        signed[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 1] = PAYLOAD_TYPE_EXECUTABLE;
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert_eq!(verifier.get_payload_type(), SignedPayloadType::Executable);
+        assert_eq!(
+            verifier.verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // Unknown types are surfaced as is, so the loader could refuse them.
+        let mut signer = PayloadSigner::new(&payload, algorithm());
+        let mut header = signer.build_header(1, 1);
+        header.payload_type = 0x7f;
+        let signed = signer.sign(header).unwrap();
+        let verifier = PayloadVerifier::new(signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+        assert_eq!(
+            verifier.get_payload_type(),
+            SignedPayloadType::Unknown(0x7f)
+        );
+
+        assert_eq!(
+            parse_payload_type("linux-kernel").unwrap(),
+            SignedPayloadType::LinuxKernel
+        );
+        assert_eq!(
+            parse_payload_type("1").unwrap(),
+            SignedPayloadType::Executable
+        );
+        assert_eq!(
+            parse_payload_type("0x80").unwrap(),
+            SignedPayloadType::Custom(0x80)
+        );
+        assert!(parse_payload_type("0x7f").is_err());
+        assert!(parse_payload_type("0x100").is_err());
+        assert!(parse_payload_type("kernel").is_err());
+    }
+
+    #[test]
+    fn test_sign_svn_policy() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let policy = SvnPolicy {
+            min_svn: 2,
+            allowed_versions: vec![0x1234],
+        };
+
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+        signer.set_svn_policy(policy.clone());
+        let header = signer.build_header(0x1234, 3);
+        assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_V2);
+        assert_eq!(
+            header.policy_offset as usize,
+            header.header_size() + payload.len()
+        );
+        assert_eq!(header.policy_size as usize, policy.size());
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert_eq!(verifier.get_svn_policy(), Some(&policy));
+        assert!(verifier.check_svn_policy().is_ok());
+        assert_eq!(
+            PayloadVerifier::get_payload_image(&signed).unwrap(),
+            &payload
+        );
+
+        // The policy is covered by the signature.
+        let length = PayloadSignHeader::from_bytes(&signed).unwrap().length as usize;
+        assert_eq!(
+            &signed[length - policy.size()..length],
+            &policy.to_bytes(Endian::Little)
+        );
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let public_key = UnparsedPublicKey::new(&ECDSA_P384_SHA384_FIXED, key.public_key());
+        assert!(public_key
+            .verify(&signed[..length], &signed[length + 96..])
+            .is_ok());
+        let mut tampered = signed.clone();
+        tampered[length - policy.size() + 8] = 1;
+        assert!(public_key
+            .verify(&tampered[..length], &tampered[length + 96..])
+            .is_err());
+    }
+
+    #[test]
+    fn test_sign_load_base() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+
+        // The loader chooses the load base by default.
+        let header = signer.build_header(1, 1);
+        assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_V1);
+        assert_eq!(header.load_base(), None);
+
+        // The load base round-trips through the signed payload, in a version 2 header.
+        signer.set_load_base(0x20_0000).unwrap();
+        let header = signer.build_header(1, 1);
+        assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_V2);
+        assert_eq!(header.length as usize, header.header_size() + payload.len());
+        let signed = signer.sign(header).unwrap().to_vec();
+        let header = PayloadSignHeader::from_bytes(&signed).unwrap();
+        assert_eq!(header.load_base(), Some(0x20_0000));
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+        assert_eq!(
+            PayloadVerifier::get_payload_image(&signed).unwrap(),
+            &payload
+        );
+
+        // The load base is covered by the signature.
+        let mut tampered = signed.clone();
+        tampered[PAYLOAD_SIGN_HEADER_V1_SIZE + 10] ^= 0x1;
+        let verifier = PayloadVerifier::new(&tampered, &[]).unwrap();
+        assert_eq!(
+            verifier.verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // Unaligned load bases are rejected, and the compact format can't record one.
+        assert_eq!(parse_load_base("0x200800").unwrap(), 0x20_0800);
+        assert_eq!(parse_load_base("2099200").unwrap(), 0x20_0800);
+        assert!(parse_load_base("0x").is_err());
+        assert!(parse_load_base("base").is_err());
+        assert!(signer.set_load_base(0x20_0800).is_err());
+        assert!(signer.set_load_base(0x1).is_err());
+        assert_eq!(signer.build_header(1, 1).load_base(), Some(0x20_0000));
+        assert!(signer.sign_compact().is_err());
+    }
+
+    #[test]
+    fn test_sign_rtmr_index() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+
+        // td-shim chooses the RTMR by default.
+        assert_eq!(signer.build_header(1, 1).rtmr_index(), None);
+
+        // The index round-trips through the signed payload, in a version 2 header.
+        for index in 0..=PAYLOAD_MAX_RTMR_INDEX {
+            signer.set_rtmr_index(index).unwrap();
+            let header = signer.build_header(1, 1);
+            assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_V2);
+            assert_eq!(header.load_base(), None);
+            let signed = signer.sign(header).unwrap().to_vec();
+            assert_eq!(
+                PayloadSignHeader::from_bytes(&signed).unwrap().rtmr_index(),
+                Some(index)
+            );
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert!(verifier.verify_signature().is_ok());
+            assert_eq!(verifier.get_payload_rtmr_index(), index);
+        }
+
+        // The index is covered by the signature.
+        let mut tampered = signer.signed_image.clone();
+        tampered[PAYLOAD_SIGN_HEADER_V1_SIZE + 16] = 2;
+        let verifier = PayloadVerifier::new(&tampered, &[]).unwrap();
+        assert_eq!(verifier.get_payload_rtmr_index(), 1);
+        assert_eq!(
+            verifier.verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // RTMRs beyond RTMR[3] don't exist, and the compact format can't record an index.
+        assert!(check_rtmr_index(4).is_err());
+        assert!(signer.set_rtmr_index(4).is_err());
+        assert_eq!(signer.build_header(1, 1).rtmr_index(), Some(3));
+        assert!(signer.sign_compact().is_err());
+    }
+
+    #[test]
+    fn test_format_version() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+
+        // Version 1 headers are emitted unless a field of a newer version is used.
+        assert_eq!(
+            signer.build_header(1, 1).struct_version,
+            PAYLOAD_SIGN_HEADER_VERSION_V1
+        );
+
+        // With every field in use, the latest format version is emitted, and verified.
+        signer.set_svn_policy(SvnPolicy {
+            min_svn: 1,
+            allowed_versions: vec![],
+        });
+        signer.set_load_base(0x20_0000).unwrap();
+        signer.set_rtmr_index(2).unwrap();
+        let header = signer.build_header(1, 1);
+        assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_LATEST);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+    }
+
+    #[test]
+    fn test_max_signature_len() {
+        let payload = [0x5au8; 64];
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let algorithms = [
+            SigningAlgorithm::EcdsaNistP384Sha384(EcdsaP384KeyPair::from_pkcs8(ecdsa).unwrap()),
+            SigningAlgorithm::Rsapss3072Sha384(RsaKeyPair::from_pkcs8(rsa).unwrap()),
+        ];
+
+        for algorithm in algorithms {
+            let key_len = algorithm.max_public_key_len();
+            let sig_len = algorithm.max_signature_len();
+            let public_key_bytes = algorithm.public_key_bytes();
+            let public_key = match &algorithm {
+                SigningAlgorithm::EcdsaNistP384Sha384(key) => key.public_key()[1..].to_vec(),
+                SigningAlgorithm::Rsapss3072Sha384(key) => key
+                    .public_key()
+                    .modulus()
+                    .big_endian_without_leading_zero()
+                    .to_vec(),
+            };
+
+            let mut signer = PayloadSigner::new(&payload, algorithm);
+            let header = signer.build_header(1, 1);
+            let signed = signer.sign(header).unwrap().to_vec();
+            let length = PayloadSignHeader::from_bytes(&signed).unwrap().length as usize;
+            assert_eq!(signed.len(), length + key_len + sig_len);
+            assert_eq!(&signed[length..length + public_key.len()], &public_key);
+            assert_eq!(&signed[length..length + key_len], &public_key_bytes);
+        }
+    }
+
+    #[test]
+    fn test_signature_region_size() {
+        let payload = [0x5au8; 64];
+        let rsa_3072 = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let rsa_4096 = include_bytes!("../../data/sample-keys/rsa-4096-private.pk8");
+        let rsa = |pkcs8: &[u8]| SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", pkcs8).unwrap();
+
+        // Region sized for RSA-3072: verify header, payload, public key and signature.
+        let region_size = PAYLOAD_SIGN_HEADER_V1_SIZE + payload.len() + 392 + 384;
+        assert_eq!(
+            max_payload_bytes(&rsa(rsa_3072), PAYLOAD_SIGN_HEADER_V1_SIZE, region_size),
+            payload.len()
+        );
+
+        let algorithm = rsa(rsa_4096);
+        assert_eq!(algorithm.max_public_key_len(), 520);
+        assert_eq!(algorithm.max_signature_len(), 512);
+        assert_eq!(
+            max_payload_bytes(&algorithm, PAYLOAD_SIGN_HEADER_V1_SIZE, region_size),
+            0
+        );
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        signer.set_region_size(region_size);
+        let header = signer.build_header(1, 1);
+        assert_eq!(
+            signer.sign(header).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(signer.sign_compact().is_err());
+
+        let mut signer = PayloadSigner::new(&payload, rsa(rsa_3072));
+        signer.set_region_size(region_size);
+        let header = signer.build_header(1, 1);
+        assert_eq!(signer.sign(header).unwrap().len(), region_size);
+
+        // One byte short.
+        let mut signer = PayloadSigner::new(&payload, rsa(rsa_3072));
+        signer.set_region_size(region_size - 1);
+        let header = signer.build_header(1, 1);
+        assert!(signer.sign(header).is_err());
+
+        // The default region is TD_SHIM_PAYLOAD_SIZE.
+        let mut signer = PayloadSigner::new(&payload, rsa(rsa_3072));
+        let header = signer.build_header(1, 1);
+        assert!(signer.sign(header).is_ok());
+    }
+
+    #[cfg(feature = "linker")]
+    #[test]
+    fn test_payload_capacity() {
+        use crate::linker::{build_tdx_metadata, build_tdx_metadata_ptr, PayloadType};
+        use td_layout::build_time::{TD_SHIM_FIRMWARE_SIZE, TD_SHIM_METADATA_OFFSET};
+        use td_layout::runtime::linux;
+        use td_shim::metadata::{payload_capacity, TdxMetadataError};
+
+        let build_image = |payload_type: PayloadType| {
+            let mut image = vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize];
+            let metadata = build_tdx_metadata(None, payload_type).unwrap().to_vec();
+            let offset = TD_SHIM_METADATA_OFFSET as usize;
+            image[offset..offset + metadata.len()].copy_from_slice(&metadata);
+            let ptr = build_tdx_metadata_ptr();
+            let offset = image.len() - 0x20;
+            image[offset..offset + 4].copy_from_slice(&ptr.to_bytes());
+            image
+        };
+
+        // The runtime capacity of the layout is the payload region the signer checks against.
+        let capacity = payload_capacity(&build_image(PayloadType::Executable)).unwrap();
+        assert_eq!(capacity, TD_SHIM_PAYLOAD_SIZE as usize);
+
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+        let max = max_payload_bytes(&algorithm, PAYLOAD_SIGN_HEADER_V1_SIZE, capacity);
+        let payload = vec![0x5au8; max + 1];
+
+        // The largest payload accepted at sign time exactly fills the payload region.
+        let mut signer = PayloadSigner::new(&payload[..max], algorithm);
+        let header = signer.build_header(1, 1);
+        assert_eq!(signer.sign(header).unwrap().len(), capacity);
+        let mut signer = PayloadSigner::new(&payload, signer.into_algorithm());
+        let header = signer.build_header(1, 1);
+        assert!(signer.sign(header).is_err());
+
+        // Linux kernels are loaded into the memory of the payload section.
+        assert_eq!(
+            payload_capacity(&build_image(PayloadType::Linux)).unwrap(),
+            linux::PAYLOAD_SIZE as usize
+        );
+
+        let mut image = build_image(PayloadType::Executable);
+        image[TD_SHIM_METADATA_OFFSET as usize] ^= 0xff;
+        assert!(matches!(
+            payload_capacity(&image),
+            Err(TdxMetadataError::InvalidMetadata)
+        ));
+        assert!(matches!(
+            payload_capacity(&[0u8; 8]),
+            Err(TdxMetadataError::InvalidMetadata)
+        ));
+    }
+
+    #[test]
+    fn test_pe_checksum() {
+        let mut payload = include_bytes!("../../data/blobs/td-payload.efi").to_vec();
+        // Checksum is not set in the sample payload.
+        assert!(check_pe_checksum(&payload).is_ok());
+
+        let checksum = pe::compute_checksum(&payload).unwrap();
+        // Offset of the CheckSum field in the sample payload.
+        payload[0xd0..0xd4].copy_from_slice(&(checksum + 1).to_le_bytes());
+        assert!(check_pe_checksum(&payload).is_err());
+
+        assert_eq!(fix_pe_checksum(&mut payload).unwrap(), checksum);
+        assert!(check_pe_checksum(&payload).is_ok());
+
+        // The signature covers the corrected image.
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let header_size = PayloadSignHeader::from_bytes(&signed)
+            .unwrap()
+            .header_size();
+        assert!(check_pe_checksum(&signed[header_size..header_size + payload.len()]).is_ok());
+
+        assert!(check_pe_checksum(&[0u8; 64]).is_err());
+        assert!(fix_pe_checksum(&mut [0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn test_from_pkcs8() {
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        assert_eq!(algorithm.id(), PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384);
+        let algorithm = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", rsa).unwrap();
+        assert_eq!(algorithm.id(), PAYLOAD_SIGN_RSA_PSS_3072_SHA384);
+
+        let err = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", ecdsa).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", rsa).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", &[0u8; 16]).is_err());
+        assert!(SigningAlgorithm::from_pkcs8("RSAPSS_2048_SHA256", rsa).is_err());
+    }
+
+    #[test]
+    fn test_key_backend() {
+        assert!(check_key_backend("data/sample-keys/ecdsa-p384-private.pk8").is_ok());
+        assert!(check_key_backend("pkcs11.pk8").is_ok());
+
+        for key in ["pkcs11:token=td-shim;object=signing-key", "piv:slot=9c"] {
+            let err = check_key_backend(key).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let err = SigningAlgorithm::from_pkcs8("ED25519", rsa).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_rng_source() {
+        assert_eq!(RngSource::from_str("system").unwrap(), RngSource::System);
+        assert_eq!(
+            RngSource::from_str("file:/dev/hwrng").unwrap(),
+            RngSource::File(PathBuf::from("/dev/hwrng"))
+        );
+        assert!(RngSource::from_str("file:").is_err());
+        assert!(RngSource::from_str("urandom").is_err());
+        assert!(RngSource::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_sign_with_file_rng() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let entropy = std::env::temp_dir().join(format!("td-shim-entropy-{}", std::process::id()));
+        std::fs::write(&entropy, [0xa5u8; ECDSA_P384_NONCE_ENTROPY_SIZE]).unwrap();
+
+        let sign = |rng: RngSource| {
+            let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+            let mut signer =
+                PayloadSigner::new(&payload, SigningAlgorithm::EcdsaNistP384Sha384(key));
+            signer.set_rng(rng);
+            let header = signer.build_header(1, 1);
+            signer.sign(header).map(|v| v.to_vec())
+        };
+
+        // The RFC 6979 nonce is deterministic for the same key, message and entropy, even if the
+        // key is loaded again.
+        let signed1 = sign(RngSource::File(entropy.clone())).unwrap();
+        let signed2 = sign(RngSource::File(entropy.clone())).unwrap();
+        assert_eq!(signed1, signed2);
+        assert_ne!(sign(RngSource::System).unwrap(), signed1);
+
+        let length = PayloadSignHeader::from_bytes(&signed1).unwrap().length as usize;
+        let key = EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap();
+        let public_key = UnparsedPublicKey::new(&ECDSA_P384_SHA384_FIXED, key.public_key());
+        assert!(public_key
+            .verify(&signed1[..length], &signed1[length + 96..])
+            .is_ok());
+
+        // Other messages are signed with another nonce.
+        let mut signer = PayloadSigner::new(
+            &payload[1..],
+            SigningAlgorithm::EcdsaNistP384Sha384(EcdsaP384KeyPair::from_pkcs8(pkcs8).unwrap()),
+        );
+        signer.set_rng(RngSource::File(entropy.clone()));
+        let header = signer.build_header(1, 1);
+        let other = signer.sign(header).unwrap().to_vec();
+        let other_length = PayloadSignHeader::from_bytes(&other).unwrap().length as usize;
+        assert_ne!(
+            &other[other_length + 96..other_length + 144],
+            &signed1[length + 96..length + 144]
+        );
+
+        // RSA-PSS signatures don't take the salt from a file.
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let mut signer = PayloadSigner::new(
+            &payload,
+            SigningAlgorithm::Rsapss3072Sha384(RsaKeyPair::from_pkcs8(rsa).unwrap()),
+        );
+        signer.set_rng(RngSource::File(entropy.clone()));
+        let header = signer.build_header(1, 1);
+        assert!(signer.sign(header).is_err());
+
+        // Not enough entropy available.
+        std::fs::write(&entropy, [0xa5u8; ECDSA_P384_NONCE_ENTROPY_SIZE - 1]).unwrap();
+        assert!(sign(RngSource::File(entropy.clone())).is_err());
+        std::fs::remove_file(&entropy).unwrap();
+        assert!(sign(RngSource::File(entropy)).is_err());
+    }
+
+    #[test]
+    fn test_sign_normalize() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 64];
+        let entropy =
+            std::env::temp_dir().join(format!("td-shim-normalize-{}", std::process::id()));
+        std::fs::write(&entropy, [0xa5u8; ECDSA_P384_NONCE_ENTROPY_SIZE]).unwrap();
+
+        let sign = |reserved: u8| {
+            let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+            let mut signer = PayloadSigner::new(&payload, algorithm);
+            signer
+                .set_rng(RngSource::File(entropy.clone()))
+                .set_normalize(true);
+            // Simulate a tool version which leaves garbage in the reserved fields.
+            let mut header = signer.build_header(1, 1);
+            header.flags |= reserved & !PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+            header.reserved = [reserved; 1];
+            header.reserved2 = [reserved; 15];
+            signer.sign(header).unwrap().to_vec()
+        };
+
+        let signed = sign(0);
+        assert_eq!(sign(0xfe), signed);
+        std::fs::remove_file(&entropy).unwrap();
+
+        let header = PayloadSignHeader::from_bytes(&signed).unwrap();
+        assert_eq!(header.flags, 0);
+        assert_eq!(header.reserved, [0u8; 1]);
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+    }
+
+    #[test]
+    fn test_sign_digest() {
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 0x100];
+
+        // RSA-PSS with SHA512, the digest is recorded in the header for the verifier.
+        let algorithm = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", rsa).unwrap();
+        assert_eq!(algorithm.default_digest(), HashAlg::Sha384);
+        assert_eq!(
+            algorithm.signature_name(HashAlg::Sha384),
+            "RSAPSS_3072_SHA384"
+        );
+        assert_eq!(
+            algorithm.signature_name(HashAlg::Sha512),
+            "RSAPSS_3072_SHA512"
+        );
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        signer.set_digest(HashAlg::Sha512).unwrap();
+        let header = signer.build_header(1, 1);
+        assert_eq!(header.digest_algorithm, HashAlg::Sha512.id() as u8);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let header = PayloadSignHeader::from_bytes(&signed).unwrap();
+        assert_eq!(header.digest(), Ok(HashAlg::Sha512));
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+        assert_eq!(extract_payload(&signed).unwrap(), payload);
+
+        // The signature doesn't verify with the default digest.
+        let mut tampered = signed.clone();
+        tampered[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 2] = PAYLOAD_SIGN_DIGEST_DEFAULT;
+        let verifier = PayloadVerifier::new(&tampered, &[]).unwrap();
+        assert_eq!(
+            verifier.verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+
+        // The default digest is recorded as such.
+        let algorithm = signer.into_algorithm();
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        signer.set_digest(HashAlg::Sha384).unwrap();
+        assert_eq!(
+            signer.build_header(1, 1).digest_algorithm,
+            PAYLOAD_SIGN_DIGEST_DEFAULT
+        );
+
+        // ECDSA_NIST_P384_SHA384 pins SHA384.
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        assert_eq!(algorithm.supported_digests(), &[HashAlg::Sha384]);
+        assert!(algorithm
+            .sign_block_with_digest(&payload, HashAlg::Sha256, &RngSource::System)
+            .is_err());
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        assert!(signer.set_digest(HashAlg::Sha512).is_err());
+        let mut header = signer.build_header(1, 1);
+        header.digest_algorithm = HashAlg::Sha512.id() as u8;
+        assert!(signer.sign(header).is_err());
+
+        // The compact format has no room for the digest.
+        let algorithm = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", rsa).unwrap();
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        signer.set_digest(HashAlg::Sha256).unwrap();
+        assert!(signer.sign_compact().is_err());
+    }
+
+    #[test]
+    fn test_check_key_change() {
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let payload = [0x5au8; 0x100];
+
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut signer = PayloadSigner::new(&payload, algorithm);
+        signer.set_svn_policy(SvnPolicy {
+            min_svn: 1,
+            allowed_versions: vec![1],
+        });
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        // The original payload is recovered for re-signing.
+        let range = unsigned_payload_range(&signed).unwrap();
+        assert_eq!(&signed[range], &payload[..]);
+        let old_key = embedded_public_key(&signed).unwrap();
+        assert_eq!(old_key.len(), PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE);
+
+        // Re-signing with the same key is caught, a new key passes.
+        let same = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let err = check_key_change(&same, &signed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let new = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", rsa).unwrap();
+        assert!(check_key_change(&new, &signed).is_ok());
+
+        assert!(check_key_change(&new, &payload).is_err());
+        assert!(check_key_change(&new, &signed[..signed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_extract_payload() {
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+
+        for (name, pkcs8) in [
+            ("ECDSA_NIST_P384_SHA384", &ecdsa[..]),
+            ("RSAPSS_3072_SHA384", &rsa[..]),
+        ] {
+            let algorithm = SigningAlgorithm::from_pkcs8(name, pkcs8).unwrap();
+            let mut signer = PayloadSigner::new(payload, algorithm);
+            let header = signer.build_header(1, 1);
+            let signed = signer.sign(header).unwrap().to_vec();
+            assert_eq!(extract_payload(&signed).unwrap(), &payload[..]);
+        }
+
+        assert!(extract_payload(payload).is_err());
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut signer = PayloadSigner::new(payload, algorithm);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let length = PayloadSignHeader::from_bytes(&signed).unwrap().length as usize;
+        assert!(extract_payload(&signed[..length - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pad_signed_payload() {
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut signer = PayloadSigner::new(payload, algorithm);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        let mut padded = signed.clone();
+        pad_signed_payload(&mut padded, TD_SHIM_PAYLOAD_SIZE as usize).unwrap();
+        assert_eq!(padded.len(), TD_SHIM_PAYLOAD_SIZE as usize);
+        assert_eq!(&padded[..signed.len()], &signed[..]);
+        assert!(padded[signed.len()..].iter().all(|v| *v == 0));
+
+        // The padding is neither part of the payload image nor taken as a signature.
+        assert_eq!(extract_payload(&padded).unwrap(), &payload[..]);
+        let verifier = PayloadVerifier::new(&padded, cfv).unwrap();
+        assert_eq!(verifier.verify_all_with(|_| Ok(())).len(), 1);
+
+        // Padding to the current size is a no-op, shrinking fails.
+        let mut same = signed.clone();
+        pad_signed_payload(&mut same, signed.len()).unwrap();
+        assert_eq!(same, signed);
+        let mut shrunk = signed.clone();
+        assert!(pad_signed_payload(&mut shrunk, signed.len() - 1).is_err());
+
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut compact = PayloadSigner::new(payload, algorithm)
+            .sign_compact()
+            .unwrap()
+            .to_vec();
+        assert!(pad_signed_payload(&mut compact, TD_SHIM_PAYLOAD_SIZE as usize).is_err());
+        let mut unsigned = payload.to_vec();
+        assert!(pad_signed_payload(&mut unsigned, TD_SHIM_PAYLOAD_SIZE as usize).is_err());
+    }
+
+    #[test]
+    fn test_edit_header() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = [0x5au8; 0x100];
+        let algorithm = || SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+
+        let mut signer = PayloadSigner::new(&payload, algorithm());
+        signer
+            .set_endian(Endian::Big)
+            .set_payload_type(SignedPayloadType::Custom(0x81))
+            .set_load_base(0x1000_0000)
+            .unwrap()
+            .set_rtmr_index(2)
+            .unwrap();
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+
+        // Editing without the key is rejected.
+        assert_eq!(
+            edit_header(&signed, None, Some(2), None)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let edited = edit_header(&signed, None, Some(2), Some(algorithm())).unwrap();
+        let header = PayloadSignHeader::from_bytes(&edited).unwrap();
+        assert_eq!(header.payload_svn, 2);
+        assert_eq!(header.payload_version, 1);
+        assert_eq!(header.endian(), Endian::Big);
+        assert_eq!(header.payload_type(), SignedPayloadType::Custom(0x81));
+        assert_eq!(header.struct_version, PAYLOAD_SIGN_HEADER_VERSION_LATEST);
+        assert_eq!(header.load_base(), Some(0x1000_0000));
+        assert_eq!(header.rtmr_index(), Some(2));
+        assert_eq!(extract_payload(&edited).unwrap(), &payload[..]);
+        let verifier = PayloadVerifier::new(&edited, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+
+        assert!(edit_header(&payload, None, Some(2), Some(algorithm())).is_err());
+    }
+
+    #[test]
+    fn test_sign_compact() {
+        let ecdsa = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let rsa = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+        let payload = b"tiny payload";
+
+        for (name, pkcs8) in [
+            ("ECDSA_NIST_P384_SHA384", &ecdsa[..]),
+            ("RSAPSS_3072_SHA384", &rsa[..]),
+        ] {
+            let algorithm = SigningAlgorithm::from_pkcs8(name, pkcs8).unwrap();
+            let id = algorithm.id();
+            let mut signer = PayloadSigner::new(payload, algorithm);
+            let signed = signer.sign_compact().unwrap().to_vec();
+            assert_eq!(
+                signed.len(),
+                payload.len() + COMPACT_SIGNATURE_FOOTER_SIZE + signature_block_size(id).unwrap()
+            );
+            assert_eq!(
+                SignedPayloadFormat::detect(&signed),
+                Some(SignedPayloadFormat::Compact)
+            );
+
+            let verifier = CompactPayloadVerifier::new(&signed, &[]).unwrap();
+            assert_eq!(verifier.signing_algorithm(), id);
+            assert_eq!(verifier.get_payload_image(), &payload[..]);
+            assert!(verifier.verify_signature().is_ok());
+            assert_eq!(extract_payload(&signed).unwrap(), &payload[..]);
+            // The standard verifier doesn't misparse the compact signed payload.
+            assert!(PayloadVerifier::new(&signed, &[]).is_err());
+
+            let mut tampered = signed.clone();
+            tampered[0] ^= 0xff;
+            let verifier = CompactPayloadVerifier::new(&tampered, &[]).unwrap();
+            assert!(verifier.verify_signature().is_err());
+        }
+
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ecdsa).unwrap();
+        let mut signer = PayloadSigner::new(payload, algorithm);
+        signer.set_svn_policy(SvnPolicy {
+            min_svn: 1,
+            allowed_versions: vec![1],
+        });
+        assert!(signer.sign_compact().is_err());
+    }
+
+    #[test]
+    fn test_sign_in_place() {
+        let pkcs8 = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+        let name = std::env::temp_dir().join(format!("td-shim-in-place-{}", std::process::id()));
+        std::fs::write(&name, payload).unwrap();
+
+        // The payload is read completely before the output truncates the same file.
+        let input = InputData::new(name.to_str().unwrap(), 0..=payload.len(), "payload").unwrap();
+        let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", pkcs8).unwrap();
+        let mut signer = PayloadSigner::new(input.as_bytes(), algorithm);
+        let header = signer.build_header(1, 1);
+        let signed = signer.sign(header).unwrap().to_vec();
+        let mut output = OutputFile::new(&name).unwrap();
+        output.seek_and_write(0, &signed, "signed payload").unwrap();
+        output.flush().unwrap();
+
+        let signed = std::fs::read(&name).unwrap();
+        std::fs::remove_file(&name).unwrap();
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.verify_signature().is_ok());
+        assert_eq!(
+            PayloadVerifier::get_payload_image(&signed).unwrap(),
+            &payload[..]
+        );
+    }
 }
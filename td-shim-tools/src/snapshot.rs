@@ -0,0 +1,160 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Recompute the measurement of a payload from a dump of the guest memory it was loaded into, for
+//! post-mortem attestation debugging.
+//!
+//! td-shim measures the payload image as stored in the payload firmware volume, before it's
+//! loaded, so the event log records the digest of the on-disk image. Loading relocates the image
+//! to its load base and lays out the segments or sections at their addresses, so the loaded image
+//! has different bytes and a different digest, which also depends on the load base.
+//! `measure_loaded_payload()` hashes the loaded image with SHA384 as td-shim hashes the on-disk
+//! one, and `load_payload()` loads an on-disk image as td-shim does, so the digest of a memory
+//! dump can be compared against the digest of the image expected to be loaded.
+//!
+//! Writable data of the payload, such as `.data` and `.bss`, is changed once the payload runs, so
+//! only pages which are not written at runtime are expected to match.
+
+use std::ops::Range;
+
+use td_layout::runtime::exec::PAYLOAD_SIZE;
+use td_loader::{elf, pe};
+use td_shim::secure_boot::HashAlg;
+
+/// Location of a loaded payload in a memory snapshot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Layout {
+    /// Guest physical address of the first byte of the snapshot.
+    pub snapshot_base: u64,
+    /// Guest physical address of the loaded image, the `image_base` logged by td-shim.
+    pub image_base: u64,
+    /// Size of the loaded image, the `image_size` logged by td-shim.
+    pub image_size: u64,
+}
+
+impl Layout {
+    /// Get the range of the loaded image in a snapshot of `snapshot_size` bytes, `None` if the
+    /// loaded image is not fully inside of the snapshot.
+    pub fn image_range(&self, snapshot_size: usize) -> Option<Range<usize>> {
+        let start = self.image_base.checked_sub(self.snapshot_base)?;
+        let end = start.checked_add(self.image_size)?;
+        if end > snapshot_size as u64 {
+            return None;
+        }
+
+        Some(start as usize..end as usize)
+    }
+}
+
+/// Measure the loaded payload described by `layout` in the memory snapshot `snapshot`, with the
+/// same SHA384 digest td-shim uses to measure the on-disk payload image.
+///
+/// # Panics
+///
+/// Panics if the loaded image is not fully inside of the snapshot, see `Layout::image_range()`.
+pub fn measure_loaded_payload(snapshot: &[u8], layout: &Layout) -> [u8; 48] {
+    let range = layout
+        .image_range(snapshot.len())
+        .expect("loaded payload image is outside of the memory snapshot");
+    let mut measurement = [0u8; 48];
+    measurement.copy_from_slice(HashAlg::Sha384.digest(&snapshot[range]).as_ref());
+    measurement
+}
+
+/// Load the ELF or PE payload `image` at the guest physical address `load_base` as td-shim does,
+/// return a snapshot of the payload region starting at `load_base` and the layout of the loaded
+/// image in it, `None` if the image can't be loaded.
+pub fn load_payload(image: &[u8], load_base: u64) -> Option<(Vec<u8>, Layout)> {
+    let mut snapshot = vec![0u8; PAYLOAD_SIZE];
+    let (image_base, image_size) = if elf::is_elf(image) {
+        let (_, base, size) =
+            elf::relocate_elf_with_per_program_header(image, &mut snapshot, load_base as usize)?;
+        (base, size)
+    } else if pe::is_x86_64_pe(image) {
+        pe::relocate(image, &mut snapshot, load_base as usize)?;
+        (load_base, image.len() as u64)
+    } else {
+        return None;
+    };
+
+    let layout = Layout {
+        snapshot_base: load_base,
+        image_base,
+        image_size,
+    };
+    layout.image_range(snapshot.len())?;
+
+    Some((snapshot, layout))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ELF: &[u8] = include_bytes!("../../data/blobs/td-payload.elf");
+    const LOAD_BASE: u64 = 0x4000_0000;
+
+    fn on_disk_measurement(image: &[u8]) -> [u8; 48] {
+        let mut measurement = [0u8; 48];
+        measurement.copy_from_slice(HashAlg::Sha384.digest(image).as_ref());
+        measurement
+    }
+
+    #[test]
+    fn test_measure_loaded_payload() {
+        let (snapshot, layout) = load_payload(ELF, LOAD_BASE).unwrap();
+        assert_eq!(layout.image_base, LOAD_BASE);
+        let loaded = measure_loaded_payload(&snapshot, &layout);
+
+        // Relocations change the bytes of the relocatable payload, per load base.
+        assert_ne!(loaded, on_disk_measurement(ELF));
+        let (other_snapshot, other_layout) = load_payload(ELF, LOAD_BASE + 0x20_0000).unwrap();
+        assert_ne!(
+            measure_loaded_payload(&other_snapshot, &other_layout),
+            loaded
+        );
+
+        // A dump of the memory around the payload region.
+        let mut dump = vec![0x5au8; 0x1000];
+        dump.extend_from_slice(&snapshot);
+        let dump_layout = Layout {
+            snapshot_base: LOAD_BASE - 0x1000,
+            ..layout
+        };
+        assert_eq!(measure_loaded_payload(&dump, &dump_layout), loaded);
+
+        // Modified loaded image.
+        dump[0x1100] ^= 0xff;
+        assert_ne!(measure_loaded_payload(&dump, &dump_layout), loaded);
+
+        assert!(load_payload(&[0x5au8; 0x100], LOAD_BASE).is_none());
+    }
+
+    #[test]
+    fn test_layout_image_range() {
+        let layout = Layout {
+            snapshot_base: 0x1000,
+            image_base: 0x2000,
+            image_size: 0x800,
+        };
+        assert_eq!(layout.image_range(0x1800), Some(0x1000..0x1800));
+        assert_eq!(layout.image_range(0x17ff), None);
+        let layout = Layout {
+            image_base: 0x800,
+            ..layout
+        };
+        assert_eq!(layout.image_range(0x10000), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_measure_loaded_payload_outside() {
+        let layout = Layout {
+            snapshot_base: 0,
+            image_base: 0x100,
+            image_size: 0x100,
+        };
+        measure_loaded_payload(&[0u8; 0x100], &layout);
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Sign a payload in separate phases, so the private key can stay on an isolated machine.
+//!
+//! 1. `prepare_signing()` builds the verify header and the SHA384 digest of the signed region on
+//!    the build machine.
+//! 2. `sign_digest()` signs the digest on the machine holding the private key. The result is the
+//!    signature block stored after the signed region, the public key followed by the signature.
+//! 3. `assemble_signed_payload()` puts the verify header, the payload and the signature block
+//!    together on the build machine and verifies the signature. The verify header and signature
+//!    block may also be kept as a detached signature of the payload, see `crate::detached`.
+//!
+//! `ring` only signs messages, so the digest is signed by the `p384` and `rsa` crates.
+
+use std::io;
+
+use log::error;
+use p384::ecdsa::signature::hazmat::PrehashSigner;
+use ring::digest;
+use rsa::pkcs8::DecodePrivateKey;
+use scroll::Endian;
+use sha2::Sha384;
+use td_shim::secure_boot::{
+    PayloadSignHeader, PayloadVerifier, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+    PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+};
+
+use crate::detached::join_detached;
+use crate::signer::{build_sign_header, check_algorithm_backend, SigningAlgorithm};
+
+/// Size of a SHA384 digest.
+pub const SHA384_DIGEST_SIZE: usize = 48;
+
+/// Salt length of RSASSA-PSS signatures, as required by the td-shim verifier.
+const RSA_PSS_SHA384_SALT_SIZE: usize = 48;
+
+/// Verify header and digest to be signed, generated in the first phase.
+pub struct SigningRequest {
+    pub header: PayloadSignHeader,
+    /// SHA384 of the signed region, the verify header followed by the payload.
+    pub digest: Vec<u8>,
+}
+
+fn signing_algorithm_id(algorithm: &str) -> io::Result<u32> {
+    match algorithm {
+        "RSAPSS_3072_SHA384" => Ok(PAYLOAD_SIGN_RSA_PSS_3072_SHA384),
+        "ECDSA_NIST_P384_SHA384" => Ok(PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384),
+        _ => {
+            check_algorithm_backend(algorithm)?;
+            error!("Unsupported signing algorithm: {}", algorithm);
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unsupported signing algorithm",
+            ))
+        }
+    }
+}
+
+/// Build the verify header for signing `payload` with `algorithm`, and the digest to be signed.
+pub fn prepare_signing(
+    payload: &[u8],
+    algorithm: &str,
+    endian: Endian,
+    payload_version: u64,
+    payload_svn: u64,
+) -> io::Result<SigningRequest> {
+    let header = build_sign_header(
+        signing_algorithm_id(algorithm)?,
+        endian,
+        None,
+        payload.len(),
+        payload_version,
+        payload_svn,
+    );
+
+    let mut context = digest::Context::new(&digest::SHA384);
+    context.update(&header.to_bytes());
+    context.update(payload);
+
+    Ok(SigningRequest {
+        header,
+        digest: context.finish().as_ref().to_vec(),
+    })
+}
+
+/// Parse a SHA384 digest, either raw bytes or a hex string.
+pub fn parse_digest(data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || {
+        error!("Invalid SHA384 digest, expect 48 raw bytes or 96 hex digits");
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid digest")
+    };
+
+    if data.len() == SHA384_DIGEST_SIZE {
+        return Ok(data.to_vec());
+    }
+    let text = std::str::from_utf8(data).map_err(|_| invalid())?;
+    let digest = hex::decode(text.trim()).map_err(|_| invalid())?;
+    if digest.len() != SHA384_DIGEST_SIZE {
+        return Err(invalid());
+    }
+
+    Ok(digest)
+}
+
+fn sign_error(e: &dyn std::fmt::Display) -> io::Error {
+    error!("Failed to sign digest: {}", e);
+    io::Error::new(io::ErrorKind::Other, "failed to sign digest")
+}
+
+/// Sign a SHA384 `digest` with the PKCS#8 private key `pkcs8` for `algorithm`.
+///
+/// Return the signature block, the public key followed by the signature.
+pub fn sign_digest(algorithm: &str, pkcs8: &[u8], digest: &[u8]) -> io::Result<Vec<u8>> {
+    if digest.len() != SHA384_DIGEST_SIZE {
+        error!("Invalid size of SHA384 digest: {}", digest.len());
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid digest size",
+        ));
+    }
+    // Also checks that the key matches the algorithm.
+    let key = SigningAlgorithm::from_pkcs8(algorithm, pkcs8)?;
+    key.enforce_strength(key.default_digest(), false)?;
+
+    let signature = match &key {
+        SigningAlgorithm::EcdsaNistP384Sha384(_) => {
+            let secret = p384::SecretKey::from_pkcs8_der(pkcs8).map_err(|e| sign_error(&e))?;
+            let signature: p384::ecdsa::Signature = p384::ecdsa::SigningKey::from(secret)
+                .sign_prehash(digest)
+                .map_err(|e| sign_error(&e))?;
+            signature.as_ref().to_vec()
+        }
+        SigningAlgorithm::Rsapss3072Sha384(_) => {
+            let secret = rsa::RsaPrivateKey::from_pkcs8_der(pkcs8).map_err(|e| sign_error(&e))?;
+            secret
+                .sign_with_rng(
+                    &mut rand_core::OsRng,
+                    rsa::PaddingScheme::new_pss_with_salt::<Sha384>(RSA_PSS_SHA384_SALT_SIZE),
+                    digest,
+                )
+                .map_err(|e| sign_error(&e))?
+        }
+    };
+    if signature.len() != key.max_signature_len() {
+        return Err(sign_error(&"unexpected signature size"));
+    }
+
+    let mut block = key.public_key_bytes();
+    block.extend_from_slice(&signature);
+    Ok(block)
+}
+
+/// Assemble a signed payload from the verify header, the original payload and the signature
+/// block generated by `sign_digest()`.
+///
+/// The signature is verified against the public key in the signature block.
+pub fn assemble_signed_payload(
+    header: &[u8],
+    payload: &[u8],
+    signature_block: &[u8],
+) -> io::Result<Vec<u8>> {
+    let signed_payload = join_detached(header, payload, signature_block)?;
+
+    PayloadVerifier::new(&signed_payload, &[])
+        .and_then(|v| v.verify_signature())
+        .map_err(|_| {
+            error!("Can not assemble signed payload: signature doesn't match the payload");
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signature doesn't match the payload",
+            )
+        })?;
+
+    Ok(signed_payload)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::secure_boot::{signature_block_size, SvnPolicy};
+
+    use crate::detached::assemble_signed_image;
+    use crate::signer::PayloadSigner;
+
+    const ECDSA_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+    const RSA_KEY: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+
+    #[test]
+    fn test_parse_digest() {
+        let digest = [0x5au8; SHA384_DIGEST_SIZE];
+        assert_eq!(parse_digest(&digest).unwrap(), digest);
+        let text = format!("{}\n", hex::encode(digest));
+        assert_eq!(parse_digest(text.as_bytes()).unwrap(), digest);
+
+        assert!(parse_digest(&digest[1..]).is_err());
+        assert!(parse_digest(b"5a5a").is_err());
+        assert!(parse_digest(&[0xffu8; 96]).is_err());
+    }
+
+    #[test]
+    fn test_split_signing() {
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+
+        for (algorithm, key) in [
+            ("ECDSA_NIST_P384_SHA384", ECDSA_KEY),
+            ("RSAPSS_3072_SHA384", RSA_KEY),
+        ] {
+            // Build machine.
+            let request = prepare_signing(payload, algorithm, Endian::Little, 1, 2).unwrap();
+            let header = request.header.to_bytes();
+            let digest = hex::encode(&request.digest);
+
+            // Isolated machine, which sees the digest only.
+            let block =
+                sign_digest(algorithm, key, &parse_digest(digest.as_bytes()).unwrap()).unwrap();
+            assert_eq!(
+                Some(block.len()),
+                signature_block_size(request.header.signing_algorithm)
+            );
+
+            // Build machine.
+            let signed = assemble_signed_payload(&header, payload, &block).unwrap();
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert!(verifier.verify_signature().is_ok());
+            assert_eq!(verifier.get_payload_svn(), 2);
+            assert_eq!(
+                PayloadVerifier::get_payload_image(&signed).unwrap(),
+                &payload[..]
+            );
+
+            // The signature doesn't cover another payload or header.
+            let mut other = payload.to_vec();
+            other[0x100] ^= 0xff;
+            assert!(assemble_signed_payload(&header, &other, &block).is_err());
+            let other = prepare_signing(payload, algorithm, Endian::Little, 1, 3).unwrap();
+            assert!(assemble_signed_payload(&other.header.to_bytes(), payload, &block).is_err());
+            assert!(assemble_signed_payload(&header, &payload[1..], &block).is_err());
+        }
+    }
+
+    #[test]
+    fn test_assemble_signed_image() {
+        let payload = include_bytes!("../../data/blobs/td-payload.elf");
+
+        for (algorithm, key, policy) in [
+            ("ECDSA_NIST_P384_SHA384", ECDSA_KEY, None),
+            ("RSAPSS_3072_SHA384", RSA_KEY, None),
+            (
+                "ECDSA_NIST_P384_SHA384",
+                ECDSA_KEY,
+                Some(SvnPolicy {
+                    min_svn: 2,
+                    allowed_versions: vec![1, 3],
+                }),
+            ),
+        ] {
+            let key = SigningAlgorithm::from_pkcs8(algorithm, key).unwrap();
+            let public_key_size = key.public_key_bytes().len();
+            let mut signer = PayloadSigner::new(payload, key);
+            if let Some(policy) = policy {
+                signer.set_svn_policy(policy);
+            }
+            let signed = signer.sign(signer.build_header(1, 2)).unwrap().to_vec();
+
+            // Split the output of sign() into its components, and lay them out again.
+            let header = PayloadSignHeader::from_bytes(&signed).unwrap();
+            let header_size = header.header_size();
+            let signed_size = header.length as usize;
+            let data = &signed[header_size..signed_size];
+            let (public_key, signature) = signed[signed_size..].split_at(public_key_size);
+            assert_eq!(
+                assemble_signed_image(data, &header, signature, public_key).unwrap(),
+                signed
+            );
+
+            // The pieces must match the verify header.
+            assert!(assemble_signed_image(&data[1..], &header, signature, public_key).is_err());
+            assert!(assemble_signed_image(data, &header, &signature[1..], public_key).is_err());
+            assert!(assemble_signed_image(data, &header, signature, &public_key[1..]).is_err());
+            let mut other = PayloadSignHeader::from_bytes(&signed).unwrap();
+            other.signing_algorithm ^= 0x3;
+            assert!(assemble_signed_image(data, &other, signature, public_key).is_err());
+            if header.policy_size != 0 {
+                let mut other = PayloadSignHeader::from_bytes(&signed).unwrap();
+                other.policy_offset -= 1;
+                assert!(assemble_signed_image(data, &other, signature, public_key).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sign_digest_invalid() {
+        let digest = [0u8; SHA384_DIGEST_SIZE];
+        assert!(sign_digest("ECDSA_NIST_P384_SHA384", ECDSA_KEY, &digest[1..]).is_err());
+        assert!(sign_digest("RSAPSS_3072_SHA384", ECDSA_KEY, &digest).is_err());
+        assert!(sign_digest("ED25519", ECDSA_KEY, &digest).is_err());
+    }
+}
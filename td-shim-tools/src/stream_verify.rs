@@ -0,0 +1,300 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Verify the primary signature of a signed payload read from a stream, without buffering the
+//! whole signed payload.
+//!
+//! The verify header is read first, the rest of the signed region is passed through the digest
+//! recorded in the verify header in chunks, then the signature block following the signed region
+//! is read and the digest is verified against it. `ring` only verifies messages, so the digest is
+//! verified by the `p384` and `rsa` crates.
+
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+use log::error;
+use p384::ecdsa::signature::hazmat::PrehashVerifier;
+use ring::digest;
+use rsa::PublicKey;
+use sha2::{Sha256, Sha384, Sha512};
+use td_shim::secure_boot::{
+    signature_block_size, HashAlg, PayloadSignHeader, VerifyErr,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+    PAYLOAD_SIGN_HEADER_V1_SIZE, PAYLOAD_SIGN_HEADER_VERSION_V1,
+    PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE, PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+    PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+
+/// Default number of bytes passed through the digest at a time.
+pub const DEFAULT_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Result of verifying the primary signature of a streamed signed payload.
+#[derive(Debug)]
+pub struct StreamStatus {
+    pub header: PayloadSignHeader,
+    /// Public key stored in the signature block.
+    pub public_key: Vec<u8>,
+    pub result: Result<(), VerifyErr>,
+}
+
+/// Verifier of signed payloads read from a stream.
+#[derive(Debug)]
+pub struct StreamVerifier {
+    chunk_size: usize,
+}
+
+impl Default for StreamVerifier {
+    fn default() -> Self {
+        StreamVerifier {
+            chunk_size: DEFAULT_STREAM_CHUNK_SIZE,
+        }
+    }
+}
+
+fn read_error(e: io::Error) -> io::Error {
+    error!("Can not read signed payload: {}", e);
+    e
+}
+
+fn invalid(reason: &str) -> io::Error {
+    error!("Invalid signed payload: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+// Verify the `digest` of the signed region, computed by `digest_alg`, against the signature block
+// `block`.
+fn verify_digest(
+    signing_algorithm: u32,
+    digest_alg: HashAlg,
+    block: &[u8],
+    digest: &[u8],
+) -> Result<(), VerifyErr> {
+    match signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 | PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            let (public_key, signature) =
+                block.split_at(PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE);
+            let mut point = vec![0x04];
+            point.extend_from_slice(public_key);
+            let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                .map_err(|_| VerifyErr::InvalidContent)?;
+            let signature = if signing_algorithm == PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 {
+                p384::ecdsa::Signature::try_from(signature).ok()
+            } else {
+                // DER SEQUENCE of R and S followed by zero padding, the length is in one byte.
+                signature
+                    .get(1)
+                    .map(|v| *v as usize)
+                    .filter(|v| {
+                        *v < 0x80 && 2 + *v <= PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE
+                    })
+                    .and_then(|v| signature.get(..2 + v))
+                    .and_then(|v| p384::ecdsa::Signature::from_der(v).ok())
+            }
+            .ok_or(VerifyErr::InvalidContent)?;
+            key.verify_prehash(digest, &signature)
+                .map_err(|_| VerifyErr::InvalidSignature)
+        }
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => {
+            let (public_key, signature) = block.split_at(PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE);
+            let (modulus, exponent) = public_key.split_at(PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE);
+            let key = rsa::RsaPublicKey::new(
+                rsa::BigUint::from_bytes_be(modulus),
+                rsa::BigUint::from_bytes_be(exponent),
+            )
+            .map_err(|_| VerifyErr::InvalidContent)?;
+            let padding = match digest_alg {
+                HashAlg::Sha256 => rsa::PaddingScheme::new_pss::<Sha256>(),
+                HashAlg::Sha384 => rsa::PaddingScheme::new_pss::<Sha384>(),
+                HashAlg::Sha512 => rsa::PaddingScheme::new_pss::<Sha512>(),
+            };
+            key.verify(padding, digest, signature)
+                .map_err(|_| VerifyErr::InvalidSignature)
+        }
+        _ => Err(VerifyErr::UnknownAlgorithm),
+    }
+}
+
+impl StreamVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set number of bytes passed through the digest at a time, default to
+    /// `DEFAULT_STREAM_CHUNK_SIZE`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) -> &mut Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Verify the primary signature of the signed payload read from `reader`, with the public key
+    /// checked by `verify_public_key`, for example `secure_boot::verify_trust_anchor()`.
+    ///
+    /// Reading failures and malformed verify headers are reported as errors, failures of the
+    /// signature as `StreamStatus::result`. Additional signatures are not read.
+    pub fn verify<R, F>(&self, mut reader: R, verify_public_key: F) -> io::Result<StreamStatus>
+    where
+        R: Read,
+        F: Fn(&[u8]) -> Result<(), VerifyErr>,
+    {
+        // The size of the verify header depends on its version.
+        let mut header_bytes = vec![0u8; PAYLOAD_SIGN_HEADER_V1_SIZE];
+        reader.read_exact(&mut header_bytes).map_err(read_error)?;
+        let header =
+            PayloadSignHeader::from_bytes(&header_bytes).ok_or_else(|| invalid("verify header"))?;
+        if header.check_version().is_err() {
+            return Err(invalid(&format!(
+                "unsupported format version {}",
+                header.struct_version
+            )));
+        }
+        if header.struct_version > PAYLOAD_SIGN_HEADER_VERSION_V1 {
+            header_bytes.resize(header.header_size(), 0);
+            reader
+                .read_exact(&mut header_bytes[PAYLOAD_SIGN_HEADER_V1_SIZE..])
+                .map_err(read_error)?;
+        }
+        let header =
+            PayloadSignHeader::from_bytes(&header_bytes).ok_or_else(|| invalid("verify header"))?;
+        if &header.type_guid != SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() {
+            return Err(invalid("verify header"));
+        }
+        if (header.length as usize) < header.header_size() {
+            return Err(invalid("length of signed region"));
+        }
+        let digest_alg = header
+            .digest()
+            .map_err(|_| invalid("digest not supported by the signing algorithm"))?;
+        let mut hasher = digest::Context::new(digest_alg.algorithm());
+        hasher.update(&header_bytes);
+
+        let mut remaining = header.length as usize - header.header_size();
+        let mut chunk = vec![0u8; self.chunk_size.min(remaining.max(1))];
+        while remaining > 0 {
+            let len = chunk.len().min(remaining);
+            reader.read_exact(&mut chunk[..len]).map_err(read_error)?;
+            hasher.update(&chunk[..len]);
+            remaining -= len;
+        }
+        let digest = hasher.finish();
+
+        let block_size = signature_block_size(header.signing_algorithm)
+            .ok_or_else(|| invalid("unknown signing algorithm"))?;
+        let mut block = vec![0u8; block_size];
+        reader.read_exact(&mut block).map_err(read_error)?;
+        let key_size = match header.signing_algorithm {
+            PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE,
+            _ => PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE,
+        };
+        let public_key = block[..key_size].to_vec();
+
+        let result = verify_public_key(&public_key).and_then(|_| {
+            verify_digest(
+                header.signing_algorithm,
+                digest_alg,
+                &block,
+                digest.as_ref(),
+            )
+        });
+
+        Ok(StreamStatus {
+            header,
+            public_key,
+            result,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::secure_boot::{
+        verify_trust_anchor, PayloadVerifier, PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE,
+        PAYLOAD_SIGN_HEADER_VERSION_LATEST,
+    };
+
+    const CFV: &[u8] = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+    const SIGNED_PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+    fn stream_verify(signed_payload: &[u8], chunk_size: usize) -> Result<(), VerifyErr> {
+        let mut verifier = StreamVerifier::new();
+        verifier.set_chunk_size(chunk_size);
+        verifier
+            .verify(signed_payload, |public_key| {
+                verify_trust_anchor(CFV, public_key)
+            })
+            .unwrap()
+            .result
+    }
+
+    fn buffered_verify(signed_payload: &[u8]) -> Result<(), VerifyErr> {
+        PayloadVerifier::new(signed_payload, CFV).unwrap().verify()
+    }
+
+    #[test]
+    fn test_stream_verify() {
+        assert!(buffered_verify(SIGNED_PAYLOAD).is_ok());
+        for chunk_size in [1, 7, 0x1000, DEFAULT_STREAM_CHUNK_SIZE] {
+            assert_eq!(
+                stream_verify(SIGNED_PAYLOAD, chunk_size),
+                buffered_verify(SIGNED_PAYLOAD)
+            );
+        }
+
+        // Tamper the payload.
+        let mut tampered = SIGNED_PAYLOAD.to_vec();
+        let header = PayloadSignHeader::from_bytes(&tampered).unwrap();
+        tampered[header.header_size()] ^= 0xff;
+        assert_eq!(buffered_verify(&tampered), Err(VerifyErr::InvalidSignature));
+        assert_eq!(stream_verify(&tampered, 0x1000), buffered_verify(&tampered));
+
+        // Untrusted key.
+        let status = StreamVerifier::new()
+            .verify(SIGNED_PAYLOAD, |_| Err(VerifyErr::InvalidPublicKey))
+            .unwrap();
+        assert_eq!(status.result, Err(VerifyErr::InvalidPublicKey));
+        assert_eq!(status.header.length, header.length);
+
+        // Truncated signature block.
+        let truncated = &SIGNED_PAYLOAD[..header.length as usize + 8];
+        assert!(StreamVerifier::new().verify(truncated, |_| Ok(())).is_err());
+        assert!(StreamVerifier::new()
+            .verify(&[0u8; 16][..], |_| Ok(()))
+            .is_err());
+
+        // Newer format version, `struct_version` is at offset 16.
+        let mut newer = SIGNED_PAYLOAD.to_vec();
+        newer[16..20].copy_from_slice(&(PAYLOAD_SIGN_HEADER_VERSION_LATEST + 1).to_le_bytes());
+        assert!(StreamVerifier::new()
+            .verify(&newer[..], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_digest_asn1() {
+        let digest = HashAlg::Sha384.digest(b"payload");
+        let verify = |signature: &[u8]| {
+            let block =
+                &SIGNED_PAYLOAD[SIGNED_PAYLOAD.len() - PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE..];
+            let mut block = block[..PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE].to_vec();
+            block.extend_from_slice(signature);
+            verify_digest(
+                PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+                HashAlg::Sha384,
+                &block,
+                digest.as_ref(),
+            )
+        };
+
+        // Missing, long-form and oversized lengths are rejected without reading past the block.
+        assert_eq!(verify(&[]), Err(VerifyErr::InvalidContent));
+        assert_eq!(verify(&[0x30]), Err(VerifyErr::InvalidContent));
+        assert_eq!(verify(&[0x30, 0x81, 0x66]), Err(VerifyErr::InvalidContent));
+        let mut oversized = vec![0x30, 0x7f];
+        oversized.resize(PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, 0);
+        assert_eq!(verify(&oversized), Err(VerifyErr::InvalidContent));
+        assert_eq!(verify(&[0x30, 0x66]), Err(VerifyErr::InvalidContent));
+    }
+}
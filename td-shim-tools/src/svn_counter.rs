@@ -0,0 +1,224 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Persistent monotonic SVN counter of the signing host, to prevent signing a payload with an SVN
+//! lower than one already signed.
+//!
+//! The counter file holds the last signed SVN in decimal. A missing counter file is treated as no
+//! payload signed yet. The counter is locked by creating `<counter>.lock` exclusively, which is
+//! removed when the `SvnCounter` is dropped, so signings sharing a counter file are serialized.
+//! The counter is updated by renaming a temporary file over it, readers never see a partial value.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+/// Default time to wait for the lock of the counter held by another signing.
+pub const SVN_COUNTER_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const SVN_COUNTER_LOCK_RETRY: Duration = Duration::from_millis(10);
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// A locked SVN counter file.
+#[derive(Debug)]
+pub struct SvnCounter {
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl SvnCounter {
+    /// Lock the counter file `path`, waiting up to `SVN_COUNTER_LOCK_TIMEOUT`.
+    pub fn lock<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::lock_with_timeout(path, SVN_COUNTER_LOCK_TIMEOUT)
+    }
+
+    pub fn lock_with_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let lock_path = with_suffix(&path, ".lock");
+        let start = Instant::now();
+
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(_) => return Ok(SvnCounter { path, lock_path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if start.elapsed() >= timeout {
+                        error!(
+                            "Can not lock SVN counter {}, remove {} if no signing is running",
+                            path.display(),
+                            lock_path.display()
+                        );
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            "SVN counter is locked",
+                        ));
+                    }
+                    thread::sleep(SVN_COUNTER_LOCK_RETRY);
+                }
+                Err(e) => {
+                    error!("Can not lock SVN counter {}: {}", path.display(), e);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Get the last signed SVN, `None` if the counter file doesn't exist.
+    pub fn last_svn(&self) -> io::Result<Option<u64>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                error!("Can not read SVN counter {}: {}", self.path.display(), e);
+                return Err(e);
+            }
+        };
+
+        content.trim().parse::<u64>().map(Some).map_err(|_| {
+            error!("Invalid SVN counter {}", self.path.display());
+            io::Error::new(io::ErrorKind::InvalidData, "invalid SVN counter")
+        })
+    }
+
+    /// Reject signing a payload with `svn` lower than the last signed SVN.
+    pub fn check(&self, svn: u64) -> io::Result<()> {
+        match self.last_svn()? {
+            Some(last) if svn < last => {
+                error!(
+                    "Security version number {} is lower than the last signed {} in {}",
+                    svn,
+                    last,
+                    self.path.display()
+                );
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "security version number is rolled back",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record `svn` as signed, the counter never goes backward.
+    pub fn advance(&self, svn: u64) -> io::Result<()> {
+        self.check(svn)?;
+        if self.last_svn()? == Some(svn) {
+            return Ok(());
+        }
+
+        let tmp_path = with_suffix(&self.path, ".tmp");
+        let result = fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(format!("{}\n", svn).as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        result.map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            error!("Can not update SVN counter {}: {}", self.path.display(), e);
+            e
+        })
+    }
+}
+
+impl Drop for SvnCounter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.lock_path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn counter_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("td-shim-svn-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_svn_counter_advance() {
+        let path = counter_path("advance");
+        let _ = fs::remove_file(&path);
+
+        let counter = SvnCounter::lock(&path).unwrap();
+        assert_eq!(counter.last_svn().unwrap(), None);
+        counter.check(0).unwrap();
+        counter.advance(3).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3\n");
+        drop(counter);
+
+        // Another signing sees the advanced counter.
+        let counter = SvnCounter::lock(&path).unwrap();
+        assert_eq!(counter.last_svn().unwrap(), Some(3));
+        // Signing the same SVN again is allowed.
+        counter.check(3).unwrap();
+        counter.advance(3).unwrap();
+        counter.advance(5).unwrap();
+        assert_eq!(counter.last_svn().unwrap(), Some(5));
+        drop(counter);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_svn_counter_reject() {
+        let path = counter_path("reject");
+        fs::write(&path, "4\n").unwrap();
+
+        let counter = SvnCounter::lock(&path).unwrap();
+        assert!(counter.check(3).is_err());
+        assert!(counter.advance(3).is_err());
+        assert_eq!(counter.last_svn().unwrap(), Some(4));
+
+        fs::write(&path, "four").unwrap();
+        assert!(counter.check(5).is_err());
+        drop(counter);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_svn_counter_concurrent() {
+        let path = counter_path("concurrent");
+        let _ = fs::remove_file(&path);
+
+        // The counter is held, others have to wait.
+        let counter = SvnCounter::lock(&path).unwrap();
+        assert!(SvnCounter::lock_with_timeout(&path, Duration::from_millis(50)).is_err());
+        drop(counter);
+
+        // Each thread reads the counter and advances it by one, no update is lost.
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let counter = SvnCounter::lock(&path).unwrap();
+                    let svn = counter.last_svn().unwrap().unwrap_or(0) + 1;
+                    counter.advance(svn).unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let counter = SvnCounter::lock(&path).unwrap();
+        assert_eq!(counter.last_svn().unwrap(), Some(8));
+        drop(counter);
+        assert!(!with_suffix(&path, ".lock").exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
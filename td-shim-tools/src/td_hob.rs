@@ -0,0 +1,209 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Build time generator for the `TD_HOB` content handed to td-shim by the VMM.
+//!
+//! The generated HOB list starts with a handoff info table, followed by resource descriptor HOBs
+//! covering the guest memory and ends with an end-of-HOB-list HOB.
+
+use std::mem::size_of;
+use std::vec::Vec;
+
+use scroll::Pread;
+use td_layout::runtime::exec::{TD_HOB_BASE, TD_HOB_SIZE};
+use td_uefi_pi::hob::align_to_next_hob_offset;
+use td_uefi_pi::pi::boot_mode::BOOT_WITH_FULL_CONFIGURATION;
+use td_uefi_pi::pi::hob::{
+    HandoffInfoTable, Header, ResourceDescription, HOB_TYPE_END_OF_HOB_LIST, HOB_TYPE_HANDOFF,
+    HOB_TYPE_RESOURCE_DESCRIPTOR, RESOURCE_ATTRIBUTE_INITIALIZED, RESOURCE_ATTRIBUTE_PRESENT,
+    RESOURCE_ATTRIBUTE_TESTED, RESOURCE_MEMORY_UNACCEPTED,
+};
+
+const PAGE_SIZE: u64 = 0x1000;
+const SIZE_4G: u64 = 0x1_0000_0000;
+/// Top of memory below 4G, the range between it and 4G is reserved for MMIO.
+const LOW_MEMORY_TOP: u64 = 0x8000_0000;
+
+const HOB_VERSION: u32 = 9;
+const MEMORY_ATTRIBUTES: u32 =
+    RESOURCE_ATTRIBUTE_PRESENT | RESOURCE_ATTRIBUTE_INITIALIZED | RESOURCE_ATTRIBUTE_TESTED;
+
+/// Build the `TD_HOB` content describing `memory_size` bytes of guest memory.
+///
+/// Memory below `LOW_MEMORY_TOP` is reported at its natural address and the remaining part is
+/// reported above 4G. The generated HOB list is parsed back to make sure it's well-formed and fits
+/// into the `TD_HOB` metadata section.
+///
+/// # Panics
+///
+/// Panics if `memory_size` is zero or not 4K aligned.
+pub fn build_td_hob(memory_size: u64) -> Vec<u8> {
+    assert!(
+        memory_size != 0 && memory_size % PAGE_SIZE == 0,
+        "memory size must be non-zero and 4K aligned"
+    );
+
+    let mut ranges = Vec::new();
+    if memory_size <= LOW_MEMORY_TOP {
+        ranges.push((0, memory_size));
+    } else {
+        ranges.push((0, LOW_MEMORY_TOP));
+        ranges.push((SIZE_4G, memory_size - LOW_MEMORY_TOP));
+    }
+
+    let hob_len = size_of::<HandoffInfoTable>()
+        + ranges.len() * size_of::<ResourceDescription>()
+        + size_of::<Header>();
+
+    let handoff_info_table = HandoffInfoTable {
+        header: Header {
+            r#type: HOB_TYPE_HANDOFF,
+            length: size_of::<HandoffInfoTable>() as u16,
+            reserved: 0,
+        },
+        version: HOB_VERSION,
+        boot_mode: BOOT_WITH_FULL_CONFIGURATION,
+        efi_memory_top: 0,
+        efi_memory_bottom: 0,
+        efi_free_memory_top: 0,
+        efi_free_memory_bottom: 0,
+        efi_end_of_hob_list: TD_HOB_BASE as u64 + hob_len as u64,
+    };
+
+    let mut hob = Vec::with_capacity(hob_len);
+    hob.extend_from_slice(handoff_info_table.as_bytes());
+    for (start, length) in ranges {
+        let resource = ResourceDescription {
+            header: Header {
+                r#type: HOB_TYPE_RESOURCE_DESCRIPTOR,
+                length: size_of::<ResourceDescription>() as u16,
+                reserved: 0,
+            },
+            owner: [0u8; 16],
+            resource_type: RESOURCE_MEMORY_UNACCEPTED,
+            resource_attribute: MEMORY_ATTRIBUTES,
+            physical_start: start,
+            resource_length: length,
+        };
+        hob.extend_from_slice(resource.as_bytes());
+    }
+    let end_of_hob = Header {
+        r#type: HOB_TYPE_END_OF_HOB_LIST,
+        length: size_of::<Header>() as u16,
+        reserved: 0,
+    };
+    hob.extend_from_slice(end_of_hob.as_bytes());
+
+    assert_eq!(
+        parse_td_hob(&hob),
+        Some(memory_size),
+        "generated TD_HOB is malformed"
+    );
+
+    hob
+}
+
+/// Walk a `TD_HOB` list generated for the `TD_HOB` metadata section.
+///
+/// Return the total size of memory described by the resource descriptor HOBs, or `None` if the
+/// HOB list is malformed or doesn't fit into the `TD_HOB` section.
+pub fn parse_td_hob(hob: &[u8]) -> Option<u64> {
+    if hob.len() > TD_HOB_SIZE {
+        return None;
+    }
+
+    let phit: HandoffInfoTable = hob.pread(0).ok()?;
+    if phit.header.r#type != HOB_TYPE_HANDOFF
+        || phit.header.length as usize != size_of::<HandoffInfoTable>()
+        || phit.efi_end_of_hob_list != TD_HOB_BASE as u64 + hob.len() as u64
+    {
+        return None;
+    }
+
+    let mut memory_size = 0u64;
+    let mut offset = 0;
+    loop {
+        let header: Header = hob.pread(offset).ok()?;
+        if header.reserved != 0 {
+            return None;
+        }
+
+        match header.r#type {
+            HOB_TYPE_HANDOFF => {
+                if offset != 0 {
+                    return None;
+                }
+            }
+            HOB_TYPE_RESOURCE_DESCRIPTOR => {
+                if header.length as usize != size_of::<ResourceDescription>() {
+                    return None;
+                }
+                let resource: ResourceDescription = hob.pread(offset).ok()?;
+                resource
+                    .physical_start
+                    .checked_add(resource.resource_length)?;
+                memory_size = memory_size.checked_add(resource.resource_length)?;
+            }
+            HOB_TYPE_END_OF_HOB_LIST => {
+                if header.length as usize != size_of::<Header>()
+                    || offset + size_of::<Header>() != hob.len()
+                {
+                    return None;
+                }
+                return Some(memory_size);
+            }
+            _ => return None,
+        }
+
+        offset = align_to_next_hob_offset(hob.len(), offset, header.length)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_td_hob() {
+        let hob = build_td_hob(0x1000_0000);
+        assert_eq!(
+            hob.len(),
+            size_of::<HandoffInfoTable>() + size_of::<ResourceDescription>() + size_of::<Header>()
+        );
+        assert_eq!(parse_td_hob(&hob), Some(0x1000_0000));
+
+        // Memory above LOW_MEMORY_TOP is reported above 4G.
+        let hob = build_td_hob(0x1_0000_0000);
+        assert_eq!(parse_td_hob(&hob), Some(0x1_0000_0000));
+        let resource: ResourceDescription = hob
+            .pread(size_of::<HandoffInfoTable>() + size_of::<ResourceDescription>())
+            .unwrap();
+        assert_eq!(resource.physical_start, SIZE_4G);
+        assert_eq!(resource.resource_length, 0x8000_0000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_build_td_hob_unaligned() {
+        build_td_hob(0x1000_0001);
+    }
+
+    #[test]
+    fn test_parse_td_hob() {
+        let hob = build_td_hob(0x2000_0000);
+        assert!(parse_td_hob(&[]).is_none());
+        assert!(parse_td_hob(&hob[..hob.len() - 1]).is_none());
+
+        // Missing end-of-HOB-list
+        let mut bad = hob.clone();
+        let end = bad.len() - size_of::<Header>();
+        bad[end..end + 2].copy_from_slice(&0x1234u16.to_le_bytes());
+        assert!(parse_td_hob(&bad).is_none());
+
+        // Non-zero reserved field
+        let mut bad = hob.clone();
+        bad[size_of::<HandoffInfoTable>() + 4] = 1;
+        assert!(parse_td_hob(&bad).is_none());
+    }
+}
@@ -5,7 +5,9 @@
 use byteorder::LittleEndian;
 use byteorder::ReadBytesExt;
 use core::fmt;
+use log::error;
 use r_efi::base::Guid;
+use schemars::JsonSchema;
 use scroll::Pread;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha384};
@@ -14,8 +16,18 @@ use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::mem::size_of;
+use std::ops::Range;
 use td_layout::build_time::TD_SHIM_METADATA_SIZE;
+use td_shim::event_log::{
+    payload_binary_measurement, payload_component_measurement, BootMeasurement, MeasuredData,
+    EXECUTABLE_PAYLOAD_MEASUREMENTS, MEASUREMENT_PAYLOAD_COMPONENT_TABLE, SEPARATOR_DATA,
+};
+use td_shim::measured_config::measured_configs;
 use td_shim::metadata::*;
+use td_shim::multi_component::MultiComponentPayload;
+use td_shim::secure_boot::{SignedPayloadType, CFV_FFS_HEADER_TRUST_ANCHOR_GUID};
+use td_uefi_pi::fv::get_file_from_fv;
+use td_uefi_pi::pi::fv::FV_FILETYPE_RAW;
 use zeroize::Zeroize;
 
 pub const SHA384_DIGEST_SIZE: usize = 0x30;
@@ -61,17 +73,22 @@ pub struct TdvfDesc {
     pub numberofsectionentry: [u8; size_of::<u32>()],
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct Manifest {
     #[serde(with = "hex::serde")]
+    #[schemars(with = "String")]
     pub attributes: [u8; size_of::<u64>()],
     #[serde(with = "hex::serde")]
+    #[schemars(with = "String")]
     pub xfam: [u8; size_of::<u64>()],
     #[serde(with = "hex::serde")]
+    #[schemars(with = "String")]
     pub mrconfigid: [u8; SHA384_DIGEST_SIZE],
     #[serde(with = "hex::serde")]
+    #[schemars(with = "String")]
     pub mrowner: [u8; SHA384_DIGEST_SIZE],
     #[serde(with = "hex::serde")]
+    #[schemars(with = "String")]
     pub mrownerconfig: [u8; SHA384_DIGEST_SIZE],
 }
 
@@ -260,6 +277,16 @@ impl TdInfoStruct {
             println!("{:?}", descriptor);
             panic!("The descriptor is not valid!\n");
         }
+        if let Err(TdxMetadataError::SectionCountMismatch {
+            declared,
+            available,
+        }) = validate_section_count(&descriptor, desc.len() - size_of::<TdxMetadataDescriptor>())
+        {
+            panic!(
+                "The section table is corrupted: {} sections declared but only {} available!\n",
+                declared, available
+            );
+        }
 
         desc_offset += size_of::<TdxMetadataDescriptor>();
 
@@ -269,16 +296,28 @@ impl TdInfoStruct {
 
         let mut sha384hasher = Sha384::new();
 
+        // DataOffset	    0	UINT32	4	The offset to the raw section in the binary image.
+        // RawDataSize	    4	UINT32	4	The size of the raw section in the image.
+        // MemoryAddress	8	UINT64	8	The guest physical address of the section loaded.
+        // MemoryDataSize	16	UINT64	8	The size of the section loaded.
+        // Type	            24	UINT32	4	The type of the TDVF_SECTION. See table 1.1-4.
+        // Attributes	    28	UINT32	4	The attribute of the section. See Table 1.1-3.
+        let mut sections = Vec::new();
         for _i in 0..descriptor.number_of_section_entry {
-            // DataOffset	    0	UINT32	4	The offset to the raw section in the binary image.
-            // RawDataSize	    4	UINT32	4	The size of the raw section in the image.
-            // MemoryAddress	8	UINT64	8	The guest physical address of the section loaded.
-            // MemoryDataSize	16	UINT64	8	The size of the section loaded.
-            // Type	            24	UINT32	4	The type of the TDVF_SECTION. See table 1.1-4.
-            // Attributes	    28	UINT32	4	The attribute of the section. See Table 1.1-3.
             let sec: TdxMetadataSection = desc.pread(desc_offset).unwrap();
             desc_offset += size_of::<TdxMetadataSection>();
+            sections.push(sec);
+        }
+
+        let misaligned = find_misaligned_measured_sections(&sections);
+        for section in misaligned.iter() {
+            error!("{}", section);
+        }
+        if !misaligned.is_empty() {
+            panic!("Data offset and raw data size of measured sections must be 4K aligned!\n");
+        }
 
+        for sec in sections {
             // sanity check
             if sec.memory_address % PAGE_SIZE != 0 {
                 panic!("Memory address must be 4K aligned!\n");
@@ -333,6 +372,229 @@ impl TdInfoStruct {
     }
 }
 
+/// Measurement register extended by a measurement step.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MeasurementRegister {
+    Mrtd,
+    Rtmr(u32),
+}
+
+impl fmt::Display for MeasurementRegister {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeasurementRegister::Mrtd => write!(f, "MRTD"),
+            MeasurementRegister::Rtmr(index) => write!(f, "RTMR[{}]", index),
+        }
+    }
+}
+
+/// A step of the measurement sequence performed for a td-shim image.
+#[derive(Clone, Debug)]
+pub struct MeasurementStep {
+    pub register: MeasurementRegister,
+    /// Description of the measured data, the event descriptor for RTMR events.
+    pub description: String,
+    /// Region of the measured data, relative to the start of its firmware volume.
+    pub region: Range<usize>,
+    /// Digest extended into the register.
+    pub digest: [u8; SHA384_DIGEST_SIZE],
+}
+
+/// A section extended into MRTD whose data isn't page aligned in the image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MisalignedSection {
+    /// Index of the section in the TDX metadata section table.
+    pub index: usize,
+    pub data_offset: u32,
+    pub raw_data_size: u32,
+}
+
+impl fmt::Display for MisalignedSection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Measured section {}: data offset 0x{:x} and raw data size 0x{:x} must be 4K aligned",
+            self.index, self.data_offset, self.raw_data_size
+        )
+    }
+}
+
+/// Find the sections with `TDX_METADATA_ATTRIBUTES_EXTENDMR` whose data offset or raw data size
+/// isn't 4K aligned.
+///
+/// TDH.MR.EXTEND measures whole pages read from the image at the data offset, so the data of such
+/// a section would be measured together with bytes of its neighbours.
+pub fn find_misaligned_measured_sections(
+    sections: &[TdxMetadataSection],
+) -> Vec<MisalignedSection> {
+    sections
+        .iter()
+        .enumerate()
+        .filter(|(_, sec)| {
+            sec.attributes & TDX_METADATA_ATTRIBUTES_EXTENDMR != 0
+                && (sec.data_offset as u64 % PAGE_SIZE != 0
+                    || sec.raw_data_size as u64 % PAGE_SIZE != 0)
+        })
+        .map(|(index, sec)| MisalignedSection {
+            index,
+            data_offset: sec.data_offset,
+            raw_data_size: sec.raw_data_size,
+        })
+        .collect()
+}
+
+/// Predict the measurement steps contributed by the Configuration Firmware Volume (CFV).
+///
+/// The CFV is not extended into MRTD. td-shim extends each measured config enrolled into the CFV
+/// into RTMR[0]. When secure boot is enabled, td-shim then extends the trust anchor enrolled into
+/// the CFV into RTMR[0] twice: first as the provisioned policy database, then as the authority
+/// which verified the payload, as declared by `EXECUTABLE_PAYLOAD_MEASUREMENTS`. No step is
+/// returned if the CFV contains neither measured configs nor a trust anchor.
+pub fn predict_cfv_measurements(cfv: &[u8]) -> Vec<MeasurementStep> {
+    cfv_measurements(cfv, true)
+}
+
+// The trust anchor steps are only predicted if `signed`, td-shim doesn't log the trust anchor for
+// an unsigned payload.
+fn cfv_measurements(cfv: &[u8], signed: bool) -> Vec<MeasurementStep> {
+    let trust_anchor =
+        get_file_from_fv(cfv, FV_FILETYPE_RAW, CFV_FFS_HEADER_TRUST_ANCHOR_GUID).filter(|_| signed);
+    let step = |m: &BootMeasurement, data: &[u8]| {
+        let start = data.as_ptr() as usize - cfv.as_ptr() as usize;
+        let mut digest = [0u8; SHA384_DIGEST_SIZE];
+        digest.copy_from_slice(Sha384::digest(data).as_slice());
+        MeasurementStep {
+            register: MeasurementRegister::Rtmr(m.rtmr_index()),
+            description: String::from_utf8_lossy(m.descriptor)
+                .trim_end_matches('\0')
+                .to_string(),
+            region: start..start + data.len(),
+            digest,
+        }
+    };
+
+    let mut steps = Vec::new();
+    for m in EXECUTABLE_PAYLOAD_MEASUREMENTS.iter() {
+        match m.data {
+            MeasuredData::CfvConfig => {
+                steps.extend(measured_configs(cfv).map(|config| step(m, config.content)))
+            }
+            MeasuredData::SecurePolicyDb | MeasuredData::SecureAuthority => {
+                if let Some(trust_anchor) = trust_anchor {
+                    steps.push(step(m, trust_anchor));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    steps
+}
+
+/// Predict the measurement steps of booting the executable payload `payload_image` with the CFV
+/// `cfv`, in the order of `EXECUTABLE_PAYLOAD_MEASUREMENTS`.
+///
+/// `payload_svn` is the SVN of the signed payload, it's measured only if the CFV contains a trust
+/// anchor, as td-shim is built with secure boot then. `None` means the payload is unsigned: td-shim
+/// built with `allow-unsigned-payload` boots it without logging the trust anchor nor the SVN. The
+/// TD HOB event is not predicted, the TD HOB is built by the VMM. Regions of the payload steps are
+/// relative to the payload image.
+///
+/// `rtmr_index` is the RTMR the payload binary is measured into, as recorded by the verify header
+/// of the signed payload, see `PayloadSignHeader::payload_rtmr_index()`.
+///
+/// `payload_type` is the payload type recorded by the verify header. A payload of the
+/// multi-component type is measured per component if the CFV contains a trust anchor, as td-shim
+/// boots it as such only once verified: the table of contents and each component are steps of
+/// their own, in place of the payload binary step.
+pub fn predict_payload_measurements(
+    cfv: &[u8],
+    payload_image: &[u8],
+    payload_svn: Option<u64>,
+    rtmr_index: u32,
+    payload_type: SignedPayloadType,
+) -> Vec<MeasurementStep> {
+    let step = |m: &BootMeasurement, data: &[u8], region: Range<usize>| {
+        let mut digest = [0u8; SHA384_DIGEST_SIZE];
+        digest.copy_from_slice(Sha384::digest(data).as_slice());
+        MeasurementStep {
+            register: MeasurementRegister::Rtmr(m.rtmr_index()),
+            description: String::from_utf8_lossy(m.descriptor)
+                .trim_end_matches('\0')
+                .to_string(),
+            region,
+            digest,
+        }
+    };
+    let secure_boot =
+        get_file_from_fv(cfv, FV_FILETYPE_RAW, CFV_FFS_HEADER_TRUST_ANCHOR_GUID).is_some();
+
+    let components = match payload_type {
+        SignedPayloadType::MultiComponent if secure_boot => {
+            MultiComponentPayload::parse(payload_image)
+        }
+        _ => None,
+    };
+
+    let mut steps = cfv_measurements(cfv, payload_svn.is_some());
+    for m in EXECUTABLE_PAYLOAD_MEASUREMENTS.iter() {
+        match m.data {
+            MeasuredData::PayloadSvn => {
+                if let (true, Some(svn)) = (secure_boot, payload_svn) {
+                    steps.push(step(m, &svn.to_le_bytes(), 0..0));
+                }
+            }
+            MeasuredData::PayloadBinary => match components {
+                Some(payload) => {
+                    let table = payload.table();
+                    steps.push(step(
+                        &MEASUREMENT_PAYLOAD_COMPONENT_TABLE,
+                        table,
+                        0..table.len(),
+                    ));
+                    for c in payload.components() {
+                        let m = payload_component_measurement(c.rtmr_index);
+                        steps.push(step(&m, c.data, c.offset..c.offset + c.data.len()));
+                    }
+                }
+                None => steps.push(step(
+                    &payload_binary_measurement(rtmr_index),
+                    payload_image,
+                    0..payload_image.len(),
+                )),
+            },
+            MeasuredData::Separator => steps.push(step(m, &SEPARATOR_DATA, 0..0)),
+            _ => {}
+        }
+    }
+
+    steps
+}
+
+/// Extend the RTMR value `rtmr` with `digest` as TDG.MR.RTMR.EXTEND does, return the new value.
+pub fn extend_rtmr(
+    rtmr: &[u8; SHA384_DIGEST_SIZE],
+    digest: &[u8; SHA384_DIGEST_SIZE],
+) -> [u8; SHA384_DIGEST_SIZE] {
+    let mut sha384hasher = Sha384::new();
+    sha384hasher.update(rtmr);
+    sha384hasher.update(digest);
+
+    let mut extended = [0u8; SHA384_DIGEST_SIZE];
+    extended.copy_from_slice(sha384hasher.finalize().as_slice());
+    extended
+}
+
+/// Replay the measurement steps extending RTMR[`index`], starting from zero.
+pub fn replay_rtmr(steps: &[MeasurementStep], index: u32) -> [u8; SHA384_DIGEST_SIZE] {
+    steps
+        .iter()
+        .filter(|v| v.register == MeasurementRegister::Rtmr(index))
+        .fold([0u8; SHA384_DIGEST_SIZE], |rtmr, step| {
+            extend_rtmr(&rtmr, &step.digest)
+        })
+}
+
 fn fill_buffer128_with_mem_page_add(buf: &mut [u8; MRTD_EXTENSION_BUFFER_SIZE], gpa: u64) {
     buf.zeroize();
 
@@ -365,3 +627,224 @@ fn fill_buffer3_128_with_mr_extend(
     file.read_exact(&mut buf[1]).unwrap();
     file.read_exact(&mut buf[2]).unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_predict_cfv_measurements() {
+        let mut cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv").to_vec();
+
+        let steps = predict_cfv_measurements(&cfv);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].description, "secure_policy_db");
+        assert_eq!(steps[1].description, "secure_authority");
+        for step in steps.iter() {
+            assert_eq!(step.register, MeasurementRegister::Rtmr(0));
+            assert_eq!(format!("{}", step.register), "RTMR[0]");
+            assert_eq!(step.region.start, 0x90);
+            assert_eq!(
+                &step.digest[..],
+                Sha384::digest(&cfv[step.region.clone()]).as_slice()
+            );
+        }
+        let rtmr0 = replay_rtmr(&steps, 0);
+        assert_ne!(rtmr0, [0u8; SHA384_DIGEST_SIZE]);
+        assert_eq!(replay_rtmr(&steps, 1), [0u8; SHA384_DIGEST_SIZE]);
+
+        // Enroll the hash of another key, the trust anchor hash is stored at offset 0xb8.
+        let other_key = Sha384::digest(b"another key");
+        cfv[0xb8..0xb8 + SHA384_DIGEST_SIZE].copy_from_slice(other_key.as_slice());
+        let enrolled = predict_cfv_measurements(&cfv);
+        assert_eq!(enrolled.len(), 2);
+        assert_eq!(enrolled[0].region, steps[0].region);
+        assert_ne!(enrolled[0].digest, steps[0].digest);
+        assert_ne!(replay_rtmr(&enrolled, 0), rtmr0);
+
+        // CFV without trust anchor doesn't contribute to the measurements.
+        assert!(predict_cfv_measurements(&[0u8; 0x1000]).is_empty());
+    }
+
+    #[cfg(feature = "enroller")]
+    #[test]
+    fn test_predict_measured_config() {
+        use crate::enroller::{create_measured_config_file, enroll_files, FirmwareRawFile};
+        use std::fs;
+        use std::str::FromStr;
+        use td_layout::build_time::{
+            TD_SHIM_CONFIG_OFFSET, TD_SHIM_CONFIG_SIZE, TD_SHIM_FIRMWARE_SIZE,
+        };
+        use td_shim::measured_config::MeasuredConfigHeader;
+
+        let enroll = |files: Vec<FirmwareRawFile>| {
+            let dir = std::env::temp_dir();
+            let image = dir.join(format!("td-shim-config-{}", std::process::id()));
+            let enrolled = dir.join(format!("td-shim-config-enrolled-{}", std::process::id()));
+            fs::write(&image, vec![0u8; TD_SHIM_FIRMWARE_SIZE as usize]).unwrap();
+            enroll_files(image.to_str().unwrap(), enrolled.clone(), files).unwrap();
+            let data = fs::read(&enrolled).unwrap();
+            fs::remove_file(&image).unwrap();
+            fs::remove_file(&enrolled).unwrap();
+            data[TD_SHIM_CONFIG_OFFSET as usize
+                ..(TD_SHIM_CONFIG_OFFSET + TD_SHIM_CONFIG_SIZE) as usize]
+                .to_vec()
+        };
+        let guid = *td_uefi_pi::pi::guid::Guid::from_str("214D240F-77A3-441B-9DA8-C588E43192C1")
+            .unwrap()
+            .as_bytes();
+
+        let blob = b"console=ttyS0";
+        let cfv = enroll(vec![create_measured_config_file(
+            &guid,
+            "boot_config",
+            blob,
+        )
+        .unwrap()]);
+        let steps = predict_cfv_measurements(&cfv);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].register, MeasurementRegister::Rtmr(0));
+        assert_eq!(steps[0].description, "cfv_config");
+        // The header and the config blob are measured.
+        let mut content = MeasuredConfigHeader::new(b"boot_config", blob.len())
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        content.extend_from_slice(blob);
+        assert_eq!(&cfv[steps[0].region.clone()], content.as_slice());
+        assert_eq!(&steps[0].digest[..], Sha384::digest(&content).as_slice());
+        let rtmr0 = replay_rtmr(&steps, 0);
+        assert_ne!(rtmr0, [0u8; SHA384_DIGEST_SIZE]);
+
+        // Another config blob predicts another RTMR[0].
+        let cfv = enroll(vec![create_measured_config_file(
+            &guid,
+            "boot_config",
+            b"console=hvc0",
+        )
+        .unwrap()]);
+        assert_ne!(replay_rtmr(&predict_cfv_measurements(&cfv), 0), rtmr0);
+
+        // Plain firmware files are not measured.
+        let mut plain = FirmwareRawFile::new(&guid);
+        plain.append(blob);
+        assert!(predict_cfv_measurements(&enroll(vec![plain])).is_empty());
+
+        assert!(create_measured_config_file(&guid, "", blob).is_err());
+        assert!(create_measured_config_file(&guid, "a_very_long_config_name", blob).is_err());
+    }
+
+    #[test]
+    fn test_predict_payload_measurements() {
+        let cfv = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = b"payload image";
+
+        let chain = |digests: &[&[u8]]| {
+            let mut rtmr = [0u8; SHA384_DIGEST_SIZE];
+            for data in digests {
+                let mut sha384hasher = Sha384::new();
+                sha384hasher.update(rtmr);
+                sha384hasher.update(Sha384::digest(data));
+                rtmr.copy_from_slice(sha384hasher.finalize().as_slice());
+            }
+            rtmr
+        };
+
+        let steps =
+            predict_payload_measurements(cfv, payload, Some(3), 1, SignedPayloadType::Unspecified);
+        assert_eq!(steps.len(), 6);
+        assert_eq!(
+            replay_rtmr(&steps, 1),
+            chain(&[&3u64.to_le_bytes(), payload, &SEPARATOR_DATA])
+        );
+        let trust_anchor =
+            get_file_from_fv(cfv, FV_FILETYPE_RAW, CFV_FFS_HEADER_TRUST_ANCHOR_GUID).unwrap();
+        assert_eq!(
+            replay_rtmr(&steps, 0),
+            chain(&[trust_anchor, trust_anchor, &SEPARATOR_DATA])
+        );
+
+        // Without secure boot, the SVN is not measured.
+        let steps = predict_payload_measurements(
+            &[0u8; 0x100],
+            payload,
+            Some(3),
+            1,
+            SignedPayloadType::Unspecified,
+        );
+        assert_eq!(replay_rtmr(&steps, 1), chain(&[payload, &SEPARATOR_DATA]));
+        assert_eq!(replay_rtmr(&steps, 0), chain(&[&SEPARATOR_DATA]));
+
+        // The payload binary is measured into the RTMR requested by the verify header, the SVN
+        // and the separator are not moved.
+        let steps =
+            predict_payload_measurements(cfv, payload, Some(3), 2, SignedPayloadType::Unspecified);
+        assert_eq!(steps[3].register, MeasurementRegister::Rtmr(2));
+        assert_eq!(replay_rtmr(&steps, 2), chain(&[payload]));
+        assert_eq!(
+            replay_rtmr(&steps, 1),
+            chain(&[&3u64.to_le_bytes(), &SEPARATOR_DATA])
+        );
+        let steps =
+            predict_payload_measurements(cfv, payload, Some(3), 0, SignedPayloadType::Unspecified);
+        assert_eq!(
+            replay_rtmr(&steps, 0),
+            chain(&[trust_anchor, trust_anchor, payload, &SEPARATOR_DATA])
+        );
+
+        // An unsigned payload is booted without logging the trust anchor nor the SVN, even if the
+        // CFV contains a trust anchor.
+        let steps =
+            predict_payload_measurements(cfv, payload, None, 1, SignedPayloadType::Unspecified);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(replay_rtmr(&steps, 1), chain(&[payload, &SEPARATOR_DATA]));
+        assert_eq!(replay_rtmr(&steps, 0), chain(&[&SEPARATOR_DATA]));
+    }
+
+    #[test]
+    fn test_find_misaligned_measured_sections() {
+        let mut sections = [
+            TdxMetadataSection {
+                data_offset: 0x2000,
+                raw_data_size: 0x1000,
+                memory_address: 0xfff02000,
+                memory_data_size: 0x1000,
+                r#type: TDX_METADATA_SECTION_TYPE_BFV,
+                attributes: TDX_METADATA_ATTRIBUTES_EXTENDMR,
+            },
+            // Not measured, so the data doesn't need to be page aligned.
+            TdxMetadataSection {
+                data_offset: 0x3100,
+                raw_data_size: 0x80,
+                memory_address: 0x800000,
+                memory_data_size: 0x1000,
+                r#type: TDX_METADATA_SECTION_TYPE_TD_HOB,
+                attributes: 0,
+            },
+        ];
+        assert!(find_misaligned_measured_sections(&sections).is_empty());
+
+        sections[0].data_offset = 0x2100;
+        sections[1].attributes = TDX_METADATA_ATTRIBUTES_EXTENDMR;
+        let misaligned = find_misaligned_measured_sections(&sections);
+        assert_eq!(
+            misaligned,
+            [
+                MisalignedSection {
+                    index: 0,
+                    data_offset: 0x2100,
+                    raw_data_size: 0x1000,
+                },
+                MisalignedSection {
+                    index: 1,
+                    data_offset: 0x3100,
+                    raw_data_size: 0x80,
+                },
+            ]
+        );
+
+        sections[0].data_offset = 0x2000;
+        sections[0].raw_data_size = 0x800;
+        assert_eq!(find_misaligned_measured_sections(&sections[..1]).len(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Trust stores of public keys trusted to sign payloads, for verifying signed payloads offline
+//! against more keys than a CFV holds.
+//!
+//! `DirTrustStore` loads a directory of trust anchors:
+//! - `<name>.pem`: PEM encoded SubjectPublicKeyInfo, `-----BEGIN PUBLIC KEY-----`.
+//! - `<name>.hash`: hex encoded SHA384 of the public key, as enrolled into the CFV.
+//!
+//! Other files in the directory are ignored.
+
+use std::path::Path;
+use std::{fs, io};
+
+use log::error;
+use td_shim::secure_boot::HashAlg;
+
+use crate::enroller::parse_pem_public_key;
+#[cfg(test)]
+use crate::enroller::{PEM_PUBLIC_KEY_BEGIN, PEM_PUBLIC_KEY_END};
+
+/// Maximum size of a trust anchor file.
+const MAX_TRUST_ANCHOR_SIZE: usize = 1024 * 1024;
+
+/// A set of trusted public keys.
+pub trait TrustStore {
+    /// Get the name of the trust anchor matching `public_key`, which is in the format of the
+    /// signature block of a signed payload, RSA:N||E or ECDSA:X||Y.
+    fn find_anchor(&self, public_key: &[u8]) -> Option<&str>;
+}
+
+/// Trust anchor of a `DirTrustStore`, the SHA384 of a trusted public key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustAnchor {
+    /// File name of the trust anchor.
+    pub name: String,
+    pub hash: Vec<u8>,
+}
+
+/// Trust store loaded from a directory of `.pem` and `.hash` files.
+#[derive(Debug, Default)]
+pub struct DirTrustStore {
+    anchors: Vec<TrustAnchor>,
+}
+
+fn anchor_error(name: &str, reason: &str) -> io::Error {
+    error!("Invalid trust anchor {}: {}", name, reason);
+    io::Error::new(io::ErrorKind::InvalidData, "invalid trust anchor")
+}
+
+fn parse_hash(text: &str, name: &str) -> io::Result<Vec<u8>> {
+    let hash = hex::decode(text.trim()).map_err(|_| anchor_error(name, "invalid hex"))?;
+    if hash.len() != HashAlg::Sha384.output_len() {
+        return Err(anchor_error(name, "expect the SHA384 of the public key"));
+    }
+
+    Ok(hash)
+}
+
+impl DirTrustStore {
+    /// Load every `.pem` and `.hash` file in `dir`, in the order of file names.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let read_error = |path: &Path, e: io::Error| {
+            error!("Can not read {}: {}", path.display(), e);
+            e
+        };
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).map_err(|e| read_error(dir, e))? {
+            let path = entry.map_err(|e| read_error(dir, e))?.path();
+            let ext = path.extension().and_then(|v| v.to_str());
+            if path.is_file() && matches!(ext, Some("pem") | Some("hash")) {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        let mut store = DirTrustStore::default();
+        for path in files {
+            let name = path.display().to_string();
+            let data = fs::read(&path).map_err(|e| read_error(&path, e))?;
+            if data.len() > MAX_TRUST_ANCHOR_SIZE {
+                return Err(anchor_error(&name, "file too big"));
+            }
+            let text = String::from_utf8(data).map_err(|_| anchor_error(&name, "not UTF-8"))?;
+            let hash = if path.extension().and_then(|v| v.to_str()) == Some("pem") {
+                let public_key = parse_pem_public_key(&text, &name)?;
+                HashAlg::Sha384.digest(&public_key).as_ref().to_vec()
+            } else {
+                parse_hash(&text, &name)?
+            };
+            store.add(TrustAnchor {
+                // Safe to unwrap() because the file has an extension.
+                name: path.file_name().unwrap().to_string_lossy().to_string(),
+                hash,
+            });
+        }
+
+        if store.anchors.is_empty() {
+            error!("No .pem or .hash trust anchor in {}", dir.display());
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no trust anchor found",
+            ));
+        }
+
+        Ok(store)
+    }
+
+    pub fn add(&mut self, anchor: TrustAnchor) {
+        self.anchors.push(anchor);
+    }
+
+    pub fn anchors(&self) -> &[TrustAnchor] {
+        &self.anchors
+    }
+}
+
+impl TrustStore for DirTrustStore {
+    fn find_anchor(&self, public_key: &[u8]) -> Option<&str> {
+        let hash = HashAlg::Sha384.digest(public_key);
+        self.anchors
+            .iter()
+            .find(|v| v.hash == hash.as_ref())
+            .map(|v| v.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ECDSA_PUBLIC_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-public.der");
+    const RSA_PUBLIC_KEY: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-public.der");
+
+    fn to_pem(der: &[u8]) -> String {
+        let encoded = base64::encode(der);
+        let lines: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(64)
+            .map(|v| std::str::from_utf8(v).unwrap())
+            .collect();
+        format!(
+            "{}\n{}\n{}\n",
+            PEM_PUBLIC_KEY_BEGIN,
+            lines.join("\n"),
+            PEM_PUBLIC_KEY_END
+        )
+    }
+
+    fn store_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("td-shim-trust-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_dir_trust_store() {
+        let ecdsa_key = parse_public_key(ECDSA_PUBLIC_KEY, "ecdsa").unwrap();
+        let rsa_key = parse_public_key(RSA_PUBLIC_KEY, "rsa").unwrap();
+
+        let dir = store_dir("match");
+        fs::write(dir.join("ecdsa.pem"), to_pem(ECDSA_PUBLIC_KEY)).unwrap();
+        let rsa_hash = hex::encode(HashAlg::Sha384.digest(&rsa_key));
+        fs::write(dir.join("rsa.hash"), format!("{}\n", rsa_hash)).unwrap();
+        fs::write(dir.join("README"), "not an anchor").unwrap();
+        let store = DirTrustStore::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(store.anchors().len(), 2);
+        assert_eq!(store.find_anchor(&ecdsa_key), Some("ecdsa.pem"));
+        assert_eq!(store.find_anchor(&rsa_key), Some("rsa.hash"));
+        assert_eq!(store.find_anchor(&ecdsa_key[1..]), None);
+    }
+
+    #[test]
+    fn test_dir_trust_store_mismatch() {
+        let ecdsa_key = parse_public_key(ECDSA_PUBLIC_KEY, "ecdsa").unwrap();
+
+        let dir = store_dir("mismatch");
+        fs::write(dir.join("rsa.pem"), to_pem(RSA_PUBLIC_KEY)).unwrap();
+        fs::write(dir.join("other.hash"), "00".repeat(48)).unwrap();
+        let store = DirTrustStore::load(&dir).unwrap();
+        assert_eq!(store.find_anchor(&ecdsa_key), None);
+
+        // Malformed anchors are rejected instead of ignored.
+        fs::write(dir.join("short.hash"), "0011").unwrap();
+        assert!(DirTrustStore::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = store_dir("empty");
+        assert!(DirTrustStore::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
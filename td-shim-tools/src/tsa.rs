@@ -0,0 +1,413 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Timestamp the payload signature by an RFC 3161 timestamp authority (TSA).
+//!
+//! The message imprint of the timestamp request is the SHA384 of the signature block, the public
+//! key followed by the signature, so the token proves the signature existed at the time of the
+//! timestamp. The token is stored in a sidecar file next to the signed payload.
+//!
+//! `check_timestamp_coverage()` checks that the token covers the signature block of the signed
+//! payload, it's not a verification of the token. The signature of the TSA over the token is not
+//! checked here, so anyone can forge a token passing the check. Please use the TSA certificate
+//! chain to verify the token, for example:
+//!
+//! ```text
+//! openssl ts -verify -in payload.tst -token_in -digest <SHA384 of the signature block> -CAfile tsa-ca.pem
+//! ```
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
+
+use log::{error, trace};
+use rand_core::RngCore;
+use ring::digest;
+use td_shim::secure_boot::{signature_block_size, PayloadSignHeader};
+
+use crate::cms::{
+    der, read_der, read_der_tag, DER_TAG_CONTEXT_0, DER_TAG_INTEGER, DER_TAG_NULL,
+    DER_TAG_OCTET_STRING, DER_TAG_SEQUENCE, OID_SHA384, OID_SIGNED_DATA,
+};
+
+const DER_TAG_BOOLEAN: u8 = 0x01;
+const DER_TAG_GENERALIZED_TIME: u8 = 0x18;
+
+// id-ct-TSTInfo, 1.2.840.113549.1.9.16.1.4
+const OID_TST_INFO: &[u8] = &[
+    0x06, 0x0b, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x10, 0x01, 0x04,
+];
+
+/// PKIStatus values of a TimeStampResp which carry a token.
+const PKI_STATUS_GRANTED: u8 = 0;
+const PKI_STATUS_GRANTED_WITH_MODS: u8 = 1;
+
+/// Maximum size of a TSA response.
+const MAX_RESPONSE_SIZE: u64 = 0x10_0000;
+const TSA_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn tsa_error(reason: &str) -> io::Error {
+    error!("Timestamp failed: {}", reason);
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Get the signature block of `signed_payload`, which is covered by the timestamp.
+pub fn timestamped_data(signed_payload: &[u8]) -> io::Result<&[u8]> {
+    let header = PayloadSignHeader::from_bytes(signed_payload)
+        .ok_or_else(|| tsa_error("invalid verify header"))?;
+    let size = signature_block_size(header.signing_algorithm)
+        .ok_or_else(|| tsa_error("unknown signing algorithm"))?;
+    signed_payload
+        .get(header.length as usize..)
+        .and_then(|v| v.get(..size))
+        .ok_or_else(|| tsa_error("truncated signature block"))
+}
+
+// Encode an unsigned big endian number as a DER INTEGER.
+fn der_unsigned(value: &[u8]) -> Vec<u8> {
+    let value = &value[value.iter().take_while(|v| **v == 0).count()..];
+    match value.first() {
+        None => der(DER_TAG_INTEGER, &[&[0]]),
+        Some(v) if *v & 0x80 != 0 => der(DER_TAG_INTEGER, &[&[0], value]),
+        Some(_) => der(DER_TAG_INTEGER, &[value]),
+    }
+}
+
+fn message_imprint(data: &[u8]) -> Vec<u8> {
+    let hash = digest::digest(&digest::SHA384, data);
+    der(
+        DER_TAG_SEQUENCE,
+        &[
+            &der(DER_TAG_SEQUENCE, &[OID_SHA384, &der(DER_TAG_NULL, &[])]),
+            &der(DER_TAG_OCTET_STRING, &[hash.as_ref()]),
+        ],
+    )
+}
+
+/// Build a DER encoded TimeStampReq over the SHA384 of `data`, asking for the TSA certificate.
+pub fn build_timestamp_request(data: &[u8], nonce: u64) -> Vec<u8> {
+    der(
+        DER_TAG_SEQUENCE,
+        &[
+            &der(DER_TAG_INTEGER, &[&[1]]),
+            &message_imprint(data),
+            &der_unsigned(&nonce.to_be_bytes()),
+            &der(DER_TAG_BOOLEAN, &[&[0xff]]),
+        ],
+    )
+}
+
+/// Get the DER encoded TimeStampToken from a DER encoded TimeStampResp.
+pub fn parse_timestamp_response(response: &[u8]) -> io::Result<&[u8]> {
+    let (response, _) = read_der_tag(response, DER_TAG_SEQUENCE)?;
+    let (status_info, rest) = read_der_tag(response.value, DER_TAG_SEQUENCE)?;
+    let (status, _) = read_der_tag(status_info.value, DER_TAG_INTEGER)?;
+    match status.value {
+        [PKI_STATUS_GRANTED] | [PKI_STATUS_GRANTED_WITH_MODS] => {}
+        _ => return Err(tsa_error("request rejected by the TSA")),
+    }
+    if rest.is_empty() {
+        return Err(tsa_error("no timestamp token in the TSA response"));
+    }
+    let (token, _) = read_der_tag(rest, DER_TAG_SEQUENCE)?;
+
+    Ok(token.encoded)
+}
+
+/// Fields of the TSTInfo of a timestamp token.
+#[derive(Debug)]
+pub struct TimestampInfo {
+    /// The genTime, in the GeneralizedTime format `YYYYMMDDhhmmss[.s...]Z`.
+    pub gen_time: String,
+    /// Content of the nonce INTEGER, `None` if there's no nonce.
+    pub nonce: Option<Vec<u8>>,
+}
+
+/// Check that the DER encoded TimeStampToken `token` covers `data`, and return its TSTInfo.
+///
+/// The signature of the TSA over the TSTInfo and its certificate chain are not verified, so the
+/// returned genTime is only claimed by the token.
+pub fn check_timestamp_coverage(token: &[u8], data: &[u8]) -> io::Result<TimestampInfo> {
+    let (content_info, _) = read_der_tag(token, DER_TAG_SEQUENCE)?;
+    let (oid, rest) = read_der(content_info.value)?;
+    if oid.encoded != OID_SIGNED_DATA {
+        return Err(tsa_error("timestamp token is not a CMS SignedData"));
+    }
+    let (explicit, _) = read_der_tag(rest, DER_TAG_CONTEXT_0)?;
+    let (signed_data, _) = read_der_tag(explicit.value, DER_TAG_SEQUENCE)?;
+    let (_version, rest) = read_der_tag(signed_data.value, DER_TAG_INTEGER)?;
+    let (_digest_algorithms, rest) = read_der(rest)?;
+    let (encapsulated, _) = read_der_tag(rest, DER_TAG_SEQUENCE)?;
+    let (oid, rest) = read_der(encapsulated.value)?;
+    if oid.encoded != OID_TST_INFO {
+        return Err(tsa_error("timestamp token doesn't contain a TSTInfo"));
+    }
+    let (explicit, _) = read_der_tag(rest, DER_TAG_CONTEXT_0)?;
+    let (content, _) = read_der_tag(explicit.value, DER_TAG_OCTET_STRING)?;
+
+    let (tst_info, _) = read_der_tag(content.value, DER_TAG_SEQUENCE)?;
+    let (_version, rest) = read_der_tag(tst_info.value, DER_TAG_INTEGER)?;
+    let (_policy, rest) = read_der(rest)?;
+    let (imprint, rest) = read_der_tag(rest, DER_TAG_SEQUENCE)?;
+    if imprint.encoded != message_imprint(data).as_slice() {
+        return Err(tsa_error("timestamp token doesn't cover the signature"));
+    }
+    let (_serial_number, rest) = read_der_tag(rest, DER_TAG_INTEGER)?;
+    let (gen_time, mut rest) = read_der_tag(rest, DER_TAG_GENERALIZED_TIME)?;
+    let gen_time = std::str::from_utf8(gen_time.value)
+        .map_err(|_| tsa_error("invalid genTime"))?
+        .to_string();
+
+    // The nonce is the only INTEGER following the genTime.
+    let mut nonce = None;
+    while !rest.is_empty() {
+        let (field, next) = read_der(rest)?;
+        if field.tag == DER_TAG_INTEGER {
+            nonce = Some(field.value.to_vec());
+            break;
+        }
+        rest = next;
+    }
+
+    Ok(TimestampInfo { gen_time, nonce })
+}
+
+// POST the DER encoded `request` to the TSA by HTTP, and return the body of the response.
+fn post_request(url: &str, request: &[u8]) -> io::Result<Vec<u8>> {
+    let address = url.strip_prefix("http://").ok_or_else(|| {
+        error!("Unsupported TSA URL {}, only http:// is supported", url);
+        io::Error::new(io::ErrorKind::InvalidInput, "unsupported TSA URL")
+    })?;
+    let (host, path) = match address.find('/') {
+        Some(pos) => (&address[..pos], &address[pos..]),
+        None => (address, "/"),
+    };
+    let server = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(&server).map_err(|e| {
+        error!("Can not connect to TSA {}: {}", server, e);
+        e
+    })?;
+    stream.set_read_timeout(Some(TSA_TIMEOUT))?;
+    stream.set_write_timeout(Some(TSA_TIMEOUT))?;
+    let head = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/timestamp-query\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        request.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(request)?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut response)?;
+
+    let end = response
+        .windows(4)
+        .position(|v| v == b"\r\n\r\n")
+        .ok_or_else(|| tsa_error("invalid HTTP response"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head.lines().next().unwrap_or_default();
+    trace!("TSA response: {}", status);
+    if status.split_whitespace().nth(1) != Some("200") {
+        error!("Unexpected TSA response: {}", status);
+        return Err(tsa_error("unexpected HTTP status"));
+    }
+
+    Ok(response[end + 4..].to_vec())
+}
+
+/// Get a timestamp token over `data` from the TSA at `url`.
+///
+/// The token is checked to cover `data` and to echo the nonce of the request.
+pub fn request_timestamp(url: &str, data: &[u8]) -> io::Result<(Vec<u8>, TimestampInfo)> {
+    let nonce = rand_core::OsRng.next_u64();
+    let request = build_timestamp_request(data, nonce);
+    let response = post_request(url, &request)?;
+    let token = parse_timestamp_response(&response)?;
+
+    let info = check_timestamp_coverage(token, data)?;
+    // The nonce INTEGER is at most 9 bytes, so its DER header is 2 bytes.
+    let expected = der_unsigned(&nonce.to_be_bytes());
+    if info.nonce.as_deref() != Some(&expected[2..]) {
+        return Err(tsa_error("nonce mismatch in the timestamp token"));
+    }
+
+    Ok((token.to_vec(), info))
+}
+
+/// Timestamp the signature of `signed_payload` by the TSA at `url`, and write the DER encoded
+/// timestamp token to `token_file`.
+pub fn timestamp_signed_payload(
+    url: &str,
+    signed_payload: &[u8],
+    token_file: &Path,
+) -> io::Result<TimestampInfo> {
+    let data = timestamped_data(signed_payload)?;
+    let (token, info) = request_timestamp(url, data)?;
+    fs::write(token_file, &token).map_err(|e| {
+        error!(
+            "Can not write timestamp token to {}: {}",
+            token_file.display(),
+            e
+        );
+        e
+    })?;
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+    const GEN_TIME: &str = "20221201080000Z";
+
+    // Build the TimeStampResp for a TimeStampReq, as a TSA would do except for signing the token.
+    fn build_response(request: &[u8]) -> Vec<u8> {
+        let (request, _) = read_der_tag(request, DER_TAG_SEQUENCE).unwrap();
+        let (_version, rest) = read_der_tag(request.value, DER_TAG_INTEGER).unwrap();
+        let (imprint, rest) = read_der_tag(rest, DER_TAG_SEQUENCE).unwrap();
+        let (nonce, _) = read_der_tag(rest, DER_TAG_INTEGER).unwrap();
+
+        let tst_info = der(
+            DER_TAG_SEQUENCE,
+            &[
+                &der(DER_TAG_INTEGER, &[&[1]]),
+                // 1.2.3.4
+                &[0x06, 0x03, 0x2a, 0x03, 0x04],
+                imprint.encoded,
+                &der(DER_TAG_INTEGER, &[&[0x12, 0x34]]),
+                &der(DER_TAG_GENERALIZED_TIME, &[GEN_TIME.as_bytes()]),
+                nonce.encoded,
+            ],
+        );
+        let signed_data = der(
+            DER_TAG_SEQUENCE,
+            &[
+                &der(DER_TAG_INTEGER, &[&[3]]),
+                &der(0x31, &[]),
+                &der(
+                    DER_TAG_SEQUENCE,
+                    &[
+                        OID_TST_INFO,
+                        &der(
+                            DER_TAG_CONTEXT_0,
+                            &[&der(DER_TAG_OCTET_STRING, &[&tst_info])],
+                        ),
+                    ],
+                ),
+                &der(0x31, &[]),
+            ],
+        );
+        let token = der(
+            DER_TAG_SEQUENCE,
+            &[OID_SIGNED_DATA, &der(DER_TAG_CONTEXT_0, &[&signed_data])],
+        );
+        der(
+            DER_TAG_SEQUENCE,
+            &[
+                &der(DER_TAG_SEQUENCE, &[&der(DER_TAG_INTEGER, &[&[0]])]),
+                &token,
+            ],
+        )
+    }
+
+    // Serve one timestamp request, return the URL of the mock TSA.
+    fn mock_tsa() -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/tsa", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 0x1000];
+            let end = loop {
+                let n = stream.read(&mut buf).unwrap();
+                assert_ne!(n, 0);
+                request.extend_from_slice(&buf[..n]);
+                if let Some(pos) = request.windows(4).position(|v| v == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8(request[..end].to_vec()).unwrap();
+            assert!(head.starts_with("POST /tsa HTTP/1.0\r\n"));
+            assert!(head.contains("Content-Type: application/timestamp-query\r\n"));
+            let length: usize = head
+                .lines()
+                .find_map(|v| v.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            while request.len() < end + length {
+                let n = stream.read(&mut buf).unwrap();
+                assert_ne!(n, 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let body = build_response(&request[end..]);
+            let head = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: application/timestamp-reply\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_timestamp_signed_payload() {
+        let (url, handle) = mock_tsa();
+        let token_file =
+            std::env::temp_dir().join(format!("td-shim-tsa-{}.tst", std::process::id()));
+        let info = timestamp_signed_payload(&url, PAYLOAD, &token_file).unwrap();
+        handle.join().unwrap();
+        assert_eq!(info.gen_time, GEN_TIME);
+
+        let token = fs::read(&token_file).unwrap();
+        fs::remove_file(&token_file).unwrap();
+        let data = timestamped_data(PAYLOAD).unwrap();
+        assert!(check_timestamp_coverage(&token, data).is_ok());
+
+        // The token doesn't cover another signature.
+        let mut other = data.to_vec();
+        other[0] ^= 0xff;
+        assert!(check_timestamp_coverage(&token, &other).is_err());
+        assert!(check_timestamp_coverage(&token[1..], data).is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp_response() {
+        let data = timestamped_data(PAYLOAD).unwrap();
+        let response = build_response(&build_timestamp_request(data, 0x8000_0000_0000_0001));
+        let token = parse_timestamp_response(&response).unwrap();
+        let info = check_timestamp_coverage(token, data).unwrap();
+        // The nonce is a positive INTEGER.
+        assert_eq!(
+            info.nonce.unwrap(),
+            [0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+
+        // The request is rejected.
+        let rejected = der(
+            DER_TAG_SEQUENCE,
+            &[&der(DER_TAG_SEQUENCE, &[&der(DER_TAG_INTEGER, &[&[2]])])],
+        );
+        assert!(parse_timestamp_response(&rejected).is_err());
+
+        assert!(request_timestamp("https://127.0.0.1/", data).is_err());
+        assert!(timestamped_data(&PAYLOAD[..0x100]).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Validate a td-shim image and collect every detected problem instead of stopping at the first.
+
+use std::fmt;
+use std::ops::Range;
+
+use scroll::{Pread, LE};
+use td_layout::build_time::{
+    TD_SHIM_CONFIG_OFFSET, TD_SHIM_FIRMWARE_SIZE, TD_SHIM_IPL_OFFSET, TD_SHIM_PAYLOAD_OFFSET,
+    TD_SHIM_PAYLOAD_SIZE,
+};
+use td_layout::runtime::exec::PAYLOAD_SIZE;
+use td_loader::{elf, pe};
+use td_shim::metadata::{
+    self, TdxMetadataError, TdxMetadataRef, TdxMetadataSection, TDX_METADATA_ATTRIBUTES_EXTENDMR,
+    TDX_METADATA_SECTION_TYPE_PAYLOAD,
+};
+use td_shim::secure_boot::{
+    signing_algorithm_name, CompactPayloadVerifier, PayloadSignHeader, PayloadVerifier,
+    SignedPayloadFormat, VerifyErr, SIGNED_PAYLOAD_FILE_HEADER_GUID,
+};
+use td_uefi_pi::fv::get_image_from_fv;
+use td_uefi_pi::pi::fv::{FirmwareVolumeHeader, FVH_SIGNATURE, FV_FILETYPE_DXE_CORE, SECTION_PE32};
+
+/// A problem detected in a td-shim image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidationProblem {
+    /// The TdxMetadata pointer, GUID or descriptor can't be found or is malformed.
+    InvalidMetadata(&'static str),
+    /// The descriptor declares more sections than the section table holds.
+    SectionCountMismatch { declared: u32, available: u32 },
+    /// The sections violate the rules of the TDX metadata specification.
+    InvalidSections,
+    /// Data of section `index` lies outside of the image.
+    SectionOutOfRange {
+        index: usize,
+        data_offset: u32,
+        raw_data_size: u32,
+    },
+    /// Data of two sections overlaps in the image.
+    SectionOverlap { first: usize, second: usize },
+    /// Two sections overlap in guest memory.
+    MemoryOverlap { first: usize, second: usize },
+    /// The payload section is larger than the payload region.
+    OversizedPayload { size: u64, max: u64 },
+    /// The firmware volume header at `offset` has a bad checksum.
+    BadFvChecksum { offset: usize },
+    /// The verify header of the signed payload is malformed.
+    InvalidVerifyHeader(VerifyErr),
+    /// The payload is not an ELF or PE image which can be loaded into the payload region.
+    InvalidPayloadImage,
+    /// The entry point declared by the payload is outside of the loaded payload image, offsets
+    /// are relative to the payload load base.
+    EntryPointMismatch { entry: u64, start: u64, end: u64 },
+}
+
+impl fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationProblem::InvalidMetadata(what) => write!(f, "invalid TdxMetadata {}", what),
+            ValidationProblem::SectionCountMismatch {
+                declared,
+                available,
+            } => write!(
+                f,
+                "{} sections declared but only {} available",
+                declared, available
+            ),
+            ValidationProblem::InvalidSections => write!(f, "invalid metadata sections"),
+            ValidationProblem::SectionOutOfRange {
+                index,
+                data_offset,
+                raw_data_size,
+            } => write!(
+                f,
+                "section {} data [0x{:x}, +0x{:x}) is out of image range",
+                index, data_offset, raw_data_size
+            ),
+            ValidationProblem::SectionOverlap { first, second } => {
+                write!(f, "data of section {} overlaps section {}", first, second)
+            }
+            ValidationProblem::MemoryOverlap { first, second } => {
+                write!(f, "memory of section {} overlaps section {}", first, second)
+            }
+            ValidationProblem::OversizedPayload { size, max } => write!(
+                f,
+                "payload size 0x{:x} exceeds payload region size 0x{:x}",
+                size, max
+            ),
+            ValidationProblem::BadFvChecksum { offset } => write!(
+                f,
+                "firmware volume header at 0x{:x} has a bad checksum",
+                offset
+            ),
+            ValidationProblem::InvalidVerifyHeader(e) => {
+                write!(f, "invalid payload verify header: {:?}", e)
+            }
+            ValidationProblem::InvalidPayloadImage => {
+                write!(f, "payload can't be loaded into the payload region")
+            }
+            ValidationProblem::EntryPointMismatch { entry, start, end } => write!(
+                f,
+                "payload entry point +0x{:x} is outside of the loaded image [+0x{:x}, +0x{:x})",
+                entry, start, end
+            ),
+        }
+    }
+}
+
+/// Entry point of a payload image loaded by td-shim, relative to the payload load base.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PayloadEntry {
+    pub entry: u64,
+    pub base: u64,
+    pub size: u64,
+}
+
+/// Get the executable image of a payload stored in the payload firmware volume.
+///
+/// The verify header of a signed payload, or the compact signature footer, is stripped. `None` is
+/// returned if it's malformed.
+pub fn payload_image(payload: &[u8]) -> Option<&[u8]> {
+    match SignedPayloadFormat::detect(payload) {
+        Some(SignedPayloadFormat::Standard) => PayloadVerifier::get_payload_image(payload).ok(),
+        Some(SignedPayloadFormat::Compact) => CompactPayloadVerifier::new(payload, &[])
+            .ok()
+            .map(|v| v.get_payload_image()),
+        None => Some(payload),
+    }
+}
+
+/// Load the ELF or PE `image` into a payload region as td-shim does, and cross-check the entry
+/// point declared by the image against the loaded image.
+///
+/// td-shim refuses to jump to an entry point outside of the loaded image, so a mismatch
+/// otherwise only shows up at boot time. Images declaring an entry point outside of their
+/// segments or sections are already rejected by the loader as invalid images.
+pub fn check_payload_entry(image: &[u8]) -> Result<PayloadEntry, ValidationProblem> {
+    let mut loaded = vec![0u8; PAYLOAD_SIZE];
+    let load_base = loaded.as_ptr() as u64;
+    let res = if elf::is_elf(image) {
+        elf::relocate_elf_mem_with_per_program_header(image, &mut loaded)
+    } else if pe::is_x86_64_pe(image) {
+        pe::relocate_pe_mem_with_per_sections(image, &mut loaded)
+    } else {
+        None
+    };
+    let (entry, base, size) = res.ok_or(ValidationProblem::InvalidPayloadImage)?;
+    if base < load_base
+        || base - load_base >= loaded.len() as u64
+        || size > loaded.len() as u64 - (base - load_base)
+    {
+        return Err(ValidationProblem::InvalidPayloadImage);
+    }
+
+    let entry = entry.wrapping_sub(load_base);
+    let base = base - load_base;
+    if entry < base || entry >= base + size {
+        return Err(ValidationProblem::EntryPointMismatch {
+            entry,
+            start: base,
+            end: base + size,
+        });
+    }
+
+    Ok(PayloadEntry { entry, base, size })
+}
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    !a.is_empty() && !b.is_empty() && a.start < b.end && b.start < a.end
+}
+
+/// Validate the layout, metadata, firmware volume checksums, verify header and payload entry point
+/// of `image`.
+///
+/// All detected problems are returned, an empty list means the image is valid.
+pub fn validate_image(image: &[u8]) -> Vec<ValidationProblem> {
+    let mut problems = Vec::new();
+
+    match validate_metadata(image) {
+        Ok(sections) => validate_sections(image, &sections, &mut problems),
+        Err(e) => problems.push(e),
+    }
+
+    // The firmware volumes are located by td-layout, as td-shim does at runtime.
+    if image.len() == TD_SHIM_FIRMWARE_SIZE as usize {
+        for offset in [
+            TD_SHIM_CONFIG_OFFSET,
+            TD_SHIM_PAYLOAD_OFFSET,
+            TD_SHIM_IPL_OFFSET,
+        ] {
+            validate_fv_header(image, offset as usize, &mut problems);
+        }
+        let payload_fv = &image[TD_SHIM_PAYLOAD_OFFSET as usize
+            ..(TD_SHIM_PAYLOAD_OFFSET + TD_SHIM_PAYLOAD_SIZE) as usize];
+        if let Some(payload) = get_image_from_fv(payload_fv, FV_FILETYPE_DXE_CORE, SECTION_PE32) {
+            validate_verify_header(payload, &mut problems);
+            // A malformed verify header has been reported above.
+            if let Some(image) = payload_image(payload) {
+                if let Err(e) = check_payload_entry(image) {
+                    problems.push(e);
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Size of the pages added to and measured into the TD.
+const MEASURED_PAGE_SIZE: u64 = 0x1000;
+
+/// A metadata section extended into MRTD by TDH.MR.EXTEND.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MeasuredSection {
+    /// Index of the section in the TDX metadata section table.
+    pub index: usize,
+    pub r#type: u32,
+    /// Range of the section data in the image.
+    pub file_range: Range<u64>,
+    /// Range of the section in guest memory.
+    pub memory_range: Range<u64>,
+    /// Number of pages measured, the whole memory range is measured page by page.
+    pub pages: u64,
+}
+
+/// Enumerate the sections measured into MRTD, that is the sections flagged with
+/// `TDX_METADATA_ATTRIBUTES_EXTENDMR`, in the order of the metadata section table, which is the
+/// order they are measured.
+///
+/// Nothing is measured from an image whose TDX metadata can't be parsed.
+pub fn measured_sections(image: &[u8]) -> impl Iterator<Item = MeasuredSection> {
+    validate_metadata(image)
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, s)| s.attributes & TDX_METADATA_ATTRIBUTES_EXTENDMR != 0)
+        .map(|(index, s)| MeasuredSection {
+            index,
+            r#type: s.r#type,
+            file_range: s.data_offset as u64..s.data_offset as u64 + s.raw_data_size as u64,
+            memory_range: s.memory_address..s.memory_address.saturating_add(s.memory_data_size),
+            pages: (s.memory_data_size + MEASURED_PAGE_SIZE - 1) / MEASURED_PAGE_SIZE,
+        })
+}
+
+fn validate_metadata(image: &[u8]) -> Result<Vec<TdxMetadataSection>, ValidationProblem> {
+    let metadata = TdxMetadataRef::parse(image).map_err(|e| match e {
+        TdxMetadataError::SectionCountMismatch {
+            declared,
+            available,
+        } => ValidationProblem::SectionCountMismatch {
+            declared,
+            available,
+        },
+        TdxMetadataError::InvalidPointer => ValidationProblem::InvalidMetadata("pointer"),
+        TdxMetadataError::InvalidGuid => ValidationProblem::InvalidMetadata("GUID"),
+        TdxMetadataError::InvalidDescriptor => ValidationProblem::InvalidMetadata("descriptor"),
+        _ => ValidationProblem::InvalidMetadata("section table"),
+    })?;
+
+    Ok(metadata.sections().collect())
+}
+
+fn validate_sections(
+    image: &[u8],
+    sections: &[TdxMetadataSection],
+    problems: &mut Vec<ValidationProblem>,
+) {
+    if metadata::validate_sections(sections).is_err() {
+        problems.push(ValidationProblem::InvalidSections);
+    }
+
+    let data_range = |s: &TdxMetadataSection| {
+        s.data_offset as u64..s.data_offset as u64 + s.raw_data_size as u64
+    };
+    let memory_range = |s: &TdxMetadataSection| {
+        s.memory_address..s.memory_address.saturating_add(s.memory_data_size)
+    };
+
+    for (index, section) in sections.iter().enumerate() {
+        if data_range(section).end > image.len() as u64 {
+            problems.push(ValidationProblem::SectionOutOfRange {
+                index,
+                data_offset: section.data_offset,
+                raw_data_size: section.raw_data_size,
+            });
+        }
+        if section.r#type == TDX_METADATA_SECTION_TYPE_PAYLOAD
+            && section.raw_data_size > TD_SHIM_PAYLOAD_SIZE
+        {
+            problems.push(ValidationProblem::OversizedPayload {
+                size: section.raw_data_size as u64,
+                max: TD_SHIM_PAYLOAD_SIZE as u64,
+            });
+        }
+    }
+
+    for first in 0..sections.len() {
+        for second in first + 1..sections.len() {
+            let (a, b) = (&sections[first], &sections[second]);
+            if overlaps(&data_range(a), &data_range(b)) {
+                problems.push(ValidationProblem::SectionOverlap { first, second });
+            }
+            if overlaps(&memory_range(a), &memory_range(b)) {
+                problems.push(ValidationProblem::MemoryOverlap { first, second });
+            }
+        }
+    }
+}
+
+fn validate_fv_header(image: &[u8], offset: usize, problems: &mut Vec<ValidationProblem>) {
+    // Regions without a firmware volume, such as a CFV before enrollment, are skipped.
+    match image.pread_with::<FirmwareVolumeHeader>(offset, LE) {
+        Ok(header) if header.signature == FVH_SIGNATURE => {
+            if !header.validate_checksum() {
+                problems.push(ValidationProblem::BadFvChecksum { offset });
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_verify_header(payload: &[u8], problems: &mut Vec<ValidationProblem>) {
+    let header = match PayloadSignHeader::from_bytes(payload) {
+        Some(h) if &h.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() => h,
+        // Unsigned payload.
+        _ => return,
+    };
+
+    if signing_algorithm_name(header.signing_algorithm).is_none() {
+        problems.push(ValidationProblem::InvalidVerifyHeader(
+            VerifyErr::UnknownAlgorithm,
+        ));
+    }
+    if let Err(e) = PayloadVerifier::get_payload_image(payload) {
+        problems.push(ValidationProblem::InvalidVerifyHeader(e));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use td_shim::metadata::{
+        TdxMetadataDescriptor, TDX_METADATA_GUID, TDX_METADATA_OFFSET,
+        TDX_METADATA_SECTION_TYPE_BFV, TDX_METADATA_SECTION_TYPE_CFV,
+        TDX_METADATA_SECTION_TYPE_TD_HOB,
+    };
+
+    const IMAGE_SIZE: usize = 0x10000;
+    const METADATA_OFFSET: usize = 0x1000;
+
+    fn build_image(sections: &[TdxMetadataSection]) -> Vec<u8> {
+        let mut image = vec![0u8; IMAGE_SIZE];
+        let mut descriptor = TdxMetadataDescriptor::default();
+        descriptor.set_sections(sections.len() as u32);
+
+        let mut offset = METADATA_OFFSET;
+        image[offset..offset + 16].copy_from_slice(TDX_METADATA_GUID.as_bytes());
+        offset += 16;
+        image[offset..offset + 16].copy_from_slice(&descriptor.to_bytes());
+        for (i, section) in sections.iter().enumerate() {
+            let pos = offset + 16 + i * 32;
+            image[pos..pos + 32].copy_from_slice(&section.to_bytes());
+        }
+        let ptr = IMAGE_SIZE - TDX_METADATA_OFFSET as usize;
+        image[ptr..ptr + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+
+        image
+    }
+
+    fn bfv() -> TdxMetadataSection {
+        TdxMetadataSection {
+            data_offset: 0x8000,
+            raw_data_size: 0x4000,
+            memory_address: 0xfff08000,
+            memory_data_size: 0x4000,
+            r#type: TDX_METADATA_SECTION_TYPE_BFV,
+            attributes: TDX_METADATA_ATTRIBUTES_EXTENDMR,
+        }
+    }
+
+    fn cfv() -> TdxMetadataSection {
+        TdxMetadataSection {
+            data_offset: 0x2000,
+            raw_data_size: 0x2000,
+            memory_address: 0xfff02000,
+            memory_data_size: 0x2000,
+            r#type: TDX_METADATA_SECTION_TYPE_CFV,
+            attributes: 0,
+        }
+    }
+
+    #[test]
+    fn test_validate_image() {
+        let image = build_image(&[bfv(), cfv()]);
+        assert!(validate_image(&image).is_empty());
+
+        let mut image = build_image(&[bfv(), cfv()]);
+        image[METADATA_OFFSET] ^= 0xff;
+        assert_eq!(
+            validate_image(&image),
+            vec![ValidationProblem::InvalidMetadata("GUID")]
+        );
+
+        assert_eq!(
+            validate_image(&[0u8; 8]),
+            vec![ValidationProblem::InvalidMetadata("pointer")]
+        );
+    }
+
+    #[test]
+    fn test_measured_sections() {
+        let hob = TdxMetadataSection {
+            data_offset: 0,
+            raw_data_size: 0,
+            memory_address: 0x800000,
+            memory_data_size: 0x2000,
+            r#type: TDX_METADATA_SECTION_TYPE_TD_HOB,
+            attributes: 0,
+        };
+        let mut payload = bfv();
+        payload.data_offset = 0xc000;
+        payload.raw_data_size = 0x1000;
+        payload.memory_address = 0x1000000;
+        payload.memory_data_size = 0x3000;
+        payload.r#type = TDX_METADATA_SECTION_TYPE_PAYLOAD;
+
+        let image = build_image(&[cfv(), bfv(), hob, payload]);
+        let measured: Vec<MeasuredSection> = measured_sections(&image).collect();
+        assert_eq!(
+            measured,
+            vec![
+                MeasuredSection {
+                    index: 1,
+                    r#type: TDX_METADATA_SECTION_TYPE_BFV,
+                    file_range: 0x8000..0xc000,
+                    memory_range: 0xfff08000..0xfff0c000,
+                    pages: 4,
+                },
+                MeasuredSection {
+                    index: 3,
+                    r#type: TDX_METADATA_SECTION_TYPE_PAYLOAD,
+                    file_range: 0xc000..0xd000,
+                    memory_range: 0x1000000..0x1003000,
+                    pages: 3,
+                },
+            ]
+        );
+
+        assert_eq!(measured_sections(&[0u8; 8]).count(), 0);
+    }
+
+    #[test]
+    fn test_check_payload_entry() {
+        let elf = include_bytes!("../../data/blobs/td-payload.elf");
+        let entry = check_payload_entry(elf).unwrap();
+        assert!(entry.entry >= entry.base && entry.entry < entry.base + entry.size);
+
+        let efi = include_bytes!("../../data/blobs/td-payload.efi");
+        assert!(check_payload_entry(efi).is_ok());
+
+        // Move the entry point declared by the ELF header out of the loaded image, the loader
+        // refuses to load it.
+        let mut elf = elf.to_vec();
+        let e_entry = entry.base + entry.size + 0x1000;
+        elf[0x18..0x20].copy_from_slice(&e_entry.to_le_bytes());
+        assert_eq!(
+            check_payload_entry(&elf),
+            Err(ValidationProblem::InvalidPayloadImage)
+        );
+
+        assert_eq!(
+            check_payload_entry(&[0x5au8; 0x100]),
+            Err(ValidationProblem::InvalidPayloadImage)
+        );
+        assert_eq!(payload_image(&[0x5au8; 0x100]), Some(&[0x5au8; 0x100][..]));
+    }
+
+    #[test]
+    fn test_validate_image_report_all() {
+        // Two independent defects: the CFV runs past the end of the image and overlaps the BFV
+        // in guest memory.
+        let mut cfv = cfv();
+        cfv.data_offset = 0xe000;
+        cfv.raw_data_size = 0x4000;
+        cfv.memory_address = 0xfff0a000;
+        cfv.memory_data_size = 0x4000;
+        let image = build_image(&[bfv(), cfv]);
+
+        assert_eq!(
+            validate_image(&image),
+            vec![
+                ValidationProblem::SectionOutOfRange {
+                    index: 1,
+                    data_offset: 0xe000,
+                    raw_data_size: 0x4000,
+                },
+                ValidationProblem::MemoryOverlap {
+                    first: 0,
+                    second: 1
+                },
+            ]
+        );
+    }
+}
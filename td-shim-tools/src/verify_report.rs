@@ -0,0 +1,174 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Machine-readable result of verifying a signed payload, for CI pipelines to assert on.
+
+use std::io;
+
+use log::error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use td_shim::secure_boot::{
+    signing_algorithm_name, HashAlg, PayloadSignHeader, SignatureStatus, VerifyErr,
+};
+
+/// Outcome of verifying a payload.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyStatus {
+    /// The primary signature is valid.
+    Verified,
+    /// The payload carries no signature, as built without signing.
+    Unsigned,
+    /// The payload is malformed or its primary signature is invalid.
+    #[default]
+    Invalid,
+}
+
+/// Result of verifying the primary signature of a signed payload.
+///
+/// Fields unknown at the point of failure, or not recorded by the compact format, are `None`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub verified: bool,
+    pub status: VerifyStatus,
+    pub algorithm: Option<String>,
+    pub version: Option<u64>,
+    pub svn: Option<u64>,
+    /// Hex encoded SHA384 of the public key of the primary signature.
+    pub key_fingerprint: Option<String>,
+    pub error: Option<String>,
+}
+
+impl VerifyReport {
+    /// Build the report of the primary signature `status` of a signed payload with verify header
+    /// `header`, `None` for the compact format.
+    pub fn new(header: Option<&PayloadSignHeader>, status: &SignatureStatus) -> Self {
+        let algorithm = signing_algorithm_name(status.signing_algorithm)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| format!("unknown({:#x})", status.signing_algorithm));
+        let key_fingerprint = if status.public_key.is_empty() {
+            None
+        } else {
+            Some(hex::encode(HashAlg::Sha384.digest(status.public_key)))
+        };
+
+        VerifyReport {
+            verified: status.result.is_ok(),
+            status: match status.result {
+                Ok(_) => VerifyStatus::Verified,
+                Err(_) => VerifyStatus::Invalid,
+            },
+            algorithm: Some(algorithm),
+            version: header.map(|v| v.payload_version),
+            svn: header.map(|v| v.payload_svn),
+            key_fingerprint,
+            error: status.result.err().map(|e| format!("{:?}", e)),
+        }
+    }
+
+    /// Build the report of a signed payload which fails before its signature is checked.
+    pub fn from_error(error: &str) -> Self {
+        VerifyReport {
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Build the report of a payload carrying no signature.
+    pub fn unsigned() -> Self {
+        VerifyReport {
+            status: VerifyStatus::Unsigned,
+            error: Some(format!("{:?}", VerifyErr::Unsigned)),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string(self).map_err(|e| {
+            error!("Can not serialize verify report: {}", e);
+            io::Error::new(io::ErrorKind::Other, "can not serialize verify report")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+    use td_shim::secure_boot::PayloadVerifier;
+
+    const CFV: &[u8] = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+    const SIGNED_PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+    fn report_json(signed_payload: &[u8], cfv: &[u8]) -> Value {
+        let header = PayloadSignHeader::from_bytes(signed_payload).unwrap();
+        let status = PayloadVerifier::new(signed_payload, cfv)
+            .unwrap()
+            .verify_all();
+        let json = VerifyReport::new(Some(&header), &status[0])
+            .to_json()
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_verify_report_pass() {
+        let header = PayloadSignHeader::from_bytes(SIGNED_PAYLOAD).unwrap();
+        let report = report_json(SIGNED_PAYLOAD, CFV);
+
+        assert_eq!(report["verified"], Value::Bool(true));
+        assert_eq!(report["status"], Value::from("verified"));
+        assert!(report["algorithm"].is_string());
+        assert_eq!(report["version"], Value::from(header.payload_version));
+        assert_eq!(report["svn"], Value::from(header.payload_svn));
+        assert_eq!(report["key_fingerprint"].as_str().unwrap().len(), 96);
+        assert!(report["error"].is_null());
+    }
+
+    #[test]
+    fn test_verify_report_fail() {
+        // No trust anchor.
+        let report = report_json(SIGNED_PAYLOAD, &[]);
+        assert_eq!(report["verified"], Value::Bool(false));
+        assert_eq!(report["status"], Value::from("invalid"));
+        assert!(report["algorithm"].is_string());
+        assert!(report["svn"].is_u64());
+        assert!(report["key_fingerprint"].is_string());
+        assert_eq!(report["error"], Value::from("InvalidPublicKey"));
+
+        // Tampered signature.
+        let mut tampered = SIGNED_PAYLOAD.to_vec();
+        *tampered.last_mut().unwrap() ^= 0xff;
+        let report = report_json(&tampered, CFV);
+        assert_eq!(report["status"], Value::from("invalid"));
+        assert_eq!(report["error"], Value::from("InvalidSignature"));
+
+        let json = VerifyReport::from_error("invalid signed payload")
+            .to_json()
+            .unwrap();
+        let report: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(report["verified"], Value::Bool(false));
+        assert_eq!(report["status"], Value::from("invalid"));
+        assert!(report["version"].is_null());
+        assert_eq!(report["error"], Value::from("invalid signed payload"));
+    }
+
+    #[test]
+    fn test_verify_report_unsigned() {
+        let image = include_bytes!("../../data/blobs/td-payload.elf");
+        assert_eq!(
+            PayloadVerifier::new(image, CFV).err(),
+            Some(VerifyErr::Unsigned)
+        );
+
+        let json = VerifyReport::unsigned().to_json().unwrap();
+        let report: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(report["verified"], Value::Bool(false));
+        assert_eq!(report["status"], Value::from("unsigned"));
+        assert!(report["algorithm"].is_null());
+        assert_eq!(report["error"], Value::from("Unsigned"));
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Known-answer tests of the payload signing algorithms.
+//!
+//! Each vector signs the version 1 verify header built by `build_sign_header()` followed by
+//! `KAT_PAYLOAD`, with the sample keys in data/sample-keys. The vectors are re-derived by
+//! tests/kat/derive_vectors.py, which only needs Python and the openssl command line:
+//! - RSAPSS_3072_SHA384 is deterministic for a fixed salt, so the signature produced by the
+//!   signer must be exactly `KAT_RSA_SIGNATURE`, computed per RFC 8017 section 8.1.1 and checked
+//!   by openssl. The salt is injected through `ring`, which has no other way to fix it.
+//! - ECDSA_NIST_P384_SHA384 nonces follow RFC 6979, hedged with the entropy read by
+//!   `--rng file:PATH`, so the signature produced by the signer from `KAT_ECDSA_ENTROPY` must be
+//!   exactly `KAT_ECDSA_SIGNATURE`. The script checks its RFC 6979 nonces against the published
+//!   vectors of RFC 6979 appendix A.2.6, which td-shim must also verify.
+
+use ring::test::rand::FixedSliceRandom;
+use scroll::Endian;
+use td_shim::secure_boot::{
+    verify_signature_block, HashAlg, PayloadVerifier, VerifyErr,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+    PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1, PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+};
+use td_shim_tools::signer::{build_sign_header, RngSource, SigningAlgorithm};
+
+const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../../data/sample-keys/rsa-3072-private.pk8");
+const ECDSA_PRIVATE_KEY: &[u8] = include_bytes!("../../data/sample-keys/ecdsa-p384-private.pk8");
+
+const KAT_PAYLOAD: &[u8] = b"td-shim known-answer test payload\n";
+
+/// RSA-PSS salt, bytes 0x00 to 0x2f.
+const KAT_RSA_SALT: [u8; 48] = {
+    let mut salt = [0u8; 48];
+    let mut i = 0;
+    while i < salt.len() {
+        salt[i] = i as u8;
+        i += 1;
+    }
+    salt
+};
+
+const KAT_RSA_SIGNATURE: &str = "\
+    1fc19d34b19ed892256a6fa85404114d64e700e613edbde1d61f559c17466c93994d04295f41b358af42e8bf867e1f4e\
+    7021cad90dc7461174c2a3c6ddb2c601342059cc3dd5f37dd02b659915e0492b17eae51d5b8f52991b44cbdc0cc65104\
+    d7a75e4ca376794d0175b20f3f313bdab44200021c0fa9c91edd692df35a6dd2c4a65960cf6c2aa16680721083388247\
+    dada184a0cbce7166b133da7d207104112f150fc27d9ec8e0a0f4721ba1e101a4c41fb35d5d0e948202100cadac1af8d\
+    36ea5a9d0de4c999f25317754650789a2a028f9d1bb4a73cae785327d83eb10d861d56c3dd6a766501353d4c2a9cceb\
+    c999a30fd345dd8ee52d1ded99ea3078aa13654afaf35b271ec3cd552c6642657b28f777f403c617cfec6d3d8c60135b7\
+    8dd192626a5fabbcbe8a3b1911838dd954c151b314944672dcf23a34cd6de27f5f72dc56c869d911c52f2e4fa28316de\
+    07d279af785e75fc2d2e537ebebab5c850c0263e17ba25b62fae49d1792c01ba5d79bda2fdfb54e926b318fac85ffe87";
+
+/// Entropy mixed into the ECDSA nonce, read from `--rng file:PATH`.
+const KAT_ECDSA_ENTROPY: [u8; 48] = [0xa5; 48];
+
+/// ECDSA signature in the R||S form, with the nonce hedged by `KAT_ECDSA_ENTROPY`.
+const KAT_ECDSA_SIGNATURE: &str = "\
+    72e712ddefe51a1464785222e140dc45c25b9145594628a0049dc0f66f8fac98a3ed6907bc3228607e69d45c775a1369\
+    dc1d09d821ee82ba5316508c4d36276306a314ce83718a8d25d2fbc06078383a4808aedba394b36dbf8fda4d8a50cdb1";
+
+/// ECDSA signature in ASN.1 DER encoding, with the deterministic RFC 6979 nonce.
+const KAT_ECDSA_ASN1_SIGNATURE: &str = "\
+    306402306850c2d80175a130e1c2677ed62a390357b6abfde51231b5b116c100f4ac61ed028aeaf6337f007137d9eb2\
+    8c73e224d02303b96ec17ab6fc5c809ef1d4dc9383d6c953ef7432bcf65a7da701e191bcb849b899ee0942a5a754b67\
+    0cfe8369bd5c2f";
+
+/// Public key of RFC 6979 appendix A.2.6, ECDSA P-384 with SHA-384, in the X||Y form.
+const RFC6979_PUBLIC_KEY: &str = "\
+    ec3a4e415b4e19a4568618029f427fa5da9a8bc4ae92e02e06aae5286b300c64def8f0ea9055866064a254515480bc13\
+    8015d9b72d7d57244ea8ef9ac0c621896708a59367f9dfb9f54ca84b3f1c9db1288b231c3ae0d4fe7344fd2533264720";
+
+/// Messages and R||S signatures of RFC 6979 appendix A.2.6 with SHA-384.
+const RFC6979_SIGNATURES: [(&[u8], &str); 2] = [
+    (
+        b"sample",
+        "94edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa73d64c4ea95ad133c81a648152e44acf96e36dd1e80fabe46\
+         99ef4aeb15f178cea1fe40db2603138f130e740a19624526203b6351d0a3a94fa329c145786e679e7b82c71a38628ac8",
+    ),
+    (
+        b"test",
+        "8203b63d3c853e8d77227fb377bcf7b7b772e97892a80f36ab775d509d7a5feb0542a7f0812998da8f1dd3ca3cf023db\
+         ddd0760448d42d8a43af45af836fce4de8be06b485e9b61b827c2f13173923e06a739f040649a667bf3b828246baa5a5",
+    ),
+];
+
+/// The "sample" signature of RFC 6979 appendix A.2.6 in ASN.1 DER encoding.
+const RFC6979_ASN1_SIGNATURE: &str = "\
+    306602310094edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa73d64c4ea95ad133c81a648152e44acf96e36d\
+    d1e80fabe4602310099ef4aeb15f178cea1fe40db2603138f130e740a19624526203b6351d0a3a94fa329c145786e67\
+    9e7b82c71a38628ac8";
+
+// The signed region of the vectors, verify header followed by the payload.
+fn kat_message(signing_algorithm: u32) -> Vec<u8> {
+    let header = build_sign_header(
+        signing_algorithm,
+        Endian::Little,
+        None,
+        KAT_PAYLOAD.len(),
+        1,
+        1,
+    );
+    let mut message = header.to_bytes();
+    message.extend_from_slice(KAT_PAYLOAD);
+    message
+}
+
+fn verify(message: &[u8], public_key: &[u8], signature: &[u8]) -> Result<(), VerifyErr> {
+    let mut signed_payload = message.to_vec();
+    signed_payload.extend_from_slice(public_key);
+    signed_payload.extend_from_slice(signature);
+    PayloadVerifier::new(&signed_payload, &[])?.verify_signature()
+}
+
+// Sign `message` with the entropy `entropy` read from a file, as `--rng file:PATH` does.
+fn sign_with_entropy(algorithm: &SigningAlgorithm, message: &[u8], entropy: &[u8]) -> Vec<u8> {
+    let path = std::env::temp_dir().join(format!("td-shim-kat-entropy-{}", std::process::id()));
+    std::fs::write(&path, entropy).unwrap();
+    let block = algorithm.sign_block(message, &RngSource::File(path.clone()));
+    std::fs::remove_file(&path).unwrap();
+    block.unwrap()
+}
+
+fn tampered(signature: &[u8]) -> Vec<u8> {
+    let mut signature = signature.to_vec();
+    signature[10] ^= 0x01;
+    signature
+}
+
+// Every signing algorithm must be covered by a known-answer test, adding a variant to
+// `SigningAlgorithm` fails to build here until its vectors are added.
+fn kat_algorithm(algorithm: &SigningAlgorithm) -> &'static str {
+    match algorithm {
+        SigningAlgorithm::Rsapss3072Sha384(_) => "test_kat_rsapss_3072_sha384",
+        SigningAlgorithm::EcdsaNistP384Sha384(_) => "test_kat_ecdsa_nist_p384_sha384",
+    }
+}
+
+#[test]
+fn test_kat_rsapss_3072_sha384() {
+    let algorithm = SigningAlgorithm::from_pkcs8("RSAPSS_3072_SHA384", RSA_PRIVATE_KEY).unwrap();
+    assert_eq!(kat_algorithm(&algorithm), "test_kat_rsapss_3072_sha384");
+    assert_eq!(algorithm.id(), PAYLOAD_SIGN_RSA_PSS_3072_SHA384);
+    let message = kat_message(algorithm.id());
+    let expected = hex::decode(KAT_RSA_SIGNATURE).unwrap();
+
+    let block = algorithm
+        .sign_block_with_rng(
+            &message,
+            &FixedSliceRandom {
+                bytes: &KAT_RSA_SALT,
+            },
+        )
+        .unwrap();
+    let public_key = algorithm.public_key_bytes();
+    assert_eq!(&block[..public_key.len()], public_key.as_slice());
+    assert_eq!(hex::encode(&block[public_key.len()..]), KAT_RSA_SIGNATURE);
+
+    assert_eq!(verify(&message, &public_key, &expected), Ok(()));
+    assert_eq!(
+        verify(&message, &public_key, &tampered(&expected)),
+        Err(VerifyErr::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_kat_ecdsa_nist_p384_sha384() {
+    let algorithm =
+        SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ECDSA_PRIVATE_KEY).unwrap();
+    assert_eq!(kat_algorithm(&algorithm), "test_kat_ecdsa_nist_p384_sha384");
+    assert_eq!(algorithm.id(), PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384);
+    let message = kat_message(algorithm.id());
+    let public_key = algorithm.public_key_bytes();
+
+    let expected = hex::decode(KAT_ECDSA_SIGNATURE).unwrap();
+
+    let block = sign_with_entropy(&algorithm, &message, &KAT_ECDSA_ENTROPY);
+    assert_eq!(&block[..public_key.len()], public_key.as_slice());
+    assert_eq!(hex::encode(&block[public_key.len()..]), KAT_ECDSA_SIGNATURE);
+
+    assert_eq!(verify(&message, &public_key, &expected), Ok(()));
+    assert_eq!(
+        verify(&message, &public_key, &tampered(&expected)),
+        Err(VerifyErr::InvalidSignature)
+    );
+}
+
+#[test]
+fn test_kat_ecdsa_nist_p384_sha384_rfc6979() {
+    let public_key = hex::decode(RFC6979_PUBLIC_KEY).unwrap();
+    let verify = |signing_algorithm: u32, message: &[u8], signature: &[u8]| {
+        let mut block = public_key.clone();
+        block.extend_from_slice(signature);
+        verify_signature_block(signing_algorithm, HashAlg::Sha384, &block, message)
+    };
+
+    for (message, signature) in RFC6979_SIGNATURES.iter() {
+        let signature = hex::decode(signature).unwrap();
+        assert_eq!(
+            verify(PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384, message, &signature),
+            Ok(())
+        );
+        assert_eq!(
+            verify(
+                PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+                message,
+                &tampered(&signature)
+            ),
+            Err(VerifyErr::InvalidSignature)
+        );
+    }
+    // Each signature only verifies its own message.
+    let signature = hex::decode(RFC6979_SIGNATURES[0].1).unwrap();
+    assert_eq!(
+        verify(
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            RFC6979_SIGNATURES[1].0,
+            &signature
+        ),
+        Err(VerifyErr::InvalidSignature)
+    );
+
+    let mut signature = hex::decode(RFC6979_ASN1_SIGNATURE).unwrap();
+    signature.resize(PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, 0);
+    assert_eq!(
+        verify(
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+            RFC6979_SIGNATURES[0].0,
+            &signature
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_kat_ecdsa_nist_p384_sha384_asn1() {
+    let algorithm =
+        SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", ECDSA_PRIVATE_KEY).unwrap();
+    let message = kat_message(PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1);
+    let public_key = algorithm.public_key_bytes();
+
+    // The DER signature is zero padded in the signature block.
+    let mut expected = hex::decode(KAT_ECDSA_ASN1_SIGNATURE).unwrap();
+    expected.resize(PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE, 0);
+    assert_eq!(verify(&message, &public_key, &expected), Ok(()));
+    assert_eq!(
+        verify(&message, &public_key, &tampered(&expected)),
+        Err(VerifyErr::InvalidSignature)
+    );
+}
@@ -0,0 +1,264 @@
+#!/usr/bin/env python3
+# Copyright (c) 2022 Intel Corporation
+#
+# SPDX-License-Identifier: BSD-2-Clause-Patent
+
+"""Re-derive the known-answer test vectors of tests/kat.rs.
+
+Only the Python standard library and the openssl command line are needed:
+
+    python3 td-shim-tools/tests/kat/derive_vectors.py
+
+The signed message is the version 1 verify header built by `build_sign_header()` followed by
+`KAT_PAYLOAD`, signed with the sample keys in data/sample-keys.
+
+- RSAPSS_3072_SHA384 is deterministic once the salt is fixed, the signature is computed here per
+  RFC 8017 section 8.1.1 (EMSA-PSS with MGF1-SHA384 and a 48-byte salt), then checked by openssl.
+- ECDSA_NIST_P384_SHA384 nonces are computed here per RFC 6979, which is first checked against
+  the published P-384/SHA-384 vectors of RFC 6979 appendix A.2.6. The signer mixes the 48 bytes
+  read by `--rng file:PATH` into the nonce as the additional data of RFC 6979 section 3.6, the
+  signature with `KAT_ECDSA_ENTROPY` is derived the same way. The ASN.1 vector uses the
+  deterministic nonce. Both signatures are checked by openssl.
+"""
+
+import hashlib
+import hmac
+import os
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+ROOT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "..", "..", "..")
+KEYS = os.path.join(ROOT, "data", "sample-keys")
+
+KAT_PAYLOAD = b"td-shim known-answer test payload\n"
+KAT_RSA_SALT = bytes(range(48))
+KAT_ECDSA_ENTROPY = bytes([0xA5] * 48)
+
+# NIST P-384.
+P384_P = 2**384 - 2**128 - 2**96 + 2**32 - 1
+P384_N = int(
+    "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf581a0db248b0a77aecec196accc52973",
+    16,
+)
+P384_G = (
+    int(
+        "aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a385502f25dbf55296c3a545e3872760ab7",
+        16,
+    ),
+    int(
+        "3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c00a60b1ce1d7e819d7a431d7c90ea0e5f",
+        16,
+    ),
+)
+
+# RFC 6979 appendix A.2.6, ECDSA P-384 with SHA-384: private key, public key and (r, s) per
+# message.
+RFC6979_X = int(
+    "6b9d3dad2e1b8c1c05b19875b6659f4de23c3b667bf297ba9aa47740787137d896d5724e4c70a825f872c9ea60d2edf5",
+    16,
+)
+RFC6979_Q = (
+    int(
+        "ec3a4e415b4e19a4568618029f427fa5da9a8bc4ae92e02e06aae5286b300c64def8f0ea9055866064a254515480bc13",
+        16,
+    ),
+    int(
+        "8015d9b72d7d57244ea8ef9ac0c621896708a59367f9dfb9f54ca84b3f1c9db1288b231c3ae0d4fe7344fd2533264720",
+        16,
+    ),
+)
+RFC6979_SIGNATURES = {
+    b"sample": (
+        int(
+            "94edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa73d64c4ea95ad133c81a648152e44acf96e36dd1e80fabe46",
+            16,
+        ),
+        int(
+            "99ef4aeb15f178cea1fe40db2603138f130e740a19624526203b6351d0a3a94fa329c145786e679e7b82c71a38628ac8",
+            16,
+        ),
+    ),
+    b"test": (
+        int(
+            "8203b63d3c853e8d77227fb377bcf7b7b772e97892a80f36ab775d509d7a5feb0542a7f0812998da8f1dd3ca3cf023db",
+            16,
+        ),
+        int(
+            "ddd0760448d42d8a43af45af836fce4de8be06b485e9b61b827c2f13173923e06a739f040649a667bf3b828246baa5a5",
+            16,
+        ),
+    ),
+}
+
+# FCF2D558-9DF5-4F4D-B0D7-3E4B798AB066
+SIGNED_PAYLOAD_FILE_HEADER_GUID = struct.pack(
+    "<IHH8B", 0xFCF2D558, 0x9DF5, 0x4F4D, 0xB0, 0xD7, 0x3E, 0x4B, 0x79, 0x8A, 0xB0, 0x66
+)
+
+
+def message(signing_algorithm):
+    header = SIGNED_PAYLOAD_FILE_HEADER_GUID
+    header += struct.pack("<IIQQI", 1, 48 + len(KAT_PAYLOAD), 1, 1, signing_algorithm)
+    # flags, payload type and reserved bytes
+    header += bytes(4)
+    return header + KAT_PAYLOAD
+
+
+def openssl(*args, data=None):
+    return subprocess.run(
+        ["openssl"] + list(args), input=data, capture_output=True, check=True
+    ).stdout
+
+
+def rsa_key(path):
+    text = openssl("pkey", "-inform", "DER", "-in", path, "-noout", "-text").decode()
+
+    def field(name):
+        block = re.search(name + r":\s*\n((?:\s+[0-9a-f:]+\n)+)", text).group(1)
+        return int(re.sub(r"[\s:]", "", block), 16)
+
+    return field("modulus"), field("privateExponent")
+
+
+def mgf1(seed, length):
+    mask = b""
+    counter = 0
+    while len(mask) < length:
+        mask += hashlib.sha384(seed + struct.pack(">I", counter)).digest()
+        counter += 1
+    return mask[:length]
+
+
+def rsa_pss_sign(n, d, msg, salt):
+    mod_bits = n.bit_length()
+    em_len = (mod_bits - 1 + 7) // 8
+    m_hash = hashlib.sha384(msg).digest()
+    h = hashlib.sha384(bytes(8) + m_hash + salt).digest()
+    db = bytes(em_len - len(salt) - len(h) - 2) + b"\x01" + salt
+    masked_db = bytearray(x ^ y for x, y in zip(db, mgf1(h, len(db))))
+    masked_db[0] &= 0xFF >> (8 * em_len - (mod_bits - 1))
+    em = bytes(masked_db) + h + b"\xbc"
+    return pow(int.from_bytes(em, "big"), d, n).to_bytes((mod_bits + 7) // 8, "big")
+
+
+def p384_add(a, b):
+    if a is None:
+        return b
+    if b is None:
+        return a
+    if a[0] == b[0] and (a[1] + b[1]) % P384_P == 0:
+        return None
+    if a == b:
+        slope = (3 * a[0] * a[0] - 3) * pow(2 * a[1], -1, P384_P)
+    else:
+        slope = (b[1] - a[1]) * pow(b[0] - a[0], -1, P384_P)
+    x = (slope * slope - a[0] - b[0]) % P384_P
+    return x, (slope * (a[0] - x) - a[1]) % P384_P
+
+
+def p384_mul(k, point):
+    result = None
+    while k:
+        if k & 1:
+            result = p384_add(result, point)
+        point = p384_add(point, point)
+        k >>= 1
+    return result
+
+
+def rfc6979_nonce(x, h, additional_data=b""):
+    # RFC 6979 section 3.2 with HMAC-SHA384, the additional data of section 3.6 follows h.
+    seed = x.to_bytes(48, "big") + (int.from_bytes(h, "big") % P384_N).to_bytes(48, "big")
+    seed += additional_data
+    k = bytes(48)
+    v = b"\x01" * 48
+    for tag in [b"\x00", b"\x01"]:
+        k = hmac.new(k, v + tag + seed, hashlib.sha384).digest()
+        v = hmac.new(k, v, hashlib.sha384).digest()
+    while True:
+        v = hmac.new(k, v, hashlib.sha384).digest()
+        nonce = int.from_bytes(v, "big")
+        if 1 <= nonce < P384_N:
+            return nonce
+        k = hmac.new(k, v + b"\x00", hashlib.sha384).digest()
+        v = hmac.new(k, v, hashlib.sha384).digest()
+
+
+def ecdsa_sign(x, msg, additional_data=b""):
+    h = hashlib.sha384(msg).digest()
+    nonce = rfc6979_nonce(x, h, additional_data)
+    r = p384_mul(nonce, P384_G)[0] % P384_N
+    s = pow(nonce, -1, P384_N) * (int.from_bytes(h, "big") + r * x) % P384_N
+    return r, s
+
+
+def der_integer(value):
+    data = value.to_bytes(49, "big").lstrip(b"\x00")
+    if data[0] & 0x80:
+        data = b"\x00" + data
+    return b"\x02" + bytes([len(data)]) + data
+
+
+def raw_to_der(r, s):
+    # SEQUENCE { INTEGER r, INTEGER s }, always shorter than 128 bytes for P-384.
+    body = der_integer(r) + der_integer(s)
+    return b"\x30" + bytes([len(body)]) + body
+
+
+def ecdsa_key(path):
+    text = openssl("pkey", "-inform", "DER", "-in", path, "-noout", "-text").decode()
+    block = re.search(r"priv:\s*\n((?:\s+[0-9a-f:]+\n)+)", text).group(1)
+    return int(re.sub(r"[\s:]", "", block), 16)
+
+
+def main():
+    rsa_private = os.path.join(KEYS, "rsa-3072-private.pk8")
+    n, d = rsa_key(rsa_private)
+    msg = message(2)
+    signature = rsa_pss_sign(n, d, msg, KAT_RSA_SALT)
+
+    with tempfile.TemporaryDirectory() as tmp:
+        paths = {name: os.path.join(tmp, name) for name in ["msg", "sig", "key.pem"]}
+        with open(paths["msg"], "wb") as f:
+            f.write(msg)
+        with open(paths["sig"], "wb") as f:
+            f.write(signature)
+        openssl("pkey", "-inform", "DER", "-in", rsa_private, "-pubout", "-out", paths["key.pem"])
+        openssl(
+            "dgst", "-sha384", "-sigopt", "rsa_padding_mode:pss", "-sigopt", "rsa_pss_saltlen:48",
+            "-verify", paths["key.pem"], "-signature", paths["sig"], paths["msg"],
+        )
+        print("RSAPSS_3072_SHA384 signature:\n" + signature.hex())
+
+        # The nonces must match the published vectors before deriving new ones.
+        assert p384_mul(RFC6979_X, P384_G) == RFC6979_Q
+        for msg, expected in RFC6979_SIGNATURES.items():
+            assert ecdsa_sign(RFC6979_X, msg) == expected
+
+        ecdsa_private = os.path.join(KEYS, "ecdsa-p384-private.pk8")
+        x = ecdsa_key(ecdsa_private)
+        openssl("pkey", "-inform", "DER", "-in", ecdsa_private, "-pubout", "-out", paths["key.pem"])
+        for algorithm, name in [(1, "ECDSA_NIST_P384_SHA384"), (3, "ECDSA_NIST_P384_SHA384_ASN1")]:
+            msg = message(algorithm)
+            additional_data = KAT_ECDSA_ENTROPY if algorithm == 1 else b""
+            r, s = ecdsa_sign(x, msg, additional_data)
+            with open(paths["msg"], "wb") as f:
+                f.write(msg)
+            with open(paths["sig"], "wb") as f:
+                f.write(raw_to_der(r, s))
+            openssl(
+                "dgst", "-sha384", "-verify", paths["key.pem"], "-signature", paths["sig"],
+                paths["msg"],
+            )
+            if algorithm == 1:
+                signature = r.to_bytes(48, "big") + s.to_bytes(48, "big")
+            else:
+                signature = raw_to_der(r, s)
+            print(name + " signature:\n" + signature.hex())
+
+
+if __name__ == "__main__":
+    sys.exit(main())
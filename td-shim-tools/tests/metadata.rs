@@ -0,0 +1,81 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! The TdxMetadata parsers of the tools agree with the in-place parser of td-shim, over the
+//! fixture used by the unit tests of `td_shim::metadata`.
+
+use std::fs;
+
+use td_shim::metadata::{
+    TdxMetadataSection, TDX_METADATA_SECTION_TYPE_BFV, TDX_METADATA_SECTION_TYPE_CFV,
+    TDX_METADATA_SECTION_TYPE_TD_HOB,
+};
+use td_shim_tools::loader::{TdShimLoader, TdxMetadataRef};
+use td_shim_tools::validator::{measured_sections, validate_image, ValidationProblem};
+
+const METADATA_IMAGE: &[u8] = include_bytes!("../../data/blobs/td-shim-metadata.bin");
+
+fn fields(sections: &[TdxMetadataSection]) -> Vec<(u32, u32, u64, u64, u32, u32)> {
+    sections
+        .iter()
+        .map(|s| {
+            (
+                s.data_offset,
+                s.raw_data_size,
+                s.memory_address,
+                s.memory_data_size,
+                s.r#type,
+                s.attributes,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_parsers_agree() {
+    let metadata = TdxMetadataRef::parse(METADATA_IMAGE).unwrap();
+    let expected = fields(&metadata.sections().collect::<Vec<_>>());
+    assert_eq!(
+        expected.iter().map(|s| s.4).collect::<Vec<_>>(),
+        vec![
+            TDX_METADATA_SECTION_TYPE_BFV,
+            TDX_METADATA_SECTION_TYPE_CFV,
+            TDX_METADATA_SECTION_TYPE_TD_HOB
+        ]
+    );
+
+    let (descriptor, sections) = TdShimLoader::parse_image(METADATA_IMAGE).unwrap();
+    assert_eq!(descriptor.number_of_section_entry, 3);
+    assert_eq!(fields(&sections), expected);
+
+    // The file based loader reads the image piece by piece.
+    let name = std::env::temp_dir().join(format!("td-shim-metadata-{}", std::process::id()));
+    fs::write(&name, METADATA_IMAGE).unwrap();
+    let parsed = TdShimLoader::parse(&name.to_str().unwrap().to_string());
+    fs::remove_file(&name).unwrap();
+    let (descriptor, sections) = parsed.unwrap();
+    assert_eq!(descriptor.length, metadata.descriptor().length);
+    assert_eq!(fields(&sections), expected);
+
+    assert!(validate_image(METADATA_IMAGE).is_empty());
+    let measured = measured_sections(METADATA_IMAGE).collect::<Vec<_>>();
+    assert_eq!(measured.len(), 1);
+    assert_eq!(measured[0].file_range, 0x800..0x1000);
+}
+
+#[test]
+fn test_parsers_reject() {
+    let mut image = METADATA_IMAGE.to_vec();
+    image[0x800] ^= 0xff;
+    assert!(TdxMetadataRef::parse(&image).is_err());
+    assert!(TdShimLoader::parse_image(&image).is_none());
+    assert_eq!(
+        validate_image(&image),
+        vec![ValidationProblem::InvalidMetadata("GUID")]
+    );
+    assert_eq!(
+        validate_image(&METADATA_IMAGE[..0x10]),
+        vec![ValidationProblem::InvalidMetadata("pointer")]
+    );
+}
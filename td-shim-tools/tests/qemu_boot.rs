@@ -0,0 +1,279 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! End-to-end boot test of a signed payload under a TDX capable QEMU.
+//!
+//! The example payload is signed, embedded into a shim image together with the trust anchor,
+//! measured and verified on the host, then the image is booted and the serial output is watched
+//! for `BOOT_MARKER`, which td-shim prints once the payload passed secure boot. An image enrolling
+//! another public key must not reach the marker.
+//!
+//! The test is built by the `qemu-test` feature only, and skips itself if the host lacks any
+//! prerequisite, please refer to doc/test_boot_in_qemu.md:
+//! - `TD_SHIM_QEMU` is the path of a TDX capable QEMU.
+//! - KVM is available and the kvm_intel module has TDX enabled.
+//! - The example payload is built, or `TD_SHIM_QEMU_PAYLOAD` is the path of the payload to sign.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use td_shim::secure_boot::{PayloadVerifier, SignedPayloadType, VerifyErr};
+use td_shim_tools::inspect::extract_region;
+use td_shim_tools::reference::predict_reference_values;
+use td_shim_tools::signer::{PayloadSigner, SigningAlgorithm};
+
+/// Serial output of td-shim once the payload passed secure boot and is about to start.
+const BOOT_MARKER: &str = "Starting td-payload hob";
+
+/// Default number of seconds to wait for `BOOT_MARKER`.
+const DEFAULT_BOOT_TIMEOUT: u64 = 30;
+
+const KVM_INTEL_TDX_PARAMETER: &str = "/sys/module/kvm_intel/parameters/tdx";
+const DEFAULT_PAYLOAD: &str = "target/x86_64-unknown-none/release/example";
+const ECDSA_PRIVATE_KEY: &str = "data/sample-keys/ecdsa-p384-private.pk8";
+const ECDSA_PUBLIC_KEY: &str = "data/sample-keys/ecdsa-p384-public.der";
+const RSA_PUBLIC_KEY: &str = "data/sample-keys/rsa-3072-public.der";
+
+/// Host prerequisites of the boot test.
+struct QemuEnv {
+    qemu: PathBuf,
+    payload: PathBuf,
+    timeout: Duration,
+}
+
+/// Serial output of a boot, up to `BOOT_MARKER` or the timeout.
+struct BootOutput {
+    reached_marker: bool,
+    output: String,
+}
+
+fn project_root() -> PathBuf {
+    // Safe to unwrap() because td-shim-tools lives in the workspace root.
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+impl QemuEnv {
+    /// Check the prerequisites, `None` if the test has to be skipped.
+    fn detect() -> Option<Self> {
+        match Self::check() {
+            Ok(v) => Some(v),
+            Err(reason) => {
+                eprintln!("Skipping the QEMU boot test: {}", reason);
+                None
+            }
+        }
+    }
+
+    fn check() -> Result<Self, String> {
+        let qemu = env::var_os("TD_SHIM_QEMU")
+            .map(PathBuf::from)
+            .ok_or("TD_SHIM_QEMU is not set to a TDX capable QEMU")?;
+        if !qemu.is_file() {
+            return Err(format!("QEMU {} does not exist", qemu.display()));
+        }
+        if !Path::new("/dev/kvm").exists() {
+            return Err("KVM is not available".to_string());
+        }
+        let tdx = fs::read_to_string(KVM_INTEL_TDX_PARAMETER).unwrap_or_default();
+        if !matches!(tdx.trim(), "Y" | "1") {
+            return Err("TDX is not enabled by the kvm_intel module".to_string());
+        }
+
+        let payload = env::var_os("TD_SHIM_QEMU_PAYLOAD")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| project_root().join(DEFAULT_PAYLOAD));
+        if !payload.is_file() {
+            return Err(format!(
+                "payload {} does not exist, build the example payload or set TD_SHIM_QEMU_PAYLOAD",
+                payload.display()
+            ));
+        }
+        let timeout = match env::var("TD_SHIM_QEMU_TIMEOUT") {
+            Ok(v) => v
+                .parse::<u64>()
+                .map_err(|_| format!("invalid TD_SHIM_QEMU_TIMEOUT {}", v))?,
+            Err(_) => DEFAULT_BOOT_TIMEOUT,
+        };
+
+        Ok(QemuEnv {
+            qemu,
+            payload,
+            timeout: Duration::from_secs(timeout),
+        })
+    }
+
+    /// Boot the shim image `firmware` and capture the serial output until `BOOT_MARKER` is
+    /// printed, QEMU exits or the timeout expires.
+    fn boot(&self, firmware: &Path) -> io::Result<BootOutput> {
+        let mut child = Command::new(&self.qemu)
+            .args(["-accel", "kvm"])
+            .args(["-name", "process=td-shim-test,debug-threads=on"])
+            .args(["-smp", "1,sockets=1", "-m", "2G"])
+            .args(["-object", "tdx-guest,id=tdx,debug=on"])
+            .args([
+                "-machine",
+                "q35,kvm-type=tdx,pic=no,kernel_irqchip=split,confidential-guest-support=tdx",
+            ])
+            .args(["-no-hpet", "-cpu", "host,pmu=off,-kvm-steal-time"])
+            .arg("-device")
+            .arg(format!("loader,file={},id=fd0", firmware.display()))
+            .args(["-nographic", "-vga", "none"])
+            .args(["-chardev", "stdio,id=mux,mux=on,signal=off"])
+            .args(["-device", "virtio-serial,romfile="])
+            .args(["-device", "virtconsole,chardev=mux"])
+            .args(["-serial", "chardev:mux", "-monitor", "chardev:mux"])
+            .arg("-no-reboot")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (tx, rx) = mpsc::channel();
+        forward_lines(child.stdout.take(), tx.clone());
+        forward_lines(child.stderr.take(), tx);
+
+        let deadline = Instant::now() + self.timeout;
+        let mut boot = BootOutput {
+            reached_marker: false,
+            output: String::new(),
+        };
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(line) => {
+                    boot.output.push_str(&line);
+                    boot.output.push('\n');
+                    if line.contains(BOOT_MARKER) {
+                        boot.reached_marker = true;
+                        break;
+                    }
+                }
+                // QEMU exited or the timeout expired.
+                Err(RecvTimeoutError::Disconnected) | Err(RecvTimeoutError::Timeout) => break,
+            }
+        }
+        kill(child);
+
+        Ok(boot)
+    }
+}
+
+// Send the lines read from `pipe` to `tx` until the pipe is closed.
+fn forward_lines<R: Read + Send + 'static>(pipe: Option<R>, tx: Sender<String>) {
+    if let Some(pipe) = pipe {
+        thread::spawn(move || {
+            for line in BufReader::new(pipe).lines() {
+                match line {
+                    Ok(line) if tx.send(line).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+    }
+}
+
+fn kill(mut child: Child) {
+    // QEMU may have exited already.
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn work_dir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("td-shim-qemu-boot-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Sign `payload` as an executable payload with the sample ECDSA key.
+fn sign_payload(payload: &Path) -> Vec<u8> {
+    let root = project_root();
+    let pkcs8 = fs::read(root.join(ECDSA_PRIVATE_KEY)).unwrap();
+    let payload = fs::read(payload).unwrap();
+    let algorithm = SigningAlgorithm::from_pkcs8("ECDSA_NIST_P384_SHA384", &pkcs8).unwrap();
+    let mut signer = PayloadSigner::new(&payload, algorithm);
+    signer.set_payload_type(SignedPayloadType::Executable);
+    let header = signer.build_header(1, 1);
+    signer.sign(header).unwrap().to_vec()
+}
+
+// Build a secure boot shim image embedding `signed_payload`, with the public key `public_key`
+// enrolled as the trust anchor.
+fn assemble_image(signed_payload: &Path, public_key: &str, output: &Path) {
+    let root = project_root();
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let status = Command::new(cargo)
+        .current_dir(&root)
+        .args(["image", "--release", "-t", "executable"])
+        .args(["--features", "secure-boot", "-H", "SHA384"])
+        .arg("-p")
+        .arg(signed_payload)
+        .arg("--enroll-key")
+        .arg(root.join(public_key))
+        .arg("-o")
+        .arg(output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "cargo image failed: {}", status);
+}
+
+// Verify the payload embedded into `image` against the trust anchor enrolled into its CFV, as
+// td-shim does at boot.
+fn verify_image(image: &Path) -> Result<(), VerifyErr> {
+    let image = image.to_str().unwrap();
+    let cfv = extract_region(image, "cfv").unwrap();
+    let payload = extract_region(image, "payload").unwrap();
+    PayloadVerifier::new(&payload, &cfv)?.verify()
+}
+
+#[test]
+fn test_boot_signed_payload() {
+    let env = match QemuEnv::detect() {
+        Some(v) => v,
+        None => return,
+    };
+    let dir = work_dir();
+
+    // Sign and embed.
+    let signed_payload = sign_payload(&env.payload);
+    let signed_file = dir.join("td-payload-signed");
+    fs::write(&signed_file, &signed_payload).unwrap();
+    let image = dir.join("final-sb-normal.bin");
+    assemble_image(&signed_file, ECDSA_PUBLIC_KEY, &image);
+
+    // Measure and verify on the host.
+    let reference = predict_reference_values(&image, &signed_payload).unwrap();
+    assert_eq!(reference.payload_svn, Some(1));
+    eprintln!("Reference values:\n{}", reference.to_json().unwrap());
+    assert_eq!(verify_image(&image), Ok(()));
+
+    // Boot.
+    let boot = env.boot(&image).unwrap();
+    assert!(
+        boot.reached_marker,
+        "{} was not reached:\n{}",
+        BOOT_MARKER, boot.output
+    );
+
+    // The payload is refused by an image trusting another public key.
+    let mismatch = dir.join("final-sb-mismatch-pubkey.bin");
+    assemble_image(&signed_file, RSA_PUBLIC_KEY, &mismatch);
+    assert_eq!(verify_image(&mismatch), Err(VerifyErr::InvalidPublicKey));
+    let boot = env.boot(&mismatch).unwrap();
+    assert!(
+        !boot.reached_marker,
+        "payload signed by an untrusted key was booted:\n{}",
+        boot.output
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,50 @@
+/*
+ * Copyright (c) 2022 Intel Corporation
+ *
+ * SPDX-License-Identifier: BSD-2-Clause-Patent
+ */
+
+#ifndef TD_SHIM_VERIFY_H
+#define TD_SHIM_VERIFY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The payload is verified. */
+#define TD_SHIM_VERIFY_OK                    0
+/* A pointer is NULL or the key hash is not a SHA384 digest. */
+#define TD_SHIM_VERIFY_INVALID_ARGUMENT     -1
+/* The signing algorithm of the payload is not supported. */
+#define TD_SHIM_VERIFY_UNKNOWN_ALGORITHM    -2
+/* The signed payload is malformed or truncated. */
+#define TD_SHIM_VERIFY_INVALID_CONTENT      -3
+/* The public key of the payload doesn't match the key hash. */
+#define TD_SHIM_VERIFY_INVALID_PUBLIC_KEY   -4
+/* The signature doesn't match the payload. */
+#define TD_SHIM_VERIFY_INVALID_SIGNATURE    -5
+/* The payload is rejected by the SVN policy attached to it. */
+#define TD_SHIM_VERIFY_SVN_POLICY_VIOLATION -6
+/* The public key of the payload is in the revocation list of the CFV. */
+#define TD_SHIM_VERIFY_REVOKED_PUBLIC_KEY   -7
+/* The payload carries no signature, as built without signing. */
+#define TD_SHIM_VERIFY_UNSIGNED             -8
+
+/*
+ * Verify the signed payload `image` of `len` bytes, whose public key must have the SHA384 hash
+ * `key_hash` of `hash_len` (48) bytes, the same hash enrolled into the CFV by td-shim-enroll.
+ *
+ * Return TD_SHIM_VERIFY_OK if the primary signature is valid and the payload is allowed by its
+ * SVN policy, a negative TD_SHIM_VERIFY_* error code otherwise. The buffers are only read during
+ * the call and stay owned by the caller.
+ */
+int td_shim_verify(const uint8_t *image, size_t len, const uint8_t *key_hash, size_t hash_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TD_SHIM_VERIFY_H */
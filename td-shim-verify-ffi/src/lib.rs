@@ -0,0 +1,150 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! C ABI to verify td-shim signed payloads, see `include/td_shim_verify.h`.
+//!
+//! The verification is done by `td_shim::secure_boot::PayloadVerifier`, the same code used by
+//! td-shim to verify the payload at boot time.
+
+use core::slice;
+
+use td_shim::secure_boot::{PayloadVerifier, VerifyErr};
+
+/// The payload is verified.
+pub const TD_SHIM_VERIFY_OK: i32 = 0;
+/// A pointer is NULL or the key hash is not a SHA384 digest.
+pub const TD_SHIM_VERIFY_INVALID_ARGUMENT: i32 = -1;
+/// The signing algorithm of the payload is not supported.
+pub const TD_SHIM_VERIFY_UNKNOWN_ALGORITHM: i32 = -2;
+/// The signed payload is malformed or truncated.
+pub const TD_SHIM_VERIFY_INVALID_CONTENT: i32 = -3;
+/// The public key of the payload doesn't match the key hash.
+pub const TD_SHIM_VERIFY_INVALID_PUBLIC_KEY: i32 = -4;
+/// The signature doesn't match the payload.
+pub const TD_SHIM_VERIFY_INVALID_SIGNATURE: i32 = -5;
+/// The payload is rejected by the SVN policy attached to it.
+pub const TD_SHIM_VERIFY_SVN_POLICY_VIOLATION: i32 = -6;
+/// The public key of the payload is in the revocation list of the CFV.
+pub const TD_SHIM_VERIFY_REVOKED_PUBLIC_KEY: i32 = -7;
+/// The payload carries no signature, as built without signing.
+pub const TD_SHIM_VERIFY_UNSIGNED: i32 = -8;
+
+/// Size of the SHA384 key hash.
+const SHA384_DIGEST_SIZE: usize = 48;
+
+fn error_code(e: VerifyErr) -> i32 {
+    match e {
+        VerifyErr::UnknownAlgorithm => TD_SHIM_VERIFY_UNKNOWN_ALGORITHM,
+        VerifyErr::InvalidContent
+        | VerifyErr::SizeMismatch { .. }
+        | VerifyErr::UnsupportedVersion(_) => TD_SHIM_VERIFY_INVALID_CONTENT,
+        VerifyErr::InvalidPublicKey => TD_SHIM_VERIFY_INVALID_PUBLIC_KEY,
+        VerifyErr::InvalidSignature => TD_SHIM_VERIFY_INVALID_SIGNATURE,
+        VerifyErr::SvnPolicyViolation => TD_SHIM_VERIFY_SVN_POLICY_VIOLATION,
+        VerifyErr::RevokedPublicKey => TD_SHIM_VERIFY_REVOKED_PUBLIC_KEY,
+        VerifyErr::Unsigned => TD_SHIM_VERIFY_UNSIGNED,
+    }
+}
+
+fn verify(image: &[u8], key_hash: &[u8]) -> Result<(), VerifyErr> {
+    let verifier = PayloadVerifier::new(image, &[])?;
+    verifier.verify_with_key_hash(key_hash)?;
+    verifier.check_svn_policy()
+}
+
+/// Verify the signed payload `image` of `len` bytes, whose public key must have the SHA384 hash
+/// `key_hash` of `hash_len` bytes, the same hash enrolled into the CFV by td-shim-enroll.
+///
+/// Return `TD_SHIM_VERIFY_OK` if the primary signature is valid and the payload is allowed by its
+/// SVN policy, a negative `TD_SHIM_VERIFY_*` error code otherwise. The buffers are only read
+/// during the call and stay owned by the caller.
+///
+/// # Safety
+///
+/// `image` and `key_hash` must point to readable buffers of at least `len` and `hash_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn td_shim_verify(
+    image: *const u8,
+    len: usize,
+    key_hash: *const u8,
+    hash_len: usize,
+) -> i32 {
+    if image.is_null() || key_hash.is_null() || hash_len != SHA384_DIGEST_SIZE {
+        return TD_SHIM_VERIFY_INVALID_ARGUMENT;
+    }
+    let image = slice::from_raw_parts(image, len);
+    let key_hash = slice::from_raw_parts(key_hash, hash_len);
+
+    match verify(image, key_hash) {
+        Ok(()) => TD_SHIM_VERIFY_OK,
+        Err(e) => error_code(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::ptr;
+
+    const CFV: &[u8] = include_bytes!("../../td-shim/fuzz/seeds/secure_boot_cfv/cfv");
+    const PAYLOAD: &[u8] =
+        include_bytes!("../../td-shim/fuzz/seeds/secure_boot_payload/td-payload-signed");
+    // The trust anchor enrolled into the CFV is the CFV public key file header followed by
+    // the key hash.
+    const CFV_PUBKEY_FILE_HEADER_SIZE: usize = 40;
+
+    fn key_hash() -> Vec<u8> {
+        let trust_anchor = PayloadVerifier::get_trust_anchor(CFV).unwrap();
+        trust_anchor[CFV_PUBKEY_FILE_HEADER_SIZE..][..SHA384_DIGEST_SIZE].to_vec()
+    }
+
+    fn verify(image: &[u8], key_hash: &[u8]) -> i32 {
+        unsafe {
+            td_shim_verify(
+                image.as_ptr(),
+                image.len(),
+                key_hash.as_ptr(),
+                key_hash.len(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_td_shim_verify() {
+        let key_hash = key_hash();
+        assert_eq!(verify(PAYLOAD, &key_hash), TD_SHIM_VERIFY_OK);
+
+        let mut other_key = key_hash.clone();
+        other_key[0] ^= 0xff;
+        assert_eq!(
+            verify(PAYLOAD, &other_key),
+            TD_SHIM_VERIFY_INVALID_PUBLIC_KEY
+        );
+
+        let mut tampered = PAYLOAD.to_vec();
+        tampered[0x1000] ^= 0xff;
+        assert_eq!(
+            verify(&tampered, &key_hash),
+            TD_SHIM_VERIFY_INVALID_SIGNATURE
+        );
+        assert_eq!(
+            verify(&PAYLOAD[..0x100], &key_hash),
+            TD_SHIM_VERIFY_INVALID_CONTENT
+        );
+        assert_eq!(verify(&[0u8; 0x100], &key_hash), TD_SHIM_VERIFY_UNSIGNED);
+
+        assert_eq!(
+            verify(PAYLOAD, &key_hash[1..]),
+            TD_SHIM_VERIFY_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            unsafe { td_shim_verify(ptr::null(), 0, key_hash.as_ptr(), key_hash.len()) },
+            TD_SHIM_VERIFY_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            unsafe { td_shim_verify(PAYLOAD.as_ptr(), PAYLOAD.len(), ptr::null(), 48) },
+            TD_SHIM_VERIFY_INVALID_ARGUMENT
+        );
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Copyright (c) 2022 Intel Corporation
+ *
+ * SPDX-License-Identifier: BSD-2-Clause-Patent
+ */
+
+/*
+ * Verify a signed payload through the C ABI of td-shim-verify-ffi.
+ *
+ * Usage: test_verify {signed_payload_file} {cfv_file}
+ *
+ * The key hash is read from the trust anchor enrolled into the CFV by td-shim-enroll.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "td_shim_verify.h"
+
+#define SHA384_DIGEST_SIZE 48
+/* Offset of the key hash in the CFV of the fuzzing seeds: FV header, FFS header and CFV public
+ * key file header. */
+#define CFV_KEY_HASH_OFFSET 0xb8
+
+static uint8_t *read_file(const char *name, size_t *len)
+{
+    FILE *file = fopen(name, "rb");
+    uint8_t *data = NULL;
+    long size;
+
+    if (file == NULL) {
+        perror(name);
+        return NULL;
+    }
+    if (fseek(file, 0, SEEK_END) == 0 && (size = ftell(file)) > 0 && fseek(file, 0, SEEK_SET) == 0) {
+        data = malloc(size);
+        if (data != NULL && fread(data, 1, size, file) != (size_t)size) {
+            free(data);
+            data = NULL;
+        }
+        *len = size;
+    }
+    fclose(file);
+    if (data == NULL) {
+        fprintf(stderr, "%s: can not read file\n", name);
+    }
+    return data;
+}
+
+static int check(const char *name, int result, int expected)
+{
+    if (result != expected) {
+        fprintf(stderr, "FAIL %s: got %d, expect %d\n", name, result, expected);
+        return 1;
+    }
+    printf("PASS %s\n", name);
+    return 0;
+}
+
+int main(int argc, char **argv)
+{
+    uint8_t key_hash[SHA384_DIGEST_SIZE];
+    uint8_t *payload, *cfv;
+    size_t payload_len, cfv_len;
+    int failed = 0;
+
+    if (argc != 3) {
+        fprintf(stderr, "Usage: %s {signed_payload_file} {cfv_file}\n", argv[0]);
+        return 2;
+    }
+    payload = read_file(argv[1], &payload_len);
+    cfv = read_file(argv[2], &cfv_len);
+    if (payload == NULL || cfv == NULL || cfv_len < CFV_KEY_HASH_OFFSET + SHA384_DIGEST_SIZE) {
+        return 2;
+    }
+    memcpy(key_hash, cfv + CFV_KEY_HASH_OFFSET, SHA384_DIGEST_SIZE);
+
+    failed += check("valid payload",
+                    td_shim_verify(payload, payload_len, key_hash, sizeof(key_hash)),
+                    TD_SHIM_VERIFY_OK);
+    failed += check("truncated key hash",
+                    td_shim_verify(payload, payload_len, key_hash, sizeof(key_hash) - 1),
+                    TD_SHIM_VERIFY_INVALID_ARGUMENT);
+    failed += check("NULL image", td_shim_verify(NULL, 0, key_hash, sizeof(key_hash)),
+                    TD_SHIM_VERIFY_INVALID_ARGUMENT);
+    failed += check("truncated payload", td_shim_verify(payload, 16, key_hash, sizeof(key_hash)),
+                    TD_SHIM_VERIFY_INVALID_CONTENT);
+
+    payload[payload_len / 2] ^= 0xff;
+    failed += check("tampered payload",
+                    td_shim_verify(payload, payload_len, key_hash, sizeof(key_hash)),
+                    TD_SHIM_VERIFY_INVALID_SIGNATURE);
+    payload[payload_len / 2] ^= 0xff;
+
+    key_hash[0] ^= 0xff;
+    failed += check("other key",
+                    td_shim_verify(payload, payload_len, key_hash, sizeof(key_hash)),
+                    TD_SHIM_VERIFY_INVALID_PUBLIC_KEY);
+
+    free(payload);
+    free(cfv);
+    return failed ? 1 : 0;
+}
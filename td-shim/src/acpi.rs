@@ -165,6 +165,101 @@ impl Ccel {
     }
 }
 
+/// Errors reported when walking ACPI tables stored back to back in a buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AcpiTableError {
+    /// The table at `offset` is cut off by the end of the buffer.
+    Truncated { offset: usize },
+    /// The length of the table at `offset` is smaller than the table header.
+    InvalidLength { offset: usize, length: u32 },
+    /// The table at `offset` doesn't sum to zero.
+    InvalidChecksum { offset: usize, signature: [u8; 4] },
+}
+
+/// An ACPI table stored in a buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct AcpiTable<'a> {
+    pub signature: [u8; 4],
+    /// Offset of the table relative to the start of the buffer.
+    pub offset: usize,
+    /// Content of the table, including the header.
+    pub data: &'a [u8],
+}
+
+/// Reader to walk ACPI tables stored back to back in a buffer, such as a blob of tables embedded
+/// into the image.
+///
+/// The iterator stops at the end of the buffer or at the free space following the last table,
+/// which is either erased (all 0xFF) or zero filled. A malformed table yields an error and
+/// terminates the iteration.
+pub struct AcpiTableReader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> AcpiTableReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        AcpiTableReader {
+            buffer,
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for AcpiTableReader<'a> {
+    type Item = Result<AcpiTable<'a>, AcpiTableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const HEADER_SIZE: usize = size_of::<GenericSdtHeader>();
+
+        if self.done {
+            return None;
+        }
+        self.done = true;
+
+        let offset = self.offset;
+        let buffer = &self.buffer[offset..];
+        let raw_header = &buffer[..buffer.len().min(HEADER_SIZE)];
+        if raw_header.iter().all(|v| *v == 0xff) || raw_header.iter().all(|v| *v == 0) {
+            return None;
+        }
+        if buffer.len() < HEADER_SIZE {
+            return Some(Err(AcpiTableError::Truncated { offset }));
+        }
+
+        // Safe to unwrap() because the buffer is big enough.
+        let header = GenericSdtHeader::read_from(raw_header).unwrap();
+        let length = header.length as usize;
+        if length < HEADER_SIZE {
+            return Some(Err(AcpiTableError::InvalidLength {
+                offset,
+                length: header.length,
+            }));
+        }
+        if length > buffer.len() {
+            return Some(Err(AcpiTableError::Truncated { offset }));
+        }
+        let data = &buffer[..length];
+        if data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0 {
+            return Some(Err(AcpiTableError::InvalidChecksum {
+                offset,
+                signature: header.signature,
+            }));
+        }
+
+        self.offset += length;
+        self.done = false;
+
+        Some(Ok(AcpiTable {
+            signature: header.signature,
+            offset,
+            data,
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -239,4 +334,89 @@ mod tests {
         assert_eq!(&ccel.header.signature, b"CCEL");
         assert_eq!(ccel.header.checksum, 45);
     }
+
+    fn build_tables() -> [u8; 256] {
+        let mut buf = [0u8; 256];
+        let ccel = Ccel::new(2, 0, 0x100, 0);
+        let ccel = ccel.as_bytes();
+        buf[..ccel.len()].copy_from_slice(ccel);
+
+        let mut apic = [0x5au8; 48];
+        let mut header = GenericSdtHeader::new(b"APIC", apic.len() as u32, 1);
+        header.write_to_prefix(&mut apic[..]).unwrap();
+        header.set_checksum(calculate_checksum(&apic));
+        header.write_to_prefix(&mut apic[..]).unwrap();
+        buf[ccel.len()..ccel.len() + apic.len()].copy_from_slice(&apic);
+
+        buf
+    }
+
+    #[test]
+    fn test_acpi_table_reader() {
+        let buf = build_tables();
+        let ccel_len = size_of::<Ccel>();
+
+        let mut reader = AcpiTableReader::new(&buf);
+        let ccel = reader.next().unwrap().unwrap();
+        assert_eq!(&ccel.signature, b"CCEL");
+        assert_eq!(ccel.offset, 0);
+        assert_eq!(ccel.data.len(), ccel_len);
+        let apic = reader.next().unwrap().unwrap();
+        assert_eq!(&apic.signature, b"APIC");
+        assert_eq!(apic.offset, ccel_len);
+        assert_eq!(apic.data.len(), 48);
+        assert!(reader.next().is_none());
+
+        // Erased free space and no space at all.
+        let mut erased = buf;
+        erased[ccel_len + 48..].fill(0xff);
+        assert_eq!(AcpiTableReader::new(&erased).count(), 2);
+        assert_eq!(AcpiTableReader::new(&buf[..ccel_len + 48]).count(), 2);
+        assert_eq!(AcpiTableReader::new(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_acpi_table_reader_errors() {
+        let buf = build_tables();
+        let ccel_len = size_of::<Ccel>();
+
+        // Corrupted checksum of the second table.
+        let mut corrupted = buf;
+        corrupted[ccel_len + 40] ^= 0xff;
+        let mut reader = AcpiTableReader::new(&corrupted);
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(
+            reader.next().unwrap().unwrap_err(),
+            AcpiTableError::InvalidChecksum {
+                offset: ccel_len,
+                signature: *b"APIC"
+            }
+        );
+        assert!(reader.next().is_none());
+
+        // Truncated table and truncated header.
+        let mut reader = AcpiTableReader::new(&buf[..ccel_len + 40]);
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(
+            reader.next().unwrap().unwrap_err(),
+            AcpiTableError::Truncated { offset: ccel_len }
+        );
+        let mut reader = AcpiTableReader::new(&buf[..ccel_len + 8]);
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(
+            reader.next().unwrap().unwrap_err(),
+            AcpiTableError::Truncated { offset: ccel_len }
+        );
+
+        // Length smaller than the header.
+        let mut short = buf;
+        short[4..8].copy_from_slice(&8u32.to_le_bytes());
+        assert_eq!(
+            AcpiTableReader::new(&short).next().unwrap().unwrap_err(),
+            AcpiTableError::InvalidLength {
+                offset: 0,
+                length: 8
+            }
+        );
+    }
 }
@@ -31,7 +31,12 @@ use td_layout::build_time::{self, *};
 use td_layout::memslice::{self, SliceType};
 use td_layout::RuntimeMemoryLayout;
 use td_shim::acpi::{Ccel, GenericSdtHeader};
-use td_shim::event_log::{log_hob_list, log_payload_binary, log_payload_parameter};
+use td_shim::event_log::{
+    log_boot_measurements, split_at_verification, BootMeasurement, BootMeasurementData,
+    EXECUTABLE_PAYLOAD_MEASUREMENTS, LINUX_KERNEL_MEASUREMENTS, MEASUREMENT_PAYLOAD_BINARY,
+    PAYLOAD_INFO_EXECUTABLE_MEASUREMENTS,
+};
+use td_shim::multi_component::{MultiComponentPayload, COMPONENT_TYPE_EXECUTABLE};
 use td_shim::{
     speculation_barrier, PayloadInfo, TdPayloadInfoHobType, TD_ACPI_TABLE_HOB_GUID,
     TD_PAYLOAD_INFO_HOB_GUID,
@@ -130,10 +135,8 @@ pub extern "win64" fn _start(
     let mut td_event_log = CcEventLogWriter::new(event_log_buf, Box::new(td::extend_rtmr))
         .expect("Failed to create and initialize the event log");
 
-    // Log the TD HOB if td-shim consumed its content
-    if let Some(td_hob) = dynamic_info.td_hob() {
-        log_hob_list(td_hob, &mut td_event_log);
-    }
+    // The TD HOB is measured if td-shim consumed its content
+    let td_hob = dynamic_info.td_hob();
 
     let num_vcpus = td::get_num_vcpus();
     //Create MADT and TDEL
@@ -149,10 +152,18 @@ pub extern "win64" fn _start(
             &mem,
             &mut td_event_log,
             num_vcpus,
+            td_hob,
         );
     }
 
-    boot_builtin_payload(&mut mem, &mut td_event_log, &dynamic_info.acpi_tables);
+    // The Payload Information GUID HOB selects the built-in payload if the kernel isn't booted.
+    boot_builtin_payload(
+        &mut mem,
+        &mut td_event_log,
+        &dynamic_info.acpi_tables,
+        td_hob,
+        dynamic_info.payload_info.is_some(),
+    );
 
     panic!("payload entry() should not return here, deadloop!!!");
 }
@@ -163,12 +174,11 @@ fn boot_linux_kernel(
     mem: &memory::Memory,
     event_log: &mut CcEventLogWriter,
     vcpus: u32,
+    td_hob: Option<&[u8]>,
 ) {
-    // Create an EV_SEPARATOR event to mark the end of the td-shim events
-    event_log.create_seperator();
-
     let image_type = TdPayloadInfoHobType::from(kernel_info.image_type);
     match image_type {
+        // Booted as the built-in payload, see `PAYLOAD_INFO_EXECUTABLE_MEASUREMENTS`.
         TdPayloadInfoHobType::ExecutablePayload => return,
         TdPayloadInfoHobType::BzImage | TdPayloadInfoHobType::RawVmLinux => {}
         _ => panic!("Unknown kernel image type {}!!!", kernel_info.image_type),
@@ -181,9 +191,19 @@ fn boot_linux_kernel(
     let payload = mem.get_dynamic_mem_slice_mut(SliceType::Payload);
     let payload_parameter = mem.get_dynamic_mem_slice(SliceType::PayloadParameter);
 
-    // Record the payload binary/paramater into event log.
-    log_payload_binary(payload, event_log);
-    log_payload_parameter(payload_parameter, event_log);
+    // Record the td-shim events, then the payload binary/paramater into event log.
+    let data = BootMeasurementData {
+        td_hob,
+        cfv: memslice::get_mem_slice(memslice::SliceType::Config),
+        trust_anchor: None,
+        payload_svn: None,
+        payload,
+        payload_rtmr_index: MEASUREMENT_PAYLOAD_BINARY.rtmr_index(),
+        components: None,
+        payload_parameter,
+    };
+    log_boot_measurements(&LINUX_KERNEL_MEASUREMENTS, &data, event_log)
+        .expect("Failed to log the boot measurements to the td event log");
 
     let mailbox = mem.get_dynamic_mem_slice_mut(SliceType::RelocatedMailbox);
 
@@ -204,6 +224,8 @@ fn boot_builtin_payload(
     mem: &mut memory::Memory,
     event_log: &mut CcEventLogWriter,
     acpi_tables: &Vec<&[u8]>,
+    td_hob: Option<&[u8]>,
+    payload_info: bool,
 ) {
     // Get and parse image file from the payload firmware volume.
     let fv_buffer = memslice::get_mem_slice(memslice::SliceType::ShimPayload);
@@ -214,16 +236,104 @@ fn boot_builtin_payload(
     )
     .expect("Failed to get image file from Firmware Volume");
 
-    #[cfg(feature = "secure-boot")]
-    {
-        payload_bin = secure_boot_verify_payload(payload_bin, event_log);
-    }
+    // The td-shim events and the enrolled trust anchor are measured before the payload is
+    // verified, the rest of the sequence once it passes.
+    let sequence: &[BootMeasurement] = if payload_info {
+        &PAYLOAD_INFO_EXECUTABLE_MEASUREMENTS
+    } else {
+        &EXECUTABLE_PAYLOAD_MEASUREMENTS
+    };
+    let (before_verification, after_verification) = split_at_verification(sequence);
+    let cfv = memslice::get_mem_slice(memslice::SliceType::Config);
+    let mut log_unverified = |trust_anchor: Option<&[u8]>| {
+        let data = BootMeasurementData {
+            td_hob,
+            cfv,
+            trust_anchor,
+            payload_svn: None,
+            payload: &[],
+            payload_rtmr_index: MEASUREMENT_PAYLOAD_BINARY.rtmr_index(),
+            components: None,
+            payload_parameter: &[],
+        };
+        log_boot_measurements(before_verification, &data, event_log)
+            .expect("Failed to log the boot measurements to the td event log");
+    };
 
-    // Record the payload binary information into event log.
-    log_payload_binary(payload_bin, event_log);
+    // Only a payload whose signed verify header records the multi-component type is booted as
+    // such, not a plain payload which happens to start with a table of contents.
+    #[cfg(not(feature = "secure-boot"))]
+    let (components, rtmr_index, trust_anchor, payload_svn): (
+        Option<MultiComponentPayload>,
+        u32,
+        Option<&[u8]>,
+        Option<u64>,
+    ) = {
+        log_unverified(None);
+        (None, MEASUREMENT_PAYLOAD_BINARY.rtmr_index(), None, None)
+    };
+    #[cfg(feature = "secure-boot")]
+    let (components, rtmr_index, trust_anchor, payload_svn) = {
+        use td_shim::secure_boot::SignedPayloadType;
+
+        let verified = secure_boot_verify_payload(payload_bin, &mut log_unverified);
+        payload_bin = verified.image;
+        // The payload is loaded into the payload region, refuse a payload whose signer requested
+        // another load base instead of running it where it isn't expected to run.
+        if let Some(load_base) = verified.load_base {
+            let base = mem.get_layout_region(SliceType::Payload).base_address as u64;
+            if load_base != base {
+                panic!(
+                    "Secure Boot: payload requests load base {:#x}, but is loaded at {:#x}",
+                    load_base, base
+                );
+            }
+        }
+        // The built-in payload is loaded as an executable image, refuse other types instead of
+        // guessing how to boot them.
+        let payload_type = verified.payload_type;
+        let components = match payload_type {
+            SignedPayloadType::Unspecified
+            | SignedPayloadType::Executable
+            | SignedPayloadType::MigrationTd => None,
+            SignedPayloadType::MultiComponent => Some(
+                MultiComponentPayload::parse(payload_bin)
+                    .expect("Secure Boot: invalid table of contents of multi-component payload"),
+            ),
+            _ => panic!(
+                "Secure Boot: payload type {} is not supported",
+                payload_type
+            ),
+        };
+        (
+            components,
+            verified.rtmr_index,
+            verified.trust_anchor,
+            verified.payload_svn,
+        )
+    };
 
-    // Create an EV_SEPARATOR event to mark the end of the td-shim events
-    event_log.create_seperator();
+    // Record the matched trust anchor and the payload binary into event log, the payload binary
+    // into the RTMR requested by the signer. The table of contents and the components of a
+    // multi-component payload are measured separately and its executable component is booted.
+    let data = BootMeasurementData {
+        td_hob,
+        cfv,
+        trust_anchor,
+        payload_svn,
+        payload: payload_bin,
+        payload_rtmr_index: rtmr_index,
+        components: components.as_ref(),
+        payload_parameter: &[],
+    };
+    log_boot_measurements(after_verification, &data, event_log)
+        .expect("Failed to log the boot measurements to the td event log");
+    if let Some(components) = components {
+        payload_bin = components
+            .find(COMPONENT_TYPE_EXECUTABLE)
+            .expect("No executable component in multi-component payload")
+            .data;
+    }
 
     let payload = mem.get_dynamic_mem_slice_mut(memslice::SliceType::Payload);
     let relocation_info = ipl::find_and_report_entry_point(mem, payload_bin, payload)
@@ -375,54 +485,111 @@ fn prepare_acpi_tables(acpi_tables: &mut Vec<&[u8]>, mem: &Memory, vcpus: u32) -
     (madt, tdel)
 }
 
+// The built-in payload checked by `secure_boot_verify_payload()`, with the fields covered by its
+// signature.
 #[cfg(feature = "secure-boot")]
-fn secure_boot_verify_payload<'a>(
-    payload: &'a [u8],
-    cc_event_log: &mut CcEventLogWriter,
-) -> &'a [u8] {
-    use td_shim::event_log::{
-        create_event_log_platform_config, PLATFORM_CONFIG_SECURE_AUTHORITY,
-        PLATFORM_CONFIG_SECURE_POLICY_DB, PLATFORM_CONFIG_SVN, PLATFORM_FIRMWARE_BLOB2_PAYLOAD,
+struct VerifiedPayload<'a> {
+    image: &'a [u8],
+    payload_type: td_shim::secure_boot::SignedPayloadType,
+    rtmr_index: u32,
+    // The trust anchor and the payload SVN to be measured, `None` for an unsigned payload.
+    trust_anchor: Option<&'static [u8]>,
+    payload_svn: Option<u64>,
+    // Load base requested by the signer, `None` if the loader chooses it.
+    load_base: Option<u64>,
+}
+
+// `log_unverified` is called with the enrolled trust anchor, `None` for an unsigned payload, right
+// before the payload is verified.
+#[cfg(feature = "secure-boot")]
+fn secure_boot_verify_payload(
+    payload: &[u8],
+    log_unverified: impl FnOnce(Option<&[u8]>),
+) -> VerifiedPayload<'_> {
+    use td_shim::secure_boot::{
+        check_min_svn, CompactPayloadVerifier, PayloadVerifier, SignedPayloadFormat,
+        SignedPayloadType, VerifyErr, PAYLOAD_DEFAULT_RTMR_INDEX,
     };
-    use td_shim::secure_boot::PayloadVerifier;
 
     let cfv = memslice::get_mem_slice(memslice::SliceType::Config);
-    let verifier = PayloadVerifier::new(payload, cfv)
-        .expect("Secure Boot: Cannot read verify header from payload binary");
+    let format = match SignedPayloadFormat::detect(payload) {
+        Some(v) => v,
+        // Payloads built without signing are only booted if permitted by the build policy. They
+        // are measured into the default RTMR, without any trust anchor nor SVN logged.
+        None if cfg!(feature = "allow-unsigned-payload") => {
+            log::warn!("Secure Boot: payload is unsigned, booting it as permitted by the build\n");
+            log_unverified(None);
+            return VerifiedPayload {
+                image: payload,
+                payload_type: SignedPayloadType::Unspecified,
+                rtmr_index: PAYLOAD_DEFAULT_RTMR_INDEX,
+                trust_anchor: None,
+                payload_svn: None,
+                load_base: None,
+            };
+        }
+        None => panic!(
+            "Secure Boot: {:?} payload is not permitted by the build",
+            VerifyErr::Unsigned
+        ),
+    };
     let trust_anchor =
         PayloadVerifier::get_trust_anchor(cfv).expect("Fail to get trust anchor from CFV");
 
     // Record the provisioned trust anchor into event log.
-    create_event_log_platform_config(
-        cc_event_log,
-        1,
-        PLATFORM_CONFIG_SECURE_POLICY_DB,
-        trust_anchor,
-    )
-    .expect("Fail to measure and log the provisioned trust anchor");
-
-    verifier.verify().expect("Verification fails");
-
-    // Record the matched trust anchor which is same as the provisioned
-    // trust anchor if it passes the verification.
-    create_event_log_platform_config(
-        cc_event_log,
-        1,
-        PLATFORM_CONFIG_SECURE_AUTHORITY,
-        trust_anchor,
-    )
-    .expect("Fail to measure and log the provisioned trust anchor");
-
-    // Record the payload SVN into event log.
-    create_event_log_platform_config(
-        cc_event_log,
-        2,
-        PLATFORM_CONFIG_SVN,
-        &u64::to_le_bytes(verifier.get_payload_svn()),
-    )
-    .expect("Fail to measure and log the payload SVN");
+    log_unverified(Some(trust_anchor));
+
+    // Parse out the image from signed payload. The compact format records no SVN, payload type,
+    // RTMR nor load base, the payload SVN is logged as 0 and the payload is measured into the
+    // default RTMR.
+    // As it escapes the SVN checks, it's only booted if permitted by the build policy and if the
+    // CFV doesn't require a minimum SVN.
+    let (payload_svn, payload_type, rtmr_index, load_base, image) = match format {
+        SignedPayloadFormat::Standard => {
+            let verifier = PayloadVerifier::new(payload, cfv)
+                .expect("Secure Boot: Cannot read verify header from payload binary");
+            verifier.verify().expect("Verification fails");
+            verifier
+                .check_svn_policy()
+                .expect("Payload is rejected by the CFV minimum SVN or the signed SVN policy");
+            let image = PayloadVerifier::get_payload_image(payload)
+                .expect("Unable to get payload image from signed binary");
+            (
+                verifier.get_payload_svn(),
+                verifier.get_payload_type(),
+                verifier.get_payload_rtmr_index(),
+                verifier.get_payload_load_base(),
+                image,
+            )
+        }
+        SignedPayloadFormat::Compact => {
+            if !cfg!(feature = "allow-compact-payload") {
+                panic!("Secure Boot: compact signed payload is not permitted by the build");
+            }
+            check_min_svn(cfv, 0).expect(
+                "Compact signed payload records no SVN and is rejected by the CFV minimum SVN",
+            );
+            let verifier = CompactPayloadVerifier::new(payload, cfv)
+                .expect("Secure Boot: Cannot read compact signature from payload binary");
+            verifier.verify().expect("Verification fails");
+            (
+                0,
+                SignedPayloadType::Unspecified,
+                PAYLOAD_DEFAULT_RTMR_INDEX,
+                None,
+                verifier.get_payload_image(),
+            )
+        }
+    };
 
-    // Parse out the image from signed payload
-    return PayloadVerifier::get_payload_image(payload)
-        .expect("Unable to get payload image from signed binary");
+    // The matched trust anchor is measured, which is same as the provisioned trust anchor since it
+    // passed the verification.
+    VerifiedPayload {
+        image,
+        payload_type,
+        rtmr_index,
+        trust_anchor: Some(trust_anchor),
+        payload_svn: Some(payload_svn),
+        load_base,
+    }
 }
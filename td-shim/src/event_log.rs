@@ -6,17 +6,209 @@
 use cc_measurement::{
     log::{CcEventLogError, CcEventLogWriter},
     UefiPlatformFirmwareBlob2, EV_EFI_PLATFORM_FIRMWARE_BLOB2, EV_PLATFORM_CONFIG_FLAGS,
+    EV_SEPARATOR,
 };
 use core::{mem::size_of, ptr::slice_from_raw_parts};
 
+use crate::measured_config::measured_configs;
+use crate::multi_component::MultiComponentPayload;
+
 pub const CCEL_CC_TYPE_TDX: u8 = 2;
 
 pub const PLATFORM_CONFIG_HOB: &[u8] = b"td_hob\0";
+pub const PLATFORM_CONFIG_CFV_CONFIG: &[u8] = b"cfv_config\0";
 pub const PLATFORM_CONFIG_PAYLOAD_PARAMETER: &[u8] = b"td_payload_info\0";
 pub const PLATFORM_CONFIG_SECURE_POLICY_DB: &[u8] = b"secure_policy_db";
 pub const PLATFORM_CONFIG_SECURE_AUTHORITY: &[u8] = b"secure_authority";
 pub const PLATFORM_CONFIG_SVN: &[u8] = b"td_payload_svn\0";
 pub const PLATFORM_FIRMWARE_BLOB2_PAYLOAD: &[u8] = b"td_payload\0";
+pub const PLATFORM_FIRMWARE_BLOB2_PAYLOAD_COMPONENT: &[u8] = b"td_payload_component\0";
+pub const PLATFORM_FIRMWARE_BLOB2_PAYLOAD_COMPONENT_TABLE: &[u8] = b"td_payload_components\0";
+
+/// Data of the separator event, which marks the end of the td-shim events.
+pub const SEPARATOR_DATA: [u8; 4] = [0u8; 4];
+
+/// Data measured by td-shim into the RTMRs during boot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MeasuredData {
+    /// The TD HOB, if td-shim consumed its content.
+    TdHob,
+    /// A measured config enrolled into the CFV, the header followed by the config blob.
+    CfvConfig,
+    /// The trust anchor enrolled into the CFV, before the payload is verified.
+    SecurePolicyDb,
+    /// The trust anchor which verified the payload.
+    SecureAuthority,
+    /// The SVN of the signed payload in little-endian.
+    PayloadSvn,
+    /// The payload image, without the verify header if signed.
+    PayloadBinary,
+    /// The table of contents of a multi-component payload, which records the type and the RTMR of
+    /// each component.
+    PayloadComponentTable,
+    /// A component of a multi-component payload, measured into the RTMR given by the table of
+    /// contents instead of the payload image.
+    PayloadComponent,
+    /// The parameter of a Linux kernel payload.
+    PayloadParameter,
+    /// `SEPARATOR_DATA`.
+    Separator,
+}
+
+/// An event of the boot measurement sequence.
+///
+/// The runtime logs the sequences through `log_boot_measurements()`, and the measurement prediction
+/// tools replay the same events, so the two can't disagree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BootMeasurement {
+    pub data: MeasuredData,
+    /// Index of the measurement register in the event log, 1 stands for RTMR[0].
+    pub mr_index: u32,
+    pub event_type: u32,
+    /// Descriptor of the event, empty if the event has none.
+    pub descriptor: &'static [u8],
+}
+
+impl BootMeasurement {
+    /// Index of the RTMR extended by the event.
+    pub const fn rtmr_index(&self) -> u32 {
+        self.mr_index - 1
+    }
+}
+
+pub const MEASUREMENT_TD_HOB: BootMeasurement = BootMeasurement {
+    data: MeasuredData::TdHob,
+    mr_index: 1,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_HOB,
+};
+pub const MEASUREMENT_CFV_CONFIG: BootMeasurement = BootMeasurement {
+    data: MeasuredData::CfvConfig,
+    mr_index: 1,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_CFV_CONFIG,
+};
+pub const MEASUREMENT_SECURE_POLICY_DB: BootMeasurement = BootMeasurement {
+    data: MeasuredData::SecurePolicyDb,
+    mr_index: 1,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_SECURE_POLICY_DB,
+};
+pub const MEASUREMENT_SECURE_AUTHORITY: BootMeasurement = BootMeasurement {
+    data: MeasuredData::SecureAuthority,
+    mr_index: 1,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_SECURE_AUTHORITY,
+};
+pub const MEASUREMENT_PAYLOAD_SVN: BootMeasurement = BootMeasurement {
+    data: MeasuredData::PayloadSvn,
+    mr_index: 2,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_SVN,
+};
+/// Measurement of the payload binary into RTMR[1], unless the verify header of a signed payload
+/// requests another RTMR, see `payload_binary_measurement()`.
+pub const MEASUREMENT_PAYLOAD_BINARY: BootMeasurement = payload_binary_measurement(1);
+/// Measurement of the payload binary into RTMR[`rtmr_index`].
+pub const fn payload_binary_measurement(rtmr_index: u32) -> BootMeasurement {
+    BootMeasurement {
+        data: MeasuredData::PayloadBinary,
+        mr_index: rtmr_index + 1,
+        event_type: EV_EFI_PLATFORM_FIRMWARE_BLOB2,
+        descriptor: PLATFORM_FIRMWARE_BLOB2_PAYLOAD,
+    }
+}
+pub const MEASUREMENT_PAYLOAD_PARAMETER: BootMeasurement = BootMeasurement {
+    data: MeasuredData::PayloadParameter,
+    mr_index: 2,
+    event_type: EV_PLATFORM_CONFIG_FLAGS,
+    descriptor: PLATFORM_CONFIG_PAYLOAD_PARAMETER,
+};
+/// Measurement of the table of contents of a multi-component payload into RTMR[1], before its
+/// components, so the type of the components, which selects the booted one, is measured.
+pub const MEASUREMENT_PAYLOAD_COMPONENT_TABLE: BootMeasurement = BootMeasurement {
+    data: MeasuredData::PayloadComponentTable,
+    mr_index: 2,
+    event_type: EV_EFI_PLATFORM_FIRMWARE_BLOB2,
+    descriptor: PLATFORM_FIRMWARE_BLOB2_PAYLOAD_COMPONENT_TABLE,
+};
+/// Measurement of a component of a multi-component payload into RTMR[`rtmr_index`].
+pub const fn payload_component_measurement(rtmr_index: u32) -> BootMeasurement {
+    BootMeasurement {
+        data: MeasuredData::PayloadComponent,
+        mr_index: rtmr_index + 1,
+        event_type: EV_EFI_PLATFORM_FIRMWARE_BLOB2,
+        descriptor: PLATFORM_FIRMWARE_BLOB2_PAYLOAD_COMPONENT,
+    }
+}
+/// The separator is measured into RTMR[0] and RTMR[1].
+pub const MEASUREMENT_SEPARATOR: [BootMeasurement; 2] = [
+    BootMeasurement {
+        data: MeasuredData::Separator,
+        mr_index: 1,
+        event_type: EV_SEPARATOR,
+        descriptor: &[],
+    },
+    BootMeasurement {
+        data: MeasuredData::Separator,
+        mr_index: 2,
+        event_type: EV_SEPARATOR,
+        descriptor: &[],
+    },
+];
+
+/// Boot measurement sequence of the executable payload stored in the payload firmware volume.
+///
+/// The TD HOB event is skipped if td-shim doesn't consume the TD HOB, the CFV config event is
+/// repeated for each measured config in the CFV, the secure boot events are skipped if td-shim is
+/// built without secure boot. The payload binary event is measured into the RTMR recorded by the
+/// verify header of the signed payload, RTMR[1] by default. The payload binary event of a
+/// multi-component payload is replaced by the `MEASUREMENT_PAYLOAD_COMPONENT_TABLE` event followed
+/// by one `payload_component_measurement()` event per component, in the order of the table of
+/// contents.
+///
+/// The events up to the enrolled trust anchor are measured before the payload is verified, see
+/// `split_at_verification()`.
+pub const EXECUTABLE_PAYLOAD_MEASUREMENTS: [BootMeasurement; 8] = [
+    MEASUREMENT_TD_HOB,
+    MEASUREMENT_CFV_CONFIG,
+    MEASUREMENT_SECURE_POLICY_DB,
+    MEASUREMENT_SECURE_AUTHORITY,
+    MEASUREMENT_PAYLOAD_SVN,
+    MEASUREMENT_PAYLOAD_BINARY,
+    MEASUREMENT_SEPARATOR[0],
+    MEASUREMENT_SEPARATOR[1],
+];
+
+/// Boot measurement sequence of the executable payload when the payload info HOB selects it.
+///
+/// td-shim measures the separator events when it handles the payload info HOB, as for a Linux
+/// kernel payload, then the events of `EXECUTABLE_PAYLOAD_MEASUREMENTS` after the td-shim events.
+pub const PAYLOAD_INFO_EXECUTABLE_MEASUREMENTS: [BootMeasurement; 10] = [
+    MEASUREMENT_TD_HOB,
+    MEASUREMENT_CFV_CONFIG,
+    MEASUREMENT_SEPARATOR[0],
+    MEASUREMENT_SEPARATOR[1],
+    MEASUREMENT_SECURE_POLICY_DB,
+    MEASUREMENT_SECURE_AUTHORITY,
+    MEASUREMENT_PAYLOAD_SVN,
+    MEASUREMENT_PAYLOAD_BINARY,
+    MEASUREMENT_SEPARATOR[0],
+    MEASUREMENT_SEPARATOR[1],
+];
+
+/// Boot measurement sequence of a Linux kernel payload.
+///
+/// The TD HOB event is skipped if td-shim doesn't consume the TD HOB, the CFV config event is
+/// repeated for each measured config in the CFV.
+pub const LINUX_KERNEL_MEASUREMENTS: [BootMeasurement; 6] = [
+    MEASUREMENT_TD_HOB,
+    MEASUREMENT_CFV_CONFIG,
+    MEASUREMENT_SEPARATOR[0],
+    MEASUREMENT_SEPARATOR[1],
+    MEASUREMENT_PAYLOAD_BINARY,
+    MEASUREMENT_PAYLOAD_PARAMETER,
+];
 
 /// Used to record configuration information into event log
 ///
@@ -68,42 +260,208 @@ pub fn create_event_log_platform_config(
     Ok(())
 }
 
+/// Measure `data` into the RTMR of `measurement` and record the event into the event log.
+pub fn log_boot_measurement(
+    cc_event_log: &mut CcEventLogWriter,
+    measurement: &BootMeasurement,
+    data: &[u8],
+) -> Result<(), CcEventLogError> {
+    match measurement.event_type {
+        EV_PLATFORM_CONFIG_FLAGS => create_event_log_platform_config(
+            cc_event_log,
+            measurement.mr_index,
+            measurement.descriptor,
+            data,
+        ),
+        EV_EFI_PLATFORM_FIRMWARE_BLOB2 => {
+            let blob2 = UefiPlatformFirmwareBlob2::new(
+                measurement.descriptor,
+                data.as_ptr() as u64,
+                data.len() as u64,
+            )
+            .ok_or(CcEventLogError::InvalidParameter)?;
+            cc_event_log.create_event_log(
+                measurement.mr_index,
+                measurement.event_type,
+                &[blob2.as_bytes()],
+                data,
+            )
+        }
+        _ => cc_event_log.create_event_log(
+            measurement.mr_index,
+            measurement.event_type,
+            &[data],
+            data,
+        ),
+    }
+}
+
 pub fn log_hob_list(hob_list: &[u8], cc_event_log: &mut CcEventLogWriter) {
-    create_event_log_platform_config(cc_event_log, 1, PLATFORM_CONFIG_HOB, hob_list)
+    log_boot_measurement(cc_event_log, &MEASUREMENT_TD_HOB, hob_list)
         .expect("Failed to log HOB list to the td event log");
 }
 
+/// Measure the measured configs enrolled into the CFV, in the order stored.
+pub fn log_cfv_configs(cfv: &[u8], cc_event_log: &mut CcEventLogWriter) {
+    for config in measured_configs(cfv) {
+        log_boot_measurement(cc_event_log, &MEASUREMENT_CFV_CONFIG, config.content)
+            .expect("Failed to log CFV config to the td event log");
+    }
+}
+
 pub fn log_payload_binary(payload: &[u8], cc_event_log: &mut CcEventLogWriter) {
-    let blob2 = UefiPlatformFirmwareBlob2::new(
-        PLATFORM_FIRMWARE_BLOB2_PAYLOAD,
-        payload.as_ptr() as u64,
-        payload.len() as u64,
+    log_payload_binary_into(
+        payload,
+        MEASUREMENT_PAYLOAD_BINARY.rtmr_index(),
+        cc_event_log,
+    )
+}
+
+/// Measure the payload binary into RTMR[`rtmr_index`], as requested by its verify header.
+pub fn log_payload_binary_into(
+    payload: &[u8],
+    rtmr_index: u32,
+    cc_event_log: &mut CcEventLogWriter,
+) {
+    log_boot_measurement(
+        cc_event_log,
+        &payload_binary_measurement(rtmr_index),
+        payload,
+    )
+    .expect("Failed to log payload binary to the td event log");
+}
+
+/// Measure the table of contents of the multi-component payload `payload` into RTMR[1], then each
+/// component into the RTMR given by its table of contents entry, in the order of the table.
+pub fn log_payload_components(
+    payload: &MultiComponentPayload,
+    cc_event_log: &mut CcEventLogWriter,
+) {
+    log_boot_measurement(
+        cc_event_log,
+        &MEASUREMENT_PAYLOAD_COMPONENT_TABLE,
+        payload.table(),
     )
-    .expect("Invalid payload binary information or descriptor");
-
-    cc_event_log
-        .create_event_log(
-            2,
-            EV_EFI_PLATFORM_FIRMWARE_BLOB2,
-            &[blob2.as_bytes()],
-            payload,
+    .expect("Failed to log payload component table to the td event log");
+    for component in payload.components() {
+        log_boot_measurement(
+            cc_event_log,
+            &payload_component_measurement(component.rtmr_index),
+            component.data,
         )
-        .expect("Failed to log HOB list to the td event log");
+        .expect("Failed to log payload component to the td event log");
+    }
 }
 
 pub fn log_payload_parameter(payload_parameter: &[u8], cc_event_log: &mut CcEventLogWriter) {
-    create_event_log_platform_config(
+    log_boot_measurement(
         cc_event_log,
-        2,
-        PLATFORM_CONFIG_PAYLOAD_PARAMETER,
+        &MEASUREMENT_PAYLOAD_PARAMETER,
         payload_parameter,
     )
-    .expect("Failed to log HOB list to the td event log");
+    .expect("Failed to log payload parameter to the td event log");
+}
+
+/// Create the separator events to mark the end of the td-shim events.
+pub fn log_separator(cc_event_log: &mut CcEventLogWriter) {
+    for measurement in MEASUREMENT_SEPARATOR.iter() {
+        log_boot_measurement(cc_event_log, measurement, &SEPARATOR_DATA)
+            .expect("Failed to log separator to the td event log");
+    }
+}
+
+/// Data measured by the events of a boot measurement sequence, by `MeasuredData`.
+pub struct BootMeasurementData<'a> {
+    /// The TD HOB, `None` if td-shim doesn't consume the TD HOB.
+    pub td_hob: Option<&'a [u8]>,
+    /// The CFV, whose measured configs are measured.
+    pub cfv: &'a [u8],
+    /// The trust anchor which verified the payload, `None` if the payload is not verified.
+    pub trust_anchor: Option<&'a [u8]>,
+    /// The payload SVN, `None` if the payload is not verified.
+    pub payload_svn: Option<u64>,
+    /// The payload image, measured unless `components` is given.
+    pub payload: &'a [u8],
+    /// Index of the RTMR the payload image is measured into.
+    pub payload_rtmr_index: u32,
+    /// The multi-component payload measured per component in place of the payload image.
+    pub components: Option<&'a MultiComponentPayload<'a>>,
+    /// The parameter of a Linux kernel payload.
+    pub payload_parameter: &'a [u8],
+}
+
+/// Split the boot measurement sequence `sequence` before the `MEASUREMENT_SECURE_AUTHORITY` event.
+///
+/// td-shim measures the first part, up to the enrolled trust anchor, before it verifies the
+/// payload, and the second part once the payload passes the verification. The second part is
+/// empty if `sequence` has no secure boot events.
+pub fn split_at_verification(
+    sequence: &[BootMeasurement],
+) -> (&[BootMeasurement], &[BootMeasurement]) {
+    let mid = sequence
+        .iter()
+        .position(|v| v.data == MeasuredData::SecureAuthority)
+        .unwrap_or(sequence.len());
+    sequence.split_at(mid)
+}
+
+/// Measure the events of the boot measurement sequence `sequence`, such as
+/// `EXECUTABLE_PAYLOAD_MEASUREMENTS`, in order, with the data of `data`.
+///
+/// Events without data are skipped, and the payload binary event is measured as documented by
+/// `EXECUTABLE_PAYLOAD_MEASUREMENTS`.
+pub fn log_boot_measurements(
+    sequence: &[BootMeasurement],
+    data: &BootMeasurementData,
+    cc_event_log: &mut CcEventLogWriter,
+) -> Result<(), CcEventLogError> {
+    for measurement in sequence.iter() {
+        match measurement.data {
+            MeasuredData::TdHob => {
+                if let Some(td_hob) = data.td_hob {
+                    log_boot_measurement(cc_event_log, measurement, td_hob)?;
+                }
+            }
+            MeasuredData::CfvConfig => {
+                for config in measured_configs(data.cfv) {
+                    log_boot_measurement(cc_event_log, measurement, config.content)?;
+                }
+            }
+            MeasuredData::SecurePolicyDb | MeasuredData::SecureAuthority => {
+                if let Some(trust_anchor) = data.trust_anchor {
+                    log_boot_measurement(cc_event_log, measurement, trust_anchor)?;
+                }
+            }
+            MeasuredData::PayloadSvn => {
+                if let Some(svn) = data.payload_svn {
+                    log_boot_measurement(cc_event_log, measurement, &svn.to_le_bytes())?;
+                }
+            }
+            MeasuredData::PayloadBinary => match data.components {
+                Some(components) => log_payload_components(components, cc_event_log),
+                None => log_boot_measurement(
+                    cc_event_log,
+                    &payload_binary_measurement(data.payload_rtmr_index),
+                    data.payload,
+                )?,
+            },
+            // Measured in place of the payload binary.
+            MeasuredData::PayloadComponentTable | MeasuredData::PayloadComponent => {}
+            MeasuredData::PayloadParameter => {
+                log_boot_measurement(cc_event_log, measurement, data.payload_parameter)?
+            }
+            MeasuredData::Separator => {
+                log_boot_measurement(cc_event_log, measurement, &SEPARATOR_DATA)?
+            }
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use super::TdShimPlatformConfigInfoHeader;
+    use super::*;
     use core::mem::size_of;
 
     #[test]
@@ -131,4 +489,200 @@ mod test {
         let descriptor: [u8; 17] = [0; 17];
         assert!(TdShimPlatformConfigInfoHeader::new(&descriptor, 0).is_none());
     }
+
+    #[cfg(feature = "secure-boot")]
+    #[test]
+    fn test_boot_measurement_sequence() {
+        extern crate alloc;
+        use alloc::{boxed::Box, rc::Rc};
+        use cc_measurement::SHA384_DIGEST_SIZE;
+        use core::cell::RefCell;
+        use ring::digest;
+
+        const HOB: &[u8] = b"td hob";
+        const TRUST_ANCHOR: &[u8] = b"trust anchor";
+        const PAYLOAD: &[u8] = b"payload";
+        // The seed CFV holds no measured config.
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        assert_eq!(measured_configs(cfv).count(), 0);
+        let data = BootMeasurementData {
+            td_hob: Some(HOB),
+            cfv,
+            trust_anchor: Some(TRUST_ANCHOR),
+            payload_svn: Some(3),
+            payload: PAYLOAD,
+            payload_rtmr_index: MEASUREMENT_PAYLOAD_BINARY.rtmr_index(),
+            components: None,
+            payload_parameter: b"",
+        };
+        let unsigned = BootMeasurementData {
+            trust_anchor: None,
+            payload_svn: None,
+            ..data
+        };
+
+        // Measure `sequence` as the runtime does, around the payload verification, with the
+        // RTMRs emulated.
+        let measure = |sequence: &[BootMeasurement], data: &BootMeasurementData| {
+            let rtmrs = Rc::new(RefCell::new([[0u8; SHA384_DIGEST_SIZE]; 4]));
+            let emulated = rtmrs.clone();
+            let mut buffer = [0u8; 0x2000];
+            let mut event_log = CcEventLogWriter::new(
+                &mut buffer,
+                Box::new(move |digest: &[u8; SHA384_DIGEST_SIZE], mr_index: u32| {
+                    let rtmr = &mut emulated.borrow_mut()[mr_index as usize - 1];
+                    let mut context = digest::Context::new(&digest::SHA384);
+                    context.update(rtmr.as_ref());
+                    context.update(digest);
+                    rtmr.copy_from_slice(context.finish().as_ref());
+                    Ok::<(), CcEventLogError>(())
+                }),
+            )
+            .unwrap();
+            let (unverified, verified) = split_at_verification(sequence);
+            log_boot_measurements(unverified, data, &mut event_log).unwrap();
+            log_boot_measurements(verified, data, &mut event_log).unwrap();
+            let rtmrs = *rtmrs.borrow();
+            assert_eq!(rtmrs[2], [0u8; SHA384_DIGEST_SIZE]);
+            assert_eq!(rtmrs[3], [0u8; SHA384_DIGEST_SIZE]);
+            [rtmrs[0], rtmrs[1]]
+        };
+        let rtmrs = |rtmr0: &str, rtmr1: &str| {
+            let mut rtmrs = [[0u8; SHA384_DIGEST_SIZE]; 2];
+            for (rtmr, hex) in rtmrs.iter_mut().zip([rtmr0, rtmr1]) {
+                for (idx, v) in rtmr.iter_mut().enumerate() {
+                    *v = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).unwrap();
+                }
+            }
+            rtmrs
+        };
+
+        // The known-good RTMRs are computed from the event order of td-shim before the sequence
+        // was declared: the TD HOB into RTMR[0], the trust anchor into RTMR[0] before and after
+        // the verification, the SVN and the payload into RTMR[1], then the separator into both.
+        assert_eq!(
+            measure(&EXECUTABLE_PAYLOAD_MEASUREMENTS, &data),
+            rtmrs(
+                "9d2b3e56b65d854ac4405436e92241e3ed61252a3fbf68b29ed7384bddf9b7aad4ddf7c02f46ffcd00734be328a92d8f",
+                "6a6b8e4ab471104b8428988452511432022e970075f6f7f0645430dcd79d5a6b873cdd0ae6cc7eb4b18d40fa6648d9c2",
+            )
+        );
+        // An unsigned payload skips the trust anchor and SVN events.
+        assert_eq!(
+            measure(&EXECUTABLE_PAYLOAD_MEASUREMENTS, &unsigned),
+            rtmrs(
+                "72c60965ec07e60a8816fc1f6c14f6180e3de29e0e11e24e2a38bc93d0eeb030090f7816178c877004156707d4946e21",
+                "d952af3ccd9d3391df12598d669cadd970b9203c2ffa9a710ea7e7bac8f8b59523173e38444bd011a12a29f5ae68734e",
+            )
+        );
+        // The payload info HOB adds the separator right after the TD HOB.
+        assert_eq!(
+            measure(&PAYLOAD_INFO_EXECUTABLE_MEASUREMENTS, &data),
+            rtmrs(
+                "1bfd899bf2ed20f1177b933da37497bfcfb063334820bfdd27389871db2e92d513f92fc0a9f5365ebaab0e6874335a8a",
+                "6137428a703e1f30c28add7646822ee3881105b30f1192e36b19e938b6ec87bcb93032f21094c34a6742780c7e385a6b",
+            )
+        );
+        // A Linux kernel is measured after the separator, followed by its empty parameter.
+        assert_eq!(
+            measure(&LINUX_KERNEL_MEASUREMENTS, &unsigned),
+            rtmrs(
+                "72c60965ec07e60a8816fc1f6c14f6180e3de29e0e11e24e2a38bc93d0eeb030090f7816178c877004156707d4946e21",
+                "54014acd5903e0c23854573c2febaa90f3004c114488829bf27dae17c95b03410728d7c76e74654071603b23ade5b263",
+            )
+        );
+
+        let (unverified, verified) = split_at_verification(&EXECUTABLE_PAYLOAD_MEASUREMENTS);
+        assert_eq!(unverified.last(), Some(&MEASUREMENT_SECURE_POLICY_DB));
+        assert_eq!(verified.first(), Some(&MEASUREMENT_SECURE_AUTHORITY));
+        let (unverified, verified) = split_at_verification(&LINUX_KERNEL_MEASUREMENTS);
+        assert_eq!(unverified, &LINUX_KERNEL_MEASUREMENTS[..]);
+        assert!(verified.is_empty());
+    }
+
+    #[cfg(feature = "secure-boot")]
+    #[test]
+    fn test_log_payload_components() {
+        extern crate alloc;
+        use crate::multi_component::{
+            toc_size, ComponentEntry, MultiComponentHeader, MULTI_COMPONENT_SIGNATURE,
+            MULTI_COMPONENT_STRUCT_VERSION_V1,
+        };
+        use alloc::{boxed::Box, rc::Rc};
+        use cc_measurement::SHA384_DIGEST_SIZE;
+        use core::cell::RefCell;
+        use ring::digest;
+        use scroll::{Endian, Pwrite};
+
+        // A kernel measured into RTMR[1], an initrd and a command line into RTMR[2].
+        let components: [(u32, &[u8]); 3] = [(1, b"kernel"), (2, b"initrd"), (2, b"cmdline")];
+        let mut image = [0u8; 0x100];
+        let header = MultiComponentHeader {
+            signature: MULTI_COMPONENT_SIGNATURE,
+            struct_version: MULTI_COMPONENT_STRUCT_VERSION_V1,
+            component_count: components.len() as u32,
+            reserved: 0,
+        };
+        image.pwrite_with(header, 0, Endian::Little).unwrap();
+        let mut offset = toc_size(components.len());
+        for (idx, (rtmr_index, data)) in components.iter().enumerate() {
+            let entry = ComponentEntry {
+                component_type: idx as u32 + 2,
+                rtmr_index: *rtmr_index,
+                offset: offset as u32,
+                size: data.len() as u32,
+            };
+            image
+                .pwrite_with(entry, toc_size(idx), Endian::Little)
+                .unwrap();
+            image[offset..offset + data.len()].copy_from_slice(data);
+            offset += data.len();
+        }
+        let extend = |rtmr: &mut [u8; SHA384_DIGEST_SIZE], data: &[u8]| {
+            let mut context = digest::Context::new(&digest::SHA384);
+            context.update(rtmr.as_ref());
+            context.update(digest::digest(&digest::SHA384, data).as_ref());
+            rtmr.copy_from_slice(context.finish().as_ref());
+        };
+        let measure = |image: &[u8]| {
+            let payload = MultiComponentPayload::parse(image).unwrap();
+            let rtmrs = Rc::new(RefCell::new([[0u8; SHA384_DIGEST_SIZE]; 4]));
+            let emulated = rtmrs.clone();
+            let mut buffer = [0u8; 0x1000];
+            let mut event_log = CcEventLogWriter::new(
+                &mut buffer,
+                Box::new(move |digest: &[u8; SHA384_DIGEST_SIZE], mr_index: u32| {
+                    let mut context = digest::Context::new(&digest::SHA384);
+                    let rtmr = &mut emulated.borrow_mut()[mr_index as usize - 1];
+                    context.update(rtmr.as_ref());
+                    context.update(digest);
+                    rtmr.copy_from_slice(context.finish().as_ref());
+                    Ok::<(), CcEventLogError>(())
+                }),
+            )
+            .unwrap();
+            log_payload_components(&payload, &mut event_log);
+            let measured = *rtmrs.borrow();
+            measured
+        };
+
+        // The table of contents is measured into RTMR[1] before the components.
+        let mut expected = [[0u8; SHA384_DIGEST_SIZE]; 4];
+        extend(&mut expected[1], &image[..toc_size(components.len())]);
+        for (rtmr_index, data) in components.iter() {
+            extend(&mut expected[*rtmr_index as usize], data);
+        }
+        assert_eq!(measure(&image), expected);
+        assert_eq!(expected[0], [0u8; SHA384_DIGEST_SIZE]);
+        assert_eq!(expected[3], [0u8; SHA384_DIGEST_SIZE]);
+
+        // Swapping the types of two components changes the measurements, though the data of each
+        // component is measured the same.
+        let mut swapped = image;
+        swapped[toc_size(0)..toc_size(0) + 4].copy_from_slice(&3u32.to_le_bytes());
+        swapped[toc_size(1)..toc_size(1) + 4].copy_from_slice(&2u32.to_le_bytes());
+        let measured = measure(&swapped);
+        assert_ne!(measured[1], expected[1]);
+        assert_eq!(measured[2], expected[2]);
+    }
 }
@@ -12,7 +12,9 @@ pub mod acpi;
 pub mod e820;
 pub mod event_log;
 pub mod fv;
+pub mod measured_config;
 pub mod metadata;
+pub mod multi_component;
 pub mod reset_vector;
 
 #[cfg(feature = "secure-boot")]
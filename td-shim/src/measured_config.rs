@@ -0,0 +1,163 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Config blobs enrolled into the Configuration Firmware Volume (CFV) to be measured at boot.
+//!
+//! A measured config is a raw firmware file whose content starts with `MeasuredConfigHeader`,
+//! followed by the config blob. td-shim measures the whole content of every measured config in the
+//! CFV into RTMR[0] before booting the payload, see `MEASUREMENT_CFV_CONFIG`.
+//!
+//! Please refer to section "Measured Config in Td-Shim" in doc/secure_boot.md for definitions.
+
+use core::mem::size_of;
+use core::ptr::slice_from_raw_parts;
+
+use r_efi::efi::Guid;
+use scroll::{Endian, Pread, Pwrite};
+use td_uefi_pi::fv::CfvReader;
+use td_uefi_pi::pi::fv::FV_FILETYPE_RAW;
+
+/// GUID of the header of a measured config file.
+pub const CFV_FILE_HEADER_MEASURED_CONFIG_GUID: Guid = Guid::from_fields(
+    0x3a0c6b1e,
+    0x5f2d,
+    0x4c8a,
+    0x9e,
+    0x71,
+    &[0x2b, 0xd4, 0x60, 0x8f, 0xc3, 0x95],
+); // {3A0C6B1E-5F2D-4C8A-9E71-2BD4608FC395}
+
+pub const MEASURED_CONFIG_STRUCT_VERSION_V1: u32 = 0x1;
+/// Maximum size of the name of a measured config.
+pub const MEASURED_CONFIG_NAME_SIZE: usize = 16;
+/// Offset of the `name` field in `MeasuredConfigHeader`.
+const MEASURED_CONFIG_NAME_OFFSET: usize = 24;
+
+#[repr(C, align(4))]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct MeasuredConfigHeader {
+    pub type_guid: [u8; 16],
+    pub struct_version: u32,
+    /// Size of the header and the config blob.
+    pub length: u32,
+    /// Name of the config, padded with zero.
+    pub name: [u8; MEASURED_CONFIG_NAME_SIZE],
+}
+
+impl MeasuredConfigHeader {
+    /// Build the header of the config `name` with `data_len` bytes of config blob.
+    pub fn new(name: &[u8], data_len: usize) -> Option<Self> {
+        if name.is_empty() || name.len() > MEASURED_CONFIG_NAME_SIZE {
+            return None;
+        }
+        let length = size_of::<Self>().checked_add(data_len)?;
+        if length > u32::MAX as usize {
+            return None;
+        }
+
+        let mut header = MeasuredConfigHeader {
+            type_guid: *CFV_FILE_HEADER_MEASURED_CONFIG_GUID.as_bytes(),
+            struct_version: MEASURED_CONFIG_STRUCT_VERSION_V1,
+            length: length as u32,
+            ..Default::default()
+        };
+        header.name[..name.len()].copy_from_slice(name);
+        Some(header)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { &*slice_from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+
+    /// Encode the header in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; size_of::<MeasuredConfigHeader>()] {
+        let mut bytes = [0u8; size_of::<MeasuredConfigHeader>()];
+        // Safe to unwrap() because the buffer is big enough.
+        bytes.pwrite_with(self, 0, Endian::Little).unwrap();
+        bytes
+    }
+}
+
+/// A measured config found in the CFV.
+#[derive(Clone, Copy, Debug)]
+pub struct MeasuredConfig<'a> {
+    /// Name of the config, without the zero padding.
+    pub name: &'a [u8],
+    /// The config blob.
+    pub data: &'a [u8],
+    /// The header followed by the config blob, which is measured.
+    pub content: &'a [u8],
+}
+
+impl<'a> MeasuredConfig<'a> {
+    /// Parse the content of a raw firmware file, return `None` if it's not a measured config.
+    pub fn parse(file: &'a [u8]) -> Option<Self> {
+        let header: MeasuredConfigHeader = file.pread_with(0, Endian::Little).ok()?;
+        if &header.type_guid != CFV_FILE_HEADER_MEASURED_CONFIG_GUID.as_bytes()
+            || header.struct_version != MEASURED_CONFIG_STRUCT_VERSION_V1
+        {
+            return None;
+        }
+        let length = header.length as usize;
+        if length < size_of::<MeasuredConfigHeader>() || length > file.len() {
+            return None;
+        }
+
+        let content = &file[..length];
+        let name_len = header
+            .name
+            .iter()
+            .position(|v| *v == 0)
+            .unwrap_or(MEASURED_CONFIG_NAME_SIZE);
+        Some(MeasuredConfig {
+            name: &content[MEASURED_CONFIG_NAME_OFFSET..MEASURED_CONFIG_NAME_OFFSET + name_len],
+            data: &content[size_of::<MeasuredConfigHeader>()..],
+            content,
+        })
+    }
+}
+
+/// Find the measured configs in the CFV, in the order stored.
+///
+/// The search stops at the first malformed firmware file, as the files following it can't be
+/// located.
+pub fn measured_configs(cfv: &[u8]) -> impl Iterator<Item = MeasuredConfig<'_>> + '_ {
+    CfvReader::new(cfv)
+        .ok()
+        .into_iter()
+        .flat_map(|reader| reader.iter())
+        .map_while(|entry| entry.ok())
+        .filter(|entry| entry.kind == FV_FILETYPE_RAW)
+        .filter_map(|entry| MeasuredConfig::parse(entry.data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_measured_config_header() {
+        assert_eq!(size_of::<MeasuredConfigHeader>(), 40);
+        assert!(MeasuredConfigHeader::new(b"", 0).is_none());
+        assert!(MeasuredConfigHeader::new(&[b'a'; 17], 0).is_none());
+
+        let header = MeasuredConfigHeader::new(b"boot_config", 5).unwrap();
+        assert_eq!(header.length, 45);
+        // Padding of the firmware file isn't part of the config.
+        let mut file = [0u8; 48];
+        file[..40].copy_from_slice(&header.to_bytes());
+        file[40..45].copy_from_slice(b"hello");
+
+        let config = MeasuredConfig::parse(&file).unwrap();
+        assert_eq!(config.name, b"boot_config");
+        assert_eq!(config.data, b"hello");
+        assert_eq!(config.content, &file[..45]);
+
+        assert!(MeasuredConfig::parse(&file[..44]).is_none());
+        let mut other = file;
+        other[0] ^= 0xff;
+        assert!(MeasuredConfig::parse(&other).is_none());
+        assert!(measured_configs(&[0u8; 0x100]).next().is_none());
+    }
+}
@@ -5,8 +5,10 @@
 extern crate alloc;
 
 use alloc::string::String;
+use core::convert::TryFrom;
 use core::{ptr::slice_from_raw_parts, str::FromStr};
 use scroll::{Pread, Pwrite};
+use td_layout::build_time::{TD_SHIM_PAYLOAD_OFFSET, TD_SHIM_PAYLOAD_SIZE};
 use td_uefi_pi::pi::guid::Guid;
 
 /// TDX Metadata GUID defined in td-shim specification
@@ -109,6 +111,14 @@ impl TdxMetadataDescriptor {
             )
         }
     }
+
+    /// Encode the descriptor in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; TDX_METADATA_DESCRIPTOR_LEN as usize] {
+        let mut bytes = [0u8; TDX_METADATA_DESCRIPTOR_LEN as usize];
+        // Safe to unwrap() because the buffer is big enough.
+        bytes.pwrite_with(self, 0, scroll::LE).unwrap();
+        bytes
+    }
 }
 
 #[repr(C)]
@@ -141,6 +151,14 @@ impl TdxMetadataSection {
             )
         }
     }
+
+    /// Encode the section in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; TDX_METADATA_SECTION_LEN as usize] {
+        let mut bytes = [0u8; TDX_METADATA_SECTION_LEN as usize];
+        // Safe to unwrap() because the buffer is big enough.
+        bytes.pwrite_with(self, 0, scroll::LE).unwrap();
+        bytes
+    }
 }
 
 #[repr(C)]
@@ -187,9 +205,64 @@ impl Default for TdxMetadataGuid {
 #[derive(Debug)]
 pub enum TdxMetadataError {
     InvalidSection,
+    /// The metadata pointer, GUID or descriptor of the image is invalid.
+    InvalidMetadata,
+    /// The metadata pointer at the end of the image points outside of the image.
+    InvalidPointer,
+    /// The metadata descriptor is not preceded by the metadata GUID.
+    InvalidGuid,
+    /// The signature, version or length of the metadata descriptor is invalid.
+    InvalidDescriptor,
+    /// The number of sections declared in the descriptor doesn't match the section table.
+    SectionCountMismatch {
+        declared: u32,
+        available: u32,
+    },
+}
+
+/// Check that the section count declared in `descriptor` is consistent with the section table.
+///
+/// `table_size` is the number of bytes available for the section array, which starts right after
+/// the descriptor. Both the bytes available and the descriptor `length` field must be able to
+/// hold `number_of_section_entry` sections.
+pub fn validate_section_count(
+    descriptor: &TdxMetadataDescriptor,
+    table_size: usize,
+) -> Result<(), TdxMetadataError> {
+    let declared = descriptor.number_of_section_entry;
+    let available = table_size / TDX_METADATA_SECTION_LEN as usize;
+    if declared as usize > available {
+        return Err(TdxMetadataError::SectionCountMismatch {
+            declared,
+            available: available.min(u32::MAX as usize) as u32,
+        });
+    }
+
+    // TdxMetadataDescriptor.length covers the descriptor and the section array.
+    let length = descriptor.length;
+    if length < TDX_METADATA_DESCRIPTOR_LEN
+        || (length - TDX_METADATA_DESCRIPTOR_LEN) % TDX_METADATA_SECTION_LEN != 0
+        || (length - TDX_METADATA_DESCRIPTOR_LEN) / TDX_METADATA_SECTION_LEN != declared
+    {
+        return Err(TdxMetadataError::SectionCountMismatch {
+            declared,
+            available: length.saturating_sub(TDX_METADATA_DESCRIPTOR_LEN)
+                / TDX_METADATA_SECTION_LEN,
+        });
+    }
+
+    Ok(())
 }
 
 pub fn validate_sections(sections: &[TdxMetadataSection]) -> Result<(), TdxMetadataError> {
+    validate_section_iter(sections.iter().copied())
+}
+
+// Sections are passed by value, so the section table could be validated in place.
+fn validate_section_iter<I>(sections: I) -> Result<(), TdxMetadataError>
+where
+    I: Iterator<Item = TdxMetadataSection>,
+{
     let mut bfv_cnt = 0;
     let mut hob_cnt = 0;
     let mut perm_mem_cnt = 0;
@@ -203,12 +276,18 @@ pub fn validate_sections(sections: &[TdxMetadataSection]) -> Result<(), TdxMetad
             if data_size != 0 && memory_size < data_size as u64 {
                 return false;
             }
+            // Neither the data in the image nor the memory region may wrap around.
+            if data_offset.checked_add(data_size).is_none()
+                || memory_address.checked_add(memory_size).is_none()
+            {
+                return false;
+            }
             if (memory_address & 0xfff) != 0 {
                 return false;
             }
             true
         };
-    for section in sections.iter() {
+    for section in sections {
         match section.r#type {
             TDX_METADATA_SECTION_TYPE_BFV => {
                 // A TD-Shim shall include at least one BFV and the reset vector shall be inside
@@ -393,6 +472,161 @@ pub fn validate_sections(sections: &[TdxMetadataSection]) -> Result<(), TdxMetad
     Ok(())
 }
 
+/// TDX metadata of an image parsed in place, without allocating, so td-shim could use it at
+/// runtime as well as the tools at build time.
+///
+/// The sections are read from the section table of the image when accessed.
+#[derive(Clone, Copy, Debug)]
+pub struct TdxMetadataRef<'a> {
+    image: &'a [u8],
+    offset: usize,
+    table: &'a [u8],
+}
+
+impl<'a> TdxMetadataRef<'a> {
+    /// Locate the TDX metadata of `image` through the metadata pointer at its end, then check
+    /// the GUID, the descriptor and that the section table fits into the image.
+    ///
+    /// The sections themselves are not checked, see `validate()`.
+    pub fn parse(image: &'a [u8]) -> Result<Self, TdxMetadataError> {
+        let ptr_offset = image
+            .len()
+            .checked_sub(TDX_METADATA_OFFSET as usize)
+            .ok_or(TdxMetadataError::InvalidPointer)?;
+        // Safe to unwrap() because the pointer is inside of the image.
+        let offset = image.pread_with::<u32>(ptr_offset, scroll::LE).unwrap() as usize;
+        // The GUID and the descriptor are placed before the metadata pointer.
+        if offset < TDX_METADATA_GUID_LEN as usize
+            || offset
+                .checked_add(TDX_METADATA_DESCRIPTOR_LEN as usize)
+                .map_or(true, |end| end > ptr_offset)
+        {
+            return Err(TdxMetadataError::InvalidPointer);
+        }
+
+        let mut guid = [0u8; TDX_METADATA_GUID_LEN as usize];
+        guid.copy_from_slice(&image[offset - TDX_METADATA_GUID_LEN as usize..offset]);
+        if TdxMetadataGuid::from_bytes(&guid).is_none() {
+            return Err(TdxMetadataError::InvalidGuid);
+        }
+
+        // Safe to unwrap() because the range has been checked above.
+        let descriptor = image
+            .pread_with::<TdxMetadataDescriptor>(offset, scroll::LE)
+            .unwrap();
+        let table_offset = offset + TDX_METADATA_DESCRIPTOR_LEN as usize;
+        validate_section_count(&descriptor, image.len() - table_offset)?;
+        if !descriptor.is_valid() {
+            return Err(TdxMetadataError::InvalidDescriptor);
+        }
+        let table_size =
+            descriptor.number_of_section_entry as usize * TDX_METADATA_SECTION_LEN as usize;
+
+        Ok(TdxMetadataRef {
+            image,
+            offset,
+            table: &image[table_offset..table_offset + table_size],
+        })
+    }
+
+    /// Offset of the descriptor in the image, as stored in the metadata pointer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn descriptor(&self) -> TdxMetadataDescriptor {
+        // Safe to unwrap() because the descriptor has been checked by `parse()`.
+        self.image
+            .pread_with::<TdxMetadataDescriptor>(self.offset, scroll::LE)
+            .unwrap()
+    }
+
+    /// The section table as stored in the image.
+    pub fn section_table(&self) -> &'a [u8] {
+        self.table
+    }
+
+    /// Number of sections.
+    pub fn len(&self) -> usize {
+        self.table.len() / TDX_METADATA_SECTION_LEN as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Get the section `idx` of the section table.
+    pub fn section(&self, idx: usize) -> Option<TdxMetadataSection> {
+        if idx >= self.len() {
+            return None;
+        }
+        self.table
+            .pread_with(idx * TDX_METADATA_SECTION_LEN as usize, scroll::LE)
+            .ok()
+    }
+
+    /// Get the sections in the order of the section table.
+    pub fn sections(&self) -> impl Iterator<Item = TdxMetadataSection> + 'a {
+        let metadata = *self;
+        (0..self.len()).filter_map(move |idx| metadata.section(idx))
+    }
+
+    /// Get the first section of type `r#type`.
+    pub fn find(&self, r#type: u32) -> Option<TdxMetadataSection> {
+        self.sections().find(|s| s.r#type == r#type)
+    }
+
+    /// Get the raw data of `section` in the image, `None` if it's out of the image.
+    pub fn section_data(&self, section: &TdxMetadataSection) -> Option<&'a [u8]> {
+        let start = section.data_offset as usize;
+        let end = start.checked_add(section.raw_data_size as usize)?;
+        self.image.get(start..end)
+    }
+
+    /// Check the sections as `validate_sections()` does.
+    pub fn validate(&self) -> Result<(), TdxMetadataError> {
+        validate_section_iter(self.sections())
+    }
+}
+
+/// Get the number of bytes the payload region of the shim `image` can hold, as declared by its
+/// metadata.
+///
+/// This is the size of the payload section, or the size of the memory the payload is loaded into
+/// if the section has no data in the image. Payloads embedded into the BFV, for which there is no
+/// payload section, are limited to the payload region of the layout. A signed payload must also
+/// fit its verify header and signature block into the region, the sign-time cap is
+/// `max_payload_bytes()` of td-shim-tools for a region of this size.
+pub fn payload_capacity(image: &[u8]) -> Result<usize, TdxMetadataError> {
+    let metadata = TdxMetadataRef::parse(image).map_err(|e| match e {
+        TdxMetadataError::InvalidPointer
+        | TdxMetadataError::InvalidGuid
+        | TdxMetadataError::InvalidDescriptor => TdxMetadataError::InvalidMetadata,
+        e => e,
+    })?;
+    metadata.validate()?;
+
+    let (data_offset, capacity) = match metadata.find(TDX_METADATA_SECTION_TYPE_PAYLOAD) {
+        Some(s) if s.raw_data_size != 0 => (s.data_offset as usize, s.raw_data_size as usize),
+        Some(s) => {
+            return usize::try_from(s.memory_data_size)
+                .map_err(|_| TdxMetadataError::InvalidSection)
+        }
+        None => (
+            TD_SHIM_PAYLOAD_OFFSET as usize,
+            TD_SHIM_PAYLOAD_SIZE as usize,
+        ),
+    };
+    if data_offset
+        .checked_add(capacity)
+        .map_or(true, |end| end > image.len())
+    {
+        return Err(TdxMetadataError::InvalidSection);
+    }
+
+    Ok(capacity)
+}
+
 #[repr(C)]
 #[derive(Default, Pwrite, Pread)]
 pub struct TdxMetadataPtr {
@@ -408,6 +642,11 @@ impl TdxMetadataPtr {
             )
         }
     }
+
+    /// Encode the pointer in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        self.ptr.to_le_bytes()
+    }
 }
 
 #[cfg(test)]
@@ -482,6 +721,43 @@ mod tests {
         assert!(TdxMetadataSection::get_type_name(7).is_none())
     }
 
+    #[test]
+    fn test_validate_section_count() {
+        let mut desc = TdxMetadataDescriptor::default();
+        desc.set_sections(3);
+        assert!(validate_section_count(&desc, 3 * 32).is_ok());
+        assert!(validate_section_count(&desc, 4 * 32 + 1).is_ok());
+
+        match validate_section_count(&desc, 2 * 32 + 31) {
+            Err(TdxMetadataError::SectionCountMismatch {
+                declared,
+                available,
+            }) => {
+                assert_eq!(declared, 3);
+                assert_eq!(available, 2);
+            }
+            _ => panic!("truncated section table should be rejected"),
+        }
+
+        // The length field claims fewer sections than the descriptor declares.
+        desc.length = 16 + 2 * 32;
+        match validate_section_count(&desc, 0x1000) {
+            Err(TdxMetadataError::SectionCountMismatch {
+                declared,
+                available,
+            }) => {
+                assert_eq!(declared, 3);
+                assert_eq!(available, 2);
+            }
+            _ => panic!("inconsistent length field should be rejected"),
+        }
+
+        desc.length = 16 + 3 * 32 + 1;
+        assert!(validate_section_count(&desc, 0x1000).is_err());
+        desc.length = 8;
+        assert!(validate_section_count(&desc, 0x1000).is_err());
+    }
+
     #[test]
     fn test_validate_sections() {
         // empty sections at leaset one bfv section
@@ -564,6 +840,15 @@ mod tests {
         sections[0].memory_address += 1;
         assert!(!validate_sections(&sections).is_ok());
         sections[0].memory_address -= 1;
+        // data or memory region wraps around
+        let (data_offset, memory_address) = (sections[0].data_offset, sections[0].memory_address);
+        sections[0].data_offset = u32::MAX - 0xfff;
+        assert!(!validate_sections(&sections).is_ok());
+        sections[0].data_offset = data_offset;
+        sections[0].memory_address = u64::MAX & !0xfff;
+        assert!(!validate_sections(&sections).is_ok());
+        sections[0].memory_address = memory_address;
+        assert!(validate_sections(&sections).is_ok());
         // multiple CFV
         sections[3].r#type = TDX_METADATA_SECTION_TYPE_BFV;
         sections[3].attributes = TDX_METADATA_ATTRIBUTES_EXTENDMR;
@@ -728,6 +1013,138 @@ mod tests {
     fn test_tdxmetadataptr() {
         let ptr = TdxMetadataPtr { ptr: 0x1000 };
 
-        assert_eq!(ptr.as_bytes(), 0x1000_i32.to_le_bytes())
+        assert_eq!(ptr.as_bytes(), 0x1000_i32.to_le_bytes());
+        assert_eq!(ptr.to_bytes(), 0x1000_i32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_metadata_little_endian_layout() {
+        // The expected layout is written out byte by byte, so the test doesn't depend on the
+        // byte order of the host or on the helpers under test.
+        let desc = TdxMetadataDescriptor {
+            signature: TDX_METADATA_SIGNATURE,
+            length: 0x0102_0304,
+            version: TDX_METADATA_VERSION,
+            number_of_section_entry: 0x0506_0708,
+        };
+        let expected: [u8; TDX_METADATA_DESCRIPTOR_LEN as usize] = [
+            b'T', b'D', b'V', b'F', 0x04, 0x03, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x08, 0x07,
+            0x06, 0x05,
+        ];
+        assert_eq!(desc.to_bytes(), expected);
+        let parsed: TdxMetadataDescriptor = expected.pread_with(0, scroll::LE).unwrap();
+        assert_eq!(parsed.length, desc.length);
+        assert_eq!(parsed.number_of_section_entry, desc.number_of_section_entry);
+
+        // Simulate a big-endian host, which would store the fields byte-swapped in memory.
+        let swapped = TdxMetadataDescriptor {
+            signature: TDX_METADATA_SIGNATURE.swap_bytes(),
+            length: 0x0102_0304u32.swap_bytes(),
+            version: TDX_METADATA_VERSION.swap_bytes(),
+            number_of_section_entry: 0x0506_0708u32.swap_bytes(),
+        };
+        assert_eq!(
+            swapped.to_bytes(),
+            [
+                b'F', b'V', b'D', b'T', 0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x01, 0x05, 0x06,
+                0x07, 0x08,
+            ]
+        );
+
+        let section = TdxMetadataSection {
+            data_offset: 0x1122_3344,
+            raw_data_size: 0x5566_7788,
+            memory_address: 0x0102_0304_0506_0708,
+            memory_data_size: 0x1112_1314_1516_1718,
+            r#type: TDX_METADATA_SECTION_TYPE_CFV,
+            attributes: 0x8000_0001,
+        };
+        let expected: [u8; TDX_METADATA_SECTION_LEN as usize] = [
+            0x44, 0x33, 0x22, 0x11, 0x88, 0x77, 0x66, 0x55, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03,
+            0x02, 0x01, 0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x80,
+        ];
+        assert_eq!(section.to_bytes(), expected);
+        let parsed: TdxMetadataSection = expected.pread_with(0, scroll::LE).unwrap();
+        assert_eq!(parsed.memory_address, section.memory_address);
+        assert_eq!(parsed.attributes, section.attributes);
+    }
+
+    // A 4 KiB image with a CFV at 0x0, a BFV at 0x800 and a TD HOB, shared with the tests of
+    // td-shim-tools.
+    const METADATA_IMAGE: &[u8; 0x1000] = include_bytes!("../../data/blobs/td-shim-metadata.bin");
+
+    #[test]
+    fn test_tdx_metadata_ref() {
+        let metadata = TdxMetadataRef::parse(METADATA_IMAGE).unwrap();
+        assert_eq!(metadata.offset(), 0x810);
+        assert_eq!(metadata.descriptor().number_of_section_entry, 3);
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata.section_table(), &METADATA_IMAGE[0x820..0x880]);
+        assert!(metadata.validate().is_ok());
+        assert_eq!(metadata.sections().count(), 3);
+        assert!(metadata.section(3).is_none());
+
+        let bfv = metadata.section(0).unwrap();
+        assert_eq!(bfv.r#type, TDX_METADATA_SECTION_TYPE_BFV);
+        assert_eq!(bfv.attributes, TDX_METADATA_ATTRIBUTES_EXTENDMR);
+        let cfv = metadata.find(TDX_METADATA_SECTION_TYPE_CFV).unwrap();
+        assert_eq!(metadata.section_data(&cfv).unwrap(), &[0xcf; 0x400][..]);
+        assert!(metadata.find(TDX_METADATA_SECTION_TYPE_PAYLOAD).is_none());
+
+        // The section data must be inside of the image.
+        let mut section = cfv;
+        section.data_offset = 0xc00;
+        section.raw_data_size = 0x800;
+        assert!(metadata.section_data(&section).is_none());
+        section.raw_data_size = u32::MAX;
+        assert!(metadata.section_data(&section).is_none());
+    }
+
+    #[test]
+    fn test_tdx_metadata_ref_invalid() {
+        assert!(matches!(
+            TdxMetadataRef::parse(&METADATA_IMAGE[..0x10]),
+            Err(TdxMetadataError::InvalidPointer)
+        ));
+        assert!(matches!(
+            TdxMetadataRef::parse(&METADATA_IMAGE[..0x800]),
+            Err(TdxMetadataError::InvalidPointer)
+        ));
+
+        let mut image = *METADATA_IMAGE;
+        image[0x800] ^= 0xff;
+        assert!(matches!(
+            TdxMetadataRef::parse(&image),
+            Err(TdxMetadataError::InvalidGuid)
+        ));
+
+        let mut image = *METADATA_IMAGE;
+        image[0x818] = 2;
+        assert!(matches!(
+            TdxMetadataRef::parse(&image),
+            Err(TdxMetadataError::InvalidDescriptor)
+        ));
+
+        // More sections declared than the image holds.
+        let mut image = *METADATA_IMAGE;
+        image[0x814..0x818].copy_from_slice(&(16u32 + 32 * 0x100).to_le_bytes());
+        image[0x81c..0x820].copy_from_slice(&0x100u32.to_le_bytes());
+        assert!(matches!(
+            TdxMetadataRef::parse(&image),
+            Err(TdxMetadataError::SectionCountMismatch {
+                declared: 0x100,
+                ..
+            })
+        ));
+
+        // Invalid sections are only reported by validate(), the type of the CFV is made unknown.
+        let mut image = *METADATA_IMAGE;
+        image[0x840 + 24] = TDX_METADATA_SECTION_TYPE_MAX as u8;
+        let metadata = TdxMetadataRef::parse(&image).unwrap();
+        assert!(matches!(
+            metadata.validate(),
+            Err(TdxMetadataError::InvalidSection)
+        ));
     }
 }
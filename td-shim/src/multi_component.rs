@@ -0,0 +1,291 @@
+// Copyright (c) 2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Payloads made of several components, such as a kernel, an initrd and a command line, which are
+//! measured separately.
+//!
+//! A multi-component payload starts with a table of contents, `MultiComponentHeader` followed by
+//! one `ComponentEntry` per component, then the data of the components in the order of the table.
+//! The whole payload is signed as one region. td-shim measures the table of contents, then each
+//! component into the RTMR given by its entry instead of measuring the payload as a whole, see
+//! `log_payload_components()`. It's only parsed as such if the verify header records the
+//! multi-component payload type.
+//!
+//! Please refer to section "Multi-Component Payload" in doc/secure_boot.md for definitions.
+
+use core::mem::size_of;
+
+use scroll::{Endian, Pread, Pwrite};
+
+/// Signature of `MultiComponentHeader`, "TDMC".
+pub const MULTI_COMPONENT_SIGNATURE: u32 = 0x434d_4454;
+pub const MULTI_COMPONENT_STRUCT_VERSION_V1: u32 = 0x1;
+/// Maximum number of components of a payload.
+pub const MULTI_COMPONENT_MAX_COUNT: usize = 16;
+/// Alignment of the component data laid out by the tools, the runtime doesn't require it.
+pub const MULTI_COMPONENT_ALIGNMENT: usize = 8;
+/// Components may be measured into RTMR[1] to RTMR[`MULTI_COMPONENT_MAX_RTMR`], RTMR[0] is
+/// reserved for the firmware configuration.
+pub const MULTI_COMPONENT_MAX_RTMR: u32 = 3;
+
+/// Executable image in the PE or ELF format, booted by td-shim.
+pub const COMPONENT_TYPE_EXECUTABLE: u32 = 1;
+/// Linux kernel image.
+pub const COMPONENT_TYPE_KERNEL: u32 = 2;
+/// Initial ramdisk of the kernel.
+pub const COMPONENT_TYPE_INITRD: u32 = 3;
+/// Command line of the kernel.
+pub const COMPONENT_TYPE_CMDLINE: u32 = 4;
+/// Component types from `COMPONENT_TYPE_CUSTOM_MIN` are reserved for custom use.
+pub const COMPONENT_TYPE_CUSTOM_MIN: u32 = 0x80;
+
+const COMPONENT_TYPE_NAMES: [(u32, &str); 4] = [
+    (COMPONENT_TYPE_EXECUTABLE, "executable"),
+    (COMPONENT_TYPE_KERNEL, "kernel"),
+    (COMPONENT_TYPE_INITRD, "initrd"),
+    (COMPONENT_TYPE_CMDLINE, "cmdline"),
+];
+
+/// Get the name of a defined component type, `None` for custom and unknown types.
+pub fn component_type_name(component_type: u32) -> Option<&'static str> {
+    COMPONENT_TYPE_NAMES
+        .iter()
+        .find(|(id, _)| *id == component_type)
+        .map(|(_, name)| *name)
+}
+
+/// Get the defined component type named `name`, such as `initrd`.
+pub fn component_type_from_name(name: &str) -> Option<u32> {
+    COMPONENT_TYPE_NAMES
+        .iter()
+        .find(|(_, v)| *v == name)
+        .map(|(id, _)| *id)
+}
+
+#[repr(C, align(4))]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct MultiComponentHeader {
+    pub signature: u32,
+    pub struct_version: u32,
+    pub component_count: u32,
+    pub reserved: u32,
+}
+
+#[repr(C, align(4))]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct ComponentEntry {
+    pub component_type: u32,
+    /// Index of the RTMR the component is measured into.
+    pub rtmr_index: u32,
+    /// Offset of the component data from the start of the payload.
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Size of the table of contents of a payload with `count` components.
+pub const fn toc_size(count: usize) -> usize {
+    size_of::<MultiComponentHeader>() + count * size_of::<ComponentEntry>()
+}
+
+/// A component of a multi-component payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PayloadComponent<'a> {
+    pub component_type: u32,
+    pub rtmr_index: u32,
+    /// Offset of `data` from the start of the payload.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// A multi-component payload with a valid table of contents.
+#[derive(Clone, Copy, Debug)]
+pub struct MultiComponentPayload<'a> {
+    image: &'a [u8],
+    count: usize,
+}
+
+impl<'a> MultiComponentPayload<'a> {
+    /// Check whether `image` starts with the signature of a multi-component payload, whether the
+    /// table of contents is valid or not.
+    pub fn is_multi_component(image: &[u8]) -> bool {
+        image.pread_with::<u32>(0, Endian::Little) == Ok(MULTI_COMPONENT_SIGNATURE)
+    }
+
+    /// Parse the table of contents of `image`, return `None` if it's not a valid multi-component
+    /// payload.
+    ///
+    /// The components must be stored after the table of contents in the order of the table,
+    /// without overlapping, and be measured into RTMR[1] to RTMR[`MULTI_COMPONENT_MAX_RTMR`].
+    pub fn parse(image: &'a [u8]) -> Option<Self> {
+        let header: MultiComponentHeader = image.pread_with(0, Endian::Little).ok()?;
+        let count = header.component_count as usize;
+        if header.signature != MULTI_COMPONENT_SIGNATURE
+            || header.struct_version != MULTI_COMPONENT_STRUCT_VERSION_V1
+            || count == 0
+            || count > MULTI_COMPONENT_MAX_COUNT
+            || toc_size(count) > image.len()
+        {
+            return None;
+        }
+
+        let mut end = toc_size(count);
+        for idx in 0..count {
+            let entry = read_entry(image, idx)?;
+            let offset = entry.offset as usize;
+            let next = offset.checked_add(entry.size as usize)?;
+            if entry.rtmr_index == 0
+                || entry.rtmr_index > MULTI_COMPONENT_MAX_RTMR
+                || offset < end
+                || next > image.len()
+            {
+                return None;
+            }
+            end = next;
+        }
+
+        Some(MultiComponentPayload { image, count })
+    }
+
+    /// Get the table of contents, the header followed by the entries, which records the type and
+    /// the RTMR of each component.
+    pub fn table(&self) -> &'a [u8] {
+        &self.image[..toc_size(self.count)]
+    }
+
+    /// Number of components.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Get the component `idx` in the order of the table of contents.
+    pub fn component(&self, idx: usize) -> Option<PayloadComponent<'a>> {
+        if idx >= self.count {
+            return None;
+        }
+        // The entries have been checked by `parse()`.
+        let entry = read_entry(self.image, idx)?;
+        let offset = entry.offset as usize;
+        Some(PayloadComponent {
+            component_type: entry.component_type,
+            rtmr_index: entry.rtmr_index,
+            offset,
+            data: &self.image[offset..offset + entry.size as usize],
+        })
+    }
+
+    /// Get the components in the order of the table of contents.
+    pub fn components(&self) -> impl Iterator<Item = PayloadComponent<'a>> + '_ {
+        (0..self.count).filter_map(move |idx| self.component(idx))
+    }
+
+    /// Get the first component of type `component_type`.
+    pub fn find(&self, component_type: u32) -> Option<PayloadComponent<'a>> {
+        self.components()
+            .find(|v| v.component_type == component_type)
+    }
+}
+
+fn read_entry(image: &[u8], idx: usize) -> Option<ComponentEntry> {
+    image.pread_with(toc_size(idx), Endian::Little).ok()
+}
+
+#[cfg(test)]
+mod test {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn build(entries: &[(u32, u32, u32, u32)], len: usize) -> Vec<u8> {
+        let mut image = alloc::vec![0u8; len];
+        let header = MultiComponentHeader {
+            signature: MULTI_COMPONENT_SIGNATURE,
+            struct_version: MULTI_COMPONENT_STRUCT_VERSION_V1,
+            component_count: entries.len() as u32,
+            reserved: 0,
+        };
+        image.pwrite_with(header, 0, Endian::Little).unwrap();
+        for (idx, (component_type, rtmr_index, offset, size)) in entries.iter().enumerate() {
+            let entry = ComponentEntry {
+                component_type: *component_type,
+                rtmr_index: *rtmr_index,
+                offset: *offset,
+                size: *size,
+            };
+            image
+                .pwrite_with(entry, toc_size(idx), Endian::Little)
+                .unwrap();
+        }
+        image
+    }
+
+    #[test]
+    fn test_multi_component_payload() {
+        assert_eq!(size_of::<MultiComponentHeader>(), 16);
+        assert_eq!(size_of::<ComponentEntry>(), 16);
+        assert_eq!(toc_size(3), 64);
+
+        let mut image = build(
+            &[
+                (COMPONENT_TYPE_KERNEL, 1, 64, 16),
+                (COMPONENT_TYPE_INITRD, 2, 80, 8),
+                (COMPONENT_TYPE_CMDLINE, 2, 88, 0),
+            ],
+            96,
+        );
+        image[64..80].fill(0x11);
+        image[80..88].fill(0x22);
+        assert!(MultiComponentPayload::is_multi_component(&image));
+
+        let payload = MultiComponentPayload::parse(&image).unwrap();
+        assert_eq!(payload.len(), 3);
+        let kernel = payload.component(0).unwrap();
+        assert_eq!(kernel.component_type, COMPONENT_TYPE_KERNEL);
+        assert_eq!(kernel.rtmr_index, 1);
+        assert_eq!(kernel.offset, 64);
+        assert_eq!(kernel.data, &[0x11; 16]);
+        let initrd = payload.find(COMPONENT_TYPE_INITRD).unwrap();
+        assert_eq!(initrd.rtmr_index, 2);
+        assert_eq!(initrd.data, &[0x22; 8]);
+        assert_eq!(payload.find(COMPONENT_TYPE_CMDLINE).unwrap().data, b"");
+        assert!(payload.find(COMPONENT_TYPE_EXECUTABLE).is_none());
+        assert!(payload.component(3).is_none());
+        assert_eq!(payload.components().count(), 3);
+        assert_eq!(payload.table(), &image[..64]);
+
+        // Truncated, overlapping, out of order or overflowing components.
+        assert!(MultiComponentPayload::parse(&image[..95]).is_none());
+        let overlap = build(&[(1, 1, 48, 16), (2, 1, 56, 8)], 80);
+        assert!(MultiComponentPayload::parse(&overlap).is_none());
+        let toc = build(&[(1, 1, 16, 16)], 64);
+        assert!(MultiComponentPayload::parse(&toc).is_none());
+        let reversed = build(&[(1, 1, 64, 8), (2, 1, 48, 8)], 80);
+        assert!(MultiComponentPayload::parse(&reversed).is_none());
+        let overflow = build(&[(1, 1, 32, u32::MAX)], 64);
+        assert!(MultiComponentPayload::parse(&overflow).is_none());
+
+        // Components are not measured into RTMR[0] nor beyond RTMR[3].
+        assert!(MultiComponentPayload::parse(&build(&[(1, 0, 32, 8)], 64)).is_none());
+        assert!(MultiComponentPayload::parse(&build(&[(1, 4, 32, 8)], 64)).is_none());
+        assert!(MultiComponentPayload::parse(&build(&[], 64)).is_none());
+
+        // Other payloads.
+        assert!(!MultiComponentPayload::is_multi_component(b"\x7fELF"));
+        assert!(MultiComponentPayload::parse(b"\x7fELF").is_none());
+    }
+
+    #[test]
+    fn test_component_type_name() {
+        for (id, name) in COMPONENT_TYPE_NAMES.iter() {
+            assert_eq!(component_type_name(*id), Some(*name));
+            assert_eq!(component_type_from_name(name), Some(*id));
+        }
+        assert_eq!(component_type_name(COMPONENT_TYPE_CUSTOM_MIN), None);
+        assert_eq!(component_type_from_name("bzImage"), None);
+    }
+}
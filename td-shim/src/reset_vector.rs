@@ -7,7 +7,7 @@ use core::ptr::slice_from_raw_parts;
 
 use r_efi::efi::Guid;
 use scroll::{Pread, Pwrite};
-use td_layout::build_time::TD_SHIM_RESET_VECTOR_SIZE;
+use td_layout::build_time::{TD_SHIM_FIRMWARE_BASE, TD_SHIM_RESET_VECTOR_SIZE};
 use td_uefi_pi::pi::fv::{CommonSectionHeader, FfsFileHeader, FV_FILETYPE_RAW, SECTION_RAW};
 
 use crate::write_u24;
@@ -81,6 +81,73 @@ impl ResetVectorParams {
     }
 }
 
+/// Architectural reset address, the processor starts execution at 16 bytes below 4GB.
+pub const RESET_VECTOR_ADDRESS: u32 = 0xFFFF_FFF0;
+/// Size of the entry stub at the reset address, up to 4GB.
+pub const RESET_VECTOR_ENTRY_SIZE: usize = 16;
+
+const NOP: u8 = 0x90;
+const JMP_REL32: u8 = 0xe9;
+// The entry stub is `nop; nop; jmp rel32`, please refer to ResetVector/Ia32/ResetVectorVtf0.asm.
+const JMP_OFFSET: usize = 2;
+const JMP_SIZE: usize = 5;
+
+/// Build the entry stub placed at the reset address, which jumps to `entry_point`.
+///
+/// The vCPU of a TD starts in 32-bit protected mode, so a near jump reaches the whole firmware.
+/// Return `None` if `entry_point` is out of the firmware mapped below 4GB or inside the stub.
+pub fn build_reset_vector_entry(entry_point: u32) -> Option<[u8; RESET_VECTOR_ENTRY_SIZE]> {
+    if entry_point < TD_SHIM_FIRMWARE_BASE || entry_point >= RESET_VECTOR_ADDRESS {
+        return None;
+    }
+
+    let next = RESET_VECTOR_ADDRESS + (JMP_OFFSET + JMP_SIZE) as u32;
+    let rel = entry_point.wrapping_sub(next);
+    let mut entry = [NOP; RESET_VECTOR_ENTRY_SIZE];
+    entry[JMP_OFFSET] = JMP_REL32;
+    entry[JMP_OFFSET + 1..JMP_OFFSET + JMP_SIZE].copy_from_slice(&rel.to_le_bytes());
+
+    // Make sure the encoded jump lands at the requested entry point.
+    if reset_vector_entry_target(&entry) != Some(entry_point) {
+        return None;
+    }
+
+    Some(entry)
+}
+
+/// Decode the jump target of the entry stub `entry` at the reset address.
+///
+/// Return `None` if `entry` is not a `nop; nop; jmp rel32` stub.
+pub fn reset_vector_entry_target(entry: &[u8]) -> Option<u32> {
+    if entry.len() < JMP_OFFSET + JMP_SIZE
+        || entry[..JMP_OFFSET] != [NOP; JMP_OFFSET]
+        || entry[JMP_OFFSET] != JMP_REL32
+    {
+        return None;
+    }
+
+    let rel = entry.pread_with::<u32>(JMP_OFFSET + 1, scroll::LE).ok()?;
+    let next = RESET_VECTOR_ADDRESS + (JMP_OFFSET + JMP_SIZE) as u32;
+    Some(next.wrapping_add(rel))
+}
+
+/// Place the entry stub jumping to `entry_point` at the reset address of `image`, which is mapped
+/// right below 4GB.
+///
+/// Return `None` if `image` is smaller than the stub or `entry_point` is out of `image`.
+pub fn place_reset_vector_entry(image: &mut [u8], entry_point: u32) -> Option<()> {
+    let image_base = 0x1_0000_0000u64.checked_sub(image.len() as u64)?;
+    if image.len() < RESET_VECTOR_ENTRY_SIZE || (entry_point as u64) < image_base {
+        return None;
+    }
+
+    let entry = build_reset_vector_entry(entry_point)?;
+    let offset = image.len() - RESET_VECTOR_ENTRY_SIZE;
+    image[offset..].copy_from_slice(&entry);
+
+    Some(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,4 +185,52 @@ mod tests {
             .as_bytes()
         );
     }
+
+    #[test]
+    fn test_reset_vector_entry() {
+        let entry_point = TD_SHIM_FIRMWARE_BASE + 0x00ff_8000;
+        let entry = build_reset_vector_entry(entry_point).unwrap();
+
+        // Decode the stub by hand: nop; nop; jmp rel32, and the rest is nop.
+        assert_eq!(&entry[..3], &[0x90, 0x90, 0xe9]);
+        let rel = i32::from_le_bytes([entry[3], entry[4], entry[5], entry[6]]);
+        assert_eq!(
+            (RESET_VECTOR_ADDRESS as i64 + 7 + rel as i64) as u32,
+            entry_point
+        );
+        assert!(rel < 0);
+        assert!(entry[7..].iter().all(|v| *v == 0x90));
+        assert_eq!(reset_vector_entry_target(&entry), Some(entry_point));
+
+        assert_eq!(
+            reset_vector_entry_target(&build_reset_vector_entry(TD_SHIM_FIRMWARE_BASE).unwrap()),
+            Some(TD_SHIM_FIRMWARE_BASE)
+        );
+        assert!(build_reset_vector_entry(TD_SHIM_FIRMWARE_BASE - 1).is_none());
+        assert!(build_reset_vector_entry(RESET_VECTOR_ADDRESS).is_none());
+        assert!(build_reset_vector_entry(0xffff_ffff).is_none());
+
+        assert_eq!(reset_vector_entry_target(&entry[..6]), None);
+        let mut bad = entry;
+        bad[2] = 0xeb;
+        assert_eq!(reset_vector_entry_target(&bad), None);
+    }
+
+    #[test]
+    fn test_place_reset_vector_entry() {
+        let mut image = [0u8; 0x1000];
+        let entry_point = RESET_VECTOR_ADDRESS - 0x100;
+        place_reset_vector_entry(&mut image, entry_point).unwrap();
+        assert!(image[..0x1000 - RESET_VECTOR_ENTRY_SIZE]
+            .iter()
+            .all(|v| *v == 0));
+        assert_eq!(
+            reset_vector_entry_target(&image[0x1000 - RESET_VECTOR_ENTRY_SIZE..]),
+            Some(entry_point)
+        );
+
+        // The entry point is below the image.
+        assert!(place_reset_vector_entry(&mut image, RESET_VECTOR_ADDRESS - 0x1010).is_none());
+        assert!(place_reset_vector_entry(&mut image[..8], RESET_VECTOR_ADDRESS - 8).is_none());
+    }
 }
@@ -11,8 +11,10 @@
 extern crate alloc;
 
 use alloc::vec::Vec;
+use core::fmt;
 use core::mem::size_of;
 use core::ptr::slice_from_raw_parts;
+use core::str::FromStr;
 
 use der::{asn1::UIntBytes, Encodable, Message};
 use r_efi::efi::Guid;
@@ -20,7 +22,7 @@ use ring::{
     digest,
     signature::{self, UnparsedPublicKey, VerificationAlgorithm},
 };
-use scroll::{Pread, Pwrite};
+use scroll::{Endian, Pread, Pwrite};
 use td_uefi_pi::{fv, pi};
 
 /// GUID for secure boot trust anchor in the Configuration Firmware Volume (CFV).
@@ -43,8 +45,137 @@ pub const CFV_FILE_HEADER_PUBKEY_GUID: Guid = Guid::from_fields(
     &[0xf7, 0x8e, 0x10, 0x5e, 0x82, 0x4e],
 ); // {BE8F65A3-A83B-415C-A1FB-F78E105E824E}
 
+/// GUID for secure boot revocation list in the Configuration Firmware Volume (CFV).
+pub const CFV_FFS_HEADER_REVOCATION_LIST_GUID: Guid = Guid::from_fields(
+    0x3c7d1f52,
+    0x6e0b,
+    0x4a8e,
+    0x9d,
+    0x42,
+    &[0x5f, 0x1a, 0xc6, 0x8b, 0x2e, 0x97],
+); // {3C7D1F52-6E0B-4A8E-9D42-5F1AC68B2E97}
+
+/// GUID for secure boot revoked pubkey hash file in the Configuration Firmware Volume (CFV).
+pub const CFV_FILE_HEADER_REVOKED_PUBKEY_GUID: Guid = Guid::from_fields(
+    0xa41e9b07,
+    0xd35c,
+    0x4f62,
+    0xb8,
+    0x1d,
+    &[0x07, 0xe4, 0x5a, 0x93, 0xc2, 0x6f],
+); // {A41E9B07-D35C-4F62-B81D-07E45A93C26F}
+
+/// GUID for secure boot minimum payload SVN in the Configuration Firmware Volume (CFV).
+pub const CFV_FFS_HEADER_MIN_SVN_GUID: Guid = Guid::from_fields(
+    0x5b2e8c14,
+    0x7a93,
+    0x4d1f,
+    0xa6,
+    0x3c,
+    &[0x91, 0xd4, 0x0e, 0x7b, 0x52, 0xc8],
+); // {5B2E8C14-7A93-4D1F-A63C-91D40E7B52C8}
+
 pub const PUBKEY_FILE_STRUCT_VERSION_V1: u32 = 0x01;
 pub const PUBKEY_HASH_ALGORITHM_SHA384: u64 = 1;
+pub const PUBKEY_HASH_ALGORITHM_SHA256: u64 = 2;
+pub const PUBKEY_HASH_ALGORITHM_SHA512: u64 = 3;
+
+/// Hash algorithm of the public key hash enrolled into the CFV.
+///
+/// Both td-shim-enroll and `PayloadVerifier` map the `hash_algorithm` field of
+/// `CfvPubKeyFileHeader` through this type, so they always agree on the ids.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HashAlg {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlg {
+    /// Canonical names of all supported hash algorithms, as accepted by `from_str()`.
+    pub const ALL_NAMES: &'static [&'static str] = &["SHA256", "SHA384", "SHA512"];
+
+    /// Get the hash algorithm from the `hash_algorithm` field of `CfvPubKeyFileHeader`.
+    pub fn from_id(id: u64) -> Option<Self> {
+        match id {
+            PUBKEY_HASH_ALGORITHM_SHA256 => Some(HashAlg::Sha256),
+            PUBKEY_HASH_ALGORITHM_SHA384 => Some(HashAlg::Sha384),
+            PUBKEY_HASH_ALGORITHM_SHA512 => Some(HashAlg::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Id stored in the `hash_algorithm` field of `CfvPubKeyFileHeader`.
+    pub fn id(&self) -> u64 {
+        match self {
+            HashAlg::Sha256 => PUBKEY_HASH_ALGORITHM_SHA256,
+            HashAlg::Sha384 => PUBKEY_HASH_ALGORITHM_SHA384,
+            HashAlg::Sha512 => PUBKEY_HASH_ALGORITHM_SHA512,
+        }
+    }
+
+    /// Size of the digest in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            HashAlg::Sha256 => 32,
+            HashAlg::Sha384 => 48,
+            HashAlg::Sha512 => 64,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlg::Sha256 => "SHA256",
+            HashAlg::Sha384 => "SHA384",
+            HashAlg::Sha512 => "SHA512",
+        }
+    }
+
+    /// Compute the digest of `data`.
+    pub fn digest(&self, data: &[u8]) -> digest::Digest {
+        digest::digest(self.algorithm(), data)
+    }
+
+    /// Get the `ring` digest algorithm, to compute the digest incrementally.
+    pub fn algorithm(&self) -> &'static digest::Algorithm {
+        match self {
+            HashAlg::Sha256 => &digest::SHA256,
+            HashAlg::Sha384 => &digest::SHA384,
+            HashAlg::Sha512 => &digest::SHA512,
+        }
+    }
+}
+
+impl FromStr for HashAlg {
+    type Err = VerifyErr;
+
+    /// Parse a hash algorithm name, such as `SHA384` or `sha-384`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Accept the dashed spelling of the names too.
+        let mut buf = [0u8; 6];
+        let mut len = 0;
+        for b in s.bytes().filter(|b| *b != b'-') {
+            if len == buf.len() {
+                return Err(VerifyErr::UnknownAlgorithm);
+            }
+            buf[len] = b.to_ascii_uppercase();
+            len += 1;
+        }
+
+        match &buf[..len] {
+            b"SHA256" => Ok(HashAlg::Sha256),
+            b"SHA384" => Ok(HashAlg::Sha384),
+            b"SHA512" => Ok(HashAlg::Sha512),
+            _ => Err(VerifyErr::UnknownAlgorithm),
+        }
+    }
+}
+
+impl fmt::Display for HashAlg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 #[repr(C, align(4))]
 #[derive(Debug, Default, Pread, Pwrite)]
@@ -61,6 +192,14 @@ impl CfvPubKeyFileHeader {
     pub fn as_bytes(&self) -> &[u8] {
         unsafe { &*slice_from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
     }
+
+    /// Encode the header in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; size_of::<CfvPubKeyFileHeader>()] {
+        let mut bytes = [0u8; size_of::<CfvPubKeyFileHeader>()];
+        // Safe to unwrap() because the buffer is big enough.
+        bytes.pwrite_with(self, 0, Endian::Little).unwrap();
+        bytes
+    }
 }
 
 /// GUID for signed payload.
@@ -75,14 +214,181 @@ pub const SIGNED_PAYLOAD_FILE_HEADER_GUID: Guid = Guid::from_fields(
 
 pub const PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384: u32 = 1;
 pub const PAYLOAD_SIGN_RSA_PSS_3072_SHA384: u32 = 2;
+/// ECDSA_NIST_P384 with the signature in ASN.1 DER encoding instead of R||S.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1: u32 = 3;
 pub const PAYLOAD_SIGN_RSA_EXPONENT_SIZE: usize = 8;
 pub const PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE: usize = 384;
 
+/// Get the canonical name of a signing algorithm in `PayloadSignHeader`.
+pub fn signing_algorithm_name(signing_algorithm: u32) -> Option<&'static str> {
+    match signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => Some("ECDSA_NIST_P384_SHA384"),
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => Some("RSAPSS_3072_SHA384"),
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => Some("ECDSA_NIST_P384_SHA384_ASN1"),
+        _ => None,
+    }
+}
+
+/// The digest of the signed region is the one pinned by the signing algorithm.
+pub const PAYLOAD_SIGN_DIGEST_DEFAULT: u8 = 0;
+
+/// Get the digests supported by a signing algorithm, the first one is the default recorded as
+/// `PAYLOAD_SIGN_DIGEST_DEFAULT`. It's empty for unknown signing algorithms.
+///
+/// ECDSA_NIST_P384 pins SHA384, while RSA-PSS accepts any supported digest.
+pub fn supported_digests(signing_algorithm: u32) -> &'static [HashAlg] {
+    match signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 | PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            &[HashAlg::Sha384]
+        }
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => &[HashAlg::Sha384, HashAlg::Sha256, HashAlg::Sha512],
+        _ => &[],
+    }
+}
+
+fn default_digest(signing_algorithm: u32) -> Result<HashAlg, VerifyErr> {
+    supported_digests(signing_algorithm)
+        .first()
+        .copied()
+        .ok_or(VerifyErr::UnknownAlgorithm)
+}
+
+/// Version 1 of `PayloadSignHeader`, without SVN policy.
+pub const PAYLOAD_SIGN_HEADER_VERSION_V1: u32 = 1;
+/// Version 2 of `PayloadSignHeader`, which may point to an SVN policy.
+pub const PAYLOAD_SIGN_HEADER_VERSION_V2: u32 = 2;
+/// Newest version of `PayloadSignHeader`, the format version of the signed payload. Signed
+/// payloads of a newer version are rejected with `VerifyErr::UnsupportedVersion`, as their layout
+/// is unknown.
+pub const PAYLOAD_SIGN_HEADER_VERSION_LATEST: u32 = PAYLOAD_SIGN_HEADER_VERSION_V2;
+/// Size of the version 1 `PayloadSignHeader`.
+pub const PAYLOAD_SIGN_HEADER_V1_SIZE: usize = 48;
+
+/// Multi-byte fields of the `PayloadSignHeader` are encoded in big-endian.
+pub const PAYLOAD_SIGN_FLAG_BIG_ENDIAN: u8 = 0x1;
+/// Offset of the `flags` field inside `PayloadSignHeader`.
+///
+/// The flags field is a single byte, so it can be read before the byte order is known.
+pub const PAYLOAD_SIGN_HEADER_FLAGS_OFFSET: usize = 44;
+const PAYLOAD_SIGN_HEADER_VERSION_OFFSET: usize = 16;
+
+/// The signer doesn't tell the type of the payload.
+pub const PAYLOAD_TYPE_UNSPECIFIED: u8 = 0;
+/// Executable image in the PE or ELF format, booted by td-shim directly.
+pub const PAYLOAD_TYPE_EXECUTABLE: u8 = 1;
+/// Linux kernel image in the bzImage format.
+pub const PAYLOAD_TYPE_LINUX_KERNEL: u8 = 2;
+/// Payload of a migration TD.
+pub const PAYLOAD_TYPE_MIGRATION_TD: u8 = 3;
+/// Payload made of several components listed by a table of contents, see `multi_component`.
+pub const PAYLOAD_TYPE_MULTI_COMPONENT: u8 = 4;
+/// Payload types from `PAYLOAD_TYPE_CUSTOM_MIN` to 0xff are reserved for custom use.
+pub const PAYLOAD_TYPE_CUSTOM_MIN: u8 = 0x80;
+
+/// The signer doesn't request a load base, the loader chooses where the payload runs.
+pub const PAYLOAD_LOAD_BASE_ANY: u64 = 0;
+/// Alignment of the load base requested for relocatable payloads, a 4 KiB page.
+pub const PAYLOAD_LOAD_BASE_ALIGNMENT: u64 = 0x1000;
+
+/// The signer doesn't request an RTMR, the payload is measured into
+/// RTMR[`PAYLOAD_DEFAULT_RTMR_INDEX`].
+pub const PAYLOAD_MR_INDEX_DEFAULT: u8 = 0;
+/// Index of the RTMR the payload is measured into unless the signer requests another one.
+pub const PAYLOAD_DEFAULT_RTMR_INDEX: u32 = 1;
+/// The payload may be measured into RTMR[0] to RTMR[`PAYLOAD_MAX_RTMR_INDEX`].
+pub const PAYLOAD_MAX_RTMR_INDEX: u32 = 3;
+
+/// Type of the payload recorded in `PayloadSignHeader`, so the loader could dispatch according
+/// to it instead of sniffing the payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignedPayloadType {
+    Unspecified,
+    Executable,
+    LinuxKernel,
+    MigrationTd,
+    MultiComponent,
+    /// Type in the range reserved for custom use.
+    Custom(u8),
+    /// Type which is neither defined nor in the custom range.
+    Unknown(u8),
+}
+
+impl Default for SignedPayloadType {
+    fn default() -> Self {
+        SignedPayloadType::Unspecified
+    }
+}
+
+impl SignedPayloadType {
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            PAYLOAD_TYPE_UNSPECIFIED => SignedPayloadType::Unspecified,
+            PAYLOAD_TYPE_EXECUTABLE => SignedPayloadType::Executable,
+            PAYLOAD_TYPE_LINUX_KERNEL => SignedPayloadType::LinuxKernel,
+            PAYLOAD_TYPE_MIGRATION_TD => SignedPayloadType::MigrationTd,
+            PAYLOAD_TYPE_MULTI_COMPONENT => SignedPayloadType::MultiComponent,
+            id if id >= PAYLOAD_TYPE_CUSTOM_MIN => SignedPayloadType::Custom(id),
+            id => SignedPayloadType::Unknown(id),
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            SignedPayloadType::Unspecified => PAYLOAD_TYPE_UNSPECIFIED,
+            SignedPayloadType::Executable => PAYLOAD_TYPE_EXECUTABLE,
+            SignedPayloadType::LinuxKernel => PAYLOAD_TYPE_LINUX_KERNEL,
+            SignedPayloadType::MigrationTd => PAYLOAD_TYPE_MIGRATION_TD,
+            SignedPayloadType::MultiComponent => PAYLOAD_TYPE_MULTI_COMPONENT,
+            SignedPayloadType::Custom(id) | SignedPayloadType::Unknown(id) => *id,
+        }
+    }
+
+    /// Get the name of a defined payload type, `None` for custom and unknown types.
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            SignedPayloadType::Unspecified => Some("unspecified"),
+            SignedPayloadType::Executable => Some("executable"),
+            SignedPayloadType::LinuxKernel => Some("linux-kernel"),
+            SignedPayloadType::MigrationTd => Some("migration-td"),
+            SignedPayloadType::MultiComponent => Some("multi-component"),
+            _ => None,
+        }
+    }
+
+    /// Get the defined payload type named `name`, such as `linux-kernel`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            SignedPayloadType::Unspecified,
+            SignedPayloadType::Executable,
+            SignedPayloadType::LinuxKernel,
+            SignedPayloadType::MigrationTd,
+            SignedPayloadType::MultiComponent,
+        ]
+        .iter()
+        .find(|v| v.name() == Some(name))
+        .copied()
+    }
+}
+
+impl fmt::Display for SignedPayloadType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignedPayloadType::Custom(id) => write!(f, "custom({:#x})", id),
+            SignedPayloadType::Unknown(id) => write!(f, "unknown({:#x})", id),
+            // Defined types always have a name.
+            v => f.write_str(v.name().unwrap()),
+        }
+    }
+}
+
 /// File header for signed payload.
 ///
+/// Fields after `reserved` only exist in version 2 and later headers, `from_bytes()` sets them to
+/// zero when parsing a version 1 header.
+///
 /// Please refer to doc/secure_boot.md for definition.
 #[repr(C, align(4))]
-#[derive(Debug, Pread, Pwrite)]
+#[derive(Debug, Default, Pread, Pwrite)]
 pub struct PayloadSignHeader {
     pub type_guid: [u8; 16],
     pub struct_version: u32,
@@ -90,26 +396,343 @@ pub struct PayloadSignHeader {
     pub payload_version: u64,
     pub payload_svn: u64,
     pub signing_algorithm: u32,
-    pub reserved: u32,
+    pub flags: u8,
+    /// Type of the payload, see `SignedPayloadType`.
+    pub payload_type: u8,
+    /// Digest of the signed region, `PAYLOAD_SIGN_DIGEST_DEFAULT` or a `HashAlg` id.
+    pub digest_algorithm: u8,
+    pub reserved: [u8; 1],
+    /// Offset of the SVN policy from the start of the header, zero if there's no policy. Version 2
+    /// and later only, zero for a version 1 header.
+    pub policy_offset: u32,
+    /// Size of the SVN policy, zero if there's no policy. Version 2 and later only, zero for a
+    /// version 1 header.
+    pub policy_size: u32,
+    /// Load base requested for a relocatable payload, `PAYLOAD_LOAD_BASE_ANY` if the loader
+    /// chooses it. Version 2 and later only, `PAYLOAD_LOAD_BASE_ANY` for a version 1 header.
+    pub load_base: u64,
+    /// Measurement register the payload is measured into, numbered as in the event log: 1 stands
+    /// for RTMR[0]. `PAYLOAD_MR_INDEX_DEFAULT` measures it into RTMR[`PAYLOAD_DEFAULT_RTMR_INDEX`].
+    /// Version 2 and later only, `PAYLOAD_MR_INDEX_DEFAULT` for a version 1 header.
+    pub mr_index: u8,
+    /// Version 2 and later only, zero for a version 1 header.
+    pub reserved2: [u8; 15],
 }
 
 impl PayloadSignHeader {
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            &*core::ptr::slice_from_raw_parts(
-                self as *const Self as *const u8,
-                core::mem::size_of::<Self>(),
-            )
+    /// Get size of the header on the wire, which depends on the header version.
+    pub fn header_size(&self) -> usize {
+        if self.struct_version <= PAYLOAD_SIGN_HEADER_VERSION_V1 {
+            PAYLOAD_SIGN_HEADER_V1_SIZE
+        } else {
+            size_of::<Self>()
+        }
+    }
+
+    /// Get the byte order of multi-byte fields recorded in the header flags.
+    pub fn endian(&self) -> Endian {
+        endian_from_flags(self.flags)
+    }
+
+    /// Parse a `PayloadSignHeader` from `bytes`, honoring the byte order recorded in the flags.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let flags = *bytes.get(PAYLOAD_SIGN_HEADER_FLAGS_OFFSET)?;
+        let endian = endian_from_flags(flags);
+        let version = bytes
+            .pread_with::<u32>(PAYLOAD_SIGN_HEADER_VERSION_OFFSET, endian)
+            .ok()?;
+
+        if version <= PAYLOAD_SIGN_HEADER_VERSION_V1 {
+            let mut buf = [0u8; size_of::<Self>()];
+            buf[..PAYLOAD_SIGN_HEADER_V1_SIZE]
+                .copy_from_slice(bytes.get(..PAYLOAD_SIGN_HEADER_V1_SIZE)?);
+            buf.pread_with::<PayloadSignHeader>(0, endian).ok()
+        } else {
+            bytes.pread_with::<PayloadSignHeader>(0, endian).ok()
+        }
+    }
+
+    /// Parse the verify header of the whole signed payload `signed_payload`, checking that it
+    /// holds the size declared by the header, see `check_size()`. Detached verify headers aren't
+    /// followed by the payload image, so they're parsed by `from_bytes()` instead.
+    ///
+    /// Return `VerifyErr::Unsigned` if `signed_payload` has no verify header and isn't signed in
+    /// the compact format either.
+    pub fn from_signed_payload(signed_payload: &[u8]) -> Result<Self, VerifyErr> {
+        let header =
+            Self::from_bytes(signed_payload).ok_or_else(|| missing_signature(signed_payload))?;
+        if &header.type_guid != SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() {
+            return Err(missing_signature(signed_payload));
+        }
+        header.check_version()?;
+        if header.length as usize <= header.header_size() {
+            return Err(VerifyErr::InvalidContent);
+        }
+        header.check_size(signed_payload.len())?;
+
+        Ok(header)
+    }
+
+    /// Check that the format version of the header is known, so the fields are not misparsed.
+    pub fn check_version(&self) -> Result<(), VerifyErr> {
+        if self.struct_version > PAYLOAD_SIGN_HEADER_VERSION_LATEST {
+            return Err(VerifyErr::UnsupportedVersion(self.struct_version));
+        }
+
+        Ok(())
+    }
+
+    /// Get the size of the signed payload declared by the header: `length` bytes of header,
+    /// payload image and SVN policy followed by the primary signature block.
+    pub fn declared_size(&self) -> Result<usize, VerifyErr> {
+        (self.length as usize)
+            .checked_add(SignatureBlock::size(self.signing_algorithm)?)
+            .ok_or(VerifyErr::InvalidContent)
+    }
+
+    /// Check that a signed payload of `actual` bytes holds the size declared by the header.
+    ///
+    /// The signed payload may be longer than declared: additional signatures follow the primary
+    /// signature block, and the payload firmware volume pads the signed payload with zeroes up to
+    /// the end of the payload region. A truncated signed payload, or an inflated `length`, fails
+    /// with `VerifyErr::SizeMismatch`.
+    pub fn check_size(&self, actual: usize) -> Result<(), VerifyErr> {
+        let declared = self.declared_size()?;
+        if actual < declared {
+            return Err(VerifyErr::SizeMismatch { declared, actual });
+        }
+
+        Ok(())
+    }
+
+    /// Get the load base requested by the signer, `None` if the loader chooses it.
+    pub fn load_base(&self) -> Option<u64> {
+        match self.load_base {
+            PAYLOAD_LOAD_BASE_ANY => None,
+            base => Some(base),
+        }
+    }
+
+    /// Get the index of the RTMR requested by the signer, `None` if the payload is measured into
+    /// the default RTMR. The index is not checked, see `payload_rtmr_index()`.
+    pub fn rtmr_index(&self) -> Option<u32> {
+        match self.mr_index {
+            PAYLOAD_MR_INDEX_DEFAULT => None,
+            index => Some(index as u32 - 1),
+        }
+    }
+
+    /// Get the index of the RTMR the payload is measured into, failing if the signer requested an
+    /// RTMR beyond RTMR[`PAYLOAD_MAX_RTMR_INDEX`].
+    pub fn payload_rtmr_index(&self) -> Result<u32, VerifyErr> {
+        match self.rtmr_index() {
+            None => Ok(PAYLOAD_DEFAULT_RTMR_INDEX),
+            Some(index) if index <= PAYLOAD_MAX_RTMR_INDEX => Ok(index),
+            Some(_) => Err(VerifyErr::InvalidContent),
+        }
+    }
+
+    /// Get the type of the payload recorded by the signer.
+    pub fn payload_type(&self) -> SignedPayloadType {
+        SignedPayloadType::from_id(self.payload_type)
+    }
+
+    /// Get the digest of the signed region, which must be supported by the signing algorithm.
+    pub fn digest(&self) -> Result<HashAlg, VerifyErr> {
+        if self.digest_algorithm == PAYLOAD_SIGN_DIGEST_DEFAULT {
+            return default_digest(self.signing_algorithm);
         }
+        HashAlg::from_id(self.digest_algorithm as u64)
+            .filter(|v| supported_digests(self.signing_algorithm).contains(v))
+            .ok_or(VerifyErr::UnknownAlgorithm)
+    }
+
+    /// Check whether payloads signed with `self` and `other` could be treated interchangeably,
+    /// that is they use the same signing algorithm, digest, header version and byte order.
+    pub fn is_compatible(&self, other: &PayloadSignHeader) -> bool {
+        self.signing_algorithm == other.signing_algorithm
+            && self.digest_algorithm == other.digest_algorithm
+            && self.struct_version == other.struct_version
+            && self.endian() == other.endian()
+    }
+
+    /// Serialize the header, encoding multi-byte fields in the byte order recorded in the flags.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = alloc::vec![0u8; size_of::<Self>()];
+        // Writing a fixed size struct into a buffer of the same size never fails.
+        bytes.pwrite_with(self, 0, self.endian()).unwrap();
+        bytes.truncate(self.header_size());
+        bytes
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for PayloadSignHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "struct_version: {}, length: {:#x}, payload_version: {:#x}, payload_svn: {}, ",
+            self.struct_version, self.length, self.payload_version, self.payload_svn
+        )?;
+        match signing_algorithm_name(self.signing_algorithm) {
+            Some(name) => write!(f, "signing_algorithm: {}", name)?,
+            None => write!(
+                f,
+                "signing_algorithm: unknown({:#x})",
+                self.signing_algorithm
+            )?,
+        }
+        match self.endian() {
+            Endian::Big => write!(f, ", endian: big")?,
+            Endian::Little => write!(f, ", endian: little")?,
+        }
+        if self.payload_type != PAYLOAD_TYPE_UNSPECIFIED {
+            write!(f, ", payload_type: {}", self.payload_type())?;
+        }
+        if self.digest_algorithm != PAYLOAD_SIGN_DIGEST_DEFAULT {
+            match HashAlg::from_id(self.digest_algorithm as u64) {
+                Some(digest) => write!(f, ", digest: {}", digest.name())?,
+                None => write!(f, ", digest: unknown({:#x})", self.digest_algorithm)?,
+            }
+        }
+        if self.struct_version >= PAYLOAD_SIGN_HEADER_VERSION_V2 {
+            write!(
+                f,
+                ", policy_offset: {:#x}, policy_size: {:#x}",
+                self.policy_offset, self.policy_size
+            )?;
+        }
+        if let Some(base) = self.load_base() {
+            write!(f, ", load_base: {:#x}", base)?;
+        }
+        if let Some(index) = self.rtmr_index() {
+            write!(f, ", rtmr_index: {}", index)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn endian_from_flags(flags: u8) -> Endian {
+    if flags & PAYLOAD_SIGN_FLAG_BIG_ENDIAN != 0 {
+        Endian::Big
+    } else {
+        Endian::Little
+    }
+}
+
+/// 'SVNP' signature
+pub const SVN_POLICY_SIGNATURE: u32 = 0x504e5653;
+pub const SVN_POLICY_STRUCT_VERSION_V1: u32 = 1;
+/// Maximum number of allowed payload versions in an SVN policy.
+pub const SVN_POLICY_MAX_VERSIONS: usize = 64;
+
+/// Header of the SVN policy attached to a signed payload.
+///
+/// The header is followed by `version_count` UINT64 allowed payload versions. All fields are
+/// encoded in the byte order of the `PayloadSignHeader`.
+#[repr(C)]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct SvnPolicyHeader {
+    pub signature: u32,
+    pub struct_version: u32,
+    pub min_svn: u64,
+    pub version_count: u32,
+    pub reserved: u32,
+}
+
+/// SVN policy enforced by td-shim at boot time.
+///
+/// The policy is attached to the signed payload by its signer, so `min_svn` is a floor declared by
+/// the payload itself: a payload rolled back to an older release carries the older policy. The
+/// minimum SVN enrolled into the CFV, see `get_min_svn()`, is the one protecting from rollback.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SvnPolicy {
+    /// Minimal payload SVN allowed to boot.
+    pub min_svn: u64,
+    /// Payload versions allowed to boot, empty means any version is allowed.
+    pub allowed_versions: Vec<u64>,
+}
+
+impl SvnPolicy {
+    /// Get size of the encoded policy.
+    pub fn size(&self) -> usize {
+        size_of::<SvnPolicyHeader>() + self.allowed_versions.len() * size_of::<u64>()
+    }
+
+    pub fn from_bytes(bytes: &[u8], endian: Endian) -> Option<Self> {
+        let mut offset = 0;
+        let header: SvnPolicyHeader = bytes.gread_with(&mut offset, endian).ok()?;
+        if header.signature != SVN_POLICY_SIGNATURE
+            || header.struct_version != SVN_POLICY_STRUCT_VERSION_V1
+            || header.version_count as usize > SVN_POLICY_MAX_VERSIONS
+            || bytes.len() != size_of::<SvnPolicyHeader>() + header.version_count as usize * 8
+        {
+            return None;
+        }
+
+        let mut allowed_versions = Vec::new();
+        for _ in 0..header.version_count {
+            allowed_versions.push(bytes.gread_with::<u64>(&mut offset, endian).ok()?);
+        }
+
+        Some(SvnPolicy {
+            min_svn: header.min_svn,
+            allowed_versions,
+        })
+    }
+
+    pub fn to_bytes(&self, endian: Endian) -> Vec<u8> {
+        let header = SvnPolicyHeader {
+            signature: SVN_POLICY_SIGNATURE,
+            struct_version: SVN_POLICY_STRUCT_VERSION_V1,
+            min_svn: self.min_svn,
+            version_count: self.allowed_versions.len() as u32,
+            reserved: 0,
+        };
+
+        let mut bytes = alloc::vec![0u8; self.size()];
+        let mut offset = 0;
+        // The buffer is sized to hold the whole policy, so writing never fails.
+        bytes.gwrite_with(header, &mut offset, endian).unwrap();
+        for version in self.allowed_versions.iter() {
+            bytes.gwrite_with(*version, &mut offset, endian).unwrap();
+        }
+
+        bytes
+    }
+
+    /// Check whether the payload with `svn` and `version` is allowed by the policy.
+    pub fn is_allowed(&self, svn: u64, version: u64) -> bool {
+        svn >= self.min_svn
+            && (self.allowed_versions.is_empty() || self.allowed_versions.contains(&version))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum VerifyErr {
     UnknownAlgorithm,
     InvalidContent,
     InvalidPublicKey,
     InvalidSignature,
+    SvnPolicyViolation,
+    RevokedPublicKey,
+    /// The payload carries no signature in any known format, as built without signing.
+    Unsigned,
+    /// The signed payload is smaller than the size declared by its verify header.
+    SizeMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    /// The verify header is of a format version newer than `PAYLOAD_SIGN_HEADER_VERSION_LATEST`.
+    UnsupportedVersion(u32),
+}
+
+// Tell payloads built without signing from malformed signed payloads.
+fn missing_signature(signed_payload: &[u8]) -> VerifyErr {
+    match SignedPayloadFormat::detect(signed_payload) {
+        Some(_) => VerifyErr::InvalidContent,
+        None => VerifyErr::Unsigned,
+    }
 }
 
 // rfc3279#section-2.3.1 RSA Keys
@@ -125,50 +748,130 @@ struct RsaPublicKeyDer<'a> {
     pub exponents: UIntBytes<'a>,
 }
 
-pub struct PayloadVerifier<'a> {
-    header: PayloadSignHeader,
-    config: &'a [u8],
-    image: &'a [u8],
-    public_key: &'a [u8],
-    formated_public_key: Vec<u8>,
-    signature: &'a [u8],
-    verify_alg: &'static dyn VerificationAlgorithm,
+/// Get the range of the SVN policy in the signed payload, `None` if there's no policy.
+fn get_policy_range(
+    header: &PayloadSignHeader,
+) -> Result<Option<core::ops::Range<usize>>, VerifyErr> {
+    if header.struct_version < PAYLOAD_SIGN_HEADER_VERSION_V2 || header.policy_size == 0 {
+        if header.policy_offset != 0 || header.policy_size != 0 {
+            return Err(VerifyErr::InvalidContent);
+        }
+        return Ok(None);
+    }
+
+    // The policy is placed right after the payload and is covered by the signature.
+    let start = header.policy_offset as usize;
+    let end = start
+        .checked_add(header.policy_size as usize)
+        .ok_or(VerifyErr::InvalidContent)?;
+    if start < header.header_size() || end != header.length as usize {
+        return Err(VerifyErr::InvalidContent);
+    }
+
+    Ok(Some(start..end))
 }
 
-impl<'a> PayloadVerifier<'a> {
-    pub fn new(signed_payload: &'a [u8], config: &'a [u8]) -> Result<Self, VerifyErr> {
-        let header = signed_payload
-            .pread::<PayloadSignHeader>(0)
-            .map_err(|_e| VerifyErr::InvalidContent)?;
+/// Size of the public key in the ECDSA_NIST_P384 signature block, X||Y.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE: usize = 96;
+/// Size of the signature in the ECDSA_NIST_P384 signature block, R||S.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE: usize = 96;
+/// Size of the public key in the RSA_3072 signature block, Mod||Exponent.
+pub const PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE: usize =
+    PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE + PAYLOAD_SIGN_RSA_EXPONENT_SIZE;
+/// Size of the signature in the RSA_3072 signature block.
+pub const PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE: usize = PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE;
+/// Size of the ECDSA_NIST_P384 signature block.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE: usize =
+    PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE + PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE;
+/// Size of the RSA_3072 signature block.
+pub const PAYLOAD_SIGN_RSA_PSS_3072_BLOCK_SIZE: usize =
+    PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE + PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE;
+/// Maximum size of the ASN.1 DER encoded ECDSA_NIST_P384 signature, zero padded to this size in
+/// the signature block.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE: usize = 104;
+/// Size of the ECDSA_NIST_P384 signature block with ASN.1 DER encoded signature.
+pub const PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_BLOCK_SIZE: usize =
+    PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE + PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE;
 
-        if &header.type_guid != SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() {
-            return Err(VerifyErr::InvalidContent);
+/// Get size of the signature block for the signing algorithm.
+pub fn signature_block_size(signing_algorithm: u32) -> Option<usize> {
+    match signing_algorithm {
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => Some(PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE),
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => Some(PAYLOAD_SIGN_RSA_PSS_3072_BLOCK_SIZE),
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+            Some(PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_BLOCK_SIZE)
         }
+        _ => None,
+    }
+}
 
-        let mut offset = header.length as usize;
-        if offset <= size_of::<PayloadSignHeader>() || offset >= signed_payload.len() {
-            return Err(VerifyErr::InvalidContent);
-        }
+/// Verify the signature block `block`, the public key followed by the signature, of `message`
+/// hashed with `digest`, as td-shim verifies the signed region of a payload.
+pub fn verify_signature_block(
+    signing_algorithm: u32,
+    digest: HashAlg,
+    block: &[u8],
+    message: &[u8],
+) -> Result<(), VerifyErr> {
+    SignatureBlock::parse(block, signing_algorithm, digest)?.verify(message)
+}
 
-        // The image to be verified contains signing header and payload ELF/PE image
-        let image = &signed_payload[0..offset];
+/// Header of an additional signature appended after the primary signature block.
+///
+/// Please refer to section "Additional Signatures" in doc/secure_boot.md for definition.
+#[repr(C)]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct AdditionalSignatureHeader {
+    pub signing_algorithm: u32,
+    pub reserved: u32,
+}
+
+/// Verification status of one signature carried by a signed payload.
+#[derive(Debug)]
+pub struct SignatureStatus<'a> {
+    /// Index of the signature, the primary signature is slot 0.
+    pub slot: usize,
+    pub signing_algorithm: u32,
+    /// Public key stored in the signature block, empty if the block is malformed.
+    pub public_key: &'a [u8],
+    pub result: Result<(), VerifyErr>,
+}
+
+struct SignatureBlock<'a> {
+    public_key: &'a [u8],
+    formated_public_key: Vec<u8>,
+    signature: &'a [u8],
+    verify_alg: &'static dyn VerificationAlgorithm,
+}
 
+impl<'a> SignatureBlock<'a> {
+    fn size(signing_algorithm: u32) -> Result<usize, VerifyErr> {
+        signature_block_size(signing_algorithm).ok_or(VerifyErr::UnknownAlgorithm)
+    }
+
+    // Parse the signature block of a signed region hashed with `digest`, which must be supported
+    // by the signing algorithm.
+    fn parse(data: &'a [u8], signing_algorithm: u32, digest: HashAlg) -> Result<Self, VerifyErr> {
+        let mut offset = 0;
         let mut formated_public_key: Vec<u8> = Vec::new();
         let verify_alg: &'static dyn VerificationAlgorithm;
         let signature;
         let public_key;
-        match header.signing_algorithm {
-            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => {
-                if signed_payload.len() < offset + 192 {
-                    return Err(VerifyErr::InvalidContent);
-                }
 
+        if data.len() < Self::size(signing_algorithm)? {
+            return Err(VerifyErr::InvalidContent);
+        }
+        if !supported_digests(signing_algorithm).contains(&digest) {
+            return Err(VerifyErr::UnknownAlgorithm);
+        }
+        match signing_algorithm {
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384 => {
                 // Public key (X: first 48 bytes, Y: second 48 bytes)
-                public_key = &signed_payload[offset..offset + 96];
-                offset += 96;
+                public_key = &data[offset..offset + PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE];
+                offset += PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE;
 
                 // Signature: (R: first 48 bytes, S: second 48 byts)
-                signature = &signed_payload[offset..offset + 96];
+                signature = &data[offset..offset + PAYLOAD_SIGN_ECDSA_NIST_P384_SIGNATURE_SIZE];
 
                 // Uncompressed public key
                 formated_public_key.push(0x04);
@@ -176,24 +879,39 @@ impl<'a> PayloadVerifier<'a> {
 
                 verify_alg = &signature::ECDSA_P384_SHA384_FIXED;
             }
-            PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => {
-                if signed_payload.len() < offset + 776 {
+            PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1 => {
+                public_key = &data[offset..offset + PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE];
+                offset += PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE;
+
+                // Signature: DER SEQUENCE of R and S, followed by zero padding. The length of the
+                // SEQUENCE is always encoded in one byte for NIST_P384.
+                if data[offset] != 0x30
+                    || 2 + data[offset + 1] as usize
+                        > PAYLOAD_SIGN_ECDSA_NIST_P384_ASN1_SIGNATURE_SIZE
+                {
                     return Err(VerifyErr::InvalidContent);
                 }
+                signature = &data[offset..offset + 2 + data[offset + 1] as usize];
+
+                formated_public_key.push(0x04);
+                formated_public_key.extend_from_slice(public_key);
 
+                verify_alg = &signature::ECDSA_P384_SHA384_ASN1;
+            }
+            PAYLOAD_SIGN_RSA_PSS_3072_SHA384 => {
                 // Store the Mod(384 bytes)||Exponent(8 bytes) to the public_key to verify hash.
-                public_key = &signed_payload[offset..offset + 392];
+                public_key = &data[offset..offset + PAYLOAD_SIGN_RSA_PSS_3072_PUBLIC_KEY_SIZE];
 
                 // Public Mod (384 bytes)
-                let modulus = &signed_payload[offset..offset + 384];
-                offset += 384;
+                let modulus = &data[offset..offset + PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE];
+                offset += PAYLOAD_SIGN_RSA_PUBLIC_KEY_MOD_SIZE;
 
                 // Public Exponent (8 bytes)
-                let exp = &signed_payload[offset..offset + 8];
-                offset += 8;
+                let exp = &data[offset..offset + PAYLOAD_SIGN_RSA_EXPONENT_SIZE];
+                offset += PAYLOAD_SIGN_RSA_EXPONENT_SIZE;
 
                 // Signature (384 bytes)
-                signature = &signed_payload[offset..offset + 384];
+                signature = &data[offset..offset + PAYLOAD_SIGN_RSA_PSS_3072_SIGNATURE_SIZE];
 
                 let der = RsaPublicKeyDer {
                     modulus: UIntBytes::new(modulus).map_err(|_e| VerifyErr::InvalidContent)?,
@@ -202,15 +920,16 @@ impl<'a> PayloadVerifier<'a> {
                 der.encode_to_vec(&mut formated_public_key)
                     .map_err(|_e| VerifyErr::InvalidContent)?;
 
-                verify_alg = &signature::RSA_PSS_2048_8192_SHA384;
+                verify_alg = match digest {
+                    HashAlg::Sha256 => &signature::RSA_PSS_2048_8192_SHA256,
+                    HashAlg::Sha384 => &signature::RSA_PSS_2048_8192_SHA384,
+                    HashAlg::Sha512 => &signature::RSA_PSS_2048_8192_SHA512,
+                };
             }
             _ => return Err(VerifyErr::UnknownAlgorithm),
         }
 
-        Ok(PayloadVerifier {
-            header,
-            image,
-            config,
+        Ok(SignatureBlock {
             public_key,
             formated_public_key,
             signature,
@@ -218,38 +937,235 @@ impl<'a> PayloadVerifier<'a> {
         })
     }
 
-    pub fn get_payload_svn(&self) -> u64 {
-        self.header.payload_svn
+    fn verify(&self, image: &[u8]) -> Result<(), VerifyErr> {
+        let signature_verifier =
+            UnparsedPublicKey::new(self.verify_alg, self.formated_public_key.as_slice());
+        signature_verifier
+            .verify(image, self.signature)
+            .map_err(|_e| VerifyErr::InvalidSignature)
     }
+}
 
-    pub fn get_trust_anchor(cfv: &'a [u8]) -> Result<&'a [u8], VerifyErr> {
-        fv::get_file_from_fv(
-            cfv,
-            pi::fv::FV_FILETYPE_RAW,
-            CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
-        )
-        .ok_or(VerifyErr::InvalidContent)
+/// Get the minimum payload SVN enrolled into the CFV `config`, stored as a UINT64 in little-endian.
+///
+/// Return `None` if there's no minimum SVN in the CFV.
+pub fn get_min_svn(config: &[u8]) -> Result<Option<u64>, VerifyErr> {
+    match fv::get_file_from_fv(config, pi::fv::FV_FILETYPE_RAW, CFV_FFS_HEADER_MIN_SVN_GUID) {
+        Some(file) if file.len() == size_of::<u64>() => file
+            .pread_with::<u64>(0, Endian::Little)
+            .map(Some)
+            .map_err(|_| VerifyErr::InvalidContent),
+        Some(_) => Err(VerifyErr::InvalidContent),
+        None => Ok(None),
     }
+}
 
-    pub fn get_payload_image(signed_payload: &'a [u8]) -> Result<&'a [u8], VerifyErr> {
-        let header = signed_payload
-            .pread::<PayloadSignHeader>(0)
-            .map_err(|_e| VerifyErr::InvalidContent)?;
-        let offset = header.length as usize;
-
-        if offset <= size_of::<PayloadSignHeader>() || offset > signed_payload.len() {
-            Err(VerifyErr::InvalidContent)
-        } else {
-            Ok(&signed_payload[size_of::<PayloadSignHeader>()..offset])
+/// Check the payload SVN `svn` against the minimum SVN enrolled into the CFV `config`.
+///
+/// The check passes if there's no minimum SVN in the CFV. A malformed minimum SVN rejects every
+/// payload.
+pub fn check_min_svn(config: &[u8], svn: u64) -> Result<(), VerifyErr> {
+    match get_min_svn(config)? {
+        Some(min_svn) if svn < min_svn => Err(VerifyErr::SvnPolicyViolation),
+        _ => Ok(()),
+    }
+}
+
+// Compare the SHA384 of the public key read from signed payload with the trusted one.
+fn verify_public_key_hash(
+    public_key: &[u8],
+    trusted_hash: &[u8],
+    hash_alg: HashAlg,
+) -> Result<(), VerifyErr> {
+    let real_hash = hash_alg.digest(public_key);
+    if real_hash.as_ref() != trusted_hash {
+        return Err(VerifyErr::InvalidPublicKey);
+    }
+
+    Ok(())
+}
+
+/// Check the public key read from signed payload against the revocation list enrolled into the
+/// CFV `config`.
+///
+/// The check passes if there's no revocation list in the CFV. A malformed revocation list rejects
+/// every public key.
+pub fn check_revocation_list(config: &[u8], public_key: &[u8]) -> Result<(), VerifyErr> {
+    let file = match fv::get_file_from_fv(
+        config,
+        pi::fv::FV_FILETYPE_RAW,
+        CFV_FFS_HEADER_REVOCATION_LIST_GUID,
+    ) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let mut readlen = 0;
+    let header = file
+        .gread_with::<CfvPubKeyFileHeader>(&mut readlen, Endian::Little)
+        .map_err(|_| VerifyErr::InvalidContent)?;
+    let hash_alg = HashAlg::from_id(header.hash_algorithm).ok_or(VerifyErr::InvalidContent)?;
+    if &header.type_guid != CFV_FILE_HEADER_REVOKED_PUBKEY_GUID.as_bytes()
+        || header.length as usize > file.len()
+        || readlen > header.length as usize
+        || (header.length as usize - readlen) % hash_alg.output_len() != 0
+    {
+        return Err(VerifyErr::InvalidContent);
+    }
+
+    let hash = hash_alg.digest(public_key);
+    if file[readlen..header.length as usize]
+        .chunks(hash_alg.output_len())
+        .any(|v| v == hash.as_ref())
+    {
+        return Err(VerifyErr::RevokedPublicKey);
+    }
+
+    Ok(())
+}
+
+/// Check the public key read from signed payload against the trust anchor enrolled into the CFV
+/// `config`.
+///
+/// The public key is rejected if it's in the revocation list of the CFV, even if it matches the
+/// trust anchor.
+pub fn verify_trust_anchor(config: &[u8], public_key: &[u8]) -> Result<(), VerifyErr> {
+    check_revocation_list(config, public_key)?;
+
+    let file = fv::get_file_from_fv(
+        config,
+        pi::fv::FV_FILETYPE_RAW,
+        CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
+    )
+    .ok_or(VerifyErr::InvalidPublicKey)?;
+
+    let mut readlen = 0;
+    let header = file
+        .gread_with::<CfvPubKeyFileHeader>(&mut readlen, Endian::Little)
+        .map_err(|_| VerifyErr::InvalidPublicKey)?;
+    if &header.type_guid != CFV_FILE_HEADER_PUBKEY_GUID.as_bytes()
+        || header.length as usize > file.len()
+        || readlen > header.length as usize
+    {
+        return Err(VerifyErr::InvalidPublicKey);
+    }
+
+    let hash_alg = HashAlg::from_id(header.hash_algorithm).ok_or(VerifyErr::InvalidPublicKey)?;
+    let trusted_hash = &file[readlen..header.length as usize];
+    verify_public_key_hash(public_key, trusted_hash, hash_alg)
+}
+
+pub struct PayloadVerifier<'a> {
+    header: PayloadSignHeader,
+    policy: Option<SvnPolicy>,
+    config: &'a [u8],
+    signed_payload: &'a [u8],
+    image: &'a [u8],
+    block: SignatureBlock<'a>,
+}
+
+impl<'a> PayloadVerifier<'a> {
+    /// Return `VerifyErr::Unsigned` if `signed_payload` has no verify header and isn't signed in
+    /// the compact format either.
+    pub fn new(signed_payload: &'a [u8], config: &'a [u8]) -> Result<Self, VerifyErr> {
+        let header = PayloadSignHeader::from_signed_payload(signed_payload)?;
+        let offset = header.length as usize;
+        header.payload_rtmr_index()?;
+
+        let policy = match get_policy_range(&header)? {
+            Some(range) => Some(
+                SvnPolicy::from_bytes(&signed_payload[range], header.endian())
+                    .ok_or(VerifyErr::InvalidContent)?,
+            ),
+            None => None,
+        };
+
+        // The image to be verified contains signing header and payload ELF/PE image
+        let image = &signed_payload[0..offset];
+        let block = SignatureBlock::parse(
+            &signed_payload[offset..],
+            header.signing_algorithm,
+            header.digest()?,
+        )?;
+
+        Ok(PayloadVerifier {
+            header,
+            policy,
+            config,
+            signed_payload,
+            image,
+            block,
+        })
+    }
+
+    pub fn get_payload_svn(&self) -> u64 {
+        self.header.payload_svn
+    }
+
+    /// Get the payload type covered by the signature.
+    pub fn get_payload_type(&self) -> SignedPayloadType {
+        self.header.payload_type()
+    }
+
+    /// Get the index of the RTMR the payload is measured into, covered by the signature.
+    pub fn get_payload_rtmr_index(&self) -> u32 {
+        // Safe to unwrap() because the index has been checked by `new()`.
+        self.header.payload_rtmr_index().unwrap()
+    }
+
+    /// Get the load base requested by the signer, covered by the signature, `None` if the loader
+    /// chooses it.
+    pub fn get_payload_load_base(&self) -> Option<u64> {
+        self.header.load_base()
+    }
+
+    /// Get the SVN policy attached to the signed payload.
+    pub fn get_svn_policy(&self) -> Option<&SvnPolicy> {
+        self.policy.as_ref()
+    }
+
+    /// Check the payload SVN against the minimum SVN enrolled into the CFV, then the payload SVN
+    /// and version against the attached SVN policy.
+    ///
+    /// Each check passes if there's no minimum SVN in the CFV, or no SVN policy attached to the
+    /// signed payload.
+    pub fn check_svn_policy(&self) -> Result<(), VerifyErr> {
+        check_min_svn(self.config, self.header.payload_svn)?;
+        match &self.policy {
+            Some(policy)
+                if !policy.is_allowed(self.header.payload_svn, self.header.payload_version) =>
+            {
+                Err(VerifyErr::SvnPolicyViolation)
+            }
+            _ => Ok(()),
         }
     }
 
-    fn verify_signature(&self) -> Result<(), VerifyErr> {
-        let signature_verifier =
-            UnparsedPublicKey::new(self.verify_alg, self.formated_public_key.as_slice());
-        signature_verifier
-            .verify(self.image, self.signature)
-            .map_err(|_e| VerifyErr::InvalidSignature)
+    pub fn get_trust_anchor(cfv: &'a [u8]) -> Result<&'a [u8], VerifyErr> {
+        fv::get_file_from_fv(
+            cfv,
+            pi::fv::FV_FILETYPE_RAW,
+            CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
+        )
+        .ok_or(VerifyErr::InvalidContent)
+    }
+
+    pub fn get_payload_image(signed_payload: &'a [u8]) -> Result<&'a [u8], VerifyErr> {
+        let header =
+            PayloadSignHeader::from_bytes(signed_payload).ok_or(VerifyErr::InvalidContent)?;
+        let mut offset = header.length as usize;
+
+        if offset <= header.header_size() || offset > signed_payload.len() {
+            return Err(VerifyErr::InvalidContent);
+        }
+        if let Some(range) = get_policy_range(&header)? {
+            offset = range.start;
+        }
+        if offset <= header.header_size() {
+            Err(VerifyErr::InvalidContent)
+        } else {
+            Ok(&signed_payload[header.header_size()..offset])
+        }
     }
 
     // Calculate the hash of public key read from signed payload, and
@@ -259,37 +1175,278 @@ impl<'a> PayloadVerifier<'a> {
     //      CFV header | FFS header | data file (header | data)
     // The public key hash is stored in the data field.
     //
-    fn verify_public_key(&self) -> Result<(), VerifyErr> {
-        let file = fv::get_file_from_fv(
-            self.config,
-            pi::fv::FV_FILETYPE_RAW,
-            CFV_FFS_HEADER_TRUST_ANCHOR_GUID,
-        )
-        .ok_or(VerifyErr::InvalidPublicKey)?;
+    fn verify_public_key(&self, public_key: &[u8]) -> Result<(), VerifyErr> {
+        verify_trust_anchor(self.config, public_key)
+    }
 
-        let mut readlen = 0;
-        let header = file.gread::<CfvPubKeyFileHeader>(&mut readlen).unwrap();
-        if &header.type_guid != CFV_FILE_HEADER_PUBKEY_GUID.as_bytes()
-            || header.length as usize > file.len()
-            || readlen > header.length as usize
-        {
-            return Err(VerifyErr::InvalidPublicKey);
+    /// Verify the primary signature of the payload, which is the one used by td-shim to boot.
+    pub fn verify(&self) -> Result<(), VerifyErr> {
+        self.verify_public_key(self.block.public_key)?;
+        self.block.verify(self.image)?;
+
+        Ok(())
+    }
+
+    /// Verify the primary signature, with the public key checked against the SHA384 `key_hash`
+    /// instead of the trust anchor enrolled into the CFV.
+    pub fn verify_with_key_hash(&self, key_hash: &[u8]) -> Result<(), VerifyErr> {
+        verify_public_key_hash(self.block.public_key, key_hash, HashAlg::Sha384)?;
+        self.block.verify(self.image)
+    }
+
+    /// Verify the primary signature against the public key embedded in the signature block only.
+    ///
+    /// The public key is not checked against the trust anchor enrolled into the CFV.
+    pub fn verify_signature(&self) -> Result<(), VerifyErr> {
+        self.block.verify(self.image)
+    }
+
+    /// Verify all signatures carried by the signed payload independently.
+    ///
+    /// The primary signature is reported as slot 0, followed by the additional signatures in
+    /// the order stored. Enumeration stops at the first malformed additional signature, which is
+    /// reported with an empty public key.
+    pub fn verify_all(&self) -> Vec<SignatureStatus<'a>> {
+        self.verify_all_with(|public_key| self.verify_public_key(public_key))
+    }
+
+    /// Verify all signatures as `verify_all()`, with the public keys checked by
+    /// `verify_public_key` instead of the trust anchor enrolled into the CFV.
+    pub fn verify_all_with<F>(&self, verify_public_key: F) -> Vec<SignatureStatus<'a>>
+    where
+        F: Fn(&[u8]) -> Result<(), VerifyErr>,
+    {
+        let mut status = Vec::new();
+        status.push(SignatureStatus {
+            slot: 0,
+            signing_algorithm: self.header.signing_algorithm,
+            public_key: self.block.public_key,
+            result: verify_public_key(self.block.public_key)
+                .and_then(|_| self.block.verify(self.image)),
+        });
+
+        // Safe to unwrap() because the primary block has been parsed.
+        let mut offset =
+            self.image.len() + SignatureBlock::size(self.header.signing_algorithm).unwrap();
+        while offset < self.signed_payload.len() {
+            let slot = status.len();
+            let data = &self.signed_payload[offset..];
+            // Zero bytes after the last signature pad the signed payload, such as up to the size
+            // of the payload region.
+            if data.iter().all(|v| *v == 0) {
+                break;
+            }
+            let mut signing_algorithm = 0;
+            let block = data
+                .pread_with::<AdditionalSignatureHeader>(0, self.header.endian())
+                .map_err(|_e| VerifyErr::InvalidContent)
+                .and_then(|hdr| {
+                    signing_algorithm = hdr.signing_algorithm;
+                    let start = size_of::<AdditionalSignatureHeader>();
+                    // Additional signatures always use the default digest of their algorithm.
+                    let block = SignatureBlock::parse(
+                        &data[start..],
+                        hdr.signing_algorithm,
+                        default_digest(hdr.signing_algorithm)?,
+                    )?;
+                    Ok((block, start + SignatureBlock::size(hdr.signing_algorithm)?))
+                });
+
+            match block {
+                Ok((block, size)) => {
+                    let result =
+                        verify_public_key(block.public_key).and_then(|_| block.verify(self.image));
+                    status.push(SignatureStatus {
+                        slot,
+                        signing_algorithm,
+                        public_key: block.public_key,
+                        result,
+                    });
+                    offset += size;
+                }
+                Err(e) => {
+                    status.push(SignatureStatus {
+                        slot,
+                        signing_algorithm,
+                        public_key: &[],
+                        result: Err(e),
+                    });
+                    break;
+                }
+            }
+        }
+
+        status
+    }
+}
+
+/// Parse the primary signature of `signed_payload`, and return the public key embedded in the
+/// signature block paired with its verification algorithm, the signed region and the signature.
+///
+/// `key.verify(signed_region, signature)` verifies the primary signature. The public key is not
+/// checked against any trust anchor.
+pub fn embedded_verifier(
+    signed_payload: &[u8],
+) -> Result<(UnparsedPublicKey<Vec<u8>>, &[u8], &[u8]), VerifyErr> {
+    let verifier = PayloadVerifier::new(signed_payload, &[])?;
+    let block = verifier.block;
+    let key = UnparsedPublicKey::new(block.verify_alg, block.formated_public_key);
+
+    Ok((key, verifier.image, block.signature))
+}
+
+/// Magic at the end of the compact signature footer.
+pub const COMPACT_SIGNATURE_MAGIC: [u8; 8] = *b"TDSHCSIG";
+/// Size of `CompactSignatureFooter`.
+pub const COMPACT_SIGNATURE_FOOTER_SIZE: usize = 12;
+
+/// Footer of a payload signed in the compact format.
+///
+/// A compact signed payload is laid out as `payload | footer | public key | signature`, the
+/// payload and the footer are signed. There's no version, SVN or SVN policy.
+///
+/// Please refer to section "Compact Signed Payload" in doc/secure_boot.md for definition.
+#[repr(C)]
+#[derive(Debug, Default, Pread, Pwrite)]
+pub struct CompactSignatureFooter {
+    pub signing_algorithm: u32,
+    pub magic: [u8; 8],
+}
+
+impl CompactSignatureFooter {
+    pub fn new(signing_algorithm: u32) -> Self {
+        CompactSignatureFooter {
+            signing_algorithm,
+            magic: COMPACT_SIGNATURE_MAGIC,
         }
+    }
+
+    /// Encode the footer in little-endian, whatever the byte order of the host is.
+    pub fn to_bytes(&self) -> [u8; COMPACT_SIGNATURE_FOOTER_SIZE] {
+        let mut bytes = [0u8; COMPACT_SIGNATURE_FOOTER_SIZE];
+        // Safe to unwrap() because the buffer is big enough.
+        bytes.pwrite_with(self, 0, Endian::Little).unwrap();
+        bytes
+    }
+}
 
-        let trusted_hash = &file[readlen..header.length as usize];
-        let real_hash = digest::digest(&digest::SHA384, self.public_key);
-        if real_hash.as_ref() != trusted_hash {
-            return Err(VerifyErr::InvalidPublicKey);
+/// Find the compact signature footer of `signed_payload`, return the footer and its offset.
+fn find_compact_footer(signed_payload: &[u8]) -> Option<(CompactSignatureFooter, usize)> {
+    [
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+        PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+        PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384_ASN1,
+    ]
+    .iter()
+    .find_map(|alg| {
+        let offset = signed_payload
+            .len()
+            .checked_sub(signature_block_size(*alg)? + COMPACT_SIGNATURE_FOOTER_SIZE)?;
+        let footer: CompactSignatureFooter =
+            signed_payload.pread_with(offset, Endian::Little).ok()?;
+        if footer.magic == COMPACT_SIGNATURE_MAGIC && footer.signing_algorithm == *alg {
+            Some((footer, offset))
+        } else {
+            None
         }
+    })
+}
 
-        Ok(())
+/// Format of a signed payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignedPayloadFormat {
+    /// Led by `PayloadSignHeader`.
+    Standard,
+    /// Ended by `CompactSignatureFooter` and the signature block.
+    Compact,
+}
+
+impl SignedPayloadFormat {
+    /// Detect the format of `signed_payload`, `None` if it's not signed in any known format.
+    pub fn detect(signed_payload: &[u8]) -> Option<Self> {
+        match PayloadSignHeader::from_bytes(signed_payload) {
+            Some(header) if &header.type_guid == SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes() => {
+                Some(SignedPayloadFormat::Standard)
+            }
+            _ => find_compact_footer(signed_payload).map(|_| SignedPayloadFormat::Compact),
+        }
     }
+}
 
+/// Verifier of payloads signed in the compact format.
+pub struct CompactPayloadVerifier<'a> {
+    footer: CompactSignatureFooter,
+    config: &'a [u8],
+    payload: &'a [u8],
+    image: &'a [u8],
+    block: SignatureBlock<'a>,
+}
+
+impl<'a> CompactPayloadVerifier<'a> {
+    pub fn new(signed_payload: &'a [u8], config: &'a [u8]) -> Result<Self, VerifyErr> {
+        let (footer, offset) =
+            find_compact_footer(signed_payload).ok_or_else(|| missing_signature(signed_payload))?;
+        if offset == 0 {
+            return Err(VerifyErr::InvalidContent);
+        }
+
+        // The image to be verified contains the payload and the footer.
+        let image = &signed_payload[..offset + COMPACT_SIGNATURE_FOOTER_SIZE];
+        // The compact format has no room to record the digest, the default one is used.
+        let block = SignatureBlock::parse(
+            &signed_payload[image.len()..],
+            footer.signing_algorithm,
+            default_digest(footer.signing_algorithm)?,
+        )?;
+
+        Ok(CompactPayloadVerifier {
+            footer,
+            config,
+            payload: &signed_payload[..offset],
+            image,
+            block,
+        })
+    }
+
+    pub fn get_payload_image(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn signing_algorithm(&self) -> u32 {
+        self.footer.signing_algorithm
+    }
+
+    /// Verify the signature, with the public key checked against the trust anchor enrolled into
+    /// the CFV.
     pub fn verify(&self) -> Result<(), VerifyErr> {
-        self.verify_public_key()?;
-        self.verify_signature()?;
+        verify_trust_anchor(self.config, self.block.public_key)?;
+        self.block.verify(self.image)
+    }
 
-        Ok(())
+    /// Verify the signature against the public key embedded in the signature block only.
+    pub fn verify_signature(&self) -> Result<(), VerifyErr> {
+        self.block.verify(self.image)
+    }
+
+    /// Verify the signature, reported as slot 0 as a compact signed payload carries no
+    /// additional signatures.
+    pub fn verify_all(&self) -> Vec<SignatureStatus<'a>> {
+        self.verify_all_with(|public_key| verify_trust_anchor(self.config, public_key))
+    }
+
+    /// Verify the signature as `verify_all()`, with the public key checked by
+    /// `verify_public_key` instead of the trust anchor enrolled into the CFV.
+    pub fn verify_all_with<F>(&self, verify_public_key: F) -> Vec<SignatureStatus<'a>>
+    where
+        F: Fn(&[u8]) -> Result<(), VerifyErr>,
+    {
+        alloc::vec![SignatureStatus {
+            slot: 0,
+            signing_algorithm: self.footer.signing_algorithm,
+            public_key: self.block.public_key,
+            result: verify_public_key(self.block.public_key)
+                .and_then(|_| self.block.verify(self.image)),
+        }]
     }
 }
 
@@ -308,26 +1465,164 @@ mod test {
             payload_version: 1,
             payload_svn: 1,
             signing_algorithm: 0,
-            reserved: 0,
+            flags: 0,
+            payload_type: 0,
+            digest_algorithm: PAYLOAD_SIGN_DIGEST_DEFAULT,
+            reserved: [0u8; 1],
+            ..Default::default()
         };
-        assert!(PayloadVerifier::new(hdr.as_bytes(), &[]).is_err());
-        hdr.length = size_of::<PayloadSignHeader>() as u32;
-        assert!(PayloadVerifier::new(hdr.as_bytes(), &[]).is_err());
+        assert!(PayloadVerifier::new(&hdr.to_bytes(), &[]).is_err());
+        hdr.length = PAYLOAD_SIGN_HEADER_V1_SIZE as u32;
+        assert!(PayloadVerifier::new(&hdr.to_bytes(), &[]).is_err());
 
-        hdr.length = size_of::<PayloadSignHeader>() as u32 + 1;
+        hdr.length = PAYLOAD_SIGN_HEADER_V1_SIZE as u32 + 1;
         let mut buf = [0u8; 2048];
-        buf[0..size_of::<PayloadSignHeader>()].copy_from_slice(hdr.as_bytes());
-        assert!(PayloadVerifier::new(&buf[0..size_of::<PayloadSignHeader>() + 1], &[]).is_err());
+        buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE].copy_from_slice(&hdr.to_bytes());
+        assert!(PayloadVerifier::new(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 1], &[]).is_err());
 
         hdr.signing_algorithm = PAYLOAD_SIGN_RSA_PSS_3072_SHA384;
-        buf[0..size_of::<PayloadSignHeader>()].copy_from_slice(hdr.as_bytes());
-        assert!(PayloadVerifier::new(&buf[0..size_of::<PayloadSignHeader>() + 1], &[]).is_err());
-        assert!(PayloadVerifier::new(&buf[0..size_of::<PayloadSignHeader>() + 777], &[]).is_ok());
+        buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE].copy_from_slice(&hdr.to_bytes());
+        assert!(PayloadVerifier::new(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 1], &[]).is_err());
+        assert!(PayloadVerifier::new(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 777], &[]).is_ok());
 
         hdr.signing_algorithm = PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384;
-        buf[0..size_of::<PayloadSignHeader>()].copy_from_slice(hdr.as_bytes());
-        assert!(PayloadVerifier::new(&buf[0..size_of::<PayloadSignHeader>() + 1], &[]).is_err());
-        assert!(PayloadVerifier::new(&buf[0..size_of::<PayloadSignHeader>() + 193], &[]).is_ok());
+        buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE].copy_from_slice(&hdr.to_bytes());
+        assert!(PayloadVerifier::new(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 1], &[]).is_err());
+        assert!(PayloadVerifier::new(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 193], &[]).is_ok());
+    }
+
+    #[test]
+    fn test_signed_payload_size() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let header = PayloadSignHeader::from_signed_payload(payload).unwrap();
+        assert_eq!(
+            header.declared_size(),
+            Ok(header.length as usize + PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE)
+        );
+        assert_eq!(header.declared_size(), Ok(payload.len()));
+
+        // Additional signatures and zero padding may follow the primary signature block.
+        let mut padded = payload.to_vec();
+        padded.resize(payload.len() + 0x1000, 0);
+        assert!(PayloadVerifier::new(&padded, cfv).unwrap().verify().is_ok());
+
+        // Truncated signed payload.
+        let truncated = &payload[..payload.len() - 1];
+        assert_eq!(
+            PayloadSignHeader::from_signed_payload(truncated).err(),
+            Some(VerifyErr::SizeMismatch {
+                declared: payload.len(),
+                actual: payload.len() - 1
+            })
+        );
+        assert_eq!(
+            PayloadVerifier::new(truncated, cfv).err(),
+            Some(VerifyErr::SizeMismatch {
+                declared: payload.len(),
+                actual: payload.len() - 1
+            })
+        );
+        // The header still parses without the payload image, as detached headers are.
+        assert!(PayloadSignHeader::from_bytes(truncated).is_some());
+
+        // Inflated declared size.
+        // `length` follows `struct_version`.
+        let offset = PAYLOAD_SIGN_HEADER_VERSION_OFFSET + 4;
+        let mut inflated = payload.to_vec();
+        inflated[offset..offset + 4].copy_from_slice(&(header.length + 0x100).to_le_bytes());
+        assert_eq!(
+            PayloadVerifier::new(&inflated, cfv).err(),
+            Some(VerifyErr::SizeMismatch {
+                declared: payload.len() + 0x100,
+                actual: payload.len()
+            })
+        );
+
+        let mut header = header;
+        header.length = u32::MAX;
+        header.signing_algorithm = 0;
+        assert_eq!(
+            header.check_size(payload.len()),
+            Err(VerifyErr::UnknownAlgorithm)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let header = PayloadSignHeader::from_signed_payload(payload).unwrap();
+        assert!(header.struct_version <= PAYLOAD_SIGN_HEADER_VERSION_LATEST);
+        assert!(header.check_version().is_ok());
+
+        // Newer format versions are rejected instead of being parsed as the latest one.
+        let version = PAYLOAD_SIGN_HEADER_VERSION_LATEST + 1;
+        let offset = PAYLOAD_SIGN_HEADER_VERSION_OFFSET;
+        let mut newer = payload.to_vec();
+        newer[offset..offset + 4].copy_from_slice(&version.to_le_bytes());
+        assert_eq!(
+            PayloadSignHeader::from_signed_payload(&newer).err(),
+            Some(VerifyErr::UnsupportedVersion(version))
+        );
+        assert_eq!(
+            PayloadVerifier::new(&newer, cfv).err(),
+            Some(VerifyErr::UnsupportedVersion(version))
+        );
+        // The header still parses, so the version can be reported.
+        assert_eq!(
+            PayloadSignHeader::from_bytes(&newer)
+                .unwrap()
+                .struct_version,
+            version
+        );
+        assert_eq!(
+            SignedPayloadFormat::detect(&newer),
+            Some(SignedPayloadFormat::Standard)
+        );
+    }
+
+    #[test]
+    fn test_unsigned_payload() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let image = include_bytes!("../../data/blobs/td-payload.elf");
+
+        // Payloads built without signing, or with a zeroed verify header.
+        assert_eq!(
+            PayloadVerifier::new(image, cfv).err(),
+            Some(VerifyErr::Unsigned)
+        );
+        assert_eq!(
+            CompactPayloadVerifier::new(image, cfv).err(),
+            Some(VerifyErr::Unsigned)
+        );
+        assert_eq!(
+            PayloadVerifier::new(&[0u8; 0x100], cfv).err(),
+            Some(VerifyErr::Unsigned)
+        );
+        assert_eq!(
+            PayloadVerifier::new(&[], cfv).err(),
+            Some(VerifyErr::Unsigned)
+        );
+
+        // Malformed or tampered signed payloads are not reported as unsigned.
+        assert_eq!(
+            PayloadVerifier::new(&payload[..0x100], cfv).err(),
+            Some(VerifyErr::SizeMismatch {
+                declared: payload.len(),
+                actual: 0x100
+            })
+        );
+        assert_eq!(
+            CompactPayloadVerifier::new(payload, cfv).err(),
+            Some(VerifyErr::InvalidContent)
+        );
+        let mut tampered = payload.to_vec();
+        let length = PayloadSignHeader::from_bytes(payload).unwrap().length as usize;
+        tampered[length + PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE] ^= 0xff;
+        let verifier = PayloadVerifier::new(&tampered, cfv).unwrap();
+        assert_eq!(verifier.verify(), Err(VerifyErr::InvalidSignature));
     }
 
     #[test]
@@ -341,30 +1636,420 @@ mod test {
             payload_version: 1,
             payload_svn: 1,
             signing_algorithm: 0,
-            reserved: 0,
+            flags: 0,
+            payload_type: 0,
+            digest_algorithm: PAYLOAD_SIGN_DIGEST_DEFAULT,
+            reserved: [0u8; 1],
+            ..Default::default()
         };
-        assert!(PayloadVerifier::get_payload_image(hdr.as_bytes()).is_err());
-        hdr.length = size_of::<PayloadSignHeader>() as u32;
-        assert!(PayloadVerifier::get_payload_image(hdr.as_bytes()).is_err());
+        assert!(PayloadVerifier::get_payload_image(&hdr.to_bytes()).is_err());
+        hdr.length = PAYLOAD_SIGN_HEADER_V1_SIZE as u32;
+        assert!(PayloadVerifier::get_payload_image(&hdr.to_bytes()).is_err());
 
-        hdr.length = size_of::<PayloadSignHeader>() as u32 + 1;
+        hdr.length = PAYLOAD_SIGN_HEADER_V1_SIZE as u32 + 1;
         let mut buf = [0u8; 2048];
-        buf[0..size_of::<PayloadSignHeader>()].copy_from_slice(hdr.as_bytes());
-        assert!(
-            PayloadVerifier::get_payload_image(&buf[0..size_of::<PayloadSignHeader>()]).is_err()
-        );
+        buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE].copy_from_slice(&hdr.to_bytes());
+        assert!(PayloadVerifier::get_payload_image(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE]).is_err());
         assert_eq!(
-            PayloadVerifier::get_payload_image(&buf[0..size_of::<PayloadSignHeader>() + 1])
-                .unwrap(),
+            PayloadVerifier::get_payload_image(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 1]).unwrap(),
             &[0u8]
         );
         assert_eq!(
-            PayloadVerifier::get_payload_image(&buf[0..size_of::<PayloadSignHeader>() + 2])
-                .unwrap(),
+            PayloadVerifier::get_payload_image(&buf[0..PAYLOAD_SIGN_HEADER_V1_SIZE + 2]).unwrap(),
             &[0u8]
         );
     }
 
+    #[test]
+    fn test_payload_sign_header_endian() {
+        let mut hdr = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: 1,
+            length: 0x1234_5678,
+            payload_version: 0x0102_0304_0506_0708,
+            payload_svn: 0x1122_3344_5566_7788,
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            flags: 0,
+            payload_type: 0,
+            digest_algorithm: PAYLOAD_SIGN_DIGEST_DEFAULT,
+            reserved: [0u8; 1],
+            ..Default::default()
+        };
+
+        let bytes = hdr.to_bytes();
+        assert_eq!(bytes.len(), PAYLOAD_SIGN_HEADER_V1_SIZE);
+        assert_eq!(&bytes[20..24], &0x1234_5678u32.to_le_bytes());
+        assert_eq!(&bytes[24..32], &0x0102_0304_0506_0708u64.to_le_bytes());
+        let parsed = PayloadSignHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.endian(), Endian::Little);
+        assert_eq!(parsed.length, hdr.length);
+        assert_eq!(parsed.payload_version, hdr.payload_version);
+        assert_eq!(parsed.payload_svn, hdr.payload_svn);
+        assert_eq!(parsed.signing_algorithm, hdr.signing_algorithm);
+
+        hdr.flags = PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+        let bytes = hdr.to_bytes();
+        assert_eq!(&bytes[0..16], SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes());
+        assert_eq!(&bytes[16..20], &1u32.to_be_bytes());
+        assert_eq!(&bytes[20..24], &0x1234_5678u32.to_be_bytes());
+        assert_eq!(&bytes[24..32], &0x0102_0304_0506_0708u64.to_be_bytes());
+        assert_eq!(&bytes[32..40], &0x1122_3344_5566_7788u64.to_be_bytes());
+        assert_eq!(
+            bytes[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET],
+            PAYLOAD_SIGN_FLAG_BIG_ENDIAN
+        );
+        let parsed = PayloadSignHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.endian(), Endian::Big);
+        assert_eq!(parsed.struct_version, 1);
+        assert_eq!(parsed.length, hdr.length);
+        assert_eq!(parsed.payload_version, hdr.payload_version);
+        assert_eq!(parsed.payload_svn, hdr.payload_svn);
+        assert_eq!(parsed.signing_algorithm, hdr.signing_algorithm);
+
+        assert!(
+            PayloadSignHeader::from_bytes(&bytes[..PAYLOAD_SIGN_HEADER_FLAGS_OFFSET]).is_none()
+        );
+        assert!(PayloadSignHeader::from_bytes(&bytes[..PAYLOAD_SIGN_HEADER_V1_SIZE - 1]).is_none());
+    }
+
+    #[test]
+    fn test_payload_sign_header_compatible() {
+        let hdr = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V1,
+            length: 0x1000,
+            payload_version: 1,
+            payload_svn: 1,
+            signing_algorithm: PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+            ..Default::default()
+        };
+        let same = || PayloadSignHeader::from_bytes(&hdr.to_bytes()).unwrap();
+        assert!(hdr.is_compatible(&same()));
+
+        // Version, SVN and length may differ between compatible payloads.
+        let mut other = same();
+        other.length = 0x2000;
+        other.payload_version = 2;
+        other.payload_svn = 2;
+        assert!(hdr.is_compatible(&other));
+        assert!(other.is_compatible(&hdr));
+
+        let mut other = same();
+        other.signing_algorithm = PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384;
+        assert!(!hdr.is_compatible(&other));
+        let mut other = same();
+        other.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V2;
+        assert!(!hdr.is_compatible(&other));
+        let mut other = same();
+        other.flags = PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+        assert!(!hdr.is_compatible(&other));
+    }
+
+    #[test]
+    fn test_cfv_pub_key_file_header_layout() {
+        let hdr = CfvPubKeyFileHeader {
+            type_guid: *CFV_FILE_HEADER_PUBKEY_GUID.as_bytes(),
+            struct_version: PUBKEY_FILE_STRUCT_VERSION_V1,
+            length: 0x0102_0304,
+            hash_algorithm: PUBKEY_HASH_ALGORITHM_SHA384,
+            ..Default::default()
+        };
+
+        let bytes = hdr.to_bytes();
+        assert_eq!(bytes.len(), size_of::<CfvPubKeyFileHeader>());
+        assert_eq!(&bytes[16..20], &PUBKEY_FILE_STRUCT_VERSION_V1.to_le_bytes());
+        assert_eq!(&bytes[20..24], &0x0102_0304u32.to_le_bytes());
+        assert_eq!(&bytes[24..32], &PUBKEY_HASH_ALGORITHM_SHA384.to_le_bytes());
+        let parsed: CfvPubKeyFileHeader = bytes.pread_with(0, Endian::Little).unwrap();
+        assert_eq!(parsed.length, hdr.length);
+        assert_eq!(parsed.hash_algorithm, hdr.hash_algorithm);
+    }
+
+    #[test]
+    fn test_payload_sign_header_display() {
+        let mut hdr = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V1,
+            length: 0x1030,
+            payload_version: 0x10002,
+            payload_svn: 7,
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            ..Default::default()
+        };
+        assert_eq!(
+            alloc::format!("{}", hdr),
+            "struct_version: 1, length: 0x1030, payload_version: 0x10002, payload_svn: 7, \
+             signing_algorithm: ECDSA_NIST_P384_SHA384, endian: little"
+        );
+
+        hdr.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V2;
+        hdr.signing_algorithm = PAYLOAD_SIGN_RSA_PSS_3072_SHA384;
+        hdr.flags = PAYLOAD_SIGN_FLAG_BIG_ENDIAN;
+        hdr.policy_offset = 0x1020;
+        hdr.policy_size = 0x10;
+        assert_eq!(
+            alloc::format!("{}", hdr),
+            "struct_version: 2, length: 0x1030, payload_version: 0x10002, payload_svn: 7, \
+             signing_algorithm: RSAPSS_3072_SHA384, endian: big, policy_offset: 0x1020, \
+             policy_size: 0x10"
+        );
+
+        hdr.mr_index = 3;
+        assert!(alloc::format!("{}", hdr).ends_with("policy_size: 0x10, rtmr_index: 2"));
+        hdr.mr_index = PAYLOAD_MR_INDEX_DEFAULT;
+
+        hdr.signing_algorithm = 5;
+        assert!(alloc::format!("{}", hdr).contains("signing_algorithm: unknown(0x5),"));
+        assert_eq!(signing_algorithm_name(5), None);
+    }
+
+    #[test]
+    fn test_signed_payload_type() {
+        let mut hdr = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V1,
+            length: 0x1030,
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            ..Default::default()
+        };
+        assert_eq!(hdr.payload_type(), SignedPayloadType::Unspecified);
+        assert!(!alloc::format!("{}", hdr).contains("payload_type"));
+
+        for endian in [0, PAYLOAD_SIGN_FLAG_BIG_ENDIAN] {
+            hdr.flags = endian;
+            hdr.payload_type = PAYLOAD_TYPE_LINUX_KERNEL;
+            let bytes = hdr.to_bytes();
+            assert_eq!(
+                bytes[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 1],
+                PAYLOAD_TYPE_LINUX_KERNEL
+            );
+            let parsed = PayloadSignHeader::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.payload_type(), SignedPayloadType::LinuxKernel);
+            assert_eq!(parsed.digest_algorithm, PAYLOAD_SIGN_DIGEST_DEFAULT);
+            assert_eq!(parsed.reserved, [0u8; 1]);
+        }
+        assert!(alloc::format!("{}", hdr).ends_with(", payload_type: linux-kernel"));
+
+        // Types outside of the defined and custom ranges are kept, not mapped to a known type.
+        assert_eq!(SignedPayloadType::from_id(7), SignedPayloadType::Unknown(7));
+        assert_eq!(SignedPayloadType::Unknown(7).id(), 7);
+        assert_eq!(SignedPayloadType::Unknown(7).name(), None);
+        hdr.payload_type = 7;
+        assert!(alloc::format!("{}", hdr).ends_with(", payload_type: unknown(0x7)"));
+
+        assert_eq!(
+            SignedPayloadType::from_id(PAYLOAD_TYPE_CUSTOM_MIN),
+            SignedPayloadType::Custom(PAYLOAD_TYPE_CUSTOM_MIN)
+        );
+        assert_eq!(
+            SignedPayloadType::from_id(0xff),
+            SignedPayloadType::Custom(0xff)
+        );
+        for id in 0..=PAYLOAD_TYPE_MULTI_COMPONENT {
+            let payload_type = SignedPayloadType::from_id(id);
+            assert_eq!(payload_type.id(), id);
+            assert_eq!(
+                SignedPayloadType::from_name(payload_type.name().unwrap()),
+                Some(payload_type)
+            );
+        }
+        assert_eq!(SignedPayloadType::from_name("custom"), None);
+    }
+
+    #[test]
+    fn test_svn_policy() {
+        let policy = SvnPolicy {
+            min_svn: 3,
+            allowed_versions: alloc::vec![1, 0x0102_0304_0506_0708],
+        };
+
+        for endian in [Endian::Little, Endian::Big] {
+            let bytes = policy.to_bytes(endian);
+            assert_eq!(bytes.len(), policy.size());
+            assert_eq!(bytes.len(), 24 + 16);
+            assert_eq!(SvnPolicy::from_bytes(&bytes, endian).unwrap(), policy);
+        }
+
+        let bytes = policy.to_bytes(Endian::Little);
+        assert_eq!(&bytes[0..4], b"SVNP");
+        assert!(SvnPolicy::from_bytes(&bytes, Endian::Big).is_none());
+        assert!(SvnPolicy::from_bytes(&bytes[..bytes.len() - 1], Endian::Little).is_none());
+        let mut bad = bytes.clone();
+        bad.push(0);
+        assert!(SvnPolicy::from_bytes(&bad, Endian::Little).is_none());
+        let mut bad = bytes.clone();
+        bad[4] = 2;
+        assert!(SvnPolicy::from_bytes(&bad, Endian::Little).is_none());
+        assert!(SvnPolicy::from_bytes(&[], Endian::Little).is_none());
+
+        assert!(policy.is_allowed(3, 1));
+        assert!(policy.is_allowed(4, 0x0102_0304_0506_0708));
+        assert!(!policy.is_allowed(2, 1));
+        assert!(!policy.is_allowed(3, 2));
+
+        let any_version = SvnPolicy {
+            min_svn: 1,
+            allowed_versions: Vec::new(),
+        };
+        assert!(any_version.is_allowed(1, 0x1234));
+        assert!(!any_version.is_allowed(0, 0x1234));
+    }
+
+    fn build_signed_payload_with_policy(
+        svn: u64,
+        policy: &SvnPolicy,
+        endian: Endian,
+    ) -> (PayloadSignHeader, Vec<u8>) {
+        let payload = [0x5au8; 16];
+        let policy = policy.to_bytes(endian);
+        let mut hdr = PayloadSignHeader {
+            type_guid: *SIGNED_PAYLOAD_FILE_HEADER_GUID.as_bytes(),
+            struct_version: PAYLOAD_SIGN_HEADER_VERSION_V2,
+            payload_version: 1,
+            payload_svn: svn,
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            flags: if endian == Endian::Big {
+                PAYLOAD_SIGN_FLAG_BIG_ENDIAN
+            } else {
+                0
+            },
+            ..Default::default()
+        };
+        hdr.policy_offset = (hdr.header_size() + payload.len()) as u32;
+        hdr.policy_size = policy.len() as u32;
+        hdr.length = hdr.policy_offset + hdr.policy_size;
+
+        let mut signed = hdr.to_bytes();
+        signed.extend_from_slice(&payload);
+        signed.extend_from_slice(&policy);
+        // Dummy public key and signature
+        signed.extend_from_slice(&[0u8; 192]);
+
+        (hdr, signed)
+    }
+
+    #[test]
+    fn test_payload_sign_header_rtmr_index() {
+        let policy = SvnPolicy {
+            min_svn: 2,
+            allowed_versions: alloc::vec![1],
+        };
+
+        for endian in [Endian::Little, Endian::Big] {
+            let (mut hdr, signed) = build_signed_payload_with_policy(2, &policy, endian);
+            assert_eq!(hdr.rtmr_index(), None);
+            assert_eq!(hdr.payload_rtmr_index(), Ok(PAYLOAD_DEFAULT_RTMR_INDEX));
+            assert_eq!(
+                PayloadVerifier::new(&signed, &[])
+                    .unwrap()
+                    .get_payload_rtmr_index(),
+                PAYLOAD_DEFAULT_RTMR_INDEX
+            );
+
+            // The index round-trips through the serialized header, RTMR[0] included.
+            for index in 0..=PAYLOAD_MAX_RTMR_INDEX {
+                hdr.mr_index = index as u8 + 1;
+                let parsed = PayloadSignHeader::from_bytes(&hdr.to_bytes()).unwrap();
+                assert_eq!(parsed.rtmr_index(), Some(index));
+                assert_eq!(parsed.payload_rtmr_index(), Ok(index));
+
+                let mut other = signed.clone();
+                other[..hdr.header_size()].copy_from_slice(&hdr.to_bytes());
+                let verifier = PayloadVerifier::new(&other, &[]).unwrap();
+                assert_eq!(verifier.get_payload_rtmr_index(), index);
+            }
+
+            // RTMRs beyond RTMR[3] don't exist.
+            hdr.mr_index = PAYLOAD_MAX_RTMR_INDEX as u8 + 2;
+            assert_eq!(hdr.payload_rtmr_index(), Err(VerifyErr::InvalidContent));
+            let mut bad = signed.clone();
+            bad[..hdr.header_size()].copy_from_slice(&hdr.to_bytes());
+            assert!(PayloadVerifier::new(&bad, &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_payload_sign_header_v2() {
+        let policy = SvnPolicy {
+            min_svn: 2,
+            allowed_versions: alloc::vec![1],
+        };
+
+        for endian in [Endian::Little, Endian::Big] {
+            let (hdr, signed) = build_signed_payload_with_policy(2, &policy, endian);
+            assert_eq!(hdr.header_size(), size_of::<PayloadSignHeader>());
+            assert_eq!(hdr.to_bytes().len(), size_of::<PayloadSignHeader>());
+
+            let parsed = PayloadSignHeader::from_bytes(&signed).unwrap();
+            assert_eq!(parsed.struct_version, PAYLOAD_SIGN_HEADER_VERSION_V2);
+            assert_eq!(parsed.policy_offset, hdr.policy_offset);
+            assert_eq!(parsed.policy_size, hdr.policy_size);
+
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert_eq!(verifier.get_svn_policy(), Some(&policy));
+            assert!(verifier.check_svn_policy().is_ok());
+
+            // The policy is not part of the payload image.
+            assert_eq!(
+                PayloadVerifier::get_payload_image(&signed).unwrap(),
+                &[0x5au8; 16]
+            );
+
+            // Rollback to an older SVN
+            let (_, signed) = build_signed_payload_with_policy(1, &policy, endian);
+            let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+            assert!(matches!(
+                verifier.check_svn_policy(),
+                Err(VerifyErr::SvnPolicyViolation)
+            ));
+        }
+
+        // The policy must be placed at the end of the signed region
+        let (mut hdr, signed) = build_signed_payload_with_policy(2, &policy, Endian::Little);
+        let mut bad = signed.clone();
+        hdr.policy_offset -= 1;
+        bad[..hdr.header_size()].copy_from_slice(&hdr.to_bytes());
+        assert!(PayloadVerifier::new(&bad, &[]).is_err());
+        assert!(PayloadVerifier::get_payload_image(&bad).is_err());
+
+        // Offsets and sizes near the maximum are rejected instead of wrapping around.
+        let good = PayloadSignHeader::from_bytes(&signed).unwrap();
+        for (policy_offset, policy_size, length) in [
+            (u32::MAX, u32::MAX, u32::MAX),
+            (u32::MAX, 1, 0),
+            (good.policy_offset, u32::MAX, u32::MAX),
+            (good.policy_offset, good.policy_size, u32::MAX),
+        ] {
+            let mut hdr = PayloadSignHeader::from_bytes(&signed).unwrap();
+            hdr.policy_offset = policy_offset;
+            hdr.policy_size = policy_size;
+            hdr.length = length;
+            let mut bad = signed.clone();
+            bad[..hdr.header_size()].copy_from_slice(&hdr.to_bytes());
+            assert!(PayloadVerifier::new(&bad, &[]).is_err());
+            assert!(PayloadVerifier::get_payload_image(&bad).is_err());
+            assert!(embedded_verifier(&bad).is_err());
+        }
+
+        // Corrupted policy
+        let mut bad = signed.clone();
+        bad[size_of::<PayloadSignHeader>() + 16] = 0;
+        assert!(PayloadVerifier::new(&bad, &[]).is_err());
+
+        // Version 1 header must not point to a policy
+        let (mut hdr, _) = build_signed_payload_with_policy(2, &policy, Endian::Little);
+        hdr.struct_version = PAYLOAD_SIGN_HEADER_VERSION_V1;
+        hdr.length = PAYLOAD_SIGN_HEADER_V1_SIZE as u32 + 16;
+        let mut signed = hdr.to_bytes();
+        signed.extend_from_slice(&[0u8; 16 + 192]);
+        let parsed = PayloadSignHeader::from_bytes(&signed).unwrap();
+        assert_eq!(parsed.policy_offset, 0);
+        assert_eq!(parsed.policy_size, 0);
+        let verifier = PayloadVerifier::new(&signed, &[]).unwrap();
+        assert!(verifier.get_svn_policy().is_none());
+        assert!(verifier.check_svn_policy().is_ok());
+    }
+
     #[test]
     fn test_get_trust_anchor() {
         let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
@@ -380,6 +2065,64 @@ mod test {
         assert!(trust_anchor.is_ok(), "Fail to get trust anchor from CFV");
     }
 
+    // Append a raw file `name` of `data` to the files of `cfv`.
+    fn with_cfv_file(cfv: &[u8], name: Guid, data: &[u8]) -> Vec<u8> {
+        let reader = fv::CfvReader::new(cfv).unwrap();
+        let end = reader
+            .iter()
+            .map(|v| v.unwrap())
+            .map(|v| v.data.as_ptr() as usize - cfv.as_ptr() as usize + v.data.len())
+            .max()
+            .unwrap();
+        let offset = (end + 7) & !7;
+
+        let mut header = pi::fv::FfsFileHeader {
+            name: *name.as_bytes(),
+            r#type: pi::fv::FV_FILETYPE_RAW,
+            ..Default::default()
+        };
+        let size = (size_of::<pi::fv::FfsFileHeader>() + data.len()) as u32;
+        header.size.copy_from_slice(&size.to_le_bytes()[..3]);
+        header.update_checksum();
+
+        let mut cfv = cfv.to_vec();
+        let header = header.as_bytes();
+        cfv[offset..offset + header.len()].copy_from_slice(header);
+        cfv[offset + header.len()..offset + size as usize].copy_from_slice(data);
+        cfv
+    }
+
+    #[test]
+    fn test_min_svn() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        assert_eq!(get_min_svn(cfv), Ok(None));
+        let verifier = PayloadVerifier::new(payload, cfv).unwrap();
+        assert_eq!(verifier.check_svn_policy(), Ok(()));
+
+        // The seed payload has SVN 1, and no SVN policy of its own.
+        let anchored = with_cfv_file(cfv, CFV_FFS_HEADER_MIN_SVN_GUID, &1u64.to_le_bytes());
+        assert_eq!(get_min_svn(&anchored), Ok(Some(1)));
+        let verifier = PayloadVerifier::new(payload, &anchored).unwrap();
+        assert_eq!(verifier.check_svn_policy(), Ok(()));
+
+        let anchored = with_cfv_file(cfv, CFV_FFS_HEADER_MIN_SVN_GUID, &2u64.to_le_bytes());
+        let verifier = PayloadVerifier::new(payload, &anchored).unwrap();
+        assert_eq!(
+            verifier.check_svn_policy(),
+            Err(VerifyErr::SvnPolicyViolation)
+        );
+        assert_eq!(check_min_svn(&anchored, 2), Ok(()));
+
+        // A malformed minimum SVN rejects every payload.
+        let malformed = with_cfv_file(cfv, CFV_FFS_HEADER_MIN_SVN_GUID, &[1u8; 4]);
+        assert_eq!(get_min_svn(&malformed), Err(VerifyErr::InvalidContent));
+        assert_eq!(
+            check_min_svn(&malformed, u64::MAX),
+            Err(VerifyErr::InvalidContent)
+        );
+    }
+
     #[test]
     fn test_get_payload_svn() {
         let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
@@ -406,4 +2149,296 @@ mod test {
 
         assert!(verifier.unwrap().verify().is_ok(), "Verification fails");
     }
+
+    #[test]
+    fn test_embedded_verifier() {
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+        let (key, signed_region, signature) = embedded_verifier(payload).unwrap();
+        assert!(key.verify(signed_region, signature).is_ok());
+        assert_eq!(
+            signed_region,
+            &payload[..PayloadSignHeader::from_bytes(payload).unwrap().length as usize]
+        );
+
+        let mut tampered = signed_region.to_vec();
+        tampered[0x1000] ^= 0xff;
+        assert!(key.verify(&tampered, signature).is_err());
+        assert!(embedded_verifier(&payload[..0x100]).is_err());
+    }
+
+    #[test]
+    fn test_verify_with_key_hash() {
+        const SHA384_DIGEST_SIZE: usize = 48;
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+
+        // The trust anchor is the CFV public key file header followed by the key hash.
+        let trust_anchor = PayloadVerifier::get_trust_anchor(cfv).unwrap();
+        let key_hash = &trust_anchor[size_of::<CfvPubKeyFileHeader>()..][..SHA384_DIGEST_SIZE];
+
+        let verifier = PayloadVerifier::new(payload, &[]).unwrap();
+        assert!(verifier.verify_with_key_hash(key_hash).is_ok());
+        assert_eq!(
+            verifier.verify_with_key_hash(&[0u8; SHA384_DIGEST_SIZE]),
+            Err(VerifyErr::InvalidPublicKey)
+        );
+        assert_eq!(
+            verifier.verify_with_key_hash(&key_hash[1..]),
+            Err(VerifyErr::InvalidPublicKey)
+        );
+
+        let status = verifier.verify_all_with(|public_key| {
+            verify_public_key_hash(public_key, key_hash, HashAlg::Sha384)
+        });
+        assert!(status[0].result.is_ok());
+        let status = verifier.verify_all_with(|_| Err(VerifyErr::InvalidPublicKey));
+        assert_eq!(status[0].result, Err(VerifyErr::InvalidPublicKey));
+    }
+
+    // Append a revocation list of `hashes` to the files of `cfv`.
+    fn with_revocation_list(cfv: &[u8], hash_alg: HashAlg, hashes: &[&[u8]]) -> Vec<u8> {
+        let mut data = CfvPubKeyFileHeader {
+            type_guid: *CFV_FILE_HEADER_REVOKED_PUBKEY_GUID.as_bytes(),
+            struct_version: PUBKEY_FILE_STRUCT_VERSION_V1,
+            length: (size_of::<CfvPubKeyFileHeader>() + hashes.concat().len()) as u32,
+            hash_algorithm: hash_alg.id(),
+            ..Default::default()
+        }
+        .to_bytes()
+        .to_vec();
+        data.extend_from_slice(&hashes.concat());
+
+        with_cfv_file(cfv, CFV_FFS_HEADER_REVOCATION_LIST_GUID, &data)
+    }
+
+    #[test]
+    fn test_revocation_list() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let block = &payload[payload.len() - PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE..];
+        let public_key = &block[..PAYLOAD_SIGN_ECDSA_NIST_P384_PUBLIC_KEY_SIZE];
+        let trusted_hash = HashAlg::Sha384.digest(public_key);
+        let other_hash = HashAlg::Sha384.digest(b"another public key");
+
+        // A clean trusted key is accepted.
+        let clean = with_revocation_list(cfv, HashAlg::Sha384, &[other_hash.as_ref()]);
+        assert_eq!(check_revocation_list(&clean, public_key), Ok(()));
+        assert_eq!(verify_trust_anchor(&clean, public_key), Ok(()));
+        let verifier = PayloadVerifier::new(payload, &clean).unwrap();
+        assert!(verifier.verify().is_ok());
+
+        // A key both trusted and revoked is rejected.
+        let revoked = with_revocation_list(
+            cfv,
+            HashAlg::Sha384,
+            &[other_hash.as_ref(), trusted_hash.as_ref()],
+        );
+        assert_eq!(
+            verify_trust_anchor(&revoked, public_key),
+            Err(VerifyErr::RevokedPublicKey)
+        );
+        let verifier = PayloadVerifier::new(payload, &revoked).unwrap();
+        assert_eq!(verifier.verify(), Err(VerifyErr::RevokedPublicKey));
+        assert_eq!(
+            verifier.verify_all()[0].result,
+            Err(VerifyErr::RevokedPublicKey)
+        );
+
+        // The revocation list may use another hash algorithm than the trust anchor.
+        let sha512_hash = HashAlg::Sha512.digest(public_key);
+        let revoked = with_revocation_list(cfv, HashAlg::Sha512, &[sha512_hash.as_ref()]);
+        assert_eq!(
+            verify_trust_anchor(&revoked, public_key),
+            Err(VerifyErr::RevokedPublicKey)
+        );
+
+        // A malformed revocation list rejects every key.
+        let malformed = with_revocation_list(cfv, HashAlg::Sha384, &[&trusted_hash.as_ref()[1..]]);
+        assert_eq!(
+            verify_trust_anchor(&malformed, public_key),
+            Err(VerifyErr::InvalidContent)
+        );
+        assert_eq!(check_revocation_list(cfv, public_key), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let block_start = payload.len() - PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE;
+        let block = &payload[block_start..];
+
+        let verifier = PayloadVerifier::new(payload, cfv).unwrap();
+        let status = verifier.verify_all();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].slot, 0);
+        assert_eq!(status[0].result, Ok(()));
+
+        let mut signed = payload.to_vec();
+        let additional = AdditionalSignatureHeader {
+            signing_algorithm: PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384,
+            reserved: 0,
+        };
+        let mut hdr = [0u8; size_of::<AdditionalSignatureHeader>()];
+        hdr.pwrite_with(&additional, 0, Endian::Little).unwrap();
+        // Slot 1: valid signature
+        signed.extend_from_slice(&hdr);
+        signed.extend_from_slice(block);
+        // Slot 2: invalid signature
+        signed.extend_from_slice(&hdr);
+        signed.extend_from_slice(block);
+        let len = signed.len();
+        signed[len - 1] ^= 0xff;
+        // Slot 3: untrusted public key
+        signed.extend_from_slice(&hdr);
+        signed.extend_from_slice(block);
+        let len = signed.len();
+        signed[len - PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE] ^= 0xff;
+        // Slot 4: truncated
+        signed.extend_from_slice(&hdr);
+        signed.extend_from_slice(&block[..100]);
+
+        let verifier = PayloadVerifier::new(&signed, cfv).unwrap();
+        assert!(verifier.verify().is_ok());
+        let status = verifier.verify_all();
+        assert_eq!(status.len(), 5);
+        for (idx, s) in status.iter().enumerate() {
+            assert_eq!(s.slot, idx);
+            assert_eq!(s.signing_algorithm, PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384);
+        }
+        assert_eq!(status[0].result, Ok(()));
+        assert_eq!(status[1].result, Ok(()));
+        assert_eq!(status[1].public_key, &block[..96]);
+        assert_eq!(status[2].result, Err(VerifyErr::InvalidSignature));
+        assert_eq!(status[3].result, Err(VerifyErr::InvalidPublicKey));
+        assert_eq!(status[4].result, Err(VerifyErr::InvalidContent));
+        assert!(status[4].public_key.is_empty());
+
+        // Unknown algorithm of an additional signature
+        let mut signed = payload.to_vec();
+        signed.extend_from_slice(&[0x5a; 8]);
+        signed.extend_from_slice(block);
+        let verifier = PayloadVerifier::new(&signed, cfv).unwrap();
+        let status = verifier.verify_all();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[1].result, Err(VerifyErr::UnknownAlgorithm));
+
+        // Zero padding after the last signature
+        let mut signed = payload.to_vec();
+        signed.extend_from_slice(&hdr);
+        signed.extend_from_slice(block);
+        signed.resize(signed.len() + 0x1000, 0);
+        let verifier = PayloadVerifier::new(&signed, cfv).unwrap();
+        let status = verifier.verify_all();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[1].result, Ok(()));
+    }
+
+    #[test]
+    fn test_hash_alg() {
+        assert_eq!(HashAlg::ALL_NAMES.len(), 3);
+        for name in HashAlg::ALL_NAMES {
+            let alg = HashAlg::from_str(name).unwrap();
+            assert_eq!(alg.name(), *name);
+            assert_eq!(HashAlg::from_id(alg.id()), Some(alg));
+            assert_eq!(alg.digest(b"td-shim").as_ref().len(), alg.output_len());
+        }
+
+        assert_eq!(HashAlg::Sha384.id(), PUBKEY_HASH_ALGORITHM_SHA384);
+        assert_eq!(HashAlg::Sha256.output_len(), 32);
+        assert_eq!(HashAlg::Sha384.output_len(), 48);
+        assert_eq!(HashAlg::Sha512.output_len(), 64);
+        assert_eq!(HashAlg::from_str("sha-512"), Ok(HashAlg::Sha512));
+        assert_eq!(HashAlg::from_str("SHA1"), Err(VerifyErr::UnknownAlgorithm));
+        assert_eq!(
+            HashAlg::from_str("SHA384SHA384"),
+            Err(VerifyErr::UnknownAlgorithm)
+        );
+        assert_eq!(HashAlg::from_id(0), None);
+    }
+
+    #[test]
+    fn test_payload_sign_header_digest() {
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        let mut hdr = PayloadSignHeader {
+            signing_algorithm: PAYLOAD_SIGN_RSA_PSS_3072_SHA384,
+            ..Default::default()
+        };
+        assert_eq!(hdr.digest(), Ok(HashAlg::Sha384));
+        for alg in [HashAlg::Sha256, HashAlg::Sha384, HashAlg::Sha512] {
+            hdr.digest_algorithm = alg.id() as u8;
+            assert_eq!(hdr.digest(), Ok(alg));
+        }
+        assert!(alloc::format!("{}", hdr).ends_with(", digest: SHA512"));
+        hdr.digest_algorithm = 9;
+        assert_eq!(hdr.digest(), Err(VerifyErr::UnknownAlgorithm));
+
+        // ECDSA_NIST_P384 pins SHA384.
+        hdr.signing_algorithm = PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384;
+        hdr.digest_algorithm = HashAlg::Sha512.id() as u8;
+        assert_eq!(hdr.digest(), Err(VerifyErr::UnknownAlgorithm));
+        hdr.digest_algorithm = HashAlg::Sha384.id() as u8;
+        assert_eq!(hdr.digest(), Ok(HashAlg::Sha384));
+        hdr.signing_algorithm = 5;
+        hdr.digest_algorithm = PAYLOAD_SIGN_DIGEST_DEFAULT;
+        assert_eq!(hdr.digest(), Err(VerifyErr::UnknownAlgorithm));
+
+        // The digest is recorded next to the payload type and covered by the signature.
+        let mut signed = payload.to_vec();
+        signed[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 2] = HashAlg::Sha512.id() as u8;
+        assert_eq!(
+            PayloadVerifier::new(&signed, &[]).err(),
+            Some(VerifyErr::UnknownAlgorithm)
+        );
+        signed[PAYLOAD_SIGN_HEADER_FLAGS_OFFSET + 2] = HashAlg::Sha384.id() as u8;
+        assert_eq!(
+            PayloadVerifier::new(&signed, &[])
+                .unwrap()
+                .verify_signature(),
+            Err(VerifyErr::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_compact_signed_payload() {
+        let cfv = include_bytes!("../fuzz/seeds/secure_boot_cfv/cfv");
+        let payload = include_bytes!("../fuzz/seeds/secure_boot_payload/td-payload-signed");
+        assert_eq!(
+            size_of::<CompactSignatureFooter>(),
+            COMPACT_SIGNATURE_FOOTER_SIZE
+        );
+        assert_eq!(
+            SignedPayloadFormat::detect(payload),
+            Some(SignedPayloadFormat::Standard)
+        );
+        assert!(CompactPayloadVerifier::new(payload, cfv).is_err());
+
+        // Reuse the signature block of the standard signed payload, the signature doesn't match.
+        let block = &payload[payload.len() - PAYLOAD_SIGN_ECDSA_NIST_P384_BLOCK_SIZE..];
+        let mut compact = b"tiny payload".to_vec();
+        compact.extend_from_slice(
+            &CompactSignatureFooter::new(PAYLOAD_SIGN_ECDSA_NIST_P384_SHA384).to_bytes(),
+        );
+        compact.extend_from_slice(block);
+        assert_eq!(
+            SignedPayloadFormat::detect(&compact),
+            Some(SignedPayloadFormat::Compact)
+        );
+        assert!(PayloadVerifier::new(&compact, cfv).is_err());
+        assert!(PayloadVerifier::get_payload_image(&compact).is_err());
+
+        let verifier = CompactPayloadVerifier::new(&compact, cfv).unwrap();
+        assert_eq!(verifier.get_payload_image(), b"tiny payload");
+        assert_eq!(verifier.verify(), Err(VerifyErr::InvalidSignature));
+        assert_eq!(verifier.verify_all().len(), 1);
+
+        // The footer must describe the algorithm of the signature block.
+        let mut other = compact.clone();
+        other[12..16].copy_from_slice(&PAYLOAD_SIGN_RSA_PSS_3072_SHA384.to_le_bytes());
+        assert_eq!(SignedPayloadFormat::detect(&other), None);
+        // No payload.
+        assert!(CompactPayloadVerifier::new(&compact[12..], cfv).is_err());
+        assert_eq!(SignedPayloadFormat::detect(&compact[..100]), None);
+    }
 }
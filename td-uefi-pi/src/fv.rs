@@ -74,21 +74,131 @@ pub fn get_file_from_fv(
     fv_file_type: FvFileType,
     file_name: Guid,
 ) -> Option<&[u8]> {
-    let fv_header = read_fv_header(fv_data)?;
+    let reader = CfvReader::new(fv_data).ok()?;
 
-    let files = Files::parse(fv_data, fv_header.header_length as usize)?;
-    for (file_header, file_data) in files {
-        if !validate_ffs_file_header(file_header) {
-            return None;
-        }
-        if file_header.r#type == fv_file_type && &file_header.name == file_name.as_bytes() {
-            return Some(file_data);
+    for entry in reader.iter() {
+        let entry = entry.ok()?;
+        if entry.kind == fv_file_type && entry.guid == file_name {
+            return Some(entry.data);
         }
     }
 
     None
 }
 
+/// Errors reported when walking firmware files in a Configuration Firmware Volume (CFV).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CfvError {
+    /// The firmware volume header is invalid.
+    InvalidHeader,
+    /// The firmware file at `offset` (relative to the start of the CFV) is malformed.
+    MalformedEntry { offset: usize },
+}
+
+/// A firmware file stored in the CFV.
+#[derive(Clone, Copy, Debug)]
+pub struct CfvEntry<'a> {
+    /// Name of the firmware file.
+    pub guid: Guid,
+    /// Type of the firmware file, such as `FV_FILETYPE_RAW`.
+    pub kind: FvFileType,
+    /// Content of the firmware file, excluding the FFS header.
+    pub data: &'a [u8],
+}
+
+/// Reader to walk the firmware files in a Configuration Firmware Volume (CFV).
+pub struct CfvReader<'a> {
+    fv_data: &'a [u8],
+    header_length: usize,
+}
+
+impl<'a> CfvReader<'a> {
+    /// Create a reader for the CFV in `fv_data` after validating the firmware volume header.
+    pub fn new(fv_data: &'a [u8]) -> Result<Self, CfvError> {
+        let header = read_fv_header(fv_data).ok_or(CfvError::InvalidHeader)?;
+
+        Ok(CfvReader {
+            fv_data,
+            header_length: header.header_length as usize,
+        })
+    }
+
+    /// Iterate over the firmware files in the CFV.
+    ///
+    /// The iterator stops at the end of the CFV region or at the free space following the last
+    /// file. A malformed file yields an error and terminates the iteration.
+    pub fn iter(&self) -> CfvEntries<'a> {
+        CfvEntries {
+            fv_data: self.fv_data,
+            offset: self.header_length,
+            done: false,
+        }
+    }
+}
+
+/// Iterator over firmware files in the CFV, created by `CfvReader::iter()`.
+pub struct CfvEntries<'a> {
+    fv_data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for CfvEntries<'a> {
+    type Item = Result<CfvEntry<'a>, CfvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const HEADER_SIZE: usize = core::mem::size_of::<FfsFileHeader>();
+
+        if self.done {
+            return None;
+        }
+
+        let offset = self.offset;
+        let buffer = match self.fv_data.get(offset..) {
+            Some(v) if v.len() >= HEADER_SIZE => v,
+            _ => {
+                self.done = true;
+                return None;
+            }
+        };
+
+        // Free space is either erased (all 0xFF) or zero filled.
+        let raw_header = &buffer[..HEADER_SIZE];
+        if raw_header.iter().all(|v| *v == 0xff) || raw_header.iter().all(|v| *v == 0) {
+            self.done = true;
+            return None;
+        }
+
+        self.done = true;
+        let header: FfsFileHeader = match buffer.pread(0) {
+            Ok(v) => v,
+            Err(_) => return Some(Err(CfvError::MalformedEntry { offset })),
+        };
+        let file_size = header.size[0] as usize
+            + ((header.size[1] as usize) << 8)
+            + ((header.size[2] as usize) << 16);
+        if file_size < HEADER_SIZE || file_size > buffer.len() || !validate_ffs_file_header(header)
+        {
+            return Some(Err(CfvError::MalformedEntry { offset }));
+        }
+
+        // Files are aligned to 8 bytes.
+        match offset.checked_add((file_size + 7) & !7) {
+            Some(next) => {
+                self.offset = next;
+                self.done = false;
+            }
+            None => return Some(Err(CfvError::MalformedEntry { offset })),
+        }
+
+        Some(Ok(CfvEntry {
+            guid: Guid::from_bytes(&header.name),
+            kind: header.r#type,
+            data: &buffer[HEADER_SIZE..file_size],
+        }))
+    }
+}
+
 fn get_image_from_sections(sections_data: &[u8], section_type: SectionType) -> Option<&[u8]> {
     let sections = Sections::parse(sections_data, 0)?;
 
@@ -186,6 +296,9 @@ impl<'a> Iterator for Files<'a> {
 
 #[cfg(test)]
 mod test {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
     use core::mem::size_of;
 
     use super::*;
@@ -296,6 +409,147 @@ mod test {
         assert_eq!(res, None);
     }
 
+    fn build_cfv(files: &[(Guid, FvFileType, &[u8])], fv_size: usize, fill: u8) -> Vec<u8> {
+        let mut fv = alloc::vec![fill; fv_size];
+        let mut header = FirmwareVolumeHeader::default();
+        header.revision = FVH_REVISION;
+        header.signature = FVH_SIGNATURE;
+        header.header_length = size_of::<FirmwareVolumeHeader>() as u16;
+        header.fv_length = fv_size as u64;
+        header.update_checksum();
+        fv[..size_of::<FirmwareVolumeHeader>()].copy_from_slice(header.as_bytes());
+
+        let mut offset = size_of::<FirmwareVolumeHeader>();
+        for (name, kind, data) in files {
+            let size = size_of::<FfsFileHeader>() + data.len();
+            let mut ffs = FfsFileHeader::default();
+            ffs.name = *name.as_bytes();
+            ffs.r#type = *kind;
+            ffs.size = [size as u8, (size >> 8) as u8, (size >> 16) as u8];
+            ffs.update_checksum();
+            fv[offset..offset + size_of::<FfsFileHeader>()].copy_from_slice(ffs.as_bytes());
+            fv[offset + size_of::<FfsFileHeader>()..offset + size].copy_from_slice(data);
+            offset += (size + 7) & !7;
+        }
+
+        fv
+    }
+
+    #[test]
+    fn test_cfv_reader() {
+        assert_eq!(CfvReader::new(&[]).err(), Some(CfvError::InvalidHeader));
+        assert_eq!(
+            CfvReader::new(&[0u8; 0x100]).err(),
+            Some(CfvError::InvalidHeader)
+        );
+
+        // Zero and erased free space terminate the iteration.
+        for fill in [0u8, 0xff] {
+            let fv = build_cfv(
+                &[
+                    (TEST_GUID1, FV_FILETYPE_RAW, b"trust anchor"),
+                    (TEST_GUID2, FV_FILETYPE_FREEFORM, b"config"),
+                ],
+                0x200,
+                fill,
+            );
+            let reader = CfvReader::new(&fv).unwrap();
+            let entries: Vec<CfvEntry> = reader.iter().map(|e| e.unwrap()).collect();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].guid, TEST_GUID1);
+            assert_eq!(entries[0].kind, FV_FILETYPE_RAW);
+            assert_eq!(entries[0].data, b"trust anchor");
+            assert_eq!(entries[1].guid, TEST_GUID2);
+            assert_eq!(entries[1].kind, FV_FILETYPE_FREEFORM);
+            assert_eq!(entries[1].data, b"config");
+        }
+
+        // File ends exactly at the end of the CFV region.
+        let data = [0x5au8; 0x100 - size_of::<FirmwareVolumeHeader>() - 24];
+        let fv = build_cfv(&[(TEST_GUID1, FV_FILETYPE_RAW, &data)], 0x100, 0);
+        let reader = CfvReader::new(&fv).unwrap();
+        let mut iter = reader.iter();
+        assert_eq!(iter.next().unwrap().unwrap().data, &data);
+        assert!(iter.next().is_none());
+
+        // Empty CFV
+        let fv = build_cfv(&[], 0x100, 0xff);
+        assert!(CfvReader::new(&fv).unwrap().iter().next().is_none());
+
+        // The seed generated by the enroll tool.
+        let bytes = include_bytes!("../fuzz/seeds/cfv_parser/cfv");
+        let reader = CfvReader::new(bytes).unwrap();
+        assert!(reader
+            .iter()
+            .any(|e| e.unwrap().guid == TEST_GUID1 && e.unwrap().kind == FV_FILETYPE_RAW));
+    }
+
+    fn set_file_size(file: &mut [u8], size: usize) {
+        let mut ffs: FfsFileHeader = file.pread(0).unwrap();
+        ffs.size = [size as u8, (size >> 8) as u8, (size >> 16) as u8];
+        ffs.update_checksum();
+        file[..size_of::<FfsFileHeader>()].copy_from_slice(ffs.as_bytes());
+    }
+
+    #[test]
+    fn test_cfv_reader_malformed() {
+        let files: [(Guid, FvFileType, &[u8]); 2] = [
+            (TEST_GUID1, FV_FILETYPE_RAW, b"first"),
+            (TEST_GUID2, FV_FILETYPE_RAW, b"second"),
+        ];
+        let second = size_of::<FirmwareVolumeHeader>() + 32;
+        let fv = build_cfv(&files, 0x200, 0);
+
+        // Broken header checksum of the second file.
+        let mut bad = fv.clone();
+        bad[second] ^= 0x1;
+        let reader = CfvReader::new(&bad).unwrap();
+        let mut iter = reader.iter();
+        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(
+            iter.next().unwrap().err(),
+            Some(CfvError::MalformedEntry { offset: second })
+        );
+        assert!(iter.next().is_none());
+
+        // File size smaller than the FFS header.
+        let mut bad = fv.clone();
+        set_file_size(&mut bad[second..], 0x10);
+        let reader = CfvReader::new(&bad).unwrap();
+        assert_eq!(
+            reader.iter().nth(1).unwrap().err(),
+            Some(CfvError::MalformedEntry { offset: second })
+        );
+
+        // File size exceeds the CFV region.
+        let mut bad = fv.clone();
+        set_file_size(&mut bad[second..], 0x1000);
+        let reader = CfvReader::new(&bad).unwrap();
+        assert_eq!(
+            reader.iter().nth(1).unwrap().err(),
+            Some(CfvError::MalformedEntry { offset: second })
+        );
+
+        // Maximum file size, the offset of the next file doesn't wrap.
+        let mut bad = fv.clone();
+        set_file_size(&mut bad[second..], 0xff_ffff);
+        let reader = CfvReader::new(&bad).unwrap();
+        assert_eq!(
+            reader.iter().nth(1).unwrap().err(),
+            Some(CfvError::MalformedEntry { offset: second })
+        );
+        assert!(get_file_from_fv(&bad, FV_FILETYPE_RAW, TEST_GUID2).is_none());
+
+        // Truncated header at the end of the CFV region is treated as end of the CFV.
+        let fv = build_cfv(
+            &files[..1],
+            size_of::<FirmwareVolumeHeader>() + 32 + 16,
+            0x5a,
+        );
+        let reader = CfvReader::new(&fv).unwrap();
+        assert_eq!(reader.iter().count(), 1);
+    }
+
     #[test]
     fn test_read_fvh() {
         let mut fv = [0u8; 0x100];
@@ -90,7 +90,7 @@ pub fn check_hob_integrity(hob_list: &[u8]) -> Option<&[u8]> {
         match header.r#type {
             HOB_TYPE_HANDOFF => {
                 if header.length as usize != size_of::<HandoffInfoTable>()
-                    || offset + size_of::<HandoffInfoTable>() > hob_list_len
+                    || offset.checked_add(size_of::<HandoffInfoTable>())? > hob_list_len
                 {
                     return None;
                 }
@@ -106,7 +106,7 @@ pub fn check_hob_integrity(hob_list: &[u8]) -> Option<&[u8]> {
                 }
             }
             HOB_TYPE_END_OF_HOB_LIST => {
-                let hob_length = offset + size_of::<Header>();
+                let hob_length = offset.checked_add(size_of::<Header>())?;
 
                 return check_hob_length(hob_list, hob_length);
             }
@@ -131,25 +131,36 @@ pub fn check_hob_integrity(hob_list: &[u8]) -> Option<&[u8]> {
                 if header.length as usize != size_of::<MemoryAllocation>() {
                     return None;
                 }
+                let alloc_hob: MemoryAllocation = hob.pread(0).ok()?;
+                alloc_hob
+                    .alloc_descriptor
+                    .memory_base_address
+                    .checked_add(alloc_hob.alloc_descriptor.memory_length)?;
             }
             HOB_TYPE_FV => {
                 if header.length as usize != size_of::<FirmwareVolume>() {
                     return None;
                 }
+                let fv_hob: FirmwareVolume = hob.pread(0).ok()?;
+                fv_hob.base_address.checked_add(fv_hob.length)?;
             }
             HOB_TYPE_FV2 => {
                 if header.length as usize != size_of::<FirmwareVolume2>() {
                     return None;
                 }
+                let fv_hob: FirmwareVolume2 = hob.pread(0).ok()?;
+                fv_hob.base_address.checked_add(fv_hob.length)?;
             }
             HOB_TYPE_FV3 => {
                 if header.length as usize != size_of::<FirmwareVolume3>() {
                     return None;
                 }
+                let fv_hob: FirmwareVolume3 = hob.pread(0).ok()?;
+                fv_hob.base_address.checked_add(fv_hob.length)?;
             }
             HOB_TYPE_CPU => {
                 if header.length as usize != size_of::<Cpu>()
-                    || offset + size_of::<Cpu>() > hob_list_len
+                    || offset.checked_add(size_of::<Cpu>())? > hob_list_len
                 {
                     return None;
                 }
@@ -335,6 +346,7 @@ mod tests {
     use super::*;
     use crate::pi::guid::Guid;
     use core::ptr::slice_from_raw_parts;
+    use scroll::Pwrite;
 
     #[test]
     fn test_align_to_next_hob() {
@@ -493,6 +505,59 @@ mod tests {
         assert!(dump_hob(&test_hob).is_some());
     }
 
+    #[test]
+    fn test_check_hob_integrity_overflow() {
+        fn build_hob_list(base_address: u64, length: u64) -> [u8; 128] {
+            let phit_len = size_of::<HandoffInfoTable>();
+            let fv_len = size_of::<FirmwareVolume>();
+            let mut buf = [0u8; 128];
+            let phit = HandoffInfoTable {
+                header: Header {
+                    r#type: HOB_TYPE_HANDOFF,
+                    length: phit_len as u16,
+                    reserved: 0,
+                },
+                version: 0,
+                boot_mode: 0,
+                efi_memory_top: 0,
+                efi_memory_bottom: 0,
+                efi_free_memory_top: 0,
+                efi_free_memory_bottom: 0,
+                efi_end_of_hob_list: 0,
+            };
+            let fv = FirmwareVolume {
+                header: Header {
+                    r#type: HOB_TYPE_FV,
+                    length: fv_len as u16,
+                    reserved: 0,
+                },
+                base_address,
+                length,
+            };
+            let end = Header {
+                r#type: HOB_TYPE_END_OF_HOB_LIST,
+                length: size_of::<Header>() as u16,
+                reserved: 0,
+            };
+            buf.pwrite(phit, 0).unwrap();
+            buf.pwrite(fv, phit_len).unwrap();
+            buf.pwrite(end, phit_len + fv_len).unwrap();
+            buf
+        }
+
+        let end = size_of::<HandoffInfoTable>() + size_of::<FirmwareVolume>() + size_of::<Header>();
+        let check = |buf: &mut [u8; 128]| {
+            let end_of_hob_list = buf.as_ptr() as u64 + end as u64;
+            buf.pwrite(end_of_hob_list, 48).unwrap();
+            check_hob_integrity(&buf[..]).is_some()
+        };
+
+        assert!(check(&mut build_hob_list(0x1000000, 0x1000)));
+        // The firmware volume wraps around the address space.
+        assert!(!check(&mut build_hob_list(u64::MAX - 0xfff, 0x2000)));
+        assert!(!check(&mut build_hob_list(0x1000, u64::MAX)));
+    }
+
     #[test]
     fn test_get_total_memory_top() {
         let hob = &include_bytes!("../fuzz/seeds/hob_parser/hob_buffer")[..];
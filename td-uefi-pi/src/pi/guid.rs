@@ -1,4 +1,4 @@
-use core::{convert::TryInto, mem::size_of, ptr::slice_from_raw_parts, str::FromStr};
+use core::str::FromStr;
 
 use scroll::{Pread, Pwrite};
 
@@ -9,13 +9,13 @@ const GUID_SPLITTER: u8 = b'-';
 
 // A GUID is a 128-bit integer (16 bytes) that can be
 // used as a unique identifier.
+//
+// The GUID is always stored in the little-endian layout of EFI_GUID, whatever the byte order of
+// the host is.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pwrite, Pread)]
 pub struct Guid {
-    f0: u32,
-    f1: u16,
-    f2: u16,
-    f3: [u8; 8],
+    bytes: [u8; 16],
 }
 
 #[derive(Debug)]
@@ -26,26 +26,24 @@ pub enum GuidParseError {
 impl Guid {
     // Create a GUID instance from several fields
     pub const fn from_fields(f0: u32, f1: u16, f2: u16, f3: [u8; 8]) -> Guid {
-        Self { f0, f1, f2, f3 }
+        let f0 = f0.to_le_bytes();
+        let f1 = f1.to_le_bytes();
+        let f2 = f2.to_le_bytes();
+
+        Self {
+            bytes: [
+                f0[0], f0[1], f0[2], f0[3], f1[0], f1[1], f2[0], f2[1], f3[0], f3[1], f3[2], f3[3],
+                f3[4], f3[5], f3[6], f3[7],
+            ],
+        }
     }
 
     pub fn as_bytes(&self) -> &[u8; 16] {
-        // Safe since the size of Guid is 16
-        unsafe {
-            (&*slice_from_raw_parts(self as *const Self as *const u8, size_of::<Self>()))
-                .try_into()
-                .unwrap()
-        }
+        &self.bytes
     }
 
     pub fn from_bytes(buffer: &[u8; 16]) -> Guid {
-        let f0 = u32::from_le_bytes(buffer[0..4].try_into().unwrap());
-        let f1 = u16::from_le_bytes(buffer[4..6].try_into().unwrap());
-        let f2 = u16::from_le_bytes(buffer[6..8].try_into().unwrap());
-        let mut f3: [u8; 8] = [0; 8];
-        f3.copy_from_slice(&buffer[8..]);
-
-        Self { f0, f1, f2, f3 }
+        Self { bytes: *buffer }
     }
 }
 
@@ -83,12 +81,7 @@ impl FromStr for Guid {
         f3 |= parse_hex(&s[24..36]).ok_or(GuidParseError::InvalidInput)?;
 
         // f3 is decoded from string so use big endian to encode into bytes
-        Ok(Self {
-            f0,
-            f1,
-            f2,
-            f3: u64::to_be_bytes(f3),
-        })
+        Ok(Self::from_fields(f0, f1, f2, u64::to_be_bytes(f3)))
     }
 }
 
@@ -115,6 +108,13 @@ mod test {
         let guid_str = Guid::from_str("F9168C5E-CEB2-4faa-B6BF-329BF39FA1E4").unwrap();
         assert_eq!(&guid_bytes, guid_str.as_bytes());
 
+        assert_eq!(Guid::from_bytes(&guid_bytes), guid_field);
+        let guid_pread: Guid = guid_bytes.pread(0).unwrap();
+        assert_eq!(guid_pread, guid_field);
+        let mut buf = [0u8; 16];
+        buf.pwrite(guid_field, 0).unwrap();
+        assert_eq!(buf, guid_bytes);
+
         let guid_str = Guid::from_str("F9168C5E");
         assert!(guid_str.is_err());
 